@@ -1,12 +1,291 @@
-use reqwest::Client;
+use reqwest::StatusCode;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::ai_transport::{self, AiRequest, AiTransport, TransportError};
+use crate::textutil;
 
 const API_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta/models";
+/// How long a key check waits for Google before calling it a network error
+const VERIFY_TIMEOUT: Duration = Duration::from_secs(5);
+/// How long a fetched model list is reused for the same key
+const MODEL_CACHE_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Models tried in order when none are configured
+pub const DEFAULT_MODELS: [&str; 3] = ["gemini-flash-latest", "gemini-1.5-flash", "gemini-1.5-pro"];
+
+/// Model output plus the model that actually produced it
+#[derive(Debug, Clone, Serialize)]
+pub struct AiReply {
+    pub text: String,
+    pub model: String,
+    pub usage: TokenUsage,
+}
+
+/// Tokens a request used, as the API reported them (zero when it didn't)
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenUsage {
+    pub tokens_in: u64,
+    pub tokens_out: u64,
+}
+
+/// Why a generate call failed; only `ModelUnavailable` moves on to the next model
+enum ChatError {
+    ModelUnavailable(String),
+    /// The model refused `responseMimeType`/`responseSchema`; worth asking
+    /// again without them
+    StructuredUnsupported(String),
+    Other(String),
+}
+
+/// Rough prompt size limit, in characters, for prompts built from clips
+pub const PROMPT_BUDGET_CHARS: usize = 100_000;
+const TRUNCATION_MARKER: &str = " […]";
+
+/// Clip texts cut down to fit a prompt budget
+#[derive(Debug, Clone)]
+pub struct BudgetFit {
+    pub parts: Vec<String>,
+    /// How many parts were shortened
+    pub truncated: usize,
+}
+
+/// Shrink the longest parts first until the total fits `budget` characters;
+/// short parts are kept whole so every source stays represented
+pub fn fit_to_budget(parts: &[String], budget: usize) -> BudgetFit {
+    let lengths: Vec<usize> = parts.iter().map(|p| textutil::grapheme_count(p)).collect();
+    if lengths.iter().sum::<usize>() <= budget {
+        return BudgetFit {
+            parts: parts.to_vec(),
+            truncated: 0,
+        };
+    }
+
+    // Find the largest per-part cap that fits (water-filling)
+    let mut sorted = lengths.clone();
+    sorted.sort_unstable();
+    let mut remaining = budget;
+    let mut cap = 0;
+    for (i, &len) in sorted.iter().enumerate() {
+        let share = remaining / (sorted.len() - i);
+        if len > share {
+            cap = share;
+            break;
+        }
+        remaining -= len;
+    }
+
+    let mut truncated = 0;
+    let parts = parts
+        .iter()
+        .zip(&lengths)
+        .map(|(part, &len)| {
+            if len <= cap {
+                return part.clone();
+            }
+            truncated += 1;
+            let keep = cap.saturating_sub(textutil::grapheme_count(TRUNCATION_MARKER));
+            let mut cut = textutil::truncate(part, keep).to_string();
+            cut.push_str(TRUNCATION_MARKER);
+            cut
+        })
+        .collect();
+
+    BudgetFit { parts, truncated }
+}
+
+/// Split clips, by their lengths, into runs in order that each fit
+/// `budget` characters. A clip longer than the budget gets a run of its own
+/// and is trimmed with `fit_to_budget` when its prompt is built.
+pub fn chunk_ranges(lengths: &[usize], budget: usize) -> Vec<Range<usize>> {
+    let mut chunks = Vec::new();
+    let (mut start, mut size) = (0, 0);
+    for (i, &len) in lengths.iter().enumerate() {
+        if i > start && size + len > budget {
+            chunks.push(start..i);
+            (start, size) = (i, 0);
+        }
+        size += len;
+    }
+    if start < lengths.len() {
+        chunks.push(start..lengths.len());
+    }
+    chunks
+}
+
+/// Sort a failed response into "try another model" or "give up"
+fn classify_error(status: reqwest::StatusCode, error_text: &str) -> ChatError {
+    let message = format!("API Error: {}", error_text);
+    // 404 for rotated aliases, 400 "not supported for generateContent" for
+    // models that exist but can't chat
+    let unavailable = status == reqwest::StatusCode::NOT_FOUND
+        || (status == reqwest::StatusCode::BAD_REQUEST
+            && error_text.contains("is not supported for generateContent"));
+    // Older models answer JSON mode with a 400 naming the field they reject
+    let structured_unsupported = status == reqwest::StatusCode::BAD_REQUEST
+        && ["responseMimeType", "response_mime_type", "responseSchema", "response_schema", "JSON mode"]
+            .iter()
+            .any(|field| error_text.contains(field));
+    if unavailable {
+        ChatError::ModelUnavailable(message)
+    } else if structured_unsupported {
+        ChatError::StructuredUnsupported(message)
+    } else {
+        ChatError::Other(message)
+    }
+}
+
+/// Error once every model has been tried and none was available
+fn no_model_available(unavailable: &[&str]) -> String {
+    if unavailable.is_empty() {
+        "No AI models configured".to_string()
+    } else {
+        format!("No configured model is available (tried {})", unavailable.join(", "))
+    }
+}
+
+/// Parse a JSON reply, dropping the Markdown fence models sometimes wrap
+/// it in despite being asked not to
+pub fn parse_json_reply<T: DeserializeOwned>(text: &str) -> Result<T, String> {
+    let cleaned = text
+        .trim()
+        .trim_start_matches("```json")
+        .trim_start_matches("```")
+        .trim_end_matches("```")
+        .trim();
+    serde_json::from_str(cleaned).map_err(|e| format!("Failed to parse AI response: {}", e))
+}
+
+/// Outcome of checking an API key against Google
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum KeyCheck {
+    /// The key works; the models it can chat with
+    Valid { models: Vec<String> },
+    /// Google rejected the key
+    Invalid { message: String },
+    /// The key is fine but out of quota
+    QuotaExhausted { message: String },
+    /// Google couldn't be reached, or failed on its side
+    NetworkError { message: String },
+}
+
+/// Google's own message from an error body, else the body itself
+fn error_message(body: &str) -> String {
+    #[derive(Deserialize)]
+    struct ErrorBody {
+        error: GeminiError,
+    }
+    serde_json::from_str::<ErrorBody>(body)
+        .map(|b| b.error.message)
+        .unwrap_or_else(|_| body.trim().to_string())
+}
+
+/// Classify a failed key check. Google answers a malformed or unknown key
+/// with 400 API_KEY_INVALID rather than 401.
+fn key_check_for(status: StatusCode, body: &str) -> KeyCheck {
+    let message = error_message(body);
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => KeyCheck::Invalid { message },
+        StatusCode::BAD_REQUEST if body.contains("API_KEY_INVALID") || message.contains("API key") => {
+            KeyCheck::Invalid { message }
+        }
+        StatusCode::TOO_MANY_REQUESTS => KeyCheck::QuotaExhausted { message },
+        status => KeyCheck::NetworkError {
+            message: format!("HTTP {}: {}", status.as_u16(), message),
+        },
+    }
+}
+
+/// `text` with every occurrence of `key` masked, for anything that might be
+/// logged or shown
+pub fn redact(text: &str, key: &str) -> String {
+    if key.is_empty() {
+        return text.to_string();
+    }
+    text.replace(key, "[redacted]")
+}
+
+/// A fetched model list, tied to the key it was fetched with by hash
+struct CachedModels {
+    key_hash: Vec<u8>,
+    models: Vec<String>,
+    fetched_at: Instant,
+}
+
+static MODEL_CACHE: Mutex<Option<CachedModels>> = Mutex::new(None);
+
+fn key_hash(key: &str) -> Vec<u8> {
+    Sha256::digest(key.as_bytes()).to_vec()
+}
+
+/// The cached model list for `key`, if it's fresh. The lock is only held
+/// to copy it out, never across a request.
+fn cached_models(key: &str) -> Option<Vec<String>> {
+    let cache = MODEL_CACHE.lock().unwrap();
+    cache
+        .as_ref()
+        .filter(|c| c.key_hash == key_hash(key) && c.fetched_at.elapsed() < MODEL_CACHE_TTL)
+        .map(|c| c.models.clone())
+}
+
+fn cache_models(key: &str, models: &[String]) {
+    *MODEL_CACHE.lock().unwrap() = Some(CachedModels {
+        key_hash: key_hash(key),
+        models: models.to_vec(),
+        fetched_at: Instant::now(),
+    });
+}
+
+/// Names of the listed models that support generateContent
+fn chat_models(list: ModelList) -> Result<Vec<String>, String> {
+    if let Some(error) = list.error {
+        return Err(format!("Gemini Error: {}", error.message));
+    }
+    let models = list
+        .models
+        .ok_or("No models found")?
+        .into_iter()
+        .filter(|m| {
+            m.supported_generation_methods
+                .as_ref()
+                .is_some_and(|methods| methods.iter().any(|m| m == "generateContent"))
+        })
+        .map(|m| m.name)
+        .collect();
+    Ok(models)
+}
+
+/// Token counts from a response's `usageMetadata`
+fn usage_of(response: &GeminiResponse) -> Option<TokenUsage> {
+    response.usage_metadata.as_ref().map(|usage| TokenUsage {
+        tokens_in: usage.prompt_token_count,
+        tokens_out: usage.candidates_token_count,
+    })
+}
+
+/// Text of the first candidate in a response
+fn first_text(response: GeminiResponse) -> Option<String> {
+    response
+        .candidates
+        .and_then(|c| c.first().cloned())
+        .and_then(|c| c.content.parts.first().cloned())
+        .map(|p| p.text)
+}
+
+/// Strip the `models/` prefix the API uses in listings
+pub fn normalize_model_name(name: &str) -> String {
+    name.trim().trim_start_matches("models/").to_string()
+}
 
 #[derive(Clone, Debug)]
 pub struct GeminiClient {
-    http_client: Client,
+    transport: Arc<dyn AiTransport>,
     api_key: String,
 }
 
@@ -14,6 +293,17 @@ pub struct GeminiClient {
 struct GeminiResponse {
     candidates: Option<Vec<Candidate>>,
     error: Option<GeminiError>,
+    #[serde(default, rename = "usageMetadata")]
+    usage_metadata: Option<UsageMetadata>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u64,
+    #[serde(default)]
+    candidates_token_count: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -52,51 +342,290 @@ struct ModelInfo {
 }
 
 impl GeminiClient {
+    /// A client using the transport the environment picks (see
+    /// `ai_transport::from_env`)
     pub fn new(api_key: String) -> Self {
-        Self {
-            http_client: Client::new(),
-            api_key,
+        Self::with_transport(api_key, ai_transport::from_env())
+    }
+
+    pub fn with_transport(api_key: String, transport: Arc<dyn AiTransport>) -> Self {
+        Self { transport, api_key }
+    }
+
+    /// POST `body` to `url`
+    async fn post(&self, url: String, body: Value) -> Result<ai_transport::AiResponse, ChatError> {
+        let request = AiRequest { url, body: Some(body), timeout: None };
+        self.transport
+            .send(request)
+            .await
+            .map_err(|e| ChatError::Other(format!("Request failed: {}", e)))
+    }
+
+    /// Try each model in order, falling through only when a model is missing
+    /// or unsupported (auth, quota and safety errors stop the chain)
+    pub async fn chat_with_fallback(&self, models: &[String], prompt: &str) -> Result<AiReply, String> {
+        let mut unavailable = Vec::new();
+
+        for model in models {
+            match self.generate(model, prompt, None).await {
+                Ok((text, usage)) => {
+                    return Ok(AiReply {
+                        text,
+                        model: model.clone(),
+                        usage,
+                    })
+                }
+                Err(ChatError::ModelUnavailable(message)) => {
+                    eprintln!("Model {} unavailable, trying next: {}", model, message);
+                    unavailable.push(model.as_str());
+                }
+                Err(ChatError::StructuredUnsupported(message) | ChatError::Other(message)) => return Err(message),
+            }
         }
+
+        Err(no_model_available(&unavailable))
+    }
+
+    /// Ask for JSON matching `schema` (an OpenAPI-style Gemini schema) and
+    /// parse it into `T`, falling through models like `chat_with_fallback`.
+    /// A model that rejects JSON mode is asked again with the plain prompt
+    /// and its reply parsed leniently. The reply comes back even when it
+    /// doesn't parse, since its tokens were spent either way.
+    pub async fn chat_structured<T: DeserializeOwned>(
+        &self,
+        models: &[String],
+        prompt: &str,
+        schema: Value,
+    ) -> Result<(Result<T, String>, AiReply), String> {
+        let config = json!({
+            "responseMimeType": "application/json",
+            "responseSchema": schema,
+        });
+        let mut unavailable = Vec::new();
+
+        for model in models {
+            let result = match self.generate(model, prompt, Some(&config)).await {
+                Err(ChatError::StructuredUnsupported(message)) => {
+                    eprintln!("Model {} rejected JSON mode, asking without it: {}", model, message);
+                    self.generate(model, prompt, None).await
+                }
+                result => result,
+            };
+            match result {
+                Ok((text, usage)) => {
+                    let value = parse_json_reply(&text);
+                    return Ok((value, AiReply { text, model: model.clone(), usage }));
+                }
+                Err(ChatError::ModelUnavailable(message)) => {
+                    eprintln!("Model {} unavailable, trying next: {}", model, message);
+                    unavailable.push(model.as_str());
+                }
+                Err(ChatError::StructuredUnsupported(message) | ChatError::Other(message)) => return Err(message),
+            }
+        }
+
+        Err(no_model_available(&unavailable))
     }
 
-    pub async fn chat(&self, model: &str, prompt: &str) -> Result<String, String> {
+    async fn generate(
+        &self,
+        model: &str,
+        prompt: &str,
+        generation_config: Option<&Value>,
+    ) -> Result<(String, TokenUsage), ChatError> {
         let url = format!("{}/{}:generateContent?key={}", API_BASE_URL, model, self.api_key);
         
-        let body = json!({
+        let mut body = json!({
             "contents": [{
                 "parts": [{ "text": prompt }]
             }]
         });
+        if let Some(config) = generation_config {
+            body["generationConfig"] = config.clone();
+        }
 
-        let response = self.http_client
-            .post(&url)
-            .json(&body)
-            .send()
-            .await
-            .map_err(|e| format!("Request failed: {}", e))?;
+        let response = self.post(url, body).await?;
 
-        if !response.status().is_success() {
+        let status = response.status;
+        if !status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("API Error: {}", error_text));
+            return Err(classify_error(status, &error_text));
         }
 
         let gemini_resp: GeminiResponse = response
             .json()
             .await
-            .map_err(|e| format!("Failed to parse response: {}", e))?;
+            .map_err(|e| ChatError::Other(format!("Failed to parse response: {}", e)))?;
             
         if let Some(error) = gemini_resp.error {
-            return Err(format!("Gemini Error: {}", error.message));
+            return Err(ChatError::Other(format!("Gemini Error: {}", error.message)));
         }
 
-        gemini_resp.candidates
-            .and_then(|c| c.first().cloned())
-            .and_then(|c| c.content.parts.first().cloned())
-            .map(|p| p.text)
-            .ok_or_else(|| "No content returned".to_string())
+        let usage = usage_of(&gemini_resp).unwrap_or_default();
+        first_text(gemini_resp)
+            .map(|text| (text, usage))
+            .ok_or_else(|| ChatError::Other("No content returned".to_string()))
     }
 
-    pub async fn magic_sort(&self, clips_content: &str) -> Result<String, String> {
+    /// Like `chat_with_fallback`, but streams the reply, calling `on_delta`
+    /// with each piece of text as it arrives
+    pub async fn chat_stream_with_fallback(
+        &self,
+        models: &[String],
+        prompt: &str,
+        mut on_delta: impl FnMut(&str),
+    ) -> Result<AiReply, String> {
+        let mut unavailable = Vec::new();
+
+        for model in models {
+            match self.generate_stream(model, prompt, &mut on_delta).await {
+                Ok((text, usage)) => {
+                    return Ok(AiReply {
+                        text,
+                        model: model.clone(),
+                        usage,
+                    })
+                }
+                Err(ChatError::ModelUnavailable(message)) => {
+                    eprintln!("Model {} unavailable, trying next: {}", model, message);
+                    unavailable.push(model.as_str());
+                }
+                Err(ChatError::StructuredUnsupported(message) | ChatError::Other(message)) => return Err(message),
+            }
+        }
+
+        Err(no_model_available(&unavailable))
+    }
+
+    async fn generate_stream(
+        &self,
+        model: &str,
+        prompt: &str,
+        on_delta: &mut impl FnMut(&str),
+    ) -> Result<(String, TokenUsage), ChatError> {
+        let url = format!(
+            "{}/{}:streamGenerateContent?alt=sse&key={}",
+            API_BASE_URL, model, self.api_key
+        );
+
+        let body = json!({
+            "contents": [{
+                "parts": [{ "text": prompt }]
+            }]
+        });
+
+        let mut response = self.post(url, body).await?;
+
+        let status = response.status;
+        if !status.is_success() {
+            let error_text = response.text().await.unwrap_or_default();
+            return Err(classify_error(status, &error_text));
+        }
+
+        // Server-sent events: `data: {json}` lines separated by blank lines
+        let mut buffer = String::new();
+        let mut text = String::new();
+        // Each event carries the running totals; the last one is final
+        let mut usage = TokenUsage::default();
+        loop {
+            let chunk = response
+                .chunk()
+                .await
+                .map_err(|e| ChatError::Other(format!("Stream failed: {}", e)))?;
+            let Some(chunk) = chunk else {
+                break;
+            };
+            buffer.push_str(&String::from_utf8_lossy(&chunk));
+
+            while let Some(end) = buffer.find('\n') {
+                let line: String = buffer.drain(..=end).collect();
+                let Some(data) = line.trim().strip_prefix("data:") else {
+                    continue;
+                };
+                let event: GeminiResponse = serde_json::from_str(data.trim())
+                    .map_err(|e| ChatError::Other(format!("Failed to parse stream: {}", e)))?;
+                if let Some(error) = event.error {
+                    return Err(ChatError::Other(format!("Gemini Error: {}", error.message)));
+                }
+                usage = usage_of(&event).unwrap_or(usage);
+                if let Some(delta) = first_text(event) {
+                    on_delta(&delta);
+                    text.push_str(&delta);
+                }
+            }
+        }
+
+        if text.is_empty() {
+            return Err(ChatError::Other("No content returned".to_string()));
+        }
+        Ok((text, usage))
+    }
+
+    /// Ask for a single document in the given style built from numbered clips,
+    /// citing them inline as [n]
+    pub fn draft_prompt(clips: &[String], style: &str) -> String {
+        let sources = clips
+            .iter()
+            .enumerate()
+            .map(|(i, clip)| format!("[{}]\n{}", i + 1, clip))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        format!(
+            "You are a careful writer. Using the numbered source clips below as your \
+            material, write a single coherent {}. Cite sources inline with their number \
+            in square brackets, like [3], wherever you use them. Do not invent facts \
+            that are not in the sources. Reply with the document only.\n\n\
+            Sources:\n\n{}",
+            style, sources
+        )
+    }
+
+    /// Ask for notes on one part of a clip set too big for a single prompt.
+    /// The clips are numbered from `first + 1` so citations keep pointing at
+    /// the same sources across parts.
+    pub fn chunk_prompt(clips: &[String], first: usize, style: &str) -> String {
+        let sources = clips
+            .iter()
+            .enumerate()
+            .map(|(i, clip)| format!("[{}]\n{}", first + i + 1, clip))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        format!(
+            "You are gathering material for a {}. The numbered source clips below are \
+            one part of a larger set. Write concise notes covering everything in them \
+            that the document could use, citing each point with its source number in \
+            square brackets, like [3]. Do not invent facts that are not in the sources. \
+            Reply with the notes only.\n\n\
+            Sources:\n\n{}",
+            style, sources
+        )
+    }
+
+    /// Ask for the finished document from the notes on every part
+    pub fn combine_prompt(notes: &[String], style: &str) -> String {
+        let parts = notes
+            .iter()
+            .enumerate()
+            .map(|(i, notes)| format!("Part {}:\n{}", i + 1, notes))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+
+        format!(
+            "You are a careful writer. Below are notes taken from successive parts of a \
+            set of numbered source clips, each point citing its sources like [3]. Using \
+            them as your material, write a single coherent {}. Keep the citations as \
+            they are wherever you use a point. Do not invent facts that are not in the \
+            notes. Reply with the document only.\n\n\
+            Notes:\n\n{}",
+            style, parts
+        )
+    }
+
+    /// Ask for a new order of the clips, as indices into them; the reply
+    /// comes back whether or not it parsed
+    pub async fn magic_sort(&self, models: &[String], clips_content: &str) -> Result<(Result<Vec<usize>, String>, AiReply), String> {
         let prompt = format!(
             "You are a helpful assistant. \
             Analyze the following list of text clips. \
@@ -109,31 +638,28 @@ impl GeminiClient {
             {}", 
             clips_content
         );
-
-        let response = self.chat("gemini-flash-latest", &prompt).await?;
-        
-        // Clean cleanup markdown if present (```json ... ```)
-        let cleaned = response
-            .trim()
-            .trim_start_matches("```json")
-            .trim_start_matches("```")
-            .trim_end_matches("```");
-            
-        Ok(cleaned.to_string())
+        let schema = json!({ "type": "ARRAY", "items": { "type": "INTEGER" } });
+        self.chat_structured(models, &prompt, schema).await
     }
 
+    /// Models this key can chat with, from the cache when it was fetched
+    /// within the last hour
     pub async fn list_models(&self) -> Result<Vec<String>, String> {
+        if let Some(models) = cached_models(&self.api_key) {
+            return Ok(models);
+        }
         let url = format!("{}?key={}", API_BASE_URL, self.api_key);
         
-        let response = self.http_client
-            .get(&url)
-            .send()
+        let request = AiRequest { url, body: None, timeout: None };
+        let response = self
+            .transport
+            .send(request)
             .await
             .map_err(|e| format!("Request failed: {}", e))?;
 
-        if !response.status().is_success() {
+        if !response.status.is_success() {
             let error_text = response.text().await.unwrap_or_default();
-            return Err(format!("API Error: {}", error_text));
+            return Err(redact(&format!("API Error: {}", error_text), &self.api_key));
         }
 
         let model_list: ModelList = response
@@ -141,21 +667,232 @@ impl GeminiClient {
             .await
             .map_err(|e| format!("Failed to parse response: {}", e))?;
 
-        if let Some(error) = model_list.error {
-            return Err(format!("Gemini Error: {}", error.message));
+        let models = chat_models(model_list)?;
+        cache_models(&self.api_key, &models);
+        Ok(models)
+    }
+
+    /// Check the key with a model list request (the cheapest authenticated
+    /// call), bypassing the cache. A valid key's models are cached.
+    pub async fn verify_key(&self) -> KeyCheck {
+        let url = format!("{}?key={}", API_BASE_URL, self.api_key);
+        let request = AiRequest { url, body: None, timeout: Some(VERIFY_TIMEOUT) };
+        let response = match self.transport.send(request).await {
+            Ok(response) => response,
+            Err(e) => {
+                let message = match e {
+                    TransportError::TimedOut => format!("No answer within {}s", VERIFY_TIMEOUT.as_secs()),
+                    TransportError::Failed(message) => message,
+                };
+                return KeyCheck::NetworkError {
+                    message: redact(&message, &self.api_key),
+                };
+            }
+        };
+
+        let status = response.status;
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return match key_check_for(status, &body) {
+                KeyCheck::Invalid { message } => KeyCheck::Invalid {
+                    message: redact(&message, &self.api_key),
+                },
+                KeyCheck::QuotaExhausted { message } => KeyCheck::QuotaExhausted {
+                    message: redact(&message, &self.api_key),
+                },
+                KeyCheck::NetworkError { message } => KeyCheck::NetworkError {
+                    message: redact(&message, &self.api_key),
+                },
+                valid => valid,
+            };
         }
 
-        let models = model_list.models
-            .ok_or("No models found")?
-            .into_iter()
-            .filter(|m| {
-                m.supported_generation_methods
-                    .as_ref()
-                    .map_or(false, |methods| methods.contains(&"generateContent".to_string()))
-            })
-            .map(|m| m.name)
-            .collect();
+        let models = match response.json::<ModelList>().await {
+            Ok(list) => chat_models(list),
+            Err(e) => Err(e),
+        };
+        match models {
+            Ok(models) => {
+                cache_models(&self.api_key, &models);
+                KeyCheck::Valid { models }
+            }
+            Err(message) => KeyCheck::NetworkError {
+                message: redact(&message, &self.api_key),
+            },
+        }
+    }
+}
 
-        Ok(models)
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ai_transport::{models_body, reply_body, stream_body, Fixture, MockTransport};
+
+    fn mock_client(key: &str, mock: MockTransport) -> (GeminiClient, Arc<MockTransport>) {
+        let mock = Arc::new(mock);
+        (GeminiClient::with_transport(key.to_string(), mock.clone()), mock)
+    }
+
+    fn models(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn clips_are_chunked_in_order_under_the_budget() {
+        assert_eq!(chunk_ranges(&[40, 30, 30, 50, 10], 100), vec![0..3, 3..5]);
+        // An oversized clip stands alone rather than being split or dropped
+        assert_eq!(chunk_ranges(&[10, 250, 10], 100), vec![0..1, 1..2, 2..3]);
+        assert!(chunk_ranges(&[], 100).is_empty());
+
+        let prompt = GeminiClient::chunk_prompt(&["a".to_string(), "b".to_string()], 3, "report");
+        assert!(prompt.contains("[4]\na\n\n[5]\nb"));
+    }
+
+    #[tokio::test]
+    async fn chat_falls_through_unavailable_models_only() {
+        let gone = Fixture { status: 404, body: "models/old is not found".to_string() };
+        let mock = MockTransport::default()
+            .on("/old:", gone)
+            .on("/new:", Fixture::ok(reply_body("hello")))
+            .on("/denied:", Fixture { status: 403, body: "permission denied".to_string() });
+        let (client, mock) = mock_client("chat-key", mock);
+
+        let reply = client.chat_with_fallback(&models(&["old", "new"]), "hi").await.unwrap();
+        assert_eq!((reply.text.as_str(), reply.model.as_str()), ("hello", "new"));
+        let err = client.chat_with_fallback(&models(&["denied", "new"]), "hi").await.unwrap_err();
+        assert_eq!(err, "API Error: permission denied");
+        let err = client.chat_with_fallback(&models(&["old"]), "hi").await.unwrap_err();
+        assert_eq!(err, "No configured model is available (tried old)");
+
+        // The key never reaches what the transport keeps
+        assert!(mock.requests().iter().all(|r| !r.url.contains("chat-key")));
+    }
+
+    #[tokio::test]
+    async fn magic_sort_retries_without_json_mode() {
+        let rejected = Fixture {
+            status: 400,
+            body: r#"{"error": {"message": "Unknown name \"responseSchema\""}}"#.to_string(),
+        };
+        let mock = MockTransport::default()
+            .on(":generateContent", rejected)
+            .on(":generateContent", Fixture::ok(reply_body("```json\n[1, 0]\n```")));
+        let (client, mock) = mock_client("sort-key", mock);
+
+        let (order, reply) = client.magic_sort(&models(&["old"]), "[0] a\n[1] b").await.unwrap();
+        assert_eq!(order.unwrap(), [1, 0]);
+        assert_eq!(reply.model, "old");
+        let requests = mock.requests();
+        assert!(requests[0].body.as_ref().unwrap().get("generationConfig").is_some());
+        assert!(requests[1].body.as_ref().unwrap().get("generationConfig").is_none());
+    }
+
+    #[tokio::test]
+    async fn unparseable_structured_replies_still_come_back() {
+        let mock = MockTransport::default().on(":generateContent", Fixture::ok(reply_body("Sorry, I can't.")));
+        let (client, _) = mock_client("unparsed-key", mock);
+        let (order, reply) = client.magic_sort(&models(&["m"]), "[0] a").await.unwrap();
+        assert!(order.is_err());
+        assert_eq!(reply.text, "Sorry, I can't.");
+    }
+
+    #[tokio::test]
+    async fn streamed_replies_arrive_in_pieces() {
+        let mock = MockTransport::default().on(":streamGenerateContent", Fixture::ok(stream_body(&["Hel", "lo"])));
+        let (client, _) = mock_client("stream-key", mock);
+        let mut pieces = Vec::new();
+        let reply = client
+            .chat_stream_with_fallback(&models(&["m"]), "hi", |piece| pieces.push(piece.to_string()))
+            .await
+            .unwrap();
+        assert_eq!(pieces, ["Hel", "lo"]);
+        assert_eq!(reply.text, "Hello");
+    }
+
+    #[tokio::test]
+    async fn model_lists_keep_chat_models_and_are_cached() {
+        let listed = r#"{"models": [
+            {"name": "models/gemini-x", "supported_generation_methods": ["generateContent"]},
+            {"name": "models/embedder", "supported_generation_methods": ["embedContent"]}
+        ]}"#;
+        let (client, mock) = mock_client("list-key", MockTransport::default().on("/models", Fixture::ok(listed)));
+        assert_eq!(client.list_models().await.unwrap(), ["models/gemini-x"]);
+        assert_eq!(client.list_models().await.unwrap(), ["models/gemini-x"]);
+        assert_eq!(mock.requests().len(), 1);
+
+        let (client, _) = mock_client("verify-key", MockTransport::default().on("/models", Fixture::ok(models_body(&["a"]))));
+        assert_eq!(client.verify_key().await, KeyCheck::Valid { models: models(&["models/a"]) });
+    }
+
+    #[tokio::test]
+    async fn canned_replies_answer_everything() {
+        let (client, _) = mock_client("canned-key", MockTransport::canned());
+        let reply = client.chat_with_fallback(&models(&["any"]), "hi").await.unwrap();
+        assert_eq!(reply.text, ai_transport::CANNED_REPLY);
+        let (order, _) = client.magic_sort(&models(&["any"]), "").await.unwrap();
+        assert!(order.unwrap().is_empty());
+        assert_eq!(client.list_models().await.unwrap().len(), DEFAULT_MODELS.len());
+    }
+
+    #[test]
+    fn key_failures_are_classified_with_googles_message() {
+        let body = r#"{"error": {"code": 400, "message": "API key not valid. Please pass a valid API key.", "status": "INVALID_ARGUMENT"}}"#;
+        assert_eq!(
+            key_check_for(StatusCode::BAD_REQUEST, body),
+            KeyCheck::Invalid { message: "API key not valid. Please pass a valid API key.".to_string() }
+        );
+        assert!(matches!(key_check_for(StatusCode::FORBIDDEN, "denied"), KeyCheck::Invalid { .. }));
+        assert!(matches!(
+            key_check_for(StatusCode::TOO_MANY_REQUESTS, "{}"),
+            KeyCheck::QuotaExhausted { .. }
+        ));
+        assert_eq!(
+            key_check_for(StatusCode::SERVICE_UNAVAILABLE, "down"),
+            KeyCheck::NetworkError { message: "HTTP 503: down".to_string() }
+        );
+    }
+
+    #[test]
+    fn structured_replies_parse_with_or_without_json_mode() {
+        // JSON mode: the candidate's text is the bare JSON
+        let structured = r#"{"candidates": [{"content": {"role": "model", "parts": [{"text": "[2,0,1]"}]}}],
+            "usageMetadata": {"promptTokenCount": 42, "candidatesTokenCount": 5}}"#;
+        let response: GeminiResponse = serde_json::from_str(structured).unwrap();
+        assert_eq!(usage_of(&response), Some(TokenUsage { tokens_in: 42, tokens_out: 5 }));
+        let indices: Vec<usize> = parse_json_reply(&first_text(response).unwrap()).unwrap();
+        assert_eq!(indices, [2, 0, 1]);
+
+        // Fallback: a model without JSON mode fences its answer anyway
+        let fenced = "```json\n[1, 0]\n```\n";
+        assert_eq!(parse_json_reply::<Vec<usize>>(fenced).unwrap(), [1, 0]);
+        assert!(parse_json_reply::<Vec<usize>>("Sure! Here is the order: 1, 0")
+            .unwrap_err()
+            .starts_with("Failed to parse AI response"));
+    }
+
+    #[test]
+    fn json_mode_rejections_are_retried_without_it() {
+        let rejected = r#"{"error": {"code": 400, "message": "Invalid JSON payload received. Unknown name \"responseSchema\" at 'generation_config': Cannot find field.", "status": "INVALID_ARGUMENT"}}"#;
+        assert!(matches!(
+            classify_error(StatusCode::BAD_REQUEST, rejected),
+            ChatError::StructuredUnsupported(_)
+        ));
+        let mime = r#"{"error": {"code": 400, "message": "JSON mode is not enabled for models/gemini-pro", "status": "INVALID_ARGUMENT"}}"#;
+        assert!(matches!(classify_error(StatusCode::BAD_REQUEST, mime), ChatError::StructuredUnsupported(_)));
+        assert!(matches!(
+            classify_error(StatusCode::BAD_REQUEST, "models/old is not supported for generateContent"),
+            ChatError::ModelUnavailable(_)
+        ));
+        assert!(matches!(classify_error(StatusCode::FORBIDDEN, "responseSchema"), ChatError::Other(_)));
+    }
+
+    #[test]
+    fn keys_are_redacted_and_cached_by_hash() {
+        assert_eq!(redact("GET /models?key=AIzaSecret failed", "AIzaSecret"), "GET /models?key=[redacted] failed");
+        assert_eq!(redact("text", ""), "text");
+
+        cache_models("key-a", &["models/gemini-x".to_string()]);
+        assert_eq!(cached_models("key-a"), Some(vec!["models/gemini-x".to_string()]));
+        assert_eq!(cached_models("key-b"), None);
     }
 }
@@ -0,0 +1,165 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::ai::{AiReply, TokenUsage};
+use crate::textutil;
+
+/// Oldest interactions are dropped past this many
+pub const MAX_ENTRIES: usize = 500;
+/// Grapheme clusters kept from the instruction and from the response
+const PREVIEW_CHARS: usize = 200;
+
+/// One AI request and what came back, for `get_ai_history`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiInteraction {
+    pub at: DateTime<Utc>,
+    /// The command that made the request, e.g. "run_preset"
+    pub command: String,
+    pub model: String,
+    /// SHA-256 of the full prompt sent, so repeats can be spotted without
+    /// keeping the clip text it was built from
+    pub prompt_hash: String,
+    /// Start of what was asked: the question, preset instruction or style
+    pub instruction: String,
+    /// Start of the reply; None when sensitive clips went into the prompt
+    #[serde(default)]
+    pub response_preview: Option<String>,
+    #[serde(flatten)]
+    pub usage: TokenUsage,
+    /// Clips sent to the model or changed by the reply
+    #[serde(default)]
+    pub clip_ids: Vec<String>,
+}
+
+/// What a command asked the model, alongside the reply it got
+pub struct AiRequest<'a> {
+    pub instruction: &'a str,
+    pub prompt: &'a str,
+    pub clip_ids: Vec<String>,
+    /// A sensitive clip went into the prompt, so the reply isn't previewed
+    pub sensitive: bool,
+}
+
+/// Narrows `get_ai_history`; every field given must match
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AiHistoryFilter {
+    pub command: Option<String>,
+    pub model: Option<String>,
+    /// Only interactions involving this clip
+    pub clip_id: Option<String>,
+    /// Case-insensitive text in the instruction or response preview
+    pub query: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+}
+
+impl AiHistoryFilter {
+    fn matches(&self, entry: &AiInteraction) -> bool {
+        let query = self.query.as_ref().map(|q| q.to_lowercase());
+        self.command.as_ref().is_none_or(|c| &entry.command == c)
+            && self.model.as_ref().is_none_or(|m| &entry.model == m)
+            && self.clip_id.as_ref().is_none_or(|id| entry.clip_ids.contains(id))
+            && self.since.is_none_or(|since| entry.at >= since)
+            && query.is_none_or(|q| {
+                entry.instruction.to_lowercase().contains(&q)
+                    || entry.response_preview.as_ref().is_some_and(|r| r.to_lowercase().contains(&q))
+            })
+    }
+}
+
+/// Add an interaction, dropping the oldest past `MAX_ENTRIES`
+pub fn record(history: &mut Vec<AiInteraction>, command: &str, request: AiRequest, reply: &AiReply, now: DateTime<Utc>) {
+    let prompt_hash = Sha256::digest(request.prompt.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    history.push(AiInteraction {
+        at: now,
+        command: command.to_string(),
+        model: reply.model.clone(),
+        prompt_hash,
+        instruction: textutil::truncate(request.instruction, PREVIEW_CHARS).to_string(),
+        response_preview: (!request.sensitive)
+            .then(|| textutil::truncate(reply.text.trim(), PREVIEW_CHARS).to_string()),
+        usage: reply.usage,
+        clip_ids: request.clip_ids,
+    });
+    if history.len() > MAX_ENTRIES {
+        history.drain(..history.len() - MAX_ENTRIES);
+    }
+}
+
+/// Matching interactions, newest first, at most `limit` of them
+pub fn query(history: &[AiInteraction], limit: usize, filter: &AiHistoryFilter) -> Vec<AiInteraction> {
+    history
+        .iter()
+        .rev()
+        .filter(|e| filter.matches(e))
+        .take(limit)
+        .cloned()
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    fn reply(text: &str) -> AiReply {
+        AiReply {
+            text: text.to_string(),
+            model: "gemini-flash-latest".to_string(),
+            usage: TokenUsage { tokens_in: 120, tokens_out: 30 },
+        }
+    }
+
+    fn request<'a>(instruction: &'a str, clip_ids: &[&str], sensitive: bool) -> AiRequest<'a> {
+        AiRequest {
+            instruction,
+            prompt: instruction,
+            clip_ids: clip_ids.iter().map(|id| id.to_string()).collect(),
+            sensitive,
+        }
+    }
+
+    #[test]
+    fn history_is_capped_and_previews_are_short() {
+        let now = Utc::now();
+        let mut history = Vec::new();
+        let long = "Summarize ".repeat(50);
+        for i in 0..MAX_ENTRIES + 3 {
+            let at = now + Duration::seconds(i as i64);
+            record(&mut history, "run_preset", request(&long, &["a"], false), &reply(&long), at);
+        }
+        assert_eq!(history.len(), MAX_ENTRIES);
+        assert_eq!(history[0].at, now + Duration::seconds(3));
+        assert_eq!(textutil::grapheme_count(&history[0].instruction), PREVIEW_CHARS);
+        assert_eq!(history[0].prompt_hash.len(), 64);
+        assert_eq!(history[0].usage.tokens_in, 120);
+
+        record(&mut history, "chat_submit", request("What's the PIN?", &["b"], true), &reply("1234"), now);
+        assert_eq!(history.last().unwrap().response_preview, None);
+    }
+
+    #[test]
+    fn queries_filter_and_come_newest_first() {
+        let now = Utc::now();
+        let mut history = Vec::new();
+        record(&mut history, "chat_submit", request("Who wrote this?", &["a", "b"], false), &reply("Ada"), now);
+        record(&mut history, "run_preset", request("Fix grammar", &["b"], false), &reply("Fixed"), now + Duration::seconds(1));
+        record(&mut history, "chat_submit", request("Translate", &["c"], false), &reply("Hola"), now + Duration::seconds(2));
+
+        let all = query(&history, 10, &AiHistoryFilter::default());
+        assert_eq!(all.iter().map(|e| e.instruction.as_str()).collect::<Vec<_>>(), ["Translate", "Fix grammar", "Who wrote this?"]);
+        assert_eq!(query(&history, 1, &AiHistoryFilter::default()).len(), 1);
+
+        let chat = AiHistoryFilter { command: Some("chat_submit".to_string()), ..Default::default() };
+        assert_eq!(query(&history, 10, &chat).len(), 2);
+        let clip_b = AiHistoryFilter { clip_id: Some("b".to_string()), ..Default::default() };
+        assert_eq!(query(&history, 10, &clip_b).len(), 2);
+        let text = AiHistoryFilter { query: Some("HOLA".to_string()), ..Default::default() };
+        assert_eq!(query(&history, 10, &text)[0].instruction, "Translate");
+        let recent = AiHistoryFilter { since: Some(now + Duration::seconds(1)), ..chat };
+        assert_eq!(query(&history, 10, &recent).len(), 1);
+    }
+}
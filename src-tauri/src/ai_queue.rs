@@ -0,0 +1,325 @@
+use serde::Serialize;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tokio::sync::{oneshot, Notify};
+
+/// Attempts per job before it lands in the failed list
+const MAX_ATTEMPTS: u32 = 3;
+/// First retry delay; doubles each attempt
+const BACKOFF_BASE: Duration = Duration::from_secs(2);
+
+pub type JobFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+/// A job is a factory so it can be run again on retry or re-run
+pub type JobFn = Arc<dyn Fn() -> JobFuture + Send + Sync>;
+/// Where a submitted job's final outcome goes
+type Done = oneshot::Sender<Result<(), String>>;
+
+/// How hard the queue may hit the AI provider
+#[derive(Debug, Clone, Copy)]
+pub struct QueueLimits {
+    pub max_concurrency: usize,
+    /// Minimum gap between the start of two requests
+    pub min_delay: Duration,
+}
+
+impl Default for QueueLimits {
+    fn default() -> Self {
+        Self {
+            max_concurrency: 1,
+            min_delay: Duration::ZERO,
+        }
+    }
+}
+
+/// A job on its way through the queue
+struct Queued {
+    id: u64,
+    label: String,
+    generation: u64,
+    max_attempts: u32,
+    job: JobFn,
+    done: Option<Done>,
+}
+
+struct FailedEntry {
+    id: u64,
+    label: String,
+    error: String,
+    attempts: u32,
+    job: JobFn,
+}
+
+#[derive(Default)]
+struct QueueState {
+    limits: QueueLimits,
+    /// Bumped by cancel; jobs from an older generation stop
+    generation: u64,
+    next_id: u64,
+    pending: usize,
+    running: usize,
+    completed: usize,
+    cancelled: usize,
+    failed: Vec<FailedEntry>,
+    next_start: Option<Instant>,
+}
+
+#[derive(Default)]
+struct Inner {
+    state: Mutex<QueueState>,
+    slot_freed: Notify,
+}
+
+/// A job that ran out of retries
+#[derive(Debug, Clone, Serialize)]
+pub struct FailedJob {
+    pub id: u64,
+    pub label: String,
+    pub error: String,
+    pub attempts: u32,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AiQueueStatus {
+    pub pending: usize,
+    pub running: usize,
+    pub completed: usize,
+    pub failed: usize,
+    pub cancelled: usize,
+    pub failed_jobs: Vec<FailedJob>,
+}
+
+/// Payload of the `ai-job-progress` event
+#[derive(Debug, Clone, Serialize)]
+struct JobProgress<'a> {
+    id: u64,
+    label: &'a str,
+    state: &'static str,
+    attempt: u32,
+    error: Option<&'a str>,
+}
+
+/// Shared queue for batch AI work with bounded concurrency and request pacing
+#[derive(Clone, Default)]
+pub struct AiQueue {
+    inner: Arc<Inner>,
+}
+
+impl AiQueue {
+    /// Queue a job and return its id; it runs once a slot is free
+    pub fn enqueue(&self, app: &AppHandle, label: String, limits: QueueLimits, job: JobFn) -> u64 {
+        self.spawn(app, label, limits, MAX_ATTEMPTS, job, None)
+    }
+
+    /// Queue a job whose caller waits for it. It gets `max_attempts` tries,
+    /// and its outcome goes to the returned receiver rather than the failed
+    /// list; a cancelled job reports an error.
+    pub fn submit(
+        &self,
+        app: &AppHandle,
+        label: String,
+        limits: QueueLimits,
+        max_attempts: u32,
+        job: JobFn,
+    ) -> oneshot::Receiver<Result<(), String>> {
+        let (tx, rx) = oneshot::channel();
+        self.spawn(app, label, limits, max_attempts, job, Some(tx));
+        rx
+    }
+
+    fn spawn(
+        &self,
+        app: &AppHandle,
+        label: String,
+        limits: QueueLimits,
+        max_attempts: u32,
+        job: JobFn,
+        done: Option<Done>,
+    ) -> u64 {
+        let (id, generation) = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.limits = limits;
+            state.next_id += 1;
+            state.pending += 1;
+            (state.next_id, state.generation)
+        };
+        // Raised limits may let waiting jobs start
+        self.inner.slot_freed.notify_waiters();
+
+        emit_progress(app, id, &label, "queued", 0, None);
+        let queue = self.clone();
+        let app = app.clone();
+        tauri::async_runtime::spawn(async move {
+            let queued = Queued {
+                id,
+                label,
+                generation,
+                max_attempts,
+                job,
+                done,
+            };
+            queue.run(app, queued).await;
+        });
+        id
+    }
+
+    /// Counts per state plus the jobs that failed for good
+    pub fn status(&self) -> AiQueueStatus {
+        let state = self.inner.state.lock().unwrap();
+        AiQueueStatus {
+            pending: state.pending,
+            running: state.running,
+            completed: state.completed,
+            failed: state.failed.len(),
+            cancelled: state.cancelled,
+            failed_jobs: state
+                .failed
+                .iter()
+                .map(|f| FailedJob {
+                    id: f.id,
+                    label: f.label.clone(),
+                    error: f.error.clone(),
+                    attempts: f.attempts,
+                })
+                .collect(),
+        }
+    }
+
+    /// Stop all pending jobs (and running ones at their next attempt)
+    pub fn cancel(&self) -> usize {
+        let pending = {
+            let mut state = self.inner.state.lock().unwrap();
+            state.generation += 1;
+            state.pending
+        };
+        self.inner.slot_freed.notify_waiters();
+        pending
+    }
+
+    /// Queue every failed job again, returning how many were re-queued
+    pub fn retry_failed(&self, app: &AppHandle, limits: QueueLimits) -> usize {
+        let failed = std::mem::take(&mut self.inner.state.lock().unwrap().failed);
+        let count = failed.len();
+        for entry in failed {
+            self.enqueue(app, entry.label, limits, entry.job);
+        }
+        count
+    }
+
+    fn is_cancelled(&self, generation: u64) -> bool {
+        self.inner.state.lock().unwrap().generation != generation
+    }
+
+    async fn run(&self, app: AppHandle, queued: Queued) {
+        let Queued {
+            id,
+            label,
+            generation,
+            max_attempts,
+            job,
+            done,
+        } = queued;
+        // Wait for a free slot
+        loop {
+            let slot_freed = self.inner.slot_freed.notified();
+            {
+                let mut state = self.inner.state.lock().unwrap();
+                if state.generation != generation {
+                    state.pending -= 1;
+                    state.cancelled += 1;
+                    drop(state);
+                    emit_progress(&app, id, &label, "cancelled", 0, None);
+                    if let Some(done) = done {
+                        let _ = done.send(Err("Cancelled".to_string()));
+                    }
+                    return;
+                }
+                if state.running < state.limits.max_concurrency.max(1) {
+                    state.pending -= 1;
+                    state.running += 1;
+                    break;
+                }
+            }
+            slot_freed.await;
+        }
+
+        let mut attempt = 0;
+        let outcome = loop {
+            attempt += 1;
+
+            // Space request starts at least min_delay apart across all jobs
+            let wait = {
+                let mut state = self.inner.state.lock().unwrap();
+                let now = Instant::now();
+                let start = state.next_start.map_or(now, |next| next.max(now));
+                state.next_start = Some(start + state.limits.min_delay);
+                start - now
+            };
+            tokio::time::sleep(wait).await;
+
+            if self.is_cancelled(generation) {
+                break None;
+            }
+
+            emit_progress(&app, id, &label, "running", attempt, None);
+            match job().await {
+                Ok(()) => break Some(Ok(())),
+                Err(e) if attempt < max_attempts => {
+                    emit_progress(&app, id, &label, "retrying", attempt, Some(&e));
+                    tokio::time::sleep(BACKOFF_BASE * 2u32.pow(attempt - 1)).await;
+                }
+                Err(e) => break Some(Err(e)),
+            }
+        };
+
+        {
+            let mut state = self.inner.state.lock().unwrap();
+            state.running -= 1;
+            match &outcome {
+                None => state.cancelled += 1,
+                Some(Ok(())) => state.completed += 1,
+                // A waiting caller deals with the failure itself
+                Some(Err(_)) if done.is_some() => {}
+                Some(Err(e)) => state.failed.push(FailedEntry {
+                    id,
+                    label: label.clone(),
+                    error: e.clone(),
+                    attempts: attempt,
+                    job,
+                }),
+            }
+        }
+        self.inner.slot_freed.notify_waiters();
+
+        match &outcome {
+            None => emit_progress(&app, id, &label, "cancelled", attempt, None),
+            Some(Ok(())) => emit_progress(&app, id, &label, "completed", attempt, None),
+            Some(Err(e)) => emit_progress(&app, id, &label, "failed", attempt, Some(e)),
+        }
+        if let Some(done) = done {
+            let _ = done.send(outcome.unwrap_or_else(|| Err("Cancelled".to_string())));
+        }
+    }
+}
+
+fn emit_progress(
+    app: &AppHandle,
+    id: u64,
+    label: &str,
+    state: &'static str,
+    attempt: u32,
+    error: Option<&str>,
+) {
+    let _ = app.emit(
+        "ai-job-progress",
+        JobProgress {
+            id,
+            label,
+            state,
+            attempt,
+            error,
+        },
+    );
+}
@@ -0,0 +1,448 @@
+use reqwest::{Client, StatusCode};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::future::Future;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::Duration;
+
+/// `1` answers every AI request with canned replies; a directory replays
+/// the fixtures recorded there
+const MOCK_ENV: &str = "STACK_AI_MOCK";
+/// A directory to record real responses into, as fixtures for `MOCK_ENV`
+const RECORD_ENV: &str = "STACK_AI_RECORD";
+/// What the canned transport says to any prompt
+pub const CANNED_REPLY: &str = "This is a canned reply; STACK_AI_MOCK is set.";
+
+pub type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// One call to the Gemini API: a GET, or a POST of `body` when there is one
+#[derive(Debug, Clone)]
+pub struct AiRequest {
+    pub url: String,
+    pub body: Option<Value>,
+    pub timeout: Option<Duration>,
+}
+
+/// Why a request got no response at all
+#[derive(Debug, Clone, PartialEq)]
+pub enum TransportError {
+    TimedOut,
+    Failed(String),
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::TimedOut => write!(f, "timed out"),
+            Self::Failed(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+/// A response body, read a piece at a time
+pub trait ResponseBody: Send {
+    /// The next piece, None once the body is done
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>, String>>;
+}
+
+/// A response's status and its body, read whole or as it arrives
+pub struct AiResponse {
+    pub status: StatusCode,
+    body: Box<dyn ResponseBody>,
+}
+
+impl AiResponse {
+    pub fn new(status: StatusCode, body: Box<dyn ResponseBody>) -> Self {
+        Self { status, body }
+    }
+
+    pub async fn chunk(&mut self) -> Result<Option<Vec<u8>>, String> {
+        self.body.chunk().await
+    }
+
+    pub async fn text(mut self) -> Result<String, String> {
+        let mut bytes = Vec::new();
+        while let Some(chunk) = self.chunk().await? {
+            bytes.extend_from_slice(&chunk);
+        }
+        Ok(String::from_utf8_lossy(&bytes).into_owned())
+    }
+
+    pub async fn json<T: DeserializeOwned>(self) -> Result<T, String> {
+        serde_json::from_str(&self.text().await?).map_err(|e| e.to_string())
+    }
+}
+
+/// How the Gemini client reaches the API: over HTTP, or from fixtures in
+/// tests and offline development
+pub trait AiTransport: Send + Sync + fmt::Debug {
+    fn send(&self, request: AiRequest) -> BoxFuture<'_, Result<AiResponse, TransportError>>;
+}
+
+/// The transport the environment asks for, shared by every client:
+/// `STACK_AI_MOCK=1` gives canned replies, `STACK_AI_MOCK=<dir>` replays
+/// fixtures from `dir`, `STACK_AI_RECORD=<dir>` records real responses
+/// into `dir`, and otherwise requests go to Google
+pub fn from_env() -> Arc<dyn AiTransport> {
+    static TRANSPORT: OnceLock<Arc<dyn AiTransport>> = OnceLock::new();
+    TRANSPORT
+        .get_or_init(|| {
+            let mock = std::env::var(MOCK_ENV).ok().filter(|v| !v.is_empty());
+            let record = std::env::var(RECORD_ENV).ok().filter(|v| !v.is_empty());
+            match (mock, record) {
+                (Some(mock), _) if mock == "1" => Arc::new(MockTransport::canned()),
+                (Some(dir), _) => match MockTransport::replaying(Path::new(&dir)) {
+                    Ok(mock) => Arc::new(mock),
+                    Err(e) => {
+                        eprintln!("Can't replay AI fixtures, using canned replies: {}", e);
+                        Arc::new(MockTransport::canned())
+                    }
+                },
+                (None, Some(dir)) => Arc::new(RecordingTransport::new(PathBuf::from(dir))),
+                (None, None) => Arc::new(HttpTransport::default()),
+            }
+        })
+        .clone()
+}
+
+/// A stand-in API key while `MOCK_ENV` is set, so the AI commands run
+/// without a real one
+pub fn mock_api_key() -> Option<String> {
+    std::env::var(MOCK_ENV).ok().filter(|v| !v.is_empty()).map(|_| "mock".to_string())
+}
+
+// ==================== HTTP ====================
+
+/// Requests over HTTP with reqwest
+#[derive(Debug, Default)]
+pub struct HttpTransport {
+    client: Client,
+}
+
+impl AiTransport for HttpTransport {
+    fn send(&self, request: AiRequest) -> BoxFuture<'_, Result<AiResponse, TransportError>> {
+        Box::pin(async move {
+            let mut builder = match &request.body {
+                Some(body) => self.client.post(&request.url).json(body),
+                None => self.client.get(&request.url),
+            };
+            if let Some(timeout) = request.timeout {
+                builder = builder.timeout(timeout);
+            }
+            // Errors drop the URL, which carries the key
+            let response = builder.send().await.map_err(|e| {
+                if e.is_timeout() {
+                    TransportError::TimedOut
+                } else {
+                    TransportError::Failed(e.without_url().to_string())
+                }
+            })?;
+            Ok(AiResponse::new(response.status(), Box::new(HttpBody(response))))
+        })
+    }
+}
+
+struct HttpBody(reqwest::Response);
+
+impl ResponseBody for HttpBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>, String>> {
+        Box::pin(async move {
+            let chunk = self.0.chunk().await.map_err(|e| e.without_url().to_string())?;
+            Ok(chunk.map(|bytes| bytes.to_vec()))
+        })
+    }
+}
+
+// ==================== FIXTURES ====================
+
+/// A recorded or canned response
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub status: u16,
+    pub body: String,
+}
+
+impl Fixture {
+    pub fn ok(body: impl Into<String>) -> Self {
+        Self { status: 200, body: body.into() }
+    }
+
+    /// A body handed out a line at a time, like a stream arriving
+    fn into_response(self) -> AiResponse {
+        let status = StatusCode::from_u16(self.status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+        let chunks = self.body.split_inclusive('\n').map(|line| line.as_bytes().to_vec()).collect();
+        AiResponse::new(status, Box::new(FixtureBody(chunks)))
+    }
+}
+
+struct FixtureBody(VecDeque<Vec<u8>>);
+
+impl ResponseBody for FixtureBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>, String>> {
+        let next = self.0.pop_front();
+        Box::pin(async move { Ok(next) })
+    }
+}
+
+/// A generateContent response whose first candidate says `text`
+pub fn reply_body(text: &str) -> String {
+    json!({
+        "candidates": [{ "content": { "role": "model", "parts": [{ "text": text }] } }],
+        "usageMetadata": { "promptTokenCount": 0, "candidatesTokenCount": 0 },
+    })
+    .to_string()
+}
+
+/// A streamGenerateContent response sending `pieces` as separate events
+pub fn stream_body(pieces: &[&str]) -> String {
+    pieces
+        .iter()
+        .map(|piece| format!("data: {}\n\n", reply_body(piece)))
+        .collect()
+}
+
+/// A model list offering `models` for generateContent
+pub fn models_body(models: &[&str]) -> String {
+    let models: Vec<Value> = models
+        .iter()
+        .map(|name| json!({ "name": format!("models/{}", name), "supported_generation_methods": ["generateContent"] }))
+        .collect();
+    json!({ "models": models }).to_string()
+}
+
+/// `url` without its `key` parameter
+fn strip_key(url: &str) -> String {
+    let Ok(mut parsed) = url::Url::parse(url) else {
+        return url.to_string();
+    };
+    let pairs: Vec<(String, String)> = parsed
+        .query_pairs()
+        .filter(|(name, _)| name != "key")
+        .map(|(name, value)| (name.into_owned(), value.into_owned()))
+        .collect();
+    parsed.set_query(None);
+    if !pairs.is_empty() {
+        parsed.query_pairs_mut().extend_pairs(pairs);
+    }
+    parsed.to_string()
+}
+
+/// File name a request's fixture is recorded under: a hash of its URL
+/// (without the key) and body
+pub fn fixture_name(request: &AiRequest) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(strip_key(&request.url));
+    if let Some(body) = &request.body {
+        hasher.update(body.to_string());
+    }
+    let hash: String = hasher.finalize()[..8].iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.json", hash)
+}
+
+// ==================== MOCK ====================
+
+/// Answers requests from fixtures, without the network. Fixtures queued
+/// with `on` come first, then recorded ones, then canned replies if enabled.
+#[derive(Debug, Default)]
+pub struct MockTransport {
+    /// Fixtures for URLs containing each fragment, used in turn; the last repeats
+    routes: Mutex<Vec<(String, VecDeque<Fixture>)>>,
+    recorded: HashMap<String, Fixture>,
+    canned: bool,
+    /// Every request seen, key removed
+    requests: Mutex<Vec<AiRequest>>,
+}
+
+impl MockTransport {
+    /// Canned answers for everything: the default models, and a placeholder
+    /// reply (an empty JSON array when JSON is asked for)
+    pub fn canned() -> Self {
+        Self { canned: true, ..Default::default() }
+    }
+
+    /// The fixtures `RecordingTransport` wrote to `dir`
+    pub fn replaying(dir: &Path) -> Result<Self, String> {
+        let entries = std::fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+        let mut recorded = HashMap::new();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let text = std::fs::read_to_string(&path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+                let fixture: Fixture =
+                    serde_json::from_str(&text).map_err(|e| format!("Bad fixture {}: {}", path.display(), e))?;
+                let name = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+                recorded.insert(name, fixture);
+            }
+        }
+        Ok(Self { recorded, ..Default::default() })
+    }
+
+    /// Answer requests whose URL contains `fragment` with `fixture`; several
+    /// for the same fragment are used in the order given
+    #[cfg(test)]
+    pub fn on(self, fragment: &str, fixture: Fixture) -> Self {
+        {
+            let mut routes = self.routes.lock().unwrap();
+            match routes.iter_mut().find(|(f, _)| f == fragment) {
+                Some((_, fixtures)) => fixtures.push_back(fixture),
+                None => routes.push((fragment.to_string(), VecDeque::from([fixture]))),
+            }
+        }
+        self
+    }
+
+    /// Requests seen so far, with the key stripped from their URLs
+    #[cfg(test)]
+    pub fn requests(&self) -> Vec<AiRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+
+    fn answer(&self, request: &AiRequest) -> Option<Fixture> {
+        let mut routes = self.routes.lock().unwrap();
+        if let Some((_, fixtures)) = routes.iter_mut().find(|(f, _)| request.url.contains(f.as_str())) {
+            return if fixtures.len() > 1 { fixtures.pop_front() } else { fixtures.front().cloned() };
+        }
+        drop(routes);
+        if let Some(fixture) = self.recorded.get(&fixture_name(request)) {
+            return Some(fixture.clone());
+        }
+        self.canned.then(|| canned_answer(request))
+    }
+}
+
+fn canned_answer(request: &AiRequest) -> Fixture {
+    let Some(body) = &request.body else {
+        return Fixture::ok(models_body(&crate::ai::DEFAULT_MODELS));
+    };
+    if request.url.contains(":streamGenerateContent") {
+        return Fixture::ok(stream_body(&[CANNED_REPLY]));
+    }
+    if body.get("generationConfig").is_some() {
+        return Fixture::ok(reply_body("[]"));
+    }
+    Fixture::ok(reply_body(CANNED_REPLY))
+}
+
+impl AiTransport for MockTransport {
+    fn send(&self, request: AiRequest) -> BoxFuture<'_, Result<AiResponse, TransportError>> {
+        let answer = self.answer(&request);
+        let url = strip_key(&request.url);
+        self.requests.lock().unwrap().push(AiRequest { url: url.clone(), ..request });
+        Box::pin(async move {
+            answer
+                .map(Fixture::into_response)
+                .ok_or_else(|| TransportError::Failed(format!("No fixture for {}", url)))
+        })
+    }
+}
+
+// ==================== RECORDING ====================
+
+/// Sends requests over HTTP and writes each response, once read to the
+/// end, to a fixture file for `MockTransport::replaying`
+#[derive(Debug)]
+pub struct RecordingTransport {
+    inner: HttpTransport,
+    dir: PathBuf,
+}
+
+impl RecordingTransport {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { inner: HttpTransport::default(), dir }
+    }
+}
+
+impl AiTransport for RecordingTransport {
+    fn send(&self, request: AiRequest) -> BoxFuture<'_, Result<AiResponse, TransportError>> {
+        Box::pin(async move {
+            let path = self.dir.join(fixture_name(&request));
+            let response = self.inner.send(request).await?;
+            let status = response.status;
+            let body = RecordingBody { inner: response.body, bytes: Vec::new(), status, path };
+            Ok(AiResponse::new(status, Box::new(body)))
+        })
+    }
+}
+
+struct RecordingBody {
+    inner: Box<dyn ResponseBody>,
+    bytes: Vec<u8>,
+    status: StatusCode,
+    path: PathBuf,
+}
+
+impl RecordingBody {
+    fn save(&self) -> Result<(), String> {
+        let fixture = Fixture {
+            status: self.status.as_u16(),
+            body: String::from_utf8_lossy(&self.bytes).into_owned(),
+        };
+        let text = serde_json::to_string_pretty(&fixture).map_err(|e| e.to_string())?;
+        std::fs::create_dir_all(self.dir()).map_err(|e| e.to_string())?;
+        std::fs::write(&self.path, text).map_err(|e| e.to_string())
+    }
+
+    fn dir(&self) -> &Path {
+        self.path.parent().unwrap_or(Path::new("."))
+    }
+}
+
+impl ResponseBody for RecordingBody {
+    fn chunk(&mut self) -> BoxFuture<'_, Result<Option<Vec<u8>>, String>> {
+        Box::pin(async move {
+            let chunk = self.inner.chunk().await?;
+            match &chunk {
+                Some(bytes) => self.bytes.extend_from_slice(bytes),
+                None => {
+                    if let Err(e) = self.save() {
+                        eprintln!("Failed to record AI fixture {}: {}", self.path.display(), e);
+                    }
+                }
+            }
+            Ok(chunk)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn recorded_fixtures_replay_by_request_without_the_key() {
+        let request = |key: &str| AiRequest {
+            url: format!("https://example.test/v1/models/m:generateContent?key={}", key),
+            body: Some(json!({ "contents": [] })),
+            timeout: None,
+        };
+        assert_eq!(fixture_name(&request("one")), fixture_name(&request("two")));
+        assert_eq!(strip_key(&request("secret").url), "https://example.test/v1/models/m:generateContent");
+
+        // What a recording leaves behind, read to the end
+        let dir = tempfile::tempdir().unwrap();
+        let recorded = Fixture { status: 200, body: reply_body("from the recording") };
+        let source = MockTransport::default().on("generateContent", recorded);
+        let response = source.send(request("one")).await.unwrap();
+        let body = RecordingBody {
+            inner: response.body,
+            bytes: Vec::new(),
+            status: response.status,
+            path: dir.path().join(fixture_name(&request("one"))),
+        };
+        let text = AiResponse::new(StatusCode::OK, Box::new(body)).text().await.unwrap();
+        assert_eq!(text, reply_body("from the recording"));
+
+        let replay = MockTransport::replaying(dir.path()).unwrap();
+        let replayed = replay.send(request("other-key")).await.unwrap();
+        assert_eq!(replayed.text().await.unwrap(), reply_body("from the recording"));
+        assert!(replay.requests()[0].url.ends_with(":generateContent"));
+
+        let unknown = AiRequest { body: None, ..request("one") };
+        assert!(matches!(replay.send(unknown).await, Err(TransportError::Failed(_))));
+    }
+}
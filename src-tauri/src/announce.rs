@@ -0,0 +1,186 @@
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+
+use crate::textutil;
+
+/// Longest announcement, in characters; screen readers read the whole string
+pub const MAX_CHARS: usize = 120;
+
+/// Language of the announcements sent with events
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    #[default]
+    En,
+    De,
+}
+
+/// Something an event tells a screen reader user
+#[derive(Debug, Clone, Copy)]
+pub enum Message<'a> {
+    Captured { chars: usize, app: &'a str },
+    /// No size or app, like the event itself
+    CapturedSensitive,
+    CapturedBatch { count: usize },
+    BlockedPaused,
+    BlockedExcludedApp,
+    FailedBinary,
+    FailedEmpty,
+    Held { app: &'a str },
+    Skipped { rule: &'a str },
+    RateLimited { app: &'a str },
+    CopiedAll { count: usize },
+    PastedAll { count: usize },
+    NothingToCopy,
+    PastedTopClip,
+    NoWindowToReturnTo,
+    NoClipsToPaste,
+}
+
+/// Every announcement for one language. `{n}`, `{app}` and `{rule}` are
+/// filled in; `{clips}` and `{chars}` become the counted phrases.
+struct Strings {
+    clip_one: &'static str,
+    clip_other: &'static str,
+    char_one: &'static str,
+    char_other: &'static str,
+    captured: &'static str,
+    captured_sensitive: &'static str,
+    captured_batch: &'static str,
+    blocked_paused: &'static str,
+    blocked_excluded_app: &'static str,
+    failed_binary: &'static str,
+    failed_empty: &'static str,
+    held: &'static str,
+    skipped: &'static str,
+    rate_limited: &'static str,
+    copied_all: &'static str,
+    pasted_all: &'static str,
+    nothing_to_copy: &'static str,
+    pasted_top_clip: &'static str,
+    no_window_to_return_to: &'static str,
+    no_clips_to_paste: &'static str,
+}
+
+const EN: Strings = Strings {
+    clip_one: "1 clip",
+    clip_other: "{n} clips",
+    char_one: "1 character",
+    char_other: "{n} characters",
+    captured: "Captured {chars} from {app}",
+    captured_sensitive: "Captured a sensitive clip",
+    captured_batch: "Captured {clips}",
+    blocked_paused: "Capture blocked: capture is paused",
+    blocked_excluded_app: "Capture blocked: application excluded",
+    failed_binary: "Capture failed: the clipboard held binary data",
+    failed_empty: "Capture failed: nothing to capture",
+    held: "Capture from {app} held: not in the focus list",
+    skipped: "Not captured: rule {rule}",
+    rate_limited: "Not captured: {app} is copying too fast",
+    copied_all: "Copied {clips}",
+    pasted_all: "Pasted {clips}",
+    nothing_to_copy: "Nothing to copy",
+    pasted_top_clip: "Pasted the top clip",
+    no_window_to_return_to: "Tray action failed: no window to return to",
+    no_clips_to_paste: "Tray action failed: no clips to paste",
+};
+
+const DE: Strings = Strings {
+    clip_one: "1 Clip",
+    clip_other: "{n} Clips",
+    char_one: "1 Zeichen",
+    char_other: "{n} Zeichen",
+    captured: "{chars} aus {app} erfasst",
+    captured_sensitive: "Vertraulichen Clip erfasst",
+    captured_batch: "{clips} erfasst",
+    blocked_paused: "Erfassung blockiert: Erfassung ist pausiert",
+    blocked_excluded_app: "Erfassung blockiert: Anwendung ausgeschlossen",
+    failed_binary: "Erfassung fehlgeschlagen: Die Zwischenablage enthielt Binärdaten",
+    failed_empty: "Erfassung fehlgeschlagen: Nichts zu erfassen",
+    held: "Erfassung aus {app} zurückgehalten: nicht in der Fokusliste",
+    skipped: "Nicht erfasst: Regel {rule}",
+    rate_limited: "Nicht erfasst: {app} kopiert zu schnell",
+    copied_all: "{clips} kopiert",
+    pasted_all: "{clips} eingefügt",
+    nothing_to_copy: "Nichts zu kopieren",
+    pasted_top_clip: "Obersten Clip eingefügt",
+    no_window_to_return_to: "Tray-Aktion fehlgeschlagen: Kein Fenster zum Zurückkehren",
+    no_clips_to_paste: "Tray-Aktion fehlgeschlagen: Keine Clips zum Einfügen",
+};
+
+fn strings(language: Language) -> &'static Strings {
+    match language {
+        Language::En => &EN,
+        Language::De => &DE,
+    }
+}
+
+static LANGUAGE: Mutex<Language> = Mutex::new(Language::En);
+
+/// Announce in this language from now on
+pub fn configure(language: Language) {
+    *LANGUAGE.lock().unwrap() = language;
+}
+
+/// The announcement for `message` in the configured language. Every event
+/// that carries an announcement gets it from here.
+pub fn text(message: Message) -> String {
+    let language = *LANGUAGE.lock().unwrap();
+    compose(language, message)
+}
+
+/// `message` in `language`, capped at `MAX_CHARS`
+pub fn compose(language: Language, message: Message) -> String {
+    let s = strings(language);
+    let counted = |one: &str, other: &str, n: usize| {
+        if n == 1 {
+            one.to_string()
+        } else {
+            other.replace("{n}", &n.to_string())
+        }
+    };
+    let text = match message {
+        Message::Captured { chars, app } => s
+            .captured
+            .replace("{chars}", &counted(s.char_one, s.char_other, chars))
+            .replace("{app}", app),
+        Message::CapturedSensitive => s.captured_sensitive.to_string(),
+        Message::CapturedBatch { count } => s.captured_batch.replace("{clips}", &counted(s.clip_one, s.clip_other, count)),
+        Message::BlockedPaused => s.blocked_paused.to_string(),
+        Message::BlockedExcludedApp => s.blocked_excluded_app.to_string(),
+        Message::FailedBinary => s.failed_binary.to_string(),
+        Message::FailedEmpty => s.failed_empty.to_string(),
+        Message::Held { app } => s.held.replace("{app}", app),
+        Message::Skipped { rule } => s.skipped.replace("{rule}", rule),
+        Message::RateLimited { app } => s.rate_limited.replace("{app}", app),
+        Message::CopiedAll { count } => s.copied_all.replace("{clips}", &counted(s.clip_one, s.clip_other, count)),
+        Message::PastedAll { count } => s.pasted_all.replace("{clips}", &counted(s.clip_one, s.clip_other, count)),
+        Message::NothingToCopy => s.nothing_to_copy.to_string(),
+        Message::PastedTopClip => s.pasted_top_clip.to_string(),
+        Message::NoWindowToReturnTo => s.no_window_to_return_to.to_string(),
+        Message::NoClipsToPaste => s.no_clips_to_paste.to_string(),
+    };
+    textutil::truncate(&text, MAX_CHARS).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn announcements_are_counted_localized_and_capped() {
+        let word = Message::Captured { chars: 214, app: "Microsoft Word" };
+        assert_eq!(compose(Language::En, word), "Captured 214 characters from Microsoft Word");
+        assert_eq!(compose(Language::De, word), "214 Zeichen aus Microsoft Word erfasst");
+        assert_eq!(compose(Language::En, Message::CopiedAll { count: 1 }), "Copied 1 clip");
+        assert_eq!(compose(Language::De, Message::PastedAll { count: 3 }), "3 Clips eingefügt");
+        assert_eq!(
+            compose(Language::En, Message::BlockedExcludedApp),
+            "Capture blocked: application excluded"
+        );
+
+        let long_app = "e\u{301}".repeat(500);
+        let capped = compose(Language::En, Message::Held { app: &long_app });
+        assert_eq!(textutil::grapheme_count(&capped), MAX_CHARS);
+    }
+}
@@ -0,0 +1,137 @@
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::collections::HashSet;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+use crate::paths;
+
+const ASSETS_DIR: &str = "assets";
+/// Unreferenced assets younger than this are kept, so a file stored just
+/// before its clip is saved isn't collected out from under it
+const GC_GRACE_PERIOD: Duration = Duration::from_secs(10 * 60);
+
+/// Asset store totals
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AssetStats {
+    pub count: usize,
+    pub total_bytes: u64,
+    pub orphan_count: usize,
+    pub orphan_bytes: u64,
+}
+
+/// Result of a garbage collection pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct GcReport {
+    pub removed: usize,
+    pub freed_bytes: u64,
+}
+
+fn assets_dir() -> PathBuf {
+    paths::data_dir().join(ASSETS_DIR)
+}
+
+/// Hashes are lowercase hex SHA-256; anything else could escape the store
+fn is_valid_hash(hash: &str) -> bool {
+    hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// Where an asset with this hash lives: assets/<first two hex chars>/<hash>
+pub fn asset_path(hash: &str) -> Result<PathBuf, String> {
+    if !is_valid_hash(hash) {
+        return Err(format!("Invalid asset hash '{}'", hash));
+    }
+    Ok(assets_dir().join(&hash[..2]).join(hash))
+}
+
+/// Store bytes under their content hash and return the hash; identical
+/// content is only ever written once
+pub fn store_asset(bytes: &[u8]) -> Result<String, String> {
+    let hash: String = Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let path = asset_path(&hash)?;
+    if path.exists() {
+        return Ok(hash);
+    }
+
+    let dir = path.parent().ok_or("Invalid asset path")?;
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create asset dir: {}", e))?;
+
+    // Write then rename so a crash never leaves a truncated file under a valid hash
+    let tmp = dir.join(format!("{}.tmp", hash));
+    fs::write(&tmp, bytes).map_err(|e| format!("Failed to write asset: {}", e))?;
+    fs::rename(&tmp, &path).map_err(|e| format!("Failed to store asset: {}", e))?;
+
+    Ok(hash)
+}
+
+/// Every file in the store as (hash or stray name, path, size, age)
+fn scan() -> Vec<(String, PathBuf, u64, Duration)> {
+    let now = SystemTime::now();
+    let mut files = Vec::new();
+
+    let Ok(prefixes) = fs::read_dir(assets_dir()) else {
+        return files;
+    };
+    for prefix in prefixes.flatten() {
+        let Ok(entries) = fs::read_dir(prefix.path()) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let Ok(metadata) = entry.metadata() else {
+                continue;
+            };
+            if !metadata.is_file() {
+                continue;
+            }
+            let age = metadata
+                .modified()
+                .ok()
+                .and_then(|modified| now.duration_since(modified).ok())
+                .unwrap_or_default();
+            let name = entry.file_name().to_string_lossy().into_owned();
+            files.push((name, entry.path(), metadata.len(), age));
+        }
+    }
+    files
+}
+
+/// Count stored assets and how many no clip references
+pub fn asset_stats(referenced: &HashSet<String>) -> AssetStats {
+    let mut stats = AssetStats::default();
+    for (name, _, size, _) in scan() {
+        stats.count += 1;
+        stats.total_bytes += size;
+        if !referenced.contains(&name) {
+            stats.orphan_count += 1;
+            stats.orphan_bytes += size;
+        }
+    }
+    stats
+}
+
+/// Delete assets that no clip references (including leftover temp files)
+pub fn gc_assets(referenced: &HashSet<String>) -> GcReport {
+    let mut report = GcReport::default();
+    for (name, path, size, age) in scan() {
+        if referenced.contains(&name) || age < GC_GRACE_PERIOD {
+            continue;
+        }
+        if fs::remove_file(&path).is_ok() {
+            report.removed += 1;
+            report.freed_bytes += size;
+        }
+    }
+
+    // Drop prefix dirs emptied by the pass
+    if let Ok(prefixes) = fs::read_dir(assets_dir()) {
+        for prefix in prefixes.flatten() {
+            let _ = fs::remove_dir(prefix.path());
+        }
+    }
+
+    report
+}
@@ -0,0 +1,121 @@
+use chrono::Local;
+
+use crate::storage::ClipObject;
+
+pub const DEFAULT_TEMPLATE: &str = "\n\n— {source_app}, {window_title}, {date}";
+
+/// Value of a placeholder for this clip; None for names we don't know.
+/// Known placeholders without data render empty.
+fn placeholder(clip: &ClipObject, name: &str) -> Option<String> {
+    let context = |key: &str| {
+        clip.metadata
+            .context
+            .as_ref()
+            .and_then(|c| c.get(key))
+            .and_then(|v| v.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    let local = clip.metadata.timestamp.with_timezone(&Local);
+
+    Some(match name {
+        "source_app" => clip.metadata.source_app.clone(),
+        "window_title" => clip.metadata.window_title.clone(),
+        "title" => clip.title.clone().unwrap_or_default(),
+        "date" => local.format("%Y-%m-%d").to_string(),
+        "time" => local.format("%H:%M").to_string(),
+        "file" => context("file"),
+        "project" => context("project"),
+        _ => return None,
+    })
+}
+
+/// Render an attribution template for a clip. `{name}` is replaced by the
+/// clip's value, `{{` and `}}` are literal braces, and unknown or unclosed
+/// placeholders are kept as written.
+pub fn render(template: &str, clip: &ClipObject) -> String {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+
+    while let Some(pos) = rest.find(['{', '}']) {
+        out.push_str(&rest[..pos]);
+        let tail = &rest[pos..];
+
+        if let Some(after) = tail.strip_prefix("{{") {
+            out.push('{');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix("}}") {
+            out.push('}');
+            rest = after;
+        } else if let Some(after) = tail.strip_prefix('}') {
+            // A lone closing brace is just text
+            out.push('}');
+            rest = after;
+        } else {
+            let Some(end) = tail.find('}') else {
+                out.push_str(tail);
+                return out;
+            };
+            match placeholder(clip, &tail[1..end]) {
+                Some(value) => out.push_str(&value),
+                None => out.push_str(&tail[..=end]),
+            }
+            rest = &tail[end + 1..];
+        }
+    }
+
+    out.push_str(rest);
+    out
+}
+
+/// The text to put on the clipboard for a clip: its content, plus the
+/// attribution when `template` is given. The stored clip is never changed.
+pub fn with_attribution(clip: &ClipObject, template: Option<&str>) -> String {
+    match template {
+        Some(template) => format!("{}{}", clip.content, render(template, clip)),
+        None => clip.content.clone(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+
+    #[test]
+    fn renders_known_placeholders() {
+        let mut clip = clip("body");
+        clip.title = Some("Title".to_string());
+        assert_eq!(
+            render("{source_app} / {window_title} / {title}", &clip),
+            "test.exe / Test Window / Title"
+        );
+    }
+
+    #[test]
+    fn missing_metadata_renders_empty() {
+        let clip = clip("body");
+        assert_eq!(render("[{title}][{file}][{project}]", &clip), "[][][]");
+    }
+
+    #[test]
+    fn editor_context_fills_file_and_project() {
+        let mut clip = clip("body");
+        clip.metadata.context = Some(serde_json::json!({ "file": "a.rs", "project": "p" }));
+        assert_eq!(render("{project}/{file}", &clip), "p/a.rs");
+    }
+
+    #[test]
+    fn braces_unknown_and_unclosed_placeholders_are_kept() {
+        let clip = clip("body");
+        assert_eq!(render("{{title}} {nope} }", &clip), "{title} {nope} }");
+        assert_eq!(render("end {source_app", &clip), "end {source_app");
+    }
+
+    #[test]
+    fn attribution_is_appended_only_with_a_template() {
+        let clip = clip("body");
+        assert_eq!(with_attribution(&clip, None), "body");
+        assert_eq!(with_attribution(&clip, Some(" ({source_app})")), "body (test.exe)");
+    }
+}
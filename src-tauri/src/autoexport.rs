@@ -0,0 +1,229 @@
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::export::{self, ExportOptions, ExportReport};
+use crate::storage::{self, AppStorage, Pastebook};
+use crate::{capture_path, AppState};
+
+/// How often the scheduler looks for exports that are due
+pub const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(5 * 60);
+/// A failed export is tried again after this long, or its interval if shorter
+const RETRY_HOURS: i64 = 1;
+/// Longest allowed interval, a year
+const MAX_INTERVAL_HOURS: u32 = 24 * 366;
+
+/// Set while an export is being written, so "run now" and the scheduler
+/// never write the same files at once
+static RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// A pastebook's scheduled export and how its last run went
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AutoExportConfig {
+    /// One of `export::FORMATS`
+    pub format: String,
+    pub destination: PathBuf,
+    pub interval_hours: u32,
+    #[serde(default)]
+    pub options: ExportOptions,
+    /// Last successful export
+    #[serde(default)]
+    pub last_run: Option<DateTime<Utc>>,
+    /// Last export tried, successful or not
+    #[serde(default)]
+    pub last_attempt: Option<DateTime<Utc>>,
+    /// Why the last attempt failed; cleared by the next success
+    #[serde(default)]
+    pub last_error: Option<String>,
+}
+
+impl AutoExportConfig {
+    /// Whether the export should run at `now`: never tried, its interval has
+    /// passed, or it failed and the retry delay has passed
+    pub fn is_due(&self, now: DateTime<Utc>) -> bool {
+        let Some(last_attempt) = self.last_attempt else {
+            return true;
+        };
+        let mut wait = Duration::hours(self.interval_hours as i64);
+        if self.last_error.is_some() {
+            wait = wait.min(Duration::hours(RETRY_HOURS));
+        }
+        now - last_attempt >= wait
+    }
+
+    /// Note how a run went. Returns true for a failure that is new (the first,
+    /// or a different error), which is the only kind worth telling the user.
+    pub fn record(&mut self, result: &Result<ExportReport, String>, now: DateTime<Utc>) -> bool {
+        self.last_attempt = Some(now);
+        match result {
+            Ok(_) => {
+                self.last_run = Some(now);
+                self.last_error = None;
+                false
+            }
+            Err(e) => {
+                let new = self.last_error.as_ref() != Some(e);
+                self.last_error = Some(e.clone());
+                new
+            }
+        }
+    }
+}
+
+/// Check a config before it's saved and start its run history afresh
+pub fn validate(mut config: AutoExportConfig) -> Result<AutoExportConfig, String> {
+    if !export::FORMATS.contains(&config.format.as_str()) {
+        return Err(format!("Unknown export format '{}'", config.format));
+    }
+    if !config.destination.is_absolute() {
+        return Err("Export path must be absolute".to_string());
+    }
+    if config.interval_hours == 0 || config.interval_hours > MAX_INTERVAL_HOURS {
+        return Err(format!("Interval must be between 1 and {} hours", MAX_INTERVAL_HOURS));
+    }
+    config.last_run = None;
+    config.last_attempt = None;
+    config.last_error = None;
+    Ok(config)
+}
+
+/// A pastebook's scheduled export, as listed by `get_auto_export_status`
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoExportStatus {
+    pub pastebook_id: String,
+    pub pastebook_name: String,
+    #[serde(flatten)]
+    pub config: AutoExportConfig,
+}
+
+/// Every pastebook with a scheduled export
+pub fn status(storage: &AppStorage) -> Vec<AutoExportStatus> {
+    storage
+        .pastebooks
+        .iter()
+        .filter_map(|p| {
+            Some(AutoExportStatus {
+                pastebook_id: p.id.clone(),
+                pastebook_name: p.name.clone(),
+                config: p.auto_export.clone()?,
+            })
+        })
+        .collect()
+}
+
+/// Payload of the `auto-export-failed` event
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoExportFailed {
+    pub pastebook_id: String,
+    pub path: PathBuf,
+    pub error: String,
+}
+
+/// Export one pastebook (a copy, so storage isn't locked while files are
+/// written), record the result on it and schedule a save. Fires
+/// `auto-export-failed` for a new failure. Refused while another export runs.
+pub fn run(app: &AppHandle, pastebook: &Pastebook) -> Result<ExportReport, String> {
+    let config = pastebook
+        .auto_export
+        .as_ref()
+        .ok_or_else(|| format!("NotFound: no auto-export for pastebook {}", pastebook.id))?;
+    if RUNNING.swap(true, Ordering::AcqRel) {
+        return Err("Conflict: an auto-export is already running".to_string());
+    }
+    let result = export::export(&config.format, pastebook, &config.destination, &config.options);
+    RUNNING.store(false, Ordering::Release);
+
+    let state = app.state::<AppState>();
+    let mut storage = state.storage.write().unwrap();
+    // Skip recording if the config was changed or removed meanwhile
+    let current = storage
+        .pastebooks
+        .iter_mut()
+        .find(|p| p.id == pastebook.id)
+        .and_then(|p| p.auto_export.as_mut())
+        .filter(|c| c.destination == config.destination && c.format == config.format);
+    if let Some(current) = current {
+        let notify = current.record(&result, Utc::now());
+        storage.commit_deferred();
+        drop(storage);
+        capture_path::schedule_save(app);
+        if let (true, Err(error)) = (notify, &result) {
+            eprintln!("Auto-export to {} failed: {}", config.destination.display(), error);
+            let _ = app.emit(
+                "auto-export-failed",
+                AutoExportFailed {
+                    pastebook_id: pastebook.id.clone(),
+                    path: config.destination.clone(),
+                    error: error.clone(),
+                },
+            );
+        }
+    }
+    result
+}
+
+/// Run every export that's due, one after another
+pub fn run_due(app: &AppHandle) {
+    let now = Utc::now();
+    let due: Vec<Pastebook> = {
+        let state = app.state::<AppState>();
+        let storage = storage::loaded(&state.storage);
+        storage
+            .pastebooks
+            .iter()
+            .filter(|p| p.auto_export.as_ref().is_some_and(|c| c.is_due(now)))
+            .cloned()
+            .collect()
+    };
+    for pastebook in &due {
+        let _ = run(app, pastebook);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(interval_hours: u32) -> AutoExportConfig {
+        AutoExportConfig {
+            format: "markdown_folder".to_string(),
+            destination: std::env::temp_dir().join("stack-wiki"),
+            interval_hours,
+            options: ExportOptions::default(),
+            last_run: None,
+            last_attempt: None,
+            last_error: None,
+        }
+    }
+
+    #[test]
+    fn failures_retry_sooner_and_notify_once() {
+        let now = Utc::now();
+        let mut config = validate(config(24)).unwrap();
+        assert!(config.is_due(now));
+
+        assert!(!config.record(&Ok(ExportReport::default()), now));
+        assert!(!config.is_due(now + Duration::hours(23)));
+        assert!(config.is_due(now + Duration::hours(24)));
+
+        let denied = Err("Failed to create W:\\wiki: permission denied".to_string());
+        assert!(config.record(&denied, now));
+        assert!(config.is_due(now + Duration::hours(RETRY_HOURS)));
+        // The same failure next cycle stays quiet; a different one doesn't
+        assert!(!config.record(&denied, now + Duration::hours(1)));
+        assert!(config.record(&Err("disk full".to_string()), now + Duration::hours(2)));
+        assert_eq!(config.last_run, Some(now));
+
+        assert!(!config.record(&Ok(ExportReport::default()), now + Duration::hours(3)));
+        assert_eq!(config.last_error, None);
+    }
+
+    #[test]
+    fn configs_are_checked() {
+        assert!(validate(config(0)).is_err());
+        assert!(validate(AutoExportConfig { format: "pdf".to_string(), ..config(1) }).is_err());
+        assert!(validate(AutoExportConfig { destination: PathBuf::from("wiki"), ..config(1) }).is_err());
+    }
+}
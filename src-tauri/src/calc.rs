@@ -0,0 +1,335 @@
+use serde::Serialize;
+
+use crate::storage::DecimalSeparator;
+
+/// Longer text is never treated as an expression
+const MAX_EXPRESSION_CHARS: usize = 256;
+/// Parenthesis nesting limit, so hostile input can't exhaust the stack
+const MAX_DEPTH: usize = 32;
+/// Decimal places kept in the formatted result
+const RESULT_DECIMALS: usize = 10;
+
+const NOT_AN_EXPRESSION: &str = "Not an expression";
+
+/// A successfully evaluated expression
+#[derive(Debug, Clone, Serialize)]
+pub struct Evaluation {
+    /// The expression as written, minus any trailing "="
+    pub expression: String,
+    pub value: f64,
+    /// The value written with the chosen decimal separator
+    pub formatted: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Token {
+    Number(f64),
+    Plus,
+    Minus,
+    Times,
+    Divide,
+    Percent,
+    Open,
+    Close,
+}
+
+/// An operand, remembering whether it was written as a percentage so
+/// `a + b%` can mean "a plus b percent of a"
+#[derive(Debug, Clone, Copy)]
+struct Value {
+    number: f64,
+    percent: bool,
+}
+
+impl Value {
+    fn plain(number: f64) -> Self {
+        Self { number, percent: false }
+    }
+
+    /// The operand as a plain number (12% is 0.12)
+    fn resolve(self) -> f64 {
+        if self.percent {
+            self.number / 100.0
+        } else {
+            self.number
+        }
+    }
+}
+
+/// Parse a number's text: `decimal` separates the fraction and `group`
+/// may separate thousands, but only in well-formed groups of three
+fn parse_number(text: &str, decimal: char, group: char) -> Option<f64> {
+    let (integer, fraction) = match text.split_once(decimal) {
+        Some((integer, fraction)) => (integer, Some(fraction)),
+        None => (text, None),
+    };
+    if fraction.is_some_and(|f| f.is_empty() || f.contains([decimal, group])) {
+        return None;
+    }
+
+    let mut groups = integer.split(group);
+    let first = groups.next().unwrap_or_default();
+    let mut digits = first.to_string();
+    let mut grouped = false;
+    for rest in groups {
+        if rest.len() != 3 {
+            return None;
+        }
+        grouped = true;
+        digits.push_str(rest);
+    }
+    if grouped && (first.is_empty() || first.len() > 3) {
+        return None;
+    }
+    if digits.is_empty() && fraction.is_none() {
+        return None;
+    }
+
+    let normalized = match fraction {
+        Some(fraction) => format!("0{}.{}", digits, fraction),
+        None => digits,
+    };
+    normalized.parse().ok()
+}
+
+fn tokenize(text: &str, separator: DecimalSeparator) -> Option<Vec<Token>> {
+    let (decimal, group) = match separator {
+        DecimalSeparator::Dot => ('.', ','),
+        DecimalSeparator::Comma => (',', '.'),
+    };
+
+    let mut tokens = Vec::new();
+    let mut chars = text.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_ascii_digit() || c == decimal {
+            let mut number = String::new();
+            while let Some(&c) = chars.peek() {
+                if c.is_ascii_digit() || c == decimal || c == group {
+                    number.push(c);
+                    chars.next();
+                } else {
+                    break;
+                }
+            }
+            tokens.push(Token::Number(parse_number(&number, decimal, group)?));
+            continue;
+        }
+
+        chars.next();
+        tokens.push(match c {
+            c if c.is_whitespace() => continue,
+            '+' => Token::Plus,
+            '-' | '−' => Token::Minus,
+            '*' | '×' | 'x' | 'X' => Token::Times,
+            '/' | '÷' => Token::Divide,
+            '%' => Token::Percent,
+            '(' => Token::Open,
+            ')' => Token::Close,
+            _ => return None,
+        });
+    }
+    Some(tokens)
+}
+
+/// Recursive descent over the tokens:
+///   expr    := term (('+' | '-') term)*
+///   term    := unary (('*' | '/') unary)*
+///   unary   := ('+' | '-') unary | postfix
+///   postfix := primary '%'?
+///   primary := number | '(' expr ')'
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+    depth: usize,
+    /// Binary operators and percent signs seen; a bare number isn't an expression
+    operators: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<Token> {
+        self.tokens.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, token: Token) -> bool {
+        if self.peek() == Some(token) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expr(&mut self) -> Result<f64, String> {
+        let mut total = self.term()?.resolve();
+        loop {
+            let sign = if self.eat(Token::Plus) {
+                1.0
+            } else if self.eat(Token::Minus) {
+                -1.0
+            } else {
+                return Ok(total);
+            };
+            self.operators += 1;
+            let rhs = self.term()?;
+            total += sign * if rhs.percent { total * rhs.number / 100.0 } else { rhs.number };
+        }
+    }
+
+    fn term(&mut self) -> Result<Value, String> {
+        let mut value = self.unary()?;
+        loop {
+            let divide = if self.eat(Token::Times) {
+                false
+            } else if self.eat(Token::Divide) {
+                true
+            } else {
+                return Ok(value);
+            };
+            self.operators += 1;
+            let rhs = self.unary()?.resolve();
+            value = Value::plain(if divide {
+                if rhs == 0.0 {
+                    return Err("Division by zero".to_string());
+                }
+                value.resolve() / rhs
+            } else {
+                value.resolve() * rhs
+            });
+        }
+    }
+
+    fn unary(&mut self) -> Result<Value, String> {
+        if self.eat(Token::Minus) {
+            let value = self.unary()?;
+            return Ok(Value { number: -value.number, ..value });
+        }
+        if self.eat(Token::Plus) {
+            return self.unary();
+        }
+        self.postfix()
+    }
+
+    fn postfix(&mut self) -> Result<Value, String> {
+        let number = self.primary()?;
+        if self.eat(Token::Percent) {
+            self.operators += 1;
+            return Ok(Value { number, percent: true });
+        }
+        Ok(Value::plain(number))
+    }
+
+    fn primary(&mut self) -> Result<f64, String> {
+        match self.peek() {
+            Some(Token::Number(n)) => {
+                self.pos += 1;
+                Ok(n)
+            }
+            Some(Token::Open) => {
+                self.pos += 1;
+                self.depth += 1;
+                if self.depth > MAX_DEPTH {
+                    return Err(NOT_AN_EXPRESSION.to_string());
+                }
+                let value = self.expr()?;
+                if !self.eat(Token::Close) {
+                    return Err(NOT_AN_EXPRESSION.to_string());
+                }
+                self.depth -= 1;
+                Ok(value)
+            }
+            _ => Err(NOT_AN_EXPRESSION.to_string()),
+        }
+    }
+}
+
+/// Write a result without float noise, using the chosen decimal separator
+fn format_value(value: f64, separator: DecimalSeparator) -> String {
+    let mut text = format!("{:.*}", RESULT_DECIMALS, value);
+    if text.contains('.') {
+        text = text.trim_end_matches('0').trim_end_matches('.').to_string();
+    }
+    if text == "-0" {
+        text = "0".to_string();
+    }
+    match separator {
+        DecimalSeparator::Dot => text,
+        DecimalSeparator::Comma => text.replace('.', ","),
+    }
+}
+
+/// Evaluate simple arithmetic: + - * / with parentheses, and `%` both as
+/// "percent of" (`200 + 10%` is 220) and as a plain percentage (`10% * 50` is 5).
+/// Anything that doesn't parse completely is rejected, never partially evaluated.
+pub fn evaluate(text: &str, separator: DecimalSeparator) -> Result<Evaluation, String> {
+    let expression = text.trim();
+    let expression = expression.strip_suffix('=').unwrap_or(expression).trim_end();
+    if expression.is_empty() || expression.chars().count() > MAX_EXPRESSION_CHARS {
+        return Err(NOT_AN_EXPRESSION.to_string());
+    }
+
+    let tokens = tokenize(expression, separator).ok_or(NOT_AN_EXPRESSION)?;
+    let mut parser = Parser {
+        tokens,
+        pos: 0,
+        depth: 0,
+        operators: 0,
+    };
+    let value = parser.expr()?;
+    if parser.pos != parser.tokens.len() || parser.operators == 0 {
+        return Err(NOT_AN_EXPRESSION.to_string());
+    }
+    if !value.is_finite() {
+        return Err("Result is out of range".to_string());
+    }
+
+    Ok(Evaluation {
+        expression: expression.to_string(),
+        value,
+        formatted: format_value(value, separator),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str) -> Result<String, String> {
+        evaluate(text, DecimalSeparator::Dot).map(|e| e.formatted)
+    }
+
+    #[test]
+    fn precedence_and_parentheses() {
+        assert_eq!(eval("2 + 3 * 4").unwrap(), "14");
+        assert_eq!(eval("(2 + 3) * 4").unwrap(), "20");
+        assert_eq!(eval("-(1 - 3) / 4").unwrap(), "0.5");
+        assert_eq!(eval("0.1 + 0.2").unwrap(), "0.3");
+        assert_eq!(eval("3 × 4 ÷ 2 =").unwrap(), "6");
+    }
+
+    #[test]
+    fn percentages() {
+        assert_eq!(eval("200 - 10%").unwrap(), "180");
+        assert_eq!(eval("17 * 34 + 12%").unwrap(), "647.36");
+        assert_eq!(eval("10% * 50").unwrap(), "5");
+        assert_eq!(eval("50%").unwrap(), "0.5");
+    }
+
+    #[test]
+    fn thousands_groups_and_comma_decimals() {
+        assert_eq!(eval("1,000 + 1").unwrap(), "1001");
+        assert!(eval("1,00 + 1").is_err());
+        let comma = evaluate("1.234,5 / 2", DecimalSeparator::Comma).unwrap();
+        assert_eq!(comma.formatted, "617,25");
+        assert_eq!(comma.value, 617.25);
+    }
+
+    #[test]
+    fn rejects_non_expressions() {
+        for text in ["", "42", "(1 + 2", "1 +", "abc", "2 ** 3", "1 + 2)"] {
+            assert_eq!(eval(text).unwrap_err(), NOT_AN_EXPRESSION, "{:?}", text);
+        }
+        assert_eq!(eval("1 / (2 - 2)").unwrap_err(), "Division by zero");
+        let deep = format!("{}1{} + 1", "(".repeat(40), ")".repeat(40));
+        assert_eq!(eval(&deep).unwrap_err(), NOT_AN_EXPRESSION);
+    }
+}
@@ -0,0 +1,201 @@
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::announce::{self, Message};
+use crate::notify::{self, NotificationKind};
+use crate::storage::CapturedClip;
+use crate::{webhooks, AppState};
+
+/// Captures closer together than this are a burst and get batched
+pub const BURST_WINDOW: Duration = Duration::from_millis(200);
+/// A long burst still reaches the UI (and disk) at least this often
+pub const MAX_BATCH_DELAY: Duration = Duration::from_secs(1);
+/// How often the flusher checks whether a batch is due
+const FLUSH_TICK: Duration = Duration::from_millis(50);
+
+/// Groups captures that arrive in a burst. Time is passed in so the
+/// batching can be tested without sleeping.
+#[derive(Debug, Default)]
+pub struct Coalescer {
+    last_capture: Option<Instant>,
+    /// When the current batch began; None when not batching
+    batch_started: Option<Instant>,
+    pending: Vec<CapturedClip>,
+}
+
+impl Coalescer {
+    pub const fn new() -> Self {
+        Self {
+            last_capture: None,
+            batch_started: None,
+            pending: Vec::new(),
+        }
+    }
+
+    /// Note a capture at `now`. Returns (batched, new batch): whether it
+    /// belongs to a burst, and whether that burst just started a batch.
+    pub fn begin(&mut self, now: Instant) -> (bool, bool) {
+        let burst = self
+            .last_capture
+            .is_some_and(|at| now.saturating_duration_since(at) < BURST_WINDOW);
+        self.last_capture = Some(now);
+        let started = burst && self.batch_started.is_none();
+        if started {
+            self.batch_started = Some(now);
+        }
+        (burst, started)
+    }
+
+    /// Hold a batched clip for the next flush
+    pub fn queue(&mut self, clip: CapturedClip) {
+        self.pending.push(clip);
+    }
+
+    /// The held clips, once captures have gone quiet for a window or the
+    /// batch has waited `MAX_BATCH_DELAY`, and whether the burst is over
+    pub fn take_due(&mut self, now: Instant) -> Option<(Vec<CapturedClip>, bool)> {
+        let started = self.batch_started?;
+        let quiet = self
+            .last_capture
+            .is_none_or(|at| now.saturating_duration_since(at) >= BURST_WINDOW);
+        if !quiet && now.saturating_duration_since(started) < MAX_BATCH_DELAY {
+            return None;
+        }
+        self.batch_started = if quiet { None } else { Some(now) };
+        Some((std::mem::take(&mut self.pending), quiet))
+    }
+}
+
+/// Payload of the `clips-captured-batch` event
+#[derive(Clone, serde::Serialize)]
+struct CapturedBatch {
+    clips: Vec<CapturedClip>,
+    announcement: String,
+}
+
+static COALESCER: Mutex<Coalescer> = Mutex::new(Coalescer::new());
+
+/// Note a capture about to be committed. Returns whether it's part of a
+/// burst, in which case the caller commits with `commit_deferred` and
+/// announces the clip with `queue`; the flusher saves and emits the batch.
+pub fn begin(app: &AppHandle) -> bool {
+    let (batched, started) = COALESCER.lock().unwrap().begin(Instant::now());
+    if started {
+        let app = app.clone();
+        std::thread::spawn(move || run_flusher(&app));
+    }
+    batched
+}
+
+/// Announce a batched capture. Webhooks still get one event per clip; only
+/// the windows see the batch.
+pub fn queue(clip: CapturedClip) {
+    webhooks::dispatch("clip-captured", &clip);
+    COALESCER.lock().unwrap().queue(clip);
+}
+
+/// Save and emit batches until the burst ends
+fn run_flusher(app: &AppHandle) {
+    loop {
+        std::thread::sleep(FLUSH_TICK);
+        let Some((batch, quiet)) = COALESCER.lock().unwrap().take_due(Instant::now()) else {
+            continue;
+        };
+
+        let state = app.state::<AppState>();
+        if let Err(e) = state.storage.write().unwrap().flush_pending() {
+            eprintln!("Failed to save captured clips: {}", e);
+        }
+        if !batch.is_empty() {
+            let count = batch.len();
+            let announcement = announce::text(Message::CapturedBatch { count });
+            let _ = app.emit("clips-captured-batch", CapturedBatch { clips: batch, announcement });
+            notify::send(app, NotificationKind::Capture, count, "success", format!("{} clips captured", count));
+        }
+        if quiet {
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{AppStorage, CaptureOutcome};
+    use crate::test_support::{clip, TempStorage};
+
+    #[test]
+    fn a_lone_capture_is_not_batched() {
+        let mut coalescer = Coalescer::new();
+        let start = Instant::now();
+        assert_eq!(coalescer.begin(start), (false, false));
+        assert_eq!(coalescer.begin(start + BURST_WINDOW), (false, false));
+        assert!(coalescer.take_due(start + BURST_WINDOW * 3).is_none());
+    }
+
+    /// One flusher tick: (emitted a batch, saved, burst over), or None if nothing was due
+    fn tick(coalescer: &mut Coalescer, storage: &mut AppStorage, now: Instant) -> Option<(bool, bool, bool)> {
+        let (batch, quiet) = coalescer.take_due(now)?;
+        Some((!batch.is_empty(), storage.flush_pending().unwrap(), quiet))
+    }
+
+    #[test]
+    fn fifty_captures_in_a_second_are_batched_and_saved_once() {
+        let mut temp = TempStorage::new();
+        let mut coalescer = Coalescer::new();
+        let start = Instant::now();
+        let (mut events, mut saves) = (0, 0);
+
+        // One capture every 20ms, with the flusher ticking every 60ms
+        let mut now = start;
+        for i in 0..50 {
+            now = start + Duration::from_millis(20 * i);
+            let (batched, _) = coalescer.begin(now);
+            let CaptureOutcome::Added(added) = temp.storage.add_captured_clip(clip(&format!("clip {}", i)))
+            else {
+                panic!("capture {} wasn't added", i);
+            };
+            if batched {
+                temp.storage.commit_deferred();
+                coalescer.queue(CapturedClip::from(&added));
+            } else {
+                // The first capture goes out alone, saved right away
+                temp.storage.commit().unwrap();
+                events += 1;
+            }
+            if i % 3 == 2 {
+                if let Some((emitted, saved, _)) = tick(&mut coalescer, &mut temp.storage, now) {
+                    events += emitted as usize;
+                    saves += saved as usize;
+                }
+            }
+        }
+        loop {
+            now += FLUSH_TICK;
+            if let Some((emitted, saved, quiet)) = tick(&mut coalescer, &mut temp.storage, now) {
+                events += emitted as usize;
+                saves += saved as usize;
+                if quiet {
+                    break;
+                }
+            }
+        }
+
+        assert!(events <= 3, "{} events for one burst", events);
+        assert_eq!(saves, 1);
+        assert_eq!(temp.reload().get_active_pastebook().unwrap().clips.len(), 50);
+    }
+
+    #[test]
+    fn deferred_commits_wait_for_the_flush() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("deferred")).unwrap();
+        temp.storage.commit_deferred();
+        assert!(temp.reload().get_active_pastebook().unwrap().clips.is_empty());
+
+        assert!(temp.storage.flush_pending().unwrap());
+        assert!(!temp.storage.flush_pending().unwrap());
+        assert_eq!(temp.reload().get_active_pastebook().unwrap().clips.len(), 1);
+    }
+}
@@ -0,0 +1,482 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Manager};
+
+use crate::storage::{AppStorage, AutoSelected, CaptureOutcome, ClipObject, TitleRouted};
+use crate::AppState;
+
+#[cfg(windows)]
+use crate::clipboard_formats;
+#[cfg(windows)]
+use windows::Win32::System::DataExchange::GetClipboardSequenceNumber;
+
+/// Longest a capture waits for the copy to reach the clipboard; nothing
+/// selected means nothing is ever copied
+pub const COPY_TIMEOUT: Duration = Duration::from_millis(250);
+/// How often the clipboard is checked while waiting for the copy
+const POLL_INTERVAL: Duration = Duration::from_millis(2);
+/// Captures are written to disk once they've been quiet this long
+const SAVE_DEBOUNCE: Duration = Duration::from_millis(300);
+/// How long a capture from an app outside the focus list waits for
+/// `capture_anyway`
+const HOLD_FOR: Duration = Duration::from_secs(120);
+/// Held captures kept at once; past this the oldest is dropped
+const MAX_HELD: usize = 5;
+/// Target from the copied text being readable to the clip event going out
+#[cfg(feature = "capture-timing")]
+pub const READY_TO_EMITTED_BUDGET: Duration = Duration::from_millis(30);
+
+/// Where a capture reads the copied text from; the system clipboard, or a
+/// fake in tests
+pub trait ClipboardSource {
+    /// A number that changes whenever anything is copied
+    fn sequence(&self) -> u64;
+    fn read_text(&self) -> Option<String>;
+    /// The text along with the names of the formats on offer, read in one
+    /// clipboard session so both describe the same copy
+    fn read_text_and_formats(&self) -> Option<(String, Vec<String>)> {
+        self.read_text().map(|text| (text, Vec::new()))
+    }
+}
+
+/// What a capture read off the clipboard
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Copied {
+    pub text: String,
+    /// Names of the formats the source app offered; empty unless asked for
+    pub formats: Vec<String>,
+}
+
+/// The system clipboard, through the clipboard plugin
+pub struct SystemClipboard<'a>(pub &'a AppHandle);
+
+impl ClipboardSource for SystemClipboard<'_> {
+    #[cfg(windows)]
+    fn sequence(&self) -> u64 {
+        unsafe { GetClipboardSequenceNumber() as u64 }
+    }
+
+    /// No sequence number off Windows, so the text itself stands in; copying
+    /// the same text again goes unnoticed
+    #[cfg(not(windows))]
+    fn sequence(&self) -> u64 {
+        use std::hash::{DefaultHasher, Hash, Hasher};
+        let mut hasher = DefaultHasher::new();
+        self.read_text().hash(&mut hasher);
+        hasher.finish()
+    }
+
+    fn read_text(&self) -> Option<String> {
+        use tauri_plugin_clipboard_manager::ClipboardExt;
+        self.0.clipboard().read_text().ok()
+    }
+
+    #[cfg(windows)]
+    fn read_text_and_formats(&self) -> Option<(String, Vec<String>)> {
+        use windows::Win32::Foundation::{HGLOBAL, HWND};
+        use windows::Win32::System::DataExchange::{CloseClipboard, GetClipboardData, OpenClipboard};
+        use windows::Win32::System::Memory::{GlobalLock, GlobalUnlock};
+        const CF_UNICODETEXT: u32 = 13;
+
+        unsafe {
+            // The copying app may still hold the clipboard; the next poll retries
+            OpenClipboard(HWND::default()).ok()?;
+            let formats = clipboard_formats::enumerate_open();
+            let text = (|| {
+                let data = HGLOBAL(GetClipboardData(CF_UNICODETEXT).ok()?.0);
+                let wide = GlobalLock(data) as *const u16;
+                if wide.is_null() {
+                    return None;
+                }
+                let len = (0..).take_while(|&i| *wide.add(i) != 0).count();
+                let text = String::from_utf16_lossy(std::slice::from_raw_parts(wide, len));
+                let _ = GlobalUnlock(data);
+                Some(text)
+            })();
+            let _ = CloseClipboard();
+            Some((text?, formats))
+        }
+    }
+}
+
+/// Wait for a copy made after the clipboard was at `before` and return its
+/// text. The owner empties the clipboard before filling it, so a change
+/// with no readable text yet keeps waiting. None once `timeout` passes.
+/// With `formats` set, the names of the formats on offer are read too.
+pub fn wait_for_copy(clipboard: &impl ClipboardSource, before: u64, timeout: Duration, formats: bool) -> Option<Copied> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if clipboard.sequence() != before {
+            let copied = if formats {
+                clipboard.read_text_and_formats()
+            } else {
+                clipboard.read_text().map(|text| (text, Vec::new()))
+            };
+            if let Some((text, formats)) = copied.filter(|(t, _)| !t.is_empty()) {
+                return Some(Copied { text, formats });
+            }
+        }
+        if Instant::now() >= deadline {
+            return None;
+        }
+        std::thread::sleep(POLL_INTERVAL);
+    }
+}
+
+/// What `store` did with a capture
+pub struct Stored {
+    pub outcome: CaptureOutcome,
+    /// The active pastebook was gone, so this one was selected for the clip
+    pub auto_selected: Option<AutoSelected>,
+    /// A rule routing by window title created a pastebook, or hit the limit
+    pub title_routed: Option<TitleRouted>,
+}
+
+/// Add a captured clip in memory and release storage, leaving the save to
+/// `schedule_save` so nothing waits on the disk before the UI hears of it.
+/// If the active pastebook has gone (a hand edit, a sync merge), another is
+/// selected and the clip goes there rather than being lost.
+pub fn store(storage: &RwLock<AppStorage>, clip: ClipObject) -> Stored {
+    store_in(&mut storage.write().unwrap(), clip)
+}
+
+/// `store` with the storage guard already taken
+fn store_in(storage: &mut AppStorage, clip: ClipObject) -> Stored {
+    let mut outcome = storage.add_captured_clip(clip);
+    let title_routed = storage.take_title_routed();
+    let mut auto_selected = None;
+    if let CaptureOutcome::NoPastebook(clip) = outcome {
+        auto_selected = storage.repair_active_pastebook();
+        let active = storage.active_pastebook_id.clone().unwrap_or_default();
+        outcome = match storage.add_capture_to_pastebook(&active, clip.clone()) {
+            true => CaptureOutcome::Added(clip),
+            false => CaptureOutcome::NoPastebook(clip),
+        };
+    }
+    if auto_selected.is_some() || title_routed.is_some() || matches!(outcome, CaptureOutcome::Added(_) | CaptureOutcome::Bumped(_) | CaptureOutcome::Reused(..)) {
+        storage.note_chrome();
+        storage.commit_deferred();
+    }
+    Stored { outcome, auto_selected, title_routed }
+}
+
+/// Captures that arrived while storage was busy (a long export or rebuild
+/// holding a guard), oldest first. This is the capture lock: the hotkey
+/// path waits only on it, never on `storage`, and `drain` stores what's
+/// queued once storage is free. Lock order: `storage` before the inbox;
+/// `admit` only ever tries `storage` while holding it.
+#[derive(Default)]
+pub struct CaptureInbox {
+    queue: Mutex<Inbox>,
+}
+
+#[derive(Default)]
+struct Inbox {
+    clips: Vec<ClipObject>,
+    draining: bool,
+}
+
+impl CaptureInbox {
+    pub const fn new() -> Self {
+        Self {
+            queue: Mutex::new(Inbox { clips: Vec::new(), draining: false }),
+        }
+    }
+
+    /// Store `clip` now if storage is free and nothing is queued ahead of
+    /// it. Otherwise queue it, calling `start_drain` if no drain is running.
+    pub fn admit(&self, storage: &RwLock<AppStorage>, clip: ClipObject, start_drain: impl FnOnce()) -> Option<Stored> {
+        let mut queue = self.queue.lock().unwrap();
+        if queue.clips.is_empty() {
+            if let Ok(mut storage) = storage.try_write() {
+                drop(queue);
+                return Some(store_in(&mut storage, clip));
+            }
+        }
+        queue.clips.push(clip);
+        if !std::mem::replace(&mut queue.draining, true) {
+            start_drain();
+        }
+        None
+    }
+
+    /// Wait for storage and store everything queued, in order. Empty once
+    /// nothing was left, which ends the drain.
+    pub fn drain(&self, storage: &RwLock<AppStorage>) -> Vec<Stored> {
+        let mut storage = storage.write().unwrap();
+        let mut queue = self.queue.lock().unwrap();
+        let clips = std::mem::take(&mut queue.clips);
+        queue.draining = !clips.is_empty();
+        drop(queue);
+        clips.into_iter().map(|clip| store_in(&mut storage, clip)).collect()
+    }
+}
+
+/// Hotkey captures waiting for storage
+pub static INBOX: CaptureInbox = CaptureInbox::new();
+
+static SAVE_SCHEDULED: AtomicBool = AtomicBool::new(false);
+
+/// Write deferred captures out after `SAVE_DEBOUNCE`; captures made in the
+/// meantime share the one write
+pub fn schedule_save(app: &AppHandle) {
+    if SAVE_SCHEDULED.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    let app = app.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(SAVE_DEBOUNCE);
+        SAVE_SCHEDULED.store(false, Ordering::Release);
+        let state = app.state::<AppState>();
+        let saved = state.storage.write().unwrap().flush_pending();
+        if let Err(e) = saved {
+            eprintln!("Failed to save captured clips: {}", e);
+        }
+    });
+}
+
+/// Captures set aside until the user confirms them, oldest first
+#[derive(Default)]
+pub struct HeldCaptures {
+    held: Vec<(String, Instant, ClipObject)>,
+}
+
+impl HeldCaptures {
+    /// Set a capture aside and return the id that claims it
+    pub fn hold(&mut self, clip: ClipObject, now: Instant) -> String {
+        self.expire(now);
+        if self.held.len() >= MAX_HELD {
+            self.held.remove(0);
+        }
+        let id = uuid::Uuid::new_v4().to_string();
+        self.held.push((id.clone(), now, clip));
+        id
+    }
+
+    /// The capture held under `id`, unless it has expired
+    pub fn take(&mut self, id: &str, now: Instant) -> Option<ClipObject> {
+        self.expire(now);
+        let index = self.held.iter().position(|(held_id, _, _)| held_id == id)?;
+        Some(self.held.remove(index).2)
+    }
+
+    fn expire(&mut self, now: Instant) {
+        self.held.retain(|(_, at, _)| now.saturating_duration_since(*at) < HOLD_FOR);
+    }
+}
+
+/// Captures from apps outside the focus list, waiting for `capture_anyway`
+pub static HELD: Mutex<HeldCaptures> = Mutex::new(HeldCaptures { held: Vec::new() });
+
+/// Stages of a hotkey capture, in order
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Hotkey,
+    CopySent,
+    ClipboardReady,
+    ClipBuilt,
+    Stored,
+    Emitted,
+}
+
+/// When each stage of one capture was reached. Only recorded with the
+/// `capture-timing` feature (and in tests); otherwise marking is free.
+#[derive(Debug, Default)]
+pub struct Trace {
+    #[cfg(any(test, feature = "capture-timing"))]
+    marks: Vec<(Stage, Instant)>,
+}
+
+impl Trace {
+    pub fn start() -> Self {
+        let mut trace = Self::default();
+        trace.mark(Stage::Hotkey);
+        trace
+    }
+
+    pub fn mark(&mut self, stage: Stage) {
+        #[cfg(any(test, feature = "capture-timing"))]
+        self.marks.push((stage, Instant::now()));
+        #[cfg(not(any(test, feature = "capture-timing")))]
+        let _ = stage;
+    }
+
+    /// Time from reaching `from` to reaching `to`, if both were marked
+    #[cfg(any(test, feature = "capture-timing"))]
+    pub fn between(&self, from: Stage, to: Stage) -> Option<Duration> {
+        let at = |stage| self.marks.iter().find(|(s, _)| *s == stage).map(|(_, at)| *at);
+        Some(at(to)?.saturating_duration_since(at(from)?))
+    }
+
+    /// Print each stage's time since the one before, flagging a capture
+    /// over budget
+    pub fn finish(self) {
+        #[cfg(feature = "capture-timing")]
+        {
+            let stages: Vec<String> = self
+                .marks
+                .windows(2)
+                .map(|w| format!("{:?} {:.1}ms", w[1].0, (w[1].1 - w[0].1).as_secs_f64() * 1000.0))
+                .collect();
+            let over = self
+                .between(Stage::ClipboardReady, Stage::Emitted)
+                .is_some_and(|d| d > READY_TO_EMITTED_BUDGET);
+            println!("Capture timing: {}{}", stages.join(", "), if over { " (over budget)" } else { "" });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::DedupAction;
+    use chrono::Utc;
+    use crate::test_support::{clip, clip_after, clip_at, storage_with, TempStorage};
+    use std::sync::atomic::AtomicU64;
+    use std::sync::Arc;
+
+    /// A clipboard the test copies to from another thread
+    #[derive(Default)]
+    struct FakeClipboard {
+        sequence: AtomicU64,
+        text: Mutex<Option<String>>,
+    }
+
+    impl FakeClipboard {
+        fn copy(&self, text: &str) {
+            *self.text.lock().unwrap() = Some(text.to_string());
+            self.sequence.fetch_add(1, Ordering::AcqRel);
+        }
+    }
+
+    impl ClipboardSource for Arc<FakeClipboard> {
+        fn sequence(&self) -> u64 {
+            self.sequence.load(Ordering::Acquire)
+        }
+
+        fn read_text(&self) -> Option<String> {
+            self.text.lock().unwrap().clone()
+        }
+
+        fn read_text_and_formats(&self) -> Option<(String, Vec<String>)> {
+            let formats = vec!["CF_UNICODETEXT".to_string(), "CF_LOCALE".to_string()];
+            self.read_text().map(|text| (text, formats))
+        }
+    }
+
+    #[test]
+    fn capture_is_stored_before_anything_is_written() {
+        let temp = TempStorage::new();
+        let path = temp.path();
+        let storage = RwLock::new(temp.storage);
+        let clipboard = Arc::new(FakeClipboard::default());
+        clipboard.copy("stale");
+
+        let mut trace = Trace::start();
+        let before = clipboard.sequence();
+        let source = clipboard.clone();
+        std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            source.copy("selected text");
+        });
+        trace.mark(Stage::CopySent);
+
+        let copied = wait_for_copy(&clipboard, before, COPY_TIMEOUT, true).unwrap();
+        trace.mark(Stage::ClipboardReady);
+        assert_eq!(copied.text, "selected text");
+        assert_eq!(copied.formats, vec!["CF_UNICODETEXT", "CF_LOCALE"]);
+        let clip = clip(&copied.text);
+        trace.mark(Stage::ClipBuilt);
+        let stored = store(&storage, clip);
+        trace.mark(Stage::Stored);
+        assert!(matches!(stored.outcome, CaptureOutcome::Added(_)));
+        trace.mark(Stage::Emitted);
+
+        // The copy is noticed within a few polls, not after a fixed sleep
+        let detect = trace.between(Stage::CopySent, Stage::ClipboardReady).unwrap();
+        assert!(detect < Duration::from_millis(120), "copy noticed after {:?}", detect);
+        // Nothing was written before the event
+        assert!(!path.exists() || !std::fs::read_to_string(&path).unwrap().contains("selected text"));
+        assert!(storage.write().unwrap().flush_pending().unwrap());
+    }
+
+    #[test]
+    fn capture_into_a_deleted_pastebook_selects_another() {
+        let mut storage = AppStorage::default();
+        let survivor = storage.create_pastebook("Survivor".to_string()).unwrap();
+        storage.active_pastebook_id = Some("deleted-by-hand".to_string());
+        let storage = RwLock::new(storage);
+
+        let stored = store(&storage, clip("not lost"));
+        assert!(matches!(stored.outcome, CaptureOutcome::Added(_)));
+        let selected = stored.auto_selected.unwrap();
+        assert!(!selected.created);
+        let storage = storage.read().unwrap();
+        assert_eq!(storage.active_pastebook_id.as_deref(), Some(selected.id.as_str()));
+        assert_ne!(selected.id, survivor.id, "the first pastebook is picked");
+        assert_eq!(storage.get_clips()[0].content, "not lost");
+    }
+
+    #[test]
+    fn hotkey_captures_follow_the_dedup_action() {
+        let base = Utc::now();
+        let storage = RwLock::new(storage_with(&[]).0);
+        storage.write().unwrap().settings.dedup_action = DedupAction::Bump;
+        assert!(matches!(store(&storage, clip_at("same", base)).outcome, CaptureOutcome::Added(_)));
+        assert!(matches!(store(&storage, clip_after("same", base, 100)).outcome, CaptureOutcome::Bumped(_)));
+        assert_eq!(storage.read().unwrap().get_clips().len(), 1);
+
+        // Ignoring a duplicate leaves nothing to save
+        storage.write().unwrap().settings.dedup_action = DedupAction::Ignore;
+        let revision = storage.read().unwrap().revision;
+        assert!(matches!(store(&storage, clip_after("same", base, 200)).outcome, CaptureOutcome::Ignored));
+        assert_eq!(storage.read().unwrap().revision, revision);
+        assert_eq!(storage.read().unwrap().get_clips().len(), 1);
+    }
+
+    #[test]
+    fn captures_queue_behind_busy_storage_and_drain_in_order() {
+        let storage = RwLock::new(storage_with(&[]).0);
+        let inbox = CaptureInbox::new();
+
+        // A long export holds a read guard: captures queue instead of waiting
+        let drains = std::cell::Cell::new(0);
+        let start_drain = || drains.set(drains.get() + 1);
+        let export = storage.read().unwrap();
+        assert!(inbox.admit(&storage, clip("first"), start_drain).is_none());
+        assert!(inbox.admit(&storage, clip("second"), start_drain).is_none());
+        drop(export);
+
+        // Storage is free, but the queue goes first
+        assert!(inbox.admit(&storage, clip("third"), start_drain).is_none());
+        assert_eq!(drains.get(), 1);
+        assert_eq!(inbox.drain(&storage).len(), 3);
+        assert!(inbox.drain(&storage).is_empty());
+        assert!(inbox.admit(&storage, clip("fourth"), start_drain).is_some());
+
+        let contents: Vec<String> = storage.read().unwrap().get_clips().iter().map(|c| c.content.clone()).collect();
+        assert_eq!(contents, vec!["fourth", "third", "second", "first"]);
+    }
+
+    #[test]
+    fn held_captures_expire_and_are_claimed_once() {
+        let mut held = HeldCaptures::default();
+        let start = Instant::now();
+        let first = held.hold(clip("first"), start);
+        let ids: Vec<String> = (0..MAX_HELD).map(|i| held.hold(clip(&i.to_string()), start)).collect();
+        // The oldest made room for the newest
+        assert!(held.take(&first, start).is_none());
+        assert_eq!(held.take(&ids[0], start).unwrap().content, "0");
+        assert!(held.take(&ids[0], start).is_none());
+        assert!(held.take(&ids[1], start + HOLD_FOR).is_none());
+    }
+
+    #[test]
+    fn no_copy_times_out_without_reading_the_old_text() {
+        let clipboard = Arc::new(FakeClipboard::default());
+        clipboard.copy("old");
+        let before = clipboard.sequence();
+        assert_eq!(wait_for_copy(&clipboard, before, Duration::from_millis(20), false), None);
+    }
+}
@@ -0,0 +1,100 @@
+/// Most format names kept per capture
+pub const MAX_FORMATS: usize = 20;
+
+/// Name of a predefined clipboard format, which has no registered name
+#[cfg_attr(not(windows), allow(dead_code))]
+fn standard_name(format: u32) -> Option<&'static str> {
+    Some(match format {
+        1 => "CF_TEXT",
+        2 => "CF_BITMAP",
+        3 => "CF_METAFILEPICT",
+        4 => "CF_SYLK",
+        5 => "CF_DIF",
+        6 => "CF_TIFF",
+        7 => "CF_OEMTEXT",
+        8 => "CF_DIB",
+        9 => "CF_PALETTE",
+        10 => "CF_PENDATA",
+        11 => "CF_RIFF",
+        12 => "CF_WAVE",
+        13 => "CF_UNICODETEXT",
+        14 => "CF_ENHMETAFILE",
+        15 => "CF_HDROP",
+        16 => "CF_LOCALE",
+        17 => "CF_DIBV5",
+        0x80 => "CF_OWNERDISPLAY",
+        0x81 => "CF_DSPTEXT",
+        0x82 => "CF_DSPBITMAP",
+        0x83 => "CF_DSPMETAFILEPICT",
+        0x8E => "CF_DSPENHMETAFILE",
+        _ => return None,
+    })
+}
+
+/// Names for `formats` in the order offered, capped at `MAX_FORMATS`.
+/// `registered` looks up an app-defined format ("HTML Format", "XML
+/// Spreadsheet"); ones it can't name show as their number.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn names(formats: impl IntoIterator<Item = u32>, registered: impl Fn(u32) -> Option<String>) -> Vec<String> {
+    formats
+        .into_iter()
+        .take(MAX_FORMATS)
+        .map(|format| match standard_name(format) {
+            Some(name) => name.to_string(),
+            None => registered(format).unwrap_or_else(|| format!("#{}", format)),
+        })
+        .collect()
+}
+
+/// The formats on offer, by name. The caller must have the clipboard open,
+/// so the list matches what it reads in the same session.
+#[cfg(windows)]
+pub fn enumerate_open() -> Vec<String> {
+    use windows::Win32::System::DataExchange::{EnumClipboardFormats, GetClipboardFormatNameW};
+
+    let formats = std::iter::successors(Some(0u32), |&previous| {
+        let next = unsafe { EnumClipboardFormats(previous) };
+        (next != 0).then_some(next)
+    })
+    .skip(1);
+    names(formats, |format| {
+        let mut buffer = [0u16; 256];
+        let len = unsafe { GetClipboardFormatNameW(format, &mut buffer) };
+        (len > 0).then(|| String::from_utf16_lossy(&buffer[..len as usize]))
+    })
+}
+
+/// The formats on the clipboard right now, for diagnostics
+#[cfg(windows)]
+pub fn current() -> Result<Vec<String>, String> {
+    use windows::Win32::Foundation::HWND;
+    use windows::Win32::System::DataExchange::{CloseClipboard, OpenClipboard};
+
+    unsafe {
+        OpenClipboard(HWND::default()).map_err(|e| format!("Couldn't open the clipboard: {}", e))?;
+        let formats = enumerate_open();
+        let _ = CloseClipboard();
+        Ok(formats)
+    }
+}
+
+#[cfg(not(windows))]
+pub fn current() -> Result<Vec<String>, String> {
+    // Format enumeration is only wired up for the Windows clipboard
+    Ok(Vec::new())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn formats_are_named_in_order_and_capped() {
+        let registered = |format: u32| (format == 0xC0A0).then(|| "HTML Format".to_string());
+        assert_eq!(
+            names([0xC0A0, 13, 0xC0FF, 16], registered),
+            vec!["HTML Format", "CF_UNICODETEXT", "#49407", "CF_LOCALE"]
+        );
+        assert_eq!(names(0xC000..0xC100, |_| None).len(), MAX_FORMATS);
+    }
+}
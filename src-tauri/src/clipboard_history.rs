@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+
+use crate::ingest;
+use crate::storage::ClipObject;
+use crate::window::WindowInfo;
+
+/// Source app recorded on imported clips
+pub const SOURCE_APP: &str = "Windows Clipboard History";
+/// Returned when Windows has clipboard history turned off
+#[cfg(windows)]
+const HISTORY_DISABLED: &str = "Windows clipboard history is turned off. Turn on Settings > System > Clipboard > Clipboard history (or press Win+V), copy something, then try again.";
+
+/// A text entry from the Windows clipboard history
+#[derive(Debug, Clone)]
+pub struct HistoryEntry {
+    pub text: String,
+    /// When it was copied, if Windows reports it
+    pub copied_at: Option<DateTime<Utc>>,
+}
+
+/// Text entries in the Windows clipboard history (Win+V), newest first.
+/// Non-text entries (images, files) are skipped. Blocks until Windows
+/// answers, so call it off the async runtime.
+#[cfg(windows)]
+pub fn read_history() -> Result<Vec<HistoryEntry>, String> {
+    use windows::ApplicationModel::DataTransfer::{
+        Clipboard, ClipboardHistoryItemsResultStatus, StandardDataFormats,
+    };
+
+    use windows::Win32::System::Com::{CoInitializeEx, COINIT_MULTITHREADED};
+
+    // Runs on a worker thread; join the MTA so the WinRT calls work there
+    unsafe {
+        let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+    }
+    let fail = |e: windows::core::Error| format!("Couldn't read clipboard history: {}", e);
+    if !Clipboard::IsHistoryEnabled().map_err(fail)? {
+        return Err(HISTORY_DISABLED.to_string());
+    }
+    let result = Clipboard::GetHistoryItemsAsync().map_err(fail)?.get().map_err(fail)?;
+    match result.Status().map_err(fail)? {
+        ClipboardHistoryItemsResultStatus::Success => {}
+        ClipboardHistoryItemsResultStatus::ClipboardHistoryDisabled => {
+            return Err(HISTORY_DISABLED.to_string());
+        }
+        _ => return Err("Windows denied access to the clipboard history".to_string()),
+    }
+
+    let text_format = StandardDataFormats::Text().map_err(fail)?;
+    let mut entries = Vec::new();
+    for item in result.Items().map_err(fail)? {
+        let content = item.Content().map_err(fail)?;
+        if !content.Contains(&text_format).unwrap_or(false) {
+            continue;
+        }
+        let Ok(text) = content.GetTextAsync().and_then(|op| op.get()) else {
+            continue;
+        };
+        let copied_at = item
+            .Timestamp()
+            .ok()
+            .and_then(|t| from_windows_ticks(t.UniversalTime));
+        entries.push(HistoryEntry {
+            text: text.to_string(),
+            copied_at,
+        });
+    }
+    Ok(entries)
+}
+
+#[cfg(not(windows))]
+pub fn read_history() -> Result<Vec<HistoryEntry>, String> {
+    Err("Clipboard history import is only available on Windows".to_string())
+}
+
+/// A WinRT DateTime: 100ns ticks since 1601-01-01 UTC
+#[cfg_attr(not(windows), allow(dead_code))]
+fn from_windows_ticks(ticks: i64) -> Option<DateTime<Utc>> {
+    const UNIX_EPOCH_TICKS: i64 = 116_444_736_000_000_000;
+    let since_unix = ticks.checked_sub(UNIX_EPOCH_TICKS)?;
+    DateTime::from_timestamp(
+        since_unix.div_euclid(10_000_000),
+        (since_unix.rem_euclid(10_000_000) * 100) as u32,
+    )
+}
+
+/// Turn history entries into clips, sanitized like any other ingest and
+/// dropping the ones that come out blank or binary. Entries without a
+/// timestamp get `now`.
+pub fn to_clips(entries: Vec<HistoryEntry>, now: DateTime<Utc>) -> Vec<ClipObject> {
+    entries
+        .into_iter()
+        .filter_map(|entry| Some((ingest::sanitize_text(&entry.text).ok()?, entry.copied_at)))
+        .map(|(text, copied_at)| {
+            let mut clip = ClipObject::new(
+                text,
+                WindowInfo {
+                    app_name: SOURCE_APP.to_string(),
+                    window_title: String::new(),
+                    ..Default::default()
+                },
+            );
+            clip.metadata.timestamp = copied_at.unwrap_or(now);
+            clip
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn windows_ticks_convert_to_utc() {
+        // 2024-01-02T03:04:05.5Z
+        let ticks = 133_486_382_455_000_000;
+        assert_eq!(
+            from_windows_ticks(ticks).unwrap().to_rfc3339(),
+            "2024-01-02T03:04:05.500+00:00"
+        );
+        assert!(from_windows_ticks(0).is_some());
+    }
+
+    #[test]
+    fn entries_are_sanitized_and_blank_ones_dropped() {
+        let now = Utc::now();
+        let clips = to_clips(
+            vec![
+                HistoryEntry { text: "kept\0\r\n\u{1b}[1mbold".to_string(), copied_at: None },
+                HistoryEntry { text: "  \n".to_string(), copied_at: None },
+                HistoryEntry { text: "\0\u{7}".to_string(), copied_at: None },
+            ],
+            now,
+        );
+        assert_eq!(clips.len(), 1);
+        assert_eq!(clips[0].content, "kept\nbold");
+        assert_eq!(clips[0].metadata.source_app, SOURCE_APP);
+        assert_eq!(clips[0].metadata.timestamp, now);
+    }
+}
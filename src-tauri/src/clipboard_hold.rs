@@ -0,0 +1,228 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::{AppHandle, Emitter};
+use tauri_plugin_clipboard_manager::ClipboardExt;
+
+use crate::capture_path::{ClipboardSource, SystemClipboard};
+use crate::tray;
+
+/// Longest a clip can be held on the clipboard
+pub const MAX_HOLD_SECS: u64 = 60 * 60;
+/// How often a hold checks the clipboard
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// How quickly a hold puts its clip back after something else is copied
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum HoldAggressiveness {
+    /// Leaves another copy long enough to paste it a few times
+    Gentle,
+    #[default]
+    Normal,
+    /// Puts the clip back almost at once
+    Aggressive,
+}
+
+impl HoldAggressiveness {
+    /// How long the clipboard must hold the same other text before the
+    /// clip is put back; a further copy starts the wait again
+    fn restore_after(self) -> Duration {
+        match self {
+            Self::Gentle => Duration::from_secs(5),
+            Self::Normal => Duration::from_secs(1),
+            Self::Aggressive => Duration::from_millis(150),
+        }
+    }
+}
+
+/// Payload of the `clipboard-hold` event; the event carries null once the
+/// hold ends
+#[derive(Debug, Clone, Serialize)]
+pub struct HoldStatus {
+    pub clip_id: String,
+    pub until: DateTime<Utc>,
+}
+
+/// What a hold should do after looking at the clipboard
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Step {
+    Wait,
+    Restore,
+    Expired,
+}
+
+/// One clip kept on the clipboard. Time is passed in so the debounce can
+/// be tested without sleeping.
+#[derive(Debug)]
+pub struct Hold {
+    content: String,
+    started: Instant,
+    duration: Duration,
+    restore_after: Duration,
+    /// Other text on the clipboard and since when
+    displaced: Option<(String, Instant)>,
+}
+
+impl Hold {
+    pub fn new(content: String, duration: Duration, aggressiveness: HoldAggressiveness, now: Instant) -> Self {
+        Self {
+            content,
+            started: now,
+            duration,
+            restore_after: aggressiveness.restore_after(),
+            displaced: None,
+        }
+    }
+
+    /// Decide what to do with the clipboard holding `current`; None is no
+    /// text, such as a copied image
+    pub fn step(&mut self, current: Option<&str>, now: Instant) -> Step {
+        if now.duration_since(self.started) >= self.duration {
+            return Step::Expired;
+        }
+        let current = current.unwrap_or("");
+        if current == self.content {
+            self.displaced = None;
+            return Step::Wait;
+        }
+        match &self.displaced {
+            Some((text, since)) if text == current => {
+                if now.duration_since(*since) < self.restore_after {
+                    return Step::Wait;
+                }
+                self.displaced = None;
+                Step::Restore
+            }
+            _ => {
+                self.displaced = Some((current.to_string(), now));
+                Step::Wait
+            }
+        }
+    }
+}
+
+/// The running hold and a number that changes whenever it's replaced or
+/// released, so an old watcher knows to stop
+static ACTIVE: Mutex<(u64, Option<Hold>)> = Mutex::new((0, None));
+
+/// Put `content` on the clipboard and keep it there for `duration`,
+/// replacing any hold already running
+pub fn start(
+    app: &AppHandle,
+    clip_id: &str,
+    content: String,
+    duration: Duration,
+    aggressiveness: HoldAggressiveness,
+) -> Result<HoldStatus, String> {
+    app.clipboard()
+        .write_text(content.clone())
+        .map_err(|e| format!("Failed to write to clipboard: {}", e))?;
+    let hold = Hold::new(content, duration, aggressiveness, Instant::now());
+    let generation = {
+        let mut active = ACTIVE.lock().unwrap();
+        active.0 += 1;
+        active.1 = Some(hold);
+        active.0
+    };
+    let status = HoldStatus {
+        clip_id: clip_id.to_string(),
+        until: Utc::now() + chrono::Duration::from_std(duration).unwrap_or_default(),
+    };
+    announce(app, Some(&status));
+
+    let app = app.clone();
+    std::thread::spawn(move || watch(&app, generation));
+    Ok(status)
+}
+
+/// End the hold, if any; the clipboard keeps whatever it has now.
+/// Returns whether one was running.
+pub fn release(app: &AppHandle) -> bool {
+    let released = {
+        let mut active = ACTIVE.lock().unwrap();
+        active.0 += 1;
+        active.1.take().is_some()
+    };
+    if released {
+        announce(app, None);
+    }
+    released
+}
+
+/// Keep the hold's clip on the clipboard until it expires or is replaced
+fn watch(app: &AppHandle, generation: u64) {
+    let clipboard = SystemClipboard(app);
+    loop {
+        std::thread::sleep(POLL_INTERVAL);
+        let current = clipboard.read_text();
+        let mut active = ACTIVE.lock().unwrap();
+        if active.0 != generation {
+            return;
+        }
+        let Some(hold) = active.1.as_mut() else {
+            return;
+        };
+        match hold.step(current.as_deref(), Instant::now()) {
+            Step::Wait => {}
+            Step::Restore => {
+                let _ = app.clipboard().write_text(hold.content.clone());
+            }
+            Step::Expired => {
+                active.1 = None;
+                drop(active);
+                announce(app, None);
+                return;
+            }
+        }
+    }
+}
+
+/// Tell the windows and show the hold on the tray icon
+fn announce(app: &AppHandle, status: Option<&HoldStatus>) {
+    let _ = app.emit("clipboard-hold", status);
+    tray::set_holding(app, status.is_some());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hold(now: Instant) -> Hold {
+        Hold::new("123456".to_string(), Duration::from_secs(60), HoldAggressiveness::Normal, now)
+    }
+
+    #[test]
+    fn other_copies_are_replaced_once_they_settle() {
+        let start = Instant::now();
+        let mut hold = hold(start);
+        let at = |ms| start + Duration::from_millis(ms);
+        assert_eq!(hold.step(Some("123456"), at(100)), Step::Wait);
+
+        // Something else is copied; it gets a second before the clip returns
+        assert_eq!(hold.step(Some("other"), at(200)), Step::Wait);
+        assert_eq!(hold.step(Some("other"), at(900)), Step::Wait);
+        // A further copy starts the wait again
+        assert_eq!(hold.step(Some("another"), at(1100)), Step::Wait);
+        assert_eq!(hold.step(Some("another"), at(1900)), Step::Wait);
+        assert_eq!(hold.step(Some("another"), at(2100)), Step::Restore);
+        assert_eq!(hold.step(Some("123456"), at(2200)), Step::Wait);
+
+        // Copying something that isn't text displaces the clip too
+        assert_eq!(hold.step(None, at(2300)), Step::Wait);
+        assert_eq!(hold.step(None, at(3300)), Step::Restore);
+    }
+
+    #[test]
+    fn holds_expire() {
+        let start = Instant::now();
+        let mut hold = hold(start);
+        assert_eq!(hold.step(Some("other"), start + Duration::from_secs(60)), Step::Expired);
+
+        let aggressive = HoldAggressiveness::Aggressive;
+        let mut quick = Hold::new("x".to_string(), Duration::from_secs(60), aggressive, start);
+        assert_eq!(quick.step(Some("y"), start), Step::Wait);
+        assert_eq!(quick.step(Some("y"), start + Duration::from_millis(150)), Step::Restore);
+    }
+}
@@ -0,0 +1,62 @@
+use chrono::{DateTime, Duration, Utc};
+use std::time::Instant;
+
+/// The wall clock going back more than this between two events is taken as
+/// a clock change, not skew, and the events as not recent to each other
+const MAX_BACKWARD_JUMP_HOURS: i64 = 24;
+
+/// Time from `earlier` to `later` by the wall clock. A small negative gap
+/// (NTP correction, DST handled badly) counts as zero; a jump back of more
+/// than a day gives None.
+pub fn wall_elapsed(earlier: DateTime<Utc>, later: DateTime<Utc>) -> Option<Duration> {
+    let elapsed = later.signed_duration_since(earlier);
+    if elapsed < -Duration::hours(MAX_BACKWARD_JUMP_HOURS) {
+        return None;
+    }
+    Some(elapsed.max(Duration::zero()))
+}
+
+/// Whether `later` came less than `window` after `earlier` by the wall clock
+pub fn wall_within(earlier: DateTime<Utc>, later: DateTime<Utc>, window: Duration) -> bool {
+    wall_elapsed(earlier, later).is_some_and(|elapsed| elapsed < window)
+}
+
+/// Whether two events are less than `window` apart: by their monotonic
+/// instants when both have one (both happened in this run), else by their
+/// persisted wall-clock times
+pub fn within(
+    earlier: (DateTime<Utc>, Option<Instant>),
+    later: (DateTime<Utc>, Option<Instant>),
+    window: Duration,
+) -> bool {
+    match (earlier.1, later.1) {
+        (Some(earlier), Some(later)) => window
+            .to_std()
+            .is_ok_and(|window| later.saturating_duration_since(earlier) < window),
+        _ => wall_within(earlier.0, later.0, window),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn small_backward_steps_count_as_no_time() {
+        let now = Utc::now();
+        assert_eq!(wall_elapsed(now, now - Duration::minutes(5)), Some(Duration::zero()));
+        assert_eq!(wall_elapsed(now, now + Duration::seconds(3)), Some(Duration::seconds(3)));
+        assert_eq!(wall_elapsed(now, now - Duration::days(2)), None);
+        assert!(!wall_within(now, now - Duration::days(2), Duration::hours(1)));
+    }
+
+    #[test]
+    fn instants_win_over_a_skewed_wall_clock() {
+        let now = Utc::now();
+        let instant = Instant::now();
+        let window = Duration::seconds(2);
+        // Wall clock says a year apart; both happened in this run moments apart
+        assert!(within((now, Some(instant)), (now + Duration::days(365), Some(instant)), window));
+        assert!(!within((now, None), (now + Duration::days(365), Some(instant)), window));
+    }
+}
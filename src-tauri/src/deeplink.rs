@@ -0,0 +1,302 @@
+use serde::{Deserialize, Serialize};
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::time::Duration;
+use url::Url;
+
+use crate::jumplist::COPY_CLIP_ARG;
+use crate::paths;
+use crate::textutil;
+
+pub const SCHEME: &str = "stack";
+/// Largest text accepted from a link
+pub const MAX_TEXT_BYTES: usize = 100_000;
+const MAX_SOURCE_CHARS: usize = 64;
+const DEFAULT_SOURCE: &str = "deeplink";
+/// Percent-encoding can triple the text, plus room for the rest of the URL
+const MAX_URL_BYTES: usize = MAX_TEXT_BYTES * 3 + 1024;
+/// Room for a few forwarded requests per connection
+const MAX_FORWARD_BYTES: u64 = (MAX_URL_BYTES as u64 + 1024) * 8;
+/// Port and per-session secret of the running instance, readable only by
+/// the user who started it
+const PORT_FILE: &str = "instance.port";
+const FORWARD_TIMEOUT: Duration = Duration::from_secs(2);
+const ADD_FILE_ARG: &str = "--add-file";
+const ADD_TEXT_ARG: &str = "--add-text";
+
+/// Something a launch of Stack asked for; handled here or forwarded to the
+/// running instance
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LaunchRequest {
+    /// A `stack://` link opened by the OS
+    Link { url: String },
+    /// `--add-file <path>`, e.g. from the Explorer "Send to Stack" verb
+    AddFile { path: PathBuf },
+    /// `--add-text <text>`
+    AddText { text: String },
+    /// `--copy-clip <id>`, from a taskbar jump list entry
+    CopyClip { id: String },
+}
+
+/// A clip pushed through `stack://add?text=...&source=...`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeepLinkClip {
+    pub text: String,
+    pub source_app: String,
+}
+
+/// Parse and validate a `stack://add` link
+pub fn parse_url(raw: &str) -> Result<DeepLinkClip, String> {
+    if raw.len() > MAX_URL_BYTES {
+        return Err("Link is too long".to_string());
+    }
+
+    let url = Url::parse(raw.trim()).map_err(|e| format!("Malformed link: {}", e))?;
+    if url.scheme() != SCHEME {
+        return Err(format!("Unsupported link scheme '{}'", url.scheme()));
+    }
+
+    // `stack://add?...` puts the action in the host, `stack:add?...` in the path
+    let action = url
+        .host_str()
+        .unwrap_or_else(|| url.path())
+        .trim_matches('/');
+    if action != "add" {
+        return Err(format!("Unsupported link action '{}'", action));
+    }
+
+    let mut text = None;
+    let mut source = None;
+    for (key, value) in url.query_pairs() {
+        match key.as_ref() {
+            "text" => text = Some(value.into_owned()),
+            "source" => source = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+
+    let text = text.ok_or("Link has no text parameter")?;
+    if text.trim().is_empty() {
+        return Err("Link text is empty".to_string());
+    }
+    if text.len() > MAX_TEXT_BYTES {
+        return Err(format!(
+            "Link text is too large ({} bytes, max {})",
+            text.len(),
+            MAX_TEXT_BYTES
+        ));
+    }
+
+    Ok(DeepLinkClip {
+        text,
+        source_app: sanitize_source(source.as_deref()),
+    })
+}
+
+/// Keep the source label short and printable
+fn sanitize_source(source: Option<&str>) -> String {
+    let source: String = source
+        .unwrap_or("")
+        .chars()
+        .filter(|c| !c.is_control())
+        .take(MAX_SOURCE_CHARS)
+        .collect();
+    let source = source.trim();
+
+    if source.is_empty() {
+        DEFAULT_SOURCE.to_string()
+    } else {
+        source.to_string()
+    }
+}
+
+/// Links and --add-file/--add-text/--copy-clip requests Stack was launched with
+pub fn requests_from_args() -> Vec<LaunchRequest> {
+    let prefix = format!("{}:", SCHEME);
+    let mut requests = Vec::new();
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == ADD_FILE_ARG {
+            if let Some(path) = args.next() {
+                requests.push(LaunchRequest::AddFile { path: path.into() });
+            }
+        } else if arg == ADD_TEXT_ARG {
+            if let Some(text) = args.next() {
+                requests.push(LaunchRequest::AddText { text });
+            }
+        } else if arg == COPY_CLIP_ARG {
+            if let Some(id) = args.next() {
+                requests.push(LaunchRequest::CopyClip { id });
+            }
+        } else if arg.to_lowercase().starts_with(&prefix) {
+            requests.push(LaunchRequest::Link { url: arg });
+        }
+    }
+    requests
+}
+
+/// Hand launch requests to an already running Stack; false if none is listening
+pub fn forward_to_running_instance(requests: &[LaunchRequest]) -> bool {
+    let Some((port, secret)) = std::fs::read_to_string(paths::data_dir().join(PORT_FILE))
+        .ok()
+        .and_then(|contents| parse_port_file(&contents))
+    else {
+        return false;
+    };
+
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let Ok(mut stream) = TcpStream::connect_timeout(&addr, FORWARD_TIMEOUT) else {
+        return false;
+    };
+    let _ = stream.set_write_timeout(Some(FORWARD_TIMEOUT));
+
+    // The secret, then one JSON request per line
+    let mut message = format!("{}\n", secret);
+    for request in requests {
+        let Ok(line) = serde_json::to_string(request) else {
+            return false;
+        };
+        message.push_str(&line);
+        message.push('\n');
+    }
+    stream.write_all(message.as_bytes()).is_ok()
+}
+
+/// Port and secret from the port file: one per line
+fn parse_port_file(contents: &str) -> Option<(u16, String)> {
+    let mut lines = contents.lines();
+    let port = lines.next()?.trim().parse().ok()?;
+    let secret = lines.next()?.trim();
+    (!secret.is_empty()).then(|| (port, secret.to_string()))
+}
+
+/// Write the port file so only the current user can read it
+fn write_port_file(port: u16, secret: &str) -> std::io::Result<()> {
+    let path = paths::data_dir().join(PORT_FILE);
+    // A file left by an earlier session may have looser permissions
+    let _ = std::fs::remove_file(&path);
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    // On Windows the data dir sits in the user's profile, which only they can read
+    options.open(path)?.write_all(format!("{}\n{}\n", port, secret).as_bytes())
+}
+
+/// Requests from one connection, or nothing unless its first line is `secret`
+fn read_requests(reader: impl BufRead, secret: &str) -> Vec<LaunchRequest> {
+    let mut lines = reader.lines();
+    match lines.next() {
+        Some(Ok(presented)) if textutil::secrets_match(presented.trim(), secret) => {}
+        _ => return Vec::new(),
+    }
+    // Anything that isn't a JSON request ends the connection
+    lines
+        .map_while(Result::ok)
+        .map_while(|line| serde_json::from_str::<LaunchRequest>(&line).ok())
+        .collect()
+}
+
+/// Listen on loopback for requests forwarded by later launches of Stack.
+/// Only clients that present this session's secret are heard, so other
+/// local processes can't push clips or read files through it.
+pub fn start_listener<F>(handler: F)
+where
+    F: Fn(LaunchRequest) + Send + 'static,
+{
+    let listener = match TcpListener::bind((Ipv4Addr::LOCALHOST, 0)) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Deep link listener unavailable: {}", e);
+            return;
+        }
+    };
+
+    let port = match listener.local_addr() {
+        Ok(addr) => addr.port(),
+        Err(_) => return,
+    };
+    let secret = crate::local_api::generate_token();
+    if let Err(e) = write_port_file(port, &secret) {
+        eprintln!("Failed to record deep link port: {}", e);
+        return;
+    }
+
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            let _ = stream.set_read_timeout(Some(FORWARD_TIMEOUT));
+
+            // Capped so a bogus client can't make us buffer forever
+            let reader = BufReader::new(stream.take(MAX_FORWARD_BYTES));
+            for request in read_requests(reader, &secret) {
+                handler(request);
+            }
+        }
+    });
+}
+
+/// Register the `stack://` scheme for the current user so links launch Stack
+#[cfg(windows)]
+pub fn register_scheme() {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::System::Registry::{RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ};
+
+    let Ok(exe) = std::env::current_exe() else {
+        return;
+    };
+
+    let set = |subkey: &str, name: Option<&str>, value: &str| {
+        let data: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+        let name = name.map(HSTRING::from);
+        unsafe {
+            let _ = RegSetKeyValueW(
+                HKEY_CURRENT_USER,
+                &HSTRING::from(subkey),
+                name.as_ref().map_or(PCWSTR::null(), |n| PCWSTR(n.as_ptr())),
+                REG_SZ.0,
+                Some(data.as_ptr() as *const _),
+                (data.len() * 2) as u32,
+            );
+        }
+    };
+
+    let key = format!("Software\\Classes\\{}", SCHEME);
+    set(&key, None, "URL:Stack");
+    set(&key, Some("URL Protocol"), "");
+    set(
+        &format!("{}\\shell\\open\\command", key),
+        None,
+        &format!("\"{}\" \"%1\"", exe.display()),
+    );
+}
+
+#[cfg(not(windows))]
+pub fn register_scheme() {
+    // Scheme registration comes from the bundle's desktop entry elsewhere
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forwarded_requests_need_the_session_secret() {
+        let add = LaunchRequest::AddText { text: "hi".to_string() };
+        let line = serde_json::to_string(&add).unwrap();
+
+        let trusted = format!("s3cret\n{}\nnot json\n{}\n", line, line);
+        assert_eq!(read_requests(trusted.as_bytes(), "s3cret"), vec![add]);
+        let forged = format!("guess\n{}\n", line);
+        assert!(read_requests(forged.as_bytes(), "s3cret").is_empty());
+        assert!(read_requests(format!("{}\n", line).as_bytes(), "s3cret").is_empty());
+
+        assert_eq!(parse_port_file("4242\nabc\n"), Some((4242, "abc".to_string())));
+        assert_eq!(parse_port_file("4242\n"), None);
+    }
+}
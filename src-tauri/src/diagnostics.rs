@@ -0,0 +1,338 @@
+use chrono::{DateTime, Datelike, Local, Timelike, Utc};
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::path::Path;
+
+use crate::assets::AssetStats;
+use crate::metrics::CommandMetrics;
+use crate::storage::AppStorage;
+use crate::{health, hotkeys, safe_mode, shutdown};
+
+/// Stands in for secrets in the bundled settings
+const REDACTED: &str = "[redacted]";
+/// Settings fields that hold secrets, wherever they're nested
+const SECRET_FIELDS: &[&str] = &["local_api_token", "bearer_token"];
+/// What the bundle never holds, shown alongside the preview
+const EXCLUDED: &[&str] = &[
+    "Clip contents, titles, notes and scratchpads",
+    "Window titles and source context",
+    "Pastebook, tag and rule names",
+    "The AI key, local API token and webhook tokens",
+    "Webhook URLs beyond their host",
+];
+/// Asked for in bug reports but not kept by this version
+const UNAVAILABLE: &[&str] = &[
+    "Logs: Stack only logs to the console, so there are none on disk",
+    "Activity log: not recorded",
+];
+
+/// What feeds the bundle, gathered by the caller
+pub struct Sources<'a> {
+    pub app_version: String,
+    pub storage: &'a AppStorage,
+    pub assets: AssetStats,
+    pub metrics: Vec<CommandMetrics>,
+    pub data_dir: &'a Path,
+}
+
+/// One file of the bundle, shown in full by `preview_diagnostics`
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsFile {
+    pub name: &'static str,
+    pub description: &'static str,
+    pub contents: String,
+}
+
+/// Everything a bundle would hold, for review before saving
+#[derive(Debug, Clone, Serialize)]
+pub struct DiagnosticsManifest {
+    pub files: Vec<DiagnosticsFile>,
+    pub excluded: &'static [&'static str],
+    pub unavailable: &'static [&'static str],
+}
+
+/// Build the bundle's files from `sources`
+pub fn manifest(sources: &Sources) -> DiagnosticsManifest {
+    let storage = sources.storage;
+    let file = |name, description, value: Value| DiagnosticsFile {
+        name,
+        description,
+        contents: serde_json::to_string_pretty(&value).unwrap_or_default(),
+    };
+
+    // A pastebook at a time, so unloaded ones aren't all read in at once
+    let (mut clips, mut sensitive, mut pinned, mut externalized) = (0, 0, 0, 0);
+    for pastebook in &storage.pastebooks {
+        let book = pastebook.clips.peek();
+        clips += book.len();
+        sensitive += book.iter().filter(|c| c.sensitive).count();
+        pinned += book.iter().filter(|c| c.pinned).count();
+        externalized += book.iter().filter(|c| c.content_ref.is_some()).count();
+    }
+    let counts = json!({
+        "pastebooks": storage.pastebooks.len(),
+        "auto_created_pastebooks": storage.pastebooks.iter().filter(|p| p.auto_created).count(),
+        "clips": clips,
+        "sensitive_clips": sensitive,
+        "pinned_clips": pinned,
+        "externalized_clips": externalized,
+        "rules": storage.rules.len(),
+        "templates": storage.templates.len(),
+        "sessions": storage.sessions.len(),
+    });
+    let files = vec![
+        file(
+            "system.json",
+            "App and OS versions",
+            json!({
+                "app_version": sources.app_version,
+                "os": std::env::consts::OS,
+                "arch": std::env::consts::ARCH,
+                "os_version": os_version(),
+                "generated_at": Utc::now(),
+            }),
+        ),
+        file("settings.json", "Settings, with secrets redacted", redacted_settings(storage)),
+        file(
+            "storage.json",
+            "Storage health, startup checks, counts and data file sizes",
+            json!({
+                "health": health::current(),
+                "previous_unclean_shutdown": shutdown::previous(),
+                "safe_mode": safe_mode::reason(),
+                "counts": counts,
+                "search_index": storage.search_index.stats(),
+                "assets": sources.assets,
+                "files": data_files(sources.data_dir),
+            }),
+        ),
+        file("shortcuts.json", "Global hotkey registration status", json!(hotkeys::status())),
+        file("metrics.json", "Command timings", json!(sources.metrics)),
+    ];
+    DiagnosticsManifest { files, excluded: EXCLUDED, unavailable: UNAVAILABLE }
+}
+
+/// The settings as JSON with secret fields replaced and webhook URLs cut
+/// down to their origin
+fn redacted_settings(storage: &AppStorage) -> Value {
+    let mut settings = serde_json::to_value(&storage.settings).unwrap_or(Value::Null);
+    redact(&mut settings);
+    settings
+}
+
+fn redact(value: &mut Value) {
+    match value {
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                if SECRET_FIELDS.contains(&key.as_str()) && !field.is_null() {
+                    *field = Value::from(REDACTED);
+                } else if key == "url" {
+                    if let Some(url) = field.as_str() {
+                        *field = Value::from(origin(url));
+                    }
+                } else {
+                    redact(field);
+                }
+            }
+        }
+        Value::Array(items) => items.iter_mut().for_each(redact),
+        _ => {}
+    }
+}
+
+/// "https://hooks.example.com" for a full URL; paths and queries often
+/// carry secrets
+fn origin(url: &str) -> String {
+    match url::Url::parse(url) {
+        Ok(url) => url.origin().ascii_serialization(),
+        Err(_) => REDACTED.to_string(),
+    }
+}
+
+/// Each top-level entry of the data dir with its size; folders are summed
+fn data_files(dir: &Path) -> Vec<Value> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    let mut files: Vec<Value> = entries
+        .flatten()
+        .map(|entry| {
+            let (bytes, files) = disk_usage(&entry.path());
+            json!({ "name": entry.file_name().to_string_lossy(), "bytes": bytes, "files": files })
+        })
+        .collect();
+    files.sort_by(|a, b| a["name"].as_str().cmp(&b["name"].as_str()));
+    files
+}
+
+/// Bytes and files under `path`
+fn disk_usage(path: &Path) -> (u64, usize) {
+    match fs::read_dir(path) {
+        Ok(entries) => entries.flatten().fold((0, 0), |(bytes, files), entry| {
+            let (b, f) = disk_usage(&entry.path());
+            (bytes + b, files + f)
+        }),
+        Err(_) => (fs::metadata(path).map(|m| m.len()).unwrap_or(0), 1),
+    }
+}
+
+/// Windows edition and build, e.g. "Windows 10 Pro (build 22631)"
+#[cfg(windows)]
+fn os_version() -> Option<String> {
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_LOCAL_MACHINE, RRF_RT_REG_SZ};
+
+    let read = |name: &str| {
+        let mut buffer = [0u16; 128];
+        let mut size = std::mem::size_of_val(&buffer) as u32;
+        let status = unsafe {
+            RegGetValueW(
+                HKEY_LOCAL_MACHINE,
+                &HSTRING::from("SOFTWARE\\Microsoft\\Windows NT\\CurrentVersion"),
+                &HSTRING::from(name),
+                RRF_RT_REG_SZ,
+                None,
+                Some(buffer.as_mut_ptr() as *mut _),
+                Some(&mut size),
+            )
+        };
+        if status != ERROR_SUCCESS {
+            return None;
+        }
+        let len = buffer.iter().position(|&c| c == 0).unwrap_or(buffer.len());
+        Some(String::from_utf16_lossy(&buffer[..len]))
+    };
+    Some(format!("{} (build {})", read("ProductName")?, read("CurrentBuild")?))
+}
+
+#[cfg(not(windows))]
+fn os_version() -> Option<String> {
+    // Only the Windows version is looked up; `os` and `arch` still say where it ran
+    None
+}
+
+/// Default file name for a bundle made at `at`
+pub fn file_name(at: DateTime<Local>) -> String {
+    format!("stack-diagnostics-{}.zip", at.format("%Y%m%d-%H%M%S"))
+}
+
+/// Write the manifest's files to `path` as a zip
+pub fn write_bundle(manifest: &DiagnosticsManifest, path: &Path) -> Result<(), String> {
+    let mut files: Vec<(&str, &[u8])> = manifest
+        .files
+        .iter()
+        .map(|f| (f.name, f.contents.as_bytes()))
+        .collect();
+    let notes = format!(
+        "Stack diagnostics\n\nNot included:\n{}\n\nNot available:\n{}\n",
+        bullets(manifest.excluded),
+        bullets(manifest.unavailable)
+    );
+    files.push(("README.txt", notes.as_bytes()));
+    fs::write(path, zip(&files, Local::now())).map_err(|e| format!("Couldn't save {}: {}", path.display(), e))
+}
+
+fn bullets(lines: &[&str]) -> String {
+    lines.iter().map(|line| format!("- {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+/// A zip archive of `files`, stored uncompressed; the bundle is small text
+fn zip(files: &[(&str, &[u8])], at: DateTime<Local>) -> Vec<u8> {
+    const UTF8_NAMES: u16 = 1 << 11;
+    // MS-DOS format: two-second resolution, years from 1980
+    let time = ((at.hour() << 11) | (at.minute() << 5) | (at.second() / 2)) as u16;
+    let date = (((at.year().clamp(1980, 2107) as u32 - 1980) << 9) | (at.month() << 5) | at.day()) as u16;
+
+    let mut out = Vec::new();
+    let mut central = Vec::new();
+    for (name, data) in files {
+        let offset = out.len() as u32;
+        let crc = crc32fast::hash(data);
+        // Shared by the local header and the central directory entry
+        let mut common = Vec::new();
+        for field in [20u16, UTF8_NAMES, 0, time, date] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        for field in [crc, data.len() as u32, data.len() as u32] {
+            common.extend_from_slice(&field.to_le_bytes());
+        }
+        common.extend_from_slice(&(name.len() as u16).to_le_bytes());
+        common.extend_from_slice(&0u16.to_le_bytes());
+
+        out.extend_from_slice(&0x0403_4b50u32.to_le_bytes());
+        out.extend_from_slice(&common);
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(data);
+
+        central.extend_from_slice(&0x0201_4b50u32.to_le_bytes());
+        central.extend_from_slice(&20u16.to_le_bytes());
+        central.extend_from_slice(&common);
+        // Comment length, disk, internal and external attributes
+        central.extend_from_slice(&[0; 10]);
+        central.extend_from_slice(&offset.to_le_bytes());
+        central.extend_from_slice(name.as_bytes());
+    }
+
+    let central_offset = out.len() as u32;
+    out.extend_from_slice(&central);
+    out.extend_from_slice(&0x0605_4b50u32.to_le_bytes());
+    out.extend_from_slice(&[0; 4]);
+    for _ in 0..2 {
+        out.extend_from_slice(&(files.len() as u16).to_le_bytes());
+    }
+    out.extend_from_slice(&(central.len() as u32).to_le_bytes());
+    out.extend_from_slice(&central_offset.to_le_bytes());
+    out.extend_from_slice(&0u16.to_le_bytes());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::storage_with;
+    use crate::webhooks::WebhookConfig;
+
+    #[test]
+    fn bundles_leave_out_clips_titles_and_secrets() {
+        let (mut storage, _) = storage_with(&["private clip text"]);
+        let clip = &mut storage.pastebooks[0].clips[0];
+        clip.metadata.window_title = "Salary review.xlsx".to_string();
+        clip.title = Some("clip title".to_string());
+        storage.pastebooks[0].name = "Secret Project".to_string();
+        storage.settings.local_api_token = Some("token-123".to_string());
+        storage.settings.webhooks = vec![WebhookConfig {
+            url: "https://hooks.example.com/T0/secret-path?key=1".to_string(),
+            enabled: true,
+            events: Vec::new(),
+            bearer_token: Some("bearer-456".to_string()),
+            include_content: false,
+        }];
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("pastebooks.json"), "{}").unwrap();
+
+        let manifest = manifest(&Sources {
+            app_version: "1.2.3".to_string(),
+            storage: &storage,
+            assets: AssetStats::default(),
+            metrics: Vec::new(),
+            data_dir: dir.path(),
+        });
+        let everything: String = manifest.files.iter().map(|f| f.contents.as_str()).collect();
+        for private in ["private clip text", "Salary review", "clip title", "Secret Project", "token-123", "bearer-456", "secret-path"] {
+            assert!(!everything.contains(private), "bundle contains {}", private);
+        }
+        assert!(everything.contains("\"url\": \"https://hooks.example.com\""));
+        assert!(everything.contains("\"app_version\": \"1.2.3\""));
+        assert!(everything.contains("\"name\": \"pastebooks.json\""));
+
+        let path = dir.path().join("bundle.zip");
+        write_bundle(&manifest, &path).unwrap();
+        let bytes = fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"PK\x03\x04");
+        let end = &bytes[bytes.len() - 22..];
+        assert_eq!(&end[..4], b"PK\x05\x06");
+        assert_eq!(u16::from_le_bytes([end[10], end[11]]) as usize, manifest.files.len() + 1);
+    }
+}
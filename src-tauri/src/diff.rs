@@ -0,0 +1,324 @@
+use serde::{Deserialize, Serialize};
+use similar::{capture_diff_slices_deadline, Algorithm, TextDiff};
+use std::ops::Range;
+use std::time::{Duration, Instant};
+
+use crate::textutil;
+
+/// How long a diff may search for a minimal edit script before settling
+/// for a coarser one
+const DIFF_DEADLINE: Duration = Duration::from_millis(200);
+/// Unchanged lines kept around each change in unified output
+const UNIFIED_CONTEXT: usize = 3;
+/// Each side of a diff shown to the user is cut to this many bytes
+const MAX_DIFF_INPUT_BYTES: usize = 256 * 1024;
+/// Hunk text returned for one diff stops after this many bytes
+const MAX_DIFF_OUTPUT_BYTES: usize = 256 * 1024;
+
+/// What to diff by
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffMode {
+    Lines,
+    Words,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DiffOp {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// A run of tokens with the same op; ranges are token indices
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffHunk {
+    pub op: DiffOp,
+    pub old_range: Range<usize>,
+    pub new_range: Range<usize>,
+    pub text: String,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DiffResult {
+    pub mode: DiffMode,
+    pub hunks: Vec<DiffHunk>,
+    /// An input was cut to `MAX_DIFF_INPUT_BYTES` or the hunks to
+    /// `MAX_DIFF_OUTPUT_BYTES`, so the diff doesn't cover everything
+    pub truncated: bool,
+}
+
+/// Split into lines (keeping newlines) or at Unicode word boundaries, so
+/// joining the tokens gives back the exact text
+fn tokenize(text: &str, mode: DiffMode) -> Vec<&str> {
+    match mode {
+        DiffMode::Lines => text.split_inclusive('\n').collect(),
+        DiffMode::Words => textutil::word_bounds(text).collect(),
+    }
+}
+
+/// Add a run of tokens to the hunks, merging it into the last one when the
+/// op matches
+fn push_hunk(hunks: &mut Vec<DiffHunk>, op: DiffOp, old_range: Range<usize>, new_range: Range<usize>, tokens: &[&str]) {
+    if old_range.is_empty() && new_range.is_empty() {
+        return;
+    }
+    match hunks.last_mut() {
+        Some(hunk) if hunk.op == op => {
+            hunk.old_range.end = old_range.end;
+            hunk.new_range.end = new_range.end;
+            hunk.text.extend(tokens.iter().copied());
+        }
+        _ => hunks.push(DiffHunk {
+            op,
+            old_range,
+            new_range,
+            text: tokens.concat(),
+        }),
+    }
+}
+
+/// Every hunk between two texts. Past `DIFF_DEADLINE` the search settles
+/// for a coarser edit script, which is still correct.
+fn hunks(old_text: &str, new_text: &str, mode: DiffMode) -> Vec<DiffHunk> {
+    let old = tokenize(old_text, mode);
+    let new = tokenize(new_text, mode);
+
+    let deadline = Instant::now() + DIFF_DEADLINE;
+    let ops = capture_diff_slices_deadline(Algorithm::Myers, &old, &new, Some(deadline));
+
+    let mut hunks = Vec::new();
+    for op in ops {
+        let (old_range, new_range) = (op.old_range(), op.new_range());
+        match op {
+            similar::DiffOp::Equal { .. } => {
+                push_hunk(&mut hunks, DiffOp::Equal, old_range.clone(), new_range, &old[old_range]);
+            }
+            similar::DiffOp::Delete { .. } => {
+                push_hunk(&mut hunks, DiffOp::Delete, old_range.clone(), new_range, &old[old_range]);
+            }
+            similar::DiffOp::Insert { .. } => {
+                push_hunk(&mut hunks, DiffOp::Insert, old_range, new_range.clone(), &new[new_range]);
+            }
+            similar::DiffOp::Replace { .. } => {
+                let (old_end, new_start) = (old_range.end, new_range.start);
+                push_hunk(&mut hunks, DiffOp::Delete, old_range.clone(), new_start..new_start, &old[old_range]);
+                push_hunk(&mut hunks, DiffOp::Insert, old_end..old_end, new_range.clone(), &new[new_range]);
+            }
+        }
+    }
+    hunks
+}
+
+/// Each side cut to `MAX_DIFF_INPUT_BYTES`, and whether either was
+fn capped_inputs<'a>(old_text: &'a str, new_text: &'a str) -> (&'a str, &'a str, bool) {
+    let old = textutil::truncate_bytes(old_text, MAX_DIFF_INPUT_BYTES);
+    let new = textutil::truncate_bytes(new_text, MAX_DIFF_INPUT_BYTES);
+    (old, new, old.len() < old_text.len() || new.len() < new_text.len())
+}
+
+/// Diff two texts into hunks of equal, deleted and inserted tokens, capped
+/// for showing: inputs and hunk text over their limits are cut and the
+/// result marked truncated. Meant to run on copies of the clips, outside
+/// the storage lock.
+pub fn diff_texts(old_text: &str, new_text: &str, mode: DiffMode) -> DiffResult {
+    let (old, new, mut truncated) = capped_inputs(old_text, new_text);
+
+    let mut kept = Vec::new();
+    let mut budget = MAX_DIFF_OUTPUT_BYTES;
+    for mut hunk in hunks(old, new, mode) {
+        if hunk.text.len() > budget {
+            // The ranges still say what the hunk spans; only its text is cut
+            hunk.text.truncate(textutil::truncate_bytes(&hunk.text, budget).len());
+            truncated = true;
+        }
+        budget -= hunk.text.len();
+        kept.push(hunk);
+        if truncated && budget == 0 {
+            break;
+        }
+    }
+
+    DiffResult {
+        mode,
+        hunks: kept,
+        truncated,
+    }
+}
+
+/// One step of a patch rebuilding an older text from a newer one
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum PatchStep {
+    /// Bytes of the newer text to keep (positive) or drop (negative)
+    Span(i64),
+    /// Text only the older version has
+    Text(String),
+}
+
+/// A compact word-level patch that turns `newer` back into `older`
+pub fn make_patch(newer: &str, older: &str) -> Vec<PatchStep> {
+    hunks(newer, older, DiffMode::Words)
+        .into_iter()
+        .map(|hunk| match hunk.op {
+            DiffOp::Equal => PatchStep::Span(hunk.text.len() as i64),
+            DiffOp::Delete => PatchStep::Span(-(hunk.text.len() as i64)),
+            DiffOp::Insert => PatchStep::Text(hunk.text),
+        })
+        .collect()
+}
+
+/// Rebuild the older text from `newer` and a patch made against it
+pub fn apply_patch(newer: &str, patch: &[PatchStep]) -> Result<String, String> {
+    let mismatch = || "Conflict: patch doesn't match the text it's applied to".to_string();
+    let mut older = String::new();
+    let mut at = 0;
+    for step in patch {
+        match step {
+            PatchStep::Span(len) => {
+                let end = at + len.unsigned_abs() as usize;
+                let span = newer.get(at..end).ok_or_else(mismatch)?;
+                if *len > 0 {
+                    older.push_str(span);
+                }
+                at = end;
+            }
+            PatchStep::Text(text) => older.push_str(text),
+        }
+    }
+    if at != newer.len() {
+        return Err(mismatch());
+    }
+    Ok(older)
+}
+
+/// Render a line diff in unified format with a few lines of context, noting
+/// at the end when the inputs were cut to fit
+pub fn unified_diff(old_text: &str, new_text: &str, old_name: &str, new_name: &str) -> String {
+    let (old, new, truncated) = capped_inputs(old_text, new_text);
+    let diff = TextDiff::configure()
+        .algorithm(Algorithm::Myers)
+        .timeout(DIFF_DEADLINE)
+        .diff_lines(old, new);
+    let mut out = diff
+        .unified_diff()
+        .context_radius(UNIFIED_CONTEXT)
+        .header(old_name, new_name)
+        .to_string();
+
+    if truncated {
+        out.push_str(&format!(
+            "# diff truncated: only the first {} KB of each clip were compared\n",
+            MAX_DIFF_INPUT_BYTES / 1024
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Rebuild both sides from the hunks
+    fn sides(result: &DiffResult) -> (String, String) {
+        let mut old = String::new();
+        let mut new = String::new();
+        for hunk in &result.hunks {
+            if hunk.op != DiffOp::Insert {
+                old.push_str(&hunk.text);
+            }
+            if hunk.op != DiffOp::Delete {
+                new.push_str(&hunk.text);
+            }
+        }
+        (old, new)
+    }
+
+    #[test]
+    fn line_diff_marks_the_changed_line() {
+        let result = diff_texts("a\nb\nc\n", "a\nB\nc\n", DiffMode::Lines);
+        let ops: Vec<_> = result.hunks.iter().map(|h| (h.op, h.text.as_str())).collect();
+        assert_eq!(
+            ops,
+            [
+                (DiffOp::Equal, "a\n"),
+                (DiffOp::Delete, "b\n"),
+                (DiffOp::Insert, "B\n"),
+                (DiffOp::Equal, "c\n"),
+            ]
+        );
+        assert_eq!(result.hunks[1].old_range, 1..2);
+        assert_eq!(result.hunks[1].new_range, 1..1);
+        assert_eq!(result.hunks[2].old_range, 2..2);
+        assert_eq!(result.hunks[2].new_range, 1..2);
+        assert!(!result.truncated);
+    }
+
+    #[test]
+    fn word_diff_hunks_rebuild_both_texts() {
+        let (old, new) = ("the quick brown fox", "the slow brown dog jumps");
+        let result = diff_texts(old, new, DiffMode::Words);
+        assert_eq!(sides(&result), (old.to_string(), new.to_string()));
+        assert!(result
+            .hunks
+            .iter()
+            .any(|h| h.op == DiffOp::Insert && h.text.contains("slow")));
+    }
+
+    #[test]
+    fn identical_and_empty_texts() {
+        let same = diff_texts("one\ntwo", "one\ntwo", DiffMode::Lines);
+        assert_eq!(same.hunks.len(), 1);
+        assert_eq!(same.hunks[0].op, DiffOp::Equal);
+
+        assert!(diff_texts("", "", DiffMode::Words).hunks.is_empty());
+        let added = diff_texts("", "new", DiffMode::Words);
+        assert_eq!(added.hunks.len(), 1);
+        assert_eq!(added.hunks[0].op, DiffOp::Insert);
+    }
+
+    #[test]
+    fn patches_rebuild_the_older_text() {
+        let (older, newer) = ("Dear Sam, see you at 5.", "Dear Alex, see you at 6 tomorrow.");
+        let patch = make_patch(newer, older);
+        assert_eq!(apply_patch(newer, &patch).unwrap(), older);
+        assert!(apply_patch("something else", &patch).unwrap_err().starts_with("Conflict:"));
+    }
+
+    #[test]
+    fn oversized_inputs_are_capped_and_reported() {
+        let big = "line\n".repeat(MAX_DIFF_INPUT_BYTES / 5 + 100);
+        let result = diff_texts(&big, "line\n", DiffMode::Lines);
+        assert!(result.truncated);
+        let (old, _) = sides(&result);
+        assert_eq!(old.len(), MAX_DIFF_INPUT_BYTES);
+        assert!(!diff_texts("a", "b", DiffMode::Lines).truncated);
+
+        let unified = unified_diff(&big, "", "a", "b");
+        assert!(unified.ends_with("KB of each clip were compared\n"), "{}", &unified[unified.len() - 80..]);
+    }
+
+    #[test]
+    fn hunk_text_stops_at_the_output_cap() {
+        let old = "a\n".repeat(MAX_DIFF_OUTPUT_BYTES / 2 - 10);
+        let new = "b\n".repeat(MAX_DIFF_OUTPUT_BYTES / 2 - 10);
+        let result = diff_texts(&old, &new, DiffMode::Lines);
+        assert!(result.truncated);
+        let shown: usize = result.hunks.iter().map(|h| h.text.len()).sum();
+        assert_eq!(shown, MAX_DIFF_OUTPUT_BYTES);
+        // Ranges still cover everything the hunks stand for
+        assert_eq!(result.hunks.last().unwrap().new_range.end, MAX_DIFF_OUTPUT_BYTES / 2 - 10);
+    }
+
+    #[test]
+    fn unified_diff_has_headers_and_context() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        let unified = unified_diff(old, new, "a", "b");
+        assert_eq!(
+            unified,
+            "--- a\n+++ b\n@@ -2,7 +2,7 @@\n 2\n 3\n 4\n-5\n+five\n 6\n 7\n 8\n"
+        );
+    }
+}
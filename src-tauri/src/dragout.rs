@@ -0,0 +1,184 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+use crate::ingest::{self, ContentKind};
+use crate::storage::ClipObject;
+
+const ALLOWED_EXTENSIONS: [&str; 6] = ["txt", "md", "json", "html", "sql", "rs"];
+const MAX_FILE_AGE: Duration = Duration::from_secs(24 * 60 * 60);
+const MAX_NAME_LEN: usize = 40;
+
+/// Get the directory drag-out files are written to
+fn get_dragout_dir() -> PathBuf {
+    std::env::temp_dir().join("Stack").join("dragout")
+}
+
+/// SQL statements start with one of these (case-insensitive)
+const SQL_KEYWORDS: &[&str] = &["select", "insert", "update", "delete", "create", "alter", "drop", "with"];
+/// Rust source has lines starting with one of these
+const RUST_MARKERS: &[&str] = &["fn ", "pub fn ", "pub struct ", "impl ", "use std::", "#[derive(", "let mut "];
+
+/// Guess a file extension from the clip content: JSON, HTML and Markdown
+/// as `ingest::classify` sees them, then SQL and Rust by their first lines,
+/// else plain text
+pub fn detect_extension(content: &str) -> &'static str {
+    match ingest::classify(content) {
+        ContentKind::Json => return "json",
+        ContentKind::Html => return "html",
+        ContentKind::Markdown => return "md",
+        _ => {}
+    }
+    let first_word = content.split_whitespace().next().unwrap_or("").to_lowercase();
+    if SQL_KEYWORDS.contains(&first_word.as_str()) && content.trim_end().ends_with(';') {
+        return "sql";
+    }
+    if content.lines().any(|l| RUST_MARKERS.iter().any(|m| l.trim_start().starts_with(m))) {
+        return "rs";
+    }
+    "txt"
+}
+
+/// Build a filesystem-safe file stem from the clip title or content preview
+pub fn sanitize_file_stem(clip: &ClipObject) -> String {
+    let source = clip
+        .title
+        .as_deref()
+        .unwrap_or_else(|| clip.content.lines().find(|l| !l.trim().is_empty()).unwrap_or(""));
+
+    let mut stem = String::new();
+    for ch in source.chars() {
+        if stem.chars().count() >= MAX_NAME_LEN {
+            break;
+        }
+        if ch.is_alphanumeric() || ch == '-' || ch == '_' {
+            stem.push(ch);
+        } else if (ch.is_whitespace() || ch == '.') && !stem.is_empty() && !stem.ends_with(' ') {
+            stem.push(' ');
+        }
+    }
+
+    let stem = stem.trim().to_string();
+    if stem.is_empty() {
+        "clip".to_string()
+    } else {
+        stem
+    }
+}
+
+/// Write a clip to a uniquely named temp file and return its absolute path
+pub fn materialize_clip(clip: &ClipObject, extension_hint: Option<&str>) -> Result<PathBuf, String> {
+    let extension = match extension_hint {
+        Some(hint) => {
+            let hint = hint.trim().trim_start_matches('.').to_lowercase();
+            if !ALLOWED_EXTENSIONS.contains(&hint.as_str()) {
+                return Err(format!(
+                    "Unsupported extension '{}'. Allowed: {}",
+                    hint,
+                    ALLOWED_EXTENSIONS.join(", ")
+                ));
+            }
+            hint
+        }
+        None => detect_extension(&clip.content).to_string(),
+    };
+
+    let dir = get_dragout_dir();
+    fs::create_dir_all(&dir).map_err(|e| format!("Failed to create drag-out dir: {}", e))?;
+
+    let unique = Uuid::new_v4().simple().to_string();
+    let file_name = format!("{} {}.{}", sanitize_file_stem(clip), &unique[..8], extension);
+    let path = dir.join(file_name);
+
+    fs::write(&path, &clip.content).map_err(|e| format!("Failed to write file: {}", e))?;
+
+    Ok(path)
+}
+
+/// CF_HDROP data for one file: a DROPFILES header (offset to the list,
+/// drop point, non-client flag, wide flag) and then the wide path, ending
+/// in an empty entry
+#[cfg_attr(not(windows), allow(dead_code))]
+fn drop_files_data(path: &Path) -> Vec<u8> {
+    const HEADER_LEN: u32 = 20;
+    let mut data = Vec::new();
+    data.extend_from_slice(&HEADER_LEN.to_le_bytes());
+    data.extend_from_slice(&[0; 12]);
+    data.extend_from_slice(&1u32.to_le_bytes());
+    let path = path.as_os_str().to_string_lossy();
+    data.extend(path.encode_utf16().chain([0, 0]).flat_map(u16::to_le_bytes));
+    data
+}
+
+/// Put `path` on the clipboard as a file, the way Explorer's Copy does, so
+/// pasting into a chat app attaches it. `owner` is the window that opens the
+/// clipboard; with none, EmptyClipboard leaves the clipboard ownerless.
+#[cfg(windows)]
+pub fn copy_file_to_clipboard(path: &Path, owner: windows::Win32::Foundation::HWND) -> Result<(), String> {
+    use windows::Win32::Foundation::{GlobalFree, HANDLE};
+    use windows::Win32::System::DataExchange::{CloseClipboard, EmptyClipboard, OpenClipboard, SetClipboardData};
+    use windows::Win32::System::Memory::{GlobalAlloc, GlobalLock, GlobalUnlock, GMEM_MOVEABLE};
+    const CF_HDROP: u32 = 15;
+
+    let data = drop_files_data(path);
+    unsafe {
+        let memory = GlobalAlloc(GMEM_MOVEABLE, data.len()).map_err(|e| format!("Failed to allocate clipboard data: {}", e))?;
+        let target = GlobalLock(memory) as *mut u8;
+        if target.is_null() {
+            let _ = GlobalFree(memory);
+            return Err("Failed to lock clipboard data".to_string());
+        }
+        std::ptr::copy_nonoverlapping(data.as_ptr(), target, data.len());
+        let _ = GlobalUnlock(memory);
+
+        if let Err(e) = OpenClipboard(owner) {
+            let _ = GlobalFree(memory);
+            return Err(format!("Couldn't open the clipboard: {}", e));
+        }
+        let result = EmptyClipboard().and_then(|_| SetClipboardData(CF_HDROP, HANDLE(memory.0)));
+        let _ = CloseClipboard();
+        // The clipboard owns the memory once it has been set
+        result.map(|_| ()).map_err(|e| {
+            let _ = GlobalFree(memory);
+            format!("Failed to write to clipboard: {}", e)
+        })
+    }
+}
+
+/// Remove drag-out files older than a day
+pub fn cleanup_stale_files() {
+    let Ok(entries) = fs::read_dir(get_dragout_dir()) else {
+        return;
+    };
+
+    let now = SystemTime::now();
+    for entry in entries.flatten() {
+        let is_stale = entry
+            .metadata()
+            .and_then(|m| m.modified())
+            .ok()
+            .and_then(|modified| now.duration_since(modified).ok())
+            .is_some_and(|age| age > MAX_FILE_AGE);
+
+        if is_stale {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn drop_files_data_is_a_wide_double_terminated_list() {
+        let data = drop_files_data(Path::new("C:\\Temp\\a.txt"));
+        assert_eq!(&data[..4], &20u32.to_le_bytes());
+        assert_eq!(&data[16..20], &1u32.to_le_bytes());
+        let wide: Vec<u16> = data[20..].chunks(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        let mut expected: Vec<u16> = "C:\\Temp\\a.txt".encode_utf16().collect();
+        expected.extend([0, 0]);
+        assert_eq!(wide, expected);
+    }
+}
@@ -0,0 +1,635 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+
+use crate::dragout;
+use crate::storage::{ClipObject, Pastebook};
+use crate::window::WindowInfo;
+
+/// Longest slug in an exported file name
+const MAX_SLUG_LEN: usize = 50;
+/// Links every exported clip, in pastebook order
+const INDEX_FILE: &str = "_index.md";
+
+/// Version written into JSON exports; newer files are refused on import
+const JSON_VERSION: u32 = 1;
+
+/// What goes into an export besides each clip's id, capture time, title and
+/// content
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ExportOptions {
+    /// Source app, window title, label and pinned flag, plus the page text
+    /// around a browser selection as a footnote
+    pub include_metadata: bool,
+    pub include_tags: bool,
+    /// Sensitive clips are left out unless this is set
+    pub include_sensitive: bool,
+    /// The pastebook's scratchpad, ahead of the clips
+    pub include_scratchpad: bool,
+}
+
+impl Default for ExportOptions {
+    fn default() -> Self {
+        Self {
+            include_metadata: true,
+            include_tags: true,
+            include_sensitive: false,
+            include_scratchpad: false,
+        }
+    }
+}
+
+impl ExportOptions {
+    /// The pastebook's clips this export covers, in pastebook order
+    fn clips<'a>(&self, pastebook: &'a Pastebook) -> impl Iterator<Item = &'a ClipObject> + 'a {
+        let include_sensitive = self.include_sensitive;
+        pastebook.clips.iter().filter(move |c| include_sensitive || !c.sensitive)
+    }
+
+    /// The scratchpad, if this export covers it and it has anything in it
+    fn scratchpad<'a>(&self, pastebook: &'a Pastebook) -> Option<&'a str> {
+        Some(pastebook.scratchpad.as_str()).filter(|s| self.include_scratchpad && !s.trim().is_empty())
+    }
+}
+
+/// What an export wrote
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct ExportReport {
+    pub added: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+}
+
+/// Lowercase words joined by dashes, without anything Windows won't accept
+/// in a file name
+fn slugify(text: &str) -> String {
+    let mut slug = String::new();
+    for ch in text.chars() {
+        if slug.chars().count() >= MAX_SLUG_LEN {
+            break;
+        }
+        if ch.is_alphanumeric() || ch == '_' {
+            slug.extend(ch.to_lowercase());
+        } else if !slug.is_empty() && !slug.ends_with('-') {
+            slug.push('-');
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+/// The clip's title, or failing that its first non-blank line
+fn display_name(clip: &ClipObject) -> &str {
+    clip.title
+        .as_deref()
+        .filter(|t| !t.trim().is_empty())
+        .unwrap_or_else(|| clip.content.lines().find(|l| !l.trim().is_empty()).unwrap_or(""))
+        .trim()
+}
+
+/// `<timestamp>-<slug>`, or just the timestamp when nothing is left to slug
+fn file_stem(clip: &ClipObject) -> String {
+    let timestamp = clip.metadata.timestamp.format("%Y%m%d-%H%M%S");
+    match slugify(display_name(clip)) {
+        slug if slug.is_empty() => timestamp.to_string(),
+        slug => format!("{}-{}", timestamp, slug),
+    }
+}
+
+/// A YAML double-quoted scalar
+fn yaml_string(text: &str) -> String {
+    let mut out = String::from("\"");
+    for ch in text.chars() {
+        match ch {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// Front-matter followed by the clip's content
+fn clip_markdown(clip: &ClipObject, options: &ExportOptions) -> String {
+    let mut out = format!(
+        "---\nid: {}\ncreated: {}\n",
+        yaml_string(&clip.id),
+        clip.metadata.timestamp.to_rfc3339(),
+    );
+    if let Some(title) = clip.title.as_deref() {
+        out.push_str(&format!("title: {}\n", yaml_string(title)));
+    }
+    if options.include_metadata {
+        let label = clip.label.as_deref().map_or("null".to_string(), yaml_string);
+        out.push_str(&format!(
+            "source_app: {}\nwindow_title: {}\nlabel: {}\npinned: {}\n",
+            yaml_string(&clip.metadata.source_app),
+            yaml_string(&clip.metadata.window_title),
+            label,
+            clip.pinned,
+        ));
+    }
+    if options.include_tags {
+        let tags: Vec<String> = clip.tags.iter().map(|t| yaml_string(t)).collect();
+        out.push_str(&format!("tags: [{}]\n", tags.join(", ")));
+    }
+    if clip.sensitive {
+        out.push_str("sensitive: true\n");
+    }
+    out.push_str("---\n\n");
+    out.push_str(&clip.content);
+    let context = options.include_metadata.then(|| selection_context(clip)).flatten();
+    if context.is_some() {
+        out.truncate(out.trim_end().len());
+        out.push_str("[^context]");
+    }
+    if !out.ends_with('\n') {
+        out.push('\n');
+    }
+    if let Some(context) = context {
+        out.push_str(&format!("\n[^context]: {}\n", context));
+    }
+    out
+}
+
+/// The page text around a browser selection as "…before [CLIP] after…",
+/// if it was captured
+fn selection_context(clip: &ClipObject) -> Option<String> {
+    let context = clip.metadata.context.as_ref()?;
+    let side = |key: &str| {
+        let text = context.get(key).and_then(|v| v.as_str()).unwrap_or("");
+        text.split_whitespace().collect::<Vec<_>>().join(" ")
+    };
+    let (before, after) = (side("context_before"), side("context_after"));
+    if before.is_empty() && after.is_empty() {
+        return None;
+    }
+    let before = if before.is_empty() { String::new() } else { format!("…{} ", before) };
+    let after = if after.is_empty() { String::new() } else { format!(" {}…", after) };
+    Some(format!("{}[CLIP]{}", before, after))
+}
+
+/// The clip id in a file's front-matter, if it's one of ours
+fn exported_id(markdown: &str) -> Option<String> {
+    let front_matter = markdown.strip_prefix("---\n")?.split("\n---\n").next()?;
+    let id = front_matter.lines().find_map(|l| l.strip_prefix("id: "))?;
+    serde_json::from_str(id).ok()
+}
+
+/// Export formats: "markdown_folder" writes one Markdown file per clip into
+/// a folder, plus an `_index.md`; "json" writes the pastebook to one file
+/// that `import_json` reads back
+pub const FORMATS: &[&str] = &["markdown_folder", "json"];
+
+/// Export a pastebook to `path` in one of `FORMATS`
+pub fn export(format: &str, pastebook: &Pastebook, path: &Path, options: &ExportOptions) -> Result<ExportReport, String> {
+    match format {
+        "markdown_folder" => markdown_folder(pastebook, path, options),
+        "json" => json(pastebook, path, options),
+        other => Err(format!("Unknown export format '{}'", other)),
+    }
+}
+
+/// Write `contents` to `path` unless it already holds exactly that
+fn write_if_changed(path: &Path, contents: &str, report: &mut ExportReport) -> Result<(), String> {
+    match fs::read_to_string(path) {
+        Ok(existing) if existing == contents => {
+            report.unchanged += 1;
+            return Ok(());
+        }
+        Ok(_) => report.updated += 1,
+        Err(_) => report.added += 1,
+    }
+    fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Export a pastebook as one Markdown file per clip plus an `_index.md`, for
+/// Obsidian-style vaults. Exporting again into the same folder rewrites a
+/// clip's existing file (found by the id in its front-matter) instead of
+/// adding another. The counts cover clip files, not the index.
+pub fn markdown_folder(pastebook: &Pastebook, dir: &Path, options: &ExportOptions) -> Result<ExportReport, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Failed to create {}: {}", dir.display(), e))?;
+
+    // Files from earlier exports, by clip id
+    let mut existing: HashMap<String, String> = HashMap::new();
+    let mut taken: HashSet<String> = HashSet::new();
+    let entries = fs::read_dir(dir).map_err(|e| format!("Failed to read {}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if !name.ends_with(".md") || name == INDEX_FILE {
+            continue;
+        }
+        taken.insert(name.to_lowercase());
+        if let Some(id) = fs::read_to_string(entry.path()).ok().as_deref().and_then(exported_id) {
+            existing.insert(id, name);
+        }
+    }
+
+    let mut report = ExportReport::default();
+    let mut index = match &pastebook.group {
+        Some(group) => format!("---\ngroup: {}\n---\n\n", yaml_string(group)),
+        None => String::new(),
+    };
+    index.push_str(&format!("# {}\n\n", pastebook.name));
+    if let Some(scratchpad) = options.scratchpad(pastebook) {
+        index.push_str(&format!("## Scratchpad\n\n{}\n\n## Clips\n\n", scratchpad.trim_end()));
+    }
+    for clip in options.clips(pastebook) {
+        let name = match existing.get(&clip.id) {
+            Some(name) => name.clone(),
+            None => {
+                // Windows file names ignore case, so collisions do too
+                let stem = file_stem(clip);
+                let mut name = format!("{}.md", stem);
+                let mut n = 2;
+                while taken.contains(&name.to_lowercase()) {
+                    name = format!("{}-{}.md", stem, n);
+                    n += 1;
+                }
+                taken.insert(name.to_lowercase());
+                name
+            }
+        };
+        write_if_changed(&dir.join(&name), &clip_markdown(clip, options), &mut report)?;
+
+        let text = match display_name(clip) {
+            "" => "(empty clip)".to_string(),
+            name => name.replace(['[', ']'], ""),
+        };
+        let tags: String = match options.include_tags {
+            true => clip
+                .tags
+                .iter()
+                .map(|t| slugify(t))
+                .filter(|t| !t.is_empty())
+                .map(|t| format!(" #{}", t))
+                .collect(),
+            false => String::new(),
+        };
+        index.push_str(&format!("- [{}](<{}>){}\n", text, name, tags));
+    }
+
+    write_if_changed(&dir.join(INDEX_FILE), &index, &mut ExportReport::default())?;
+    Ok(report)
+}
+
+/// A clip as the JSON export writes it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ExportedClip {
+    pub id: String,
+    pub created: DateTime<Utc>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub source_app: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub window_title: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub label: Option<String>,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub pinned: bool,
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub sensitive: bool,
+}
+
+impl ExportedClip {
+    fn new(clip: &ClipObject, options: &ExportOptions) -> Self {
+        let metadata = options.include_metadata;
+        Self {
+            id: clip.id.clone(),
+            created: clip.metadata.timestamp,
+            title: clip.title.clone(),
+            content: clip.content.clone(),
+            source_app: metadata.then(|| clip.metadata.source_app.clone()),
+            window_title: metadata.then(|| clip.metadata.window_title.clone()),
+            tags: if options.include_tags { clip.tags.clone() } else { Vec::new() },
+            label: clip.label.clone().filter(|_| metadata),
+            pinned: clip.pinned && metadata,
+            sensitive: clip.sensitive,
+        }
+    }
+
+    /// A new clip (with a fresh id) carrying everything exported
+    pub fn into_clip(self) -> ClipObject {
+        let window_info = WindowInfo {
+            app_name: self.source_app.unwrap_or_default(),
+            window_title: self.window_title.unwrap_or_default(),
+            ..Default::default()
+        };
+        let mut clip = ClipObject::new(self.content, window_info);
+        clip.metadata.timestamp = self.created;
+        clip.title = self.title;
+        clip.tags = self.tags;
+        clip.label = self.label;
+        clip.pinned = self.pinned;
+        clip.sensitive = self.sensitive;
+        clip
+    }
+}
+
+/// A pastebook as the JSON export writes it
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PastebookFile {
+    pub version: u32,
+    pub name: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scratchpad: Option<String>,
+    pub clips: Vec<ExportedClip>,
+}
+
+/// Export a pastebook as a single JSON file. The counts cover that file.
+pub fn json(pastebook: &Pastebook, path: &Path, options: &ExportOptions) -> Result<ExportReport, String> {
+    let file = PastebookFile {
+        version: JSON_VERSION,
+        name: pastebook.name.clone(),
+        group: pastebook.group.clone(),
+        scratchpad: options.scratchpad(pastebook).map(str::to_string),
+        clips: options.clips(pastebook).map(|c| ExportedClip::new(c, options)).collect(),
+    };
+    let mut json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+    json.push('\n');
+    let mut report = ExportReport::default();
+    write_if_changed(path, &json, &mut report)?;
+    Ok(report)
+}
+
+/// Line endings written into a single-clip export
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+}
+
+impl LineEnding {
+    /// `text` with every line ending made this one
+    pub fn apply(self, text: &str) -> String {
+        let lf = text.replace("\r\n", "\n");
+        match self {
+            Self::Lf => lf,
+            Self::Crlf => lf.replace('\n', "\r\n"),
+        }
+    }
+}
+
+/// What `export_clip` did
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ClipExport {
+    Saved { path: String },
+    /// The user dismissed the save dialog
+    Cancelled,
+}
+
+/// Suggested file name for a clip saved on its own, from its title or
+/// first line
+pub fn clip_file_name(clip: &ClipObject) -> String {
+    format!("{}.{}", dragout::sanitize_file_stem(clip), dragout::detect_extension(&clip.content))
+}
+
+/// Write a clip's content to `path` as UTF-8 without a BOM, with
+/// `line_ending` line endings
+pub fn write_clip(clip: &ClipObject, path: &Path, line_ending: LineEnding) -> Result<(), String> {
+    let content = clip.content.strip_prefix('\u{feff}').unwrap_or(&clip.content);
+    fs::write(path, line_ending.apply(content)).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Read a pastebook written by `json`
+pub fn import_json(path: &Path) -> Result<PastebookFile, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let file: PastebookFile =
+        serde_json::from_str(&json).map_err(|e| format!("Not a pastebook export: {}", e))?;
+    if file.version > JSON_VERSION {
+        return Err(format!("Pastebook export version {} is newer than this Stack", file.version));
+    }
+    Ok(file)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::AppStorage;
+    use crate::test_support::{clip, clip_at};
+    use chrono::{Duration, TimeZone};
+
+    fn pastebook(contents: &[&str]) -> Pastebook {
+        let mut pastebook = Pastebook::new("Notes".to_string());
+        pastebook.clips = contents.iter().map(|c| clip(c)).collect();
+        pastebook
+    }
+
+    #[test]
+    fn slugs_drop_characters_windows_rejects() {
+        assert_eq!(slugify("What's <this>: a/b\\c|d?*"), "what-s-this-a-b-c-d");
+        assert_eq!(slugify("  ...  "), "");
+        assert!(slugify(&"long ".repeat(30)).len() <= MAX_SLUG_LEN);
+    }
+
+    #[test]
+    fn clips_become_files_with_front_matter_and_an_index() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut book = pastebook(&["Same line", "Same line"]);
+        book.group = Some("Work".to_string());
+        book.clips[0].tags = vec!["rust".to_string(), "say \"hi\"".to_string()];
+        book.clips[1].metadata.timestamp = book.clips[0].metadata.timestamp;
+
+        let report = markdown_folder(&book, dir.path(), &ExportOptions::default()).unwrap();
+        assert_eq!(report, ExportReport { added: 2, ..Default::default() });
+
+        let stem = file_stem(&book.clips[0]);
+        let first = fs::read_to_string(dir.path().join(format!("{}.md", stem))).unwrap();
+        assert!(first.contains(r#"tags: ["rust", "say \"hi\""]"#));
+        assert!(first.ends_with("---\n\nSame line\n"));
+        assert_eq!(exported_id(&first).as_deref(), Some(book.clips[0].id.as_str()));
+        assert!(dir.path().join(format!("{}-2.md", stem)).exists());
+
+        let index = fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert!(index.starts_with("---\ngroup: \"Work\"\n---\n\n# Notes\n"));
+        assert!(index.contains(&format!("- [Same line](<{}.md>) #rust #say-hi\n- [Same line](<{}-2.md>)\n", stem, stem)));
+    }
+
+    #[test]
+    fn exporting_again_updates_files_in_place() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut book = pastebook(&["one", "two"]);
+        markdown_folder(&book, dir.path(), &ExportOptions::default()).unwrap();
+
+        book.clips[1].content = "two, edited".to_string();
+        book.clips[1].title = Some("Renamed".to_string());
+        book.clips.push(clip("three"));
+        let report = markdown_folder(&book, dir.path(), &ExportOptions::default()).unwrap();
+        assert_eq!(report, ExportReport { added: 1, updated: 1, unchanged: 1 });
+        // The renamed clip kept its file
+        let files = fs::read_dir(dir.path()).unwrap().count();
+        assert_eq!(files, 4);
+    }
+
+    /// Expected output checked in under tests/fixtures/export
+    fn golden(name: &str) -> String {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/export").join(name);
+        fs::read_to_string(&path).unwrap_or_else(|e| panic!("{}: {}", path.display(), e))
+    }
+
+    /// A tagged, labelled, pinned clip, a plain one and a sensitive one, with
+    /// fixed ids and times
+    fn fixture() -> Pastebook {
+        let at = Utc.with_ymd_and_hms(2026, 5, 4, 9, 30, 0).unwrap();
+        let mut book = Pastebook::new("Research".to_string());
+        book.group = Some("Work".to_string());
+
+        let mut tagged = clip_at("fn main() {}\n", at);
+        tagged.id = "clip-tagged".to_string();
+        tagged.title = Some("Entry point".to_string());
+        tagged.tags = vec!["rust".to_string(), "code snippets".to_string()];
+        tagged.label = Some("green".to_string());
+        tagged.pinned = true;
+        let mut plain = clip_at("Call Ada back", at + Duration::hours(1));
+        plain.id = "clip-plain".to_string();
+        let mut secret = clip_at("hunter2", at + Duration::hours(2));
+        secret.id = "clip-secret".to_string();
+        secret.sensitive = true;
+
+        *book.clips = vec![secret, plain, tagged];
+        book
+    }
+
+    #[test]
+    fn markdown_export_matches_golden_files() {
+        let dir = tempfile::tempdir().unwrap();
+        markdown_folder(&fixture(), dir.path(), &ExportOptions::default()).unwrap();
+
+        let names = ["20260504-093000-entry-point.md", "20260504-103000-call-ada-back.md", INDEX_FILE];
+        for name in names {
+            let written = fs::read_to_string(dir.path().join(name)).unwrap();
+            assert_eq!(written, golden(name), "{}", name);
+        }
+        // The sensitive clip was left out
+        assert_eq!(fs::read_dir(dir.path()).unwrap().count(), names.len());
+    }
+
+    #[test]
+    fn json_export_matches_golden_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("research.json");
+        json(&fixture(), &path, &ExportOptions::default()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), golden("research.json"));
+    }
+
+    #[test]
+    fn options_leave_out_metadata_and_tags() {
+        let book = fixture();
+        let bare = ExportOptions { include_metadata: false, include_tags: false, include_sensitive: true, ..Default::default() };
+        let markdown = clip_markdown(&book.clips[2], &bare);
+        assert!(!markdown.contains("source_app") && !markdown.contains("tags:") && !markdown.contains("pinned"));
+        assert!(clip_markdown(&book.clips[0], &bare).contains("sensitive: true\n"));
+
+        let exported = ExportedClip::new(&book.clips[2], &bare);
+        assert_eq!((exported.label, exported.pinned, exported.tags.len()), (None, false, 0));
+        assert_eq!(bare.clips(&book).count(), 3);
+    }
+
+    #[test]
+    fn selection_context_becomes_a_footnote() {
+        let mut quoted = clip("the key finding");
+        quoted.metadata.context = Some(serde_json::json!({
+            "context_before": "As the study shows,\n",
+            "context_after": " held up in every trial.",
+        }));
+        let markdown = clip_markdown(&quoted, &ExportOptions::default());
+        assert!(markdown.ends_with(
+            "the key finding[^context]\n\n[^context]: …As the study shows, [CLIP] held up in every trial.…\n"
+        ));
+
+        let bare = ExportOptions { include_metadata: false, ..Default::default() };
+        assert!(!clip_markdown(&quoted, &bare).contains("[CLIP]"));
+        quoted.metadata.context = Some(serde_json::json!({ "editor": "code" }));
+        assert!(!clip_markdown(&quoted, &ExportOptions::default()).contains("[^context]"));
+    }
+
+    #[test]
+    fn scratchpads_are_exported_only_when_asked() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut book = pastebook(&["one"]);
+        book.scratchpad = "Remember the *why*\n".to_string();
+        let with_scratchpad = ExportOptions { include_scratchpad: true, ..Default::default() };
+
+        markdown_folder(&book, dir.path(), &ExportOptions::default()).unwrap();
+        let index = fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert!(!index.contains("Remember"));
+        markdown_folder(&book, dir.path(), &with_scratchpad).unwrap();
+        let index = fs::read_to_string(dir.path().join(INDEX_FILE)).unwrap();
+        assert!(index.starts_with("# Notes\n\n## Scratchpad\n\nRemember the *why*\n\n## Clips\n\n- [one]"));
+
+        let path = dir.path().join("notes.json");
+        json(&book, &path, &with_scratchpad).unwrap();
+        assert_eq!(import_json(&path).unwrap().scratchpad.as_deref(), Some("Remember the *why*\n"));
+        json(&book, &path, &ExportOptions::default()).unwrap();
+        assert_eq!(import_json(&path).unwrap().scratchpad, None);
+    }
+
+    #[test]
+    fn single_clips_get_a_fitting_name_and_line_endings() {
+        let mut query = clip("SELECT id\nFROM clips\nWHERE pinned;");
+        query.title = Some("Pinned clips: query".to_string());
+        assert_eq!(clip_file_name(&query), "Pinned clips query.sql");
+        assert_eq!(clip_file_name(&clip("{\"port\": 8080}")), "port 8080.json");
+        assert_eq!(dragout::detect_extension("use std::fs;\n\nfn main() {}"), "rs");
+        assert_eq!(dragout::detect_extension("# Notes\n\ntext"), "md");
+        assert_eq!(dragout::detect_extension("select the best option"), "txt");
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("query.sql");
+        query.content = "\u{feff}SELECT 1;\r\nSELECT 2;\n".to_string();
+        write_clip(&query, &path, LineEnding::Crlf).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"SELECT 1;\r\nSELECT 2;\r\n");
+        write_clip(&query, &path, LineEnding::Lf).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"SELECT 1;\nSELECT 2;\n");
+    }
+
+    #[test]
+    fn json_round_trip_restores_organization() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("research.json");
+        let book = fixture();
+        let everything = ExportOptions { include_sensitive: true, ..Default::default() };
+        json(&book, &path, &everything).unwrap();
+
+        let file = import_json(&path).unwrap();
+        let clips = file.clips.into_iter().map(ExportedClip::into_clip).collect();
+        let mut storage = AppStorage::default();
+        storage.create_pastebook("Research".to_string()).unwrap();
+        let imported = storage.import_pastebook(&file.name, file.group, clips).unwrap();
+
+        assert_eq!(imported.name, "Research (2)");
+        assert_eq!(imported.group.as_deref(), Some("Work"));
+        assert_eq!(imported.clips.len(), book.clips.len());
+        for (restored, original) in imported.clips.iter().zip(&book.clips) {
+            assert_ne!(restored.id, original.id);
+            assert_eq!(restored.content, original.content);
+            assert_eq!(restored.title, original.title);
+            assert_eq!(restored.metadata.timestamp, original.metadata.timestamp);
+            assert_eq!(restored.metadata.source_app, original.metadata.source_app);
+            assert_eq!(restored.tags, original.tags);
+            assert_eq!(restored.label, original.label);
+            assert_eq!((restored.pinned, restored.sensitive), (original.pinned, original.sensitive));
+        }
+        assert_eq!(storage.search_pastebook(&imported.id, "ada").unwrap().len(), 1);
+
+        fs::write(&path, r#"{"version": 99, "name": "x", "clips": []}"#).unwrap();
+        assert!(import_json(&path).unwrap_err().contains("newer"));
+    }
+}
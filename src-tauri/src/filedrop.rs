@@ -0,0 +1,104 @@
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+
+use crate::ingest;
+
+/// Files with these extensions are captured by content when small enough
+const TEXT_EXTENSIONS: &[&str] = &["txt", "md", "log", "csv", "json"];
+/// Largest text file captured by content; bigger ones are listed by path
+pub const MAX_DROPPED_TEXT_BYTES: u64 = 1024 * 1024;
+/// Source app recorded on clips dropped onto the window
+pub const SOURCE_APP: &str = "drag-drop";
+
+/// What a dropped file becomes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Dropped {
+    /// A text file's sanitized contents
+    Text { name: String, content: String },
+    /// A text file holding binary data (or nothing), refused
+    Rejected { name: String, reason: ingest::RejectReason },
+    /// Anything else, listed by path in one clip for the whole drop
+    Listed(PathBuf),
+}
+
+/// Payload of the `drop-rejected` event
+#[derive(Debug, Clone, Serialize)]
+pub struct DropRejected {
+    pub name: String,
+    pub reason: String,
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+/// Read a dropped path: small text files by content, everything else
+/// (folders, other types, text files over the cap) by path
+pub fn classify(path: &Path) -> Dropped {
+    let is_text = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    let small_file = std::fs::metadata(path).is_ok_and(|m| m.is_file() && m.len() <= MAX_DROPPED_TEXT_BYTES);
+    if !is_text || !small_file {
+        return Dropped::Listed(path.to_path_buf());
+    }
+
+    let name = file_name(path);
+    let Ok(bytes) = std::fs::read(path) else {
+        return Dropped::Listed(path.to_path_buf());
+    };
+    match ingest::sanitize_text(&String::from_utf8_lossy(&bytes)) {
+        Ok(content) => Dropped::Text { name, content },
+        Err(reason) => Dropped::Rejected { name, reason },
+    }
+}
+
+/// Content and window title of the clip listing non-text files
+pub fn file_list(paths: &[PathBuf]) -> (String, String) {
+    let content = paths
+        .iter()
+        .map(|p| p.display().to_string())
+        .collect::<Vec<_>>()
+        .join("\n");
+    let title = match paths {
+        [only] => file_name(only),
+        _ => format!("{} files", paths.len()),
+    };
+    (content, title)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ingest::RejectReason;
+
+    #[test]
+    fn dropped_files_are_read_listed_or_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let notes = dir.path().join("notes.TXT");
+        std::fs::write(&notes, "line one\r\nline two").unwrap();
+        let binary = dir.path().join("data.txt");
+        std::fs::write(&binary, [0u8, 159, 146, 150, 0, 1, 2, 3, 0xff, 0xfe]).unwrap();
+        let image = dir.path().join("shot.png");
+        std::fs::write(&image, [137u8, 80, 78, 71]).unwrap();
+
+        assert_eq!(
+            classify(&notes),
+            Dropped::Text { name: "notes.TXT".to_string(), content: "line one\nline two".to_string() }
+        );
+        assert_eq!(
+            classify(&binary),
+            Dropped::Rejected { name: "data.txt".to_string(), reason: RejectReason::BinaryContent }
+        );
+        assert_eq!(classify(&image), Dropped::Listed(image.clone()));
+        assert_eq!(classify(dir.path()), Dropped::Listed(dir.path().to_path_buf()));
+
+        let (content, title) = file_list(&[image.clone(), dir.path().to_path_buf()]);
+        assert_eq!(content.lines().count(), 2);
+        assert_eq!(title, "2 files");
+        assert_eq!(file_list(std::slice::from_ref(&image)).1, "shot.png");
+    }
+}
@@ -0,0 +1,125 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+use crate::paths;
+
+/// How often an unwritable data dir is tried again
+pub const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+const PROBE_FILE: &str = ".write-test";
+
+/// Whether storage writes reach disk
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StorageMode {
+    Persistent,
+    /// The data dir can't be written; everything lives in memory until it can
+    InMemory,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StorageHealth {
+    pub mode: StorageMode,
+    pub data_dir: PathBuf,
+    /// Why storage went in-memory
+    pub error: Option<String>,
+    pub since: Option<DateTime<Utc>>,
+    pub last_retry: Option<DateTime<Utc>>,
+    /// The existing pastebooks file couldn't be read at startup; it's backed
+    /// up before the in-memory state is first written over it
+    #[serde(skip)]
+    pub unreadable_on_load: bool,
+}
+
+static HEALTH: Mutex<Option<StorageHealth>> = Mutex::new(None);
+static APP: OnceLock<AppHandle> = OnceLock::new();
+
+fn with_health<T>(f: impl FnOnce(&mut StorageHealth) -> T) -> T {
+    let mut health = HEALTH.lock().unwrap();
+    let health = health.get_or_insert_with(|| StorageHealth {
+        mode: StorageMode::Persistent,
+        data_dir: paths::data_dir_info().path,
+        error: None,
+        since: None,
+        last_retry: None,
+        unreadable_on_load: false,
+    });
+    f(health)
+}
+
+/// Give health tracking an app handle so mode changes reach the UI
+pub fn attach(app: AppHandle) {
+    let _ = APP.set(app);
+}
+
+pub fn current() -> StorageHealth {
+    with_health(|health| {
+        health.data_dir = paths::data_dir_info().path;
+        health.clone()
+    })
+}
+
+pub fn is_in_memory() -> bool {
+    with_health(|health| health.mode == StorageMode::InMemory)
+}
+
+/// Check the data dir (`dir`) can be created and written to
+pub fn probe(dir: &Path) -> Result<(), String> {
+    fs::create_dir_all(dir).map_err(|e| format!("Cannot create {}: {}", dir.display(), e))?;
+
+    let probe = dir.join(PROBE_FILE);
+    fs::write(&probe, b"ok").map_err(|e| format!("Cannot write to {}: {}", dir.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    Ok(())
+}
+
+/// Switch to in-memory mode, telling the UI the first time
+pub fn mark_unavailable(error: String) {
+    eprintln!("Storage unavailable, keeping data in memory: {}", error);
+    let changed = with_health(|health| {
+        let changed = health.mode != StorageMode::InMemory;
+        if changed {
+            health.mode = StorageMode::InMemory;
+            health.since = Some(Utc::now());
+        }
+        health.error = Some(error);
+        changed
+    });
+    if changed {
+        emit("storage-unavailable");
+    }
+}
+
+/// Remember that the pastebooks file existed but couldn't be read
+pub fn mark_unreadable_on_load() {
+    with_health(|health| health.unreadable_on_load = true);
+}
+
+/// Back to persistent mode; returns whether the unreadable file needs a backup first
+pub fn mark_available() -> bool {
+    let (changed, needs_backup) = with_health(|health| {
+        let changed = health.mode != StorageMode::Persistent;
+        health.mode = StorageMode::Persistent;
+        health.error = None;
+        health.since = None;
+        (changed, std::mem::take(&mut health.unreadable_on_load))
+    });
+    if changed {
+        emit("storage-recovered");
+    }
+    needs_backup
+}
+
+pub fn note_retry() {
+    with_health(|health| health.last_retry = Some(Utc::now()));
+}
+
+fn emit(event: &str) {
+    if let Some(app) = APP.get() {
+        let _ = app.emit(event, current());
+    }
+}
@@ -0,0 +1,396 @@
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+use tauri::AppHandle;
+use tauri_plugin_global_shortcut::{
+    Code, GlobalShortcutExt, Modifiers, Shortcut, ShortcutEvent, ShortcutState,
+};
+
+use crate::storage::Settings;
+
+/// A second clear-all press within this window confirms the first
+pub const CLEAR_CONFIRM_WINDOW: Duration = Duration::from_millis(2000);
+
+/// The fixed capture hotkey, which the optional ones can't take over
+pub fn capture_shortcut() -> Shortcut {
+    Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyC)
+}
+
+/// Something a global hotkey can do
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Capture,
+    CopyAll,
+    ClearAll,
+    QuickNote,
+}
+
+/// Where an action's hotkey stands after the last reconcile
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum ShortcutStatus {
+    /// No shortcut is set for the action
+    Off,
+    Registered { shortcut: String },
+    /// Stack couldn't claim it, usually because another app has it
+    Unavailable { shortcut: String, error: String },
+    /// The setting isn't a usable shortcut
+    Invalid { error: String },
+}
+
+/// Hotkeys Stack has registered, by action
+static OWNED: Mutex<BTreeMap<Action, Shortcut>> = Mutex::new(BTreeMap::new());
+/// One reconcile at a time
+static RECONCILING: Mutex<()> = Mutex::new(());
+/// Outcome of the last reconcile, for `get_shortcut_status`
+static STATUS: Mutex<BTreeMap<Action, ShortcutStatus>> = Mutex::new(BTreeMap::new());
+/// When clear-all was first pressed, waiting for the confirming second press
+static CLEAR_ARMED_AT: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// What to run for each hotkey
+pub struct Handlers {
+    pub capture: fn(&AppHandle),
+    pub copy_all: fn(&AppHandle),
+    pub clear_all: fn(&AppHandle),
+    pub quick_note: fn(&AppHandle),
+}
+
+impl Handlers {
+    fn get(&self, action: Action) -> fn(&AppHandle) {
+        match action {
+            Action::Capture => self.capture,
+            Action::CopyAll => self.copy_all,
+            Action::ClearAll => self.clear_all,
+            Action::QuickNote => self.quick_note,
+        }
+    }
+}
+
+/// The OS side of global hotkeys, behind a trait so reconciling can be
+/// tested without registering real ones
+pub trait ShortcutManager {
+    fn is_registered(&self, shortcut: Shortcut) -> bool;
+    fn register(&self, action: Action, shortcut: Shortcut) -> Result<(), String>;
+    fn unregister(&self, shortcut: Shortcut) -> Result<(), String>;
+}
+
+/// The global shortcut plugin, running `handlers` on key press
+pub struct Plugin<'a> {
+    pub app: &'a AppHandle,
+    pub handlers: &'a Handlers,
+}
+
+impl ShortcutManager for Plugin<'_> {
+    fn is_registered(&self, shortcut: Shortcut) -> bool {
+        self.app.global_shortcut().is_registered(shortcut)
+    }
+
+    fn register(&self, action: Action, shortcut: Shortcut) -> Result<(), String> {
+        let handler = self.handlers.get(action);
+        self.app
+            .global_shortcut()
+            .on_shortcut(shortcut, move |app, _shortcut, event: ShortcutEvent| {
+                if event.state == ShortcutState::Pressed {
+                    handler(app);
+                }
+            })
+            .map_err(|e| format!("Couldn't register {}: {}", shortcut, e))
+    }
+
+    fn unregister(&self, shortcut: Shortcut) -> Result<(), String> {
+        self.app
+            .global_shortcut()
+            .unregister(shortcut)
+            .map_err(|e| format!("Couldn't unregister {}: {}", shortcut, e))
+    }
+}
+
+/// Parse a shortcut such as "Ctrl+Alt+V"
+pub fn parse(text: &str) -> Result<Shortcut, String> {
+    text.trim()
+        .parse::<Shortcut>()
+        .map_err(|e| format!("Invalid shortcut '{}': {}", text.trim(), e))
+}
+
+/// The hotkey each action should have per `settings`: the fixed capture
+/// one, then the optional ones (copy all, clear all, quick note), each
+/// checked against those before it for clashes
+fn bindings(settings: &Settings) -> Vec<(Action, Result<Option<Shortcut>, String>)> {
+    let optional = [
+        (Action::CopyAll, &settings.copy_all_shortcut),
+        (Action::ClearAll, &settings.clear_all_shortcut),
+        (Action::QuickNote, &settings.quick_note_shortcut),
+    ];
+    let mut bindings = vec![(Action::Capture, Ok(Some(capture_shortcut())))];
+    for (action, setting) in optional {
+        let binding = setting
+            .as_deref()
+            .filter(|s| !s.trim().is_empty())
+            .map(parse)
+            .transpose()
+            .and_then(|shortcut| match shortcut {
+                Some(s) if s == capture_shortcut() => Err(format!("{} is already the capture shortcut", s)),
+                Some(s) if bindings.iter().any(|(_, b)| b == &Ok(Some(s))) => {
+                    Err(format!("{} is set for more than one action", s))
+                }
+                _ => Ok(shortcut),
+            });
+        bindings.push((action, binding));
+    }
+    bindings
+}
+
+/// Check the shortcut settings parse and don't clash
+pub fn validate(settings: &Settings) -> Result<(), String> {
+    bindings(settings).into_iter().try_for_each(|(_, binding)| binding.map(|_| ()))
+}
+
+/// Claim `shortcut` for `action`, clearing a registration left over from
+/// an unregister that failed earlier, and release the action's old hotkey.
+/// If it can't be claimed the old one stays; the flag says it did.
+fn claim(
+    manager: &impl ShortcutManager,
+    owned: &mut BTreeMap<Action, Shortcut>,
+    action: Action,
+    shortcut: Shortcut,
+) -> (ShortcutStatus, bool) {
+    if manager.is_registered(shortcut) {
+        let _ = manager.unregister(shortcut);
+    }
+    match manager.register(action, shortcut) {
+        Ok(()) => {
+            if let Some(old) = owned.insert(action, shortcut).filter(|old| *old != shortcut) {
+                let _ = manager.unregister(old);
+            }
+            (ShortcutStatus::Registered { shortcut: shortcut.to_string() }, false)
+        }
+        Err(error) => {
+            let old = owned.get(&action);
+            let error = match old {
+                Some(old) => format!("{}; {} still works", error, old),
+                None => error,
+            };
+            (ShortcutStatus::Unavailable { shortcut: shortcut.to_string(), error }, old.is_some())
+        }
+    }
+}
+
+/// Bring what `manager` has registered in line with `settings`. New
+/// hotkeys are claimed before the ones they replace are released, so an
+/// action whose new hotkey can't be claimed keeps its old one; only a
+/// hotkey moving from one action to another has to be released first. One
+/// that can't be claimed only affects its own action.
+fn converge(
+    manager: &impl ShortcutManager,
+    owned: &mut BTreeMap<Action, Shortcut>,
+    settings: &Settings,
+) -> BTreeMap<Action, ShortcutStatus> {
+    let bindings = bindings(settings);
+    // Hotkeys the OS dropped behind Stack's back are claimed again
+    owned.retain(|_, shortcut| manager.is_registered(*shortcut));
+
+    let mut statuses = BTreeMap::new();
+    let mut kept_old = Vec::new();
+    let mut moved = Vec::new();
+    for (action, binding) in &bindings {
+        let status = match binding {
+            Err(error) => ShortcutStatus::Invalid { error: error.clone() },
+            Ok(None) => ShortcutStatus::Off,
+            Ok(Some(shortcut)) if owned.get(action) == Some(shortcut) => {
+                ShortcutStatus::Registered { shortcut: shortcut.to_string() }
+            }
+            Ok(Some(shortcut)) if owned.values().any(|s| s == shortcut) => {
+                moved.push((*action, *shortcut));
+                continue;
+            }
+            Ok(Some(shortcut)) => {
+                let (status, kept) = claim(manager, owned, *action, *shortcut);
+                if kept {
+                    kept_old.push(*action);
+                }
+                status
+            }
+        };
+        statuses.insert(*action, status);
+    }
+
+    // Release what's no longer wanted, then claim hotkeys that moved
+    // between actions now that they're free
+    owned.retain(|action, shortcut| {
+        let keep = kept_old.contains(action)
+            || bindings.iter().any(|(a, b)| a == action && b == &Ok(Some(*shortcut)));
+        if !keep {
+            let _ = manager.unregister(*shortcut);
+        }
+        keep
+    });
+    for (action, shortcut) in moved {
+        let status = if owned.iter().any(|(a, s)| *a != action && *s == shortcut) {
+            let error = format!("{} is still in use for another action", shortcut);
+            ShortcutStatus::Unavailable { shortcut: shortcut.to_string(), error }
+        } else {
+            claim(manager, owned, action, shortcut).0
+        };
+        statuses.insert(action, status);
+    }
+    statuses
+}
+
+/// Reconcile the registered hotkeys with `settings`, at startup and after
+/// every settings change, returning where each action ended up. The OS
+/// calls run on a copy of the owned hotkeys, with no lock `status` needs.
+pub fn reconcile(app: &AppHandle, settings: &Settings, handlers: &Handlers) -> BTreeMap<Action, ShortcutStatus> {
+    let _reconciling = RECONCILING.lock().unwrap_or_else(|e| e.into_inner());
+    let mut owned = OWNED.lock().unwrap().clone();
+    let statuses = converge(&Plugin { app, handlers }, &mut owned, settings);
+    *OWNED.lock().unwrap() = owned;
+    *STATUS.lock().unwrap() = statuses.clone();
+    statuses
+}
+
+/// Where each action's hotkey stood after the last reconcile; empty when
+/// hotkeys are off, as in safe mode
+pub fn status() -> BTreeMap<Action, ShortcutStatus> {
+    STATUS.lock().unwrap().clone()
+}
+
+/// A clear-all press: true if it confirms one made within the window,
+/// otherwise it arms the confirmation and returns false
+pub fn confirm_clear(now: Instant) -> bool {
+    let mut armed = CLEAR_ARMED_AT.lock().unwrap();
+    match armed.take() {
+        Some(at) if now.duration_since(at) <= CLEAR_CONFIRM_WINDOW => true,
+        _ => {
+            *armed = Some(now);
+            false
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn settings(copy_all: Option<&str>, clear_all: Option<&str>) -> Settings {
+        Settings {
+            copy_all_shortcut: copy_all.map(str::to_string),
+            clear_all_shortcut: clear_all.map(str::to_string),
+            quick_note_shortcut: None,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn shortcut_settings_are_validated() {
+        assert!(validate(&settings(None, Some(" "))).is_ok());
+        assert!(validate(&settings(Some("Ctrl+Alt+V"), Some("Ctrl+Alt+X"))).is_ok());
+        assert!(validate(&settings(Some("Ctrl+Nope"), None)).is_err());
+        assert!(validate(&settings(Some("shift+ctrl+c"), None))
+            .unwrap_err()
+            .contains("capture shortcut"));
+        assert!(validate(&settings(Some("Ctrl+Alt+V"), Some("alt+ctrl+v"))).is_err());
+    }
+
+    #[test]
+    fn quick_note_defaults_on_and_cannot_share_a_shortcut() {
+        assert!(validate(&Settings::default()).is_ok());
+        let clash = Settings {
+            copy_all_shortcut: Some("ctrl+shift+n".to_string()),
+            ..Default::default()
+        };
+        assert!(validate(&clash).unwrap_err().contains("more than one action"));
+    }
+
+    #[test]
+    fn clearing_needs_a_second_press_in_time() {
+        let start = Instant::now();
+        assert!(!confirm_clear(start));
+        assert!(confirm_clear(start + Duration::from_millis(500)));
+        // Confirming disarms; a late second press arms again instead
+        assert!(!confirm_clear(start + Duration::from_millis(600)));
+        assert!(!confirm_clear(start + Duration::from_millis(3000)));
+        assert!(confirm_clear(start + Duration::from_millis(3100)));
+    }
+
+    /// Records registrations, refusing the shortcuts in `taken` as if
+    /// another app held them
+    #[derive(Default)]
+    struct FakeManager {
+        registered: RefCell<BTreeMap<String, Action>>,
+        taken: Vec<Shortcut>,
+    }
+
+    impl ShortcutManager for FakeManager {
+        fn is_registered(&self, shortcut: Shortcut) -> bool {
+            self.registered.borrow().contains_key(&shortcut.to_string())
+        }
+
+        fn register(&self, action: Action, shortcut: Shortcut) -> Result<(), String> {
+            if self.taken.contains(&shortcut) || self.is_registered(shortcut) {
+                return Err(format!("{} is taken", shortcut));
+            }
+            self.registered.borrow_mut().insert(shortcut.to_string(), action);
+            Ok(())
+        }
+
+        fn unregister(&self, shortcut: Shortcut) -> Result<(), String> {
+            self.registered.borrow_mut().remove(&shortcut.to_string());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn reconciling_converges_and_reports_what_it_could_not_claim() {
+        let taken = parse("Ctrl+Alt+X").unwrap();
+        let manager = FakeManager { taken: vec![taken], ..Default::default() };
+        let mut owned = BTreeMap::new();
+
+        let statuses = converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+V"), Some("Ctrl+Alt+X")));
+        assert!(matches!(statuses[&Action::Capture], ShortcutStatus::Registered { .. }));
+        assert!(matches!(statuses[&Action::CopyAll], ShortcutStatus::Registered { .. }));
+        assert!(matches!(statuses[&Action::ClearAll], ShortcutStatus::Unavailable { .. }));
+        assert_eq!(statuses[&Action::QuickNote], ShortcutStatus::Off);
+        assert_eq!(owned.len(), 2);
+
+        // Swapping copy all onto the freed key and clearing clear all
+        // releases the old binding and retries nothing that's gone
+        let statuses = converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+B"), None));
+        assert_eq!(statuses[&Action::ClearAll], ShortcutStatus::Off);
+        let held: Vec<String> = manager.registered.borrow().keys().cloned().collect();
+        let mut expected = vec![capture_shortcut().to_string(), parse("Ctrl+Alt+B").unwrap().to_string()];
+        expected.sort();
+        assert_eq!(held, expected);
+
+        // A hotkey the OS dropped behind Stack's back is claimed again
+        manager.registered.borrow_mut().clear();
+        let statuses = converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+B"), Some("Ctrl+Nope")));
+        assert!(matches!(statuses[&Action::CopyAll], ShortcutStatus::Registered { .. }));
+        assert!(matches!(statuses[&Action::ClearAll], ShortcutStatus::Invalid { .. }));
+        assert_eq!(manager.registered.borrow().len(), 2);
+    }
+
+    #[test]
+    fn an_action_keeps_its_hotkey_until_the_new_one_is_claimed() {
+        let manager = FakeManager { taken: vec![parse("Ctrl+Alt+X").unwrap()], ..Default::default() };
+        let mut owned = BTreeMap::new();
+        converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+V"), Some("Ctrl+Alt+B")));
+
+        let statuses = converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+X"), Some("Ctrl+Alt+B")));
+        match &statuses[&Action::CopyAll] {
+            ShortcutStatus::Unavailable { error, .. } => assert!(error.contains("still works"), "{}", error),
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(owned[&Action::CopyAll], parse("Ctrl+Alt+V").unwrap());
+        assert!(manager.is_registered(parse("Ctrl+Alt+V").unwrap()));
+
+        // Swapping two hotkeys frees each before the other action claims it
+        let statuses = converge(&manager, &mut owned, &settings(Some("Ctrl+Alt+B"), Some("Ctrl+Alt+V")));
+        assert!(matches!(statuses[&Action::CopyAll], ShortcutStatus::Registered { .. }));
+        assert!(matches!(statuses[&Action::ClearAll], ShortcutStatus::Registered { .. }));
+        assert_eq!(manager.registered.borrow()[&parse("Ctrl+Alt+B").unwrap().to_string()], Action::CopyAll);
+        assert_eq!(manager.registered.borrow()[&parse("Ctrl+Alt+V").unwrap().to_string()], Action::ClearAll);
+    }
+}
@@ -0,0 +1,323 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::sync::OnceLock;
+
+use crate::terminal;
+
+/// Share of replacement/non-printable chars above which text is treated as binary
+const MAX_GARBAGE_RATIO: f64 = 0.5;
+
+/// Why incoming text was refused
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RejectReason {
+    /// Mostly replacement characters or control codes (mis-decoded binary data)
+    BinaryContent,
+    /// Nothing left after sanitizing
+    Empty,
+}
+
+impl RejectReason {
+    pub fn message(self) -> &'static str {
+        match self {
+            RejectReason::BinaryContent => "Content looks like binary data, not text",
+            RejectReason::Empty => "Content is empty",
+        }
+    }
+}
+
+/// What captured text looks like, for rules and file types
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContentKind {
+    Url,
+    Email,
+    Number,
+    Json,
+    Html,
+    Markdown,
+    Text,
+}
+
+/// Classify sanitized text. Single-token kinds (url, email, number) need the
+/// whole clip to be that token; otherwise structure decides.
+pub fn classify(content: &str) -> ContentKind {
+    let trimmed = content.trim();
+
+    if !trimmed.is_empty() && !trimmed.contains(char::is_whitespace) {
+        let lower = trimmed.to_lowercase();
+        if ["http://", "https://", "www."].iter().any(|p| lower.starts_with(p)) {
+            return ContentKind::Url;
+        }
+        if let Some((user, domain)) = trimmed.split_once('@') {
+            let domain_ok = domain.contains('.') && !domain.contains('@') && !domain.ends_with('.');
+            if !user.is_empty() && domain_ok {
+                return ContentKind::Email;
+            }
+        }
+        if trimmed.replace(',', "").parse::<f64>().is_ok_and(f64::is_finite) {
+            return ContentKind::Number;
+        }
+    }
+
+    if (trimmed.starts_with('{') || trimmed.starts_with('['))
+        && serde_json::from_str::<serde_json::Value>(trimmed).is_ok()
+    {
+        return ContentKind::Json;
+    }
+
+    let lower = trimmed.to_lowercase();
+    if lower.starts_with("<!doctype html") || lower.starts_with("<html") {
+        return ContentKind::Html;
+    }
+
+    let looks_like_markdown = trimmed.lines().any(|line| {
+        let line = line.trim_start();
+        line.starts_with("# ") || line.starts_with("## ") || line.starts_with("```") || line.starts_with("- [")
+    });
+    if looks_like_markdown {
+        return ContentKind::Markdown;
+    }
+
+    ContentKind::Text
+}
+
+fn is_garbage(ch: char) -> bool {
+    ch == char::REPLACEMENT_CHARACTER || (ch.is_control() && !matches!(ch, '\n' | '\r' | '\t'))
+}
+
+/// Clean up text from any ingest path: reject mis-decoded binary, drop
+/// terminal escape sequences whole (so no "[32m" is left behind), NUL and
+/// the other C0 control characters but tab, CR and LF, and turn CRLF into LF
+pub fn sanitize_text(raw: &str) -> Result<String, RejectReason> {
+    let total = raw.chars().count();
+    if total == 0 {
+        return Err(RejectReason::Empty);
+    }
+
+    let garbage = raw.chars().filter(|&c| is_garbage(c)).count();
+    if garbage as f64 / total as f64 > MAX_GARBAGE_RATIO {
+        return Err(RejectReason::BinaryContent);
+    }
+
+    let cleaned: String = terminal::strip_ansi(&raw.replace("\r\n", "\n"))
+        .chars()
+        .filter(|&c| !c.is_ascii_control() || matches!(c, '\n' | '\r' | '\t'))
+        .collect();
+
+    if cleaned.trim().is_empty() {
+        return Err(RejectReason::Empty);
+    }
+    Ok(cleaned)
+}
+
+/// A built-in rewrite applied to every capture, in the order configured
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IngestTransform {
+    /// Whitespace at the start and end of the text and of every line
+    Trim,
+    /// Lone CRs become LF (CRLF already does on every path)
+    NormalizeNewlines,
+    /// Runs of empty lines become one; lines of spaces aren't empty, so
+    /// trim first to fold those too
+    CollapseBlankLines,
+    /// Curly quotes and primes become ' and "
+    StraightQuotes,
+    /// utm_* and click-id parameters are dropped from http(s) links
+    StripTrackingParams,
+}
+
+impl IngestTransform {
+    pub const ALL: [IngestTransform; 5] = [
+        IngestTransform::Trim,
+        IngestTransform::NormalizeNewlines,
+        IngestTransform::CollapseBlankLines,
+        IngestTransform::StraightQuotes,
+        IngestTransform::StripTrackingParams,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            IngestTransform::Trim => "trim",
+            IngestTransform::NormalizeNewlines => "normalize_newlines",
+            IngestTransform::CollapseBlankLines => "collapse_blank_lines",
+            IngestTransform::StraightQuotes => "straight_quotes",
+            IngestTransform::StripTrackingParams => "strip_tracking_params",
+        }
+    }
+
+    pub fn apply(self, text: &str) -> String {
+        match self {
+            IngestTransform::Trim => text.trim().lines().map(str::trim_end).collect::<Vec<_>>().join("\n"),
+            IngestTransform::NormalizeNewlines => text.replace("\r\n", "\n").replace('\r', "\n"),
+            IngestTransform::CollapseBlankLines => {
+                let mut out: Vec<&str> = Vec::new();
+                for line in text.split('\n') {
+                    if !(line.is_empty() && out.last().is_some_and(|l| l.is_empty())) {
+                        out.push(line);
+                    }
+                }
+                out.join("\n")
+            }
+            IngestTransform::StraightQuotes => text
+                .chars()
+                .map(|c| match c {
+                    '\u{2018}' | '\u{2019}' | '\u{201A}' | '\u{201B}' | '\u{2032}' => '\'',
+                    '\u{201C}' | '\u{201D}' | '\u{201E}' | '\u{201F}' | '\u{2033}' => '"',
+                    c => c,
+                })
+                .collect(),
+            IngestTransform::StripTrackingParams => strip_tracking_params(text),
+        }
+    }
+}
+
+/// Parse transform names, refusing unknown and repeated ones
+pub fn parse_transforms(names: &[String]) -> Result<Vec<IngestTransform>, String> {
+    let mut transforms = Vec::new();
+    for name in names {
+        let transform = IngestTransform::ALL
+            .into_iter()
+            .find(|t| t.name() == name.trim())
+            .ok_or_else(|| format!("Validation: unknown transform '{}'", name))?;
+        if transforms.contains(&transform) {
+            return Err(format!("Validation: transform '{}' is listed twice", name));
+        }
+        transforms.push(transform);
+    }
+    Ok(transforms)
+}
+
+/// Run `transforms` over `text` in order
+pub fn apply_transforms(text: &str, transforms: &[IngestTransform]) -> String {
+    transforms.iter().fold(text.to_string(), |text, t| t.apply(&text))
+}
+
+/// Query parameters that only tell a site where a click came from
+fn is_tracking_param(name: &str) -> bool {
+    const NAMES: [&str; 10] =
+        ["fbclid", "gclid", "dclid", "msclkid", "yclid", "igshid", "mc_cid", "mc_eid", "_hsenc", "_hsmi"];
+    name.starts_with("utm_") || NAMES.contains(&name)
+}
+
+fn strip_tracking_params(text: &str) -> String {
+    static LINK: OnceLock<Regex> = OnceLock::new();
+    let link = LINK.get_or_init(|| Regex::new(r#"https?://[^\s<>"']+"#).unwrap());
+    link.replace_all(text, |found: &regex::Captures| {
+        let found = &found[0];
+        // Sentence punctuation after a link isn't part of it
+        let end = found.trim_end_matches(['.', ',', ';', ':', '!', '?', ')', ']']).len();
+        let (link, tail) = found.split_at(end);
+        let Ok(mut url) = url::Url::parse(link) else {
+            return found.to_string();
+        };
+        let pairs: Vec<(String, String)> = url.query_pairs().into_owned().collect();
+        let kept: Vec<&(String, String)> = pairs.iter().filter(|(name, _)| !is_tracking_param(name)).collect();
+        if kept.len() == pairs.len() {
+            return found.to_string();
+        }
+        url.set_query(None);
+        if !kept.is_empty() {
+            url.query_pairs_mut().extend_pairs(kept);
+        }
+        format!("{}{}", url, tail)
+    })
+    .into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transforms_run_in_the_order_given() {
+        let text = "  a\n \n \nb  ";
+        // Trimming first empties the space-only lines, so they collapse
+        let trim_first = [IngestTransform::Trim, IngestTransform::CollapseBlankLines];
+        assert_eq!(apply_transforms(text, &trim_first), "a\n\nb");
+        let collapse_first = [IngestTransform::CollapseBlankLines, IngestTransform::Trim];
+        assert_eq!(apply_transforms(text, &collapse_first), "a\n\n\nb");
+        assert_eq!(apply_transforms(text, &[]), text);
+    }
+
+    #[test]
+    fn each_transform_does_one_thing() {
+        assert_eq!(IngestTransform::NormalizeNewlines.apply("a\rb\r\nc"), "a\nb\nc");
+        assert_eq!(IngestTransform::CollapseBlankLines.apply("a\n\n\n\nb\n"), "a\n\nb\n");
+        assert_eq!(IngestTransform::StraightQuotes.apply("\u{201C}it\u{2019}s\u{201D}"), "\"it's\"");
+        assert_eq!(
+            IngestTransform::StripTrackingParams
+                .apply("see https://example.com/a?id=7&utm_source=x&fbclid=y. and https://example.com/?q=1"),
+            "see https://example.com/a?id=7. and https://example.com/?q=1"
+        );
+        assert_eq!(
+            IngestTransform::StripTrackingParams.apply("(https://example.com/p?utm_medium=mail)"),
+            "(https://example.com/p)"
+        );
+    }
+
+    #[test]
+    fn transform_names_are_validated() {
+        let names = |list: &[&str]| list.iter().map(|n| n.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            parse_transforms(&names(&["trim", "straight_quotes"])).unwrap(),
+            [IngestTransform::Trim, IngestTransform::StraightQuotes]
+        );
+        assert_eq!(
+            parse_transforms(&names(&["trim", "shout"])).unwrap_err(),
+            "Validation: unknown transform 'shout'"
+        );
+        assert!(parse_transforms(&names(&["trim", "trim"])).unwrap_err().contains("listed twice"));
+    }
+
+    #[test]
+    fn sanitize_strips_control_chars_and_normalizes_newlines() {
+        assert_eq!(sanitize_text("a\0b\r\nc\td\x07").unwrap(), "ab\nc\td");
+        assert_eq!(sanitize_text("lone\rcr").unwrap(), "lone\rcr");
+        assert_eq!(sanitize_text("\u{1b}[1mbold\u{1b}[0m").unwrap(), "bold");
+        assert_eq!(sanitize_text("title\u{1b}]0;tab\u{7} \u{9b}31mred").unwrap(), "title red");
+        assert!(!sanitize_text("stray \u{1b}").unwrap().contains('\u{1b}'));
+    }
+
+    #[test]
+    fn sanitize_rejects_empty_and_binary_text() {
+        assert_eq!(sanitize_text(""), Err(RejectReason::Empty));
+        assert_eq!(sanitize_text(" \n\0\t "), Err(RejectReason::Empty));
+        assert_eq!(sanitize_text("\u{FFFD}\u{FFFD}\u{1}ab"), Err(RejectReason::BinaryContent));
+        assert!(sanitize_text("ok\u{FFFD}").is_ok());
+    }
+
+    #[test]
+    fn sanitize_rejects_mis_decoded_utf16() {
+        // Lone surrogates and control codes from binary data read as UTF-16
+        let utf16: Vec<u16> = vec![0xD800, 0x0000, 0xDC01, 0x5089, 0x474E, 0x0A0D, 0xDBFF, 0x001A];
+        let decoded = String::from_utf16_lossy(&utf16);
+        assert_eq!(sanitize_text(&decoded), Err(RejectReason::BinaryContent));
+        assert_eq!(serde_json::to_value(RejectReason::BinaryContent).unwrap(), "binary-content");
+
+        // Exactly half is still text
+        assert_eq!(sanitize_text("ab\u{FFFD}\0").unwrap(), "ab\u{FFFD}");
+        assert_eq!(sanitize_text("ab\u{FFFD}\0\u{1}"), Err(RejectReason::BinaryContent));
+    }
+
+    #[test]
+    fn classify_single_tokens() {
+        assert_eq!(classify("https://example.com/a?b"), ContentKind::Url);
+        assert_eq!(classify(" www.example.com "), ContentKind::Url);
+        assert_eq!(classify("me@example.com"), ContentKind::Email);
+        assert_eq!(classify("me@example."), ContentKind::Text);
+        assert_eq!(classify("1,234.50"), ContentKind::Number);
+        assert_eq!(classify("inf"), ContentKind::Text);
+        assert_eq!(classify("visit https://example.com"), ContentKind::Text);
+    }
+
+    #[test]
+    fn classify_structured_text() {
+        assert_eq!(classify("{\"a\": [1, 2]}"), ContentKind::Json);
+        assert_eq!(classify("{not json"), ContentKind::Text);
+        assert_eq!(classify("<!DOCTYPE html><html></html>"), ContentKind::Html);
+        assert_eq!(classify("intro\n## Heading\nbody"), ContentKind::Markdown);
+        assert_eq!(classify("plain words"), ContentKind::Text);
+    }
+}
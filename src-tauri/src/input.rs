@@ -1,85 +1,243 @@
+#[cfg(windows)]
 use std::mem::size_of;
+#[cfg(windows)]
+use std::sync::atomic::{AtomicBool, Ordering};
 
 #[cfg(windows)]
 use windows::Win32::UI::Input::KeyboardAndMouse::{
-    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP, VK_C, VK_CONTROL,
-    VK_SHIFT,
+    GetAsyncKeyState, SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, KEYBDINPUT, KEYEVENTF_KEYUP,
+    VIRTUAL_KEY, VK_C, VK_CONTROL, VK_LCONTROL, VK_LSHIFT, VK_LWIN, VK_MENU, VK_RCONTROL,
+    VK_RSHIFT, VK_RWIN, VK_SHIFT, VK_V,
 };
 
+/// Modifier keys we may inject or find stuck, with names for logs and the UI
 #[cfg(windows)]
-pub fn simulate_copy() {
-    unsafe {
-        let mut inputs = [
-            // Release Shift (temporarily break the trigger hotkey modifiers)
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_SHIFT,
-                        dwFlags: KEYEVENTF_KEYUP,
-                        ..Default::default()
-                    },
-                },
-            },
-            // Press Ctrl
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_CONTROL,
-                        ..Default::default()
-                    },
-                },
-            },
-            // Press C
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_C,
-                        ..Default::default()
-                    },
-                },
-            },
-            // Release C
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_C,
-                        dwFlags: KEYEVENTF_KEYUP,
-                        ..Default::default()
-                    },
-                },
-            },
-            // Release Ctrl
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_CONTROL,
-                        dwFlags: KEYEVENTF_KEYUP,
-                        ..Default::default()
-                    },
-                },
-            },
-             // Restore Shift (optional, but good manners)
-            INPUT {
-                r#type: INPUT_KEYBOARD,
-                Anonymous: INPUT_0 {
-                    ki: KEYBDINPUT {
-                        wVk: VK_SHIFT,
-                        ..Default::default()
-                    },
-                },
+const MODIFIERS: [(VIRTUAL_KEY, &str); 6] = [
+    (VK_LCONTROL, "LeftCtrl"),
+    (VK_RCONTROL, "RightCtrl"),
+    (VK_CONTROL, "Ctrl"),
+    (VK_LSHIFT, "LeftShift"),
+    (VK_RSHIFT, "RightShift"),
+    (VK_SHIFT, "Shift"),
+];
+
+/// Modifiers Stack pressed itself during the last copy, checked afterwards
+#[cfg(windows)]
+static INJECTED_DOWN: std::sync::Mutex<Vec<VIRTUAL_KEY>> = std::sync::Mutex::new(Vec::new());
+
+/// Keys the user is physically holding, by virtual key code, as seen by a
+/// low-level keyboard hook that ignores injected input. Unlike
+/// `GetAsyncKeyState`, this isn't moved by keys `SendInput` pressed or
+/// released.
+#[cfg(windows)]
+static PHYSICAL_DOWN: [AtomicBool; 256] = [const { AtomicBool::new(false) }; 256];
+/// The hook is running, so `PHYSICAL_DOWN` can be trusted
+#[cfg(windows)]
+static HOOKED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(windows)]
+unsafe extern "system" fn keyboard_hook(
+    code: i32,
+    wparam: windows::Win32::Foundation::WPARAM,
+    lparam: windows::Win32::Foundation::LPARAM,
+) -> windows::Win32::Foundation::LRESULT {
+    use windows::Win32::UI::WindowsAndMessaging::{
+        CallNextHookEx, HHOOK, KBDLLHOOKSTRUCT, LLKHF_INJECTED, WM_KEYDOWN, WM_SYSKEYDOWN,
+    };
+
+    if code >= 0 {
+        let event = &*(lparam.0 as *const KBDLLHOOKSTRUCT);
+        if event.flags.0 & LLKHF_INJECTED.0 == 0 {
+            let down = matches!(wparam.0 as u32, WM_KEYDOWN | WM_SYSKEYDOWN);
+            if let Some(key) = PHYSICAL_DOWN.get(event.vkCode as usize) {
+                key.store(down, Ordering::Relaxed);
+            }
+        }
+    }
+    CallNextHookEx(HHOOK::default(), code, wparam, lparam)
+}
+
+/// Start watching the physical keyboard, on a thread of its own since a
+/// low-level hook needs a message loop
+#[cfg(windows)]
+pub fn start_key_tracking() {
+    use windows::Win32::Foundation::{HINSTANCE, HWND};
+    use windows::Win32::UI::WindowsAndMessaging::{GetMessageW, SetWindowsHookExW, MSG, WH_KEYBOARD_LL};
+
+    std::thread::spawn(|| unsafe {
+        match SetWindowsHookExW(WH_KEYBOARD_LL, Some(keyboard_hook), HINSTANCE::default(), 0) {
+            Ok(_) => HOOKED.store(true, Ordering::Relaxed),
+            Err(e) => {
+                eprintln!("Keyboard hook unavailable, judging held keys from key state: {}", e);
+                return;
+            }
+        }
+        let mut message = MSG::default();
+        while GetMessageW(&mut message, HWND::default(), 0, 0).as_bool() {}
+    });
+}
+
+#[cfg(windows)]
+fn key(vk: VIRTUAL_KEY, up: bool) -> INPUT {
+    INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                dwFlags: if up { KEYEVENTF_KEYUP } else { Default::default() },
+                ..Default::default()
             },
-        ];
+        },
+    }
+}
+
+/// Whether the key is down right now, as the keyboard last reported it;
+/// this includes keys Stack injected
+#[cfg(windows)]
+fn is_down(vk: VIRTUAL_KEY) -> bool {
+    unsafe { (GetAsyncKeyState(vk.0 as i32) as u16) & 0x8000 != 0 }
+}
 
-        SendInput(&inputs, size_of::<INPUT>() as i32);
+/// Whether the user's own finger is on the key. Shift and Ctrl count
+/// either side. Without the hook, falls back to `is_down`.
+#[cfg(windows)]
+fn is_held(vk: VIRTUAL_KEY) -> bool {
+    if !HOOKED.load(Ordering::Relaxed) {
+        return is_down(vk);
+    }
+    let sides: &[VIRTUAL_KEY] = match vk {
+        VK_SHIFT => &[VK_LSHIFT, VK_RSHIFT],
+        VK_CONTROL => &[VK_LCONTROL, VK_RCONTROL],
+        _ => &[vk],
+    };
+    sides.iter().any(|side| PHYSICAL_DOWN[side.0 as usize].load(Ordering::Relaxed))
+}
+
+#[cfg(windows)]
+fn send(inputs: &[INPUT]) {
+    unsafe {
+        SendInput(inputs, size_of::<INPUT>() as i32);
     }
 }
 
+/// Send Ctrl+C to the focused app while the capture hotkey (Ctrl+Shift+C) may
+/// still be held, touching the modifiers as little as possible
+#[cfg(windows)]
+pub fn simulate_copy() {
+    let shift_held = is_down(VK_SHIFT);
+    let ctrl_held = is_down(VK_CONTROL);
+    let mut injected_down = Vec::new();
+
+    let mut inputs = Vec::with_capacity(5);
+    // Shift+C would be a different shortcut in many apps
+    if shift_held {
+        inputs.push(key(VK_SHIFT, true));
+    }
+    if !ctrl_held {
+        inputs.push(key(VK_CONTROL, false));
+    }
+    inputs.push(key(VK_C, false));
+    inputs.push(key(VK_C, true));
+    if !ctrl_held {
+        inputs.push(key(VK_CONTROL, true));
+    }
+    send(&inputs);
+
+    // Re-press Shift only if the user is still holding it. Our own release
+    // moved the key state, so ask the hook, which only sees real keys;
+    // pressing it after they had let go is what used to leave Shift stuck.
+    if shift_held && is_held(VK_SHIFT) {
+        send(&[key(VK_SHIFT, false)]);
+        injected_down.push(VK_SHIFT);
+    }
+
+    *INJECTED_DOWN.lock().unwrap() = injected_down;
+}
+
+/// How long a paste waits for the user to let go of the hotkey's modifiers
+#[cfg(windows)]
+const PASTE_RELEASE_WAIT: std::time::Duration = std::time::Duration::from_millis(1000);
+
+/// Send Ctrl+V to the focused app. Waits (briefly) for Shift, Alt and Win
+/// from the triggering hotkey to be released, since they'd change the chord.
+#[cfg(windows)]
+pub fn simulate_paste() {
+    let started = std::time::Instant::now();
+    while [VK_SHIFT, VK_MENU, VK_LWIN, VK_RWIN].into_iter().any(is_down)
+        && started.elapsed() < PASTE_RELEASE_WAIT
+    {
+        std::thread::sleep(std::time::Duration::from_millis(20));
+    }
+
+    let ctrl_held = is_down(VK_CONTROL);
+    let mut inputs = Vec::with_capacity(4);
+    if !ctrl_held {
+        inputs.push(key(VK_CONTROL, false));
+    }
+    inputs.push(key(VK_V, false));
+    inputs.push(key(VK_V, true));
+    if !ctrl_held {
+        inputs.push(key(VK_CONTROL, true));
+    }
+    send(&inputs);
+}
+
+/// After a capture: release any modifier Stack pressed that is still down
+/// though the user has let go of it. Returns the keys released.
+#[cfg(windows)]
+pub fn verify_modifiers() -> Vec<&'static str> {
+    let injected = std::mem::take(&mut *INJECTED_DOWN.lock().unwrap());
+    let mut released = Vec::new();
+    for (vk, name) in MODIFIERS {
+        if injected.contains(&vk) && is_down(vk) && !is_held(vk) {
+            send(&[key(vk, true)]);
+            released.push(name);
+        }
+    }
+    if !released.is_empty() {
+        println!("Released modifiers left down after capture: {}", released.join(", "));
+    }
+    released
+}
+
+/// Release every Ctrl/Shift the system thinks is down. Meant to be run by
+/// the user when no key is actually held, so anything down is stuck.
+#[cfg(windows)]
+pub fn fix_stuck_modifiers() -> Vec<&'static str> {
+    let mut released = Vec::new();
+    for (vk, name) in MODIFIERS {
+        if is_down(vk) {
+            send(&[key(vk, true)]);
+            released.push(name);
+        }
+    }
+    if !released.is_empty() {
+        println!("Released stuck modifiers: {}", released.join(", "));
+    }
+    released
+}
+
+#[cfg(not(windows))]
+pub fn start_key_tracking() {
+    // No keyboard hook on non-windows
+}
+
 #[cfg(not(windows))]
 pub fn simulate_copy() {
     // No-op for now on non-windows
 }
+
+#[cfg(not(windows))]
+pub fn simulate_paste() {
+    // No-op for now on non-windows
+}
+
+#[cfg(not(windows))]
+pub fn verify_modifiers() -> Vec<&'static str> {
+    Vec::new()
+}
+
+#[cfg(not(windows))]
+pub fn fix_stuck_modifiers() -> Vec<&'static str> {
+    Vec::new()
+}
@@ -0,0 +1,186 @@
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::storage::ClipObject;
+use crate::textutil;
+
+/// Pinned clips shown in the taskbar jump list
+pub const MAX_ITEMS: usize = 5;
+/// Longest jump list entry title, in characters
+pub const MAX_TITLE_CHARS: usize = 60;
+/// Argument each entry launches Stack with, followed by the clip id
+pub const COPY_CLIP_ARG: &str = "--copy-clip";
+/// Changes inside this window are coalesced into one rebuild
+const DEBOUNCE: Duration = Duration::from_millis(500);
+#[cfg(windows)]
+const CATEGORY: &str = "Pinned clips";
+
+/// One jump list entry
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JumpItem {
+    pub clip_id: String,
+    pub title: String,
+}
+
+/// Entries for the first `MAX_ITEMS` pinned clips of `clips`, leaving out
+/// sensitive ones, titled with a one-line preview
+pub fn items_for<'a>(clips: impl IntoIterator<Item = &'a ClipObject>) -> Vec<JumpItem> {
+    clips
+        .into_iter()
+        .filter(|c| c.pinned && !c.sensitive)
+        .take(MAX_ITEMS)
+        .map(|clip| {
+            let preview = clip.title.as_deref().unwrap_or(&clip.content);
+            let line = preview.split_whitespace().collect::<Vec<_>>().join(" ");
+            JumpItem {
+                clip_id: clip.id.clone(),
+                title: textutil::truncate(&line, MAX_TITLE_CHARS).to_string(),
+            }
+        })
+        .collect()
+}
+
+struct JumpState {
+    enabled: bool,
+    /// Latest entries wanted, waiting for the debounce
+    pending: Option<Vec<JumpItem>>,
+    /// Entries the jump list currently has
+    applied: Option<Vec<JumpItem>>,
+    rebuild_scheduled: bool,
+}
+
+static STATE: Mutex<JumpState> = Mutex::new(JumpState {
+    enabled: true,
+    pending: None,
+    applied: None,
+    rebuild_scheduled: false,
+});
+
+/// Turn the jump list on or off; off empties it
+pub fn configure(enabled: bool) {
+    let mut state = STATE.lock().unwrap();
+    if state.enabled == enabled {
+        return;
+    }
+    state.enabled = enabled;
+    let wanted = state.pending.take().or_else(|| state.applied.clone()).unwrap_or_default();
+    state.applied = None;
+    schedule(state, wanted);
+}
+
+/// Record the entries after a change. Cheap enough to call after every change:
+/// the list is only rebuilt once per debounce, and only if it differs.
+pub fn note(items: Vec<JumpItem>) {
+    let state = STATE.lock().unwrap();
+    let current = state.pending.as_ref().or(state.applied.as_ref());
+    if current == Some(&items) {
+        return;
+    }
+    schedule(state, items);
+}
+
+fn schedule(mut state: std::sync::MutexGuard<JumpState>, items: Vec<JumpItem>) {
+    state.pending = Some(items);
+    if state.rebuild_scheduled {
+        return;
+    }
+    state.rebuild_scheduled = true;
+    drop(state);
+
+    std::thread::spawn(|| {
+        std::thread::sleep(DEBOUNCE);
+        let (items, enabled) = {
+            let mut state = STATE.lock().unwrap();
+            state.rebuild_scheduled = false;
+            let Some(items) = state.pending.take() else {
+                return;
+            };
+            state.applied = Some(items.clone());
+            (items, state.enabled)
+        };
+        let shown = if enabled { items.as_slice() } else { &[] };
+        if let Err(e) = rebuild(shown) {
+            eprintln!("Failed to update the jump list: {}", e);
+        }
+    });
+}
+
+/// Replace the jump list's pinned clips category with `items`; none
+/// removes Stack's custom list altogether
+#[cfg(windows)]
+fn rebuild(items: &[JumpItem]) -> Result<(), String> {
+    use windows::core::{Interface, HSTRING, PCWSTR, PROPVARIANT};
+    use windows::Win32::Storage::EnhancedStorage::PKEY_Title;
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER, COINIT_APARTMENTTHREADED,
+    };
+    use windows::Win32::UI::Shell::Common::{IObjectArray, IObjectCollection};
+    use windows::Win32::UI::Shell::PropertiesSystem::IPropertyStore;
+    use windows::Win32::UI::Shell::{
+        DestinationList, EnumerableObjectCollection, ICustomDestinationList, IShellLinkW, ShellLink,
+    };
+
+    let exe = std::env::current_exe().map_err(|e| e.to_string())?;
+    unsafe {
+        let initialized = CoInitializeEx(None, COINIT_APARTMENTTHREADED).is_ok();
+        let result = (|| -> windows::core::Result<()> {
+            let list: ICustomDestinationList = CoCreateInstance(&DestinationList, None, CLSCTX_INPROC_SERVER)?;
+            if items.is_empty() {
+                return list.DeleteList(PCWSTR::null());
+            }
+
+            let mut slots = 0u32;
+            let _removed: IObjectArray = list.BeginList(&mut slots)?;
+            let links: IObjectCollection =
+                CoCreateInstance(&EnumerableObjectCollection, None, CLSCTX_INPROC_SERVER)?;
+            for item in items {
+                let link: IShellLinkW = CoCreateInstance(&ShellLink, None, CLSCTX_INPROC_SERVER)?;
+                link.SetPath(&HSTRING::from(exe.as_path()))?;
+                link.SetArguments(&HSTRING::from(format!("{} {}", COPY_CLIP_ARG, item.clip_id)))?;
+                link.SetIconLocation(&HSTRING::from(exe.as_path()), 0)?;
+                link.SetDescription(&HSTRING::from(item.title.as_str()))?;
+                // The jump list shows the title property, not the description
+                let properties: IPropertyStore = link.cast()?;
+                properties.SetValue(&PKEY_Title, &PROPVARIANT::from(item.title.as_str()))?;
+                properties.Commit()?;
+                links.AddObject(&link)?;
+            }
+            list.AppendCategory(&HSTRING::from(CATEGORY), &links.cast::<IObjectArray>()?)?;
+            list.CommitList()
+        })();
+        if initialized {
+            CoUninitialize();
+        }
+        result.map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(not(windows))]
+fn rebuild(_items: &[JumpItem]) -> Result<(), String> {
+    // Jump lists are a Windows taskbar feature
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+
+    #[test]
+    fn only_the_first_pinned_clips_that_arent_sensitive_are_listed() {
+        let mut clips: Vec<ClipObject> = (0..8).map(|i| clip(&format!("clip {}", i))).collect();
+        for clip in &mut clips[1..] {
+            clip.pinned = true;
+        }
+        clips[2].sensitive = true;
+        clips[3].content = format!("  spread\nover\tlines {}", "x".repeat(100));
+
+        let items = items_for(&clips);
+        let listed: Vec<&str> = items.iter().map(|i| i.clip_id.as_str()).collect();
+        let expected: Vec<&str> = [1, 3, 4, 5, 6].iter().map(|&i| clips[i].id.as_str()).collect();
+        assert_eq!(listed, expected);
+        assert!(items[1].title.starts_with("spread over lines xx"));
+        assert_eq!(items[1].title.chars().count(), MAX_TITLE_CHARS);
+        assert_eq!(items[0].title, "clip 1");
+    }
+}
@@ -0,0 +1,280 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::net::{IpAddr, SocketAddr, UdpSocket};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tiny_http::{Header, Method, Request, Response, Server};
+use uuid::Uuid;
+
+use crate::qr;
+
+/// Longest a share link stays open
+pub const MAX_TTL_SECS: u64 = 60 * 60;
+/// Every share link is `/s/<token>`; `/s/<token>.txt` is the bare text
+const SHARE_PATH: &str = "/s/";
+const GONE: &str = "This link has expired or was already used.";
+const NO_NETWORK: &str = "Couldn't find this PC's address on the local network";
+
+/// A share link as handed to the UI
+#[derive(Debug, Clone, Serialize)]
+pub struct LanShare {
+    pub url: String,
+    /// The URL as a QR code for a phone to scan
+    pub qr_data_url: String,
+    pub expires_at: DateTime<Utc>,
+    pub single_use: bool,
+}
+
+struct Share {
+    content: String,
+    expires: Instant,
+    single_use: bool,
+}
+
+/// Open shares by token. Time is passed in so expiry can be tested
+/// without sleeping.
+#[derive(Default)]
+struct Shares(BTreeMap<String, Share>);
+
+impl Shares {
+    fn add(&mut self, token: String, content: String, ttl: Duration, single_use: bool, now: Instant) {
+        let share = Share { content, expires: now + ttl, single_use };
+        self.0.insert(token, share);
+    }
+
+    /// The content behind `token`, using up a single-use share
+    fn fetch(&mut self, token: &str, now: Instant) -> Option<String> {
+        self.prune(now);
+        if self.0.get(token)?.single_use {
+            return self.0.remove(token).map(|s| s.content);
+        }
+        self.0.get(token).map(|s| s.content.clone())
+    }
+
+    fn prune(&mut self, now: Instant) {
+        self.0.retain(|_, share| share.expires > now);
+    }
+}
+
+struct Listener {
+    server: Arc<Server>,
+    /// `http://<lan address>:<port>`
+    base_url: String,
+}
+
+struct LanState {
+    shares: Shares,
+    /// Running only while there are shares
+    listener: Option<Listener>,
+}
+
+static STATE: Mutex<LanState> = Mutex::new(LanState {
+    shares: Shares(BTreeMap::new()),
+    listener: None,
+});
+
+/// Serve `content` at a fresh unguessable URL on the local network for
+/// `ttl`, starting the listener if it isn't running
+pub fn share(content: String, ttl: Duration, single_use: bool) -> Result<LanShare, String> {
+    let mut state = STATE.lock().unwrap();
+    state.shares.prune(Instant::now());
+    let base_url = match &state.listener {
+        Some(listener) => listener.base_url.clone(),
+        None => {
+            let listener = listen()?;
+            let base_url = listener.base_url.clone();
+            state.listener = Some(listener);
+            base_url
+        }
+    };
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let url = format!("{}{}{}", base_url, SHARE_PATH, token);
+    let qr_data_url = match qr::svg_data_url(&url) {
+        Ok(qr) => qr,
+        Err(e) => {
+            stop_if_idle(&mut state);
+            return Err(e);
+        }
+    };
+    state.shares.add(token, content, ttl, single_use, Instant::now());
+    drop(state);
+
+    std::thread::spawn(move || {
+        std::thread::sleep(ttl);
+        let mut state = STATE.lock().unwrap();
+        state.shares.prune(Instant::now());
+        stop_if_idle(&mut state);
+    });
+    Ok(LanShare {
+        url,
+        qr_data_url,
+        expires_at: Utc::now() + chrono::Duration::from_std(ttl).unwrap_or_default(),
+        single_use,
+    })
+}
+
+/// Close every share and the listener, e.g. when sharing is turned off
+pub fn stop_all() {
+    let mut state = STATE.lock().unwrap();
+    state.shares.0.clear();
+    stop_if_idle(&mut state);
+}
+
+fn stop_if_idle(state: &mut LanState) {
+    if state.shares.0.is_empty() {
+        if let Some(listener) = state.listener.take() {
+            listener.server.unblock();
+        }
+    }
+}
+
+/// This PC's address on the local network: the one it would reach the
+/// internet from. Connecting a UDP socket sends nothing.
+fn lan_address() -> Result<IpAddr, String> {
+    let socket = UdpSocket::bind(("0.0.0.0", 0)).map_err(|e| format!("{}: {}", NO_NETWORK, e))?;
+    socket.connect(("8.8.8.8", 80)).map_err(|e| format!("{}: {}", NO_NETWORK, e))?;
+    let ip = socket.local_addr().map_err(|e| format!("{}: {}", NO_NETWORK, e))?.ip();
+    if ip.is_loopback() || ip.is_unspecified() {
+        return Err(NO_NETWORK.to_string());
+    }
+    Ok(ip)
+}
+
+/// Start a listener on the LAN address, on whatever port is free
+fn listen() -> Result<Listener, String> {
+    let ip = lan_address()?;
+    let server = Server::http((ip, 0)).map_err(|e| format!("Couldn't start sharing on {}: {}", ip, e))?;
+    let port = server
+        .server_addr()
+        .to_ip()
+        .map(|addr| addr.port())
+        .ok_or_else(|| "Couldn't start sharing: no port".to_string())?;
+    let server = Arc::new(server);
+    {
+        let server = server.clone();
+        std::thread::spawn(move || {
+            // Ends once `unblock` is called
+            for request in server.incoming_requests() {
+                respond(request);
+            }
+        });
+    }
+    Ok(Listener {
+        server,
+        base_url: format!("http://{}", SocketAddr::new(ip, port)),
+    })
+}
+
+fn respond(request: Request) {
+    let (status, content_type, body) = {
+        let mut state = STATE.lock().unwrap();
+        let response = handle(&mut state.shares, request.method(), request.url(), Instant::now());
+        stop_if_idle(&mut state);
+        response
+    };
+    let response = Response::from_string(body)
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", content_type).unwrap())
+        .with_header(Header::from_bytes("Cache-Control", "no-store").unwrap())
+        .with_header(Header::from_bytes("Referrer-Policy", "no-referrer").unwrap());
+    let _ = request.respond(response);
+}
+
+/// Status, content type and body for a request
+fn handle(shares: &mut Shares, method: &Method, url: &str, now: Instant) -> (u16, &'static str, String) {
+    const TEXT: &str = "text/plain; charset=utf-8";
+    if *method != Method::Get {
+        return (405, TEXT, "Only GET is supported.".to_string());
+    }
+    let path = url.split(['?', '#']).next().unwrap_or_default();
+    let Some(token) = path.strip_prefix(SHARE_PATH) else {
+        return (404, TEXT, GONE.to_string());
+    };
+    let (token, raw) = match token.strip_suffix(".txt") {
+        Some(token) => (token, true),
+        None => (token, false),
+    };
+    match shares.fetch(token, now) {
+        Some(content) if raw => (200, TEXT, content),
+        Some(content) => (200, "text/html; charset=utf-8", page(&content)),
+        None => (404, TEXT, GONE.to_string()),
+    }
+}
+
+/// The clip in a read-only text box with a copy button. Plain HTTP isn't a
+/// secure context, so the button copies the selection rather than using
+/// the async clipboard API.
+fn page(content: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Stack clip</title>
+<style>
+body {{ font-family: system-ui, sans-serif; margin: 16px; }}
+textarea {{ box-sizing: border-box; width: 100%; height: 70vh; font: 14px monospace; }}
+button {{ font-size: 18px; padding: 12px 24px; margin-top: 12px; }}
+</style>
+</head>
+<body>
+<textarea id="clip" readonly>{}</textarea>
+<button id="copy">Copy</button>
+<script>
+document.getElementById("copy").onclick = function () {{
+  var text = document.getElementById("clip");
+  text.select();
+  text.setSelectionRange(0, text.value.length);
+  var copied = false;
+  try {{ copied = document.execCommand("copy"); }} catch (e) {{}}
+  this.textContent = copied ? "Copied" : "Select the text and copy it";
+}};
+</script>
+</body>
+</html>
+"#,
+        escape_html(content)
+    )
+}
+
+fn escape_html(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for ch in text.chars() {
+        match ch {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn shares_expire_and_single_use_ones_serve_once() {
+        let now = Instant::now();
+        let mut shares = Shares::default();
+        shares.add("once".to_string(), "<b>hi</b>".to_string(), Duration::from_secs(60), true, now);
+        shares.add("often".to_string(), "text".to_string(), Duration::from_secs(60), false, now);
+
+        let (status, content_type, body) = handle(&mut shares, &Method::Get, "/s/once", now);
+        assert_eq!((status, content_type), (200, "text/html; charset=utf-8"));
+        assert!(body.contains("&lt;b&gt;hi&lt;/b&gt;</textarea>"));
+        assert_eq!(handle(&mut shares, &Method::Get, "/s/once.txt", now).0, 404);
+
+        for _ in 0..2 {
+            assert_eq!(handle(&mut shares, &Method::Get, "/s/often.txt?x=1", now).2, "text");
+        }
+        assert_eq!(handle(&mut shares, &Method::Post, "/s/often", now).0, 405);
+        assert_eq!(handle(&mut shares, &Method::Get, "/often", now).0, 404);
+        assert_eq!(handle(&mut shares, &Method::Get, "/s/often", now + Duration::from_secs(60)).0, 404);
+        assert!(shares.0.is_empty());
+    }
+}
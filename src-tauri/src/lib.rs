@@ -2,19 +2,131 @@ mod storage;
 mod window;
 mod input;
 mod ai;
+mod ai_transport;
+mod dragout;
+mod paths;
+mod sync;
+mod metrics;
+mod deeplink;
+mod assets;
+mod diff;
+mod ai_queue;
+mod ingest;
+mod shell;
+mod search;
+mod theme;
+mod health;
+mod attribution;
+mod titlebar;
+mod calc;
+mod rules;
+mod mirror;
+mod hotkeys;
+mod webhooks;
+mod terminal;
+mod local_api;
+mod presets;
+mod shutdown;
+mod capture_batch;
+mod export;
+mod clipboard_history;
+mod tray;
+mod usage;
+mod clock;
+mod capture_path;
+mod quick_note;
+mod textutil;
+mod autoexport;
+mod migration;
+mod filedrop;
+mod ai_history;
+mod stats;
+mod undo;
+mod safe_mode;
+mod notify;
+mod clipboard_hold;
+mod announce;
+mod jumplist;
+mod qr;
+mod lan_share;
+mod clipboard_formats;
+mod diagnostics;
+mod rate_limit;
+#[cfg(test)]
+mod test_support;
 
-use std::sync::Mutex;
-use storage::{AppStorage, ClipObject, Pastebook};
+use announce::Message;
+use chrono::{DateTime, Utc};
+use std::path::PathBuf;
+use std::sync::{Mutex, RwLock};
+use storage::{
+    normalize_tags, AppFilter, AppStorage, BulkOutcome, BulkUpdateResult, CaptureOutcome, CapturedClip,
+    ClipObject, ClipPatch, DecimalSeparator, MergeOptions, MergeOrder,
+    CaptureSession, Pastebook, PastebookGroup, PastebookStats, PastebookSummary, PastebookTemplate, Reminder, Revisioned, SessionReport, Settings, SortOrder,
+    ThemePreference, TimelineHour,
+};
 use tauri::{AppHandle, Manager, Emitter};
 use tauri_plugin_clipboard_manager::ClipboardExt;
-use tauri_plugin_global_shortcut::{Code, GlobalShortcutExt, Modifiers, Shortcut, ShortcutState};
-use window::get_active_window_info;
-use uuid::Uuid;
-use ai::GeminiClient;
+use tauri_plugin_dialog::DialogExt;
+use window::{capture_window_info, ForegroundRecord, WindowInfo};
+use ai::{AiReply, GeminiClient};
+use ai_history::{AiHistoryFilter, AiInteraction, AiRequest};
+use metrics::{CommandMetrics, Metrics};
+use ai_queue::{AiQueue, AiQueueStatus, QueueLimits};
+use rate_limit::{RateLimitAction, RateLimiter};
+use deeplink::LaunchRequest;
+use shell::RegistryChange;
+use rules::{CaptureRule, RuleSample, RuleTestResult};
+use presets::{PresetOutput, PromptPreset};
+use search::SearchIndexStats;
+use health::StorageHealth;
+use notify::NotificationKind;
+use ingest::IngestTransform;
 
-// Global storage state
+/// Global state. Lock order: `storage` before `settings`; never wait for
+/// `storage` while holding `settings`. Transient capture state (held
+/// captures, the batch being coalesced) sits behind its own small locks in
+/// `capture_path` and `capture_batch` and is never held across either.
+/// Hotkey captures go through `capture_path::INBOX`, which only tries
+/// `storage` and queues the capture when it's busy.
 struct AppState {
-    storage: Mutex<AppStorage>,
+    /// Everything saved to disk. Commands that only read take a read guard,
+    /// and long work (backups, index rebuilds) copies or builds what it
+    /// needs under one so a capture waits only for the copy.
+    storage: RwLock<AppStorage>,
+    /// A copy of `storage.settings` for readers that need nothing else, such
+    /// as the hotkey capture path, so they never wait behind clip work.
+    /// Kept current by `publish_settings`.
+    settings: RwLock<Settings>,
+    metrics: Metrics,
+    ai_queue: AiQueue,
+    /// Holds back captures from apps copying faster than the settings allow
+    rate_limiter: Mutex<RateLimiter>,
+}
+
+impl AppState {
+    fn new(storage: AppStorage) -> Self {
+        let settings = &storage.settings;
+        let rate_limiter = RateLimiter::new(settings.capture_rate_limit, settings.capture_rate_window());
+        Self {
+            settings: RwLock::new(storage.settings.clone()),
+            storage: RwLock::new(storage),
+            metrics: Metrics::default(),
+            ai_queue: AiQueue::default(),
+            rate_limiter: Mutex::new(rate_limiter),
+        }
+    }
+
+    /// The current settings, without touching `storage`
+    fn settings(&self) -> std::sync::RwLockReadGuard<'_, Settings> {
+        self.settings.read().unwrap()
+    }
+
+    /// Refresh the settings copy after `storage.settings` changed; called
+    /// with the storage guard still held, which the lock order allows
+    fn publish_settings(&self, settings: &Settings) {
+        *self.settings.write().unwrap() = settings.clone();
+    }
 }
 
 // ==================== CLIP COMMANDS ====================
@@ -26,22 +138,330 @@ fn greet(name: &str) -> String {
 
 #[tauri::command]
 async fn set_api_key(api_key: String, state: tauri::State<'_, AppState>) -> Result<(), String> {
-    let mut storage = state.storage.lock().unwrap();
+    let _timer = state.metrics.time("set_api_key");
+    let mut storage = state.storage.write().unwrap();
     storage.api_key = Some(api_key);
     storage.save().map_err(|e| e.to_string())
 }
 
+/// Get user settings
+#[tauri::command]
+fn get_settings(state: tauri::State<AppState>) -> Settings {
+    let _timer = state.metrics.time("get_settings");
+    state.settings().clone()
+}
+
+/// Where each hotkey stands: registered, off, or not claimable and why
+#[tauri::command]
+fn get_shortcut_status(state: tauri::State<AppState>) -> std::collections::BTreeMap<hotkeys::Action, hotkeys::ShortcutStatus> {
+    let _timer = state.metrics.time("get_shortcut_status");
+    hotkeys::status()
+}
+
+/// Replace user settings
+#[tauri::command]
+fn update_settings(
+    app: AppHandle,
+    mut settings: Settings,
+    state: tauri::State<AppState>,
+) -> Result<Settings, String> {
+    let _timer = state.metrics.time("update_settings");
+    let mut storage = state.storage.write().unwrap();
+    // Pause state only changes through set_capture_paused
+    settings.capture_paused = storage.settings.capture_paused;
+    settings.capture_resume_at = storage.settings.capture_resume_at;
+    settings.normalize_app_lists();
+    let theme_changed = settings.theme != storage.settings.theme;
+    settings.webhooks = webhooks::validate(settings.webhooks)?;
+    let bad_price = settings.ai_prices.iter().any(|p| {
+        p.model.trim().is_empty() || !(p.input_per_million >= 0.0 && p.output_per_million >= 0.0)
+    });
+    if bad_price {
+        return Err("Every AI price needs a model and prices of 0 or more".to_string());
+    }
+    if settings.ai_chunk_budget_chars == 0 || settings.ai_max_chunks == 0 {
+        return Err("Validation: AI chunk size and chunk limit must be at least 1".to_string());
+    }
+    // The token only changes through regenerate_local_api_token
+    settings.local_api_token = storage.settings.local_api_token.clone();
+    if settings.local_api_enabled {
+        if settings.local_api_port == 0 {
+            return Err("Local API port must be between 1 and 65535".to_string());
+        }
+        settings.local_api_token.get_or_insert_with(local_api::generate_token);
+    }
+    hotkeys::validate(&settings)?;
+    // Nothing changes unless the new settings are on disk
+    let previous = std::mem::replace(&mut storage.settings, settings);
+    if let Err(e) = storage.save() {
+        storage.settings = previous;
+        return Err(e);
+    }
+    state.publish_settings(&storage.settings);
+    let settings = storage.settings.clone();
+    drop(storage);
+
+    if !settings.lan_share_enabled {
+        lan_share::stop_all();
+    }
+    apply_settings(&app, &state, &settings);
+    if theme_changed {
+        theme::apply(&app, settings.theme);
+    }
+    // A port in use leaves the API off; the settings are kept so it starts next time
+    local_api::apply(&app, &settings).map_err(|e| format!("Settings saved, but: {}", e))?;
+    Ok(settings)
+}
+
+/// Bring everything that follows the settings into line with them after
+/// they change
+fn apply_settings(app: &AppHandle, state: &AppState, settings: &Settings) {
+    titlebar::set_badge_enabled(app, settings.unseen_badge);
+    webhooks::configure(&settings.webhooks);
+    notify::configure(settings.notification_policy);
+    notify::report_holding(app);
+    announce::configure(settings.language);
+    jumplist::configure(settings.jump_list);
+    state.rate_limiter.lock().unwrap().configure(settings.capture_rate_limit, settings.capture_rate_window());
+    // Saved even when a hotkey can't be claimed; get_shortcut_status reports it
+    if !safe_mode::is_active() {
+        hotkeys::reconcile(app, settings, &HOTKEY_HANDLERS);
+    }
+}
+
+/// Delivery counters for each configured webhook
+#[tauri::command]
+fn get_webhook_status(state: tauri::State<AppState>) -> Vec<webhooks::WebhookStatus> {
+    let _timer = state.metrics.time("get_webhook_status");
+    webhooks::current_status()
+}
+
+/// Bearer token for the local HTTP API, created on first use
+#[tauri::command]
+fn get_local_api_token(state: tauri::State<AppState>) -> Result<String, String> {
+    let _timer = state.metrics.time("get_local_api_token");
+    let mut storage = state.storage.write().unwrap();
+    if let Some(token) = &storage.settings.local_api_token {
+        return Ok(token.clone());
+    }
+    let token = local_api::generate_token();
+    storage.settings.local_api_token = Some(token.clone());
+    state.publish_settings(&storage.settings);
+    storage.save()?;
+    Ok(token)
+}
+
+/// Replace the local API token; clients using the old one get 401 from now on
+#[tauri::command]
+fn regenerate_local_api_token(state: tauri::State<AppState>) -> Result<String, String> {
+    let _timer = state.metrics.time("regenerate_local_api_token");
+    let mut storage = state.storage.write().unwrap();
+    let token = local_api::generate_token();
+    storage.settings.local_api_token = Some(token.clone());
+    state.publish_settings(&storage.settings);
+    storage.save()?;
+    Ok(token)
+}
+
+/// Light or dark, with "system" resolved against the OS
+#[tauri::command]
+fn get_effective_theme(app: AppHandle, state: tauri::State<AppState>) -> tauri::Theme {
+    let _timer = state.metrics.time("get_effective_theme");
+    let preference = state.settings().theme;
+    theme::effective_theme(Some(&app), preference)
+}
+
+/// Result of a magic sort: the new clip order and the model that produced it
+#[derive(serde::Serialize)]
+struct MagicSortResult {
+    ids: Vec<String>,
+    model: String,
+}
+
+/// Payload of the `capture-blocked` event
+#[derive(Clone, serde::Serialize)]
+struct CaptureBlocked {
+    reason: &'static str,
+    announcement: String,
+}
+
+/// True (and tell the UI) if capture is paused
+fn capture_blocked(app: &AppHandle, settings: &Settings) -> bool {
+    if settings.capture_paused {
+        let announcement = announce::text(Message::BlockedPaused);
+        let _ = app.emit("capture-blocked", CaptureBlocked { reason: "paused", announcement });
+        return true;
+    }
+    false
+}
+
+/// Payload of the `capture-failed` event
+#[derive(Clone, serde::Serialize)]
+struct CaptureFailed {
+    reason: ingest::RejectReason,
+    announcement: String,
+}
+
+/// Sanitize captured text, telling the UI when it had to be refused
+fn sanitize_capture(app: &AppHandle, raw: &str) -> Result<String, String> {
+    ingest::sanitize_text(raw).map_err(|reason| {
+        let announcement = announce::text(match reason {
+            ingest::RejectReason::BinaryContent => Message::FailedBinary,
+            ingest::RejectReason::Empty => Message::FailedEmpty,
+        });
+        let _ = app.emit("capture-failed", CaptureFailed { reason, announcement });
+        reason.message().to_string()
+    })
+}
+
+/// End a timed pause at `resume_at`, unless the pause was changed meanwhile.
+/// Waits in steps of at most a minute so a clock change meanwhile still
+/// resumes at the right wall-clock time.
+fn schedule_capture_resume(app: AppHandle, resume_at: DateTime<Utc>) {
+    std::thread::spawn(move || {
+        while let Ok(wait) = (resume_at - Utc::now()).to_std() {
+            if wait.is_zero() {
+                break;
+            }
+            std::thread::sleep(wait.min(std::time::Duration::from_secs(60)));
+        }
+
+        let state = app.state::<AppState>();
+        let mut storage = state.storage.write().unwrap();
+        if !storage.settings.capture_paused
+            || storage.settings.capture_resume_at != Some(resume_at)
+        {
+            return;
+        }
+        storage.settings.capture_paused = false;
+        storage.settings.capture_resume_at = None;
+        state.publish_settings(&storage.settings);
+        let _ = storage.save();
+        drop(storage);
+
+        let _ = app.emit("capture-resumed", ());
+    });
+}
+
+/// Pause or resume all capture, optionally resuming after a number of minutes
+#[tauri::command]
+fn set_capture_paused(
+    app: AppHandle,
+    paused: bool,
+    resume_after_minutes: Option<u32>,
+    state: tauri::State<AppState>,
+) -> Result<Settings, String> {
+    let _timer = state.metrics.time("set_capture_paused");
+    let resume_at = match resume_after_minutes {
+        Some(minutes) if paused && minutes > 0 => {
+            Some(Utc::now() + chrono::Duration::minutes(minutes as i64))
+        }
+        _ => None,
+    };
+
+    let mut storage = state.storage.write().unwrap();
+    storage.settings.capture_paused = paused;
+    storage.settings.capture_resume_at = resume_at;
+    state.publish_settings(&storage.settings);
+    storage.save()?;
+    let settings = storage.settings.clone();
+    drop(storage);
+
+    if let Some(resume_at) = resume_at {
+        schedule_capture_resume(app.clone(), resume_at);
+    }
+    if !paused {
+        let _ = app.emit("capture-resumed", ());
+    }
+
+    Ok(settings)
+}
+
+/// Payload of the `capture-skipped` event
+#[derive(Clone, serde::Serialize)]
+struct CaptureSkipped {
+    #[serde(flatten)]
+    reason: SkipReason,
+    announcement: String,
+}
+
+/// Why a capture was skipped
+#[derive(Clone, serde::Serialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+enum SkipReason {
+    /// A capture rule matched; its name
+    Rule { rule: String },
+    /// Its app is copying faster than the rate limit
+    RateLimited { source_app: String },
+}
+
+/// Payload of the `capture-held` event
+#[derive(Clone, serde::Serialize)]
+struct CaptureHeld {
+    pending_id: String,
+    source_app: String,
+    announcement: String,
+}
+
+/// Set aside a capture from an app outside the focus list and ask the UI
+/// whether to keep it
+fn hold_capture(app: &AppHandle, clip: ClipObject) {
+    let source_app = clip.metadata.source_app.clone();
+    let announcement = announce::text(Message::Held { app: &source_app });
+    let pending_id = capture_path::HELD.lock().unwrap().hold(clip, std::time::Instant::now());
+    let _ = app.emit("capture-held", CaptureHeld { pending_id, source_app, announcement });
+}
+
+/// Store a capture that was held because its app isn't in the focus list
+#[tauri::command]
+fn capture_anyway(app: AppHandle, pending_id: String, state: tauri::State<AppState>) -> Result<(), String> {
+    let _timer = state.metrics.time("capture_anyway");
+    let clip = capture_path::HELD
+        .lock()
+        .unwrap()
+        .take(&pending_id, std::time::Instant::now())
+        .ok_or_else(|| "NotFound: That capture has expired".to_string())?;
+    if capture_blocked(&app, &state.settings()) {
+        return Err("Capture is paused".to_string());
+    }
+    add_external_clip(&app, clip);
+    Ok(())
+}
+
+/// The Gemini API key: the one set in settings, else the GOOGLE_API_KEY or
+/// GEMINI_API_KEY env var, else the mock transport's stand-in
+fn api_key(storage: &AppStorage) -> Option<String> {
+    storage
+        .api_key
+        .clone()
+        .or_else(|| std::env::var("GOOGLE_API_KEY").ok())
+        .or_else(|| std::env::var("GEMINI_API_KEY").ok())
+        .or_else(ai_transport::mock_api_key)
+}
+
+/// Let the model reorder the active pastebook, or just the clips in `ids`
+/// (e.g. the ones a search left showing); a scoped sort moves those clips
+/// among the slots they already hold and leaves the rest alone
 #[tauri::command]
-async fn magic_sort(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
+async fn magic_sort(
+    app: AppHandle,
+    ids: Option<Vec<String>>,
+    expected_revision: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Revisioned<MagicSortResult>, String> {
+    let mut timer = state.metrics.time("magic_sort");
+    safe_mode::check_ai()?;
     // Get data in a block to drop the lock immediately
-    let (api_key, clips_content) = {
-        let storage = state.storage.lock().unwrap();
-        let api_key = storage.api_key.clone()
-            .or_else(|| std::env::var("GOOGLE_API_KEY").ok())
-            .or_else(|| std::env::var("GEMINI_API_KEY").ok())
-            .ok_or("API Key not found")?;
-        let clips_content = storage.get_all_content();
-        (api_key, clips_content)
+    let (api_key, models, clips_content, clip_ids, sensitive, read_revision) = {
+        let storage = storage::loaded(&state.storage);
+        storage.check_revision(expected_revision)?;
+        let api_key = api_key(&storage).ok_or("API Key not found")?;
+        let clips = storage.scoped_clips(ids.as_deref())?;
+        let clips_content = storage::joined_content(clips.iter().copied());
+        timer.payload(clips_content.len(), clips.len());
+        let clip_ids = clips.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        let sensitive = clips.iter().any(|c| c.sensitive);
+        (api_key, storage.settings.model_fallbacks.clone(), clips_content, clip_ids, sensitive, storage.revision)
     };
 
     if clips_content.is_empty() {
@@ -49,54 +469,75 @@ async fn magic_sort(state: tauri::State<'_, AppState>) -> Result<Vec<String>, St
     }
     
     let client = GeminiClient::new(api_key);
-    let json_indices = client.magic_sort(&clips_content).await?;
+    let (indices, reply) = client.magic_sort(&models, &clips_content).await?;
+    let request = AiRequest {
+        instruction: "Reorder clips into a logical structure",
+        prompt: &clips_content,
+        clip_ids: clip_ids.clone(),
+        sensitive,
+    };
+    record_ai_usage(&app, "magic_sort", request, &reply);
+    let indices = indices?;
     
-    // Parse indices
-    let indices: Vec<usize> = serde_json::from_str(&json_indices)
-        .map_err(|e| format!("Failed to parse AI response: {}", e))?;
-        
-    // Reorder clips in storage
-    let mut storage = state.storage.lock().unwrap();
-    let pastebook = storage.get_active_pastebook().ok_or("No active pastebook")?;
-    let current_ids: Vec<String> = pastebook.clips.iter().map(|c| c.id.clone()).collect();
+    // Reorder clips in storage, unless they changed while the AI was thinking
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(Some(read_revision))?;
     
     let mut new_ids = Vec::new();
     
     // Map indices back to IDs
     for idx in indices {
-        if let Some(id) = current_ids.get(idx) {
+        if let Some(id) = clip_ids.get(idx) {
             new_ids.push(id.clone());
         }
     }
     
     // Add any missing IDs (if AI hallucinated or skipped)
-    for id in &current_ids {
+    for id in &clip_ids {
         if !new_ids.contains(id) {
             new_ids.push(id.clone());
         }
     }
     
-    storage.reorder_clips(new_ids.clone());
-    storage.save().map_err(|e| e.to_string())?;
+    // Pinned clips keep their positions whatever the model suggested
+    let new_ids = match ids {
+        Some(_) => storage.apply_scoped_order(&new_ids),
+        None => storage.apply_order(&new_ids),
+    };
+    storage.note_chrome();
+    let revision = storage.commit()?;
     
-    Ok(new_ids)
+    Ok(Revisioned {
+        revision,
+        data: MagicSortResult {
+            ids: new_ids,
+            model: reply.model,
+        },
+    })
 }
 
 #[tauri::command]
-async fn chat_submit(prompt: String, state: tauri::State<'_, AppState>) -> Result<String, String> {
-    let (api_key, context_clips) = {
-        let storage = state.storage.lock().unwrap();
-        let api_key = storage.api_key.clone()
-            .or_else(|| std::env::var("GOOGLE_API_KEY").ok())
-            .or_else(|| std::env::var("GEMINI_API_KEY").ok())
-            .ok_or("API Key not found")?;
+async fn chat_submit(
+    app: AppHandle,
+    prompt: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<AiReply, String> {
+    let _timer = state.metrics.time("chat_submit");
+    safe_mode::check_ai()?;
+    let (api_key, models, context_clips, clip_ids, sensitive) = {
+        let storage = storage::loaded(&state.storage);
+        let api_key = api_key(&storage).ok_or("API Key not found")?;
         
         // Optimize: Limit context to last 10 clips to avoid token limits on free tier
-        let context_clips = storage.get_active_pastebook()
-            .map(|p| p.clips.iter().take(10).map(|c| c.content.clone()).collect::<Vec<_>>().join("\n---\n"))
+        let context: Vec<&ClipObject> = storage
+            .get_active_pastebook()
+            .map(|p| p.clips.iter().take(10).collect())
             .unwrap_or_default();
+        let context_clips = context.iter().map(|c| c.content.clone()).collect::<Vec<_>>().join("\n---\n");
+        let clip_ids = context.iter().map(|c| c.id.clone()).collect::<Vec<_>>();
+        let sensitive = context.iter().any(|c| c.sensitive);
             
-        (api_key, context_clips)
+        (api_key, storage.settings.model_fallbacks.clone(), context_clips, clip_ids, sensitive)
     };
     
     let client = GeminiClient::new(api_key);
@@ -105,251 +546,4042 @@ async fn chat_submit(prompt: String, state: tauri::State<'_, AppState>) -> Resul
         context_clips, prompt
     );
     
-    client.chat("gemini-flash-latest", &full_prompt).await
+    let reply = client.chat_with_fallback(&models, &full_prompt).await?;
+    let request = AiRequest { instruction: &prompt, prompt: &full_prompt, clip_ids, sensitive };
+    record_ai_usage(&app, "chat_submit", request, &reply);
+    Ok(reply)
 }
 
 #[tauri::command]
 async fn get_models(state: tauri::State<'_, AppState>) -> Result<Vec<String>, String> {
-    let api_key = {
-        let storage = state.storage.lock().unwrap();
-        storage.api_key.clone()
-            .or_else(|| std::env::var("GOOGLE_API_KEY").ok())
-            .or_else(|| std::env::var("GEMINI_API_KEY").ok())
-            .ok_or("API Key not found. Please set it in Settings or via GOOGLE_API_KEY env var.")?
-    };
+    let _timer = state.metrics.time("get_models");
+    safe_mode::check_ai()?;
+    let api_key = api_key(&state.storage.read().unwrap())
+        .ok_or("API Key not found. Please set it in Settings or via GOOGLE_API_KEY env var.")?;
     
     let client = GeminiClient::new(api_key);
     client.list_models().await
 }
-/// Get all clips from active pastebook
+
+/// Check an API key (or the saved one) with a quick authenticated call.
+/// Nothing is saved unless `save_on_success` and the key is valid; the
+/// key itself never appears in the result.
 #[tauri::command]
-fn get_clips(state: tauri::State<AppState>) -> Vec<ClipObject> {
-    let storage = state.storage.lock().unwrap();
-    storage.get_clips()
+async fn verify_api_key(
+    key: Option<String>,
+    save_on_success: Option<bool>,
+    state: tauri::State<'_, AppState>,
+) -> Result<ai::KeyCheck, String> {
+    let _timer = state.metrics.time("verify_api_key");
+    safe_mode::check_ai()?;
+    let candidate = key.as_deref().map(str::trim).filter(|k| !k.is_empty());
+    let key = match candidate {
+        Some(key) => key.to_string(),
+        None => api_key(&state.storage.read().unwrap())
+            .ok_or("API Key not found. Please set it in Settings or via GOOGLE_API_KEY env var.")?,
+    };
+
+    let check = GeminiClient::new(key.clone()).verify_key().await;
+    if matches!(check, ai::KeyCheck::Valid { .. }) && save_on_success.unwrap_or(false) {
+        let mut storage = state.storage.write().unwrap();
+        storage.api_key = Some(key);
+        storage.save()?;
+    }
+    Ok(check)
 }
 
-/// Capture current clipboard with metadata
+/// Set the ordered list of AI models to fall back through
 #[tauri::command]
-fn capture_clip(app: AppHandle, state: tauri::State<AppState>) -> Result<ClipObject, String> {
-    let content = app
-        .clipboard()
-        .read_text()
-        .unwrap_or_default();
-
-    if content.trim().is_empty() {
-        return Err("Clipboard is empty".to_string());
+async fn set_model_fallbacks(
+    models: Vec<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Vec<String>, String> {
+    let _timer = state.metrics.time("set_model_fallbacks");
+    let mut normalized: Vec<String> = Vec::new();
+    for model in models.iter().map(|m| ai::normalize_model_name(m)) {
+        if !model.is_empty() && !normalized.contains(&model) {
+            normalized.push(model);
+        }
+    }
+    if normalized.is_empty() {
+        return Err("At least one model is required".to_string());
     }
 
-    let window_info = get_active_window_info();
-    let clip = ClipObject::new(content, window_info);
+    let api_key = api_key(&state.storage.read().unwrap());
+
+    // Without a key there is nothing to check against; accept the list as-is
+    if let Some(api_key) = api_key {
+        let available: Vec<String> = GeminiClient::new(api_key)
+            .list_models()
+            .await?
+            .iter()
+            .map(|m| ai::normalize_model_name(m))
+            .collect();
+        let unknown: Vec<&str> = normalized
+            .iter()
+            .filter(|m| !available.contains(m))
+            .map(String::as_str)
+            .collect();
+        if !unknown.is_empty() {
+            return Err(format!("Unknown or unsupported models: {}", unknown.join(", ")));
+        }
+    }
 
-    let mut storage = state.storage.lock().unwrap();
-    storage.add_clip(clip.clone());
+    let mut storage = state.storage.write().unwrap();
+    storage.settings.model_fallbacks = normalized;
+    state.publish_settings(&storage.settings);
     storage.save()?;
+    Ok(storage.settings.model_fallbacks.clone())
+}
 
-    Ok(clip)
+/// The transforms applied to every capture, in order
+#[tauri::command]
+fn get_ingest_transforms(state: tauri::State<AppState>) -> Vec<IngestTransform> {
+    let _timer = state.metrics.time("get_ingest_transforms");
+    state.settings().ingest_transforms.clone()
 }
 
-/// Delete a clip
+/// Set the transforms applied to every capture, by name, in the order they run
 #[tauri::command]
-fn delete_clip(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let deleted = storage.delete_clip(&id);
+fn set_ingest_transforms(
+    transforms: Vec<String>,
+    state: tauri::State<AppState>,
+) -> Result<Vec<IngestTransform>, String> {
+    let _timer = state.metrics.time("set_ingest_transforms");
+    let transforms = ingest::parse_transforms(&transforms)?;
+    let mut storage = state.storage.write().unwrap();
+    storage.settings.ingest_transforms = transforms;
+    state.publish_settings(&storage.settings);
     storage.save()?;
-    Ok(deleted)
+    Ok(storage.settings.ingest_transforms.clone())
+}
+
+/// Payload of the `ai-budget-exceeded` event
+#[derive(Clone, serde::Serialize)]
+struct AiBudgetExceeded {
+    used: u64,
+    budget: u64,
+}
+
+/// Log an AI reply's token usage and, unless settings turn it off, the
+/// interaction itself; warns when the reply takes this month's total past
+/// the budget
+fn record_ai_usage(app: &AppHandle, command: &str, request: AiRequest, reply: &AiReply) {
+    let state = app.state::<AppState>();
+    let mut storage = state.storage.write().unwrap();
+    let now = Utc::now();
+    let before = usage::month_tokens(&storage.ai_usage, &chrono::Local, now);
+    usage::record(&mut storage.ai_usage, command, reply, now);
+    if storage.settings.record_ai_history {
+        ai_history::record(&mut storage.ai_history, command, request, reply, now);
+    }
+    let used = before + reply.usage.tokens_in + reply.usage.tokens_out;
+    let budget = storage.settings.ai_monthly_token_budget;
+    let _ = storage.save();
+    drop(storage);
+
+    if let Some(budget) = budget.filter(|&budget| before <= budget && used > budget) {
+        let _ = app.emit("ai-budget-exceeded", AiBudgetExceeded { used, budget });
+    }
 }
 
-/// Update a clip's content
+/// Refuse batch AI work once the month's budget is used up, when settings
+/// ask for that
+fn check_batch_budget(storage: &AppStorage) -> Result<(), String> {
+    let Some(budget) = storage
+        .settings
+        .ai_monthly_token_budget
+        .filter(|_| storage.settings.ai_budget_blocks_batch)
+    else {
+        return Ok(());
+    };
+    let used = usage::month_tokens(&storage.ai_usage, &chrono::Local, Utc::now());
+    if used > budget {
+        return Err(format!(
+            "Monthly AI budget used up ({} of {} tokens); batch jobs resume next month",
+            used, budget
+        ));
+    }
+    Ok(())
+}
+
+/// Tokens and estimated cost of AI requests from `from` to `to` (inclusive,
+/// local dates), per day and per command
 #[tauri::command]
-fn update_clip(id: String, content: String, state: tauri::State<AppState>) -> Result<bool, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let updated = storage.update_clip(&id, content);
-    storage.save()?;
-    Ok(updated)
+fn get_ai_usage(
+    from: chrono::NaiveDate,
+    to: chrono::NaiveDate,
+    state: tauri::State<AppState>,
+) -> Result<usage::UsageReport, String> {
+    let _timer = state.metrics.time("get_ai_usage");
+    if from > to {
+        return Err("'from' is after 'to'".to_string());
+    }
+    let storage = state.storage.read().unwrap();
+    Ok(usage::report(&storage.ai_usage, &storage.settings.ai_prices, &chrono::Local, from, to))
 }
 
-/// Reorder clips
+/// Recent AI interactions, newest first, narrowed by `filter`
 #[tauri::command]
-fn reorder_clips(ids: Vec<String>, state: tauri::State<AppState>) -> Result<(), String> {
-    let mut storage = state.storage.lock().unwrap();
-    storage.reorder_clips(ids);
-    storage.save()?;
-    Ok(())
+fn get_ai_history(
+    limit: Option<usize>,
+    filter: Option<AiHistoryFilter>,
+    state: tauri::State<AppState>,
+) -> Vec<AiInteraction> {
+    let _timer = state.metrics.time("get_ai_history");
+    let storage = state.storage.read().unwrap();
+    let limit = limit.unwrap_or(ai_history::MAX_ENTRIES);
+    ai_history::query(&storage.ai_history, limit, &filter.unwrap_or_default())
 }
 
-/// Merge multiple clips
+/// Forget every recorded AI interaction; returns how many there were
 #[tauri::command]
-fn merge_clips(ids: Vec<String>, state: tauri::State<AppState>) -> Result<Option<ClipObject>, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let merged = storage.merge_clips(ids);
+fn clear_ai_history(state: tauri::State<AppState>) -> Result<usize, String> {
+    let _timer = state.metrics.time("clear_ai_history");
+    let mut storage = state.storage.write().unwrap();
+    let cleared = std::mem::take(&mut storage.ai_history).len();
     storage.save()?;
-    Ok(merged)
+    Ok(cleared)
 }
 
-/// Get all content as single string
-#[tauri::command]
-fn get_all_content(state: tauri::State<AppState>) -> String {
-    let storage = state.storage.lock().unwrap();
-    storage.get_all_content()
+/// Longest clip text sent to the AI when asking for a title
+const TITLE_PROMPT_CHARS: usize = 4000;
+const MAX_TITLE_CHARS: usize = 80;
+
+fn queue_limits(settings: &Settings) -> QueueLimits {
+    QueueLimits {
+        max_concurrency: settings.ai_max_concurrency,
+        min_delay: std::time::Duration::from_millis(settings.ai_min_delay_ms),
+    }
 }
 
-/// Copy all content to clipboard
+/// Queue AI title generation for every untitled clip in the active pastebook
 #[tauri::command]
-fn copy_all_to_clipboard(app: AppHandle, state: tauri::State<AppState>) -> Result<(), String> {
-    let storage = state.storage.lock().unwrap();
-    let content = storage.get_all_content();
-    
-    app.clipboard()
-        .write_text(content)
-        .map_err(|e| format!("Failed to write to clipboard: {}", e))?;
-    
-    Ok(())
+fn generate_missing_titles(app: AppHandle, state: tauri::State<AppState>) -> Result<usize, String> {
+    let _timer = state.metrics.time("generate_missing_titles");
+    safe_mode::check_ai()?;
+    let storage = storage::loaded(&state.storage);
+    let api_key = api_key(&storage).ok_or("API Key not found")?;
+    check_batch_budget(&storage)?;
+    let models = storage.settings.model_fallbacks.clone();
+    let limits = queue_limits(&storage.settings);
+    let untitled: Vec<(String, String, bool)> = storage
+        .get_clips()
+        .into_iter()
+        .filter(|c| c.title.is_none())
+        .map(|c| (c.id, c.content, c.sensitive))
+        .collect();
+    drop(storage);
+
+    let client = GeminiClient::new(api_key);
+    for (id, content, sensitive) in &untitled {
+        let excerpt = textutil::truncate_sentences(content, TITLE_PROMPT_CHARS);
+        let prompt = format!(
+            "Write a short title (at most 8 words) for the following text. \
+            Reply with the title only, no quotes.\n\n{}",
+            excerpt
+        );
+
+        let label = format!("title {}", id);
+        let (app_handle, client, models, id, sensitive) =
+            (app.clone(), client.clone(), models.clone(), id.clone(), *sensitive);
+        let job: ai_queue::JobFn = std::sync::Arc::new(move || {
+            let (app, client, models, id, prompt) =
+                (app_handle.clone(), client.clone(), models.clone(), id.clone(), prompt.clone());
+            Box::pin(async move {
+                // Queued jobs stop too once the budget runs out
+                check_batch_budget(&app.state::<AppState>().storage.read().unwrap())?;
+                let reply = client.chat_with_fallback(&models, &prompt).await?;
+                let request = AiRequest {
+                    instruction: "Write a short title",
+                    prompt: &prompt,
+                    clip_ids: vec![id.clone()],
+                    sensitive,
+                };
+                record_ai_usage(&app, "generate_missing_titles", request, &reply);
+                let title: String = reply
+                    .text
+                    .lines()
+                    .map(|l| l.trim().trim_matches('"'))
+                    .find(|l| !l.is_empty())
+                    .unwrap_or_default()
+                    .chars()
+                    .take(MAX_TITLE_CHARS)
+                    .collect();
+                if title.is_empty() {
+                    return Err("AI returned an empty title".to_string());
+                }
+
+                let state = app.state::<AppState>();
+                let mut storage = state.storage.write().unwrap();
+                // The clip may have been deleted or titled while queued
+                if let Some(clip) = storage.set_missing_title(&id, title) {
+                    storage.note_chrome();
+                    storage.commit()?;
+                    drop(storage);
+                    emit_clip_updated(&app, &clip);
+                }
+                Ok(())
+            })
+        });
+        state.ai_queue.enqueue(&app, label, limits, job);
+    }
+
+    Ok(untitled.len())
+}
+
+/// Result of drafting a document from clips
+#[derive(serde::Serialize)]
+struct DraftResult {
+    clip: ClipObject,
+    model: String,
+    /// Clips shortened to fit the prompt budget
+    truncated_clips: usize,
+    /// Parts a clip set too big for one prompt was drafted in; 0 if it fit
+    parts: usize,
+    /// Clips left out because their part failed
+    omitted_clips: usize,
+}
+
+/// Payload of the `draft-chunk` event
+#[derive(Clone, serde::Serialize)]
+struct DraftChunk<'a> {
+    stream_id: &'a str,
+    text: &'a str,
 }
 
-/// Clear all clips in active pastebook
+/// Weave clips (the given ids, or the whole active pastebook) into one
+/// document in the given style, saved as a new clip citing its sources
 #[tauri::command]
-fn clear_all_clips(state: tauri::State<AppState>) -> Result<(), String> {
-    let mut storage = state.storage.lock().unwrap();
-    storage.clear_clips();
-    storage.save()?;
-    Ok(())
+async fn draft_document(
+    app: AppHandle,
+    ids: Option<Vec<String>>,
+    style: String,
+    stream_id: Option<String>,
+    state: tauri::State<'_, AppState>,
+) -> Result<DraftResult, String> {
+    let mut timer = state.metrics.time("draft_document");
+    safe_mode::check_ai()?;
+    let style = style.trim().to_string();
+    if style.is_empty() {
+        return Err("Style is empty".to_string());
+    }
+
+    let (api_key, models, sources) = {
+        let storage = storage::loaded(&state.storage);
+        let api_key = api_key(&storage).ok_or("API Key not found")?;
+        let sources: Vec<ClipObject> = storage
+            .scoped_clips(ids.as_deref())?
+            .into_iter()
+            .cloned()
+            .collect();
+        (api_key, storage.settings.model_fallbacks.clone(), sources)
+    };
+    if sources.is_empty() {
+        return Err("No clips to draft from".to_string());
+    }
+
+    let contents: Vec<String> = sources.iter().map(|c| c.content.clone()).collect();
+    timer.payload(contents.iter().map(String::len).sum(), contents.len());
+    let client = GeminiClient::new(api_key);
+    let total_chars: usize = contents.iter().map(|c| textutil::grapheme_count(c)).sum();
+    let (prompt, chunked) = if total_chars > ai::PROMPT_BUDGET_CHARS {
+        let chunked = draft_in_parts(&app, &client, &models, &sources, &style).await?;
+        let fit = ai::fit_to_budget(&chunked.notes, ai::PROMPT_BUDGET_CHARS);
+        (GeminiClient::combine_prompt(&fit.parts, &style), chunked)
+    } else {
+        let fit = ai::fit_to_budget(&contents, ai::PROMPT_BUDGET_CHARS);
+        let chunked = DraftParts {
+            truncated: fit.truncated,
+            ..Default::default()
+        };
+        (GeminiClient::draft_prompt(&fit.parts, &style), chunked)
+    };
+    let used: Vec<&ClipObject> = sources.iter().filter(|c| !chunked.omitted.contains(&c.id)).collect();
+
+    let reply = match &stream_id {
+        Some(stream_id) => {
+            client
+                .chat_stream_with_fallback(&models, &prompt, |text| {
+                    let _ = app.emit("draft-chunk", DraftChunk { stream_id, text });
+                })
+                .await
+        }
+        None => client.chat_with_fallback(&models, &prompt).await,
+    };
+    // The combining step is the last one, whether or not it worked
+    if chunked.parts > 0 {
+        let total = chunked.parts + 1;
+        let _ = app.emit("ai-progress", AiProgress { done: total, total });
+    }
+    let reply = reply?;
+    let request = AiRequest {
+        instruction: &style,
+        prompt: &prompt,
+        clip_ids: used.iter().map(|c| c.id.clone()).collect(),
+        sensitive: used.iter().any(|c| c.sensitive),
+    };
+    record_ai_usage(&app, "draft_document", request, &reply);
+
+    let window_info = WindowInfo {
+        app_name: "Stack".to_string(),
+        window_title: "AI draft".to_string(),
+        ..Default::default()
+    };
+    let mut clip = ClipObject::new(reply.text.trim().to_string(), window_info);
+    clip.title = Some(format!("Draft ({})", style));
+    clip.sensitive = sources.iter().any(|c| c.sensitive);
+    clip.provenance = Some(storage::Provenance {
+        operation: "draft_document".to_string(),
+        source_ids: used.iter().map(|c| c.id.clone()).collect(),
+        model: Some(reply.model.clone()),
+        detail: Some(style),
+        omitted_ids: chunked.omitted.clone(),
+    });
+
+    {
+        let mut storage = state.storage.write().unwrap();
+        storage.add_clip(clip.clone())?;
+        storage.note_chrome();
+        storage.commit()?;
+    }
+    emit_clip_captured(&app, &clip);
+
+    Ok(DraftResult {
+        clip,
+        model: reply.model,
+        truncated_clips: chunked.truncated,
+        parts: chunked.parts,
+        omitted_clips: chunked.omitted.len(),
+    })
 }
 
-// ==================== PASTEBOOK COMMANDS ====================
+/// Tries per part of a chunked draft: the first and one retry
+const DRAFT_PART_ATTEMPTS: u32 = 2;
 
-/// Get list of all pastebooks
+/// Payload of the `ai-progress` event: prompts finished out of all a
+/// chunked draft needs, the combining one included
+#[derive(Clone, serde::Serialize)]
+struct AiProgress {
+    done: usize,
+    total: usize,
+}
+
+/// Notes from drafting a clip set part by part
+#[derive(Default)]
+struct DraftParts {
+    /// Notes from the parts that succeeded, in order
+    notes: Vec<String>,
+    /// How many parts the clips were split into; 0 when drafted in one go
+    parts: usize,
+    truncated: usize,
+    /// Clips in parts that failed even after a retry
+    omitted: Vec<String>,
+}
+
+/// Draft notes on clips too big for one prompt: split them into parts under
+/// the chunk budget and ask for notes on each through the AI queue, so parts
+/// run concurrently within its limits. A part that fails twice is left out.
+async fn draft_in_parts(
+    app: &AppHandle,
+    client: &GeminiClient,
+    models: &[String],
+    sources: &[ClipObject],
+    style: &str,
+) -> Result<DraftParts, String> {
+    let state = app.state::<AppState>();
+    let (chunk_budget, max_chunks, limits) = {
+        let storage = storage::loaded(&state.storage);
+        check_batch_budget(&storage)?;
+        let settings = &storage.settings;
+        // A part's notes can't be given more room than one prompt has
+        let chunk_budget = settings.ai_chunk_budget_chars.min(ai::PROMPT_BUDGET_CHARS);
+        (chunk_budget, settings.ai_max_chunks, queue_limits(settings))
+    };
+    let contents: Vec<String> = sources.iter().map(|c| c.content.clone()).collect();
+    let lengths: Vec<usize> = contents.iter().map(|c| textutil::grapheme_count(c)).collect();
+    let ranges = ai::chunk_ranges(&lengths, chunk_budget);
+    if ranges.len() > max_chunks {
+        return Err(format!(
+            "Validation: these clips need {} parts, more than the limit of {}; draft from fewer clips or raise the limit",
+            ranges.len(),
+            max_chunks
+        ));
+    }
+
+    let total = ranges.len() + 1;
+    let _ = app.emit("ai-progress", AiProgress { done: 0, total });
+    let notes = std::sync::Arc::new(Mutex::new(vec![None::<String>; ranges.len()]));
+    let mut truncated = 0;
+    let mut waiting = tokio::task::JoinSet::new();
+    for (i, range) in ranges.iter().enumerate() {
+        let fit = ai::fit_to_budget(&contents[range.clone()], chunk_budget);
+        truncated += fit.truncated;
+        let prompt = GeminiClient::chunk_prompt(&fit.parts, range.start, style);
+        let part = &sources[range.clone()];
+        let clip_ids: Vec<String> = part.iter().map(|c| c.id.clone()).collect();
+        let sensitive = part.iter().any(|c| c.sensitive);
+
+        let (app_handle, client, models, notes, style) =
+            (app.clone(), client.clone(), models.to_vec(), notes.clone(), style.to_string());
+        let job: ai_queue::JobFn = std::sync::Arc::new(move || {
+            let (app, client, models, notes, prompt, clip_ids, style) = (
+                app_handle.clone(),
+                client.clone(),
+                models.clone(),
+                notes.clone(),
+                prompt.clone(),
+                clip_ids.clone(),
+                style.clone(),
+            );
+            Box::pin(async move {
+                check_batch_budget(&app.state::<AppState>().storage.read().unwrap())?;
+                let reply = client.chat_with_fallback(&models, &prompt).await?;
+                let request = AiRequest {
+                    instruction: &style,
+                    prompt: &prompt,
+                    clip_ids,
+                    sensitive,
+                };
+                record_ai_usage(&app, "draft_document", request, &reply);
+                notes.lock().unwrap()[i] = Some(reply.text);
+                Ok(())
+            })
+        });
+        let label = format!("draft part {} of {}", i + 1, ranges.len());
+        let done = state.ai_queue.submit(app, label, limits, DRAFT_PART_ATTEMPTS, job);
+        waiting.spawn(async move { (i, done.await) });
+    }
+
+    let mut failed = vec![None; ranges.len()];
+    let mut done = 0;
+    while let Some(finished) = waiting.join_next().await {
+        let (i, outcome) = finished.map_err(|e| e.to_string())?;
+        if let Err(e) = outcome.unwrap_or_else(|_| Err("Cancelled".to_string())) {
+            failed[i] = Some(e);
+        }
+        done += 1;
+        let _ = app.emit("ai-progress", AiProgress { done, total });
+    }
+
+    let notes: Vec<String> = std::mem::take(&mut *notes.lock().unwrap()).into_iter().flatten().collect();
+    if notes.is_empty() {
+        let _ = app.emit("ai-progress", AiProgress { done: total, total });
+        let error = failed.into_iter().flatten().next().unwrap_or_default();
+        return Err(format!("Every part of the draft failed: {}", error));
+    }
+    let omitted = ranges
+        .iter()
+        .zip(&failed)
+        .filter(|(_, error)| error.is_some())
+        .flat_map(|(range, _)| sources[range.clone()].iter().map(|c| c.id.clone()))
+        .collect();
+    Ok(DraftParts {
+        notes,
+        parts: ranges.len(),
+        truncated,
+        omitted,
+    })
+}
+
+/// Result of evaluating a clip (or text) as arithmetic
+#[derive(serde::Serialize)]
+struct ExpressionResult {
+    #[serde(flatten)]
+    evaluation: calc::Evaluation,
+    /// The "<expression> = <result>" clip, when one was asked for
+    clip: Option<ClipObject>,
+}
+
+/// Evaluate a clip's content (or, if no clip has that id, the text itself)
+/// as arithmetic, optionally saving "<expression> = <result>" as a new clip
 #[tauri::command]
-fn list_pastebooks(state: tauri::State<AppState>) -> Vec<(String, String, usize)> {
-    let storage = state.storage.lock().unwrap();
-    storage.list_pastebooks()
+fn evaluate_expression(
+    app: AppHandle,
+    id_or_text: String,
+    create_clip: Option<bool>,
+    decimal_separator: Option<DecimalSeparator>,
+    state: tauri::State<AppState>,
+) -> Result<ExpressionResult, String> {
+    let _timer = state.metrics.time("evaluate_expression");
+    let mut storage = storage::loaded_mut(&state.storage);
+    let source = storage.find_clip(&id_or_text).cloned();
+    let text = source.as_ref().map_or(id_or_text.as_str(), |c| c.content.as_str());
+    let separator = decimal_separator.unwrap_or(storage.settings.decimal_separator);
+    let evaluation = calc::evaluate(text, separator)?;
+
+    if !create_clip.unwrap_or(false) {
+        return Ok(ExpressionResult { evaluation, clip: None });
+    }
+
+    let window_info = WindowInfo {
+        app_name: "Stack".to_string(),
+        window_title: "Calculation".to_string(),
+        ..Default::default()
+    };
+    let content = format!("{} = {}", evaluation.expression, evaluation.formatted);
+    let mut clip = ClipObject::new(content, window_info);
+    if let Some(source) = &source {
+        clip.sensitive = source.sensitive;
+        clip.provenance = Some(storage::Provenance {
+            operation: "evaluate_expression".to_string(),
+            source_ids: vec![source.id.clone()],
+            model: None,
+            detail: None,
+            omitted_ids: Vec::new(),
+        });
+    }
+    storage.add_clip(clip.clone())?;
+    storage.note_chrome();
+    storage.commit()?;
+    drop(storage);
+    emit_clip_captured(&app, &clip);
+
+    Ok(ExpressionResult { evaluation, clip: Some(clip) })
 }
 
-/// Get active pastebook info
+/// Get counts of queued, running, completed and failed AI jobs
 #[tauri::command]
-fn get_active_pastebook(state: tauri::State<AppState>) -> Option<Pastebook> {
-    let storage = state.storage.lock().unwrap();
-    storage.get_active_pastebook().cloned()
+fn get_ai_queue_status(state: tauri::State<AppState>) -> AiQueueStatus {
+    state.ai_queue.status()
 }
 
-/// Create a new pastebook
+/// Cancel all queued AI jobs, returning how many were pending
 #[tauri::command]
-fn create_pastebook(name: String, state: tauri::State<AppState>) -> Result<Pastebook, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let pastebook = storage.create_pastebook(name);
-    storage.save()?;
-    Ok(pastebook)
+fn cancel_ai_queue(state: tauri::State<AppState>) -> usize {
+    state.ai_queue.cancel()
 }
 
-/// Switch to a pastebook
+/// Re-queue AI jobs that failed after all retries
 #[tauri::command]
-fn switch_pastebook(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let switched = storage.switch_pastebook(id);
-    storage.save()?;
-    Ok(switched)
+fn retry_failed_ai_jobs(app: AppHandle, state: tauri::State<AppState>) -> usize {
+    let limits = queue_limits(&state.settings());
+    state.ai_queue.retry_failed(&app, limits)
 }
 
-/// Delete a pastebook
+/// Release Ctrl/Shift keys the system believes are held, returning which were released
 #[tauri::command]
-fn delete_pastebook(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let deleted = storage.delete_pastebook(&id);
-    storage.save()?;
-    Ok(deleted)
+fn fix_stuck_modifiers() -> Vec<&'static str> {
+    input::fix_stuck_modifiers()
 }
 
-/// Rename a pastebook
+/// Get the last window the user was in before switching to Stack
 #[tauri::command]
-fn rename_pastebook(id: String, name: String, state: tauri::State<AppState>) -> Result<bool, String> {
-    let mut storage = state.storage.lock().unwrap();
-    let renamed = storage.rename_pastebook(&id, name);
-    storage.save()?;
-    Ok(renamed)
+fn get_last_foreground() -> Option<ForegroundRecord> {
+    window::get_last_foreground()
 }
 
-// ==================== APP SETUP ====================
+/// Emit an event to every window and POST it to the webhooks that want it
+fn broadcast<S: serde::Serialize + Clone>(app: &AppHandle, event: &'static str, payload: S) {
+    webhooks::dispatch(event, &payload);
+    let _ = app.emit(event, payload);
+}
 
-#[cfg_attr(mobile, tauri::mobile_entry_point)]
-pub fn run() {
-    tauri::Builder::default()
-        .plugin(tauri_plugin_opener::init())
-        .plugin(tauri_plugin_clipboard_manager::init())
-        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
-        .manage(AppState {
-            storage: Mutex::new(AppStorage::load()),
-        })
-        .invoke_handler(tauri::generate_handler![
-            greet,
-            set_api_key,
-            get_models,
-            magic_sort,
-            chat_submit,
-            get_clips,
-            capture_clip,
-            delete_clip,
-            update_clip,
-            reorder_clips,
-            merge_clips,
-            copy_all_to_clipboard,
-            clear_all_clips,
-            list_pastebooks,
-            get_active_pastebook,
-            create_pastebook,
-            switch_pastebook,
-            delete_pastebook,
-            rename_pastebook
-        ])
-        .setup(|app| {
-            // Register global hotkey (Ctrl+Shift+C)
-            let shortcut = Shortcut::new(Some(Modifiers::CONTROL | Modifiers::SHIFT), Code::KeyC);
-            
-            let app_handle = app.handle().clone();
-            app.global_shortcut().on_shortcut(shortcut, move |_app, _shortcut, _event| {
-                // 1. Simulate Ctrl+C to copy selected text
-                input::simulate_copy();
-                
-                // 2. Wait for clipboard to update (100ms)
-                std::thread::sleep(std::time::Duration::from_millis(100));
-
-                // 3. Perform capture
-                let clipboard_content = app_handle.clipboard().read_text().unwrap_or_default();
-                
-                if clipboard_content.trim().is_empty() {
-                    return;
-                }
-                
-                // Get active window info
-                let window_info = get_active_window_info();
-                
-                // Create clip
-                let clip = ClipObject::new(clipboard_content, window_info);
-                
-                // Save to storage
-                let state = app_handle.state::<AppState>();
-                let mut storage = state.storage.lock().unwrap();
-                
-                // Deduplication: Check if the last clip is identical and created recently (< 2000ms) -- increased to 2s to be safe against user holding keys
-                if let Some(pastebook) = storage.get_active_pastebook() {
-                    if let Some(last_clip) = pastebook.clips.first() {
-                        if last_clip.content == clip.content {
-                            let time_diff = clip.metadata.timestamp.signed_duration_since(last_clip.metadata.timestamp);
-                            if time_diff.num_milliseconds() < 2000 {
-                                println!("Ignoring duplicate capture");
-                                return;
-                            }
-                        }
-                    }
-                }
-                
-                storage.add_clip(clip.clone());
-                let _ = storage.save();
-                
-                // Emit the new clip to the window
-                let _ = app_handle.emit("clip-captured", clip);
-            })?;
+/// The `clip-captured` payload, with what a screen reader should say, the
+/// pastebook the clip is in and where the clip it duplicates is
+fn captured_with_announcement(app: &AppHandle, clip: &ClipObject) -> CapturedClip {
+    let message = match clip.sensitive {
+        true => Message::CapturedSensitive,
+        false => Message::Captured {
+            chars: textutil::grapheme_count(&clip.content),
+            app: &clip.metadata.source_app,
+        },
+    };
+    let state = app.state::<AppState>();
+    let storage = state.storage.read().unwrap();
+    let duplicate_of = match clip.metadata.duplicate_of.is_some() && !clip.sensitive {
+        true => storage.duplicate_match(clip),
+        false => None,
+    };
+    CapturedClip {
+        announcement: Some(announce::text(message)),
+        pastebook_id: storage.pastebook_of_clip(&clip.id).map(str::to_string),
+        duplicate_of,
+        ..CapturedClip::from(clip)
+    }
+}
 
-            Ok(())
-        })
-        .run(tauri::generate_context!())
-        .expect("error while running tauri application");
+/// Tell every window about a new clip, without broadcasting its content
+fn emit_clip_captured(app: &AppHandle, clip: &ClipObject) {
+    broadcast(app, "clip-captured", captured_with_announcement(app, clip));
+    notify::send(app, NotificationKind::Capture, 1, "success", "Clip captured!".to_string());
+}
+
+/// Tell every window a clip changed. Like `clip-captured`, only the
+/// preview and metadata go out (nothing for a sensitive clip); windows
+/// fetch the clip if they show it.
+fn emit_clip_updated(app: &AppHandle, clip: &ClipObject) {
+    broadcast(app, "clip-updated", CapturedClip::from(clip));
+}
+
+/// Payload of `clip-revealed`: the windows switch to the pastebook and
+/// bring the clip into view
+#[derive(Clone, serde::Serialize)]
+struct RevealedClip<'a> {
+    clip_id: &'a str,
+    pastebook_id: Option<String>,
+    /// Name of the pastebook a reused capture switched to, so the UI can
+    /// say why the view changed
+    #[serde(skip_serializing_if = "Option::is_none")]
+    switched_to: Option<String>,
+    revision: u64,
+}
+
+/// Tell the windows to show clip `id`, whose pastebook is now the active one
+fn emit_clip_revealed(app: &AppHandle, id: &str, switched_to: Option<String>) {
+    let revealed = {
+        let state = app.state::<AppState>();
+        let storage = state.storage.read().unwrap();
+        RevealedClip {
+            clip_id: id,
+            pastebook_id: storage.active_pastebook_id.clone(),
+            switched_to,
+            revision: storage.revision,
+        }
+    };
+    let _ = app.emit("clip-revealed", revealed);
+}
+
+/// Switch to the pastebook holding a clip and bring the main window up with
+/// the clip in view, e.g. from "also in Research" after a capture
+#[tauri::command]
+fn reveal_clip(
+    id: String,
+    expected_revision: Option<u64>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<String>, String> {
+    let _timer = state.metrics.time("reveal_clip");
+    let (revision, pastebook_id) = {
+        let mut storage = state.storage.write().unwrap();
+        storage.check_revision(expected_revision)?;
+        let pastebook_id = storage
+            .reveal_clip(&id)
+            .ok_or_else(|| format!("NotFound: clip {}", id))?;
+        storage.note_chrome();
+        (storage.commit()?, pastebook_id)
+    };
+    if let Some(window) = app.get_webview_window("main") {
+        let _ = window.unminimize();
+        let _ = window.show();
+        let _ = window.set_focus();
+    }
+    emit_clip_revealed(&app, &id, None);
+    Ok(Revisioned { revision, data: pastebook_id })
+}
+
+/// Get a single clip (from any pastebook) with its full content
+#[tauri::command]
+fn get_clip(id: String, state: tauri::State<AppState>) -> Result<ClipObject, String> {
+    let _timer = state.metrics.time("get_clip");
+    let storage = state.storage.read().unwrap();
+    let mut clip = storage
+        .find_clip(&id)
+        .cloned()
+        .ok_or_else(|| format!("NotFound: clip {}", id))?;
+    clip.content = storage.clip_content(&id).unwrap_or(clip.content);
+    Ok(clip)
+}
+
+/// Names of the formats on the clipboard right now, to compare with a
+/// clip's `available_formats` when a capture looks wrong
+#[tauri::command]
+fn get_clipboard_formats(state: tauri::State<AppState>) -> Result<Vec<String>, String> {
+    let _timer = state.metrics.time("get_clipboard_formats");
+    clipboard_formats::current()
+}
+
+/// Get just a clip's full content (from any pastebook)
+#[tauri::command]
+fn get_clip_content(id: String, state: tauri::State<AppState>) -> Result<String, String> {
+    let _timer = state.metrics.time("get_clip_content");
+    let storage = state.storage.read().unwrap();
+    storage.clip_content(&id).ok_or_else(|| format!("NotFound: clip {}", id))
+}
+
+/// Get all clips from active pastebook
+#[tauri::command]
+fn get_clips(state: tauri::State<AppState>) -> Revisioned<Vec<ClipObject>> {
+    let mut timer = state.metrics.time("get_clips");
+    let storage = storage::loaded(&state.storage);
+    let clips = storage.get_clips();
+    timer.payload(clips.iter().map(|c| c.content.len()).sum(), clips.len());
+    storage.revisioned(clips)
+}
+
+/// Search a pastebook's clips (the active one by default): content, title,
+/// source app and window title, most relevant first. `virtual_desktop` and `monitor` narrow it
+/// to clips captured there.
+#[tauri::command]
+fn search_clips(
+    query: String,
+    pastebook_id: Option<String>,
+    virtual_desktop: Option<String>,
+    monitor: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<ClipObject>>, String> {
+    let mut timer = state.metrics.time("search_clips");
+    let storage = storage::loaded(&state.storage);
+    let id = &storage.resolve_pastebook(pastebook_id.as_deref())?.id;
+    let clips: Vec<ClipObject> = storage
+        .search_pastebook(id, &query)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|c| c.metadata.in_workspace(virtual_desktop.as_deref(), monitor.as_deref()))
+        .cloned()
+        .collect();
+    timer.payload(clips.iter().map(|c| c.content.len()).sum(), clips.len());
+    Ok(storage.revisioned(clips))
+}
+
+/// Re-index every clip for search, returning the new index sizes
+#[tauri::command]
+fn rebuild_search_index(state: tauri::State<AppState>) -> SearchIndexStats {
+    let _timer = state.metrics.time("rebuild_search_index");
+    // Built under a read guard so captures only wait for the swap
+    let (index, built_at) = {
+        let storage = state.storage.read().unwrap();
+        (storage.build_search_index(), storage.revision)
+    };
+    state.storage.write().unwrap().install_search_index(index, built_at)
+}
+
+/// Capture current clipboard with metadata
+#[tauri::command]
+fn capture_clip(
+    app: AppHandle,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<ClipObject>, String> {
+    let _timer = state.metrics.time("capture_clip");
+    if capture_blocked(&app, &state.settings()) {
+        return Err("Capture is paused".to_string());
+    }
+
+    let content = app
+        .clipboard()
+        .read_text()
+        .unwrap_or_default();
+
+    if content.trim().is_empty() {
+        return Err("Clipboard is empty".to_string());
+    }
+    let content = sanitize_capture(&app, &content)?;
+
+    // Stack has focus when this is invoked, so use the last app the user was in
+    let window_info = capture_window_info();
+    let clip = ClipObject::new(content, window_info);
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let outcome = storage.add_captured_clip(clip);
+    let title_routed = storage.take_title_routed();
+    let (clip, bumped, reused) = match outcome {
+        CaptureOutcome::Added(clip) => (clip, false, None),
+        CaptureOutcome::Bumped(clip) => (clip, true, None),
+        CaptureOutcome::Reused(clip, switched_to) => (clip, false, Some(switched_to)),
+        CaptureOutcome::Ignored => return Err("Duplicate of a recent clip".to_string()),
+        CaptureOutcome::Skipped(rule) => return Err(format!("Skipped by rule '{}'", rule)),
+        CaptureOutcome::NoPastebook(_) => return Err(storage::NO_ACTIVE_PASTEBOOK.to_string()),
+    };
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    drop(storage);
+
+    if let Some(routed) = title_routed {
+        broadcast(&app, "pastebook-title-routed", routed);
+    }
+    if bumped {
+        emit_clip_updated(&app, &clip);
+    }
+    // The reveal reloads the windows, so the bumped clip needs no update
+    if let Some(switched_to) = reused {
+        emit_clip_revealed(&app, &clip.id, switched_to);
+    }
+
+    Ok(Revisioned { revision, data: clip })
+}
+
+/// Create a clip from text typed into Stack (manual note)
+#[tauri::command]
+fn create_clip(
+    app: AppHandle,
+    content: String,
+    pastebook_id: Option<String>,
+    title: Option<String>,
+    tags: Option<Vec<String>>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<ClipObject>, String> {
+    let _timer = state.metrics.time("create_clip");
+    storage::validate_content(&content, false)?;
+    let content = ingest::sanitize_text(&content).map_err(|reason| reason.message().to_string())?;
+
+    let window_info = WindowInfo {
+        app_name: "Stack".to_string(),
+        window_title: "Manual entry".to_string(),
+        ..Default::default()
+    };
+    let mut clip = ClipObject::new(content, window_info);
+    clip.title = title
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty());
+    clip.tags = normalize_tags(tags.unwrap_or_default());
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    match pastebook_id {
+        Some(id) => {
+            if !storage.add_clip_to_pastebook(&id, clip.clone()) {
+                return Err("Pastebook not found".to_string());
+            }
+        }
+        None => storage.add_clip(clip.clone())?,
+    }
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    drop(storage);
+
+    emit_clip_captured(&app, &clip);
+
+    Ok(Revisioned { revision, data: clip })
+}
+
+/// Save text typed into the quick note prompt as a clip in the active
+/// pastebook, then close the prompt. The window title is that of the app
+/// the user was in when they opened it.
+#[tauri::command]
+fn quick_note(
+    app: AppHandle,
+    content: String,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<ClipObject>, String> {
+    let _timer = state.metrics.time("quick_note");
+    storage::validate_content(&content, false)?;
+    let content = ingest::sanitize_text(&content).map_err(|reason| reason.message().to_string())?;
+
+    let window_info = WindowInfo {
+        app_name: quick_note::SOURCE_APP.to_string(),
+        window_title: window::get_last_foreground()
+            .map(|record| record.info.window_title)
+            .unwrap_or_default(),
+        ..Default::default()
+    };
+    let clip = ClipObject::new(content, window_info);
+
+    let mut storage = state.storage.write().unwrap();
+    storage.add_clip(clip.clone())?;
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    drop(storage);
+
+    quick_note::dismiss(&app);
+    emit_clip_captured(&app, &clip);
+    Ok(Revisioned { revision, data: clip })
+}
+
+/// Close the quick note prompt without saving
+#[tauri::command]
+fn dismiss_quick_note(app: AppHandle) {
+    quick_note::dismiss(&app);
+}
+
+/// Delete a clip from whichever pastebook holds it; `pastebook_id` is only
+/// a hint. The data is the pastebook it was in, null if there was no such clip.
+#[tauri::command]
+fn delete_clip(
+    id: String,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<String>>, String> {
+    let _timer = state.metrics.time("delete_clip");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let deleted = storage.delete_clip(&id, pastebook_id.as_deref());
+    storage.note_chrome();
+    let revision = storage.commit_if(deleted.is_some())?;
+    Ok(Revisioned { revision, data: deleted })
+}
+
+/// Update a clip's content in any pastebook; emptying it needs `allow_empty`.
+/// The data is the pastebook it's in, null if there's no such clip.
+#[tauri::command]
+fn update_clip(
+    id: String,
+    content: String,
+    allow_empty: Option<bool>,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<String>>, String> {
+    let _timer = state.metrics.time("update_clip");
+    // Diff for the history on a copy, without holding the lock
+    let current = storage::loaded(&state.storage).find_clip(&id).cloned();
+    let edit = current.map(|clip| clip.plan_content(content, "manual", Utc::now()));
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let updated = match edit {
+        Some(edit) => storage.edit_clip_content(&id, edit, allow_empty.unwrap_or(false), pastebook_id.as_deref())?,
+        None => None,
+    };
+    storage.note_chrome();
+    let revision = storage.commit_if(updated.is_some())?;
+    Ok(Revisioned { revision, data: updated })
+}
+
+/// A clip's earlier contents (any pastebook), newest first
+#[tauri::command]
+fn get_clip_revisions(id: String, state: tauri::State<AppState>) -> Result<Vec<storage::ClipRevisionView>, String> {
+    let _timer = state.metrics.time("get_clip_revisions");
+    state.storage.read().unwrap().clip_revisions(&id)
+}
+
+/// Put back a clip's content from one of its revisions
+#[tauri::command]
+fn revert_clip(
+    app: AppHandle,
+    id: String,
+    revision: usize,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<ClipObject>, String> {
+    let _timer = state.metrics.time("revert_clip");
+    let current = storage::loaded(&state.storage)
+        .find_clip(&id)
+        .cloned()
+        .ok_or_else(|| format!("NotFound: clip {}", id))?;
+    let edit = current.plan_revert(revision, Utc::now())?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let clip = storage.revert_clip(&id, edit)?;
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    drop(storage);
+
+    emit_clip_updated(&app, &clip);
+    Ok(Revisioned { revision, data: clip })
+}
+
+/// Correct a clip's source app, window title or capture time; None keeps
+/// each as it is
+#[tauri::command]
+fn update_clip_metadata(
+    app: AppHandle,
+    id: String,
+    source_app: Option<String>,
+    window_title: Option<String>,
+    timestamp: Option<DateTime<Utc>>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<ClipObject>, String> {
+    let _timer = state.metrics.time("update_clip_metadata");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let clip = storage.update_clip_metadata(&id, source_app, window_title, timestamp, Utc::now())?;
+    let revision = storage.commit()?;
+    drop(storage);
+
+    emit_clip_updated(&app, &clip);
+    Ok(Revisioned { revision, data: clip })
+}
+
+/// Set or clear a clip's color label; the data is the pastebook it's in
+#[tauri::command]
+fn set_clip_label(
+    id: String,
+    label: Option<String>,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<String>>, String> {
+    let _timer = state.metrics.time("set_clip_label");
+    let label = storage::validate_label(label)?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let updated = storage.set_clip_label(&id, label, pastebook_id.as_deref());
+    let revision = storage.commit_if(updated.is_some())?;
+    Ok(Revisioned { revision, data: updated })
+}
+
+/// Move a clip to its pastebook's trash, or restore it; trashed clips are
+/// left out of lists, counts and search and purged after
+/// `TRASH_RETENTION_DAYS`. Returns the pastebook it's in, or None if it
+/// was already there.
+#[tauri::command]
+fn set_clip_trashed(
+    id: String,
+    trashed: bool,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<String>>, String> {
+    let _timer = state.metrics.time("set_clip_trashed");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let updated = storage.set_clip_trashed(&id, trashed, pastebook_id.as_deref(), Utc::now())?;
+    if updated.is_some() {
+        storage.note_chrome();
+    }
+    let revision = storage.commit_if(updated.is_some())?;
+    Ok(Revisioned { revision, data: updated })
+}
+
+/// Payload of the `storage-changed` event
+#[derive(Clone, serde::Serialize)]
+struct StorageChanged {
+    revision: u64,
+    /// The pastebook the changed clips are in
+    pastebook_id: Option<String>,
+    ids: Vec<String>,
+}
+
+/// Apply one patch (tags, label, status, pinned) to many clips with a single
+/// save, reporting what happened to each id
+#[tauri::command]
+fn bulk_update_clips(
+    app: AppHandle,
+    ids: Vec<String>,
+    patch: ClipPatch,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<BulkUpdateResult>>, String> {
+    let _timer = state.metrics.time("bulk_update_clips");
+    let patch = patch.validate()?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let results = storage.bulk_update_clips(&ids, &patch);
+
+    let updated: Vec<String> = results
+        .iter()
+        .filter(|r| r.outcome == BulkOutcome::Updated)
+        .map(|r| r.id.clone())
+        .collect();
+    if updated.is_empty() {
+        return Ok(storage.revisioned(results));
+    }
+    let pastebook_id = storage.active_pastebook_id.clone();
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    broadcast(&app, "storage-changed", StorageChanged { revision, pastebook_id, ids: updated });
+    Ok(Revisioned { revision, data: results })
+}
+
+/// Set or clear the color label on several clips
+#[tauri::command]
+fn set_label_for(
+    ids: Vec<String>,
+    label: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<usize>, String> {
+    let _timer = state.metrics.time("set_label_for");
+    let label = storage::validate_label(label)?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let updated = storage.set_label_for(&ids, label);
+    let revision = storage.commit_if(updated > 0)?;
+    Ok(Revisioned { revision, data: updated })
+}
+
+/// Flag or unflag a clip as sensitive; the data is the pastebook it's in
+#[tauri::command]
+fn set_clip_sensitive(
+    id: String,
+    sensitive: bool,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<String>>, String> {
+    let _timer = state.metrics.time("set_clip_sensitive");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let updated = storage.set_clip_sensitive(&id, sensitive, pastebook_id.as_deref());
+    storage.note_chrome();
+    let revision = storage.commit_if(updated.is_some())?;
+    Ok(Revisioned { revision, data: updated })
+}
+
+/// Remind the user about a clip at `at`, replacing any earlier reminder
+#[tauri::command]
+fn set_clip_reminder(
+    id: String,
+    at: DateTime<Utc>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<Reminder>>, String> {
+    let _timer = state.metrics.time("set_clip_reminder");
+    set_reminder(&state, &id, Some(at), expected_revision)
+}
+
+/// Move a clip's reminder to `minutes` from now, re-arming it if it already fired
+#[tauri::command]
+fn snooze_reminder(
+    id: String,
+    minutes: u32,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<Reminder>>, String> {
+    let _timer = state.metrics.time("snooze_reminder");
+    if minutes == 0 {
+        return Err("Snooze must be at least a minute".to_string());
+    }
+    let at = Utc::now() + chrono::Duration::minutes(minutes as i64);
+    set_reminder(&state, &id, Some(at), expected_revision)
+}
+
+/// Remove a clip's reminder
+#[tauri::command]
+fn clear_reminder(
+    id: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<Reminder>>, String> {
+    let _timer = state.metrics.time("clear_reminder");
+    set_reminder(&state, &id, None, expected_revision)
+}
+
+fn set_reminder(
+    state: &AppState,
+    id: &str,
+    at: Option<DateTime<Utc>>,
+    expected_revision: Option<u64>,
+) -> Result<Revisioned<Option<Reminder>>, String> {
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let (clip, changed) = storage
+        .set_clip_reminder(id, at)
+        .ok_or_else(|| format!("NotFound: clip {}", id))?;
+    let revision = storage.commit_if(changed)?;
+    Ok(Revisioned { revision, data: clip.reminder })
+}
+
+/// Fire every reminder that has come due: mark it fired, save, and tell the UI
+fn fire_due_reminders(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    let due = {
+        let mut storage = state.storage.write().unwrap();
+        let due = storage.take_due_reminders(Utc::now());
+        if due.is_empty() {
+            return;
+        }
+        if let Err(e) = storage.commit() {
+            eprintln!("Failed to save fired reminders: {}", e);
+        }
+        due
+    };
+    for clip in &due {
+        let _ = app.emit("clip-reminder-due", CapturedClip::from(clip));
+        let text = if clip.sensitive {
+            "a sensitive clip"
+        } else {
+            textutil::truncate(&clip.content, 60)
+        };
+        notify::send(app, NotificationKind::Reminder, 1, "success", format!("Reminder: {}", text));
+    }
+}
+
+/// Whether the notification policy is holding toasts right now; the UI
+/// follows `notifications-held` after this
+#[tauri::command]
+fn get_notifications_held() -> bool {
+    notify::is_holding()
+}
+
+/// Count a toast the UI held back, for the summary once the hold ends
+#[tauri::command]
+fn hold_notification() {
+    notify::hold_other();
+}
+
+/// Clips captured on a local calendar day, bucketed by hour, as previews only.
+/// Without a pastebook id every pastebook is included.
+#[tauri::command]
+fn get_timeline(
+    date: chrono::NaiveDate,
+    pastebook_id: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<Vec<TimelineHour>, String> {
+    let _timer = state.metrics.time("get_timeline");
+    let storage = storage::loaded(&state.storage);
+    storage.timeline(&chrono::Local, date, pastebook_id.as_deref())
+}
+
+/// Get clips with a given color label (None returns unlabeled clips)
+#[tauri::command]
+fn get_clips_by_label(
+    label: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<ClipObject>>, String> {
+    let _timer = state.metrics.time("get_clips_by_label");
+    let label = storage::validate_label(label)?;
+    let storage = storage::loaded(&state.storage);
+    Ok(storage.revisioned(storage.get_clips_by_label(label.as_deref())))
+}
+
+/// Reorder clips
+#[tauri::command]
+fn reorder_clips(
+    ids: Vec<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<String>>, String> {
+    let _timer = state.metrics.time("reorder_clips");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let order = storage.reorder_clips(ids);
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: order })
+}
+
+/// Sort a pastebook (the active one by default); pinned clips keep their positions
+#[tauri::command]
+fn sort_clips(
+    by: SortOrder,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<String>>, String> {
+    let _timer = state.metrics.time("sort_clips");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let id = storage.resolve_pastebook(pastebook_id.as_deref())?.id.clone();
+    let order = storage.sort_pastebook_clips(&id, by).unwrap_or_default();
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: order })
+}
+
+/// Preview the result of merging clips without changing anything
+#[tauri::command]
+fn preview_merge(
+    ids: Vec<String>,
+    separator: Option<String>,
+    order: Option<MergeOrder>,
+    state: tauri::State<AppState>,
+) -> Option<ClipObject> {
+    let _timer = state.metrics.time("preview_merge");
+    let options = MergeOptions {
+        separator,
+        order: order.unwrap_or_default(),
+        keep_sources: false,
+    };
+    let storage = storage::loaded(&state.storage);
+    storage.build_merged_clip(&ids, &options)
+}
+
+/// A merged clip with the byte size of its content
+#[derive(serde::Serialize)]
+struct MergedClip {
+    #[serde(flatten)]
+    clip: ClipObject,
+    bytes: usize,
+    /// The clips merged away, top first
+    source_ids: Vec<String>,
+}
+
+/// Merge multiple clips. A merge over the clipboard size limit is refused
+/// unless `force` is set.
+#[tauri::command]
+fn merge_clips(
+    ids: Vec<String>,
+    separator: Option<String>,
+    order: Option<MergeOrder>,
+    keep_sources: Option<bool>,
+    force: Option<bool>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Option<MergedClip>>, String> {
+    let _timer = state.metrics.time("merge_clips");
+    let options = MergeOptions {
+        separator,
+        order: order.unwrap_or_default(),
+        keep_sources: keep_sources.unwrap_or(false),
+    };
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    if let Some(preview) = storage.build_merged_clip(&ids, &options) {
+        storage::validate_content(&preview.content, false)?;
+        storage
+            .settings
+            .check_clipboard_size(preview.content.len(), force.unwrap_or(false))?;
+    }
+    let source_ids = storage.merge_source_ids(&ids);
+    let merged = storage.merge_clips(ids, &options).map(|clip| MergedClip {
+        bytes: clip.content.len(),
+        clip,
+        source_ids,
+    });
+    storage.note_chrome();
+    let revision = storage.commit_if(merged.is_some())?;
+    Ok(Revisioned { revision, data: merged })
+}
+
+/// Write a clip to a temp file so the webview can drag it out as a file
+#[tauri::command]
+fn materialize_clip_file(
+    id: String,
+    extension_hint: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<String, String> {
+    let _timer = state.metrics.time("materialize_clip_file");
+    let clip = {
+        let storage = storage::loaded(&state.storage);
+        storage.get_clip(&id).cloned().ok_or("Clip not found")?
+    };
+
+    let path = dragout::materialize_clip(&clip, extension_hint.as_deref())?;
+    Ok(path.to_string_lossy().into_owned())
+}
+
+/// How `copy_clip_as_file` put a clip on the clipboard
+#[derive(serde::Serialize)]
+#[serde(tag = "mode", rename_all = "snake_case")]
+enum CopiedAs {
+    /// A temp file, pasted as an attachment
+    File { path: String },
+    /// Plain text, where file references aren't supported
+    Text { warning: String },
+}
+
+/// Write a clip to a temp file and put a reference to it on the clipboard,
+/// so pasting into a chat app attaches the file. Temp files are purged
+/// with the drag-out ones. Sensitive clips need `confirm_sensitive`.
+#[tauri::command]
+fn copy_clip_as_file(
+    id: String,
+    extension_hint: Option<String>,
+    confirm_sensitive: Option<bool>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<CopiedAs, String> {
+    let _timer = state.metrics.time("copy_clip_as_file");
+    let clip = {
+        let storage = storage::loaded(&state.storage);
+        storage.find_clip(&id).cloned().ok_or_else(|| format!("NotFound: clip {}", id))?
+    };
+    if clip.sensitive && !confirm_sensitive.unwrap_or(false) {
+        return Err("Validation: this clip is sensitive; confirm to copy it as a file".to_string());
+    }
+    if !cfg!(windows) {
+        copy_clip_to_clipboard(&app, &state, &id)?;
+        return Ok(CopiedAs::Text {
+            warning: "Copying as a file isn't supported on this platform; copied as text".to_string(),
+        });
+    }
+    let path = dragout::materialize_clip(&clip, extension_hint.as_deref())?;
+    #[cfg(windows)]
+    {
+        let owner = app
+            .get_webview_window("main")
+            .and_then(|window| window.hwnd().ok())
+            .map(|hwnd| windows::Win32::Foundation::HWND(hwnd.0))
+            .unwrap_or_default();
+        dragout::copy_file_to_clipboard(&path, owner)?;
+    }
+    Ok(CopiedAs::File {
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+/// Save one clip as a file at `path`, or when None wherever the user picks
+/// in a save dialog named and typed after the clip. Sensitive clips need
+/// `confirm_sensitive`.
+#[tauri::command]
+async fn export_clip(
+    id: String,
+    path: Option<PathBuf>,
+    confirm_sensitive: Option<bool>,
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<export::ClipExport, String> {
+    let _timer = state.metrics.time("export_clip");
+    let (clip, line_ending) = {
+        let storage = storage::loaded(&state.storage);
+        let clip = storage
+            .find_clip(&id)
+            .cloned()
+            .ok_or_else(|| format!("NotFound: clip {}", id))?;
+        (clip, storage.settings.export_line_ending)
+    };
+    if clip.sensitive && !confirm_sensitive.unwrap_or(false) {
+        return Err("Validation: this clip is sensitive; confirm to save it to a file".to_string());
+    }
+
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let extension = dragout::detect_extension(&clip.content);
+            let dialog = app
+                .dialog()
+                .file()
+                .set_file_name(export::clip_file_name(&clip))
+                .add_filter(extension.to_uppercase(), &[extension]);
+            // The dialog blocks until the user picks, so keep it off the async runtime
+            let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_save_file())
+                .await
+                .map_err(|e| e.to_string())?;
+            match picked {
+                Some(picked) => picked.into_path().map_err(|e| e.to_string())?,
+                None => return Ok(export::ClipExport::Cancelled),
+            }
+        }
+    };
+    export::write_clip(&clip, &path, line_ending)?;
+    Ok(export::ClipExport::Saved {
+        path: path.to_string_lossy().into_owned(),
+    })
+}
+
+/// Store a file in the asset store and reference it from a clip in any pastebook
+#[tauri::command]
+fn attach_clip_asset(
+    id: String,
+    path: String,
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<String>, String> {
+    let mut timer = state.metrics.time("attach_clip_asset");
+    let bytes = std::fs::read(&path).map_err(|e| format!("Failed to read file: {}", e))?;
+    timer.payload(bytes.len(), 1);
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let hash = assets::store_asset(&bytes)?;
+    if storage.attach_asset(&id, hash.clone(), pastebook_id.as_deref()).is_none() {
+        return Err("Clip not found".to_string());
+    }
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: hash })
+}
+
+/// A diff between two clips, plus the clip it was saved as if requested
+#[derive(serde::Serialize)]
+struct ClipDiff {
+    diff: diff::DiffResult,
+    clip: Option<ClipObject>,
+}
+
+/// Diff two clips (from any pastebook) by lines or words
+#[tauri::command]
+fn diff_clips(
+    app: AppHandle,
+    id_a: String,
+    id_b: String,
+    mode: diff::DiffMode,
+    diff_to_clip: Option<bool>,
+    state: tauri::State<AppState>,
+) -> Result<ClipDiff, String> {
+    let mut timer = state.metrics.time("diff_clips");
+    // Diff copies so a large diff doesn't hold up the storage lock
+    let (old, new) = {
+        let storage = storage::loaded(&state.storage);
+        let old = storage
+            .find_clip(&id_a)
+            .cloned()
+            .ok_or_else(|| format!("NotFound: clip {}", id_a))?;
+        let new = storage
+            .find_clip(&id_b)
+            .cloned()
+            .ok_or_else(|| format!("NotFound: clip {}", id_b))?;
+        (old, new)
+    };
+    timer.payload(old.content.len() + new.content.len(), 2);
+
+    let result = diff::diff_texts(&old.content, &new.content, mode);
+
+    let clip = if diff_to_clip.unwrap_or(false) {
+        let name = |clip: &ClipObject| clip.title.clone().unwrap_or_else(|| clip.id.clone());
+        let unified = diff::unified_diff(&old.content, &new.content, &name(&old), &name(&new));
+        let window_info = WindowInfo {
+            app_name: "Stack".to_string(),
+            window_title: "Diff".to_string(),
+            ..Default::default()
+        };
+        let mut clip = ClipObject::new(unified, window_info);
+        clip.title = Some(format!("Diff: {} → {}", name(&old), name(&new)));
+        let mut storage = state.storage.write().unwrap();
+        storage.add_clip(clip.clone())?;
+        storage.commit()?;
+        Some(clip)
+    } else {
+        None
+    };
+
+    if let Some(clip) = &clip {
+        emit_clip_captured(&app, clip);
+    }
+
+    Ok(ClipDiff { diff: result, clip })
+}
+
+/// Find and replace text across clips in the active pastebook (or the given ids)
+#[tauri::command]
+fn find_replace_clips(
+    pattern: String,
+    replacement: String,
+    regex: bool,
+    ids: Option<Vec<String>>,
+    dry_run: bool,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<storage::FindReplaceResult>, String> {
+    let mut timer = state.metrics.time("find_replace_clips");
+    let find = storage::build_find_regex(&pattern, regex)?;
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let result = storage.find_replace_clips(&find, &replacement, regex, ids.as_deref(), dry_run);
+    timer.payload(0, result.clips.len());
+    if result.changed > 0 {
+        storage.note_chrome();
+        storage.commit()?;
+    }
+    Ok(storage.revisioned(result))
+}
+
+/// Every clip's content in a pastebook (the active one by default) as one string
+#[tauri::command]
+fn get_all_content(pastebook_id: Option<String>, state: tauri::State<AppState>) -> Result<String, String> {
+    let mut timer = state.metrics.time("get_all_content");
+    let storage = storage::loaded(&state.storage);
+    let pastebook = storage.resolve_pastebook(pastebook_id.as_deref())?;
+    let content = storage.pastebook_content(&pastebook.id).unwrap_or_default();
+    timer.payload(content.len(), pastebook.live_clip_count());
+    Ok(content)
+}
+
+/// Every clip in a pastebook as copied out, attribution included when enabled
+fn pastebook_text(storage: &AppStorage, pastebook: &Pastebook) -> String {
+    let template = storage.settings.attribution();
+    pastebook
+        .clips
+        .iter()
+        .map(|c| attribution::with_attribution(c, template))
+        .collect::<Vec<_>>()
+        .join("\n\n")
+}
+
+/// `pastebook_text` for the active pastebook
+fn all_clips_text(storage: &AppStorage) -> String {
+    storage
+        .get_active_pastebook()
+        .map(|p| pastebook_text(storage, p))
+        .unwrap_or_default()
+}
+
+/// How much text a copy put on the clipboard
+#[derive(serde::Serialize)]
+struct CopyResult {
+    bytes: usize,
+}
+
+/// Copy a pastebook's content (the active one's by default) to the
+/// clipboard. Past the clipboard size limit this fails
+/// with `TooLarge` unless `force` is set; `export_all_content` writes the
+/// same text to a file instead.
+#[tauri::command]
+fn copy_all_to_clipboard(
+    force: Option<bool>,
+    pastebook_id: Option<String>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<CopyResult, String> {
+    let mut timer = state.metrics.time("copy_all_to_clipboard");
+    let storage = storage::loaded(&state.storage);
+    let pastebook = storage.resolve_pastebook(pastebook_id.as_deref())?;
+    let content = pastebook_text(&storage, pastebook);
+    timer.payload(content.len(), pastebook.live_clip_count());
+    storage
+        .settings
+        .check_clipboard_size(content.len(), force.unwrap_or(false))?;
+    let bytes = content.len();
+
+    app.clipboard()
+        .write_text(content)
+        .map_err(|e| format!("Failed to write to clipboard: {}", e))?;
+    
+    Ok(CopyResult { bytes })
+}
+
+/// Write what `copy_all_to_clipboard` would copy to a file at `path`,
+/// returning the bytes written
+#[tauri::command]
+fn export_all_content(path: PathBuf, state: tauri::State<AppState>) -> Result<usize, String> {
+    let mut timer = state.metrics.time("export_all_content");
+    if !path.is_absolute() {
+        return Err("Export path must be absolute".to_string());
+    }
+    let content = {
+        let storage = storage::loaded(&state.storage);
+        let content = all_clips_text(&storage);
+        timer.payload(content.len(), storage.get_clips_count());
+        content
+    };
+    std::fs::write(&path, &content)
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+    Ok(content.len())
+}
+
+/// Copy one clip to the clipboard, with the attribution appended when enabled
+#[tauri::command]
+fn copy_clip(id: String, app: AppHandle, state: tauri::State<AppState>) -> Result<(), String> {
+    let _timer = state.metrics.time("copy_clip");
+    copy_clip_to_clipboard(&app, &state, &id)
+}
+
+fn copy_clip_to_clipboard(app: &AppHandle, state: &AppState, id: &str) -> Result<(), String> {
+    let storage = storage::loaded(&state.storage);
+    let clip = storage
+        .find_clip(id)
+        .ok_or_else(|| format!("NotFound: clip {}", id))?;
+    let content = attribution::with_attribution(clip, storage.settings.attribution());
+
+    app.clipboard()
+        .write_text(content)
+        .map_err(|e| format!("Failed to write to clipboard: {}", e))
+}
+
+/// Put a clip on the clipboard (without attribution) and keep it there for
+/// `duration_secs`, putting it back whenever something else is copied.
+/// Sensitive clips need `confirm_sensitive`.
+#[tauri::command]
+fn hold_on_clipboard(
+    id: String,
+    duration_secs: u64,
+    confirm_sensitive: Option<bool>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<clipboard_hold::HoldStatus, String> {
+    let _timer = state.metrics.time("hold_on_clipboard");
+    if duration_secs == 0 || duration_secs > clipboard_hold::MAX_HOLD_SECS {
+        return Err(format!(
+            "Validation: a hold lasts 1 to {} seconds",
+            clipboard_hold::MAX_HOLD_SECS
+        ));
+    }
+    let (content, aggressiveness) = {
+        let storage = storage::loaded(&state.storage);
+        let clip = storage
+            .find_clip(&id)
+            .ok_or_else(|| format!("NotFound: clip {}", id))?;
+        if clip.sensitive && !confirm_sensitive.unwrap_or(false) {
+            return Err("Validation: this clip is sensitive; confirm to hold it on the clipboard".to_string());
+        }
+        (clip.content.clone(), storage.settings.clipboard_hold_aggressiveness)
+    };
+    let duration = std::time::Duration::from_secs(duration_secs);
+    clipboard_hold::start(&app, &id, content, duration, aggressiveness)
+}
+
+/// Serve a clip at a one-time URL on the local network for `ttl_secs`, with
+/// the URL as a QR code for a phone to scan. Needs `lan_share_enabled`;
+/// sensitive clips need `confirm_sensitive`.
+#[tauri::command]
+fn share_clip_via_lan(
+    id: String,
+    ttl_secs: u64,
+    confirm_sensitive: Option<bool>,
+    state: tauri::State<AppState>,
+) -> Result<lan_share::LanShare, String> {
+    let _timer = state.metrics.time("share_clip_via_lan");
+    if ttl_secs == 0 || ttl_secs > lan_share::MAX_TTL_SECS {
+        return Err(format!("Validation: a share lasts 1 to {} seconds", lan_share::MAX_TTL_SECS));
+    }
+    let (content, single_use) = {
+        let storage = storage::loaded(&state.storage);
+        if !storage.settings.lan_share_enabled {
+            return Err(
+                "Locked: sharing over the local network is off. Turning it on lets anyone on the network \
+                 who has a share link read that clip until the link expires."
+                    .to_string(),
+            );
+        }
+        let clip = storage
+            .find_clip(&id)
+            .ok_or_else(|| format!("NotFound: clip {}", id))?;
+        if clip.sensitive && !confirm_sensitive.unwrap_or(false) {
+            return Err("Validation: this clip is sensitive; confirm to share it on the network".to_string());
+        }
+        (clip.content.clone(), storage.settings.lan_share_single_use)
+    };
+    lan_share::share(content, std::time::Duration::from_secs(ttl_secs), single_use)
+}
+
+/// Stop holding a clip on the clipboard; returns whether one was held
+#[tauri::command]
+fn release_clipboard_hold(app: AppHandle, state: tauri::State<AppState>) -> bool {
+    let _timer = state.metrics.time("release_clipboard_hold");
+    clipboard_hold::release(&app)
+}
+
+/// Render the attribution for a clip with `template` (or the saved one),
+/// whether or not appending is enabled, so settings can preview it
+#[tauri::command]
+fn preview_attribution(
+    id: String,
+    template: Option<String>,
+    state: tauri::State<AppState>,
+) -> Result<String, String> {
+    let _timer = state.metrics.time("preview_attribution");
+    let storage = state.storage.read().unwrap();
+    let clip = storage
+        .find_clip(&id)
+        .ok_or_else(|| format!("NotFound: clip {}", id))?;
+    let template = template.as_deref().unwrap_or(&storage.settings.attribution_template);
+    Ok(attribution::render(template, clip))
+}
+
+/// Clear all clips in a pastebook (the active one by default) except locked
+/// ones, returning how many went
+#[tauri::command]
+fn clear_all_clips(
+    pastebook_id: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<usize>, String> {
+    let _timer = state.metrics.time("clear_all_clips");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let id = storage.resolve_pastebook(pastebook_id.as_deref())?.id.clone();
+    let cleared = storage.clear_pastebook_clips(&id).unwrap_or_default().len();
+    storage.note_chrome();
+    let revision = storage.commit_if(cleared > 0)?;
+    Ok(Revisioned { revision, data: cleared })
+}
+
+/// A pastebook's scratchpad (the active one's by default)
+#[tauri::command]
+fn get_scratchpad(pastebook_id: Option<String>, state: tauri::State<AppState>) -> Result<storage::Scratchpad, String> {
+    let _timer = state.metrics.time("get_scratchpad");
+    let storage = state.storage.read().unwrap();
+    Ok(storage.resolve_pastebook(pastebook_id.as_deref())?.scratchpad())
+}
+
+/// Replace a pastebook's scratchpad. Called as the user types, so the
+/// write is debounced like captures are.
+#[tauri::command]
+fn set_scratchpad(
+    pastebook_id: Option<String>,
+    text: String,
+    expected_revision: Option<u64>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<storage::Scratchpad>, String> {
+    let _timer = state.metrics.time("set_scratchpad");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage.resolve_pastebook_mut(pastebook_id.as_deref())?;
+    let changed = pastebook.set_scratchpad(text, Utc::now());
+    let scratchpad = pastebook.scratchpad();
+    if changed {
+        storage.commit_deferred();
+        capture_path::schedule_save(&app);
+    }
+    Ok(storage.revisioned(scratchpad))
+}
+
+/// Put a pastebook's scratchpad on the clipboard
+#[tauri::command]
+fn copy_scratchpad_to_clipboard(
+    pastebook_id: Option<String>,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<(), String> {
+    let _timer = state.metrics.time("copy_scratchpad_to_clipboard");
+    let text = state.storage.read().unwrap().resolve_pastebook(pastebook_id.as_deref())?.scratchpad.clone();
+    if text.trim().is_empty() {
+        return Err("Validation: the scratchpad is empty".to_string());
+    }
+    app.clipboard()
+        .write_text(text)
+        .map_err(|e| format!("Failed to write to clipboard: {}", e))
+}
+
+// ==================== CAPTURE ====================
+
+/// Copy the selection in the focused app and capture it: the capture hotkey,
+/// and a tray double-click once focus is back on the user's window. Nothing
+/// is copied or captured while paused.
+fn capture_selection(app: &AppHandle) {
+    // Nothing gets copied or captured while paused
+    let state = app.state::<AppState>();
+    if capture_blocked(app, &state.settings()) {
+        return;
+    }
+
+    let mut trace = capture_path::Trace::start();
+
+    // 0. Record the source window as of the key press, before focus can move
+    let window_info = capture_window_info();
+
+    // Focus mode: excluded apps are never captured; copies from apps off the
+    // include list are held until the user asks to capture them anyway
+    let app_filter = state.settings().app_filter(&window_info.app_name);
+    if app_filter == AppFilter::Excluded {
+        let announcement = announce::text(Message::BlockedExcludedApp);
+        let _ = app.emit("capture-blocked", CaptureBlocked { reason: "excluded_app", announcement });
+        return;
+    }
+
+    // Opt-in: read the page text around a browser selection alongside the copy
+    let pending_context = (state.settings().capture_selection_context
+        && window::is_browser(&window_info.app_name))
+    .then(window::read_selection_context);
+
+    // 1. Simulate Ctrl+C to copy selected text
+    let clipboard = capture_path::SystemClipboard(app);
+    let before = capture_path::ClipboardSource::sequence(&clipboard);
+    input::simulate_copy();
+    trace.mark(capture_path::Stage::CopySent);
+    
+    // 2. Wait for the copy to land; nothing lands when nothing is selected
+    let formats = state.settings().record_clipboard_formats;
+    let copied = capture_path::wait_for_copy(&clipboard, before, capture_path::COPY_TIMEOUT, formats);
+    trace.mark(capture_path::Stage::ClipboardReady);
+
+    // Don't leave a modifier we pressed stuck down
+    input::verify_modifiers();
+    
+    let Some(copied) = copied.filter(|copied| !copied.text.trim().is_empty()) else {
+        return;
+    };
+    let clipboard_content = copied.text;
+    let Ok(clipboard_content) = sanitize_capture(app, &clipboard_content) else {
+        return;
+    };
+    
+    // 3. Create clip
+    let mut clip = ClipObject::new(clipboard_content, window_info);
+    clip.captured_instant = Some(std::time::Instant::now());
+    clip.metadata.available_formats = copied.formats;
+    if let Some(context) = pending_context
+        .and_then(|pending| pending.wait(window::SELECTION_CONTEXT_BUDGET))
+    {
+        context.merge_into(&mut clip.metadata.context);
+    }
+    trace.mark(capture_path::Stage::ClipBuilt);
+    if app_filter == AppFilter::NotIncluded {
+        hold_capture(app, clip);
+        return;
+    }
+    
+    // 4. Store in memory (dedup window/action come from settings), or
+    // queue it if long work holds storage
+    let timer = state.metrics.time("hotkey_capture");
+    let start_drain = || {
+        let app = app.clone();
+        std::thread::spawn(move || drain_capture_inbox(&app));
+    };
+    let Some(stored) = capture_path::INBOX.admit(&state.storage, clip, start_drain) else {
+        return;
+    };
+    trace.mark(capture_path::Stage::Stored);
+    
+    // 5. Tell the window, then write to disk; mid-burst, new clips go out
+    // together (and are saved) once the burst settles
+    let batched = capture_batch::begin(app);
+    announce_capture(app, stored, batched);
+    drop(timer);
+    trace.mark(capture_path::Stage::Emitted);
+    trace.finish();
+}
+
+/// Store and announce hotkey captures queued while storage was busy, until
+/// none are left
+fn drain_capture_inbox(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    loop {
+        let stored = capture_path::INBOX.drain(&state.storage);
+        if stored.is_empty() {
+            return;
+        }
+        for stored in stored {
+            let batched = capture_batch::begin(app);
+            announce_capture(app, stored, batched);
+        }
+    }
+}
+
+/// Whether a scripted capture from `clip`'s app is within the rate limit.
+/// Every capture held back emits `capture-skipped`; the first of a burst
+/// also tells the user and starts watching for the burst to end.
+fn rate_limit_allows(app: &AppHandle, clip: &ClipObject) -> bool {
+    let state = app.state::<AppState>();
+    let source = clip.metadata.source_app.clone();
+    let mut limiter = state.rate_limiter.lock().unwrap();
+    let verdict = limiter.check(&source, std::time::Instant::now());
+    if verdict == rate_limit::Verdict::Allow {
+        return true;
+    }
+    let settle = verdict == rate_limit::Verdict::SuppressStart && limiter.schedule_settle();
+    drop(limiter);
+
+    let announcement = announce::text(Message::RateLimited { app: &source });
+    let skipped = CaptureSkipped { reason: SkipReason::RateLimited { source_app: source.clone() }, announcement };
+    let _ = app.emit("capture-skipped", skipped);
+    if verdict == rate_limit::Verdict::Suppress {
+        return false;
+    }
+
+    let message = format!("{} is copying too fast; holding its captures back until it slows down", source);
+    notify::send(app, NotificationKind::RateLimited, 1, "info", message);
+    if settle {
+        let app = app.clone();
+        std::thread::spawn(move || settle_rate_limited(&app));
+    }
+    false
+}
+
+/// Report bursts of held-back captures once each app calms down, as one
+/// summary clip or a notification
+fn settle_rate_limited(app: &AppHandle) {
+    let state = app.state::<AppState>();
+    loop {
+        let interval = state.rate_limiter.lock().unwrap().refill_interval();
+        std::thread::sleep(interval.max(std::time::Duration::from_millis(250)));
+        let (finished, ongoing) = state.rate_limiter.lock().unwrap().settle(std::time::Instant::now());
+        let action = state.settings().rate_limit_action;
+        for (source, suppressed) in finished {
+            match action {
+                RateLimitAction::Coalesce => {
+                    let window_info = WindowInfo {
+                        app_name: source.clone(),
+                        window_title: "Rate limited".to_string(),
+                        ..Default::default()
+                    };
+                    add_external_clip(app, ClipObject::new(rate_limit::summary(&source, suppressed), window_info));
+                }
+                RateLimitAction::Drop => {
+                    let message = format!("Dropped {} rapid captures from {}", suppressed, source);
+                    notify::send(app, NotificationKind::RateLimited, suppressed, "info", message);
+                }
+            }
+        }
+        if !ongoing {
+            return;
+        }
+    }
+}
+
+/// Emit a stored capture's outcome and queue the save of a deferred commit
+fn announce_capture(app: &AppHandle, stored: capture_path::Stored, batched: bool) {
+    // Before the clip, so the UI is showing the pastebook it went into
+    if let Some(selected) = stored.auto_selected {
+        broadcast(app, "pastebook-auto-selected", selected);
+    }
+    if let Some(routed) = stored.title_routed {
+        broadcast(app, "pastebook-title-routed", routed);
+    }
+    match stored.outcome {
+        CaptureOutcome::Added(clip) if batched => {
+            capture_batch::queue(captured_with_announcement(app, &clip));
+        }
+        CaptureOutcome::Added(clip) => {
+            emit_clip_captured(app, &clip);
+        }
+        CaptureOutcome::Bumped(clip) => {
+            emit_clip_updated(app, &clip);
+        }
+        CaptureOutcome::Reused(clip, switched_to) => {
+            emit_clip_revealed(app, &clip.id, switched_to);
+        }
+        CaptureOutcome::Ignored => println!("Ignoring duplicate capture"),
+        CaptureOutcome::Skipped(rule) => {
+            let announcement = announce::text(Message::Skipped { rule: &rule });
+            let _ = app.emit("capture-skipped", CaptureSkipped { reason: SkipReason::Rule { rule }, announcement });
+        }
+        CaptureOutcome::NoPastebook(_) => eprintln!("Capture lost: {}", storage::NO_ACTIVE_PASTEBOOK),
+    }
+    if !batched {
+        capture_path::schedule_save(app);
+    }
+}
+
+// ==================== HOTKEY ACTIONS ====================
+
+const HOTKEY_HANDLERS: hotkeys::Handlers = hotkeys::Handlers {
+    capture: capture_selection,
+    copy_all: copy_all_hotkey,
+    clear_all: clear_all_hotkey,
+    quick_note: quick_note_hotkey,
+};
+
+/// Payload of the `copied-all` event
+#[derive(Clone, serde::Serialize)]
+struct CopiedAll {
+    clips: usize,
+    pasted: bool,
+    announcement: String,
+    /// Set when nothing was copied because the text is over the clipboard limit
+    error: Option<String>,
+}
+
+/// Copy-all hotkey: put every clip on the clipboard, then paste it into the
+/// focused app when enabled. Runs off the shortcut thread since the paste
+/// waits for the hotkey to be released.
+fn copy_all_hotkey(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let mut timer = state.metrics.time("copy_all_hotkey");
+        let (content, clips, paste, too_large) = {
+            let storage = storage::loaded(&state.storage);
+            let content = all_clips_text(&storage);
+            let too_large = storage.settings.check_clipboard_size(content.len(), false).err();
+            (content, storage.get_clips_count(), storage.settings.copy_all_pastes, too_large)
+        };
+        timer.payload(content.len(), clips);
+        // A hotkey can't pass force, so the limit always holds here
+        if let Some(error) = too_large {
+            let _ = app.emit("copied-all", CopiedAll { clips, pasted: false, announcement: error.clone(), error: Some(error) });
+            return;
+        }
+
+        let pasted = clips > 0 && paste;
+        if clips > 0 {
+            let written = if pasted {
+                paste_text(&app, content)
+            } else {
+                app.clipboard().write_text(content).map_err(|e| e.to_string())
+            };
+            if let Err(e) = written {
+                eprintln!("Copy all hotkey failed: {}", e);
+                return;
+            }
+        }
+        let announcement = announce::text(match (clips, pasted) {
+            (0, _) => Message::NothingToCopy,
+            (count, true) => Message::PastedAll { count },
+            (count, false) => Message::CopiedAll { count },
+        });
+        let _ = app.emit("copied-all", CopiedAll { clips, pasted, announcement, error: None });
+    });
+}
+
+/// Quick note hotkey: open the prompt for typing a clip
+fn quick_note_hotkey(app: &AppHandle) {
+    if let Err(e) = quick_note::show(app) {
+        eprintln!("{}", e);
+    }
+}
+
+/// Put `text` on the clipboard and paste it into the focused app. Blocks
+/// briefly, so call it off the main thread.
+fn paste_text(app: &AppHandle, text: String) -> Result<(), String> {
+    app.clipboard().write_text(text).map_err(|e| e.to_string())?;
+    // Let the clipboard settle before the target app reads it
+    std::thread::sleep(std::time::Duration::from_millis(100));
+    input::simulate_paste();
+    Ok(())
+}
+
+/// Payload of the `clear-all-armed` event
+#[derive(Clone, serde::Serialize)]
+struct ClearAllArmed {
+    /// How long the second press has to confirm
+    window_ms: u64,
+}
+
+/// Payload of the `clips-cleared` event
+#[derive(Clone, serde::Serialize)]
+struct ClipsCleared {
+    revision: u64,
+    clips: usize,
+}
+
+/// Clear-all hotkey: the first press only arms it, a second press within
+/// the confirm window clears the active pastebook
+fn clear_all_hotkey(app: &AppHandle) {
+    if !hotkeys::confirm_clear(std::time::Instant::now()) {
+        let window_ms = hotkeys::CLEAR_CONFIRM_WINDOW.as_millis() as u64;
+        let _ = app.emit("clear-all-armed", ClearAllArmed { window_ms });
+        return;
+    }
+
+    let state = app.state::<AppState>();
+    let _timer = state.metrics.time("clear_all_hotkey");
+    let mut storage = state.storage.write().unwrap();
+    let ids = storage.clear_clips();
+    let pastebook_id = storage.active_pastebook_id.clone();
+    storage.note_chrome();
+    let revision = match storage.commit() {
+        Ok(revision) => revision,
+        Err(e) => {
+            eprintln!("Clear all hotkey failed: {}", e);
+            return;
+        }
+    };
+    drop(storage);
+
+    let clips = ids.len();
+    broadcast(app, "storage-changed", StorageChanged { revision, pastebook_id, ids });
+    broadcast(app, "clips-cleared", ClipsCleared { revision, clips });
+}
+
+// ==================== TRAY ACTIONS ====================
+
+const TRAY_HANDLERS: tray::Handlers = tray::Handlers {
+    double_click: capture_from_tray,
+    middle_click: paste_top_clip_from_tray,
+    create_diagnostics: diagnostics_from_tray,
+};
+
+/// Payload of the `tray-action-failed` event
+#[derive(Clone, serde::Serialize)]
+struct TrayActionFailed {
+    reason: &'static str,
+    announcement: String,
+}
+
+impl TrayActionFailed {
+    fn new(reason: &'static str, message: Message) -> Self {
+        Self { reason, announcement: announce::text(message) }
+    }
+}
+
+/// Payload of the `top-clip-pasted` event
+#[derive(Clone, serde::Serialize)]
+struct TopClipPasted {
+    id: String,
+    announcement: String,
+}
+
+/// Hand focus back from the taskbar to the window the user was in, telling
+/// the UI when there isn't one
+fn refocus_after_tray(app: &AppHandle) -> bool {
+    if window::focus_last_foreground() {
+        // Give the window a moment to take focus before keys are sent to it
+        std::thread::sleep(std::time::Duration::from_millis(100));
+        return true;
+    }
+    let failed = TrayActionFailed::new("no window to return to", Message::NoWindowToReturnTo);
+    let _ = app.emit("tray-action-failed", failed);
+    false
+}
+
+/// Payload of the `diagnostics-saved` event
+#[derive(Clone, serde::Serialize)]
+struct DiagnosticsSaved {
+    path: String,
+}
+
+/// Payload of the `diagnostics-failed` event
+#[derive(Clone, serde::Serialize)]
+struct DiagnosticsFailed {
+    error: String,
+}
+
+/// Tray menu: save a diagnostics bundle where the user picks. The dialog
+/// blocks, so it runs off the event loop.
+fn diagnostics_from_tray(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || match save_diagnostics(&app, None) {
+        Ok(Some(path)) => {
+            let path = path.to_string_lossy().into_owned();
+            let _ = app.emit("diagnostics-saved", DiagnosticsSaved { path });
+        }
+        Ok(None) => {}
+        Err(error) => {
+            let _ = app.emit("diagnostics-failed", DiagnosticsFailed { error });
+        }
+    });
+}
+
+/// Tray double-click: capture from the window that had focus before the
+/// click, like the capture hotkey
+fn capture_from_tray(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        if capture_blocked(&app, &app.state::<AppState>().settings()) {
+            return;
+        }
+        if refocus_after_tray(&app) {
+            capture_selection(&app);
+        }
+    });
+}
+
+/// Tray middle-click, when enabled: paste the active pastebook's top clip
+/// into the window that had focus before the click
+fn paste_top_clip_from_tray(app: &AppHandle) {
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let top = {
+            let storage = storage::loaded(&state.storage);
+            if !storage.settings.tray_middle_click_paste || capture_blocked(&app, &storage.settings) {
+                return;
+            }
+            storage
+                .get_active_pastebook()
+                .and_then(|p| p.clips.first())
+                .map(|clip| (clip.id.clone(), clip.content.clone()))
+        };
+        let Some((id, content)) = top else {
+            let failed = TrayActionFailed::new("no clips to paste", Message::NoClipsToPaste);
+            let _ = app.emit("tray-action-failed", failed);
+            return;
+        };
+        if !refocus_after_tray(&app) {
+            return;
+        }
+
+        let _timer = state.metrics.time("tray_paste");
+        match paste_text(&app, content) {
+            Ok(()) => {
+                let announcement = announce::text(Message::PastedTopClip);
+                let _ = app.emit("top-clip-pasted", TopClipPasted { id, announcement });
+            }
+            Err(e) => eprintln!("Tray paste failed: {}", e),
+        }
+    });
+}
+
+// ==================== PASTEBOOK COMMANDS ====================
+
+/// Get list of all pastebooks
+#[tauri::command]
+fn list_pastebooks(state: tauri::State<AppState>) -> Revisioned<Vec<PastebookSummary>> {
+    let _timer = state.metrics.time("list_pastebooks");
+    let storage = state.storage.read().unwrap();
+    storage.revisioned(storage.list_pastebooks())
+}
+
+/// Live and trashed clip counts for one pastebook
+#[tauri::command]
+fn get_pastebook_stats(pastebook_id: String, state: tauri::State<AppState>) -> Result<Revisioned<PastebookStats>, String> {
+    let _timer = state.metrics.time("get_pastebook_stats");
+    let storage = state.storage.read().unwrap();
+    let stats = storage
+        .pastebook_stats(&pastebook_id)
+        .ok_or_else(|| format!("NotFound: pastebook {}", pastebook_id))?;
+    Ok(storage.revisioned(stats))
+}
+
+/// The clips in a pastebook's trash, most recently trashed first
+#[tauri::command]
+fn get_trashed_clips(pastebook_id: String, state: tauri::State<AppState>) -> Result<Revisioned<Vec<ClipObject>>, String> {
+    let _timer = state.metrics.time("get_trashed_clips");
+    let storage = storage::loaded(&state.storage);
+    let clips = storage
+        .trashed_clips(&pastebook_id)
+        .ok_or_else(|| format!("NotFound: pastebook {}", pastebook_id))?;
+    Ok(storage.revisioned(clips))
+}
+
+/// Get active pastebook info
+#[tauri::command]
+fn get_active_pastebook(state: tauri::State<AppState>) -> Revisioned<Option<Pastebook>> {
+    let _timer = state.metrics.time("get_active_pastebook");
+    let storage = storage::loaded(&state.storage);
+    storage.revisioned(storage.get_active_pastebook().cloned())
+}
+
+/// Find a pastebook by name, ignoring case
+#[tauri::command]
+fn find_pastebook_by_name(name: String, state: tauri::State<AppState>) -> Revisioned<Option<Pastebook>> {
+    let _timer = state.metrics.time("find_pastebook_by_name");
+    let storage = storage::loaded(&state.storage);
+    storage.revisioned(storage.find_pastebook_by_name(&name).cloned())
+}
+
+/// Create a new pastebook
+#[tauri::command]
+fn create_pastebook(
+    name: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("create_pastebook");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage.create_pastebook(name)?;
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+/// Switch to a pastebook
+#[tauri::command]
+fn switch_pastebook(
+    id: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<bool>, String> {
+    let _timer = state.metrics.time("switch_pastebook");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let changed = storage.active_pastebook_id.as_deref() != Some(id.as_str());
+    let switched = storage.switch_pastebook(id);
+    storage.note_chrome();
+    let revision = storage.commit_if(switched && changed)?;
+    Ok(Revisioned { revision, data: switched })
+}
+
+/// The user is looking at a pastebook: clear its new-clip count, leaving
+/// the others'. Viewing isn't an edit, so the revision stays put.
+#[tauri::command]
+fn mark_pastebook_viewed(
+    id: String,
+    app: AppHandle,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<PastebookSummary>, String> {
+    let _timer = state.metrics.time("mark_pastebook_viewed");
+    let mut storage = state.storage.write().unwrap();
+    let (summary, changed) = storage
+        .mark_pastebook_viewed(&id, Utc::now())
+        .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+    // Runs on every focus; written with the next debounced save
+    if changed {
+        storage.note_chrome();
+        storage.defer_save();
+        capture_path::schedule_save(&app);
+    }
+    Ok(storage.revisioned(summary))
+}
+
+/// Delete a pastebook; one with clips outside the trash needs `confirm`
+#[tauri::command]
+fn delete_pastebook(
+    id: String,
+    confirm: Option<bool>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<bool>, String> {
+    let _timer = state.metrics.time("delete_pastebook");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let deleted = storage.delete_pastebook(&id, confirm.unwrap_or(false))?;
+    storage.note_chrome();
+    let revision = storage.commit_if(deleted)?;
+    Ok(Revisioned { revision, data: deleted })
+}
+
+/// Rename a pastebook
+#[tauri::command]
+fn rename_pastebook(
+    id: String,
+    name: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<bool>, String> {
+    let _timer = state.metrics.time("rename_pastebook");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let renamed = storage.rename_pastebook(&id, name)?;
+    storage.note_chrome();
+    let revision = storage.commit_if(renamed)?;
+    Ok(Revisioned { revision, data: renamed })
+}
+
+/// File a pastebook under a group, or ungroup it with None
+#[tauri::command]
+fn set_pastebook_group(
+    id: String,
+    group: Option<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("set_pastebook_group");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage.set_pastebook_group(&id, group)?;
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+/// Undo the latest pastebook operation (see `AppStorage::undo_last_operation`)
+#[tauri::command]
+fn undo_last_operation(
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<undo::UndoOutcome>, String> {
+    let _timer = state.metrics.time("undo_last_operation");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let outcome = storage.undo_last_operation()?;
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: outcome })
+}
+
+/// Pastebooks bucketed by group, ungrouped ones under a None group
+#[tauri::command]
+fn list_pastebook_groups(state: tauri::State<AppState>) -> Revisioned<Vec<PastebookGroup>> {
+    let _timer = state.metrics.time("list_pastebook_groups");
+    let storage = state.storage.read().unwrap();
+    storage.revisioned(storage.pastebook_groups())
+}
+
+/// Rename a group on all its pastebooks at once
+#[tauri::command]
+fn rename_pastebook_group(
+    old: String,
+    new: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<usize>, String> {
+    let _timer = state.metrics.time("rename_pastebook_group");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let renamed = storage.rename_pastebook_group(&old, &new)?;
+    let revision = storage.commit_if(renamed > 0)?;
+    Ok(Revisioned { revision, data: renamed })
+}
+
+/// Reorder the pastebooks within one group (None for the ungrouped ones)
+#[tauri::command]
+fn reorder_pastebooks(
+    group: Option<String>,
+    ids: Vec<String>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Vec<PastebookGroup>>, String> {
+    let _timer = state.metrics.time("reorder_pastebooks");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    storage.reorder_pastebooks(group.as_deref(), &ids)?;
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: storage.pastebook_groups() })
+}
+
+/// Mirror a pastebook's new clips to a file (e.g. notes.md), or stop with None
+#[tauri::command]
+fn set_pastebook_mirror(
+    id: String,
+    path: Option<PathBuf>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("set_pastebook_mirror");
+    let path = path.map(|path| mirror::validate_path(&path)).transpose()?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage
+        .set_pastebook_mirror(&id, path)
+        .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+// ==================== SESSION COMMANDS ====================
+
+/// Start a capture session; clips captured until it ends are tagged with it
+#[tauri::command]
+fn start_session(label: Option<String>, state: tauri::State<AppState>) -> Result<CaptureSession, String> {
+    let _timer = state.metrics.time("start_session");
+    let label = label.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    let mut storage = state.storage.write().unwrap();
+    let session = storage.start_session(label);
+    storage.commit()?;
+    Ok(session)
+}
+
+/// End the current capture session
+#[tauri::command]
+fn end_session(state: tauri::State<AppState>) -> Result<Option<CaptureSession>, String> {
+    let _timer = state.metrics.time("end_session");
+    let mut storage = state.storage.write().unwrap();
+    let session = storage.end_session();
+    storage.commit_if(session.is_some())?;
+    Ok(session)
+}
+
+/// Get all capture sessions, oldest first
+#[tauri::command]
+fn list_sessions(state: tauri::State<AppState>) -> Vec<CaptureSession> {
+    let _timer = state.metrics.time("list_sessions");
+    let storage = state.storage.read().unwrap();
+    storage.sessions.clone()
+}
+
+/// Get what a session gathered
+#[tauri::command]
+fn get_session_report(session_id: String, state: tauri::State<AppState>) -> Result<SessionReport, String> {
+    let _timer = state.metrics.time("get_session_report");
+    let storage = storage::loaded(&state.storage);
+    storage
+        .session_report(&session_id)
+        .ok_or_else(|| format!("NotFound: session {}", session_id))
+}
+
+// ==================== TEMPLATE COMMANDS ====================
+
+/// Get all pastebook templates
+#[tauri::command]
+fn list_templates(state: tauri::State<AppState>) -> Vec<PastebookTemplate> {
+    let _timer = state.metrics.time("list_templates");
+    let storage = state.storage.read().unwrap();
+    storage.templates.clone()
+}
+
+/// Save a pastebook's structure as a template
+#[tauri::command]
+fn save_pastebook_as_template(
+    id: String,
+    template_name: String,
+    state: tauri::State<AppState>,
+) -> Result<PastebookTemplate, String> {
+    let _timer = state.metrics.time("save_pastebook_as_template");
+    let template_name = storage::validate_name("template name", &template_name)?;
+
+    let mut storage = storage::loaded_mut(&state.storage);
+    let template = storage
+        .save_pastebook_as_template(&id, template_name)
+        .ok_or("Pastebook not found")?;
+    storage.save()?;
+    Ok(template)
+}
+
+/// Create a new pastebook from a template and switch to it
+#[tauri::command]
+fn create_pastebook_from_template(
+    template_id: String,
+    name: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("create_pastebook_from_template");
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage.create_pastebook_from_template(&template_id, name)?;
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+/// Delete a template
+#[tauri::command]
+fn delete_template(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
+    let _timer = state.metrics.time("delete_template");
+    let mut storage = state.storage.write().unwrap();
+    let deleted = storage.delete_template(&id);
+    storage.save()?;
+    Ok(deleted)
+}
+
+// ==================== ASSET COMMANDS ====================
+
+/// Delete stored assets no clip references any more
+#[tauri::command]
+fn gc_assets(state: tauri::State<AppState>) -> assets::GcReport {
+    let _timer = state.metrics.time("gc_assets");
+    let storage = state.storage.read().unwrap();
+    assets::gc_assets(&storage.referenced_assets())
+}
+
+/// App-wide dashboard numbers across every pastebook. Results younger than
+/// `cache_ttl_secs` (default a minute) are reused; 0 forces a recount.
+#[tauri::command]
+fn get_global_stats(cache_ttl_secs: Option<u64>, state: tauri::State<AppState>) -> stats::GlobalStats {
+    let _timer = state.metrics.time("get_global_stats");
+    let storage = storage::loaded(&state.storage);
+    let ttl = cache_ttl_secs.unwrap_or(stats::DEFAULT_CACHE_TTL_SECS);
+    stats::cached(&storage, &chrono::Local, ttl)
+}
+
+/// Get asset count, size and orphan totals
+#[tauri::command]
+fn get_asset_stats(state: tauri::State<AppState>) -> assets::AssetStats {
+    let _timer = state.metrics.time("get_asset_stats");
+    let storage = state.storage.read().unwrap();
+    assets::asset_stats(&storage.referenced_assets())
+}
+
+// ==================== DATA DIR COMMANDS ====================
+
+/// Get the active data directory and how it was chosen
+#[tauri::command]
+fn get_data_dir() -> paths::DataDirInfo {
+    paths::data_dir_info()
+}
+
+/// Move or copy all Stack data to a new directory and switch to it
+#[tauri::command]
+fn migrate_data_dir(
+    new_path: String,
+    move_files: bool,
+    state: tauri::State<AppState>,
+) -> Result<paths::DataDirInfo, String> {
+    let _timer = state.metrics.time("migrate_data_dir");
+    // Hold the lock so no save can race the copy
+    let mut storage = state.storage.write().unwrap();
+    storage.save()?;
+
+    let info = paths::migrate_data_dir(PathBuf::from(new_path), move_files)?;
+    storage.set_storage_path(AppStorage::default_path());
+    storage.save()?;
+
+    Ok(info)
+}
+
+// ==================== SYNC COMMANDS ====================
+
+/// Point sync at a shared folder, encrypting with the given passphrase
+#[tauri::command]
+fn configure_sync(
+    dir: String,
+    passphrase: String,
+    state: tauri::State<AppState>,
+) -> Result<sync::SyncStatus, String> {
+    let _timer = state.metrics.time("configure_sync");
+    let previous = state.storage.read().unwrap().sync.clone();
+    let configured = sync::configure(previous, PathBuf::from(dir), &passphrase)?;
+    let status = sync::status(Some(&configured));
+    let mut storage = state.storage.write().unwrap();
+    storage.sync = Some(configured);
+    storage.save()?;
+    Ok(status)
+}
+
+/// Run a sync pass now
+#[tauri::command]
+fn sync_now(app: AppHandle, state: tauri::State<AppState>) -> Result<sync::SyncReport, String> {
+    let _timer = state.metrics.time("sync_now");
+    let report = sync::sync(&state.storage)?;
+    let mut storage = state.storage.write().unwrap();
+    if report.applied > 0 {
+        storage.note_chrome();
+        storage.commit()?;
+    } else {
+        storage.save()?;
+    }
+    drop(storage);
+
+    if report.applied > 0 {
+        broadcast(&app, "clips-updated", ());
+    }
+
+    Ok(report)
+}
+
+/// Get sync configuration and last result
+#[tauri::command]
+fn get_sync_status(state: tauri::State<AppState>) -> sync::SyncStatus {
+    let _timer = state.metrics.time("get_sync_status");
+    let sync_state = state.storage.read().unwrap().sync.clone();
+    sync::status(sync_state.as_ref())
+}
+
+/// Pull the text entries of the Windows clipboard history (Win+V) into a
+/// pastebook, oldest to newest, skipping content it already has
+#[tauri::command]
+async fn import_windows_clipboard_history(
+    app: AppHandle,
+    pastebook_id: String,
+    expected_revision: Option<u64>,
+    state: tauri::State<'_, AppState>,
+) -> Result<Revisioned<usize>, String> {
+    let _timer = state.metrics.time("import_windows_clipboard_history");
+    // Windows can take a while to answer, and the WinRT calls block, so
+    // read on a blocking thread before locking storage
+    let entries = tauri::async_runtime::spawn_blocking(clipboard_history::read_history)
+        .await
+        .map_err(|e| format!("Couldn't read clipboard history: {}", e))??;
+    let clips = clipboard_history::to_clips(entries, Utc::now());
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let added = storage
+        .import_clips(&pastebook_id, clips)
+        .ok_or_else(|| format!("NotFound: pastebook {}", pastebook_id))?;
+    storage.note_chrome();
+    let revision = if added > 0 { storage.commit()? } else { storage.revision };
+    drop(storage);
+
+    if added > 0 {
+        broadcast(&app, "clips-updated", ());
+    }
+    Ok(Revisioned { revision, data: added })
+}
+
+// ==================== DIAGNOSTICS COMMANDS ====================
+
+/// Whether storage is persisting to disk or holding everything in memory
+#[tauri::command]
+fn get_storage_health() -> StorageHealth {
+    health::current()
+}
+
+/// Try the data dir again now instead of waiting for the periodic retry
+#[tauri::command]
+fn retry_storage_init(state: tauri::State<AppState>) -> StorageHealth {
+    let _timer = state.metrics.time("retry_storage_init");
+    state.storage.write().unwrap().retry_persistence()
+}
+
+/// Whether the last session ended without its final save, and the backup
+/// from the last clean exit if there is one
+#[tauri::command]
+fn get_previous_shutdown() -> Option<shutdown::UncleanShutdown> {
+    shutdown::previous()
+}
+
+/// Replace everything with the backup from the last clean exit
+#[tauri::command]
+fn restore_clean_backup(app: AppHandle, state: tauri::State<AppState>) -> Result<u64, String> {
+    let _timer = state.metrics.time("restore_clean_backup");
+    let backup = shutdown::previous()
+        .and_then(|previous| previous.backup)
+        .ok_or("NotFound: no backup from a clean exit")?;
+    let mut storage = state.storage.write().unwrap();
+    storage.restore_from(&backup)?;
+    state.publish_settings(&storage.settings);
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    let settings = storage.settings.clone();
+    drop(storage);
+
+    // The backup brings its own settings
+    apply_settings(&app, &state, &settings);
+    if let Err(e) = local_api::apply(&app, &settings) {
+        eprintln!("Local API not started after restore: {}", e);
+    }
+    theme::apply(&app, settings.theme);
+    shutdown::dismiss();
+    broadcast(&app, "clips-updated", ());
+    Ok(revision)
+}
+
+/// Keep the current data and stop reporting the unclean shutdown
+#[tauri::command]
+fn dismiss_previous_shutdown() {
+    shutdown::dismiss();
+}
+
+/// Write everything to a user-chosen file, e.g. when the data dir is
+/// unwritable, at `path` or when None wherever the user picks in a save
+/// dialog. Returns the bytes written, or None if the dialog was dismissed.
+#[tauri::command]
+async fn export_backup(
+    path: Option<PathBuf>,
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<u64>, String> {
+    let _timer = state.metrics.time("export_backup");
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let dialog = app
+                .dialog()
+                .file()
+                .set_file_name(format!("stack-backup-{}.json", chrono::Local::now().format("%Y-%m-%d")))
+                .add_filter("JSON", &["json"]);
+            let picked = tauri::async_runtime::spawn_blocking(move || dialog.blocking_save_file())
+                .await
+                .map_err(|e| e.to_string())?;
+            match picked {
+                Some(picked) => picked.into_path().map_err(|e| e.to_string())?,
+                None => return Ok(None),
+            }
+        }
+    };
+    // Copied under a read guard; serializing and writing happen unlocked
+    let backup = storage::loaded(&state.storage).backup_copy();
+    let written = backup.write_backup(&path)?;
+    safe_mode::note_backup_exported();
+    Ok(Some(written))
+}
+
+/// Wrap the command handler so safe mode refuses commands that change
+/// stored data before they run, instead of failing the save afterwards
+fn safe_mode_gate<R: tauri::Runtime>(
+    handler: impl Fn(tauri::ipc::Invoke<R>) -> bool + Send + Sync + 'static,
+) -> impl Fn(tauri::ipc::Invoke<R>) -> bool + Send + Sync + 'static {
+    move |invoke| {
+        if let Err(e) = safe_mode::check_command(invoke.message.command()) {
+            invoke.resolver.reject(e);
+            return true;
+        }
+        handler(invoke)
+    }
+}
+
+/// Why this session started in safe mode, or None outside it
+#[tauri::command]
+fn get_safe_mode_reason() -> Option<safe_mode::SafeModeReason> {
+    safe_mode::reason()
+}
+
+/// Leave safe mode once a backup has been exported. Saving and AI come back
+/// now; shortcuts and background work return at the next launch.
+#[tauri::command]
+fn exit_safe_mode(app: AppHandle) -> Result<(), String> {
+    safe_mode::exit()?;
+    let _ = app.emit("safe-mode-exited", ());
+    Ok(())
+}
+
+/// Export a pastebook to `path` in `format`, one of `export::FORMATS`
+#[tauri::command]
+fn export_pastebook(
+    id: String,
+    format: String,
+    path: PathBuf,
+    options: Option<export::ExportOptions>,
+    state: tauri::State<AppState>,
+) -> Result<export::ExportReport, String> {
+    let _timer = state.metrics.time("export_pastebook");
+    if !path.is_absolute() {
+        return Err("Export path must be absolute".to_string());
+    }
+    // Copied so storage isn't locked while the files are written
+    let pastebook = state
+        .storage
+        .read()
+        .unwrap()
+        .pastebooks
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+
+    export::export(&format, &pastebook, &path, &options.unwrap_or_default())
+}
+
+/// Add a pastebook from a "json" export as a new one, renamed if its name
+/// is taken
+#[tauri::command]
+fn import_pastebook(
+    path: PathBuf,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("import_pastebook");
+    let file = export::import_json(&path)?;
+    let clips = file.clips.into_iter().map(export::ExportedClip::into_clip).collect();
+
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let mut pastebook = storage.import_pastebook(&file.name, file.group, clips)?;
+    if let Some(scratchpad) = file.scratchpad {
+        let imported = storage.resolve_pastebook_mut(Some(&pastebook.id))?;
+        imported.scratchpad = scratchpad;
+        pastebook = imported.clone();
+    }
+    storage.note_chrome();
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+/// Re-export a pastebook on a schedule, or stop with None
+#[tauri::command]
+fn set_pastebook_auto_export(
+    id: String,
+    config: Option<autoexport::AutoExportConfig>,
+    expected_revision: Option<u64>,
+    state: tauri::State<AppState>,
+) -> Result<Revisioned<Pastebook>, String> {
+    let _timer = state.metrics.time("set_pastebook_auto_export");
+    let config = config.map(autoexport::validate).transpose()?;
+    let mut storage = state.storage.write().unwrap();
+    storage.check_revision(expected_revision)?;
+    let pastebook = storage
+        .set_pastebook_auto_export(&id, config)
+        .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+    let revision = storage.commit()?;
+    Ok(Revisioned { revision, data: pastebook })
+}
+
+/// Run a pastebook's scheduled export now, whether or not it's due
+#[tauri::command]
+fn run_auto_export_now(
+    app: AppHandle,
+    id: String,
+    state: tauri::State<AppState>,
+) -> Result<export::ExportReport, String> {
+    let _timer = state.metrics.time("run_auto_export_now");
+    let pastebook = state
+        .storage
+        .read()
+        .unwrap()
+        .pastebooks
+        .iter()
+        .find(|p| p.id == id)
+        .cloned()
+        .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+    autoexport::run(&app, &pastebook)
+}
+
+/// Every scheduled export with its last run and last error
+#[tauri::command]
+fn get_auto_export_status(state: tauri::State<AppState>) -> Vec<autoexport::AutoExportStatus> {
+    let _timer = state.metrics.time("get_auto_export_status");
+    autoexport::status(&state.storage.read().unwrap())
+}
+
+/// Get p50/p95 timings per command over recent invocations
+#[tauri::command]
+fn get_perf_metrics(state: tauri::State<AppState>) -> Vec<CommandMetrics> {
+    state.metrics.summary()
+}
+
+// ==================== DIAGNOSTICS ====================
+
+fn diagnostics_manifest(app: &AppHandle) -> diagnostics::DiagnosticsManifest {
+    let state = app.state::<AppState>();
+    let storage = state.storage.read().unwrap();
+    let data_dir = paths::data_dir_info().path;
+    diagnostics::manifest(&diagnostics::Sources {
+        app_version: app.package_info().version.to_string(),
+        storage: &storage,
+        assets: assets::asset_stats(&storage.referenced_assets()),
+        metrics: state.metrics.summary(),
+        data_dir: &data_dir,
+    })
+}
+
+/// Save a diagnostics bundle to `path`, or where the user picks; None if
+/// they cancelled. Blocks while the dialog is open, so async callers run it
+/// with `spawn_blocking`.
+fn save_diagnostics(app: &AppHandle, path: Option<PathBuf>) -> Result<Option<PathBuf>, String> {
+    let path = match path {
+        Some(path) => path,
+        None => {
+            let picked = app
+                .dialog()
+                .file()
+                .set_file_name(diagnostics::file_name(chrono::Local::now()))
+                .add_filter("ZIP", &["zip"])
+                .blocking_save_file();
+            match picked {
+                Some(picked) => picked.into_path().map_err(|e| e.to_string())?,
+                None => return Ok(None),
+            }
+        }
+    };
+    diagnostics::write_bundle(&diagnostics_manifest(app), &path)?;
+    Ok(Some(path))
+}
+
+/// Everything a diagnostics bundle would hold, for review before saving
+#[tauri::command]
+fn preview_diagnostics(app: AppHandle, state: tauri::State<AppState>) -> diagnostics::DiagnosticsManifest {
+    let _timer = state.metrics.time("preview_diagnostics");
+    diagnostics_manifest(&app)
+}
+
+/// Save a zip of versions, redacted settings, storage health, hotkey status
+/// and timings for a bug report; never clip contents or window titles.
+/// Returns the path, or None if the save dialog was dismissed.
+#[tauri::command]
+async fn generate_diagnostics(
+    path: Option<PathBuf>,
+    app: AppHandle,
+    state: tauri::State<'_, AppState>,
+) -> Result<Option<String>, String> {
+    let _timer = state.metrics.time("generate_diagnostics");
+    let saved = tauri::async_runtime::spawn_blocking(move || save_diagnostics(&app, path))
+        .await
+        .map_err(|e| e.to_string())??;
+    Ok(saved.map(|path| path.to_string_lossy().into_owned()))
+}
+
+// ==================== DEEP LINKS ====================
+
+/// Turn a `stack://add` link into a clip, or tell the UI why it was rejected
+fn handle_deep_link(app: &AppHandle, url: &str) {
+    let state = app.state::<AppState>();
+    let _timer = state.metrics.time("deep_link");
+    if capture_blocked(app, &state.settings()) {
+        return;
+    }
+
+    let result = if state.settings().deep_links_enabled {
+        deeplink::parse_url(url)
+    } else {
+        Err("Deep links are disabled in settings".to_string())
+    };
+    let link = match result {
+        Ok(link) => link,
+        Err(e) => {
+            eprintln!("Rejected deep link: {}", e);
+            let _ = app.emit("deeplink-rejected", e);
+            return;
+        }
+    };
+
+    let Ok(text) = sanitize_capture(app, &link.text) else {
+        return;
+    };
+
+    let window_info = WindowInfo {
+        app_name: link.source_app,
+        window_title: "Deep link".to_string(),
+        ..Default::default()
+    };
+    add_scripted_clip(app, ClipObject::new(text, window_info));
+}
+
+/// Store a clip from a source a script can drive (links, the command
+/// line), unless its app is over the capture rate limit
+fn add_scripted_clip(app: &AppHandle, clip: ClipObject) {
+    if rate_limit_allows(app, &clip) {
+        add_external_clip(app, clip);
+    }
+}
+
+/// Store a clip that came from outside the hotkey path and tell the UI
+fn add_external_clip(app: &AppHandle, mut clip: ClipObject) {
+    clip.captured_instant = Some(std::time::Instant::now());
+    let state = app.state::<AppState>();
+    let batched = capture_batch::begin(app);
+    let stored = capture_path::store(&state.storage, clip);
+    announce_capture(app, stored, batched);
+}
+
+/// Capture files dropped onto the main window: each small text file becomes
+/// a clip, binary ones are refused by name and anything else is listed by
+/// path in one clip
+fn capture_dropped_files(app: &AppHandle, paths: Vec<PathBuf>) {
+    let state = app.state::<AppState>();
+    let _timer = state.metrics.time("drop_files");
+    if capture_blocked(app, &state.settings()) {
+        return;
+    }
+
+    let mut listed = Vec::new();
+    for path in paths {
+        match filedrop::classify(&path) {
+            filedrop::Dropped::Text { name, content } => {
+                let window_info = WindowInfo {
+                    app_name: filedrop::SOURCE_APP.to_string(),
+                    window_title: name,
+                    ..Default::default()
+                };
+                add_external_clip(app, ClipObject::new(content, window_info));
+            }
+            filedrop::Dropped::Rejected { name, reason } => {
+                let rejected = filedrop::DropRejected {
+                    name,
+                    reason: reason.message().to_string(),
+                };
+                let _ = app.emit("drop-rejected", rejected);
+            }
+            filedrop::Dropped::Listed(path) => listed.push(path),
+        }
+    }
+    if !listed.is_empty() {
+        let (content, window_title) = filedrop::file_list(&listed);
+        let window_info = WindowInfo {
+            app_name: filedrop::SOURCE_APP.to_string(),
+            window_title,
+            ..Default::default()
+        };
+        add_external_clip(app, ClipObject::new(content, window_info));
+    }
+}
+
+/// Capture text dragged onto the main window from another app. It takes
+/// the capture path like a dropped file, so pause, app rules and dedup apply.
+#[tauri::command]
+fn capture_dropped_text(app: AppHandle, content: String, state: tauri::State<AppState>) -> Result<(), String> {
+    let _timer = state.metrics.time("drop_text");
+    // Blocked and rejected captures are reported by their own events
+    if capture_blocked(&app, &state.settings()) {
+        return Ok(());
+    }
+    if content.len() as u64 > filedrop::MAX_DROPPED_TEXT_BYTES {
+        return Err(format!("Validation: dropped text is over {} bytes", filedrop::MAX_DROPPED_TEXT_BYTES));
+    }
+    let Ok(text) = sanitize_capture(&app, &content) else {
+        return Ok(());
+    };
+    let window_info = WindowInfo {
+        app_name: filedrop::SOURCE_APP.to_string(),
+        window_title: "Dropped text".to_string(),
+        ..Default::default()
+    };
+    add_external_clip(&app, ClipObject::new(text, window_info));
+    Ok(())
+}
+
+/// Handle a link or --add-file/--add-text/--copy-clip request from a launch of Stack
+fn handle_launch_request(app: &AppHandle, request: LaunchRequest) {
+    match request {
+        LaunchRequest::Link { url } => handle_deep_link(app, &url),
+        LaunchRequest::AddFile { path } => {
+            let state = app.state::<AppState>();
+            let _timer = state.metrics.time("shell_add_file");
+            if capture_blocked(app, &state.settings()) {
+                return;
+            }
+
+            let file = match shell::read_sent_file(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Rejected sent file: {}", e);
+                    let _ = app.emit("deeplink-rejected", e);
+                    return;
+                }
+            };
+            let Ok(text) = sanitize_capture(app, &file.content) else {
+                return;
+            };
+
+            let window_info = WindowInfo {
+                app_name: "Shell".to_string(),
+                window_title: file.title.clone(),
+                ..Default::default()
+            };
+            let mut clip = ClipObject::new(text, window_info);
+            clip.title = Some(file.title);
+            add_scripted_clip(app, clip);
+        }
+        LaunchRequest::AddText { text } => {
+            let state = app.state::<AppState>();
+            let _timer = state.metrics.time("shell_add_text");
+            if capture_blocked(app, &state.settings()) {
+                return;
+            }
+
+            if text.len() > deeplink::MAX_TEXT_BYTES {
+                let e = format!(
+                    "Text is too large ({} bytes, max {})",
+                    text.len(),
+                    deeplink::MAX_TEXT_BYTES
+                );
+                let _ = app.emit("deeplink-rejected", e);
+                return;
+            }
+            let Ok(text) = sanitize_capture(app, &text) else {
+                return;
+            };
+
+            let window_info = WindowInfo {
+                app_name: "Shell".to_string(),
+                window_title: "Command line".to_string(),
+                ..Default::default()
+            };
+            add_scripted_clip(app, ClipObject::new(text, window_info));
+        }
+        LaunchRequest::CopyClip { id } => {
+            let state = app.state::<AppState>();
+            let _timer = state.metrics.time("jump_list_copy");
+            // Only pinned, non-sensitive clips are listed; refuse the rest
+            // rather than trust the id a launch passed in
+            let sensitive = state.storage.read().unwrap().find_clip(&id).map(|c| c.sensitive);
+            let copied = match sensitive {
+                Some(true) => Err("Validation: sensitive clips can only be copied from Stack itself".to_string()),
+                _ => copy_clip_to_clipboard(app, &state, &id),
+            };
+            if let Err(e) = copied {
+                eprintln!("Jump list copy failed: {}", e);
+                let _ = app.emit("deeplink-rejected", e);
+            }
+        }
+    }
+}
+
+// ==================== CAPTURE RULE COMMANDS ====================
+
+/// Capture rules, in the order they run
+#[tauri::command]
+fn list_rules(state: tauri::State<AppState>) -> Vec<CaptureRule> {
+    let _timer = state.metrics.time("list_rules");
+    state.storage.read().unwrap().rules.clone()
+}
+
+/// Add a rule at the end of the list
+#[tauri::command]
+fn add_rule(rule: CaptureRule, state: tauri::State<AppState>) -> Result<CaptureRule, String> {
+    let _timer = state.metrics.time("add_rule");
+    let mut storage = state.storage.write().unwrap();
+    let rule = storage.add_rule(rule)?;
+    storage.save()?;
+    Ok(rule)
+}
+
+/// Replace a rule in place
+#[tauri::command]
+fn update_rule(
+    id: String,
+    rule: CaptureRule,
+    state: tauri::State<AppState>,
+) -> Result<CaptureRule, String> {
+    let _timer = state.metrics.time("update_rule");
+    let mut storage = state.storage.write().unwrap();
+    let rule = storage.update_rule(&id, rule)?;
+    storage.save()?;
+    Ok(rule)
+}
+
+#[tauri::command]
+fn delete_rule(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
+    let _timer = state.metrics.time("delete_rule");
+    let mut storage = state.storage.write().unwrap();
+    let deleted = storage.delete_rule(&id);
+    storage.save()?;
+    Ok(deleted)
+}
+
+/// Check which of a rule's conditions hold for sample text, without saving anything
+#[tauri::command]
+fn test_rule(
+    rule: CaptureRule,
+    sample_clip: RuleSample,
+    state: tauri::State<AppState>,
+) -> Result<RuleTestResult, String> {
+    let _timer = state.metrics.time("test_rule");
+    rules::test_rule(rule, &sample_clip)
+}
+
+// ==================== PROMPT PRESET COMMANDS ====================
+
+/// Saved AI instructions, in button order
+#[tauri::command]
+fn list_presets(state: tauri::State<AppState>) -> Vec<PromptPreset> {
+    let _timer = state.metrics.time("list_presets");
+    state.storage.read().unwrap().prompt_presets.clone()
+}
+
+/// Add a preset at the end of the list
+#[tauri::command]
+fn add_preset(preset: PromptPreset, state: tauri::State<AppState>) -> Result<PromptPreset, String> {
+    let _timer = state.metrics.time("add_preset");
+    let mut storage = state.storage.write().unwrap();
+    let preset = storage.add_preset(preset)?;
+    storage.save()?;
+    Ok(preset)
+}
+
+/// Replace a preset in place
+#[tauri::command]
+fn update_preset(
+    id: String,
+    preset: PromptPreset,
+    state: tauri::State<AppState>,
+) -> Result<PromptPreset, String> {
+    let _timer = state.metrics.time("update_preset");
+    let mut storage = state.storage.write().unwrap();
+    let preset = storage.update_preset(&id, preset)?;
+    storage.save()?;
+    Ok(preset)
+}
+
+#[tauri::command]
+fn delete_preset(id: String, state: tauri::State<AppState>) -> Result<bool, String> {
+    let _timer = state.metrics.time("delete_preset");
+    let mut storage = state.storage.write().unwrap();
+    let deleted = storage.delete_preset(&id);
+    storage.save()?;
+    Ok(deleted)
+}
+
+/// Write every preset to a JSON file to share
+#[tauri::command]
+fn export_presets(path: PathBuf, state: tauri::State<AppState>) -> Result<usize, String> {
+    let _timer = state.metrics.time("export_presets");
+    let storage = state.storage.read().unwrap();
+    presets::export(&storage.prompt_presets, &path)?;
+    Ok(storage.prompt_presets.len())
+}
+
+/// Add presets from an exported file, replacing ones with the same name
+#[tauri::command]
+fn import_presets(path: PathBuf, state: tauri::State<AppState>) -> Result<Vec<PromptPreset>, String> {
+    let _timer = state.metrics.time("import_presets");
+    let imported = presets::import(&path)?;
+    let mut storage = state.storage.write().unwrap();
+    storage.import_presets(imported)?;
+    storage.save()?;
+    Ok(storage.prompt_presets.clone())
+}
+
+/// Result of running a preset on a clip
+#[derive(serde::Serialize)]
+struct PresetResult {
+    /// The rewritten clip, or the new one
+    clip: ClipObject,
+    output: PresetOutput,
+    model: String,
+}
+
+/// Run a preset's instruction on a clip in the active pastebook, rewriting
+/// it or saving the result as a new clip depending on the preset
+#[tauri::command]
+async fn run_preset(
+    app: AppHandle,
+    clip_id: String,
+    preset_id: String,
+    state: tauri::State<'_, AppState>,
+) -> Result<PresetResult, String> {
+    let mut timer = state.metrics.time("run_preset");
+    safe_mode::check_ai()?;
+    let (api_key, models, preset, source) = {
+        let storage = storage::loaded(&state.storage);
+        let api_key = api_key(&storage).ok_or("API Key not found")?;
+        let preset = storage
+            .prompt_presets
+            .iter()
+            .find(|p| p.id == preset_id)
+            .cloned()
+            .ok_or_else(|| format!("NotFound: preset {}", preset_id))?;
+        let source = storage
+            .get_clip(&clip_id)
+            .cloned()
+            .ok_or_else(|| format!("NotFound: clip {}", clip_id))?;
+        let mut models = storage.settings.model_fallbacks.clone();
+        if let Some(model) = &preset.model {
+            models.retain(|m| m != model);
+            models.insert(0, model.clone());
+        }
+        (api_key, models, preset, source)
+    };
+    timer.payload(source.content.len(), 1);
+
+    let prompt = presets::prompt(&preset.instruction, &source.content);
+    let reply = GeminiClient::new(api_key).chat_with_fallback(&models, &prompt).await?;
+    let request = AiRequest {
+        instruction: &preset.instruction,
+        prompt: &prompt,
+        clip_ids: vec![source.id.clone()],
+        sensitive: source.sensitive,
+    };
+    record_ai_usage(&app, "run_preset", request, &reply);
+    let text = reply.text.trim().to_string();
+    let provenance = storage::Provenance {
+        operation: "run_preset".to_string(),
+        source_ids: vec![source.id.clone()],
+        model: Some(reply.model.clone()),
+        detail: Some(preset.name.clone()),
+        omitted_ids: Vec::new(),
+    };
+
+    let clip = match preset.output {
+        PresetOutput::InPlace => {
+            let edit = source.plan_content(text, &provenance.operation, Utc::now());
+            let mut storage = state.storage.write().unwrap();
+            let clip = storage.rewrite_clip(&source.id, edit, provenance)?;
+            storage.note_chrome();
+            storage.commit()?;
+            drop(storage);
+            emit_clip_updated(&app, &clip);
+            clip
+        }
+        PresetOutput::NewClip => {
+            let window_info = WindowInfo {
+                app_name: "Stack".to_string(),
+                window_title: format!("Preset: {}", preset.name),
+                ..Default::default()
+            };
+            let mut clip = ClipObject::new(text, window_info);
+            clip.title = Some(preset.name.clone());
+            clip.sensitive = source.sensitive;
+            clip.provenance = Some(provenance);
+            {
+                let mut storage = state.storage.write().unwrap();
+                storage.add_clip(clip.clone())?;
+                storage.note_chrome();
+                storage.commit()?;
+            }
+            emit_clip_captured(&app, &clip);
+            clip
+        }
+    };
+
+    Ok(PresetResult {
+        clip,
+        output: preset.output,
+        model: reply.model,
+    })
+}
+
+// ==================== SHELL INTEGRATION COMMANDS ====================
+
+/// Add the Explorer "Send to Stack" verb, listing every registry key written
+#[tauri::command]
+fn register_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    shell::register_shell_integration()
+}
+
+/// Remove the Explorer "Send to Stack" verb, listing every registry key removed
+#[tauri::command]
+fn unregister_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    shell::unregister_shell_integration()
+}
+
+// ==================== APP SETUP ====================
+
+#[cfg_attr(mobile, tauri::mobile_entry_point)]
+pub fn run() {
+    // Links and --add-file/--add-text given while Stack is already running
+    // go to that instance
+    let startup_requests = deeplink::requests_from_args();
+    if !startup_requests.is_empty() && deeplink::forward_to_running_instance(&startup_requests) {
+        return;
+    }
+
+    // Resolve the theme before any window exists so the first paint matches
+    let storage = AppStorage::load();
+    let startup_theme = theme::effective_theme(None, storage.settings.theme);
+
+    tauri::Builder::default()
+        .plugin(tauri_plugin_opener::init())
+        .plugin(tauri_plugin_clipboard_manager::init())
+        .plugin(tauri_plugin_dialog::init())
+        .plugin(tauri_plugin_global_shortcut::Builder::new().build())
+        .plugin(theme::init(startup_theme))
+        .on_window_event(|window, event| {
+            // Files dragged onto the window from Explorer or another app; safe
+            // mode stores nothing new
+            if let tauri::WindowEvent::DragDrop(tauri::DragDropEvent::Drop { paths, .. }) = event {
+                if window.label() == "main" && !paths.is_empty() && !safe_mode::is_active() {
+                    let app = window.app_handle().clone();
+                    let paths = paths.clone();
+                    std::thread::spawn(move || capture_dropped_files(&app, paths));
+                }
+            }
+
+            // Follow OS theme switches unless the user picked one
+            if let tauri::WindowEvent::ThemeChanged(_) = event {
+                if window.label() != "main" {
+                    return;
+                }
+                let preference = window.state::<AppState>().settings().theme;
+                if preference == ThemePreference::System {
+                    let effective = theme::effective_theme(Some(window.app_handle()), preference);
+                    let _ = window.emit("theme-changed", effective);
+                }
+            }
+        })
+        .manage(AppState::new(storage))
+        .invoke_handler(safe_mode_gate(tauri::generate_handler![
+            greet,
+            set_api_key,
+            get_settings,
+            get_shortcut_status,
+            get_webhook_status,
+            get_local_api_token,
+            regenerate_local_api_token,
+            get_effective_theme,
+            update_settings,
+            set_capture_paused,
+            capture_anyway,
+            get_models,
+            verify_api_key,
+            set_model_fallbacks,
+            get_ingest_transforms,
+            set_ingest_transforms,
+            generate_missing_titles,
+            draft_document,
+            get_ai_queue_status,
+            cancel_ai_queue,
+            retry_failed_ai_jobs,
+            magic_sort,
+            chat_submit,
+            get_clips,
+            search_clips,
+            rebuild_search_index,
+            get_clip,
+            get_clip_content,
+            get_clipboard_formats,
+            reveal_clip,
+            evaluate_expression,
+            get_last_foreground,
+            fix_stuck_modifiers,
+            capture_clip,
+            create_clip,
+            capture_dropped_text,
+            quick_note,
+            dismiss_quick_note,
+            delete_clip,
+            update_clip,
+            get_clip_revisions,
+            revert_clip,
+            update_clip_metadata,
+            set_clip_label,
+            set_clip_trashed,
+            set_label_for,
+            bulk_update_clips,
+            set_clip_sensitive,
+            set_clip_reminder,
+            snooze_reminder,
+            clear_reminder,
+            get_clips_by_label,
+            get_timeline,
+            reorder_clips,
+            sort_clips,
+            preview_merge,
+            merge_clips,
+            materialize_clip_file,
+            copy_clip_as_file,
+            export_clip,
+            attach_clip_asset,
+            diff_clips,
+            find_replace_clips,
+            get_all_content,
+            copy_all_to_clipboard,
+            export_all_content,
+            copy_clip,
+            hold_on_clipboard,
+            release_clipboard_hold,
+            share_clip_via_lan,
+            preview_attribution,
+            clear_all_clips,
+            get_scratchpad,
+            set_scratchpad,
+            copy_scratchpad_to_clipboard,
+            list_pastebooks,
+            get_pastebook_stats,
+            get_trashed_clips,
+            mark_pastebook_viewed,
+            get_active_pastebook,
+            create_pastebook,
+            find_pastebook_by_name,
+            switch_pastebook,
+            delete_pastebook,
+            rename_pastebook,
+            set_pastebook_mirror,
+            set_pastebook_group,
+            undo_last_operation,
+            list_pastebook_groups,
+            rename_pastebook_group,
+            reorder_pastebooks,
+            start_session,
+            end_session,
+            list_sessions,
+            get_session_report,
+            list_templates,
+            save_pastebook_as_template,
+            create_pastebook_from_template,
+            delete_template,
+            gc_assets,
+            get_asset_stats,
+            get_global_stats,
+            list_rules,
+            add_rule,
+            update_rule,
+            delete_rule,
+            test_rule,
+            list_presets,
+            add_preset,
+            update_preset,
+            delete_preset,
+            export_presets,
+            import_presets,
+            run_preset,
+            register_shell_integration,
+            unregister_shell_integration,
+            get_data_dir,
+            migrate_data_dir,
+            configure_sync,
+            sync_now,
+            get_sync_status,
+            get_perf_metrics,
+            preview_diagnostics,
+            generate_diagnostics,
+            get_storage_health,
+            retry_storage_init,
+            export_backup,
+            get_safe_mode_reason,
+            get_notifications_held,
+            hold_notification,
+            exit_safe_mode,
+            export_pastebook,
+            import_pastebook,
+            set_pastebook_auto_export,
+            run_auto_export_now,
+            get_auto_export_status,
+            get_ai_usage,
+            get_ai_history,
+            clear_ai_history,
+            import_windows_clipboard_history,
+            get_previous_shutdown,
+            restore_clean_backup,
+            dismiss_previous_shutdown
+        ]))
+        .setup(move |app| {
+            app.state::<AppState>().metrics.attach(app.handle().clone());
+            health::attach(app.handle().clone());
+            mirror::attach(app.handle().clone());
+
+            // Read back the oversized clip contents the startup load skipped,
+            // then build the search index over them. Anything reading clip
+            // content before then loads it itself (`storage::loaded`).
+            let contents_handle = app.handle().clone();
+            std::thread::spawn(move || {
+                let state = contents_handle.state::<AppState>();
+                state.storage.write().unwrap().load_contents();
+                let (index, built_at) = {
+                    let storage = state.storage.read().unwrap();
+                    (storage.build_search_index(), storage.revision)
+                };
+                state.storage.write().unwrap().install_search_index(index, built_at);
+            });
+            // After repeated crashes, skip shortcuts, watchers and schedulers
+            // so whatever caused them is less likely to run again
+            let safe = safe_mode::is_active();
+
+            // Keep trying an unwritable data dir so in-memory data gets saved once it's back
+            let health_handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(health::RETRY_INTERVAL);
+                if health::is_in_memory() {
+                    let state = health_handle.state::<AppState>();
+                    state.storage.write().unwrap().retry_persistence();
+                }
+            });
+
+            if !safe {
+                // Check reminders every minute. The first tick runs right away, so
+                // reminders that came due while Stack was closed fire on startup.
+                let reminder_handle = app.handle().clone();
+                tauri::async_runtime::spawn(async move {
+                    let mut interval = tokio::time::interval(std::time::Duration::from_secs(60));
+                    loop {
+                        interval.tick().await;
+                        fire_due_reminders(&reminder_handle);
+                    }
+                });
+            }
+
+            // Window chrome follows an explicit theme choice from the start
+            let theme_preference = app.state::<AppState>().settings().theme;
+            app.set_theme(theme::preferred_theme(theme_preference));
+
+            // Show the active pastebook in the window title, the new-clip badge and
+            // the pinned clips in the jump list
+            {
+                let state = app.state::<AppState>();
+                let storage = state.storage.read().unwrap();
+                storage.note_chrome();
+                titlebar::set_badge_enabled(app.handle(), storage.settings.unseen_badge);
+            }
+            titlebar::attach(app.handle().clone());
+
+            // Purge drag-out files left over from previous sessions
+            dragout::cleanup_stale_files();
+
+            // Collect assets orphaned by deletes or a crash mid-save
+            if !safe {
+                let state = app.state::<AppState>();
+                let storage = state.storage.read().unwrap();
+                let report = assets::gc_assets(&storage.referenced_assets());
+                if report.removed > 0 {
+                    println!("Removed {} orphaned assets", report.removed);
+                }
+            }
+
+            // Pick up a timed capture pause left over from the last session
+            let resume_at = {
+                let state = app.state::<AppState>();
+                let storage = state.storage.read().unwrap();
+                storage.settings.capture_resume_at.filter(|_| storage.settings.capture_paused)
+            };
+            if let Some(resume_at) = resume_at {
+                schedule_capture_resume(app.handle().clone(), resume_at);
+            }
+
+            if !safe {
+                // Close a session left open by the last run if it has gone idle since,
+                // then keep checking so idle sessions end without another capture
+                let session_handle = app.handle().clone();
+                std::thread::spawn(move || loop {
+                    {
+                        let state = session_handle.state::<AppState>();
+                        let mut storage = state.storage.write().unwrap();
+                        if storage.purge_trash(Utc::now()) > 0 {
+                            let _ = storage.commit();
+                        }
+                        if let Some(session) = storage.expire_idle_session(Utc::now()) {
+                            let _ = storage.save();
+                            drop(storage);
+                            let _ = session_handle.emit("session-ended", session);
+                        }
+                    }
+                    std::thread::sleep(std::time::Duration::from_secs(60));
+                });
+
+                // Run scheduled pastebook exports; the first check comes right
+                // away so exports missed while Stack was closed catch up
+                let export_handle = app.handle().clone();
+                std::thread::spawn(move || loop {
+                    autoexport::run_due(&export_handle);
+                    std::thread::sleep(autoexport::CHECK_INTERVAL);
+                });
+
+                // Track the foreground window so captures get the right source app
+                window::start_foreground_tracker();
+                // And which keys are really held, so a copy never leaves one stuck
+                input::start_key_tracking();
+            }
+
+            // Accept links and sent files/text, from our own launch or forwarded by later ones.
+            // Safe mode takes none: they would all change stored data.
+            if !safe {
+                deeplink::register_scheme();
+                let link_handle = app.handle().clone();
+                deeplink::start_listener(move |request| handle_launch_request(&link_handle, request));
+                for request in startup_requests {
+                    handle_launch_request(app.handle(), request);
+                }
+            } else if !startup_requests.is_empty() {
+                eprintln!("Safe mode: ignoring {} launch request(s)", startup_requests.len());
+            }
+
+            if !safe {
+                // Periodically reconcile with other devices when sync is configured
+                let sync_handle = app.handle().clone();
+                std::thread::spawn(move || loop {
+                    std::thread::sleep(sync::SYNC_INTERVAL);
+
+                    let state = sync_handle.state::<AppState>();
+                    if state.storage.read().unwrap().sync.is_none() {
+                        continue;
+                    }
+
+                    let result = sync::sync(&state.storage);
+                    let mut storage = state.storage.write().unwrap();
+                    match result {
+                        Ok(report) => {
+                            let _ = if report.applied > 0 {
+                                storage.note_chrome();
+                                storage.commit().map(|_| ())
+                            } else {
+                                storage.save()
+                            };
+                            drop(storage);
+                            if report.applied > 0 {
+                                broadcast(&sync_handle, "clips-updated", ());
+                            }
+                        }
+                        Err(e) => {
+                            if let Some(sync_state) = storage.sync.as_mut() {
+                                sync_state.last_error = Some(e);
+                            }
+                        }
+                    }
+                });
+            }
+
+            let settings = app.state::<AppState>().settings().clone();
+            webhooks::configure(&settings.webhooks);
+            notify::configure(settings.notification_policy);
+            announce::configure(settings.language);
+            jumplist::configure(settings.jump_list);
+            // Deliver a summary of notifications held during Focus Assist once it ends
+            let notify_handle = app.handle().clone();
+            std::thread::spawn(move || loop {
+                std::thread::sleep(notify::POLL_INTERVAL);
+                notify::report_holding(&notify_handle);
+                notify::deliver_held(&notify_handle);
+            });
+            if !safe {
+                // Capture (Ctrl+Shift+C) plus the optional hotkeys; a taken
+                // shortcut only disables itself
+                for (action, status) in hotkeys::reconcile(app.handle(), &settings, &HOTKEY_HANDLERS) {
+                    if let hotkeys::ShortcutStatus::Unavailable { error, .. } | hotkeys::ShortcutStatus::Invalid { error } = status {
+                        eprintln!("{:?} hotkey: {}", action, error);
+                    }
+                }
+                if let Err(e) = local_api::apply(app.handle(), &settings) {
+                    eprintln!("{}", e);
+                }
+            }
+            if let Err(e) = tray::create(app.handle(), &TRAY_HANDLERS) {
+                eprintln!("Couldn't create the tray icon: {}", e);
+            }
+
+            Ok(())
+        })
+        .build(tauri::generate_context!())
+        .expect("error while building tauri application")
+        .run(|app, event| {
+            // Last chance to save, whether quitting normally or the OS is
+            // shutting down. Not on a window closing: the sidebar or tray
+            // can keep the app running, and a later exit would find the
+            // flush already done.
+            if let tauri::RunEvent::Exit = event {
+                shutdown::flush_on_exit(app);
+            }
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::storage_with;
+    use serde_json::{json, Value};
+    use tauri::ipc::{CallbackFn, InvokeBody};
+    use tauri::test::{mock_builder, mock_context, noop_assets, MockRuntime, INVOKE_KEY};
+    use tauri::webview::InvokeRequest;
+    use tauri::WebviewWindow;
+
+    /// A mock app managing `storage`, with the state-only commands under test
+    fn app(storage: AppStorage) -> (tauri::App<MockRuntime>, WebviewWindow<MockRuntime>) {
+        let app = mock_builder()
+            .manage(AppState::new(storage))
+            .invoke_handler(tauri::generate_handler![
+                get_clips,
+                get_clip,
+                update_clip,
+                delete_clip,
+                set_clip_label,
+                list_pastebooks,
+                switch_pastebook,
+                preview_attribution,
+            ])
+            .build(mock_context(noop_assets()))
+            .expect("mock app");
+        let window = tauri::WebviewWindowBuilder::new(&app, "main", Default::default())
+            .build()
+            .expect("mock window");
+        (app, window)
+    }
+
+    fn invoke(window: &WebviewWindow<MockRuntime>, cmd: &str, args: Value) -> Result<Value, Value> {
+        tauri::test::get_ipc_response(
+            window,
+            InvokeRequest {
+                cmd: cmd.into(),
+                callback: CallbackFn(0),
+                error: CallbackFn(1),
+                url: "http://tauri.localhost".parse().unwrap(),
+                body: InvokeBody::Json(args),
+                headers: Default::default(),
+                invoke_key: INVOKE_KEY.to_string(),
+            },
+        )
+        .map(|body| body.deserialize::<Value>().unwrap())
+    }
+
+    #[test]
+    fn get_clips_returns_the_active_pastebook_with_its_revision() {
+        let (storage, ids) = storage_with(&["a", "b"]);
+        let (_app, window) = app(storage);
+        let response = invoke(&window, "get_clips", json!({})).unwrap();
+        assert_eq!(response["revision"], 0);
+        assert_eq!(response["data"][0]["id"], ids[1].as_str());
+        assert_eq!(response["data"][1]["content"], "a");
+    }
+
+    #[test]
+    fn unknown_clips_are_reported_as_not_found() {
+        let (_app, window) = app(AppStorage::default());
+        let err = invoke(&window, "get_clip", json!({ "id": "nope" })).unwrap_err();
+        assert_eq!(err, "NotFound: clip nope");
+
+        let deleted = invoke(&window, "delete_clip", json!({ "id": "nope" })).unwrap();
+        assert_eq!(deleted["data"], Value::Null);
+    }
+
+    #[test]
+    fn writes_bump_the_revision_and_reject_stale_ones() {
+        let (storage, ids) = storage_with(&["a"]);
+        let pastebook_id = storage.pastebooks[0].id.clone();
+        let (_app, window) = app(storage);
+
+        let args = json!({ "id": ids[0], "content": "edited", "expectedRevision": 0 });
+        let updated = invoke(&window, "update_clip", args.clone()).unwrap();
+        assert_eq!(updated, json!({ "revision": 1, "data": pastebook_id }));
+
+        let err = invoke(&window, "update_clip", args).unwrap_err();
+        assert!(err.as_str().unwrap().starts_with("Conflict"), "{}", err);
+
+        let clip = invoke(&window, "get_clip", json!({ "id": ids[0] })).unwrap();
+        assert_eq!(clip["content"], "edited");
+    }
+
+    #[test]
+    fn invalid_labels_are_refused_before_touching_storage() {
+        let (storage, ids) = storage_with(&["a"]);
+        let (_app, window) = app(storage);
+        let args = json!({ "id": ids[0], "label": "not-a-color" });
+        assert!(invoke(&window, "set_clip_label", args).is_err());
+        let clips = invoke(&window, "get_clips", json!({})).unwrap();
+        assert_eq!(clips["revision"], 0);
+    }
+
+    #[test]
+    fn switching_pastebooks_changes_what_get_clips_sees() {
+        let (mut storage, _) = storage_with(&["first book"]);
+        let first = storage.pastebooks[0].id.clone();
+        storage.create_pastebook("Second".to_string()).unwrap();
+        let (_app, window) = app(storage);
+
+        let listed = invoke(&window, "list_pastebooks", json!({})).unwrap();
+        assert_eq!(listed["data"].as_array().unwrap().len(), 2);
+        assert_eq!(invoke(&window, "get_clips", json!({})).unwrap()["data"], json!([]));
+
+        let switched = invoke(&window, "switch_pastebook", json!({ "id": first })).unwrap();
+        assert_eq!(switched["data"], true);
+        let clips = invoke(&window, "get_clips", json!({})).unwrap();
+        assert_eq!(clips["data"][0]["content"], "first book");
+
+        let missing = invoke(&window, "switch_pastebook", json!({ "id": "nope" })).unwrap();
+        assert_eq!(missing["data"], false);
+    }
+
+    #[test]
+    fn attribution_is_added_on_the_way_out_only() {
+        let (mut storage, ids) = storage_with(&["quote"]);
+        storage.settings.attribution_template = " ({source_app}{file})".to_string();
+        let copied = pastebook_text(&storage, &storage.pastebooks[0]);
+        assert_eq!(copied, "quote");
+
+        storage.settings.append_attribution = true;
+        let copied = pastebook_text(&storage, &storage.pastebooks[0]);
+        assert_eq!(copied, "quote (test.exe)");
+        assert_eq!(storage.get_clips()[0].content, "quote");
+
+        let (_app, window) = app(storage);
+        let saved = invoke(&window, "preview_attribution", json!({ "id": ids[0] })).unwrap();
+        assert_eq!(saved, " (test.exe)");
+        let args = json!({ "id": ids[0], "template": "{{{window_title}}}" });
+        assert_eq!(invoke(&window, "preview_attribution", args).unwrap(), "{Test Window}");
+        let err = invoke(&window, "preview_attribution", json!({ "id": "nope" })).unwrap_err();
+        assert_eq!(err, "NotFound: clip nope");
+    }
+
+    #[test]
+    fn capture_search_and_export_run_side_by_side() {
+        let state = AppState::new(AppStorage::default());
+        let dir = tempfile::tempdir().unwrap();
+        const CAPTURES: usize = 300;
+
+        std::thread::scope(|scope| {
+            scope.spawn(|| {
+                for i in 0..CAPTURES {
+                    let clip = crate::test_support::clip(&format!("capture {}", i));
+                    let stored = capture_path::store(&state.storage, clip);
+                    assert!(matches!(stored.outcome, CaptureOutcome::Added(_)));
+                    // The hotkey path reads settings without touching storage
+                    assert!(!state.settings().capture_paused);
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..100 {
+                    let found = state.storage.read().unwrap().search_clips("capture");
+                    assert!(found.len() <= CAPTURES);
+                }
+            });
+            scope.spawn(|| {
+                for i in 0..10 {
+                    let backup = state.storage.read().unwrap().backup_copy();
+                    backup.write_backup(&dir.path().join(format!("backup-{}.json", i))).unwrap();
+                    let (index, built_at) = {
+                        let storage = state.storage.read().unwrap();
+                        (storage.build_search_index(), storage.revision)
+                    };
+                    state.storage.write().unwrap().install_search_index(index, built_at);
+                }
+            });
+            scope.spawn(|| {
+                for _ in 0..50 {
+                    let mut storage = state.storage.write().unwrap();
+                    storage.settings.dedup_window_ms += 1;
+                    state.publish_settings(&storage.settings);
+                }
+            });
+        });
+
+        let storage = state.storage.read().unwrap();
+        assert_eq!(storage.get_clips().len(), CAPTURES);
+        assert_eq!(storage.search_clips("capture").len(), CAPTURES, "index kept up with every capture");
+        assert_eq!(state.settings().dedup_window_ms, storage.settings.dedup_window_ms);
+    }
 }
@@ -0,0 +1,204 @@
+use serde_json::{json, Value};
+use std::sync::{Arc, Mutex};
+use tauri::{AppHandle, Manager};
+use tiny_http::{Header, Method, Request, Response, Server};
+use url::Url;
+use uuid::Uuid;
+
+use crate::storage::{self, AppStorage, ClipObject, Settings};
+use crate::textutil;
+use crate::AppState;
+
+/// Clips per page when the request doesn't say
+const DEFAULT_PAGE_SIZE: usize = 50;
+const MAX_PAGE_SIZE: usize = 500;
+
+/// An error response: HTTP status and message
+type ApiError = (u16, String);
+
+struct Running {
+    port: u16,
+    server: Arc<Server>,
+}
+
+static RUNNING: Mutex<Option<Running>> = Mutex::new(None);
+
+/// A fresh bearer token for the local API
+pub fn generate_token() -> String {
+    format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple())
+}
+
+/// Start, stop or move the server so it matches `settings`. A stopped
+/// server's thread finishes the request in hand on its own, so this never
+/// waits on storage and can be called with it locked.
+pub fn apply(app: &AppHandle, settings: &Settings) -> Result<(), String> {
+    let wanted = settings.local_api_enabled.then_some(settings.local_api_port);
+    let mut running = RUNNING.lock().unwrap();
+    if running.as_ref().map(|r| r.port) == wanted {
+        return Ok(());
+    }
+
+    if let Some(old) = running.take() {
+        old.server.unblock();
+    }
+    let Some(port) = wanted else {
+        return Ok(());
+    };
+
+    let server = Server::http(("127.0.0.1", port))
+        .map_err(|e| format!("Couldn't start the local API on port {}: {}", port, e))?;
+    let server = Arc::new(server);
+    {
+        let server = server.clone();
+        let app = app.clone();
+        std::thread::spawn(move || {
+            // Ends once `unblock` is called
+            for request in server.incoming_requests() {
+                respond(&app, request);
+            }
+        });
+    }
+    *running = Some(Running { port, server });
+    Ok(())
+}
+
+fn respond(app: &AppHandle, request: Request) {
+    let token = request
+        .headers()
+        .iter()
+        .find(|h| h.field.equiv("Authorization"))
+        .and_then(|h| h.value.as_str().strip_prefix("Bearer "))
+        .map(str::to_string);
+
+    let result = {
+        let state = app.state::<AppState>();
+        let _timer = state.metrics.time("local_api");
+        // Held only while the response is built from storage, like a command
+        let storage = storage::loaded(&state.storage);
+        handle(&storage, request.method(), request.url(), token.as_deref())
+    };
+
+    let (status, body) = match result {
+        Ok(body) => (200, body),
+        Err((status, error)) => (status, json!({ "error": error })),
+    };
+    let response = Response::from_string(body.to_string())
+        .with_status_code(status)
+        .with_header(Header::from_bytes("Content-Type", "application/json").unwrap());
+    let _ = request.respond(response);
+}
+
+/// Route a request against storage
+fn handle(storage: &AppStorage, method: &Method, url: &str, token: Option<&str>) -> Result<Value, ApiError> {
+    if *method != Method::Get {
+        return Err((405, "Only GET is supported".to_string()));
+    }
+    match storage.settings.local_api_token.as_deref() {
+        Some(expected) if token.is_some_and(|token| textutil::secrets_match(token, expected)) => {}
+        _ => return Err((401, "Missing or wrong bearer token".to_string())),
+    }
+
+    let url = Url::parse(&format!("http://localhost{}", url))
+        .map_err(|e| (400, format!("Bad request URL: {}", e)))?;
+    let segments: Vec<&str> = url
+        .path_segments()
+        .map(|s| s.filter(|s| !s.is_empty()).collect())
+        .unwrap_or_default();
+
+    match segments.as_slice() {
+        ["pastebooks"] => Ok(json!(storage.list_pastebooks())),
+        ["pastebooks", id, "clips"] => {
+            let param = |name: &str| url.query_pairs().find(|(k, _)| k == name).map(|(_, v)| v.into_owned());
+            let number = |name: &str, default: usize| match param(name) {
+                Some(value) => value
+                    .parse::<usize>()
+                    .map_err(|_| (400, format!("'{}' must be a number", name))),
+                None => Ok(default),
+            };
+            let offset = number("offset", 0)?;
+            let limit = number("limit", DEFAULT_PAGE_SIZE)?.min(MAX_PAGE_SIZE);
+            let query = param("q").unwrap_or_default();
+
+            let clips = storage
+                .search_pastebook(id, &query)
+                .ok_or_else(|| (404, format!("NotFound: pastebook {}", id)))?;
+            let page: Vec<ClipObject> = clips.iter().skip(offset).take(limit).map(|c| public(c)).collect();
+            Ok(json!({ "total": clips.len(), "offset": offset, "limit": limit, "clips": page }))
+        }
+        ["clips", id] => storage
+            .find_clip(id)
+            .map(|clip| json!(public(clip)))
+            .ok_or_else(|| (404, format!("NotFound: clip {}", id))),
+        _ => Err((404, format!("No endpoint at {}", url.path()))),
+    }
+}
+
+/// A clip as served: sensitive clips keep their metadata but not their text
+fn public(clip: &ClipObject) -> ClipObject {
+    let mut clip = clip.clone();
+    if clip.sensitive {
+        clip.content = String::new();
+        clip.original_content = None;
+        clip.history.clear();
+    }
+    clip
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::storage_with;
+
+    fn get(storage: &AppStorage, url: &str) -> Result<Value, ApiError> {
+        handle(storage, &Method::Get, url, Some("secret"))
+    }
+
+    fn storage() -> (AppStorage, Vec<String>) {
+        let (mut storage, ids) = storage_with(&["alpha", "beta", "gamma note", "delta note"]);
+        storage.settings.local_api_token = Some("secret".to_string());
+        (storage, ids)
+    }
+
+    #[test]
+    fn requests_need_the_token_and_get() {
+        let (storage, _) = storage();
+        assert_eq!(handle(&storage, &Method::Get, "/pastebooks", None).unwrap_err().0, 401);
+        assert_eq!(handle(&storage, &Method::Get, "/pastebooks", Some("nope")).unwrap_err().0, 401);
+        assert_eq!(handle(&storage, &Method::Get, "/pastebooks", Some("secreT")).unwrap_err().0, 401);
+        assert_eq!(handle(&storage, &Method::Post, "/pastebooks", Some("secret")).unwrap_err().0, 405);
+        assert_eq!(get(&storage, "/settings").unwrap_err().0, 404);
+
+        let mut untokened = storage;
+        untokened.settings.local_api_token = None;
+        assert_eq!(handle(&untokened, &Method::Get, "/pastebooks", Some("")).unwrap_err().0, 401);
+    }
+
+    #[test]
+    fn pastebook_clips_are_paged_and_searchable() {
+        let (storage, _) = storage();
+        let pastebooks = get(&storage, "/pastebooks").unwrap();
+        let id = pastebooks[0]["id"].as_str().unwrap().to_string();
+        assert_eq!(pastebooks[0]["clip_count"], 4);
+
+        let page = get(&storage, &format!("/pastebooks/{}/clips?offset=1&limit=2", id)).unwrap();
+        assert_eq!(page["total"], 4);
+        assert_eq!(page["clips"].as_array().unwrap().len(), 2);
+
+        let found = get(&storage, &format!("/pastebooks/{}/clips?q=note", id)).unwrap();
+        assert_eq!(found["total"], 2);
+        assert_eq!(get(&storage, &format!("/pastebooks/{}/clips?limit=x", id)).unwrap_err().0, 400);
+        assert_eq!(get(&storage, "/pastebooks/missing/clips").unwrap_err().0, 404);
+    }
+
+    #[test]
+    fn single_clips_hide_sensitive_text() {
+        let (mut storage, ids) = storage();
+        assert_eq!(get(&storage, &format!("/clips/{}", ids[0])).unwrap()["content"], "alpha");
+
+        storage.set_clip_sensitive(&ids[0], true, None);
+        let clip = get(&storage, &format!("/clips/{}", ids[0])).unwrap();
+        assert_eq!(clip["content"], "");
+        assert_eq!(clip["sensitive"], true);
+        assert_eq!(get(&storage, "/clips/missing").unwrap_err().0, 404);
+    }
+}
@@ -0,0 +1,178 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Instant;
+use tauri::{AppHandle, Emitter};
+
+/// Number of recent invocations kept per command
+const WINDOW: usize = 128;
+/// Commands slower than this emit a `slow-operation` event
+const SLOW_THRESHOLD_MS: f64 = 250.0;
+
+/// Rolling timings for one command (fixed-size ring, no allocation per call)
+struct CommandStats {
+    durations_us: [u64; WINDOW],
+    len: usize,
+    next: usize,
+    calls: u64,
+    last_payload_bytes: usize,
+    last_clip_count: usize,
+}
+
+impl CommandStats {
+    fn new() -> Self {
+        Self {
+            durations_us: [0; WINDOW],
+            len: 0,
+            next: 0,
+            calls: 0,
+            last_payload_bytes: 0,
+            last_clip_count: 0,
+        }
+    }
+
+    fn record(&mut self, duration_us: u64, payload_bytes: usize, clip_count: usize) {
+        self.durations_us[self.next] = duration_us;
+        self.next = (self.next + 1) % WINDOW;
+        self.len = (self.len + 1).min(WINDOW);
+        self.calls += 1;
+        self.last_payload_bytes = payload_bytes;
+        self.last_clip_count = clip_count;
+    }
+}
+
+/// Summary of a command's recent timings
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandMetrics {
+    pub command: String,
+    pub calls: u64,
+    pub samples: usize,
+    pub p50_ms: f64,
+    pub p95_ms: f64,
+    pub max_ms: f64,
+    pub last_payload_bytes: usize,
+    pub last_clip_count: usize,
+}
+
+/// Payload of the `slow-operation` event
+#[derive(Debug, Clone, Serialize)]
+struct SlowOperation {
+    command: &'static str,
+    duration_ms: f64,
+}
+
+/// In-memory per-command timing store
+#[derive(Default)]
+pub struct Metrics {
+    commands: Mutex<HashMap<&'static str, CommandStats>>,
+    app: OnceLock<AppHandle>,
+}
+
+impl Metrics {
+    /// Give the store an app handle so it can emit `slow-operation`
+    pub fn attach(&self, app: AppHandle) {
+        let _ = self.app.set(app);
+    }
+
+    /// Start timing a command; the duration is recorded when the timer drops
+    pub fn time(&self, command: &'static str) -> CommandTimer<'_> {
+        CommandTimer {
+            metrics: self,
+            command,
+            started: Instant::now(),
+            payload_bytes: 0,
+            clip_count: 0,
+        }
+    }
+
+    fn record(
+        &self,
+        command: &'static str,
+        duration_us: u64,
+        payload_bytes: usize,
+        clip_count: usize,
+    ) {
+        self.commands
+            .lock()
+            .unwrap()
+            .entry(command)
+            .or_insert_with(CommandStats::new)
+            .record(duration_us, payload_bytes, clip_count);
+
+        let duration_ms = duration_us as f64 / 1000.0;
+        if duration_ms > SLOW_THRESHOLD_MS {
+            if let Some(app) = self.app.get() {
+                let _ = app.emit(
+                    "slow-operation",
+                    SlowOperation {
+                        command,
+                        duration_ms,
+                    },
+                );
+            }
+        }
+    }
+
+    /// p50/p95/max per command over the recent window
+    pub fn summary(&self) -> Vec<CommandMetrics> {
+        let commands = self.commands.lock().unwrap();
+
+        let mut summary: Vec<CommandMetrics> = commands
+            .iter()
+            .map(|(command, stats)| {
+                let mut samples = stats.durations_us[..stats.len].to_vec();
+                samples.sort_unstable();
+                let percentile = |p: f64| {
+                    if samples.is_empty() {
+                        return 0.0;
+                    }
+                    let index = ((samples.len() - 1) as f64 * p).round() as usize;
+                    samples[index] as f64 / 1000.0
+                };
+
+                CommandMetrics {
+                    command: command.to_string(),
+                    calls: stats.calls,
+                    samples: samples.len(),
+                    p50_ms: percentile(0.50),
+                    p95_ms: percentile(0.95),
+                    max_ms: samples.last().copied().unwrap_or(0) as f64 / 1000.0,
+                    last_payload_bytes: stats.last_payload_bytes,
+                    last_clip_count: stats.last_clip_count,
+                }
+            })
+            .collect();
+
+        summary.sort_by(|a, b| a.command.cmp(&b.command));
+        summary
+    }
+}
+
+/// Guard that records a command's duration when dropped
+pub struct CommandTimer<'a> {
+    metrics: &'a Metrics,
+    command: &'static str,
+    started: Instant,
+    payload_bytes: usize,
+    clip_count: usize,
+}
+
+impl CommandTimer<'_> {
+    /// Note the size of the data the command handled
+    pub fn payload(&mut self, payload_bytes: usize, clip_count: usize) {
+        self.payload_bytes = payload_bytes;
+        self.clip_count = clip_count;
+    }
+}
+
+impl Drop for CommandTimer<'_> {
+    fn drop(&mut self) {
+        let duration_us = self.started.elapsed().as_micros() as u64;
+        self.metrics.record(
+            self.command,
+            duration_us,
+            self.payload_bytes,
+            self.clip_count,
+        );
+    }
+}
@@ -0,0 +1,414 @@
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+const JOURNAL_FILE: &str = "migration.journal";
+/// Suffix of new files written ahead of the switch
+const STAGED_SUFFIX: &str = "migrating";
+/// Suffix a file replaced or removed by a migration is kept under until it commits
+const BACKUP_SUFFIX: &str = "premigration";
+
+/// One step of the switch. Paths are relative to the data dir.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum FileOp {
+    /// Move a staged file into place. `replaces` records whether `to`
+    /// existed before the run, i.e. whether there's an old file to restore.
+    Rename {
+        from: PathBuf,
+        to: PathBuf,
+        #[serde(default)]
+        replaces: bool,
+    },
+    /// Take a file out of the layout
+    Remove { path: PathBuf },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum JournalState {
+    /// Switching; a crash here is finished if it can be, else reverted
+    Pending,
+    /// Every op is done; only the backups are left to clean up
+    Committed,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Journal {
+    name: String,
+    state: JournalState,
+    ops: Vec<FileOp>,
+    /// Ops fully done; the one at this index may be part done
+    done: usize,
+}
+
+/// What `recover` found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Recovery {
+    /// No migration was interrupted
+    Clean,
+    /// The named migration was finished
+    RolledForward(String),
+    /// The named migration couldn't be finished and was undone
+    RolledBack(String),
+}
+
+/// A change to the data dir's file layout. New files are staged first;
+/// `run` then writes `migration.journal` listing the renames and removals
+/// that switch layouts and performs them, recording progress in the journal.
+/// Every step can be repeated and undone, so a run cut short at any point is
+/// finished or reverted by `recover` on the next start.
+#[derive(Debug)]
+#[cfg_attr(not(test), allow(dead_code))]
+pub struct Migration {
+    dir: PathBuf,
+    name: String,
+    ops: Vec<FileOp>,
+}
+
+#[cfg_attr(not(test), allow(dead_code))]
+impl Migration {
+    pub fn new(dir: &Path, name: &str) -> Self {
+        Self {
+            dir: dir.to_path_buf(),
+            name: name.to_string(),
+            ops: Vec::new(),
+        }
+    }
+
+    /// Stage new contents for `path` (relative to the data dir); it replaces
+    /// the current file when the migration runs
+    pub fn write(&mut self, path: &Path, contents: &[u8]) -> Result<(), String> {
+        let staged = with_suffix(path, STAGED_SUFFIX);
+        write_synced(&self.dir.join(&staged), contents)?;
+        self.ops.push(FileOp::Rename {
+            from: staged,
+            to: path.to_path_buf(),
+            replaces: false,
+        });
+        Ok(())
+    }
+
+    /// Remove `path` (relative to the data dir) when the migration runs
+    pub fn remove(&mut self, path: &Path) {
+        self.ops.push(FileOp::Remove { path: path.to_path_buf() });
+    }
+
+    /// Switch to the new layout
+    pub fn run(self) -> Result<(), String> {
+        self.run_with(&mut |_| false)
+    }
+
+    /// `run`, calling `crash` before each change to the file system; when
+    /// it returns true the run stops there as if the process had died
+    fn run_with(self, crash: &mut dyn FnMut(usize) -> bool) -> Result<(), String> {
+        if self.dir.join(JOURNAL_FILE).exists() {
+            return Err("Another migration is unfinished; restart Stack to recover it".to_string());
+        }
+        let mut ops = self.ops;
+        for op in &mut ops {
+            if let FileOp::Rename { to, replaces, .. } = op {
+                *replaces = self.dir.join(&*to).exists();
+            }
+        }
+        let mut journal = Journal {
+            name: self.name,
+            state: JournalState::Pending,
+            ops,
+            done: 0,
+        };
+        let mut steps = Steps { crash, count: 0 };
+        steps.next()?;
+        write_journal(&self.dir, &journal)?;
+        forward(&self.dir, &mut journal, &mut steps)?;
+        finish(&self.dir, &mut journal, &mut steps)
+    }
+}
+
+/// Counts changes to the file system so tests can stop a run at any one
+struct Steps<'a> {
+    crash: &'a mut dyn FnMut(usize) -> bool,
+    count: usize,
+}
+
+impl Steps<'_> {
+    fn next(&mut self) -> Result<(), String> {
+        let step = self.count;
+        self.count += 1;
+        if (self.crash)(step) {
+            return Err(format!("Migration stopped at step {}", step));
+        }
+        Ok(())
+    }
+}
+
+/// `path` with `.suffix` added to its file name
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(".");
+    name.push(suffix);
+    path.with_file_name(name)
+}
+
+fn write_synced(path: &Path, contents: &[u8]) -> Result<(), String> {
+    let mut file = File::create(path).map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+    file.write_all(contents)
+        .and_then(|_| file.sync_all())
+        .map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Replace the journal in one rename, so it's never half written
+fn write_journal(dir: &Path, journal: &Journal) -> Result<(), String> {
+    let json = serde_json::to_vec(journal).map_err(|e| format!("Failed to serialize journal: {}", e))?;
+    let temp = dir.join(with_suffix(Path::new(JOURNAL_FILE), "tmp"));
+    write_synced(&temp, &json)?;
+    rename(&temp, &dir.join(JOURNAL_FILE))
+}
+
+fn rename(from: &Path, to: &Path) -> Result<(), String> {
+    fs::rename(from, to).map_err(|e| format!("Failed to move {} to {}: {}", from.display(), to.display(), e))
+}
+
+fn remove(path: &Path) -> Result<(), String> {
+    match fs::remove_file(path) {
+        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+            Err(format!("Failed to remove {}: {}", path.display(), e))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Do the ops from `journal.done` on; each checks what's already done first
+fn forward(dir: &Path, journal: &mut Journal, steps: &mut Steps) -> Result<(), String> {
+    while journal.done < journal.ops.len() {
+        match &journal.ops[journal.done] {
+            FileOp::Rename { from, to, replaces } => {
+                let (from, to) = (dir.join(from), dir.join(to));
+                let backup = with_suffix(&to, BACKUP_SUFFIX);
+                if from.exists() {
+                    if *replaces && !backup.exists() {
+                        steps.next()?;
+                        rename(&to, &backup)?;
+                    }
+                    steps.next()?;
+                    rename(&from, &to)?;
+                } else {
+                    // Already moved if the new file is in place (with the old one backed up)
+                    let moved = to.exists() && (!*replaces || backup.exists());
+                    if !moved {
+                        return Err(format!("Staged file {} is missing", from.display()));
+                    }
+                }
+            }
+            FileOp::Remove { path } => {
+                let path = dir.join(path);
+                if path.exists() {
+                    steps.next()?;
+                    rename(&path, &with_suffix(&path, BACKUP_SUFFIX))?;
+                }
+            }
+        }
+        journal.done += 1;
+        steps.next()?;
+        write_journal(dir, journal)?;
+    }
+    Ok(())
+}
+
+/// Mark the migration committed, then drop its backups and journal
+fn finish(dir: &Path, journal: &mut Journal, steps: &mut Steps) -> Result<(), String> {
+    if journal.state != JournalState::Committed {
+        journal.state = JournalState::Committed;
+        steps.next()?;
+        write_journal(dir, journal)?;
+    }
+    for op in &journal.ops {
+        let path = match op {
+            FileOp::Rename { to, .. } => to,
+            FileOp::Remove { path } => path,
+        };
+        steps.next()?;
+        remove(&with_suffix(&dir.join(path), BACKUP_SUFFIX))?;
+    }
+    steps.next()?;
+    remove(&dir.join(JOURNAL_FILE))
+}
+
+/// Undo the ops a pending migration started, newest first, and drop its
+/// staged files
+fn backward(dir: &Path, journal: &Journal) -> Result<(), String> {
+    let started = (journal.done + 1).min(journal.ops.len());
+    for op in journal.ops[..started].iter().rev() {
+        match op {
+            FileOp::Rename { to, replaces, .. } => {
+                let to = dir.join(to);
+                let backup = with_suffix(&to, BACKUP_SUFFIX);
+                if backup.exists() {
+                    rename(&backup, &to)?;
+                } else if !*replaces {
+                    remove(&to)?;
+                }
+            }
+            FileOp::Remove { path } => {
+                let path = dir.join(path);
+                let backup = with_suffix(&path, BACKUP_SUFFIX);
+                if backup.exists() {
+                    rename(&backup, &path)?;
+                }
+            }
+        }
+    }
+    for op in &journal.ops {
+        if let FileOp::Rename { from, .. } = op {
+            remove(&dir.join(from))?;
+        }
+    }
+    remove(&dir.join(JOURNAL_FILE))
+}
+
+/// Finish or undo a migration that was cut short. Call before loading
+/// storage from `dir`.
+pub fn recover(dir: &Path) -> Result<Recovery, String> {
+    let _ = fs::remove_file(dir.join(with_suffix(Path::new(JOURNAL_FILE), "tmp")));
+    let path = dir.join(JOURNAL_FILE);
+    let json = match fs::read(&path) {
+        Ok(json) => json,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            remove_stray_staged(dir);
+            return Ok(Recovery::Clean);
+        }
+        Err(e) => return Err(format!("Failed to read {}: {}", path.display(), e)),
+    };
+    let mut journal: Journal = serde_json::from_slice(&json)
+        .map_err(|e| format!("{} is corrupt: {}", path.display(), e))?;
+
+    let mut steps = Steps { crash: &mut |_| false, count: 0 };
+    let name = journal.name.clone();
+    if journal.state == JournalState::Pending {
+        if let Err(e) = forward(dir, &mut journal, &mut steps) {
+            eprintln!("Couldn't finish migration '{}' ({}), undoing it", name, e);
+            backward(dir, &journal)?;
+            return Ok(Recovery::RolledBack(name));
+        }
+    }
+    finish(dir, &mut journal, &mut steps)?;
+    Ok(Recovery::RolledForward(name))
+}
+
+/// Staged files left by a migration that stopped before writing its journal
+fn remove_stray_staged(dir: &Path) {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return;
+    };
+    let suffix = format!(".{}", STAGED_SUFFIX);
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().ends_with(&suffix) {
+            let _ = fs::remove_file(entry.path());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::AppStorage;
+    use crate::test_support::{clip, contents};
+
+    const STORE: &str = "pastebooks.json";
+
+    /// A data dir with a saved store holding one clip, plus a file the
+    /// migration removes
+    fn old_layout(dir: &Path) {
+        let mut storage = AppStorage::load_from(dir.join(STORE));
+        storage.add_clip(clip("old layout")).unwrap();
+        storage.save().unwrap();
+        fs::write(dir.join("legacy.idx"), "legacy").unwrap();
+    }
+
+    /// Rewrite the store, add a sidecar and drop the legacy file
+    fn migration(dir: &Path) -> Migration {
+        let mut storage = AppStorage::load_from(dir.join(STORE));
+        storage.get_active_pastebook_mut().unwrap().clips[0].content = "new layout".to_string();
+        let json = serde_json::to_vec(&storage).unwrap();
+
+        let mut migration = Migration::new(dir, "sidecars");
+        migration.write(Path::new(STORE), &json).unwrap();
+        migration.write(Path::new("sidecar.json"), b"{}").unwrap();
+        migration.remove(Path::new("legacy.idx"));
+        migration
+    }
+
+    fn leftovers(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .flatten()
+            .map(|e| e.file_name().to_string_lossy().to_string())
+            .filter(|n| n.ends_with(STAGED_SUFFIX) || n.ends_with(BACKUP_SUFFIX) || n.starts_with(JOURNAL_FILE))
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn a_migration_stopped_at_any_step_recovers_to_one_layout() {
+        let mut step = 0;
+        loop {
+            let dir = tempfile::tempdir().unwrap();
+            old_layout(dir.path());
+            let crashed = migration(dir.path())
+                .run_with(&mut |at| at == step)
+                .is_err();
+
+            let recovery = recover(dir.path()).unwrap();
+            let content = contents(&AppStorage::load_from(dir.path().join(STORE)));
+            let migrated = content == vec!["new layout"];
+            assert!(migrated || content == vec!["old layout"], "step {}: {:?}", step, content);
+            // Every file matches the layout the store is in
+            assert_eq!(dir.path().join("sidecar.json").exists(), migrated, "step {}", step);
+            assert_eq!(dir.path().join("legacy.idx").exists(), !migrated, "step {}", step);
+            assert!(leftovers(dir.path()).is_empty(), "step {}: {:?}", step, leftovers(dir.path()));
+            if !crashed {
+                assert_eq!(recovery, Recovery::Clean);
+                assert!(migrated);
+                break;
+            }
+            // Before the journal is written there's nothing to recover
+            assert_eq!(recovery == Recovery::Clean, step == 0, "step {}", step);
+            step += 1;
+        }
+        assert!(step > 10, "only {} steps were exercised", step);
+    }
+
+    #[test]
+    fn a_missing_staged_file_rolls_back() {
+        let dir = tempfile::tempdir().unwrap();
+        old_layout(dir.path());
+        // Remove the legacy file and add the sidecar, then stop before the
+        // store is backed up and lose its staged file
+        let mut partial = migration(dir.path());
+        partial.ops.swap(0, 2);
+        assert!(partial.run_with(&mut |at| at == 5).is_err());
+        assert!(dir.path().join("sidecar.json").exists());
+        fs::remove_file(dir.path().join("pastebooks.json.migrating")).unwrap();
+
+        assert_eq!(recover(dir.path()).unwrap(), Recovery::RolledBack("sidecars".to_string()));
+        assert_eq!(contents(&AppStorage::load_from(dir.path().join(STORE))), vec!["old layout"]);
+        assert!(dir.path().join("legacy.idx").exists());
+        assert!(leftovers(dir.path()).is_empty());
+
+        // Nothing stops the migration being run again
+        migration(dir.path()).run().unwrap();
+        assert_eq!(contents(&AppStorage::load_from(dir.path().join(STORE))), vec!["new layout"]);
+    }
+
+    #[test]
+    fn stray_staged_files_without_a_journal_are_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        old_layout(dir.path());
+        let _unrun = migration(dir.path());
+        assert_eq!(recover(dir.path()).unwrap(), Recovery::Clean);
+        assert!(leftovers(dir.path()).is_empty());
+        assert_eq!(contents(&AppStorage::load_from(dir.path().join(STORE))), vec!["old layout"]);
+    }
+}
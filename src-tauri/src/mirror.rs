@@ -0,0 +1,187 @@
+use chrono::Local;
+use serde::Serialize;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, Sender};
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+use crate::paths;
+use crate::storage::ClipObject;
+
+/// Tries per entry; another editor holding the file locked usually lets go quickly
+const ATTEMPTS: u32 = 5;
+const RETRY_DELAY: Duration = Duration::from_millis(200);
+
+/// Payload of the `mirror-failed` event
+#[derive(Debug, Clone, Serialize)]
+pub struct MirrorFailed {
+    pub pastebook_id: String,
+    pub path: PathBuf,
+    pub error: String,
+}
+
+struct Entry {
+    pastebook_id: String,
+    path: PathBuf,
+    text: String,
+}
+
+static APP: OnceLock<AppHandle> = OnceLock::new();
+/// Appends run on one background thread, in capture order
+static QUEUE: OnceLock<Mutex<Sender<Entry>>> = OnceLock::new();
+
+/// Give mirroring an app handle so failures reach the UI
+pub fn attach(app: AppHandle) {
+    let _ = APP.set(app);
+}
+
+/// Check a mirror file path: absolute, its folder must exist, and it can't
+/// be a folder or live inside Stack's own data dir
+pub fn validate_path(path: &Path) -> Result<PathBuf, String> {
+    if !path.is_absolute() {
+        return Err("Mirror file path must be absolute".to_string());
+    }
+    if path.is_dir() {
+        return Err("Mirror file path is a folder".to_string());
+    }
+    let (Some(parent), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err("Mirror file path has no file name".to_string());
+    };
+    let parent = fs::canonicalize(parent)
+        .map_err(|_| format!("Folder {} does not exist", parent.display()))?;
+
+    let data_dir = paths::data_dir();
+    let data_dir = fs::canonicalize(&data_dir).unwrap_or(data_dir);
+    if parent.starts_with(&data_dir) {
+        return Err("Mirror file can't be inside Stack's data folder".to_string());
+    }
+    Ok(parent.join(name))
+}
+
+/// The text appended for a clip: a timestamp heading, then the content
+pub fn format_entry(clip: &ClipObject) -> String {
+    let local = clip.metadata.timestamp.with_timezone(&Local);
+    let mut heading = format!("## {}", local.format("%Y-%m-%d %H:%M"));
+    if !clip.metadata.source_app.is_empty() {
+        heading.push_str(&format!(" — {}", clip.metadata.source_app));
+    }
+    format!("{}\n\n{}\n\n", heading, clip.content.trim_end())
+}
+
+/// Queue a clip to be appended to a pastebook's mirror file. Never blocks
+/// the caller; sensitive clips are not mirrored.
+pub fn append(pastebook_id: &str, path: &Path, clip: &ClipObject) {
+    if clip.sensitive {
+        return;
+    }
+    let entry = Entry {
+        pastebook_id: pastebook_id.to_string(),
+        path: path.to_path_buf(),
+        text: format_entry(clip),
+    };
+    let queue = QUEUE.get_or_init(|| {
+        let (sender, receiver) = mpsc::channel::<Entry>();
+        std::thread::spawn(move || {
+            for entry in receiver {
+                if let Err(error) = append_with_retry(&entry.path, &entry.text) {
+                    report_failure(entry, error);
+                }
+            }
+        });
+        Mutex::new(sender)
+    });
+    let _ = queue.lock().unwrap().send(entry);
+}
+
+fn report_failure(entry: Entry, error: String) {
+    eprintln!("Failed to mirror to {}: {}", entry.path.display(), error);
+    if let Some(app) = APP.get() {
+        let _ = app.emit(
+            "mirror-failed",
+            MirrorFailed {
+                pastebook_id: entry.pastebook_id,
+                path: entry.path,
+                error,
+            },
+        );
+    }
+}
+
+fn append_with_retry(path: &Path, text: &str) -> Result<(), String> {
+    let mut attempt = 1;
+    loop {
+        match append_once(path, text) {
+            Ok(()) => return Ok(()),
+            Err(_) if attempt < ATTEMPTS => {
+                attempt += 1;
+                std::thread::sleep(RETRY_DELAY);
+            }
+            Err(e) => return Err(e.to_string()),
+        }
+    }
+}
+
+/// Append `text`, first ending the file's last line if the user left it open
+fn append_once(path: &Path, text: &str) -> std::io::Result<()> {
+    let needs_break = match File::open(path) {
+        Ok(mut file) if file.metadata()?.len() > 0 => {
+            let mut last = [0u8; 1];
+            file.seek(SeekFrom::End(-1))?;
+            file.read_exact(&mut last)?;
+            last[0] != b'\n'
+        }
+        _ => false,
+    };
+
+    let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+    if needs_break {
+        file.write_all(b"\n\n")?;
+    }
+    file.write_all(text.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+
+    #[test]
+    fn entries_are_appended_after_existing_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        fs::write(&path, "# Notes").unwrap();
+
+        let first = clip("first\n");
+        append_once(&path, &format_entry(&first)).unwrap();
+        append_once(&path, &format_entry(&clip("second"))).unwrap();
+
+        let written = fs::read_to_string(&path).unwrap();
+        let headings: Vec<&str> = written.lines().filter(|l| l.starts_with("## ")).collect();
+        assert_eq!(headings.len(), 2);
+        assert!(headings[0].ends_with(" — test.exe"));
+        assert!(written.starts_with("# Notes\n\n## "));
+        assert!(written.contains("\n\nfirst\n\n## "));
+        assert!(written.ends_with("\n\nsecond\n\n"));
+    }
+
+    #[test]
+    fn missing_file_is_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("new.md");
+        append_with_retry(&path, "text").unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "text");
+    }
+
+    #[test]
+    fn paths_are_validated() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(validate_path(Path::new("notes.md")).is_err());
+        assert!(validate_path(dir.path()).is_err());
+        assert!(validate_path(&dir.path().join("missing").join("notes.md")).is_err());
+        let valid = validate_path(&dir.path().join("notes.md")).unwrap();
+        assert_eq!(valid.file_name().unwrap(), "notes.md");
+    }
+}
@@ -0,0 +1,191 @@
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+use tauri::{AppHandle, Emitter};
+
+/// How often held notifications check whether they can be delivered
+pub const POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+/// When Stack shows notifications
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationPolicy {
+    Always,
+    /// Hold them while Windows says the user is busy (Focus Assist quiet
+    /// hours, presenting, a full-screen app) and sum them up afterwards
+    #[default]
+    RespectFocusAssist,
+    Never,
+}
+
+/// What a notification is about, for the summary of held ones
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NotificationKind {
+    Capture,
+    Reminder,
+    /// Captures held back from an app copying too fast
+    RateLimited,
+    /// Any other toast the UI would have shown
+    Other,
+}
+
+impl NotificationKind {
+    fn describe(self, count: usize) -> String {
+        match (self, count) {
+            (Self::Capture, 1) => "1 clip captured".to_string(),
+            (Self::Capture, n) => format!("{} clips captured", n),
+            (Self::Reminder, 1) => "1 reminder came due".to_string(),
+            (Self::Reminder, n) => format!("{} reminders came due", n),
+            (Self::RateLimited, 1) => "1 rapid capture was held back".to_string(),
+            (Self::RateLimited, n) => format!("{} rapid captures were held back", n),
+            (Self::Other, 1) => "1 other notification".to_string(),
+            (Self::Other, n) => format!("{} other notifications", n),
+        }
+    }
+}
+
+/// Payload of the `notification` event; the UI shows it as a toast
+#[derive(Debug, Clone, Serialize)]
+pub struct Notification {
+    pub kind: NotificationKind,
+    /// Toast style: "success", "info" or "error"
+    pub level: &'static str,
+    pub message: String,
+}
+
+static POLICY: Mutex<NotificationPolicy> = Mutex::new(NotificationPolicy::RespectFocusAssist);
+/// Held notifications per kind, in the order each kind first came up
+static HELD: Mutex<Vec<(NotificationKind, usize)>> = Mutex::new(Vec::new());
+
+/// Whether the windows were last told notifications are being held
+static HOLDING: AtomicBool = AtomicBool::new(false);
+
+/// Payload of `notifications-held`
+#[derive(Debug, Clone, Serialize)]
+pub struct NotificationsHeld {
+    pub held: bool,
+}
+
+/// Use this policy from now on
+pub fn configure(policy: NotificationPolicy) {
+    *POLICY.lock().unwrap() = policy;
+}
+
+/// Whether the policy keeps notifications from showing right now
+pub fn is_holding() -> bool {
+    match *POLICY.lock().unwrap() {
+        NotificationPolicy::Always => false,
+        NotificationPolicy::RespectFocusAssist => user_is_busy(),
+        NotificationPolicy::Never => true,
+    }
+}
+
+/// Tell the windows when notifications start or stop being held, so the
+/// toasts they raise themselves follow the policy too
+pub fn report_holding(app: &AppHandle) {
+    let holding = is_holding();
+    if HOLDING.swap(holding, Ordering::Relaxed) != holding {
+        let _ = app.emit("notifications-held", NotificationsHeld { held: holding });
+    }
+}
+
+/// Count a toast the UI held back, for the summary; dropped under `never`
+pub fn hold_other() {
+    if *POLICY.lock().unwrap() != NotificationPolicy::Never {
+        hold(&mut HELD.lock().unwrap(), NotificationKind::Other, 1);
+    }
+}
+
+/// Whether Windows asks apps to keep quiet right now
+#[cfg(windows)]
+pub fn user_is_busy() -> bool {
+    use windows::Win32::UI::Shell::{SHQueryUserNotificationState, QUNS_ACCEPTS_NOTIFICATIONS, QUNS_APP};
+
+    match unsafe { SHQueryUserNotificationState() } {
+        Ok(state) => state != QUNS_ACCEPTS_NOTIFICATIONS && state != QUNS_APP,
+        Err(_) => false,
+    }
+}
+
+#[cfg(not(windows))]
+pub fn user_is_busy() -> bool {
+    // No Focus Assist equivalent wired up off Windows
+    false
+}
+
+/// Show a notification, or hold or drop it as the policy says. Every
+/// notification goes through here; `count` is how many things it covers
+/// (e.g. clips in a batch), for the summary if it's held.
+pub fn send(app: &AppHandle, kind: NotificationKind, count: usize, level: &'static str, message: String) {
+    let policy = *POLICY.lock().unwrap();
+    match policy {
+        NotificationPolicy::Never => {}
+        NotificationPolicy::RespectFocusAssist if user_is_busy() => hold(&mut HELD.lock().unwrap(), kind, count),
+        _ => {
+            let _ = app.emit("notification", Notification { kind, level, message });
+        }
+    }
+}
+
+fn hold(held: &mut Vec<(NotificationKind, usize)>, kind: NotificationKind, count: usize) {
+    match held.iter_mut().find(|(k, _)| *k == kind) {
+        Some((_, held_count)) => *held_count += count,
+        None => held.push((kind, count)),
+    }
+}
+
+/// One line covering every held notification, e.g. "3 clips captured and
+/// 1 reminder came due while notifications were held"
+fn summary(held: &[(NotificationKind, usize)]) -> Option<String> {
+    let parts: Vec<String> = held.iter().map(|(kind, count)| kind.describe(*count)).collect();
+    let (last, rest) = parts.split_last()?;
+    let listed = if rest.is_empty() {
+        last.clone()
+    } else {
+        format!("{} and {}", rest.join(", "), last)
+    };
+    Some(format!("{} while notifications were held", listed))
+}
+
+/// Deliver the summary of held notifications once Windows is no longer
+/// busy, or straight away if the policy changed meanwhile
+pub fn deliver_held(app: &AppHandle) {
+    let policy = *POLICY.lock().unwrap();
+    if policy == NotificationPolicy::RespectFocusAssist && user_is_busy() {
+        return;
+    }
+    let held = std::mem::take(&mut *HELD.lock().unwrap());
+    if policy == NotificationPolicy::Never {
+        return;
+    }
+    if let Some(message) = summary(&held) {
+        let kind = held[0].0;
+        let _ = app.emit("notification", Notification { kind, level: "info", message });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn held_notifications_are_summed_up_in_one_line() {
+        let mut held = Vec::new();
+        assert_eq!(summary(&held), None);
+        hold(&mut held, NotificationKind::Capture, 1);
+        hold(&mut held, NotificationKind::Capture, 2);
+        assert_eq!(summary(&held).unwrap(), "3 clips captured while notifications were held");
+        hold(&mut held, NotificationKind::Reminder, 1);
+        assert_eq!(
+            summary(&held).unwrap(),
+            "3 clips captured and 1 reminder came due while notifications were held"
+        );
+        hold(&mut held, NotificationKind::Other, 2);
+        assert_eq!(
+            summary(&held).unwrap(),
+            "3 clips captured, 1 reminder came due and 2 other notifications while notifications were held"
+        );
+    }
+}
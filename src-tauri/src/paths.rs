@@ -0,0 +1,250 @@
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock};
+
+const PORTABLE_MARKER: &str = "stack.portable";
+const PORTABLE_DIR_NAME: &str = "StackData";
+const LOCATION_FILE: &str = "location.txt";
+const DATA_DIR_ENV: &str = "STACK_DATA_DIR";
+const DATA_DIR_ARG: &str = "--data-dir";
+
+/// Where the active data directory setting came from
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DataDirSource {
+    CommandLine,
+    Environment,
+    Portable,
+    Relocated,
+    Default,
+}
+
+/// The resolved data directory and how it was chosen
+#[derive(Debug, Clone, Serialize)]
+pub struct DataDirInfo {
+    pub path: PathBuf,
+    pub source: DataDirSource,
+}
+
+static DATA_DIR: OnceLock<RwLock<DataDirInfo>> = OnceLock::new();
+
+/// The OS default location (%LOCALAPPDATA%\Stack), also home of the relocation pointer
+fn default_data_dir() -> PathBuf {
+    dirs_next::data_local_dir()
+        .unwrap_or_else(|| PathBuf::from("."))
+        .join("Stack")
+}
+
+fn exe_dir() -> Option<PathBuf> {
+    std::env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(Path::to_path_buf))
+}
+
+fn data_dir_from_args() -> Option<PathBuf> {
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if arg == DATA_DIR_ARG {
+            return args.next().map(PathBuf::from);
+        }
+        if let Some(value) = arg.strip_prefix("--data-dir=") {
+            return Some(PathBuf::from(value));
+        }
+    }
+    None
+}
+
+fn resolve_data_dir() -> DataDirInfo {
+    if let Some(path) = data_dir_from_args() {
+        return DataDirInfo {
+            path,
+            source: DataDirSource::CommandLine,
+        };
+    }
+
+    if let Some(path) = std::env::var_os(DATA_DIR_ENV).filter(|v| !v.is_empty()) {
+        return DataDirInfo {
+            path: PathBuf::from(path),
+            source: DataDirSource::Environment,
+        };
+    }
+
+    if let Some(dir) = exe_dir() {
+        if dir.join(PORTABLE_MARKER).exists() {
+            return DataDirInfo {
+                path: dir.join(PORTABLE_DIR_NAME),
+                source: DataDirSource::Portable,
+            };
+        }
+    }
+
+    let default_dir = default_data_dir();
+    if let Ok(location) = fs::read_to_string(default_dir.join(LOCATION_FILE)) {
+        let location = location.trim();
+        if !location.is_empty() {
+            return DataDirInfo {
+                path: PathBuf::from(location),
+                source: DataDirSource::Relocated,
+            };
+        }
+    }
+
+    DataDirInfo {
+        path: default_dir,
+        source: DataDirSource::Default,
+    }
+}
+
+fn data_dir_lock() -> &'static RwLock<DataDirInfo> {
+    DATA_DIR.get_or_init(|| RwLock::new(resolve_data_dir()))
+}
+
+/// Get the active data directory info
+pub fn data_dir_info() -> DataDirInfo {
+    data_dir_lock().read().unwrap().clone()
+}
+
+/// Get the active data directory, creating it if it doesn't exist
+pub fn data_dir() -> PathBuf {
+    let dir = data_dir_info().path;
+    if !dir.exists() {
+        let _ = fs::create_dir_all(&dir);
+    }
+    dir
+}
+
+/// Recursively copy `from` into `to`, returning the relative paths of copied files
+fn copy_dir(
+    from: &Path,
+    to: &Path,
+    relative: &Path,
+    copied: &mut Vec<PathBuf>,
+) -> Result<(), String> {
+    fs::create_dir_all(to.join(relative))
+        .map_err(|e| format!("Failed to create {}: {}", to.join(relative).display(), e))?;
+
+    let entries = fs::read_dir(from.join(relative))
+        .map_err(|e| format!("Failed to read {}: {}", from.join(relative).display(), e))?;
+
+    for entry in entries.flatten() {
+        let rel = relative.join(entry.file_name());
+        let file_type = entry.file_type().map_err(|e| e.to_string())?;
+
+        if file_type.is_dir() {
+            copy_dir(from, to, &rel, copied)?;
+        } else if file_type.is_file() {
+            // The relocation pointer belongs to the default dir only
+            if relative.as_os_str().is_empty() && entry.file_name() == LOCATION_FILE {
+                continue;
+            }
+            fs::copy(from.join(&rel), to.join(&rel))
+                .map_err(|e| format!("Failed to copy {}: {}", rel.display(), e))?;
+            copied.push(rel);
+        }
+    }
+
+    Ok(())
+}
+
+fn verify_copy(from: &Path, to: &Path, files: &[PathBuf]) -> Result<(), String> {
+    for rel in files {
+        let original = fs::read(from.join(rel)).map_err(|e| e.to_string())?;
+        let copy = fs::read(to.join(rel)).map_err(|e| e.to_string())?;
+        if original != copy {
+            return Err(format!("Verification failed for {}", rel.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Point the default location at `new_dir` (or clear the pointer when moving back)
+fn write_location_pointer(new_dir: &Path) -> Result<(), String> {
+    let default_dir = default_data_dir();
+    let pointer = default_dir.join(LOCATION_FILE);
+
+    if new_dir == default_dir {
+        if pointer.exists() {
+            fs::remove_file(&pointer)
+                .map_err(|e| format!("Failed to reset data location: {}", e))?;
+        }
+        return Ok(());
+    }
+
+    fs::create_dir_all(&default_dir)
+        .map_err(|e| format!("Failed to create {}: {}", default_dir.display(), e))?;
+
+    // Write then rename so the pointer is never half-written
+    let tmp = default_dir.join(format!("{}.tmp", LOCATION_FILE));
+    fs::write(&tmp, new_dir.to_string_lossy().as_bytes())
+        .map_err(|e| format!("Failed to write data location: {}", e))?;
+    fs::rename(&tmp, &pointer).map_err(|e| format!("Failed to write data location: {}", e))
+}
+
+/// Copy the data directory to `new_dir`, verify it, then switch to it.
+/// With `move_files` the copied files are removed from the old directory afterwards.
+pub fn migrate_data_dir(new_dir: PathBuf, move_files: bool) -> Result<DataDirInfo, String> {
+    let current = data_dir_info();
+
+    match current.source {
+        DataDirSource::CommandLine => {
+            return Err(format!(
+                "Data directory is set by {}; change it there",
+                DATA_DIR_ARG
+            ))
+        }
+        DataDirSource::Environment => {
+            return Err(format!(
+                "Data directory is set by {}; change it there",
+                DATA_DIR_ENV
+            ))
+        }
+        DataDirSource::Portable => {
+            return Err("Data directory is fixed in portable mode".to_string())
+        }
+        DataDirSource::Relocated | DataDirSource::Default => {}
+    }
+
+    if !new_dir.is_absolute() {
+        return Err("Data directory must be an absolute path".to_string());
+    }
+    if new_dir == current.path {
+        return Ok(current);
+    }
+    if new_dir.starts_with(&current.path) {
+        return Err("New data directory can't be inside the current one".to_string());
+    }
+    if new_dir.join("pastebooks.json").exists() {
+        return Err(format!("{} already contains Stack data", new_dir.display()));
+    }
+
+    let mut copied = Vec::new();
+    if current.path.exists() {
+        copy_dir(&current.path, &new_dir, Path::new(""), &mut copied)?;
+        verify_copy(&current.path, &new_dir, &copied)?;
+    } else {
+        fs::create_dir_all(&new_dir)
+            .map_err(|e| format!("Failed to create {}: {}", new_dir.display(), e))?;
+    }
+
+    write_location_pointer(&new_dir)?;
+
+    let source = if new_dir == default_data_dir() {
+        DataDirSource::Default
+    } else {
+        DataDirSource::Relocated
+    };
+    let info = DataDirInfo {
+        path: new_dir,
+        source,
+    };
+    *data_dir_lock().write().unwrap() = info.clone();
+
+    if move_files {
+        for rel in &copied {
+            let _ = fs::remove_file(current.path.join(rel));
+        }
+    }
+
+    Ok(info)
+}
@@ -0,0 +1,145 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::Path;
+use uuid::Uuid;
+
+/// Most presets a user can have; each one is a button in the UI
+pub const MAX_PRESETS: usize = 50;
+/// Version written to exported preset files
+const EXPORT_VERSION: u32 = 1;
+
+/// Where a preset's output goes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum PresetOutput {
+    /// Replace the clip's content
+    InPlace,
+    /// Save the result as a new clip, leaving the source alone
+    #[default]
+    NewClip,
+}
+
+/// A saved AI instruction that can be run on any clip
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PromptPreset {
+    /// Assigned when the preset is added
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    /// What to do with the clip, e.g. "Make this more concise"
+    pub instruction: String,
+    /// Model to try before the configured fallbacks
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub output: PresetOutput,
+}
+
+/// The shareable file written by `export`
+#[derive(Debug, Serialize, Deserialize)]
+struct PresetFile {
+    version: u32,
+    presets: Vec<PromptPreset>,
+}
+
+fn preset(name: &str, instruction: &str, output: PresetOutput) -> PromptPreset {
+    PromptPreset {
+        id: Uuid::new_v4().to_string(),
+        name: name.to_string(),
+        instruction: instruction.to_string(),
+        model: None,
+        output,
+    }
+}
+
+/// Presets a new install starts with
+pub fn default_presets() -> Vec<PromptPreset> {
+    vec![
+        preset("Formalize", "Rewrite this in a formal, professional tone.", PresetOutput::InPlace),
+        preset("Concise", "Make this more concise without losing any information.", PresetOutput::InPlace),
+        preset("Fix grammar", "Fix spelling, grammar and punctuation. Change nothing else.", PresetOutput::InPlace),
+        preset("Explain like I'm five", "Explain this in simple words a five-year-old would understand.", PresetOutput::NewClip),
+        preset("Summarize", "Summarize this in three bullet points.", PresetOutput::NewClip),
+    ]
+}
+
+/// Trim a preset and check it has a name and an instruction
+pub fn validate(mut preset: PromptPreset) -> Result<PromptPreset, String> {
+    preset.name = preset.name.trim().to_string();
+    preset.instruction = preset.instruction.trim().to_string();
+    preset.model = preset
+        .model
+        .map(|m| m.trim().to_string())
+        .filter(|m| !m.is_empty());
+    if preset.name.is_empty() {
+        return Err("Preset name is empty".to_string());
+    }
+    if preset.instruction.is_empty() {
+        return Err(format!("Preset '{}' has no instruction", preset.name));
+    }
+    Ok(preset)
+}
+
+/// The prompt for running `instruction` on a clip
+pub fn prompt(instruction: &str, content: &str) -> String {
+    format!(
+        "{}\n\nReply with the result only, without commentary or quotes.\n\nText:\n\n{}",
+        instruction, content
+    )
+}
+
+/// Write presets to a JSON file others can import
+pub fn export(presets: &[PromptPreset], path: &Path) -> Result<(), String> {
+    let file = PresetFile {
+        version: EXPORT_VERSION,
+        presets: presets.to_vec(),
+    };
+    let json = serde_json::to_string_pretty(&file).map_err(|e| e.to_string())?;
+    fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+}
+
+/// Read presets exported by `export`, validated but not yet given ids
+pub fn import(path: &Path) -> Result<Vec<PromptPreset>, String> {
+    let json = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let file: PresetFile =
+        serde_json::from_str(&json).map_err(|e| format!("Not a preset file: {}", e))?;
+    if file.version > EXPORT_VERSION {
+        return Err(format!("Preset file version {} is newer than this Stack", file.version));
+    }
+    file.presets.into_iter().map(validate).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn presets_are_trimmed_and_need_text() {
+        let mut raw = preset("  Shout ", " Uppercase it ", PresetOutput::InPlace);
+        raw.model = Some(" ".to_string());
+        let valid = validate(raw).unwrap();
+        assert_eq!((valid.name.as_str(), valid.instruction.as_str()), ("Shout", "Uppercase it"));
+        assert!(valid.model.is_none());
+
+        assert!(validate(preset(" ", "x", PresetOutput::NewClip)).is_err());
+        assert!(validate(preset("x", " ", PresetOutput::NewClip)).is_err());
+    }
+
+    #[test]
+    fn exported_presets_import_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("presets.json");
+        export(&default_presets(), &path).unwrap();
+
+        let imported = import(&path).unwrap();
+        assert_eq!(imported.len(), default_presets().len());
+        assert_eq!(imported[0].name, "Formalize");
+        assert_eq!(imported[0].output, PresetOutput::InPlace);
+
+        fs::write(&path, r#"{"version": 9, "presets": []}"#).unwrap();
+        assert!(import(&path).unwrap_err().contains("newer"));
+        fs::write(&path, "[]").unwrap();
+        assert!(import(&path).is_err());
+    }
+}
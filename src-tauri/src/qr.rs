@@ -0,0 +1,31 @@
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use qrcode::render::svg;
+use qrcode::QrCode;
+
+/// Smallest side of a rendered code, in pixels
+const MIN_SIZE: u32 = 240;
+
+/// `text` as a QR code in an SVG `data:` URL, ready for an `<img src>`.
+/// Fails when the text is too long for any QR version.
+pub fn svg_data_url(text: &str) -> Result<String, String> {
+    let code = QrCode::new(text.as_bytes()).map_err(|e| format!("Couldn't make a QR code: {}", e))?;
+    let image = code
+        .render::<svg::Color>()
+        .min_dimensions(MIN_SIZE, MIN_SIZE)
+        .quiet_zone(true)
+        .build();
+    Ok(format!("data:image/svg+xml;base64,{}", BASE64.encode(image)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes_are_svg_data_urls() {
+        let url = svg_data_url("http://192.168.1.20:50123/s/token").unwrap();
+        let svg = BASE64.decode(url.strip_prefix("data:image/svg+xml;base64,").unwrap()).unwrap();
+        assert!(String::from_utf8(svg).unwrap().contains("<svg"));
+        assert!(svg_data_url(&"x".repeat(8000)).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use tauri::{AppHandle, LogicalSize, Manager, PhysicalPosition, WebviewUrl, WebviewWindow, WebviewWindowBuilder};
+
+use crate::window;
+
+/// Label of the quick note prompt window
+pub const LABEL: &str = "quick-note";
+/// Source app recorded on quick notes
+pub const SOURCE_APP: &str = "Quick note";
+const WIDTH: f64 = 420.0;
+const HEIGHT: f64 = 56.0;
+/// Gap between the cursor and the prompt's top-left corner
+const CURSOR_OFFSET: f64 = 12.0;
+
+/// The prompt window, created hidden the first time it's needed
+fn prompt_window(app: &AppHandle) -> tauri::Result<WebviewWindow> {
+    if let Some(window) = app.get_webview_window(LABEL) {
+        return Ok(window);
+    }
+    WebviewWindowBuilder::new(app, LABEL, WebviewUrl::App("quick-note.html".into()))
+        .title("Quick note")
+        .inner_size(WIDTH, HEIGHT)
+        .decorations(false)
+        .always_on_top(true)
+        .skip_taskbar(true)
+        .resizable(false)
+        .visible(false)
+        .build()
+}
+
+/// Show the prompt next to the mouse cursor and give it focus. The
+/// foreground tracker ignores Stack's own windows, so the app the user was
+/// in stays the "previous app" for pastes and attribution.
+pub fn show(app: &AppHandle) -> Result<(), String> {
+    let window = prompt_window(app).map_err(|e| format!("Couldn't open the quick note prompt: {}", e))?;
+    let _ = window.set_size(LogicalSize::new(WIDTH, HEIGHT));
+    if let Ok(cursor) = app.cursor_position() {
+        let scale = window.scale_factor().unwrap_or(1.0);
+        let offset = CURSOR_OFFSET * scale;
+        let _ = window.set_position(PhysicalPosition::new(cursor.x + offset, cursor.y + offset));
+    }
+    window.show().map_err(|e| e.to_string())?;
+    window.set_focus().map_err(|e| e.to_string())
+}
+
+/// Hide the prompt and hand focus back to the app the user was in
+pub fn dismiss(app: &AppHandle) {
+    if let Some(window) = app.get_webview_window(LABEL) {
+        let _ = window.hide();
+    }
+    window::focus_last_foreground();
+}
@@ -0,0 +1,194 @@
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+/// What happens to captures held back from an app copying too fast
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitAction {
+    /// One "N rapid captures suppressed" clip once the app calms down
+    #[default]
+    Coalesce,
+    /// Only a notification saying how many were dropped
+    Drop,
+}
+
+/// What to do with one capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Allow,
+    /// Held back, and the first of its burst
+    SuppressStart,
+    Suppress,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    refilled_at: Instant,
+    /// Captures held back in the current burst
+    suppressed: usize,
+    last_suppressed: Option<Instant>,
+}
+
+/// A token bucket per source app: `limit` captures, refilled evenly over
+/// `window`. Once an app runs dry, everything from it is held back until a
+/// token refills, which ends the burst. Time is passed in so bursts can be
+/// tested without sleeping.
+#[derive(Debug)]
+pub struct RateLimiter {
+    /// Captures per window; 0 turns limiting off
+    limit: u32,
+    window: Duration,
+    buckets: HashMap<String, Bucket>,
+    /// Bursts that ended: app and how many captures were held back
+    finished: Vec<(String, usize)>,
+    /// A settle pass is waiting to report finished bursts
+    settle_scheduled: bool,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        Self {
+            limit,
+            window,
+            buckets: HashMap::new(),
+            finished: Vec::new(),
+            settle_scheduled: false,
+        }
+    }
+
+    /// How long one token takes to refill
+    pub fn refill_interval(&self) -> Duration {
+        self.window / self.limit.max(1)
+    }
+
+    /// Use new limits; buckets start over, but held-back bursts still get
+    /// reported
+    pub fn configure(&mut self, limit: u32, window: Duration) {
+        if (limit, window) == (self.limit, self.window) {
+            return;
+        }
+        for (app, bucket) in self.buckets.drain() {
+            if bucket.suppressed > 0 {
+                self.finished.push((app, bucket.suppressed));
+            }
+        }
+        self.limit = limit;
+        self.window = window;
+    }
+
+    /// Take a token for a capture from `app` at `now`
+    pub fn check(&mut self, app: &str, now: Instant) -> Verdict {
+        if self.limit == 0 || self.window.is_zero() {
+            return Verdict::Allow;
+        }
+        self.end_calmed_bursts(now);
+        let bucket = self.buckets.entry(app.to_string()).or_insert(Bucket {
+            tokens: self.limit as f64,
+            refilled_at: now,
+            suppressed: 0,
+            last_suppressed: None,
+        });
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Verdict::Allow;
+        }
+        bucket.suppressed += 1;
+        bucket.last_suppressed = Some(now);
+        if bucket.suppressed == 1 {
+            Verdict::SuppressStart
+        } else {
+            Verdict::Suppress
+        }
+    }
+
+    /// Refill every bucket up to `now`, close bursts whose app has a token
+    /// again, and forget apps whose buckets are full
+    fn end_calmed_bursts(&mut self, now: Instant) {
+        let capacity = self.limit as f64;
+        let per_second = capacity / self.window.as_secs_f64();
+        let finished = &mut self.finished;
+        self.buckets.retain(|app, bucket| {
+            let elapsed = now.saturating_duration_since(bucket.refilled_at).as_secs_f64();
+            bucket.tokens = (bucket.tokens + elapsed * per_second).min(capacity);
+            bucket.refilled_at = bucket.refilled_at.max(now);
+            if bucket.suppressed > 0 && bucket.tokens >= 1.0 {
+                finished.push((app.clone(), std::mem::take(&mut bucket.suppressed)));
+                bucket.last_suppressed = None;
+            }
+            bucket.suppressed > 0 || bucket.tokens < capacity
+        });
+    }
+
+    /// Mark a settle pass as scheduled; false if one already is
+    pub fn schedule_settle(&mut self) -> bool {
+        !std::mem::replace(&mut self.settle_scheduled, true)
+    }
+
+    /// Bursts that have ended, and whether any are still going. The pass
+    /// is no longer scheduled once none are.
+    pub fn settle(&mut self, now: Instant) -> (Vec<(String, usize)>, bool) {
+        if self.limit > 0 && !self.window.is_zero() {
+            self.end_calmed_bursts(now);
+        }
+        let ongoing = self.buckets.values().any(|b| b.suppressed > 0);
+        self.settle_scheduled = ongoing;
+        (std::mem::take(&mut self.finished), ongoing)
+    }
+}
+
+/// Text of the clip standing in for a coalesced burst
+pub fn summary(app: &str, suppressed: usize) -> String {
+    match suppressed {
+        1 => format!("1 rapid capture suppressed from {}", app),
+        n => format!("{} rapid captures suppressed from {}", n, app),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_burst_is_held_back_per_app_until_it_calms_down() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut limiter = RateLimiter::new(10, Duration::from_secs(10));
+
+        // 50 copies a second from one app: the first 10 get through
+        let verdicts: Vec<Verdict> = (0..50).map(|i| limiter.check("spam.exe", at(i * 20))).collect();
+        assert!(verdicts[..10].iter().all(|v| *v == Verdict::Allow));
+        assert_eq!(verdicts[10], Verdict::SuppressStart);
+        assert!(verdicts[11..].iter().all(|v| *v == Verdict::Suppress));
+        // Other apps keep their own budget
+        assert_eq!(limiter.check("editor.exe", at(980)), Verdict::Allow);
+        assert!(limiter.schedule_settle());
+        assert!(!limiter.schedule_settle());
+
+        // Under a token a second the burst goes on
+        assert_eq!(limiter.settle(at(990)), (Vec::new(), true));
+
+        // Once a token refills the burst is over: reported once, and the
+        // next capture gets through
+        assert_eq!(limiter.settle(at(1200)), (vec![("spam.exe".to_string(), 40)], false));
+        assert_eq!(limiter.settle(at(1201)), (Vec::new(), false));
+        assert!(limiter.schedule_settle());
+        assert_eq!(limiter.check("spam.exe", at(1300)), Verdict::Allow);
+        assert_eq!(limiter.check("spam.exe", at(1310)), Verdict::SuppressStart);
+        assert!((0..10).all(|i| limiter.check("spam.exe", at(12_000 + i)) == Verdict::Allow));
+    }
+
+    #[test]
+    fn a_zero_limit_allows_everything_and_reconfiguring_reports_held_bursts() {
+        let now = Instant::now();
+        let mut limiter = RateLimiter::new(1, Duration::from_secs(10));
+        assert_eq!(limiter.check("a", now), Verdict::Allow);
+        assert_eq!(limiter.check("a", now), Verdict::SuppressStart);
+
+        limiter.configure(0, Duration::from_secs(10));
+        assert!((0..100).all(|_| limiter.check("a", now) == Verdict::Allow));
+        assert_eq!(limiter.settle(now).0, vec![("a".to_string(), 1)]);
+        assert_eq!(summary("a", 1), "1 rapid capture suppressed from a");
+    }
+}
@@ -0,0 +1,507 @@
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+
+use crate::ingest::{self, ContentKind};
+use crate::storage::{self, ClipObject};
+use crate::textutil;
+
+/// Most rules a user can have; every capture runs through all of them
+pub const MAX_RULES: usize = 100;
+/// Only this much of a clip is matched against content regexes. The regex
+/// engine runs in linear time, so this bounds the cost of every rule.
+const MAX_MATCHED_BYTES: usize = 64 * 1024;
+/// Compiled size cap, so a rule can't build a huge automaton
+const RULE_REGEX_SIZE_LIMIT: usize = 1 << 20;
+
+/// When a rule applies. Every condition that is set must hold; a rule with
+/// no conditions matches every capture.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleCondition {
+    /// Source app, compared case-insensitively (e.g. "slack.exe")
+    pub source_app: Option<String>,
+    pub title_regex: Option<String>,
+    pub content_regex: Option<String>,
+    pub kind: Option<ContentKind>,
+}
+
+/// What a matching rule does to the capture
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RuleActions {
+    /// Send the clip to this pastebook instead of the active one
+    pub pastebook_id: Option<String>,
+    /// Send the clip to the pastebook named by the title regex's first
+    /// capture group, creating it if missing ("(\S+) — VS Code"). Falls
+    /// back to `pastebook_id` when the group didn't match.
+    pub pastebook_from_title: bool,
+    /// Names `pastebook_from_title` may route to; empty allows any
+    pub title_pastebooks: Vec<String>,
+    pub add_tags: Vec<String>,
+    pub label: Option<String>,
+    pub sensitive: bool,
+    /// Don't store the capture at all
+    pub skip: bool,
+}
+
+impl RuleActions {
+    fn is_empty(&self) -> bool {
+        self.pastebook_id.is_none()
+            && !self.pastebook_from_title
+            && self.add_tags.is_empty()
+            && self.label.is_none()
+            && !self.sensitive
+            && !self.skip
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureRule {
+    /// Assigned when the rule is added
+    #[serde(default)]
+    pub id: String,
+    pub name: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub condition: RuleCondition,
+    #[serde(default)]
+    pub actions: RuleActions,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+/// Text to dry-run a rule against
+#[derive(Debug, Clone, Deserialize)]
+pub struct RuleSample {
+    pub content: String,
+    #[serde(default)]
+    pub source_app: String,
+    #[serde(default)]
+    pub window_title: String,
+}
+
+/// How one condition of a dry-run rule fared
+#[derive(Debug, Clone, Serialize)]
+pub struct ConditionResult {
+    pub condition: &'static str,
+    pub matched: bool,
+}
+
+/// Result of `test_rule`
+#[derive(Debug, Clone, Serialize)]
+pub struct RuleTestResult {
+    pub matched: bool,
+    pub kind: ContentKind,
+    pub conditions: Vec<ConditionResult>,
+}
+
+/// What the rules decided for one capture
+#[derive(Debug, Clone, Default)]
+pub struct RuleOutcome {
+    /// Name of the rule that asked for the capture to be dropped
+    pub skipped_by: Option<String>,
+    pub pastebook_id: Option<String>,
+    /// Pastebook named after the window title, to find or create; set
+    /// instead of `pastebook_id`
+    pub pastebook_name: Option<String>,
+}
+
+/// A rule with its regexes compiled
+#[derive(Debug, Clone)]
+struct CompiledRule {
+    rule: CaptureRule,
+    title: Option<Regex>,
+    content: Option<Regex>,
+}
+
+/// The enabled rules, compiled once and reused for every capture
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<CompiledRule>,
+}
+
+fn compile(pattern: &Option<String>, field: &str) -> Result<Option<Regex>, String> {
+    pattern
+        .as_deref()
+        .map(|pattern| {
+            RegexBuilder::new(pattern)
+                .size_limit(RULE_REGEX_SIZE_LIMIT)
+                .dfa_size_limit(RULE_REGEX_SIZE_LIMIT)
+                .build()
+                .map_err(|e| format!("Invalid {}: {}", field, e))
+        })
+        .transpose()
+}
+
+fn compile_rule(rule: CaptureRule) -> Result<CompiledRule, String> {
+    Ok(CompiledRule {
+        title: compile(&rule.condition.title_regex, "title regex")?,
+        content: compile(&rule.condition.content_regex, "content regex")?,
+        rule,
+    })
+}
+
+/// Check and normalize a rule before it's saved: regexes must compile, the
+/// label must be valid and the rule must do something
+pub fn validate(mut rule: CaptureRule) -> Result<CaptureRule, String> {
+    rule.name = rule.name.trim().to_string();
+    if rule.name.is_empty() {
+        return Err("Rule name is empty".to_string());
+    }
+
+    let condition = &mut rule.condition;
+    condition.source_app = condition
+        .source_app
+        .take()
+        .map(|app| app.trim().to_string())
+        .filter(|app| !app.is_empty());
+    for pattern in [&mut condition.title_regex, &mut condition.content_regex] {
+        *pattern = pattern.take().filter(|p| !p.is_empty());
+    }
+
+    let actions = &mut rule.actions;
+    actions.add_tags = storage::normalize_tags(std::mem::take(&mut actions.add_tags));
+    actions.label = storage::validate_label(actions.label.take())?;
+    actions.title_pastebooks = std::mem::take(&mut actions.title_pastebooks)
+        .iter()
+        .filter_map(|name| pastebook_name_from_title(name))
+        .collect();
+    if actions.is_empty() {
+        return Err("Rule has no actions".to_string());
+    }
+
+    let compiled = compile_rule(rule.clone())?;
+    if rule.actions.pastebook_from_title && compiled.title.as_ref().is_none_or(|t| t.captures_len() < 2) {
+        return Err("Routing by window title needs a title regex with a capture group".to_string());
+    }
+    Ok(rule)
+}
+
+/// A pastebook name made from text matched in a window title: control
+/// characters and runs of spaces collapsed, separator punctuation trimmed
+/// and the length capped. None if nothing is left.
+pub fn pastebook_name_from_title(text: &str) -> Option<String> {
+    let text: String = text.chars().map(|c| if c.is_control() { ' ' } else { c }).collect();
+    let name = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    let name = name.trim_matches(|c: char| c.is_whitespace() || "-–—|·•:".contains(c));
+    let name = textutil::truncate(name, storage::MAX_NAME_CHARS).trim_end();
+    (!name.is_empty()).then(|| name.to_string())
+}
+
+/// Cap text to what rules look at, on a grapheme cluster boundary
+fn matched_part(text: &str) -> &str {
+    textutil::truncate_bytes(text, MAX_MATCHED_BYTES)
+}
+
+impl CompiledRule {
+    fn conditions(
+        &self,
+        content: &str,
+        source_app: &str,
+        window_title: &str,
+        kind: ContentKind,
+    ) -> Vec<ConditionResult> {
+        let condition = &self.rule.condition;
+        let mut results = Vec::new();
+        if let Some(app) = &condition.source_app {
+            results.push(ConditionResult {
+                condition: "source_app",
+                matched: app.eq_ignore_ascii_case(source_app),
+            });
+        }
+        if let Some(regex) = &self.title {
+            results.push(ConditionResult {
+                condition: "title_regex",
+                matched: regex.is_match(matched_part(window_title)),
+            });
+        }
+        if let Some(regex) = &self.content {
+            results.push(ConditionResult {
+                condition: "content_regex",
+                matched: regex.is_match(matched_part(content)),
+            });
+        }
+        if let Some(wanted) = condition.kind {
+            results.push(ConditionResult {
+                condition: "kind",
+                matched: wanted == kind,
+            });
+        }
+        results
+    }
+
+    fn matches(
+        &self,
+        content: &str,
+        source_app: &str,
+        window_title: &str,
+        kind: ContentKind,
+    ) -> bool {
+        self.conditions(content, source_app, window_title, kind)
+            .iter()
+            .all(|c| c.matched)
+    }
+
+    /// The pastebook `window_title` names, if the rule routes by title and
+    /// the name is allowed
+    fn title_pastebook(&self, window_title: &str) -> Option<String> {
+        let actions = &self.rule.actions;
+        if !actions.pastebook_from_title {
+            return None;
+        }
+        let captures = self.title.as_ref()?.captures(matched_part(window_title))?;
+        let name = pastebook_name_from_title(captures.iter().skip(1).flatten().next()?.as_str())?;
+        if actions.title_pastebooks.is_empty() {
+            return Some(name);
+        }
+        // The allow-list's spelling wins over the title's
+        actions
+            .title_pastebooks
+            .iter()
+            .find(|allowed| allowed.to_lowercase() == name.to_lowercase())
+            .cloned()
+    }
+}
+
+impl RuleSet {
+    /// Compile the enabled rules; the rules were validated when saved, so
+    /// any that no longer compile are left out rather than failing capture
+    pub fn new(rules: &[CaptureRule]) -> Self {
+        let rules = rules
+            .iter()
+            .filter(|rule| rule.enabled)
+            .filter_map(|rule| match compile_rule(rule.clone()) {
+                Ok(compiled) => Some(compiled),
+                Err(e) => {
+                    eprintln!("Ignoring rule '{}': {}", rule.name, e);
+                    None
+                }
+            })
+            .collect();
+        Self { rules }
+    }
+
+    /// Run the rules over a capture in order. Tags add up, later rules
+    /// override earlier routes and labels, and a skip stops evaluation.
+    pub fn apply(&self, clip: &mut ClipObject) -> RuleOutcome {
+        let mut outcome = RuleOutcome::default();
+        if self.rules.is_empty() {
+            return outcome;
+        }
+
+        let kind = ingest::classify(&clip.content);
+        for compiled in &self.rules {
+            if !compiled.matches(
+                &clip.content,
+                &clip.metadata.source_app,
+                &clip.metadata.window_title,
+                kind,
+            ) {
+                continue;
+            }
+
+            let actions = &compiled.rule.actions;
+            if actions.skip {
+                outcome.skipped_by = Some(compiled.rule.name.clone());
+                return outcome;
+            }
+            for tag in &actions.add_tags {
+                if !clip.tags.contains(tag) {
+                    clip.tags.push(tag.clone());
+                }
+            }
+            if actions.label.is_some() {
+                clip.label = actions.label.clone();
+            }
+            clip.sensitive |= actions.sensitive;
+            if let Some(name) = compiled.title_pastebook(&clip.metadata.window_title) {
+                outcome.pastebook_name = Some(name);
+                outcome.pastebook_id = None;
+            } else if actions.pastebook_id.is_some() {
+                outcome.pastebook_id = actions.pastebook_id.clone();
+                outcome.pastebook_name = None;
+            }
+        }
+        outcome
+    }
+}
+
+/// Dry-run one rule (enabled or not) against sample text
+pub fn test_rule(rule: CaptureRule, sample: &RuleSample) -> Result<RuleTestResult, String> {
+    let compiled = compile_rule(rule)?;
+    let kind = ingest::classify(&sample.content);
+    let conditions = compiled.conditions(
+        &sample.content,
+        &sample.source_app,
+        &sample.window_title,
+        kind,
+    );
+    Ok(RuleTestResult {
+        matched: conditions.iter().all(|c| c.matched),
+        kind,
+        conditions,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+
+    fn rule(name: &str, condition: RuleCondition, actions: RuleActions) -> CaptureRule {
+        CaptureRule {
+            id: String::new(),
+            name: name.to_string(),
+            enabled: true,
+            condition,
+            actions,
+        }
+    }
+
+    fn tagging(tag: &str) -> RuleActions {
+        RuleActions {
+            add_tags: vec![tag.to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn validate_normalizes_and_rejects() {
+        let normalized = validate(rule(
+            "  Trim me ",
+            RuleCondition {
+                source_app: Some("  ".to_string()),
+                title_regex: Some(String::new()),
+                ..Default::default()
+            },
+            tagging(" a "),
+        ))
+        .unwrap();
+        assert_eq!(normalized.name, "Trim me");
+        assert!(normalized.condition.source_app.is_none());
+        assert!(normalized.condition.title_regex.is_none());
+        assert_eq!(normalized.actions.add_tags, vec!["a"]);
+
+        assert_eq!(
+            validate(rule(" ", Default::default(), tagging("a"))).unwrap_err(),
+            "Rule name is empty"
+        );
+        assert_eq!(
+            validate(rule("Idle", Default::default(), Default::default())).unwrap_err(),
+            "Rule has no actions"
+        );
+        let bad_regex = RuleCondition {
+            content_regex: Some("(".to_string()),
+            ..Default::default()
+        };
+        assert!(validate(rule("Bad", bad_regex, tagging("a")))
+            .unwrap_err()
+            .starts_with("Invalid content regex"));
+    }
+
+    #[test]
+    fn dry_run_reports_each_condition() {
+        let condition = RuleCondition {
+            source_app: Some("SLACK.EXE".to_string()),
+            title_regex: Some("^#general".to_string()),
+            kind: Some(ContentKind::Url),
+            ..Default::default()
+        };
+        let sample = RuleSample {
+            content: "https://example.com".to_string(),
+            source_app: "slack.exe".to_string(),
+            window_title: "#random".to_string(),
+        };
+        let result = test_rule(rule("Links", condition, tagging("link")), &sample).unwrap();
+        assert!(!result.matched);
+        assert_eq!(result.kind, ContentKind::Url);
+        let matched: Vec<(&str, bool)> = result
+            .conditions
+            .iter()
+            .map(|c| (c.condition, c.matched))
+            .collect();
+        assert_eq!(
+            matched,
+            vec![("source_app", true), ("title_regex", false), ("kind", true)]
+        );
+    }
+
+    #[test]
+    fn later_rules_override_and_skip_stops() {
+        let mut first = rule("First", Default::default(), tagging("one"));
+        first.actions.label = Some("red".to_string());
+        first.actions.pastebook_id = Some("a".to_string());
+        let mut second = rule("Second", Default::default(), tagging("two"));
+        second.actions.pastebook_id = Some("b".to_string());
+        let mut disabled = rule("Disabled", Default::default(), Default::default());
+        disabled.enabled = false;
+        disabled.actions.skip = true;
+
+        let mut captured = clip("text");
+        let outcome = RuleSet::new(&[first.clone(), disabled, second]).apply(&mut captured);
+        assert!(outcome.skipped_by.is_none());
+        assert_eq!(outcome.pastebook_id.as_deref(), Some("b"));
+        assert_eq!(captured.tags, vec!["one", "two"]);
+        assert_eq!(captured.label.as_deref(), Some("red"));
+
+        let mut skip = rule("Skip", Default::default(), Default::default());
+        skip.actions.skip = true;
+        let mut captured = clip("text");
+        let outcome = RuleSet::new(&[skip, first]).apply(&mut captured);
+        assert_eq!(outcome.skipped_by.as_deref(), Some("Skip"));
+        assert!(captured.tags.is_empty());
+    }
+
+    #[test]
+    fn content_matching_is_capped_on_a_char_boundary() {
+        let text = format!("{}é", "a".repeat(MAX_MATCHED_BYTES - 1));
+        assert_eq!(matched_part(&text).len(), MAX_MATCHED_BYTES - 1);
+        assert_eq!(matched_part("short"), "short");
+    }
+
+    #[test]
+    fn title_routes_name_a_pastebook_from_the_capture_group() {
+        let condition = RuleCondition {
+            title_regex: Some(r"^(?:[^—]+ — )?([\w -]+?) (?:—|-) (?:Visual Studio Code|Figma)$".to_string()),
+            ..Default::default()
+        };
+        let mut by_title = rule(
+            "Projects",
+            condition,
+            RuleActions {
+                pastebook_from_title: true,
+                pastebook_id: Some("inbox".to_string()),
+                title_pastebooks: vec![" Stack Redesign ".to_string(), "\t".to_string()],
+                ..Default::default()
+            },
+        );
+        by_title = validate(by_title).unwrap();
+        assert_eq!(by_title.actions.title_pastebooks, vec!["Stack Redesign"]);
+
+        let route = |title: &str, rule: &CaptureRule| {
+            let mut captured = clip("text");
+            captured.metadata.window_title = title.to_string();
+            let outcome = RuleSet::new(std::slice::from_ref(rule)).apply(&mut captured);
+            (outcome.pastebook_name, outcome.pastebook_id)
+        };
+        assert_eq!(route("stack redesign - Figma", &by_title), (Some("Stack Redesign".to_string()), None));
+        // Not on the allow-list, or no match: the fixed route still applies
+        assert_eq!(route("main.rs — stack-backend — Visual Studio Code", &by_title), (None, Some("inbox".to_string())));
+        by_title.actions.title_pastebooks.clear();
+        assert_eq!(
+            route("main.rs — stack-backend — Visual Studio Code", &by_title),
+            (Some("stack-backend".to_string()), None)
+        );
+
+        assert_eq!(pastebook_name_from_title(" — a\u{7}  b | ").as_deref(), Some("a b"));
+        assert_eq!(pastebook_name_from_title(" -- "), None);
+        assert_eq!(pastebook_name_from_title(&"x".repeat(300)).unwrap().len(), storage::MAX_NAME_CHARS);
+
+        let mut no_group = by_title.clone();
+        no_group.condition.title_regex = Some("Figma".to_string());
+        assert!(validate(no_group).unwrap_err().contains("capture group"));
+    }
+}
@@ -0,0 +1,153 @@
+use serde::Serialize;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+/// Unclean exits in a row, kept next to the storage file
+const CRASH_COUNT_FILE: &str = "crash_count";
+/// This many unclean exits in a row start the next session in safe mode
+pub const CRASHES_BEFORE_SAFE_MODE: u32 = 2;
+/// Error from anything safe mode turns off
+pub const READ_ONLY: &str = "Locked: Safe mode: storage is read-only until you export a backup and leave safe mode";
+const AI_OFF: &str = "Locked: Safe mode: AI is off until you export a backup and leave safe mode";
+
+/// Why this session started in safe mode, for `get_safe_mode_reason`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SafeModeReason {
+    /// Unclean exits in a row before this session
+    pub crashes: u32,
+    /// A backup has been exported this session, so safe mode can be left
+    pub backup_exported: bool,
+}
+
+static ACTIVE: Mutex<Option<SafeModeReason>> = Mutex::new(None);
+
+/// Commands that only read, export or look around (switching pastebooks
+/// included), the ones safe mode lets through. Anything not listed is
+/// refused before it runs, so a new command is blocked until it's added here.
+const READ_ONLY_COMMANDS: &[&str] = &[
+    "greet", "get_settings", "get_shortcut_status", "get_webhook_status", "get_local_api_token",
+    "get_effective_theme", "get_models", "get_ingest_transforms", "get_ai_queue_status", "cancel_ai_queue",
+    "get_clips", "search_clips", "rebuild_search_index", "get_clip", "get_clip_content", "get_clipboard_formats",
+    "reveal_clip", "get_last_foreground", "fix_stuck_modifiers", "dismiss_quick_note", "get_clip_revisions",
+    "get_clips_by_label", "get_timeline", "preview_merge", "materialize_clip_file", "copy_clip_as_file",
+    "export_clip", "get_all_content", "copy_all_to_clipboard", "export_all_content", "copy_clip",
+    "hold_on_clipboard", "release_clipboard_hold", "share_clip_via_lan", "preview_attribution", "get_scratchpad",
+    "copy_scratchpad_to_clipboard", "list_pastebooks", "get_pastebook_stats", "get_trashed_clips",
+    "get_active_pastebook", "find_pastebook_by_name", "switch_pastebook", "list_pastebook_groups",
+    "list_sessions", "get_session_report", "list_templates", "get_asset_stats", "get_global_stats",
+    "list_rules", "test_rule", "list_presets", "export_presets", "get_data_dir", "get_sync_status",
+    "get_perf_metrics", "preview_diagnostics", "generate_diagnostics", "get_storage_health", "export_backup",
+    "get_safe_mode_reason", "get_notifications_held", "hold_notification", "exit_safe_mode", "export_pastebook",
+    "get_auto_export_status", "get_ai_usage", "get_ai_history", "get_previous_shutdown",
+    "dismiss_previous_shutdown",
+];
+
+/// Update the unclean-exit count next to `storage_file` for how the last
+/// session ended and return it; a clean exit resets it
+pub fn count_crashes(storage_file: &Path, clean: bool) -> u32 {
+    let file = storage_file.with_file_name(CRASH_COUNT_FILE);
+    if clean {
+        let _ = fs::remove_file(&file);
+        return 0;
+    }
+    let crashes = fs::read_to_string(&file)
+        .ok()
+        .and_then(|count| count.trim().parse::<u32>().ok())
+        .unwrap_or(0)
+        .saturating_add(1);
+    if let Err(e) = fs::write(&file, crashes.to_string()) {
+        eprintln!("Failed to write {}: {}", file.display(), e);
+    }
+    crashes
+}
+
+/// Count the last exit at startup and enter safe mode after repeated crashes
+pub fn note_startup(storage_file: &Path, clean: bool) {
+    let crashes = count_crashes(storage_file, clean);
+    if crashes >= CRASHES_BEFORE_SAFE_MODE {
+        eprintln!("Stack crashed {} times in a row; starting in safe mode", crashes);
+        *ACTIVE.lock().unwrap() = Some(SafeModeReason { crashes, backup_exported: false });
+    }
+}
+
+pub fn is_active() -> bool {
+    ACTIVE.lock().unwrap().is_some()
+}
+
+pub fn reason() -> Option<SafeModeReason> {
+    ACTIVE.lock().unwrap().clone()
+}
+
+/// Whether `command` may change stored data, and so is refused in safe mode
+fn is_mutating(command: &str) -> bool {
+    !READ_ONLY_COMMANDS.contains(&command)
+}
+
+/// Refuse a command that would change stored data while in safe mode
+pub fn check_command(command: &str) -> Result<(), String> {
+    if is_mutating(command) && is_active() {
+        return Err(READ_ONLY.to_string());
+    }
+    Ok(())
+}
+
+/// Refuse AI requests in safe mode
+pub fn check_ai() -> Result<(), String> {
+    if is_active() {
+        return Err(AI_OFF.to_string());
+    }
+    Ok(())
+}
+
+/// A backup was written; safe mode may now be left
+pub fn note_backup_exported() {
+    if let Some(reason) = ACTIVE.lock().unwrap().as_mut() {
+        reason.backup_exported = true;
+    }
+}
+
+/// Leave safe mode, which takes an exported backup first. Background work
+/// turned off at startup stays off until the next launch.
+pub fn exit() -> Result<(), String> {
+    let mut active = ACTIVE.lock().unwrap();
+    match active.as_ref() {
+        None => Ok(()),
+        Some(reason) if !reason.backup_exported => {
+            Err("Conflict: Export a backup before leaving safe mode".to_string())
+        }
+        Some(_) => {
+            *active = None;
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn crash_count_builds_up_and_resets_after_a_clean_exit() {
+        let dir = tempfile::tempdir().unwrap();
+        let storage_file = dir.path().join("pastebooks.json");
+        assert_eq!(count_crashes(&storage_file, false), 1);
+        assert_eq!(count_crashes(&storage_file, false), CRASHES_BEFORE_SAFE_MODE);
+        assert_eq!(count_crashes(&storage_file, true), 0);
+        assert_eq!(count_crashes(&storage_file, false), 1);
+
+        std::fs::write(dir.path().join(CRASH_COUNT_FILE), "garbage").unwrap();
+        assert_eq!(count_crashes(&storage_file, false), 1);
+    }
+
+    #[test]
+    fn only_commands_that_change_data_are_refused() {
+        assert!(is_mutating("delete_clip") && is_mutating("update_settings") && is_mutating("sync_now"));
+        // Missed by the old list of mutating commands; unknown ones are refused too
+        assert!(is_mutating("mark_pastebook_viewed") && is_mutating("retry_failed_ai_jobs"));
+        assert!(is_mutating("a_command_added_later"));
+        for reading in ["get_clips", "export_backup", "export_pastebook", "switch_pastebook", "exit_safe_mode"] {
+            assert!(!is_mutating(reading), "{} should stay available", reading);
+        }
+    }
+}
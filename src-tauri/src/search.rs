@@ -0,0 +1,425 @@
+use serde::Serialize;
+use std::collections::{HashMap, HashSet};
+
+use crate::storage::ClipObject;
+use crate::textutil;
+
+/// Chars of each field that get indexed; clips with longer fields are kept
+/// as unconditional candidates instead, bounding the index at roughly
+/// 4 bytes per indexed char
+const MAX_INDEXED_CHARS: usize = 2048;
+
+/// Three lowercased chars packed 21 bits apiece
+type Trigram = u64;
+
+/// Index sizes, for diagnostics
+#[derive(Debug, Clone, Serialize)]
+pub struct SearchIndexStats {
+    pub clips: usize,
+    pub trigrams: usize,
+    pub entries: usize,
+    /// Clips with text past the indexed length, always verified directly
+    pub partial: usize,
+}
+
+#[derive(Debug, Clone)]
+struct Doc {
+    clip_id: String,
+    entries: usize,
+    partial: bool,
+}
+
+/// Inverted trigram index over clip text (content, title, source app and
+/// window title), used to narrow down which clips a search has to scan
+#[derive(Debug, Clone, Default)]
+pub struct SearchIndex {
+    /// Trigram -> doc numbers, ascending because docs are only ever appended
+    postings: HashMap<Trigram, Vec<u32>>,
+    docs: HashMap<u32, Doc>,
+    /// Clip id -> its current doc number
+    by_clip: HashMap<String, u32>,
+    next_doc: u32,
+    /// Posting entries that point at replaced or removed docs
+    dead_entries: usize,
+    live_entries: usize,
+}
+
+/// Lowercase the way both the index and the matcher see text
+fn fold(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// The text fields a search looks at
+fn fields(clip: &ClipObject) -> [&str; 4] {
+    [
+        &clip.content,
+        clip.title.as_deref().unwrap_or(""),
+        &clip.metadata.source_app,
+        &clip.metadata.window_title,
+    ]
+}
+
+/// Trigrams of `text` (already folded), appended to `out`
+fn trigrams_into(text: &str, out: &mut Vec<Trigram>) {
+    let chars: Vec<char> = text.chars().collect();
+    for window in chars.windows(3) {
+        out.push(((window[0] as u64) << 42) | ((window[1] as u64) << 21) | window[2] as u64);
+    }
+}
+
+/// How much a hit counts in each of `fields`: content, title, source app
+/// and window title
+const FIELD_WEIGHTS: [u32; 4] = [1, 4, 2, 2];
+/// A search with fewer exact hits than this also looks for fuzzy ones
+pub const FUZZY_BELOW: usize = 20;
+/// Most clips, newest first, a fuzzy pass looks at; fuzzy scoring folds
+/// every field, so a query that hits nothing shouldn't scan a whole pastebook
+pub const MAX_FUZZY_SCAN: usize = 2_000;
+/// Shortest term matched fuzzily; shorter ones would match almost anything
+const MIN_FUZZY_CHARS: usize = 3;
+
+/// A folded query split into words, matched in any order
+pub struct Query {
+    folded: String,
+    terms: Vec<String>,
+}
+
+impl Query {
+    pub fn new(query: &str) -> Self {
+        let folded = fold(query.trim());
+        let terms = folded.split_whitespace().map(str::to_string).collect();
+        Self { folded, terms }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    pub fn terms(&self) -> impl Iterator<Item = &str> {
+        self.terms.iter().map(String::as_str)
+    }
+
+    /// Relevance of `clip`, or None if some term hits none of its fields.
+    /// Terms hit as substrings, scoring more at word boundaries and in the
+    /// title; with `fuzzy` set they may instead hit as letters in order
+    /// within one word ("cptr" for "capture"), which always scores lower.
+    /// Fuzzy scoring only reads the indexed head of each field, so long
+    /// clips cost the same as short ones.
+    pub fn score(&self, clip: &ClipObject, fuzzy: bool) -> Option<u32> {
+        // Rule clips out before folding anything, which is most of the cost
+        if !fuzzy && !self.terms.iter().all(|term| fields(clip).iter().any(|f| contains_folded(f, term))) {
+            return None;
+        }
+        let folded: Vec<String> = fields(clip)
+            .iter()
+            .map(|field| match fuzzy {
+                true => fold(textutil::truncate(field, MAX_INDEXED_CHARS)),
+                false => fold(field),
+            })
+            .collect();
+
+        let mut total = 0;
+        for term in &self.terms {
+            total += folded
+                .iter()
+                .zip(FIELD_WEIGHTS)
+                .filter_map(|(field, weight)| {
+                    let hit = substring_score(field, term)
+                        .or_else(|| fuzzy.then(|| fuzzy_score(field, term)).flatten())?;
+                    Some(hit * weight)
+                })
+                .max()?;
+        }
+        // The words together, as typed, beat the same words apart
+        if self.terms.len() > 1 {
+            total += folded
+                .iter()
+                .zip(FIELD_WEIGHTS)
+                .filter(|(field, _)| field.contains(&self.folded))
+                .map(|(_, weight)| 10 * weight)
+                .max()
+                .unwrap_or(0);
+        }
+        Some(total)
+    }
+}
+
+/// Whether `field` contains the folded `term`, without allocating when the
+/// field is ASCII
+fn contains_folded(field: &str, term: &str) -> bool {
+    if !field.is_ascii() {
+        return fold(field).contains(term);
+    }
+    let term = term.as_bytes();
+    term.is_empty() || field.as_bytes().windows(term.len()).any(|window| window.eq_ignore_ascii_case(term))
+}
+
+/// Score of `term` appearing in `field`: 10, plus 5 at the start of a word
+/// and 5 more for the whole word
+fn substring_score(field: &str, term: &str) -> Option<u32> {
+    let is_word = |c: Option<char>| c.is_some_and(char::is_alphanumeric);
+    field
+        .match_indices(term)
+        .map(|(at, _)| {
+            let starts = !is_word(field[..at].chars().next_back());
+            let ends = !is_word(field[at + term.len()..].chars().next());
+            10 + if starts { 5 } else { 0 } + if starts && ends { 5 } else { 0 }
+        })
+        .max()
+}
+
+/// Score of `term`'s letters appearing in order inside one word of `field`
+/// that's at most twice as long, below any substring hit
+fn fuzzy_score(field: &str, term: &str) -> Option<u32> {
+    let len = term.chars().count();
+    if len < MIN_FUZZY_CHARS {
+        return None;
+    }
+    field
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|word| word.chars().count() <= len * 2)
+        .any(|word| {
+            let mut letters = word.chars();
+            term.chars().all(|t| letters.any(|c| c == t))
+        })
+        .then_some(2)
+}
+
+impl SearchIndex {
+    /// Throw everything away and index `clips` from scratch
+    pub fn rebuild<'a>(&mut self, clips: impl Iterator<Item = &'a ClipObject>) {
+        *self = Self::default();
+        for clip in clips {
+            self.insert(clip);
+        }
+    }
+
+    /// Index a clip, replacing whatever was indexed for it before
+    pub fn insert(&mut self, clip: &ClipObject) {
+        self.remove(&clip.id);
+
+        let mut partial = false;
+        let mut grams = Vec::new();
+        for field in fields(clip) {
+            let folded = fold(field);
+            let mut chars = folded.chars();
+            let head: String = chars.by_ref().take(MAX_INDEXED_CHARS).collect();
+            partial |= chars.next().is_some();
+            trigrams_into(&head, &mut grams);
+        }
+        grams.sort_unstable();
+        grams.dedup();
+
+        let doc = self.next_doc;
+        self.next_doc += 1;
+        for gram in &grams {
+            self.postings.entry(*gram).or_default().push(doc);
+        }
+        self.live_entries += grams.len();
+        self.docs.insert(
+            doc,
+            Doc {
+                clip_id: clip.id.clone(),
+                entries: grams.len(),
+                partial,
+            },
+        );
+        self.by_clip.insert(clip.id.clone(), doc);
+    }
+
+    /// Drop a clip from the index; its posting entries are cleaned up lazily
+    pub fn remove(&mut self, clip_id: &str) {
+        let Some(doc) = self.by_clip.remove(clip_id) else {
+            return;
+        };
+        if let Some(removed) = self.docs.remove(&doc) {
+            self.live_entries -= removed.entries;
+            self.dead_entries += removed.entries;
+        }
+        if self.dead_entries > self.live_entries.max(1024) {
+            self.compact();
+        }
+    }
+
+    /// Whether the clip has been indexed at all
+    pub fn contains(&self, clip_id: &str) -> bool {
+        self.by_clip.contains_key(clip_id)
+    }
+
+    /// Strip dead docs out of the posting lists
+    fn compact(&mut self) {
+        let docs = &self.docs;
+        self.postings.retain(|_, list| {
+            list.retain(|doc| docs.contains_key(doc));
+            !list.is_empty()
+        });
+        self.dead_entries = 0;
+    }
+
+    /// Clip ids that may match `folded_query`, or None when the query is too
+    /// short for the index to narrow anything down
+    pub fn candidates(&self, folded_query: &str) -> Option<HashSet<&str>> {
+        let mut grams = Vec::new();
+        trigrams_into(folded_query, &mut grams);
+        if grams.is_empty() {
+            return None;
+        }
+        grams.sort_unstable();
+        grams.dedup();
+
+        // Intersect starting from the rarest trigram
+        let mut lists = Vec::with_capacity(grams.len());
+        for gram in &grams {
+            match self.postings.get(gram) {
+                Some(list) => lists.push(list),
+                None => {
+                    lists.clear();
+                    break;
+                }
+            }
+        }
+        lists.sort_by_key(|list| list.len());
+
+        let mut result: HashSet<&str> = HashSet::new();
+        if let Some((first, rest)) = lists.split_first() {
+            for doc in first.iter() {
+                if rest.iter().all(|list| list.binary_search(doc).is_ok()) {
+                    if let Some(entry) = self.docs.get(doc) {
+                        result.insert(&entry.clip_id);
+                    }
+                }
+            }
+        }
+
+        // Text past the cap isn't indexed, so those clips can't be ruled out
+        result.extend(
+            self.docs
+                .values()
+                .filter(|entry| entry.partial)
+                .map(|entry| entry.clip_id.as_str()),
+        );
+        Some(result)
+    }
+
+    pub fn stats(&self) -> SearchIndexStats {
+        SearchIndexStats {
+            clips: self.docs.len(),
+            trigrams: self.postings.len(),
+            entries: self.live_entries + self.dead_entries,
+            partial: self.docs.values().filter(|entry| entry.partial).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::AppStorage;
+    use crate::test_support::{clip, storage_with};
+
+    #[test]
+    fn finds_content_title_and_source_fields() {
+        let (mut storage, ids) = storage_with(&["Quarterly Report", "grocery list"]);
+        let mut titled = clip("body");
+        titled.title = Some("Meeting notes".to_string());
+        storage.add_clip(titled).unwrap();
+
+        assert_eq!(storage.search_clips("REPORT").len(), 1);
+        assert_eq!(storage.search_clips("meeting").len(), 1);
+        assert_eq!(storage.search_clips("test window").len(), 3);
+        assert_eq!(storage.search_clips("gr")[0].id, ids[1]);
+        assert!(storage.search_clips("missing").is_empty());
+        assert_eq!(storage.search_clips("").len(), 3);
+    }
+
+    #[test]
+    fn results_are_ranked_and_partial_words_still_hit() {
+        let (mut storage, ids) = storage_with(&[
+            "notes on the release capture",
+            "captures",
+            "screen recaptured later",
+            "nothing relevant",
+        ]);
+        let mut titled = clip("body text");
+        titled.title = Some("Capture plan".to_string());
+        let titled_id = titled.id.clone();
+        storage.add_clip(titled).unwrap();
+
+        let ranked: Vec<String> = storage.search_clips("CAPTURE").into_iter().map(|c| c.id).collect();
+        assert_eq!(ranked, vec![titled_id, ids[0].clone(), ids[1].clone(), ids[2].clone()]);
+        // Words in any order, partial words, and letters in order as a fallback
+        assert_eq!(storage.search_clips("release notes")[0].id, ids[0]);
+        assert_eq!(storage.search_clips("relev")[0].id, ids[3]);
+        assert_eq!(storage.search_clips("nthng")[0].id, ids[3]);
+        assert!(storage.search_clips("nothing capture").is_empty());
+    }
+
+    #[test]
+    fn text_past_the_indexed_length_is_still_found() {
+        let (mut storage, _) = storage_with(&[]);
+        let long = format!("{} tail-marker", "x".repeat(super::MAX_INDEXED_CHARS + 10));
+        storage.add_clip(clip(&long)).unwrap();
+        assert_eq!(storage.search_clips("tail-marker").len(), 1);
+        assert_eq!(storage.search_index.stats().partial, 1);
+    }
+
+    #[test]
+    fn removed_clips_are_compacted_away() {
+        let (mut storage, ids) = storage_with(&["alpha", "beta"]);
+        for _ in 0..50 {
+            storage.update_clip(&ids[0], format!("alpha {}", "y".repeat(100)), None);
+        }
+        let stats = storage.search_index.stats();
+        assert_eq!(stats.clips, 2);
+        assert_eq!(storage.search_clips("alpha").len(), 1);
+        assert_eq!(storage.search_clips("beta").len(), 1);
+    }
+
+    /// The timing tests below are ignored; this checks, without a clock,
+    /// that the index leaves few clips to verify
+    #[test]
+    fn index_narrows_a_large_pastebook_to_a_few_candidates() {
+        let mut storage = AppStorage::default();
+        for i in 0..2_000 {
+            storage.add_clip(clip(&format!("clip number {} about topic {}", i, i % 97))).unwrap();
+        }
+        let candidates = storage.search_index.candidates("topic 42").unwrap();
+        assert!(candidates.len() < 100, "{} candidates", candidates.len());
+        let exact = storage.get_clips().iter().filter(|c| c.content.ends_with("topic 42")).count();
+        assert!(exact > 0);
+        assert_eq!(
+            candidates.iter().filter(|id| storage.find_clip(id).unwrap().content.contains("topic 42")).count(),
+            exact
+        );
+    }
+
+    /// Run with `cargo test --release -- --ignored` for a meaningful number
+    #[test]
+    #[ignore]
+    fn search_over_ten_thousand_clips_is_fast() {
+        let mut storage = AppStorage::default();
+        for i in 0..10_000 {
+            storage.add_clip(clip(&format!("clip number {} about topic {}", i, i % 97))).unwrap();
+        }
+        let started = std::time::Instant::now();
+        let found = storage.search_clips("topic 42");
+        let elapsed = started.elapsed();
+        assert!(!found.is_empty());
+        assert!(elapsed.as_millis() < 10, "search took {:?}", elapsed);
+    }
+
+    /// A query that hits nothing is the worst case: no exact hits, so the
+    /// fuzzy pass runs, capped at `MAX_FUZZY_SCAN` clips
+    #[test]
+    #[ignore]
+    fn no_match_search_over_ten_thousand_clips_is_fast() {
+        let mut storage = AppStorage::default();
+        for i in 0..10_000 {
+            storage.add_clip(clip(&format!("clip number {} about topic {}", i, i % 97))).unwrap();
+        }
+        let started = std::time::Instant::now();
+        let found = storage.search_clips("zzqxj vvkw");
+        let elapsed = started.elapsed();
+        assert!(found.is_empty());
+        assert!(elapsed.as_millis() < 20, "search took {:?}", elapsed);
+    }
+}
@@ -0,0 +1,176 @@
+use serde::Serialize;
+use std::path::Path;
+
+/// Files with these extensions are captured by content when small enough
+const TEXT_EXTENSIONS: &[&str] = &["txt", "md"];
+/// Largest text file captured by content; bigger ones are captured by path
+const MAX_FILE_TEXT_BYTES: u64 = 100_000;
+#[cfg(windows)]
+const VERB: &str = "SendToStack";
+/// Classes that get the verb: every file type, and folders
+#[cfg(windows)]
+const CLASSES: &[&str] = &["*", "Directory"];
+
+/// A file handed to Stack with `--add-file`, ready to become a clip
+#[derive(Debug, Clone)]
+pub struct SentFile {
+    pub content: String,
+    pub title: String,
+}
+
+/// Turn a sent path into clip content: the text of a small .txt/.md file,
+/// otherwise the path itself
+pub fn read_sent_file(path: &Path) -> Result<SentFile, String> {
+    let metadata =
+        std::fs::metadata(path).map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let title = path
+        .file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string());
+
+    let is_text = path
+        .extension()
+        .and_then(|ext| ext.to_str())
+        .is_some_and(|ext| TEXT_EXTENSIONS.contains(&ext.to_lowercase().as_str()));
+    if metadata.is_file() && is_text && metadata.len() <= MAX_FILE_TEXT_BYTES {
+        // Not valid UTF-8 after all: fall back to the path
+        if let Ok(content) = std::fs::read_to_string(path) {
+            return Ok(SentFile { content, title });
+        }
+    }
+
+    Ok(SentFile {
+        content: path.display().to_string(),
+        title,
+    })
+}
+
+/// What a registry operation did to one key
+// Only constructed by the Windows registry code
+#[cfg_attr(not(windows), allow(dead_code))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RegistryAction {
+    Set,
+    Deleted,
+    NotPresent,
+}
+
+/// One registry key touched by (un)registering the shell integration
+#[derive(Debug, Clone, Serialize)]
+pub struct RegistryChange {
+    pub key: String,
+    /// Value name for `Set`; None is the key's default value
+    pub value: Option<String>,
+    pub action: RegistryAction,
+}
+
+#[cfg(windows)]
+fn verb_key(class: &str) -> String {
+    format!("Software\\Classes\\{}\\shell\\{}", class, VERB)
+}
+
+#[cfg(windows)]
+fn set_string(
+    changes: &mut Vec<RegistryChange>,
+    subkey: &str,
+    name: Option<&str>,
+    value: &str,
+) -> Result<(), String> {
+    use windows::core::{HSTRING, PCWSTR};
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::System::Registry::{RegSetKeyValueW, HKEY_CURRENT_USER, REG_SZ};
+
+    let data: Vec<u16> = value.encode_utf16().chain(std::iter::once(0)).collect();
+    let name_w = name.map(HSTRING::from);
+    let status = unsafe {
+        RegSetKeyValueW(
+            HKEY_CURRENT_USER,
+            &HSTRING::from(subkey),
+            name_w.as_ref().map_or(PCWSTR::null(), |n| PCWSTR(n.as_ptr())),
+            REG_SZ.0,
+            Some(data.as_ptr() as *const _),
+            (data.len() * 2) as u32,
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return Err(format!(
+            "Failed to set HKCU\\{} ({:?}) after {} change(s): {:?}",
+            subkey,
+            name.unwrap_or("default"),
+            changes.len(),
+            status
+        ));
+    }
+
+    changes.push(RegistryChange {
+        key: format!("HKCU\\{}", subkey),
+        value: name.map(str::to_string),
+        action: RegistryAction::Set,
+    });
+    Ok(())
+}
+
+/// Add a "Send to Stack" entry to the Explorer context menu for the current
+/// user, launching this exe with `--add-file`
+#[cfg(windows)]
+pub fn register_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    let exe = std::env::current_exe().map_err(|e| format!("Cannot locate Stack: {}", e))?;
+    let exe = exe.display().to_string();
+
+    let mut changes = Vec::new();
+    for class in CLASSES {
+        let key = verb_key(class);
+        set_string(&mut changes, &key, None, "Send to Stack")?;
+        set_string(&mut changes, &key, Some("Icon"), &exe)?;
+        set_string(
+            &mut changes,
+            &format!("{}\\command", key),
+            None,
+            &format!("\"{}\" --add-file \"%1\"", exe),
+        )?;
+    }
+    Ok(changes)
+}
+
+/// Remove the "Send to Stack" context menu entries
+#[cfg(windows)]
+pub fn unregister_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::{ERROR_FILE_NOT_FOUND, ERROR_SUCCESS};
+    use windows::Win32::System::Registry::{RegDeleteTreeW, HKEY_CURRENT_USER};
+
+    let mut changes = Vec::new();
+    for class in CLASSES {
+        let key = verb_key(class);
+        let status = unsafe { RegDeleteTreeW(HKEY_CURRENT_USER, &HSTRING::from(key.as_str())) };
+        let action = if status == ERROR_SUCCESS {
+            RegistryAction::Deleted
+        } else if status == ERROR_FILE_NOT_FOUND {
+            RegistryAction::NotPresent
+        } else {
+            return Err(format!(
+                "Failed to delete HKCU\\{} after {} change(s): {:?}",
+                key,
+                changes.len(),
+                status
+            ));
+        };
+        changes.push(RegistryChange {
+            key: format!("HKCU\\{}", key),
+            value: None,
+            action,
+        });
+    }
+    Ok(changes)
+}
+
+#[cfg(not(windows))]
+pub fn register_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    Err("Shell integration is only available on Windows".to_string())
+}
+
+#[cfg(not(windows))]
+pub fn unregister_shell_integration() -> Result<Vec<RegistryChange>, String> {
+    Err("Shell integration is only available on Windows".to_string())
+}
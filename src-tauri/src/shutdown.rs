@@ -0,0 +1,150 @@
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Mutex};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::health;
+use crate::safe_mode;
+use crate::storage::AppStorage;
+use crate::AppState;
+
+/// Written next to the storage file by a clean exit, removed on startup
+const MARKER_FILE: &str = "clean_shutdown";
+/// Copy of the storage file as of the last clean exit
+const BACKUP_FILE: &str = "pastebooks.last-clean.json";
+/// Longest exit waits for the final save
+pub const FLUSH_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// The previous session ended without a final save (crash, kill, power loss)
+#[derive(Debug, Clone, Serialize)]
+pub struct UncleanShutdown {
+    /// Storage as of the last clean exit, if there was one
+    pub backup: Option<PathBuf>,
+    pub backup_saved_at: Option<DateTime<Utc>>,
+}
+
+static PREVIOUS: Mutex<Option<UncleanShutdown>> = Mutex::new(None);
+static FLUSHED: AtomicBool = AtomicBool::new(false);
+
+/// Check (and consume) the marker left next to `storage_file` by the last
+/// exit. A first run, with no storage file yet, counts as clean.
+pub fn check_previous(storage_file: &Path) -> Option<UncleanShutdown> {
+    let marker = storage_file.with_file_name(MARKER_FILE);
+    let clean = marker.exists() || !storage_file.exists();
+    // Gone until this session exits cleanly, so a crash shows up next time
+    let _ = fs::remove_file(&marker);
+    if clean {
+        return None;
+    }
+
+    let backup = storage_file.with_file_name(BACKUP_FILE);
+    let backup_saved_at = fs::metadata(&backup)
+        .and_then(|m| m.modified())
+        .ok()
+        .map(DateTime::<Utc>::from);
+    Some(UncleanShutdown {
+        backup: backup_saved_at.is_some().then_some(backup),
+        backup_saved_at,
+    })
+}
+
+/// Check the last exit at startup and remember the result for the UI
+pub fn note_previous(storage_file: &Path) {
+    let unclean = check_previous(storage_file);
+    safe_mode::note_startup(storage_file, unclean.is_none());
+    if unclean.is_some() {
+        eprintln!("Stack did not shut down cleanly last time; recent changes may be missing");
+    }
+    *PREVIOUS.lock().unwrap() = unclean;
+}
+
+/// How the previous session ended, if it wasn't cleanly
+pub fn previous() -> Option<UncleanShutdown> {
+    PREVIOUS.lock().unwrap().clone()
+}
+
+/// Forget the unclean-shutdown warning once the user has dealt with it
+pub fn dismiss() {
+    *PREVIOUS.lock().unwrap() = None;
+}
+
+/// Save storage, keep a copy as the last-clean backup and write the marker.
+/// Nothing is marked clean while storage is in memory. In safe mode storage
+/// is read-only, so only the marker is written.
+pub fn finish(storage: &mut AppStorage) -> Result<(), String> {
+    let read_only = safe_mode::is_active();
+    if !read_only {
+        storage.save()?;
+    }
+    let Some(path) = storage.storage_path() else {
+        return Ok(());
+    };
+    if health::is_in_memory() {
+        return Err("Storage is in memory; the final save couldn't be written".to_string());
+    }
+
+    if !read_only {
+        storage.write_copy(&path.with_file_name(BACKUP_FILE))?;
+    }
+    let marker = path.with_file_name(MARKER_FILE);
+    fs::write(&marker, Utc::now().to_rfc3339())
+        .map_err(|e| format!("Failed to write {}: {}", marker.display(), e))
+}
+
+/// The final save on the way out. Runs once, and gives up after
+/// `FLUSH_TIMEOUT` so a stuck lock can't keep the app from exiting.
+pub fn flush_on_exit(app: &AppHandle) {
+    if FLUSHED.swap(true, Ordering::SeqCst) {
+        return;
+    }
+    let (sender, receiver) = mpsc::channel();
+    let app = app.clone();
+    std::thread::spawn(move || {
+        let state = app.state::<AppState>();
+        let result = finish(&mut state.storage.write().unwrap());
+        let _ = sender.send(result);
+    });
+
+    match receiver.recv_timeout(FLUSH_TIMEOUT) {
+        Ok(Ok(())) => {}
+        Ok(Err(e)) => eprintln!("Final save failed: {}", e),
+        Err(_) => eprintln!("Final save didn't finish within {:?}", FLUSH_TIMEOUT),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{clip, TempStorage};
+
+    #[test]
+    fn clean_exit_leaves_a_marker_and_backup() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("kept")).unwrap();
+        finish(&mut temp.storage).unwrap();
+
+        assert!(check_previous(&temp.path()).is_none());
+        // The marker was consumed: without another clean exit, the next start warns
+        let unclean = check_previous(&temp.path()).unwrap();
+        let backup = unclean.backup.unwrap();
+        assert!(fs::read_to_string(backup).unwrap().contains("kept"));
+    }
+
+    #[test]
+    fn first_run_is_not_unclean() {
+        let dir = tempfile::tempdir().unwrap();
+        assert!(check_previous(&dir.path().join("pastebooks.json")).is_none());
+    }
+
+    #[test]
+    fn crash_without_any_clean_exit_has_no_backup() {
+        let temp = TempStorage::new();
+        fs::write(temp.path(), "{}").unwrap();
+        let unclean = check_previous(&temp.path()).unwrap();
+        assert!(unclean.backup.is_none());
+    }
+}
@@ -0,0 +1,262 @@
+use chrono::{DateTime, Duration, TimeZone, Timelike, Utc};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::storage::AppStorage;
+use crate::textutil;
+
+/// How many source apps the dashboard lists
+const TOP_APPS: usize = 10;
+/// How many virtual desktops and monitors it lists
+const TOP_WORKSPACES: usize = 10;
+/// Weeks of average clip length, counting back from now
+const TREND_WEEKS: usize = 8;
+/// Dashboard opens this soon after the last computation reuse it
+pub const DEFAULT_CACHE_TTL_SECS: u64 = 60;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct AppCount {
+    pub app: String,
+    pub clips: usize,
+}
+
+/// Clips captured on one virtual desktop or monitor
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WorkspaceCount {
+    pub name: String,
+    pub clips: usize,
+}
+
+/// Clips captured in one 7-day window and their average length
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct WeekLength {
+    /// Start of the window; the last one ends now
+    pub from: DateTime<Utc>,
+    pub clips: usize,
+    /// Characters per clip, 0 for an empty week
+    pub average_chars: f64,
+}
+
+/// App-wide overview across every pastebook, for the dashboard
+#[derive(Debug, Clone, Serialize)]
+pub struct GlobalStats {
+    pub pastebooks: usize,
+    pub total_clips: usize,
+    /// Captured in the last 7 days
+    pub clips_this_week: usize,
+    /// Captured 7 to 14 days ago
+    pub clips_last_week: usize,
+    /// Clips per local hour of capture, 0 to 23
+    pub clips_by_hour: [usize; 24],
+    /// Local hour with the most captures; None with no clips
+    pub busiest_hour: Option<u32>,
+    /// Most used source apps, most first
+    pub top_apps: Vec<AppCount>,
+    /// Clips per virtual desktop, most first; clips without one aren't counted
+    pub by_desktop: Vec<WorkspaceCount>,
+    /// Clips per monitor, most first
+    pub by_monitor: Vec<WorkspaceCount>,
+    /// Average length per week, oldest first
+    pub length_trend: Vec<WeekLength>,
+    pub computed_at: DateTime<Utc>,
+}
+
+/// Everything in one pass over the clips, a pastebook at a time: ones not
+/// read yet are read for the pass and dropped after, so they aren't all held
+/// at once. Nothing but app, desktop and monitor names is copied.
+pub fn compute<Tz: TimeZone>(storage: &AppStorage, tz: &Tz, now: DateTime<Utc>) -> GlobalStats {
+    let week = Duration::days(7);
+    let mut total_clips = 0;
+    let (mut clips_this_week, mut clips_last_week) = (0, 0);
+    let mut clips_by_hour = [0usize; 24];
+    let mut per_app: HashMap<String, usize> = HashMap::new();
+    let mut per_desktop: HashMap<String, usize> = HashMap::new();
+    let mut per_monitor: HashMap<String, usize> = HashMap::new();
+    let mut trend = [(0usize, 0usize); TREND_WEEKS];
+
+    for pastebook in &storage.pastebooks {
+        for clip in pastebook.clips.peek().iter() {
+            total_clips += 1;
+            let at = clip.metadata.timestamp;
+            clips_by_hour[at.with_timezone(tz).hour() as usize] += 1;
+            count(&mut per_app, &clip.metadata.source_app);
+            if let Some(desktop) = &clip.metadata.virtual_desktop {
+                count(&mut per_desktop, desktop);
+            }
+            if let Some(monitor) = &clip.metadata.monitor {
+                count(&mut per_monitor, monitor);
+            }
+
+            // Future timestamps (clock changes) count as this week
+            let age = (now - at).max(Duration::zero());
+            let weeks_ago = (age.num_seconds() / week.num_seconds()) as usize;
+            match weeks_ago {
+                0 => clips_this_week += 1,
+                1 => clips_last_week += 1,
+                _ => {}
+            }
+            if weeks_ago < TREND_WEEKS {
+                let (clips, chars) = &mut trend[TREND_WEEKS - 1 - weeks_ago];
+                *clips += 1;
+                *chars += textutil::grapheme_count(&clip.content);
+            }
+        }
+    }
+
+    let busiest_hour = (total_clips > 0).then(|| {
+        // Earliest hour wins a tie
+        (0..24u32).max_by_key(|&h| (clips_by_hour[h as usize], std::cmp::Reverse(h))).unwrap_or(0)
+    });
+    let top_apps = most_first(per_app, TOP_APPS)
+        .map(|(app, clips)| AppCount { app, clips })
+        .collect();
+    let workspaces = |counts| {
+        most_first(counts, TOP_WORKSPACES)
+            .map(|(name, clips)| WorkspaceCount { name, clips })
+            .collect()
+    };
+    let length_trend = trend
+        .iter()
+        .enumerate()
+        .map(|(i, &(clips, chars))| WeekLength {
+            from: now - week * (TREND_WEEKS - i) as i32,
+            clips,
+            average_chars: if clips == 0 { 0.0 } else { chars as f64 / clips as f64 },
+        })
+        .collect();
+
+    GlobalStats {
+        pastebooks: storage.pastebooks.len(),
+        total_clips,
+        clips_this_week,
+        clips_last_week,
+        clips_by_hour,
+        busiest_hour,
+        top_apps,
+        by_desktop: workspaces(per_desktop),
+        by_monitor: workspaces(per_monitor),
+        length_trend,
+        computed_at: now,
+    }
+}
+
+/// The `limit` biggest counts, ties in name order
+fn most_first(counts: HashMap<String, usize>, limit: usize) -> impl Iterator<Item = (String, usize)> {
+    let mut counts: Vec<(String, usize)> = counts.into_iter().collect();
+    counts.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+    counts.into_iter().take(limit)
+}
+
+/// Count one more for `key`, copying it only the first time
+fn count(counts: &mut HashMap<String, usize>, key: &str) {
+    match counts.get_mut(key) {
+        Some(clips) => *clips += 1,
+        None => {
+            counts.insert(key.to_string(), 1);
+        }
+    }
+}
+
+static CACHE: Mutex<Option<(Instant, GlobalStats)>> = Mutex::new(None);
+
+/// The last computed stats if they're under `ttl_secs` old, else fresh ones
+/// (which replace them); a ttl of 0 always recomputes
+pub fn cached<Tz: TimeZone>(storage: &AppStorage, tz: &Tz, ttl_secs: u64) -> GlobalStats {
+    let mut cache = CACHE.lock().unwrap();
+    if let Some((at, stats)) = cache.as_ref() {
+        if at.elapsed().as_secs() < ttl_secs {
+            return stats.clone();
+        }
+    }
+    let stats = compute(storage, tz, Utc::now());
+    *cache = Some((Instant::now(), stats.clone()));
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::Pastebook;
+    use crate::test_support::clip_at;
+    use chrono::FixedOffset;
+
+    fn app_clip(content: &str, app: &str, at: DateTime<Utc>) -> crate::storage::ClipObject {
+        let mut clip = clip_at(content, at);
+        clip.metadata.source_app = app.to_string();
+        clip
+    }
+
+    #[test]
+    fn stats_cover_every_pastebook() {
+        let now = Utc.with_ymd_and_hms(2026, 3, 20, 12, 0, 0).unwrap();
+        let mut storage = AppStorage::default();
+        let mut work = Pastebook::new("Work".to_string());
+        *work.clips = vec![
+            // Five characters as seen, six code points
+            app_clip("he\u{301}llo", "code.exe", now - Duration::hours(2)),
+            app_clip("abc", "chrome.exe", now - Duration::hours(26)),
+            app_clip("abcdefg", "code.exe", now - Duration::days(9)),
+        ];
+        *storage.pastebooks[0].clips = vec![
+            app_clip("xy", "code.exe", now - Duration::days(60)),
+            app_clip("future", "slack.exe", now + Duration::hours(1)),
+        ];
+        storage.pastebooks[0].clips[1].metadata.virtual_desktop = Some("Research".to_string());
+        for clip in work.clips.iter_mut().take(2) {
+            clip.metadata.virtual_desktop = Some("Desktop 1".to_string());
+            clip.metadata.monitor = Some("\\\\.\\DISPLAY1".to_string());
+        }
+        storage.pastebooks.push(work);
+
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let stats = compute(&storage, &tz, now);
+        assert_eq!(stats.pastebooks, 2);
+        assert_eq!(stats.total_clips, 5);
+        assert_eq!((stats.clips_this_week, stats.clips_last_week), (3, 1));
+        assert_eq!(stats.top_apps[0], AppCount { app: "code.exe".to_string(), clips: 3 });
+        assert_eq!(stats.top_apps.len(), 3);
+        assert_eq!(
+            stats.by_desktop,
+            vec![
+                WorkspaceCount { name: "Desktop 1".to_string(), clips: 2 },
+                WorkspaceCount { name: "Research".to_string(), clips: 1 },
+            ]
+        );
+        assert_eq!(stats.by_monitor.len(), 1);
+        // Two clips each at local 12:00 and 14:00; the earlier hour wins the tie
+        assert_eq!(stats.clips_by_hour.iter().sum::<usize>(), 5);
+        assert_eq!(stats.busiest_hour, Some(12));
+
+        assert_eq!(stats.length_trend.len(), TREND_WEEKS);
+        let this_week = stats.length_trend.last().unwrap();
+        assert_eq!(this_week.from, now - Duration::days(7));
+        assert_eq!(this_week.clips, 3);
+        assert!((this_week.average_chars - 14.0 / 3.0).abs() < 1e-9);
+        assert_eq!(stats.length_trend[TREND_WEEKS - 2].average_chars, 7.0);
+
+        let empty = compute(&AppStorage::default(), &tz, now);
+        assert_eq!(empty.busiest_hour, None);
+        assert!(empty.top_apps.is_empty());
+    }
+
+    /// Run with `cargo test --release -- --ignored` for a meaningful number
+    #[test]
+    #[ignore]
+    fn stats_over_twenty_thousand_clips_are_fast() {
+        let now = Utc::now();
+        let mut storage = AppStorage::default();
+        storage.pastebooks[0].clips = (0..20_000)
+            .map(|i| {
+                let content = format!("clip number {} about topic {} ", i, i % 97).repeat(8);
+                app_clip(&content, &format!("app{}.exe", i % 40), now - Duration::minutes(i * 7))
+            })
+            .collect();
+        let started = Instant::now();
+        let stats = compute(&storage, &chrono::Local, now);
+        let elapsed = started.elapsed();
+        assert_eq!(stats.total_clips, 20_000);
+        assert!(elapsed.as_millis() < 50, "stats took {:?}", elapsed);
+    }
+}
@@ -1,18 +1,175 @@
-use chrono::{DateTime, Utc};
-use serde::{Deserialize, Serialize};
+use chrono::{DateTime, NaiveDate, TimeZone, Timelike, Utc};
+use regex::{NoExpand, Regex, RegexBuilder};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::cell::Cell;
+use std::collections::{HashMap, HashSet};
 use std::fs;
-use std::path::PathBuf;
+use std::ops::{Deref, DerefMut};
+use std::path::{Path, PathBuf};
+use std::sync::{OnceLock, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::Instant;
 use uuid::Uuid;
 
-use crate::window::WindowInfo;
+use crate::ai;
+use crate::announce::Language;
+use crate::ai_history::AiInteraction;
+use crate::assets;
+use crate::attribution;
+use crate::autoexport::AutoExportConfig;
+use crate::export::LineEnding;
+use crate::rate_limit::RateLimitAction;
+use crate::clipboard_hold::HoldAggressiveness;
+use crate::clock;
+use crate::diff::{self, PatchStep};
+use crate::health::{self, StorageHealth};
+use crate::ingest::{self, IngestTransform};
+use crate::jumplist::{self, JumpItem};
+use crate::migration;
+use crate::mirror;
+use crate::notify::NotificationPolicy;
+use crate::paths;
+use crate::presets::{self, PromptPreset};
+use crate::rules::{self, CaptureRule, RuleSet};
+use crate::safe_mode;
+use crate::search::{self, SearchIndex, SearchIndexStats};
+use crate::shutdown;
+use crate::usage::{self, ModelPrice, UsageEntry};
+use crate::sync::SyncState;
+use crate::terminal;
+use crate::textutil;
+use crate::titlebar;
+use crate::undo::{PastebookUndo, UndoOutcome, UndoStack};
+use crate::webhooks::WebhookConfig;
+use crate::window::{self, WindowInfo};
 
 /// A single clip captured by the user
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ClipObject {
     pub id: String,
+    #[serde(serialize_with = "serialize_content")]
     pub content: String,
+    /// Asset holding the full content when it's too big to keep inline in the
+    /// storage file. Only written there; in memory `content` is complete once
+    /// `AppStorage::load_contents` has run.
+    #[serde(default, skip_serializing_if = "skip_content_ref")]
+    pub content_ref: Option<String>,
     pub metadata: ClipMetadata,
     pub status: String,
+    #[serde(default)]
+    pub title: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(default)]
+    pub label: Option<String>,
+    /// Content hashes of sidecar files in the asset store
+    #[serde(default)]
+    pub assets: Vec<String>,
+    /// Content is kept out of broadcasts and previews
+    #[serde(default)]
+    pub sensitive: bool,
+    /// How a generated clip was produced
+    #[serde(default)]
+    pub provenance: Option<Provenance>,
+    #[serde(default)]
+    pub reminder: Option<Reminder>,
+    /// Holds its position through reorders and merges
+    #[serde(default)]
+    pub pinned: bool,
+    /// Survives bulk deletes (clear all, merging it away); deleting it on
+    /// its own still works
+    #[serde(default)]
+    pub locked: bool,
+    /// When this run of Stack captured the clip, so dedup doesn't depend on
+    /// the wall clock; unset for clips loaded from disk
+    #[serde(skip)]
+    pub captured_instant: Option<Instant>,
+    /// The capture as it arrived, when terminal cleanup changed `content`
+    #[serde(default)]
+    pub original_content: Option<String>,
+    /// Earlier contents, oldest first, for `revert_clip`
+    #[serde(default)]
+    pub history: Vec<ClipRevision>,
+    /// When the clip was last edited; unset until it is
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_at: Option<DateTime<Utc>>,
+    /// When the clip was moved to the trash; unset while it's live
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub trashed_at: Option<DateTime<Utc>>,
+}
+
+/// Edits with the same cause this close together share one revision
+const REVISION_COALESCE_SECS: i64 = 10;
+/// Revisions kept per clip; the oldest drop off past this
+const MAX_REVISIONS: usize = 50;
+
+/// An earlier content of a clip, stored as a patch against the content that
+/// replaced it (the next revision's, or the current one for the newest)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClipRevision {
+    /// When it was replaced
+    pub at: DateTime<Utc>,
+    /// What replaced it: "manual" for an edit, "revert", "metadata", or
+    /// the operation that rewrote the clip
+    pub cause: String,
+    pub patch: Vec<PatchStep>,
+    /// The source app, window title and time it had before a metadata
+    /// edit; None when only the content changed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ClipMetadata>,
+}
+
+/// A content change worked out on a copy of a clip by `plan_content`,
+/// applied under the storage lock
+#[derive(Debug, Clone)]
+pub struct ContentEdit {
+    /// The content, revision count and sensitivity it was planned against
+    base: (String, usize, bool),
+    content: String,
+    cause: String,
+    at: DateTime<Utc>,
+    /// The history after the edit; None when the content doesn't change
+    history: Option<Vec<ClipRevision>>,
+    /// Metadata a revert puts back, if it differs from the current
+    metadata: Option<ClipMetadata>,
+}
+
+/// A revision with its content rebuilt, as listed by `get_clip_revisions`
+#[derive(Debug, Clone, Serialize)]
+pub struct ClipRevisionView {
+    pub index: usize,
+    pub at: DateTime<Utc>,
+    pub cause: String,
+    pub content: String,
+    pub metadata: ClipMetadata,
+}
+
+/// When to remind the user about a clip
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Reminder {
+    pub at: DateTime<Utc>,
+    /// Set once the reminder has gone off, so it fires once per set time
+    #[serde(default)]
+    pub fired: bool,
+}
+
+/// Where a clip produced by Stack (AI, merge, ...) came from
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Provenance {
+    /// The command that produced the clip, e.g. "draft_document"
+    pub operation: String,
+    /// Source clip ids, in the order they were used
+    pub source_ids: Vec<String>,
+    /// AI model that served the request, if any
+    #[serde(default)]
+    pub model: Option<String>,
+    /// Operation-specific detail such as the requested style
+    #[serde(default)]
+    pub detail: Option<String>,
+    /// Source clips left out because the AI couldn't process their part
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub omitted_ids: Vec<String>,
 }
 
 /// Metadata associated with a clip
@@ -21,11 +178,56 @@ pub struct ClipMetadata {
     pub timestamp: DateTime<Utc>,
     pub source_app: String,
     pub window_title: String,
+    /// Structured details parsed from the window title (e.g. editor file/project)
+    #[serde(default)]
+    pub context: Option<serde_json::Value>,
+    /// Capture session the clip was gathered in
+    #[serde(default)]
+    pub session_id: Option<String>,
+    #[serde(default)]
+    pub session_label: Option<String>,
+    /// An earlier clip with the same content, in any pastebook, when this
+    /// one was captured
+    #[serde(default)]
+    pub duplicate_of: Option<String>,
+    /// Names of the clipboard formats the source app offered (not their
+    /// data), for working out why a capture came through oddly
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub available_formats: Vec<String>,
+    /// Virtual desktop the source window was on, for filtering by context
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_desktop: Option<String>,
+    /// GUID of that desktop, which stays put when it's renamed or reordered
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub virtual_desktop_id: Option<String>,
+    /// Monitor the source window was on
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub monitor: Option<String>,
+}
+
+impl ClipMetadata {
+    /// Whether the source app, window title and time, the parts a metadata
+    /// edit can change, match
+    fn same_origin(&self, other: &ClipMetadata) -> bool {
+        self.source_app == other.source_app && self.window_title == other.window_title && self.timestamp == other.timestamp
+    }
+    
+    /// Whether the clip came from `virtual_desktop` (its label or GUID) and
+    /// `monitor`, each matched case-insensitively; None matches anything
+    pub fn in_workspace(&self, virtual_desktop: Option<&str>, monitor: Option<&str>) -> bool {
+        let matches = |wanted: Option<&str>, actual: &Option<String>| match wanted {
+            Some(wanted) => actual.as_deref().is_some_and(|a| a.eq_ignore_ascii_case(wanted)),
+            None => true,
+        };
+        let desktop = matches(virtual_desktop, &self.virtual_desktop) || matches(virtual_desktop, &self.virtual_desktop_id);
+        desktop && matches(monitor, &self.monitor)
+    }
 }
 
 impl ClipObject {
     /// Create a new clip from content and window info
     pub fn new(content: String, window_info: WindowInfo) -> Self {
+        let context = window::source_context(&window_info.app_name, &window_info.window_title);
         Self {
             id: Uuid::new_v4().to_string(),
             content,
@@ -33,289 +235,5766 @@ impl ClipObject {
                 timestamp: Utc::now(),
                 source_app: window_info.app_name,
                 window_title: window_info.window_title,
+                context,
+                session_id: None,
+                session_label: None,
+                duplicate_of: None,
+                available_formats: Vec::new(),
+                virtual_desktop: window_info.virtual_desktop,
+                virtual_desktop_id: window_info.virtual_desktop_id,
+                monitor: window_info.monitor,
             },
             status: "raw".to_string(),
+            title: None,
+            tags: Vec::new(),
+            label: None,
+            assets: Vec::new(),
+            sensitive: false,
+            provenance: None,
+            reminder: None,
+            pinned: false,
+            locked: false,
+            content_ref: None,
+            original_content: None,
+            history: Vec::new(),
+            edited_at: None,
+            trashed_at: None,
+            captured_instant: None,
+        }
+    }
+    
+    /// Replace the content, keeping the old one as the newest revision. An
+    /// edit with the same cause as the newest revision and within
+    /// `REVISION_COALESCE_SECS` of it folds into it, so autosaves while
+    /// typing leave one revision per burst. Sensitive clips keep no history.
+    pub fn set_content(&mut self, content: String, cause: &str, now: DateTime<Utc>) {
+        if content == self.content {
+            return;
+        }
+        self.history = self.history_after(&content, cause, now);
+        self.content = content;
+    }
+    
+    /// The history once `content` replaces the current content
+    fn history_after(&self, content: &str, cause: &str, now: DateTime<Utc>) -> Vec<ClipRevision> {
+        if self.sensitive {
+            return Vec::new();
+        }
+        let mut history = self.history.clone();
+        let coalesce = history.last().is_some_and(|last| {
+            last.cause == cause && clock::wall_within(last.at, now, chrono::Duration::seconds(REVISION_COALESCE_SECS))
+        });
+        let folded = coalesce
+            .then(|| diff::apply_patch(&self.content, &history.last()?.patch).ok())
+            .flatten();
+        let older = match folded {
+            Some(older) => {
+                history.pop();
+                older
+            }
+            None => self.content.clone(),
+        };
+        // Typing back to where the burst started leaves nothing to keep
+        if older != content {
+            history.push(ClipRevision {
+                at: now,
+                cause: cause.to_string(),
+                patch: diff::make_patch(content, &older),
+                metadata: None,
+            });
+        }
+        if history.len() > MAX_REVISIONS {
+            history.drain(..history.len() - MAX_REVISIONS);
+        }
+        history
+    }
+    
+    /// Replace the source app, window title and time, keeping the old ones
+    /// as a revision of their own. Returns whether anything changed.
+    pub fn set_metadata(&mut self, metadata: &ClipMetadata, cause: &str, now: DateTime<Utc>) -> bool {
+        if self.metadata.same_origin(metadata) {
+            return false;
+        }
+        if !self.sensitive {
+            self.history.push(ClipRevision {
+                at: now,
+                cause: cause.to_string(),
+                patch: vec![PatchStep::Span(self.content.len() as i64)],
+                metadata: Some(self.metadata.clone()),
+            });
+            if self.history.len() > MAX_REVISIONS {
+                self.history.drain(..self.history.len() - MAX_REVISIONS);
+            }
+        }
+        self.metadata.source_app = metadata.source_app.clone();
+        self.metadata.window_title = metadata.window_title.clone();
+        self.metadata.timestamp = metadata.timestamp;
+        true
+    }
+    
+    /// Work out replacing the content on a copy of the clip, so the diff
+    /// for its history runs without the storage lock held
+    pub fn plan_content(&self, content: String, cause: &str, now: DateTime<Utc>) -> ContentEdit {
+        let history = (content != self.content).then(|| self.history_after(&content, cause, now));
+        ContentEdit {
+            base: (self.content.clone(), self.history.len(), self.sensitive),
+            content,
+            cause: cause.to_string(),
+            at: now,
+            history,
+            metadata: None,
+        }
+    }
+    
+    /// Plan putting back the content and metadata of revision `index`.
+    /// What it replaces becomes a revision too, so a revert can be reverted.
+    pub fn plan_revert(&self, index: usize, now: DateTime<Utc>) -> Result<ContentEdit, String> {
+        let revision = self
+            .revisions()?
+            .into_iter()
+            .find(|r| r.index == index)
+            .ok_or_else(|| format!("NotFound: revision {} of clip {}", index, self.id))?;
+        let mut edit = self.plan_content(revision.content, "revert", now);
+        edit.metadata = Some(revision.metadata).filter(|m| !m.same_origin(&self.metadata));
+        Ok(edit)
+    }
+    
+    /// Apply a planned edit, diffing again only if the clip changed since
+    fn apply_content(&mut self, edit: ContentEdit) {
+        let unchanged = edit.base.0 == self.content && edit.base.1 == self.history.len() && edit.base.2 == self.sensitive;
+        match edit.history {
+            Some(history) if unchanged => {
+                self.history = history;
+                self.content = edit.content;
+            }
+            _ => self.set_content(edit.content, &edit.cause, edit.at),
         }
+        if let Some(metadata) = edit.metadata {
+            self.set_metadata(&metadata, &edit.cause, edit.at);
+        }
+    }
+    
+    /// When the clip last changed: its last edit, or else its capture.
+    /// Sync keeps whichever side changed last.
+    pub fn modified_at(&self) -> DateTime<Utc> {
+        self.edited_at.unwrap_or(self.metadata.timestamp)
+    }
+    
+    /// Every revision with its content rebuilt, newest first
+    pub fn revisions(&self) -> Result<Vec<ClipRevisionView>, String> {
+        let mut content = self.content.clone();
+        let mut metadata = &self.metadata;
+        let mut views = Vec::with_capacity(self.history.len());
+        for (index, revision) in self.history.iter().enumerate().rev() {
+            content = diff::apply_patch(&content, &revision.patch)?;
+            metadata = revision.metadata.as_ref().unwrap_or(metadata);
+            views.push(ClipRevisionView {
+                index,
+                at: revision.at,
+                cause: revision.cause.clone(),
+                content: content.clone(),
+                metadata: metadata.clone(),
+            });
+        }
+        Ok(views)
     }
 }
 
-/// A Pastebook is a named collection of clips
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct Pastebook {
+/// Characters of content included in a capture event preview
+const EVENT_PREVIEW_CHARS: usize = 200;
+
+/// What the `clip-captured` event carries: enough to render a card, never
+/// the full content (fetch that with `get_clip`), and only the id for
+/// sensitive clips
+#[derive(Debug, Clone, Serialize)]
+pub struct CapturedClip {
     pub id: String,
-    pub name: String,
-    pub created_at: DateTime<Utc>,
-    pub clips: Vec<ClipObject>,
+    /// What a screen reader should say about the capture; only on `clip-captured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub announcement: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<ClipMetadata>,
+    pub sensitive: bool,
+    /// Pastebook the clip went into; only on `clip-captured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pastebook_id: Option<String>,
+    /// The earlier clip with the same content; only on `clip-captured`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub duplicate_of: Option<DuplicateMatch>,
 }
 
-impl Pastebook {
-    pub fn new(name: String) -> Self {
+impl From<&ClipObject> for CapturedClip {
+    fn from(clip: &ClipObject) -> Self {
+        if clip.sensitive {
+            return Self {
+                id: clip.id.clone(),
+                announcement: None,
+                preview: None,
+                metadata: None,
+                sensitive: true,
+                pastebook_id: None,
+                duplicate_of: None,
+            };
+        }
+        
         Self {
-            id: Uuid::new_v4().to_string(),
-            name,
-            created_at: Utc::now(),
-            clips: Vec::new(),
+            id: clip.id.clone(),
+            announcement: None,
+            preview: Some(textutil::truncate(&clip.content, EVENT_PREVIEW_CHARS).to_string()),
+            metadata: Some(clip.metadata.clone()),
+            sensitive: false,
+            pastebook_id: None,
+            duplicate_of: None,
         }
     }
 }
 
-/// Storage container for all pastebooks
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct AppStorage {
-    pub pastebooks: Vec<Pastebook>,
-    pub active_pastebook_id: Option<String>,
-    #[serde(default)]
-    pub api_key: Option<String>,
+/// Where the clip a capture duplicates lives, for "also in Research
+/// (3 weeks ago)"
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DuplicateMatch {
+    pub clip_id: String,
+    pub pastebook_id: String,
+    pub pastebook_name: String,
+    pub captured_at: DateTime<Utc>,
 }
 
-impl Default for AppStorage {
-    fn default() -> Self {
-        // Create a default pastebook
-        let default_pastebook = Pastebook::new("My First Pastebook".to_string());
-        let default_id = default_pastebook.id.clone();
-        Self {
-            pastebooks: vec![default_pastebook],
-            active_pastebook_id: Some(default_id),
-            api_key: None,
+/// Chars of an externalized clip's content kept inline in the storage file
+const EXTERNAL_PREVIEW_CHARS: usize = 200;
+
+thread_local! {
+    /// Set while writing the storage file: content over this many bytes is
+    /// written as a preview, its full text being in the clip's content_ref asset
+    static EXTERNALIZE_OVER: Cell<Option<usize>> = const { Cell::new(None) };
+}
+
+fn serialize_content<S: Serializer>(content: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    match EXTERNALIZE_OVER.get() {
+        Some(limit) if content.len() > limit => {
+            serializer.serialize_str(textutil::truncate(content, EXTERNAL_PREVIEW_CHARS))
         }
+        _ => serializer.serialize_str(content),
     }
 }
 
-impl AppStorage {
-    /// Get the path to the storage file
-    fn get_storage_path() -> PathBuf {
-        let app_data = dirs_next::data_local_dir()
-            .unwrap_or_else(|| PathBuf::from("."));
-        let stack_dir = app_data.join("Stack");
-        
-        // Create directory if it doesn't exist
-        if !stack_dir.exists() {
-            let _ = fs::create_dir_all(&stack_dir);
+/// An externalized clip's full content from the assets store
+fn read_external_content(hash: &str) -> Result<String, String> {
+    let path = assets::asset_path(hash)?;
+    fs::read_to_string(&path).map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// A read guard on `storage` with every clip's full content in place,
+/// running `load_contents` first if the startup thread hasn't yet. Anything
+/// that reads clip content takes storage this way (or with `loaded_mut`) so
+/// an externalized clip's preview is never taken for its content.
+pub fn loaded(storage: &RwLock<AppStorage>) -> RwLockReadGuard<'_, AppStorage> {
+    {
+        let guard = storage.read().unwrap();
+        if !guard.contents_pending {
+            return guard;
         }
-        
-        stack_dir.join("pastebooks.json")
     }
-    
-    /// Load from storage
-    pub fn load() -> Self {
-        let path = Self::get_storage_path();
-        
-        if path.exists() {
-            match fs::read_to_string(&path) {
-                Ok(content) => {
-                    serde_json::from_str(&content).unwrap_or_default()
-                }
-                Err(_) => Self::default(),
+    storage.write().unwrap().load_contents();
+    storage.read().unwrap()
+}
+
+/// `loaded` for writers
+pub fn loaded_mut(storage: &RwLock<AppStorage>) -> RwLockWriteGuard<'_, AppStorage> {
+    let mut guard = storage.write().unwrap();
+    guard.load_contents();
+    guard
+}
+
+/// content_ref is an on-disk detail, left out of IPC, sync and backups
+fn skip_content_ref(content_ref: &Option<String>) -> bool {
+    content_ref.is_none() || EXTERNALIZE_OVER.get().is_none()
+}
+
+/// One clip on the timeline: enough to draw a row, never the full content
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineEntry {
+    pub id: String,
+    pub pastebook_id: String,
+    pub timestamp: DateTime<Utc>,
+    pub source_app: String,
+    pub window_title: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub title: Option<String>,
+    /// None for sensitive clips
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preview: Option<String>,
+    pub label: Option<String>,
+    pub sensitive: bool,
+}
+
+impl TimelineEntry {
+    fn new(clip: &ClipObject, pastebook_id: &str) -> Self {
+        Self {
+            id: clip.id.clone(),
+            pastebook_id: pastebook_id.to_string(),
+            timestamp: clip.metadata.timestamp,
+            source_app: clip.metadata.source_app.clone(),
+            window_title: clip.metadata.window_title.clone(),
+            title: clip.title.clone(),
+            preview: (!clip.sensitive)
+                .then(|| textutil::truncate(&clip.content, EVENT_PREVIEW_CHARS).to_string()),
+            label: clip.label.clone(),
+            sensitive: clip.sensitive,
+        }
+    }
+}
+
+/// Clips captured during one local hour, oldest first
+#[derive(Debug, Clone, Serialize)]
+pub struct TimelineHour {
+    /// Local hour of day, 0-23
+    pub hour: u32,
+    pub entries: Vec<TimelineEntry>,
+}
+
+/// Hex SHA-256 of a clip's content
+pub fn content_hash(content: &str) -> String {
+    Sha256::digest(content.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Color labels a clip can carry
+pub const CLIP_LABELS: [&str; 6] = ["red", "orange", "yellow", "green", "blue", "purple"];
+
+/// Normalize a label and check it's one of `CLIP_LABELS` (None clears the label)
+pub fn validate_label(label: Option<String>) -> Result<Option<String>, String> {
+    match label {
+        None => Ok(None),
+        Some(label) => {
+            let label = label.trim().to_lowercase();
+            if CLIP_LABELS.contains(&label.as_str()) {
+                Ok(Some(label))
+            } else {
+                Err(format!(
+                    "Invalid label '{}'. Allowed: {}",
+                    label,
+                    CLIP_LABELS.join(", ")
+                ))
             }
-        } else {
-            Self::default()
         }
     }
-    
-    /// Save to storage
-    pub fn save(&self) -> Result<(), String> {
-        let path = Self::get_storage_path();
-        
-        let json = serde_json::to_string_pretty(self)
-            .map_err(|e| format!("Failed to serialize: {}", e))?;
-        
-        fs::write(&path, json)
-            .map_err(|e| format!("Failed to write file: {}", e))?;
-        
-        Ok(())
+}
+
+/// Longest name (pastebook, group, template) in characters, after trimming
+pub const MAX_NAME_CHARS: usize = 100;
+
+/// Trim a name and check it's 1 to `MAX_NAME_CHARS` characters and not
+/// only control characters. `field` names it in the error.
+pub fn validate_name(field: &str, name: &str) -> Result<String, String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(format!("Validation: {} must not be empty", field));
     }
-    
-    /// Get the active pastebook
-    pub fn get_active_pastebook(&self) -> Option<&Pastebook> {
-        self.active_pastebook_id.as_ref().and_then(|id| {
-            self.pastebooks.iter().find(|p| &p.id == id)
-        })
+    if textutil::grapheme_count(name) > MAX_NAME_CHARS {
+        return Err(format!("Validation: {} must be at most {} characters", field, MAX_NAME_CHARS));
     }
-    
-    /// Get mutable reference to active pastebook
-    pub fn get_active_pastebook_mut(&mut self) -> Option<&mut Pastebook> {
-        let id = self.active_pastebook_id.clone();
-        id.and_then(move |id| {
-            self.pastebooks.iter_mut().find(|p| p.id == id)
-        })
+    if name.chars().all(char::is_control) {
+        return Err(format!("Validation: {} must not be only control characters", field));
     }
-    
-    /// Create a new pastebook
-    pub fn create_pastebook(&mut self, name: String) -> Pastebook {
-        let pastebook = Pastebook::new(name);
-        self.pastebooks.push(pastebook.clone());
-        self.active_pastebook_id = Some(pastebook.id.clone());
-        pastebook
+    Ok(name.to_string())
+}
+
+fn locked_error(id: &str) -> String {
+    format!("Locked: clip {} can't be edited", id)
+}
+
+/// Blank clip content is only accepted when asked for; otherwise the clip
+/// should be deleted instead
+pub fn validate_content(content: &str, allow_empty: bool) -> Result<(), String> {
+    if content.trim().is_empty() && !allow_empty {
+        return Err("Validation: content must not be empty (delete the clip instead, or pass allow_empty)".to_string());
     }
-    
-    /// Switch to a pastebook
-    pub fn switch_pastebook(&mut self, id: String) -> bool {
-        if self.pastebooks.iter().any(|p| p.id == id) {
-            self.active_pastebook_id = Some(id);
-            true
-        } else {
-            false
+    Ok(())
+}
+
+/// Clips' contents as one string, a blank line between each
+pub fn joined_content<'a>(clips: impl IntoIterator<Item = &'a ClipObject>) -> String {
+    clips.into_iter().map(|c| c.content.as_str()).collect::<Vec<_>>().join("\n\n")
+}
+
+/// Trim tags, drop empty ones and remove duplicates (keeping first occurrence)
+pub fn normalize_tags(tags: Vec<String>) -> Vec<String> {
+    let mut normalized: Vec<String> = Vec::new();
+    for tag in tags {
+        let tag = tag.trim().to_string();
+        if !tag.is_empty() && !normalized.contains(&tag) {
+            normalized.push(tag);
         }
     }
-    
-    /// Delete a pastebook
-    pub fn delete_pastebook(&mut self, id: &str) -> bool {
-        if self.pastebooks.len() <= 1 {
-            return false; // Can't delete the last pastebook
+    normalized
+}
+
+/// Clips of the pastebooks in `pastebooks` that have been read
+fn loaded_clips_mut(pastebooks: &mut [Pastebook]) -> impl Iterator<Item = &mut ClipObject> {
+    pastebooks
+        .iter_mut()
+        .filter(|p| p.clips.is_loaded())
+        .flat_map(|p| p.clips.iter_mut())
+}
+
+/// Directory beside the storage file holding each pastebook's clips in a
+/// file of its own
+const BOOKS_DIR: &str = "books";
+
+thread_local! {
+    /// Set while writing the storage file as an index: pastebooks go in
+    /// without their clips, which are in their book files
+    static WRITING_INDEX: Cell<bool> = const { Cell::new(false) };
+}
+
+fn writing_index<T>(_: &T) -> bool {
+    WRITING_INDEX.get()
+}
+
+fn not_writing_index<T>(_: &T) -> bool {
+    !WRITING_INDEX.get()
+}
+
+/// The book file of pastebook `id`, beside the storage file at `path`
+fn book_path(path: &Path, id: &str) -> PathBuf {
+    path.with_file_name(BOOKS_DIR).join(format!("{}.json", id))
+}
+
+/// Read a book file. Externalized contents are read back as well unless
+/// `contents` is false, leaving them to `load_contents`. As with the storage
+/// file, a corrupt one is set aside and the book starts empty, and one that
+/// can't be read puts storage in memory so the empty book isn't saved over it.
+fn read_book(path: &Path, contents: bool) -> Vec<ClipObject> {
+    let json = match fs::read_to_string(path) {
+        Ok(json) => json,
+        Err(e) => {
+            health::mark_unreadable_on_load();
+            health::mark_unavailable(format!("Failed to read {}: {}", path.display(), e));
+            return Vec::new();
         }
-        
-        let initial_len = self.pastebooks.len();
-        self.pastebooks.retain(|p| p.id != id);
-        
-        // If we deleted the active pastebook, switch to the first one
-        if self.active_pastebook_id.as_ref() == Some(&id.to_string()) {
-            self.active_pastebook_id = self.pastebooks.first().map(|p| p.id.clone());
+    };
+    let mut clips: Vec<ClipObject> = match serde_json::from_str(&json) {
+        Ok(clips) => clips,
+        Err(e) => {
+            eprintln!("{} is corrupt ({}), starting it empty", path.display(), e);
+            AppStorage::set_aside_corrupt(path);
+            return Vec::new();
+        }
+    };
+    for clip in clips.iter_mut().filter(|_| contents) {
+        let Some(hash) = &clip.content_ref else {
+            continue;
+        };
+        match read_external_content(hash) {
+            Ok(content) => clip.content = content,
+            Err(e) => eprintln!("Clip {} keeps only its preview, content unreadable: {}", clip.id, e),
+        }
+    }
+    clips
+}
+
+/// What the storage file keeps of a pastebook in place of its clips, so the
+/// sidebar and reminders don't need the book file read
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct BookCounts {
+    pub clip_count: usize,
+    pub new_clip_count: usize,
+    /// The soonest reminder still to go off
+    #[serde(default)]
+    pub next_reminder: Option<DateTime<Utc>>,
+}
+
+/// A pastebook's clips. In a store saved as book files only the active
+/// pastebook's are read at startup; the others stay unloaded until first
+/// used, when they're read from their file. The `OnceLock` is what lets
+/// that happen under a read lock, so everything that reads clips goes on
+/// working unchanged.
+#[derive(Debug, Clone)]
+pub struct BookClips {
+    loaded: OnceLock<Vec<ClipObject>>,
+    /// Where the clips are read from while unloaded
+    file: Option<PathBuf>,
+}
+
+impl BookClips {
+    /// Clips still in `file`
+    fn unloaded(file: PathBuf) -> Self {
+        Self {
+            loaded: OnceLock::new(),
+            file: Some(file),
         }
-        
-        self.pastebooks.len() < initial_len
     }
     
-    /// Rename a pastebook
-    pub fn rename_pastebook(&mut self, id: &str, new_name: String) -> bool {
-        if let Some(pastebook) = self.pastebooks.iter_mut().find(|p| p.id == id) {
-            pastebook.name = new_name;
-            true
-        } else {
-            false
+    /// Left out of the storage file, i.e. in a book file `load_from` sets
+    fn absent() -> Self {
+        Self {
+            loaded: OnceLock::new(),
+            file: None,
         }
     }
     
-    /// Get list of all pastebooks (id, name)
-    pub fn list_pastebooks(&self) -> Vec<(String, String, usize)> {
-        self.pastebooks
-            .iter()
-            .map(|p| (p.id.clone(), p.name.clone(), p.clips.len()))
-            .collect()
+    /// The clips, if they've been read
+    pub fn get_loaded(&self) -> Option<&Vec<ClipObject>> {
+        self.loaded.get()
     }
     
-    // ==================== CLIP OPERATIONS ====================
+    pub fn is_loaded(&self) -> bool {
+        self.loaded.get().is_some()
+    }
     
-    /// Add a clip to the active pastebook
-    pub fn add_clip(&mut self, clip: ClipObject) -> bool {
-        if let Some(pastebook) = self.get_active_pastebook_mut() {
-            pastebook.clips.insert(0, clip);
-            true
-        } else {
-            false
+    /// The clips without keeping them: unloaded ones are read and dropped
+    /// again, so going through every pastebook holds one book at a time
+    pub fn peek(&self) -> Cow<'_, [ClipObject]> {
+        match self.loaded.get() {
+            Some(clips) => Cow::Borrowed(clips),
+            None => Cow::Owned(self.read()),
         }
     }
     
-    /// Get clips from active pastebook
-    pub fn get_clips(&self) -> Vec<ClipObject> {
-        self.get_active_pastebook()
-            .map(|p| p.clips.clone())
-            .unwrap_or_default()
+    fn read(&self) -> Vec<ClipObject> {
+        self.file.as_deref().map(|path| read_book(path, true)).unwrap_or_default()
     }
-    
-    /// Delete a clip from active pastebook
-    pub fn delete_clip(&mut self, id: &str) -> bool {
-        if let Some(pastebook) = self.get_active_pastebook_mut() {
-            let initial_len = pastebook.clips.len();
-            pastebook.clips.retain(|c| c.id != id);
-            pastebook.clips.len() < initial_len
-        } else {
-            false
+}
+
+impl Default for BookClips {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl From<Vec<ClipObject>> for BookClips {
+    fn from(clips: Vec<ClipObject>) -> Self {
+        Self {
+            loaded: OnceLock::from(clips),
+            file: None,
         }
     }
+}
+
+impl Deref for BookClips {
+    type Target = Vec<ClipObject>;
     
-    /// Update a clip's content
-    pub fn update_clip(&mut self, id: &str, content: String) -> bool {
-        if let Some(pastebook) = self.get_active_pastebook_mut() {
-            if let Some(clip) = pastebook.clips.iter_mut().find(|c| c.id == id) {
-                clip.content = content;
-                return true;
-            }
+    fn deref(&self) -> &Vec<ClipObject> {
+        self.loaded.get_or_init(|| self.read())
+    }
+}
+
+impl DerefMut for BookClips {
+    fn deref_mut(&mut self) -> &mut Vec<ClipObject> {
+        if self.loaded.get().is_none() {
+            let clips = self.read();
+            let _ = self.loaded.set(clips);
         }
-        false
+        self.loaded.get_mut().unwrap()
     }
+}
+
+impl<'a> IntoIterator for &'a BookClips {
+    type Item = &'a ClipObject;
+    type IntoIter = std::slice::Iter<'a, ClipObject>;
     
-    /// Reorder clips
-    pub fn reorder_clips(&mut self, ids: Vec<String>) {
-        if let Some(pastebook) = self.get_active_pastebook_mut() {
-            let mut new_clips = Vec::new();
-            
-            for id in ids {
-                if let Some(clip) = pastebook.clips.iter().find(|c| c.id == id).cloned() {
-                    new_clips.push(clip);
-                }
-            }
-            
-            for clip in &pastebook.clips {
-                if !new_clips.iter().any(|c| c.id == clip.id) {
-                    new_clips.push(clip.clone());
-                }
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a mut BookClips {
+    type Item = &'a mut ClipObject;
+    type IntoIter = std::slice::IterMut<'a, ClipObject>;
+    
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+impl FromIterator<ClipObject> for BookClips {
+    fn from_iter<I: IntoIterator<Item = ClipObject>>(clips: I) -> Self {
+        clips.into_iter().collect::<Vec<_>>().into()
+    }
+}
+
+impl Serialize for BookClips {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.peek().serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for BookClips {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::<ClipObject>::deserialize(deserializer).map(Self::from)
+    }
+}
+
+/// A Pastebook is a named collection of clips
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Pastebook {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    #[serde(default = "BookClips::absent", skip_serializing_if = "writing_index")]
+    pub clips: BookClips,
+    /// In the storage file, what's needed of the clips in the book file
+    #[serde(default, skip_serializing_if = "not_writing_index")]
+    pub book: Option<BookCounts>,
+    /// File every clip added here is also appended to
+    #[serde(default)]
+    pub mirror_file: Option<PathBuf>,
+    /// Sidebar group the pastebook is filed under (one level, no nesting)
+    #[serde(default)]
+    pub group: Option<String>,
+    /// Re-export the pastebook on a schedule, e.g. for a wiki to pick up
+    #[serde(default)]
+    pub auto_export: Option<AutoExportConfig>,
+    /// When the user last looked at the pastebook; clips captured after
+    /// this are new. None (files from before this was tracked) counts
+    /// nothing as new until it is first viewed.
+    #[serde(default)]
+    pub last_viewed_at: Option<DateTime<Utc>>,
+    /// Free-form notes kept alongside the clips; clearing clips leaves it
+    #[serde(default)]
+    pub scratchpad: String,
+    /// What the scratchpad held before its latest burst of edits
+    #[serde(default)]
+    pub previous_scratchpad: Option<String>,
+    #[serde(default)]
+    pub scratchpad_edited_at: Option<DateTime<Utc>>,
+    /// Created by a capture rule routing by window title; these count
+    /// towards `max_auto_pastebooks`
+    #[serde(default)]
+    pub auto_created: bool,
+}
+
+/// A pastebook's scratchpad with its one kept earlier version
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Scratchpad {
+    pub pastebook_id: String,
+    pub text: String,
+    pub previous: Option<String>,
+}
+
+/// A pastebook as listed in the sidebar
+#[derive(Debug, Clone, Serialize)]
+pub struct PastebookSummary {
+    pub id: String,
+    pub name: String,
+    pub clip_count: usize,
+    /// Clips captured since the pastebook was last viewed
+    pub new_clip_count: usize,
+}
+
+/// What `get_pastebook_stats` reports about one pastebook
+#[derive(Debug, Clone, Serialize)]
+pub struct PastebookStats {
+    pub id: String,
+    pub name: String,
+    /// Clips not in the trash, as the sidebar counts them
+    pub clip_count: usize,
+    pub trashed_count: usize,
+    pub new_clip_count: usize,
+}
+
+/// Trashed clips are purged this long after they were trashed
+pub const TRASH_RETENTION_DAYS: i64 = 30;
+
+/// The pastebooks in one group, in order; `group` None holds the ungrouped ones
+#[derive(Debug, Clone, Serialize)]
+pub struct PastebookGroup {
+    pub group: Option<String>,
+    pub pastebooks: Vec<PastebookSummary>,
+}
+
+impl Pastebook {
+    pub fn new(name: String) -> Self {
+        let created_at = Utc::now();
+        Self {
+            id: Uuid::new_v4().to_string(),
+            name,
+            created_at,
+            clips: BookClips::default(),
+            book: None,
+            mirror_file: None,
+            group: None,
+            auto_export: None,
+            last_viewed_at: Some(created_at),
+            scratchpad: String::new(),
+            previous_scratchpad: None,
+            scratchpad_edited_at: None,
+            auto_created: false,
+        }
+    }
+    
+    pub fn scratchpad(&self) -> Scratchpad {
+        Scratchpad {
+            pastebook_id: self.id.clone(),
+            text: self.scratchpad.clone(),
+            previous: self.previous_scratchpad.clone(),
+        }
+    }
+    
+    /// Replace the scratchpad. The text replaced becomes the previous
+    /// version unless the scratchpad was edited within
+    /// `REVISION_COALESCE_SECS`, so a burst of typing keeps what was there
+    /// before it. Returns whether anything changed.
+    pub fn set_scratchpad(&mut self, text: String, now: DateTime<Utc>) -> bool {
+        if text == self.scratchpad {
+            return false;
+        }
+        let coalesce = self
+            .scratchpad_edited_at
+            .is_some_and(|at| clock::wall_within(at, now, chrono::Duration::seconds(REVISION_COALESCE_SECS)));
+        let older = std::mem::replace(&mut self.scratchpad, text);
+        if !coalesce && !older.is_empty() {
+            self.previous_scratchpad = Some(older);
+        }
+        self.scratchpad_edited_at = Some(now);
+        true
+    }
+    
+    /// Clips captured after the pastebook was last viewed
+    pub fn new_clip_count(&self) -> usize {
+        let Some(viewed) = self.last_viewed_at else {
+            return 0;
+        };
+        if let Some(book) = self.unloaded_counts() {
+            return book.new_clip_count;
+        }
+        self.live_clips().filter(|c| c.metadata.timestamp > viewed).count()
+    }
+    
+    /// The counts kept in the storage file, while the clips are unloaded
+    fn unloaded_counts(&self) -> Option<&BookCounts> {
+        self.book.as_ref().filter(|_| !self.clips.is_loaded())
+    }
+    
+    /// What the storage file keeps in place of the clips
+    fn book_counts(&self) -> BookCounts {
+        BookCounts {
+            clip_count: self.live_clip_count(),
+            new_clip_count: self.new_clip_count(),
+            next_reminder: self
+                .clips
+                .iter()
+                .filter_map(|c| c.reminder.as_ref().filter(|r| !r.fired))
+                .map(|r| r.at)
+                .min(),
+        }
+    }
+    
+    pub fn summary(&self) -> PastebookSummary {
+        PastebookSummary {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            clip_count: self.live_clip_count(),
+            new_clip_count: self.new_clip_count(),
+        }
+    }
+    
+    pub fn stats(&self) -> PastebookStats {
+        PastebookStats {
+            id: self.id.clone(),
+            name: self.name.clone(),
+            clip_count: self.live_clip_count(),
+            trashed_count: self.trashed_clip_count(),
+            new_clip_count: self.new_clip_count(),
+        }
+    }
+    
+    /// Clips that count towards the pastebook's size: all but the trashed
+    /// ones. An unloaded book answers from the counts in the storage file.
+    pub fn live_clip_count(&self) -> usize {
+        match self.unloaded_counts() {
+            Some(book) => book.clip_count,
+            None => self.live_clips().count(),
+        }
+    }
+    
+    /// Clips in the trash; reads the book if it's unloaded
+    pub fn trashed_clip_count(&self) -> usize {
+        // `len` reads the book first, so the live count is of the clips read
+        self.clips.len() - self.live_clip_count()
+    }
+    
+    /// Clips not in the trash, in order
+    pub fn live_clips(&self) -> impl Iterator<Item = &ClipObject> {
+        self.clips.iter().filter(|c| c.trashed_at.is_none())
+    }
+    
+    /// Put a captured clip on top, copying it to the mirror file if there
+    /// is one. Clips Stack makes itself (AI output, diffs, manual entries)
+    /// go in with `insert_top` and aren't mirrored.
+    fn push_capture(&mut self, clip: ClipObject) {
+        if let Some(path) = &self.mirror_file {
+            mirror::append(&self.id, path, &clip);
+        }
+        self.insert_top(clip);
+    }
+    
+    /// Put a clip first among the unpinned ones; pinned clips keep their
+    /// positions instead of being pushed down
+    fn insert_top(&mut self, clip: ClipObject) {
+        let (pinned, mut rest) = split_pinned(std::mem::take(&mut self.clips));
+        rest.insert(0, clip);
+        *self.clips = place_pinned(pinned, rest);
+    }
+    
+    /// Update the clip at `index` with `touch` and move it to the top as
+    /// `insert_top` does; a pinned clip is updated where it is
+    fn bump(&mut self, index: usize, touch: impl FnOnce(&mut ClipObject)) -> ClipObject {
+        touch(&mut self.clips[index]);
+        let bumped = self.clips[index].clone();
+        if !bumped.pinned {
+            let (pinned, mut rest) = split_pinned(std::mem::take(&mut self.clips));
+            rest.retain(|c| c.id != bumped.id);
+            rest.insert(0, bumped.clone());
+            *self.clips = place_pinned(pinned, rest);
+        }
+        bumped
+    }
+}
+
+/// A clip skeleton inside a pastebook template
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TemplateClip {
+    pub title: Option<String>,
+    /// Placeholder text the new clip starts with
+    pub content: String,
+    #[serde(default)]
+    pub tags: Vec<String>,
+}
+
+/// A reusable pastebook structure
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PastebookTemplate {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub clips: Vec<TemplateClip>,
+}
+
+/// A timeboxed research session; captures made while it's open are tagged with it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CaptureSession {
+    pub id: String,
+    pub label: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: Option<DateTime<Utc>>,
+    pub last_capture_at: Option<DateTime<Utc>>,
+}
+
+impl CaptureSession {
+    /// When the session last saw activity
+    fn last_activity(&self) -> DateTime<Utc> {
+        self.last_capture_at.unwrap_or(self.started_at)
+    }
+}
+
+/// Summary of what a session gathered
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionReport {
+    pub session: CaptureSession,
+    pub duration_secs: i64,
+    pub clip_count: usize,
+    /// Clips per source app, most first
+    pub per_app: Vec<(String, usize)>,
+    pub total_chars: usize,
+}
+
+/// What to do when a capture repeats a recent clip
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DedupAction {
+    /// Drop the new capture
+    Ignore,
+    /// Move the existing clip to the top with a refreshed timestamp
+    Bump,
+    /// Store the capture anyway
+    AlwaysAdd,
+}
+
+/// What a capture already held by some clip (outside the dedup window, in
+/// any pastebook) does
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateElsewhere {
+    /// Store it, noting the earlier clip in `duplicate_of`
+    #[default]
+    Flag,
+    /// Don't store it; bump the earlier clip and reveal it instead
+    Reuse,
+}
+
+/// Light/dark preference for the UI and window chrome
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ThemePreference {
+    /// Follow the OS
+    #[default]
+    System,
+    Light,
+    Dark,
+}
+
+/// Which character separates a number's fraction; the other one may group thousands
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum DecimalSeparator {
+    /// 1,234.5
+    #[default]
+    Dot,
+    /// 1.234,5
+    Comma,
+}
+
+/// User-configurable settings
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    /// How recent an identical clip must be to count as a duplicate (0 disables dedup)
+    pub dedup_window_ms: u64,
+    pub dedup_action: DedupAction,
+    pub duplicate_elsewhere: DuplicateElsewhere,
+    /// Captures allowed from one app per `capture_rate_window_secs` before
+    /// the rest are held back (0 is no limit)
+    pub capture_rate_limit: u32,
+    pub capture_rate_window_secs: u32,
+    pub rate_limit_action: RateLimitAction,
+    /// Record the names of the clipboard formats offered with each capture
+    pub record_clipboard_formats: bool,
+    /// Most pastebooks capture rules may create from window titles; past
+    /// it, captures go to the active pastebook (0 only routes to existing ones)
+    pub max_auto_pastebooks: usize,
+    /// Accept clips pushed through `stack://add` links
+    pub deep_links_enabled: bool,
+    /// AI models to try in order when one is missing or unsupported
+    pub model_fallbacks: Vec<String>,
+    /// While set, every capture path refuses to add clips
+    pub capture_paused: bool,
+    /// When a timed pause ends on its own
+    pub capture_resume_at: Option<DateTime<Utc>>,
+    /// Sessions end after this many minutes without a capture (0 never)
+    pub session_idle_minutes: u32,
+    /// Batch AI requests allowed in flight at once
+    pub ai_max_concurrency: usize,
+    /// Minimum gap between batch AI requests
+    pub ai_min_delay_ms: u64,
+    /// Characters of clips per prompt when a draft is too big for one and
+    /// is done in parts
+    pub ai_chunk_budget_chars: usize,
+    /// Most parts one draft may be split into
+    pub ai_max_chunks: usize,
+    pub theme: ThemePreference,
+    /// Store the page text around browser selections (best effort, Windows only)
+    pub capture_selection_context: bool,
+    /// Append `attribution_template` to clips copied out of Stack
+    pub append_attribution: bool,
+    /// Placeholders: {source_app} {window_title} {title} {date} {time} {file} {project}
+    pub attribution_template: String,
+    /// Clip content over this many KB goes to the assets store instead of
+    /// inline in pastebooks.json (0 keeps everything inline)
+    pub externalize_content_kb: u32,
+    /// Copying or merging more than this many KB at once needs `force`
+    /// (0 is no limit)
+    pub clipboard_limit_kb: u32,
+    /// How numbers in clips are written, for features that read them
+    pub decimal_separator: DecimalSeparator,
+    /// Badge the taskbar icon with clips captured since their pastebooks were last viewed
+    pub unseen_badge: bool,
+    /// Global shortcut that copies every clip, e.g. "Ctrl+Alt+V" (None is off)
+    pub copy_all_shortcut: Option<String>,
+    /// Paste into the focused app right after the copy-all shortcut copies
+    pub copy_all_pastes: bool,
+    /// Global shortcut that clears every clip when pressed twice (None is off)
+    pub clear_all_shortcut: Option<String>,
+    /// Global shortcut that opens the quick note prompt (None is off)
+    pub quick_note_shortcut: Option<String>,
+    /// Middle-clicking the tray icon pastes the top clip into the last
+    /// focused window
+    pub tray_middle_click_paste: bool,
+    /// URLs that captures and changes are POSTed to
+    pub webhooks: Vec<WebhookConfig>,
+    /// Strip escape codes and terminal line wrapping from terminal captures
+    pub terminal_cleanup: bool,
+    /// Also drop shell prompts ("$ ", "PS C:\dir> ") from terminal captures
+    pub strip_terminal_prompts: bool,
+    /// Serve clips read-only over HTTP on 127.0.0.1:`local_api_port`
+    pub local_api_enabled: bool,
+    pub local_api_port: u16,
+    /// Bearer token the local API requires; only changes through
+    /// `regenerate_local_api_token`
+    pub local_api_token: Option<String>,
+    /// Let `share_clip_via_lan` serve clips to the local network. Anyone on
+    /// the network who has a share link can read that clip until it expires.
+    pub lan_share_enabled: bool,
+    /// A share link stops working after its first fetch, not only at expiry
+    pub lan_share_single_use: bool,
+    /// Line endings `export_clip` writes
+    pub export_line_ending: LineEnding,
+    /// Per-model prices for the AI usage cost estimate
+    pub ai_prices: Vec<ModelPrice>,
+    /// Tokens a month before `ai-budget-exceeded` fires (None is no budget)
+    pub ai_monthly_token_budget: Option<u64>,
+    /// Past the budget, refuse batch AI jobs such as generating titles
+    pub ai_budget_blocks_batch: bool,
+    /// Keep a history of AI requests and replies; off, nothing new is added
+    pub record_ai_history: bool,
+    /// When set and non-empty, only copies made in these apps are captured
+    /// (e.g. "chrome.exe"; case-insensitive, ".exe" optional)
+    pub include_apps: Option<Vec<String>>,
+    /// Copies made in these apps are never captured; wins over `include_apps`
+    pub exclude_apps: Vec<String>,
+    /// When notifications (capture and reminder toasts) are shown
+    pub notification_policy: NotificationPolicy,
+    /// How quickly `hold_on_clipboard` puts its clip back over other copies
+    pub clipboard_hold_aggressiveness: HoldAggressiveness,
+    /// Language of the screen reader announcements sent with events
+    pub language: Language,
+    /// List pinned clips in the taskbar jump list (Windows)
+    pub jump_list: bool,
+    /// Rewrites applied to every capture, in order
+    pub ingest_transforms: Vec<IngestTransform>,
+}
+
+/// Whether captures from an app are allowed by the include and exclude lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AppFilter {
+    Allowed,
+    Excluded,
+    /// There is an include list and the app isn't on it
+    NotIncluded,
+}
+
+/// App name as the filter lists compare it: lowercase, without ".exe"
+fn app_key(app: &str) -> String {
+    let app = app.trim().to_lowercase();
+    match app.strip_suffix(".exe") {
+        Some(stem) => stem.to_string(),
+        None => app,
+    }
+}
+
+/// Trim an app list, dropping blanks and repeats
+fn normalize_app_list(apps: Vec<String>) -> Vec<String> {
+    let mut seen = HashSet::new();
+    apps.into_iter()
+        .map(|app| app.trim().to_string())
+        .filter(|app| !app.is_empty() && seen.insert(app_key(app)))
+        .collect()
+}
+
+impl Settings {
+    /// The attribution template to apply when copying, if enabled
+    pub fn attribution(&self) -> Option<&str> {
+        self.append_attribution.then_some(self.attribution_template.as_str())
+    }
+    
+    /// The span `capture_rate_limit` counts captures over
+    pub fn capture_rate_window(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.capture_rate_window_secs as u64)
+    }
+
+    /// Refuse `bytes` of clipboard text over the size limit unless `force`
+    pub fn check_clipboard_size(&self, bytes: usize, force: bool) -> Result<(), String> {
+        let limit = self.clipboard_limit_kb as usize * 1024;
+        if force || limit == 0 || bytes <= limit {
+            return Ok(());
+        }
+        Err(format!(
+            "TooLarge: {} bytes is over the {} KB clipboard limit. Export it to a file with export_all_content instead, or pass force to copy anyway.",
+            bytes, self.clipboard_limit_kb
+        ))
+    }
+
+    /// Check a capture's source app against the exclude and include lists
+    pub fn app_filter(&self, source_app: &str) -> AppFilter {
+        let key = app_key(source_app);
+        let listed = |apps: &[String]| apps.iter().any(|app| app_key(app) == key);
+        if listed(&self.exclude_apps) {
+            return AppFilter::Excluded;
+        }
+        match &self.include_apps {
+            Some(apps) if !apps.is_empty() && !listed(apps) => AppFilter::NotIncluded,
+            _ => AppFilter::Allowed,
+        }
+    }
+
+    /// Tidy the app lists; an include list left empty is turned off
+    pub fn normalize_app_lists(&mut self) {
+        self.exclude_apps = normalize_app_list(std::mem::take(&mut self.exclude_apps));
+        self.include_apps = self
+            .include_apps
+            .take()
+            .map(normalize_app_list)
+            .filter(|apps| !apps.is_empty());
+    }
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Self {
+            dedup_window_ms: 2000,
+            dedup_action: DedupAction::Ignore,
+            duplicate_elsewhere: DuplicateElsewhere::Flag,
+            max_auto_pastebooks: 30,
+            record_clipboard_formats: true,
+            capture_rate_limit: 10,
+            capture_rate_window_secs: 10,
+            rate_limit_action: RateLimitAction::Coalesce,
+            deep_links_enabled: true,
+            model_fallbacks: ai::DEFAULT_MODELS.iter().map(|m| m.to_string()).collect(),
+            capture_paused: false,
+            capture_resume_at: None,
+            session_idle_minutes: 30,
+            ai_max_concurrency: 2,
+            ai_min_delay_ms: 1000,
+            ai_chunk_budget_chars: 50_000,
+            ai_max_chunks: 16,
+            theme: ThemePreference::System,
+            capture_selection_context: false,
+            append_attribution: false,
+            attribution_template: attribution::DEFAULT_TEMPLATE.to_string(),
+            externalize_content_kb: 256,
+            clipboard_limit_kb: 10 * 1024,
+            decimal_separator: DecimalSeparator::Dot,
+            unseen_badge: true,
+            copy_all_shortcut: None,
+            copy_all_pastes: false,
+            clear_all_shortcut: None,
+            quick_note_shortcut: Some("Ctrl+Shift+N".to_string()),
+            tray_middle_click_paste: false,
+            webhooks: Vec::new(),
+            terminal_cleanup: true,
+            strip_terminal_prompts: false,
+            local_api_enabled: false,
+            local_api_port: 27123,
+            local_api_token: None,
+            lan_share_enabled: false,
+            lan_share_single_use: true,
+            export_line_ending: LineEnding::Lf,
+            ai_prices: usage::default_prices(),
+            ai_monthly_token_budget: None,
+            ai_budget_blocks_batch: false,
+            record_ai_history: true,
+            include_apps: None,
+            exclude_apps: Vec::new(),
+            notification_policy: NotificationPolicy::default(),
+            clipboard_hold_aggressiveness: HoldAggressiveness::default(),
+            language: Language::default(),
+            jump_list: true,
+            ingest_transforms: Vec::new(),
+        }
+    }
+}
+
+/// Result of adding a captured clip
+#[derive(Debug, Clone)]
+pub enum CaptureOutcome {
+    Added(ClipObject),
+    Bumped(ClipObject),
+    /// Already held by a clip outside the dedup window, which was bumped
+    /// and its pastebook made the active one (`DuplicateElsewhere::Reuse`);
+    /// carries that pastebook's name when the active one changed
+    Reused(ClipObject, Option<String>),
+    Ignored,
+    /// Dropped by the named capture rule
+    Skipped(String),
+    /// The active pastebook no longer exists, so nothing was added
+    NoPastebook(ClipObject),
+}
+
+/// A capture rule routed by window title to a pastebook that didn't exist
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum TitleRouted {
+    /// Created for the capture
+    Created { id: String, name: String },
+    /// Not created, since `limit` pastebooks were created this way already;
+    /// the capture went to the active pastebook
+    LimitReached { name: String, limit: usize },
+}
+
+/// Where a capture routed by window title goes
+enum TitleTarget {
+    Existing(String),
+    /// A valid name with no pastebook yet; created if the clip is added
+    New(String),
+    /// The active pastebook, since the name isn't valid or creating it
+    /// would pass `max_auto_pastebooks`
+    Fallback(Option<TitleRouted>),
+}
+
+/// Name of the pastebook new installs start with
+const DEFAULT_PASTEBOOK_NAME: &str = "My First Pastebook";
+/// Name given to a pastebook whose stored name has nothing usable left
+const UNTITLED_PASTEBOOK_NAME: &str = "Untitled";
+
+/// Error from adding a clip when `active_pastebook_id` is unset or stale
+pub const NO_ACTIVE_PASTEBOOK: &str = "NotFound: No active pastebook";
+
+/// Payload of the `pastebook-auto-selected` event
+#[derive(Debug, Clone, Serialize)]
+pub struct AutoSelected {
+    pub id: String,
+    pub name: String,
+    /// There was no pastebook left, so a new default one was made
+    pub created: bool,
+}
+
+const DEFAULT_MERGE_SEPARATOR: &str = "\n\n";
+
+/// Pull pinned clips out of a pastebook's list, with their indexes
+fn split_pinned(clips: Vec<ClipObject>) -> (Vec<(usize, ClipObject)>, Vec<ClipObject>) {
+    let mut pinned = Vec::new();
+    let mut rest = Vec::with_capacity(clips.len());
+    for (index, clip) in clips.into_iter().enumerate() {
+        if clip.pinned {
+            pinned.push((index, clip));
+        } else {
+            rest.push(clip);
+        }
+    }
+    (pinned, rest)
+}
+
+/// Put pinned clips back at their indexes with `rest` filling the other
+/// slots in order; pinned clips past the end of a shorter list go last
+fn place_pinned(pinned: Vec<(usize, ClipObject)>, rest: Vec<ClipObject>) -> Vec<ClipObject> {
+    let mut clips = Vec::with_capacity(pinned.len() + rest.len());
+    let mut pinned = pinned.into_iter().peekable();
+    let mut rest = rest.into_iter();
+    loop {
+        if let Some((_, clip)) = pinned.next_if(|(index, _)| *index <= clips.len()) {
+            clips.push(clip);
+        } else if let Some(clip) = rest.next() {
+            clips.push(clip);
+        } else {
+            clips.extend(pinned.map(|(_, clip)| clip));
+            return clips;
+        }
+    }
+}
+
+/// Orders `sort_clips` can put a pastebook in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Newest,
+    Oldest,
+    SourceApp,
+    Title,
+}
+
+/// Order in which merged clips are joined
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MergeOrder {
+    /// The order the ids were given in, i.e. the order they were clicked
+    Selection,
+    /// Top to bottom, as the clips sit in the pastebook
+    #[default]
+    Display,
+    /// Oldest capture first
+    Chronological,
+}
+
+/// Options shared by merge preview and merge
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MergeOptions {
+    pub separator: Option<String>,
+    pub order: MergeOrder,
+    /// Keep the source clips and just add the merged clip on top
+    pub keep_sources: bool,
+}
+
+/// Lets a patch field tell "set to null" (Some(None)) apart from "absent" (None)
+fn present<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    T::deserialize(deserializer).map(Some)
+}
+
+/// Changes applied to every clip in a bulk update; absent fields are left alone
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ClipPatch {
+    pub add_tags: Vec<String>,
+    pub remove_tags: Vec<String>,
+    /// Some(None) clears the label
+    #[serde(deserialize_with = "present")]
+    pub label: Option<Option<String>>,
+    pub status: Option<String>,
+    pub pinned: Option<bool>,
+    pub locked: Option<bool>,
+}
+
+impl ClipPatch {
+    /// Normalize the fields, rejecting invalid values and patches that change nothing
+    pub fn validate(self) -> Result<Self, String> {
+        let label = match self.label {
+            Some(label) => Some(validate_label(label)?),
+            None => None,
+        };
+        let status = match self.status {
+            Some(status) if status.trim().is_empty() => {
+                return Err("Status cannot be empty".to_string())
+            }
+            Some(status) => Some(status.trim().to_string()),
+            None => None,
+        };
+        let patch = Self {
+            add_tags: normalize_tags(self.add_tags),
+            remove_tags: normalize_tags(self.remove_tags),
+            label,
+            status,
+            pinned: self.pinned,
+            locked: self.locked,
+        };
+        if patch.add_tags.is_empty()
+            && patch.remove_tags.is_empty()
+            && patch.label.is_none()
+            && patch.status.is_none()
+            && patch.pinned.is_none()
+            && patch.locked.is_none()
+        {
+            return Err("Patch is empty".to_string());
+        }
+        Ok(patch)
+    }
+    
+    fn apply(&self, clip: &mut ClipObject) {
+        clip.tags.retain(|tag| !self.remove_tags.contains(tag));
+        for tag in &self.add_tags {
+            if !clip.tags.contains(tag) {
+                clip.tags.push(tag.clone());
+            }
+        }
+        if let Some(label) = &self.label {
+            clip.label = label.clone();
+        }
+        if let Some(status) = &self.status {
+            clip.status = status.clone();
+        }
+        if let Some(pinned) = self.pinned {
+            clip.pinned = pinned;
+        }
+        if let Some(locked) = self.locked {
+            clip.locked = locked;
+        }
+        clip.edited_at = Some(Utc::now());
+    }
+}
+
+/// What happened to one clip in a bulk update
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BulkOutcome {
+    Updated,
+    NotFound,
+    /// Left alone because it's locked; a patch that unlocks it applies
+    Locked,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct BulkUpdateResult {
+    pub id: String,
+    pub outcome: BulkOutcome,
+}
+
+const FIND_REGEX_SIZE_LIMIT: usize = 1 << 20;
+const FIND_PREVIEW_CONTEXT: usize = 30;
+
+/// Per-clip result of a find & replace
+#[derive(Debug, Clone, Serialize)]
+pub struct FindReplaceMatch {
+    pub id: String,
+    pub matches: usize,
+    /// Text around the first match, before and after replacement
+    pub preview_before: String,
+    pub preview_after: String,
+}
+
+/// Result of a find & replace across clips
+#[derive(Debug, Clone, Serialize)]
+pub struct FindReplaceResult {
+    pub clips: Vec<FindReplaceMatch>,
+    /// Number of clips changed (always 0 for a dry run)
+    pub changed: usize,
+}
+
+/// Compile a find pattern, escaping it unless `is_regex` is set
+pub fn build_find_regex(pattern: &str, is_regex: bool) -> Result<Regex, String> {
+    if pattern.is_empty() {
+        return Err("Search pattern is empty".to_string());
+    }
+    
+    let source = if is_regex {
+        pattern.to_string()
+    } else {
+        regex::escape(pattern)
+    };
+    
+    RegexBuilder::new(&source)
+        .size_limit(FIND_REGEX_SIZE_LIMIT)
+        .build()
+        .map_err(|e| format!("Invalid pattern: {}", e))
+}
+
+/// A command result tagged with the storage revision it was read at
+#[derive(Debug, Clone, Serialize)]
+pub struct Revisioned<T> {
+    pub revision: u64,
+    pub data: T,
+}
+
+/// Storage container for all pastebooks
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AppStorage {
+    pub pastebooks: Vec<Pastebook>,
+    pub active_pastebook_id: Option<String>,
+    /// Bumped on every committed mutation, for optimistic concurrency
+    #[serde(default)]
+    pub revision: u64,
+    #[serde(default)]
+    pub api_key: Option<String>,
+    #[serde(default)]
+    pub settings: Settings,
+    #[serde(default)]
+    pub sync: Option<SyncState>,
+    #[serde(default)]
+    pub templates: Vec<PastebookTemplate>,
+    #[serde(default)]
+    pub sessions: Vec<CaptureSession>,
+    /// Applied in order to every capture
+    #[serde(default)]
+    pub rules: Vec<CaptureRule>,
+    #[serde(default = "presets::default_presets")]
+    pub prompt_presets: Vec<PromptPreset>,
+    /// Token usage of every AI request, for `get_ai_usage`
+    #[serde(default)]
+    pub ai_usage: Vec<UsageEntry>,
+    /// Recent AI requests and replies, newest last, for `get_ai_history`
+    #[serde(default)]
+    pub ai_history: Vec<AiInteraction>,
+    /// Built after startup (see `build_search_index`) and kept current by the
+    /// clip operations below; clips it hasn't seen are never ruled out
+    #[serde(skip)]
+    pub search_index: SearchIndex,
+    /// `rules`, compiled; rebuilt whenever they change
+    #[serde(skip)]
+    rule_set: RuleSet,
+    /// Pastebook operations of this session, for `undo_last_operation`
+    #[serde(skip)]
+    undo: UndoStack,
+    /// The file this storage loads from and saves to; None keeps it purely
+    /// in memory (scratch copies and tests)
+    #[serde(skip)]
+    storage_path: Option<PathBuf>,
+    /// A deferred commit hasn't been written yet
+    #[serde(skip)]
+    save_pending: bool,
+    /// The storage file is an index with the clips in book files, rather
+    /// than one file holding everything as stores from before did
+    #[serde(skip)]
+    book_files: bool,
+    /// Externalized clips still hold only their previews: `load_from`
+    /// leaves reading them back to `load_contents`, after startup
+    #[serde(skip)]
+    contents_pending: bool,
+    /// What the last capture's title route did about a missing pastebook
+    #[serde(skip)]
+    title_routed: Option<TitleRouted>,
+}
+
+impl Default for AppStorage {
+    fn default() -> Self {
+        // Create a default pastebook
+        let default_pastebook = Pastebook::new(DEFAULT_PASTEBOOK_NAME.to_string());
+        let default_id = default_pastebook.id.clone();
+        Self {
+            pastebooks: vec![default_pastebook],
+            active_pastebook_id: Some(default_id),
+            revision: 0,
+            api_key: None,
+            settings: Settings::default(),
+            sync: None,
+            templates: Vec::new(),
+            sessions: Vec::new(),
+            rules: Vec::new(),
+            prompt_presets: presets::default_presets(),
+            ai_usage: Vec::new(),
+            ai_history: Vec::new(),
+            search_index: SearchIndex::default(),
+            rule_set: RuleSet::default(),
+            undo: UndoStack::default(),
+            storage_path: None,
+            save_pending: false,
+            book_files: false,
+            contents_pending: false,
+            title_routed: None,
+        }
+    }
+}
+
+impl AppStorage {
+    /// The storage file in the current data dir
+    pub fn default_path() -> PathBuf {
+        paths::data_dir().join("pastebooks.json")
+    }
+    
+    /// Load from the storage file in the data dir, noting first whether the
+    /// last session shut down cleanly
+    pub fn load() -> Self {
+        let path = Self::default_path();
+        shutdown::note_previous(&path);
+        Self::load_from(path)
+    }
+    
+    /// Load from `path`, which later saves write back to. A file that isn't
+    /// valid JSON is set aside as pastebooks.corrupt-<time>.json and storage
+    /// starts empty; one that can't be read at all puts storage in memory.
+    pub fn load_from(path: PathBuf) -> Self {
+        let dir = path.parent().unwrap_or(Path::new("."));
+        if let Err(e) = health::probe(dir) {
+            health::mark_unavailable(e);
+        }
+        // Settle a layout change cut short last time, so what's read is one layout
+        match migration::recover(dir) {
+            Ok(migration::Recovery::Clean) => {}
+            Ok(recovery) => println!("Recovered an interrupted migration: {:?}", recovery),
+            Err(e) => eprintln!("Failed to recover an interrupted migration: {}", e),
+        }
+        
+        let mut storage: Self = if path.exists() {
+            match fs::read_to_string(&path) {
+                Ok(content) => match serde_json::from_str(&content) {
+                    Ok(storage) => storage,
+                    Err(e) => {
+                        eprintln!("{} is corrupt ({}), starting empty", path.display(), e);
+                        Self::set_aside_corrupt(&path);
+                        Self::default()
+                    }
+                },
+                Err(e) => {
+                    // Don't let the empty default overwrite what we couldn't read
+                    health::mark_unreadable_on_load();
+                    health::mark_unavailable(format!("Failed to read {}: {}", path.display(), e));
+                    Self::default()
+                }
+            }
+        } else {
+            Self::default()
+        };
+        // Pastebooks saved without clips have them in book files; only the
+        // active one's are read now
+        let active = storage.active_pastebook_id.clone();
+        for pastebook in storage.pastebooks.iter_mut().filter(|p| !p.clips.is_loaded()) {
+            storage.book_files = true;
+            let file = book_path(&path, &pastebook.id);
+            if active.as_ref() == Some(&pastebook.id) {
+                pastebook.clips = read_book(&file, false).into();
+            } else {
+                pastebook.clips = BookClips::unloaded(file);
+            }
+        }
+        storage.storage_path = Some(path);
+        let renamed = storage.repair_pastebook_names();
+        if renamed > 0 {
+            println!("Renamed {} pastebooks that shared a name", renamed);
+        }
+        // Externalized contents are read after startup, see `load_contents`,
+        // and so is the search index built; until then searches scan every clip
+        let pending = storage.loaded_clips().any(|c| c.content_ref.is_some());
+        storage.contents_pending = pending;
+        storage.rule_set = RuleSet::new(&storage.rules);
+        if let Some(sync) = storage.sync.as_mut() {
+            sync.seal_legacy_key();
+        }
+        storage
+    }
+    
+    /// Keep a corrupt storage file for recovery instead of saving over it
+    fn set_aside_corrupt(path: &Path) {
+        let backup = path.with_extension(format!(
+            "corrupt-{}.json",
+            Utc::now().format("%Y%m%d%H%M%S")
+        ));
+        if let Err(e) = fs::rename(path, &backup) {
+            health::mark_unreadable_on_load();
+            health::mark_unavailable(format!("Failed to set aside corrupt {}: {}", path.display(), e));
+        }
+    }
+    
+    /// Save to a different file from now on (after the data dir moves,
+    /// book files and all)
+    pub fn set_storage_path(&mut self, path: PathBuf) {
+        for pastebook in self.pastebooks.iter_mut().filter(|p| !p.clips.is_loaded()) {
+            pastebook.clips = BookClips::unloaded(book_path(&path, &pastebook.id));
+        }
+        self.storage_path = Some(path);
+    }
+    
+    /// The file saves go to, if any
+    pub fn storage_path(&self) -> Option<&Path> {
+        self.storage_path.as_deref()
+    }
+    
+    /// Replace everything with a storage file saved earlier (e.g. the copy
+    /// from the last clean exit). Saves keep going to the current file and
+    /// the revision keeps counting up from here.
+    pub fn restore_from(&mut self, path: &Path) -> Result<(), String> {
+        let json = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+        let mut restored: Self = serde_json::from_str(&json)
+            .map_err(|e| format!("{} is not a Stack storage file: {}", path.display(), e))?;
+        restored.storage_path = self.storage_path.take();
+        restored.book_files = self.book_files;
+        restored.revision = self.revision;
+        restored.repair_pastebook_names();
+        restored.contents_pending = true;
+        restored.load_contents();
+        restored.rebuild_search_index();
+        restored.rule_set = RuleSet::new(&restored.rules);
+        if let Some(sync) = restored.sync.as_mut() {
+            sync.seal_legacy_key();
+        }
+        *self = restored;
+        Ok(())
+    }
+    
+    /// Read back the content that was too big to keep inline, which
+    /// `load_from` leaves for after startup; does nothing once it's done.
+    /// A clip edited meanwhile has dropped its content_ref and is skipped.
+    pub fn load_contents(&mut self) {
+        if !std::mem::take(&mut self.contents_pending) {
+            return;
+        }
+        for clip in loaded_clips_mut(&mut self.pastebooks) {
+            let Some(hash) = &clip.content_ref else {
+                continue;
+            };
+            match read_external_content(hash) {
+                Ok(content) => {
+                    clip.content = content;
+                    self.search_index.insert(clip);
+                }
+                Err(e) => eprintln!("Clip {} keeps only its preview, content unreadable: {}", clip.id, e),
+            }
+        }
+    }
+    
+    /// A clip's full content (any pastebook), read from its asset if
+    /// `load_contents` hasn't got to it yet
+    pub fn clip_content(&self, id: &str) -> Option<String> {
+        let clip = self.find_clip(id)?;
+        if let (true, Some(hash)) = (self.contents_pending, &clip.content_ref) {
+            match read_external_content(hash) {
+                Ok(content) => return Some(content),
+                Err(e) => eprintln!("Clip {} has only its preview, content unreadable: {}", clip.id, e),
+            }
+        }
+        Some(clip.content.clone())
+    }
+    
+    /// Store oversized clip contents as assets (once per content version);
+    /// false if any couldn't be stored and so must stay inline
+    fn externalize_large_contents(&mut self, limit: usize) -> bool {
+        let mut complete = true;
+        for clip in loaded_clips_mut(&mut self.pastebooks) {
+            if clip.content.len() <= limit || clip.content_ref.is_some() {
+                continue;
+            }
+            match assets::store_asset(clip.content.as_bytes()) {
+                Ok(hash) => clip.content_ref = Some(hash),
+                Err(e) => {
+                    eprintln!("Keeping clip {} inline: {}", clip.id, e);
+                    complete = false;
+                }
+            }
+        }
+        complete
+    }
+    
+    /// Save to storage. If the data dir can't be written, storage switches to
+    /// in-memory mode instead of failing, and writes resume once it recovers.
+    pub fn save(&mut self) -> Result<(), String> {
+        self.save_pending = false;
+        // A preview must never be written back as a clip's whole content
+        self.load_contents();
+        let Some(path) = self.storage_path.clone() else {
+            return Ok(());
+        };
+        if health::is_in_memory() {
+            return Ok(());
+        }
+        // Changes are refused up front in safe mode; what gets here (the
+        // pastebook on screen) just isn't written
+        if safe_mode::is_active() {
+            return Ok(());
+        }
+        
+        let limit = (self.settings.externalize_content_kb > 0)
+            .then(|| self.settings.externalize_content_kb as usize * 1024)
+            .filter(|limit| self.externalize_large_contents(*limit));
+        EXTERNALIZE_OVER.set(limit);
+        let written = self.write_books(&path);
+        EXTERNALIZE_OVER.set(None);
+        if let Err(e) = written {
+            health::mark_unavailable(e);
+        }
+        
+        Ok(())
+    }
+    
+    /// Write the storage file as an index plus a book file for each loaded
+    /// pastebook; unloaded ones haven't changed since their file was read.
+    /// A store still in one file is switched over by a migration, so a
+    /// crash part way leaves one layout or the other.
+    fn write_books(&mut self, path: &Path) -> Result<(), String> {
+        for pastebook in self.pastebooks.iter_mut().filter(|p| p.clips.is_loaded()) {
+            pastebook.book = Some(pastebook.book_counts());
+        }
+        // Compact: pretty-printing a large store costs more than the write
+        let mut books = Vec::new();
+        for pastebook in self.pastebooks.iter() {
+            if let Some(clips) = pastebook.clips.get_loaded() {
+                let json = serde_json::to_string(clips).map_err(|e| format!("Failed to serialize: {}", e))?;
+                books.push((book_path(path, &pastebook.id), json));
+            }
+        }
+        WRITING_INDEX.set(true);
+        let index = serde_json::to_string(self);
+        WRITING_INDEX.set(false);
+        let index = index.map_err(|e| format!("Failed to serialize: {}", e))?;
+        
+        let dir = path.parent().unwrap_or(Path::new("."));
+        let books_dir = path.with_file_name(BOOKS_DIR);
+        fs::create_dir_all(&books_dir).map_err(|e| format!("Failed to create {}: {}", books_dir.display(), e))?;
+        if !self.book_files && path.exists() {
+            let mut migration = migration::Migration::new(dir, "book files");
+            for (file, json) in &books {
+                migration.write(file.strip_prefix(dir).unwrap_or(file), json.as_bytes())?;
+            }
+            migration.write(path.strip_prefix(dir).unwrap_or(path), index.as_bytes())?;
+            migration.run()?;
+        } else {
+            for (file, json) in &books {
+                fs::write(file, json).map_err(|e| format!("Failed to write {}: {}", file.display(), e))?;
+            }
+            fs::write(path, index).map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        }
+        self.book_files = true;
+        self.remove_stray_books(path);
+        Ok(())
+    }
+    
+    /// Delete book files of pastebooks that are gone; corrupt ones set
+    /// aside (with a dot in their name) stay
+    fn remove_stray_books(&self, path: &Path) {
+        let Ok(entries) = fs::read_dir(path.with_file_name(BOOKS_DIR)) else {
+            return;
+        };
+        for entry in entries.flatten() {
+            let name = entry.file_name();
+            let id = name.to_string_lossy();
+            let Some(id) = id.strip_suffix(".json").filter(|id| !id.contains('.')) else {
+                continue;
+            };
+            if !self.pastebooks.iter().any(|p| p.id == id) {
+                let _ = fs::remove_file(entry.path());
+            }
+        }
+    }
+    
+    /// Write everything to one file, as `restore_from` reads, e.g. the copy
+    /// kept from a clean exit. Unloaded pastebooks are read to be written.
+    pub fn write_copy(&self, path: &Path) -> Result<(), String> {
+        let json = serde_json::to_string(self).map_err(|e| format!("Failed to serialize: {}", e))?;
+        fs::write(path, json).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    }
+    
+    /// Clips of the pastebooks that have been read
+    fn loaded_clips(&self) -> impl Iterator<Item = &ClipObject> {
+        self.pastebooks.iter().filter_map(|p| p.clips.get_loaded()).flatten()
+    }
+    
+    /// Pastebooks already read first, so finding a clip reads a book file
+    /// only when it's in none of them
+    fn books_loaded_first(&self) -> impl Iterator<Item = &Pastebook> {
+        let loaded = self.pastebooks.iter().filter(|p| p.clips.is_loaded());
+        loaded.chain(self.pastebooks.iter().filter(|p| !p.clips.is_loaded()))
+    }
+    
+    /// Try the data dir again after going in-memory; on success everything
+    /// held in memory is written out
+    pub fn retry_persistence(&mut self) -> StorageHealth {
+        health::note_retry();
+        if !health::is_in_memory() {
+            return health::current();
+        }
+        let Some(path) = self.storage_path.clone() else {
+            return health::current();
+        };
+        if let Err(e) = health::probe(path.parent().unwrap_or(Path::new("."))) {
+            health::mark_unavailable(e);
+            return health::current();
+        }
+        
+        if health::mark_available() && path.exists() {
+            let backup = path.with_extension("unreadable.json");
+            if let Err(e) = fs::rename(&path, &backup) {
+                health::mark_unreadable_on_load();
+                health::mark_unavailable(format!("Failed to set aside unreadable {}: {}", path.display(), e));
+                return health::current();
+            }
+        }
+        let _ = self.save();
+        health::current()
+    }
+    
+    /// Everything a backup holds: this storage minus the API key and sync
+    /// credentials
+    pub fn backup_copy(&self) -> AppStorage {
+        let mut backup = self.clone();
+        backup.api_key = None;
+        backup.sync = None;
+        backup
+    }
+    
+    /// Write a `backup_copy` to `path`, returning its size; works in
+    /// in-memory mode too
+    pub fn write_backup(&self, path: &Path) -> Result<u64, String> {
+        if !path.is_absolute() {
+            return Err("Backup path must be absolute".to_string());
+        }
+        if path.is_dir() {
+            return Err(format!("{} is a directory", path.display()));
+        }
+        
+        let json = serde_json::to_string_pretty(self)
+            .map_err(|e| format!("Failed to serialize: {}", e))?;
+        fs::write(path, &json)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(json.len() as u64)
+    }
+    
+    /// Fail with a conflict if the caller's view of storage is out of date
+    pub fn check_revision(&self, expected: Option<u64>) -> Result<(), String> {
+        match expected {
+            Some(expected) if expected != self.revision => Err(format!(
+                "Conflict: expected revision {} but storage is at {}",
+                expected, self.revision
+            )),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Bump the revision and save, returning the new revision. If the save
+    /// fails the revision is put back, so it only moves for a stored change.
+    pub fn commit(&mut self) -> Result<u64, String> {
+        self.revision += 1;
+        if let Err(e) = self.save() {
+            self.revision -= 1;
+            return Err(e);
+        }
+        Ok(self.revision)
+    }
+    
+    /// `commit` if the command changed anything; otherwise the revision
+    /// stays put and nothing is written
+    pub fn commit_if(&mut self, changed: bool) -> Result<u64, String> {
+        if !changed {
+            return Ok(self.revision);
+        }
+        self.commit()
+    }
+    
+    /// Bump the revision but leave the save for `flush_pending`, so a burst
+    /// of captures is written once
+    pub fn commit_deferred(&mut self) -> u64 {
+        self.revision += 1;
+        self.save_pending = true;
+        self.revision
+    }
+    
+    /// Leave a change that isn't an edit (the revision stays put) for
+    /// `flush_pending` to write
+    pub fn defer_save(&mut self) {
+        self.save_pending = true;
+    }
+    
+    /// Write out any deferred commit; returns whether there was one
+    pub fn flush_pending(&mut self) -> Result<bool, String> {
+        if !self.save_pending {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+    
+    /// Wrap a result with the current revision
+    pub fn revisioned<T>(&self, data: T) -> Revisioned<T> {
+        Revisioned {
+            revision: self.revision,
+            data,
+        }
+    }
+    
+    /// Get the active pastebook
+    pub fn get_active_pastebook(&self) -> Option<&Pastebook> {
+        self.active_pastebook_id.as_ref().and_then(|id| {
+            self.pastebooks.iter().find(|p| &p.id == id)
+        })
+    }
+    
+    /// Get mutable reference to active pastebook
+    pub fn get_active_pastebook_mut(&mut self) -> Option<&mut Pastebook> {
+        let id = self.active_pastebook_id.clone();
+        id.and_then(move |id| {
+            self.pastebooks.iter_mut().find(|p| p.id == id)
+        })
+    }
+    
+    /// The pastebook with `id`, or the active one when None
+    pub fn resolve_pastebook(&self, id: Option<&str>) -> Result<&Pastebook, String> {
+        match id {
+            Some(id) => self
+                .pastebooks
+                .iter()
+                .find(|p| p.id == id)
+                .ok_or_else(|| format!("NotFound: pastebook {}", id)),
+            None => self.get_active_pastebook().ok_or_else(|| NO_ACTIVE_PASTEBOOK.to_string()),
+        }
+    }
+    
+    /// Like `resolve_pastebook`, mutably
+    pub fn resolve_pastebook_mut(&mut self, id: Option<&str>) -> Result<&mut Pastebook, String> {
+        match id {
+            Some(id) => self
+                .pastebooks
+                .iter_mut()
+                .find(|p| p.id == id)
+                .ok_or_else(|| format!("NotFound: pastebook {}", id)),
+            None => self.get_active_pastebook_mut().ok_or_else(|| NO_ACTIVE_PASTEBOOK.to_string()),
+        }
+    }
+    
+    /// Find a pastebook by name, ignoring case and surrounding spaces
+    pub fn find_pastebook_by_name(&self, name: &str) -> Option<&Pastebook> {
+        let name = name.trim().to_lowercase();
+        self.pastebooks
+            .iter()
+            .find(|p| p.name.trim().to_lowercase() == name)
+    }
+    
+    /// `name`, or the first free "name (2)", "name (3)", ... if it's taken
+    pub fn unique_pastebook_name(&self, name: &str) -> String {
+        let name = name.trim();
+        if self.find_pastebook_by_name(name).is_none() {
+            return name.to_string();
+        }
+        (2..)
+            .map(|n| format!("{} ({})", name, n))
+            .find(|candidate| self.find_pastebook_by_name(candidate).is_none())
+            .unwrap()
+    }
+    
+    /// Trim a new pastebook name and make sure no other pastebook has it
+    fn validate_pastebook_name(&self, name: &str, except_id: Option<&str>) -> Result<String, String> {
+        let name = validate_name("pastebook name", name)?;
+        let name = name.as_str();
+        match self.find_pastebook_by_name(name) {
+            Some(existing) if Some(existing.id.as_str()) != except_id => Err(format!(
+                "AlreadyExists: pastebook '{}' (try '{}')",
+                existing.name,
+                self.unique_pastebook_name(name)
+            )),
+            _ => Ok(name.to_string()),
+        }
+    }
+    
+    /// A pastebook name that may not have passed `validate_name` (one from
+    /// another device or an old file), made valid: control characters
+    /// dropped, trimmed and cut to `MAX_NAME_CHARS`, or "Untitled" if empty
+    fn valid_pastebook_name(name: &str) -> String {
+        let name: String = name.chars().filter(|c| !c.is_control()).collect();
+        let name = textutil::truncate(name.trim(), MAX_NAME_CHARS).trim_end();
+        if name.is_empty() {
+            UNTITLED_PASTEBOOK_NAME.to_string()
+        } else {
+            name.to_string()
+        }
+    }
+    
+    /// `valid_pastebook_name`, suffixed if another pastebook has it
+    pub fn usable_pastebook_name(&self, name: &str) -> String {
+        self.unique_pastebook_name(&Self::valid_pastebook_name(name))
+    }
+    
+    /// Make every pastebook name valid, and suffix duplicates (ignoring
+    /// case) so each is unique; the first keeps its name. Returns how many
+    /// were renamed.
+    fn repair_pastebook_names(&mut self) -> usize {
+        let mut renamed = 0;
+        for i in 0..self.pastebooks.len() {
+            // Out of the way, so the pastebook doesn't clash with itself
+            let old = std::mem::take(&mut self.pastebooks[i].name);
+            let name = Self::valid_pastebook_name(&old);
+            let key = name.to_lowercase();
+            let duplicate = self.pastebooks[..i].iter().any(|p| p.name.to_lowercase() == key);
+            let name = if duplicate { self.unique_pastebook_name(&name) } else { name };
+            if name != old {
+                renamed += 1;
+            }
+            self.pastebooks[i].name = name;
+        }
+        renamed
+    }
+    
+    /// Create a new pastebook and switch to it; names must be unique
+    pub fn create_pastebook(&mut self, name: String) -> Result<Pastebook, String> {
+        let name = self.validate_pastebook_name(&name, None)?;
+        let pastebook = Pastebook::new(name);
+        self.pastebooks.push(pastebook.clone());
+        self.active_pastebook_id = Some(pastebook.id.clone());
+        Ok(pastebook)
+    }
+    
+    /// Add an imported pastebook after the others in its group (or at the
+    /// end), suffixing its name if taken. Clips with no content are dropped,
+    /// unknown labels cleared and tags normalized.
+    pub fn import_pastebook(&mut self, name: &str, group: Option<String>, clips: Vec<ClipObject>) -> Result<Pastebook, String> {
+        let name = validate_name("pastebook name", name)?;
+        let mut pastebook = Pastebook::new(self.unique_pastebook_name(&name));
+        pastebook.group = match group.filter(|g| !g.trim().is_empty()) {
+            Some(group) => Some(self.group_name(&group)?),
+            None => None,
+        };
+        for mut clip in clips {
+            if validate_content(&clip.content, false).is_err() {
+                continue;
+            }
+            clip.label = validate_label(clip.label).unwrap_or(None);
+            clip.tags = normalize_tags(clip.tags);
+            self.search_index.insert(&clip);
+            pastebook.clips.push(clip);
+        }
+        let at = self
+            .pastebooks
+            .iter()
+            .rposition(|p| Self::in_group(p, pastebook.group.as_deref()))
+            .map_or(self.pastebooks.len(), |i| i + 1);
+        self.pastebooks.insert(at, pastebook.clone());
+        Ok(pastebook)
+    }
+    
+    /// Switch to a pastebook
+    pub fn switch_pastebook(&mut self, id: String) -> bool {
+        if let Some(pastebook) = self.pastebooks.iter().find(|p| p.id == id) {
+            // Read it now, and index it for searching
+            if !pastebook.clips.is_loaded() {
+                pastebook.clips.iter().for_each(|c| self.search_index.insert(c));
+            }
+            self.active_pastebook_id = Some(id);
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// Delete a pastebook. One with live clips needs `confirmed`; one that
+    /// is empty or holds only trashed clips goes without. The undo stack
+    /// keeps a copy of it, clips and all.
+    pub fn delete_pastebook(&mut self, id: &str, confirmed: bool) -> Result<bool, String> {
+        if self.pastebooks.len() <= 1 {
+            return Ok(false); // Can't delete the last pastebook
+        }
+        
+        if let Some(index) = self.pastebooks.iter().position(|p| p.id == id) {
+            let pastebook = &self.pastebooks[index];
+            let live = pastebook.live_clip_count();
+            if live > 0 && !confirmed {
+                return Err(format!(
+                    "Validation: pastebook '{}' still has {} clip(s); confirm to delete it",
+                    pastebook.name, live
+                ));
+            }
+            self.load_contents();
+            let pastebook = &self.pastebooks[index];
+            // The copy holds the clips itself: the book file goes with the next save
+            let mut copy = pastebook.clone();
+            copy.clips = pastebook.clips.peek().into_owned().into();
+            for clip in &copy.clips {
+                self.search_index.remove(&clip.id);
+            }
+            let undo = PastebookUndo::Delete {
+                pastebook: Box::new(copy),
+                index,
+                was_active: self.active_pastebook_id.as_deref() == Some(id),
+            };
+            self.undo.push(format!("Delete pastebook '{}'", pastebook.name), Some(undo));
+        }
+        
+        let initial_len = self.pastebooks.len();
+        self.pastebooks.retain(|p| p.id != id);
+        
+        // If we deleted the active pastebook, switch to the first one
+        if self.active_pastebook_id.as_ref() == Some(&id.to_string()) {
+            self.active_pastebook_id = self.pastebooks.first().map(|p| p.id.clone());
+        }
+        
+        Ok(self.pastebooks.len() < initial_len)
+    }
+    
+    /// Rename a pastebook; false if there's no such pastebook
+    pub fn rename_pastebook(&mut self, id: &str, new_name: String) -> Result<bool, String> {
+        let new_name = self.validate_pastebook_name(&new_name, Some(id))?;
+        if let Some(pastebook) = self.pastebooks.iter_mut().find(|p| p.id == id) {
+            let old_name = std::mem::replace(&mut pastebook.name, new_name);
+            let label = format!("Rename pastebook '{}'", old_name);
+            self.undo.push(label, Some(PastebookUndo::Rename { id: id.to_string(), old_name }));
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+    
+    /// Set or clear the file a pastebook's new clips are appended to; the
+    /// path must already be validated
+    pub fn set_pastebook_mirror(&mut self, id: &str, path: Option<PathBuf>) -> Option<Pastebook> {
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == id)?;
+        pastebook.mirror_file = path;
+        Some(pastebook.clone())
+    }
+    
+    /// Set or clear a pastebook's scheduled export; the config must already
+    /// be validated
+    pub fn set_pastebook_auto_export(&mut self, id: &str, config: Option<AutoExportConfig>) -> Option<Pastebook> {
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == id)?;
+        pastebook.auto_export = config;
+        Some(pastebook.clone())
+    }
+    
+    /// The spelling of an existing group matching `name` ignoring case, or
+    /// the validated name itself
+    fn group_name(&self, name: &str) -> Result<String, String> {
+        let name = validate_name("group name", name)?;
+        let existing = self
+            .pastebooks
+            .iter()
+            .filter_map(|p| p.group.as_deref())
+            .find(|g| g.to_lowercase() == name.to_lowercase());
+        Ok(existing.map_or(name, str::to_string))
+    }
+    
+    /// Whether a pastebook is in `group`, with group names ignoring case
+    fn in_group(pastebook: &Pastebook, group: Option<&str>) -> bool {
+        pastebook.group.as_deref().map(str::to_lowercase) == group.map(str::to_lowercase)
+    }
+    
+    /// File a pastebook under a group (None or blank ungroups it). It goes
+    /// last in its new group.
+    pub fn set_pastebook_group(&mut self, id: &str, group: Option<String>) -> Result<Pastebook, String> {
+        let group = match group.filter(|g| !g.trim().is_empty()) {
+            Some(group) => Some(self.group_name(&group)?),
+            None => None,
+        };
+        let index = self
+            .pastebooks
+            .iter()
+            .position(|p| p.id == id)
+            .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+        let mut pastebook = self.pastebooks.remove(index);
+        let undo = PastebookUndo::Group {
+            id: id.to_string(),
+            old_group: pastebook.group.clone(),
+            old_index: index,
+            old_next: self.pastebooks.get(index).map(|p| p.id.clone()),
+        };
+        self.undo.push(format!("Move pastebook '{}'", pastebook.name), Some(undo));
+        let last_member = self
+            .pastebooks
+            .iter()
+            .rposition(|p| Self::in_group(p, group.as_deref()));
+        let at = last_member.map_or(self.pastebooks.len(), |i| i + 1);
+        pastebook.group = group;
+        self.pastebooks.insert(at, pastebook.clone());
+        Ok(pastebook)
+    }
+    
+    /// Pastebooks bucketed by group, groups in the order their first
+    /// pastebook appears
+    pub fn pastebook_groups(&self) -> Vec<PastebookGroup> {
+        let mut groups: Vec<PastebookGroup> = Vec::new();
+        for pastebook in &self.pastebooks {
+            let summary = pastebook.summary();
+            match groups.iter_mut().find(|g| Self::in_group(pastebook, g.group.as_deref())) {
+                Some(group) => group.pastebooks.push(summary),
+                None => groups.push(PastebookGroup {
+                    group: pastebook.group.clone(),
+                    pastebooks: vec![summary],
+                }),
+            }
+        }
+        groups
+    }
+    
+    /// Rename a group on every pastebook in it; returns how many moved
+    pub fn rename_pastebook_group(&mut self, old: &str, new: &str) -> Result<usize, String> {
+        let old = old.trim();
+        let new = validate_name("group name", new)?;
+        let new = new.as_str();
+        if !self.pastebooks.iter().any(|p| Self::in_group(p, Some(old))) {
+            return Err(format!("NotFound: group '{}'", old));
+        }
+        let clash = self
+            .pastebooks
+            .iter()
+            .filter_map(|p| p.group.as_deref())
+            .find(|g| g.to_lowercase() == new.to_lowercase() && g.to_lowercase() != old.to_lowercase());
+        if let Some(existing) = clash {
+            return Err(format!("AlreadyExists: group '{}'", existing));
+        }
+        
+        let mut ids = Vec::new();
+        let mut old_group = old.to_string();
+        for pastebook in self.pastebooks.iter_mut().filter(|p| Self::in_group(p, Some(old))) {
+            // Keep the stored spelling, which may differ in case from `old`
+            old_group = pastebook.group.replace(new.to_string()).unwrap_or(old_group);
+            ids.push(pastebook.id.clone());
+        }
+        let renamed = ids.len();
+        self.undo.push(
+            format!("Rename group '{}'", old_group),
+            Some(PastebookUndo::GroupRename { ids, old_group }),
+        );
+        Ok(renamed)
+    }
+    
+    /// Take back the latest pastebook operation: renames, group moves and
+    /// renames, pastebook deletes, and clip deletes, clears, merges,
+    /// reorders, bulk updates and trash purges. One that can't be undone is
+    /// reported and dropped so the next call reaches the one before it.
+    pub fn undo_last_operation(&mut self) -> Result<UndoOutcome, String> {
+        // The entry stays on the stack until it's been put back, so an undo
+        // that fails (the old name has been taken meanwhile) can be retried
+        let entry = self.undo.last().cloned().ok_or("NotFound: Nothing to undo")?;
+        let Some(undo) = entry.undo else {
+            self.undo.pop();
+            return Ok(UndoOutcome { label: entry.label, undone: false });
+        };
+        match undo {
+            PastebookUndo::Rename { id, old_name } => {
+                let old_name = self.validate_pastebook_name(&old_name, Some(&id))?;
+                let pastebook = self
+                    .pastebooks
+                    .iter_mut()
+                    .find(|p| p.id == id)
+                    .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+                pastebook.name = old_name;
+            }
+            PastebookUndo::Group { id, old_group, old_index, old_next } => {
+                let index = self
+                    .pastebooks
+                    .iter()
+                    .position(|p| p.id == id)
+                    .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+                let mut pastebook = self.pastebooks.remove(index);
+                pastebook.group = old_group;
+                let at = old_next
+                    .and_then(|next| self.pastebooks.iter().position(|p| p.id == next))
+                    .unwrap_or(old_index.min(self.pastebooks.len()));
+                self.pastebooks.insert(at, pastebook);
+            }
+            PastebookUndo::GroupRename { ids, old_group } => {
+                for pastebook in self.pastebooks.iter_mut().filter(|p| ids.contains(&p.id)) {
+                    pastebook.group = Some(old_group.clone());
+                }
+            }
+            PastebookUndo::Delete { mut pastebook, index, was_active } => {
+                pastebook.name = self.validate_pastebook_name(&pastebook.name, None)?;
+                for clip in &pastebook.clips {
+                    self.search_index.insert(clip);
+                }
+                if was_active {
+                    self.active_pastebook_id = Some(pastebook.id.clone());
+                }
+                self.pastebooks.insert(index.min(self.pastebooks.len()), *pastebook);
+            }
+            PastebookUndo::Clips { id, order, before, added } => {
+                let (pastebook, index) = self
+                    .pastebook_and_index(&id)
+                    .ok_or_else(|| format!("NotFound: pastebook {}", id))?;
+                let mut clips = std::mem::take(&mut *pastebook.clips);
+                for clip in clips.iter().filter(|c| added.contains(&c.id)) {
+                    index.remove(&clip.id);
+                }
+                clips.retain(|c| !added.contains(&c.id));
+                for clip in before {
+                    index.insert(&clip);
+                    match clips.iter_mut().find(|c| c.id == clip.id) {
+                        Some(current) => *current = clip,
+                        None => clips.push(clip),
+                    }
+                }
+                // Clips that arrived since stay on top; the rest go back in their old order
+                let old_position: HashMap<&str, usize> = order.iter().enumerate().map(|(i, id)| (id.as_str(), i)).collect();
+                let (newer, mut older): (Vec<ClipObject>, Vec<ClipObject>) =
+                    clips.into_iter().partition(|c| !old_position.contains_key(c.id.as_str()));
+                older.sort_by_key(|c| old_position[c.id.as_str()]);
+                *pastebook.clips = newer.into_iter().chain(older).collect();
+            }
+        }
+        self.undo.pop();
+        Ok(UndoOutcome { label: entry.label, undone: true })
+    }
+    
+    /// The first half of a `PastebookUndo::Clips`, taken before an
+    /// operation on pastebook `id`: its order and copies of the clips
+    /// `touched` picks, contents read
+    fn clips_before(&mut self, id: &str, touched: impl Fn(&ClipObject) -> bool) -> Option<PastebookUndo> {
+        self.load_contents();
+        let pastebook = self.pastebooks.iter().find(|p| p.id == id)?;
+        Some(PastebookUndo::Clips {
+            id: id.to_string(),
+            order: pastebook.clips.iter().map(|c| c.id.clone()).collect(),
+            before: pastebook.clips.iter().filter(|c| touched(c)).cloned().collect(),
+            added: Vec::new(),
+        })
+    }
+    
+    /// Push a `clips_before` as `label` once its operation has changed
+    /// something, noting the clips the operation added
+    fn push_clips_undo(&mut self, label: String, undo: Option<PastebookUndo>) {
+        let Some(PastebookUndo::Clips { id, order, before, .. }) = undo else {
+            return;
+        };
+        let Some(pastebook) = self.pastebooks.iter().find(|p| p.id == id) else {
+            return;
+        };
+        let old: HashSet<&str> = order.iter().map(String::as_str).collect();
+        let added = pastebook.clips.iter().filter(|c| !old.contains(c.id.as_str())).map(|c| c.id.clone()).collect();
+        self.undo.push(label, Some(PastebookUndo::Clips { id, order, before, added }));
+    }
+    
+    /// Reorder the pastebooks in one group; `ids` must list each of its
+    /// pastebooks exactly once. Other groups keep their places.
+    pub fn reorder_pastebooks(&mut self, group: Option<&str>, ids: &[String]) -> Result<(), String> {
+        let slots: Vec<usize> = (0..self.pastebooks.len())
+            .filter(|&i| Self::in_group(&self.pastebooks[i], group))
+            .collect();
+        let mut members: Vec<&str> = slots.iter().map(|&i| self.pastebooks[i].id.as_str()).collect();
+        let mut requested: Vec<&str> = ids.iter().map(String::as_str).collect();
+        members.sort_unstable();
+        requested.sort_unstable();
+        if members != requested {
+            return Err("Conflict: the ids don't match the pastebooks in the group".to_string());
+        }
+        
+        let mut reordered: Vec<Pastebook> = ids
+            .iter()
+            .map(|id| self.pastebooks.iter().find(|p| &p.id == id).unwrap().clone())
+            .collect();
+        for &slot in slots.iter().rev() {
+            self.pastebooks[slot] = reordered.pop().unwrap();
+        }
+        Ok(())
+    }
+    
+    /// Save a pastebook's clips as a template of titled, tagged skeletons
+    pub fn save_pastebook_as_template(&mut self, id: &str, name: String) -> Option<PastebookTemplate> {
+        let pastebook = self.pastebooks.iter().find(|p| p.id == id)?;
+        
+        let template = PastebookTemplate {
+            id: Uuid::new_v4().to_string(),
+            name,
+            created_at: Utc::now(),
+            clips: pastebook
+                .clips
+                .iter()
+                .map(|c| TemplateClip {
+                    title: c.title.clone(),
+                    content: c.content.clone(),
+                    tags: c.tags.clone(),
+                })
+                .collect(),
+        };
+        self.templates.push(template.clone());
+        Some(template)
+    }
+    
+    /// Create a pastebook from a template with fresh ids and timestamps, and switch to it
+    pub fn create_pastebook_from_template(&mut self, template_id: &str, name: String) -> Result<Pastebook, String> {
+        let name = self.validate_pastebook_name(&name, None)?;
+        let template = self
+            .templates
+            .iter()
+            .find(|t| t.id == template_id)
+            .ok_or("Template not found")?;
+        
+        let mut pastebook = Pastebook::new(name);
+        pastebook.clips = template
+            .clips
+            .iter()
+            .map(|skeleton| {
+                let window_info = WindowInfo {
+                    app_name: "Stack".to_string(),
+                    window_title: format!("Template: {}", template.name),
+                    ..Default::default()
+                };
+                let mut clip = ClipObject::new(skeleton.content.clone(), window_info);
+                clip.title = skeleton.title.clone();
+                clip.tags = skeleton.tags.clone();
+                clip
+            })
+            .collect();
+        
+        for clip in &pastebook.clips {
+            self.search_index.insert(clip);
+        }
+        self.pastebooks.push(pastebook.clone());
+        self.active_pastebook_id = Some(pastebook.id.clone());
+        Ok(pastebook)
+    }
+    
+    /// Delete a template
+    pub fn delete_template(&mut self, id: &str) -> bool {
+        let initial_len = self.templates.len();
+        self.templates.retain(|t| t.id != id);
+        self.templates.len() < initial_len
+    }
+    
+    /// The active pastebook alongside the search index, for edits that must
+    /// keep the two in step
+    fn active_pastebook_and_index(&mut self) -> Option<(&mut Pastebook, &mut SearchIndex)> {
+        let id = self.active_pastebook_id.clone()?;
+        self.pastebook_and_index(&id)
+    }
+    
+    fn pastebook_and_index(&mut self, id: &str) -> Option<(&mut Pastebook, &mut SearchIndex)> {
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == id)?;
+        Some((pastebook, &mut self.search_index))
+    }
+    
+    /// Position of the pastebook holding clip `id`: `hint` is looked in
+    /// first, then the active pastebook, then the rest
+    fn pastebook_holding(&self, id: &str, hint: Option<&str>) -> Option<usize> {
+        let holds = |i: &usize| self.pastebooks[*i].clips.iter().any(|c| c.id == id);
+        let position = |book: Option<&str>| book.and_then(|b| self.pastebooks.iter().position(|p| p.id == b));
+        position(hint)
+            .filter(holds)
+            .or_else(|| position(self.active_pastebook_id.as_deref()).filter(holds))
+            .or_else(|| position(self.books_loaded_first().find(|p| p.clips.iter().any(|c| c.id == id)).map(|p| p.id.as_str())))
+    }
+    
+    /// Clip `id` from whichever pastebook holds it, with that pastebook's id
+    /// and the search index. Counts as an edit of the clip.
+    fn clip_anywhere_mut(&mut self, id: &str, hint: Option<&str>) -> Option<(String, &mut ClipObject, &mut SearchIndex)> {
+        let position = self.pastebook_holding(id, hint)?;
+        let pastebook = &mut self.pastebooks[position];
+        let clip = pastebook.clips.iter_mut().find(|c| c.id == id)?;
+        clip.edited_at = Some(Utc::now());
+        Some((pastebook.id.clone(), clip, &mut self.search_index))
+    }
+    
+    /// Get list of all pastebooks (id, name)
+    pub fn list_pastebooks(&self) -> Vec<PastebookSummary> {
+        self.pastebooks.iter().map(Pastebook::summary).collect()
+    }
+    
+    /// The user has looked at a pastebook: nothing in it is new any more.
+    /// Other pastebooks keep their counts. Returns its summary and whether
+    /// the mark moved, which it only does if something new was waiting or
+    /// it had none; None if there's no such pastebook.
+    pub fn mark_pastebook_viewed(&mut self, id: &str, at: DateTime<Utc>) -> Option<(PastebookSummary, bool)> {
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == id)?;
+        let changed = pastebook.last_viewed_at.is_none() || pastebook.new_clip_count() > 0;
+        if changed {
+            pastebook.last_viewed_at = Some(at);
+            if let Some(book) = pastebook.book.as_mut() {
+                book.new_clip_count = 0;
+            }
+        }
+        Some((pastebook.summary(), changed))
+    }
+    
+    /// New clips across every pastebook, for the badge and tray tooltip
+    pub fn new_clip_total(&self) -> usize {
+        self.pastebooks.iter().map(Pastebook::new_clip_count).sum()
+    }
+    
+    // ==================== CLIP OPERATIONS ====================
+    
+    /// Add a clip to the active pastebook
+    pub fn add_clip(&mut self, clip: ClipObject) -> Result<(), String> {
+        let (pastebook, index) = self
+            .active_pastebook_and_index()
+            .ok_or_else(|| NO_ACTIVE_PASTEBOOK.to_string())?;
+        index.insert(&clip);
+        pastebook.insert_top(clip);
+        Ok(())
+    }
+    
+    /// Point a missing or stale `active_pastebook_id` at the first pastebook,
+    /// creating the default one if there are none. Returns what was selected,
+    /// or None if the active pastebook was fine.
+    pub fn repair_active_pastebook(&mut self) -> Option<AutoSelected> {
+        if self.get_active_pastebook().is_some() {
+            return None;
+        }
+        let created = self.pastebooks.is_empty();
+        if created {
+            self.pastebooks.push(Pastebook::new(DEFAULT_PASTEBOOK_NAME.to_string()));
+        }
+        let pastebook = &self.pastebooks[0];
+        self.active_pastebook_id = Some(pastebook.id.clone());
+        Some(AutoSelected {
+            id: pastebook.id.clone(),
+            name: pastebook.name.clone(),
+            created,
+        })
+    }
+    
+    /// Add a captured clip, applying the ingest transforms, the capture rules
+    /// and then the dedup settings. It goes to the active pastebook unless a rule routes it.
+    pub fn add_captured_clip(&mut self, mut clip: ClipObject) -> CaptureOutcome {
+        if self.settings.terminal_cleanup {
+            terminal::clean_clip(&mut clip, self.settings.strip_terminal_prompts);
+        } else if clip.content.contains('\u{1b}') {
+            // Cleanup is off, but raw escape codes still don't belong in content
+            clip.content = terminal::strip_ansi(&clip.content);
+        }
+        let transformed = ingest::apply_transforms(&clip.content, &self.settings.ingest_transforms);
+        if transformed != clip.content && !transformed.trim().is_empty() {
+            let raw = std::mem::replace(&mut clip.content, transformed);
+            // Terminal cleanup may already have kept the rawer text
+            clip.original_content.get_or_insert(raw);
+        }
+        let outcome = self.rule_set.apply(&mut clip);
+        if let Some(rule) = outcome.skipped_by {
+            return CaptureOutcome::Skipped(rule);
+        }
+        // A route to a pastebook deleted since the rule was saved falls back
+        // to the active one. A title route's new pastebook is only created
+        // once the clip is actually added to it.
+        let mut new_pastebook = None;
+        let mut title_routed = None;
+        let target = match outcome.pastebook_name.map(|name| self.title_target(&name)) {
+            Some(TitleTarget::Existing(id)) => Some(id),
+            Some(TitleTarget::New(name)) => {
+                new_pastebook = Some(name);
+                None
+            }
+            Some(TitleTarget::Fallback(routed)) => {
+                title_routed = routed;
+                None
+            }
+            None => outcome.pastebook_id.filter(|id| self.pastebooks.iter().any(|p| &p.id == id)),
+        };
+        let target = match new_pastebook {
+            Some(_) => None,
+            None => target.or_else(|| self.active_pastebook_id.clone()),
+        };
+        
+        // Tagged with the open session now; the session itself only hears
+        // about the capture once it's stored
+        let captured_at = clip.metadata.timestamp;
+        if let Some(session) = self.session_for(captured_at) {
+            clip.metadata.session_id = Some(session.id.clone());
+            clip.metadata.session_label = session.label.clone();
+        }
+        
+        let window_ms = self.settings.dedup_window_ms as i64;
+        let action = self.settings.dedup_action;
+        
+        if window_ms > 0 && action != DedupAction::AlwaysAdd {
+            if let Some(pastebook) = self.pastebooks.iter_mut().find(|p| Some(&p.id) == target.as_ref()) {
+                let window = chrono::Duration::milliseconds(window_ms);
+                let captured = (clip.metadata.timestamp, clip.captured_instant);
+                let duplicate = pastebook.clips.iter().position(|c| {
+                    c.content == clip.content
+                        && clock::within((c.metadata.timestamp, c.captured_instant), captured, window)
+                });
+                
+                if let Some(index) = duplicate {
+                    if action == DedupAction::Ignore {
+                        return CaptureOutcome::Ignored;
+                    }
+                    
+                    let existing = pastebook.bump(index, |existing| {
+                        existing.metadata.timestamp = clip.metadata.timestamp;
+                        existing.captured_instant = clip.captured_instant;
+                    });
+                    self.note_session_capture(captured_at);
+                    return CaptureOutcome::Bumped(existing);
+                }
+            }
+        }
+        
+        if let Some((book, index)) = self.latest_with_content(&clip.content) {
+            if self.settings.duplicate_elsewhere == DuplicateElsewhere::Reuse {
+                let pastebook = &mut self.pastebooks[book];
+                let existing = pastebook.bump(index, |existing| {
+                    existing.metadata.timestamp = clip.metadata.timestamp;
+                    existing.captured_instant = clip.captured_instant;
+                });
+                let switched = self.active_pastebook_id.as_deref() != Some(pastebook.id.as_str());
+                let switched_to = switched.then(|| pastebook.name.clone());
+                self.active_pastebook_id = Some(pastebook.id.clone());
+                self.note_session_capture(captured_at);
+                return CaptureOutcome::Reused(existing, switched_to);
+            }
+            clip.metadata.duplicate_of = Some(self.pastebooks[book].clips[index].id.clone());
+        }
+        
+        let target = match new_pastebook {
+            Some(name) => Some(self.create_title_pastebook(name)),
+            None => {
+                self.title_routed = title_routed;
+                target
+            }
+        };
+        let added = target.is_some_and(|target| self.add_capture_to_pastebook(&target, clip.clone()));
+        if !added {
+            return CaptureOutcome::NoPastebook(clip);
+        }
+        self.note_session_capture(captured_at);
+        CaptureOutcome::Added(clip)
+    }
+    
+    /// Where a title route to `name` goes, without creating anything
+    fn title_target(&self, name: &str) -> TitleTarget {
+        if let Some(existing) = self.find_pastebook_by_name(name) {
+            return TitleTarget::Existing(existing.id.clone());
+        }
+        let Ok(name) = self.validate_pastebook_name(name, None) else {
+            return TitleTarget::Fallback(None);
+        };
+        let limit = self.settings.max_auto_pastebooks;
+        if self.pastebooks.iter().filter(|p| p.auto_created).count() >= limit {
+            return TitleTarget::Fallback(Some(TitleRouted::LimitReached { name, limit }));
+        }
+        TitleTarget::New(name)
+    }
+    
+    /// Create the pastebook a title route named, returning its id
+    fn create_title_pastebook(&mut self, name: String) -> String {
+        let mut pastebook = Pastebook::new(name);
+        pastebook.auto_created = true;
+        let id = pastebook.id.clone();
+        self.title_routed = Some(TitleRouted::Created { id: id.clone(), name: pastebook.name.clone() });
+        self.pastebooks.push(pastebook);
+        id
+    }
+    
+    /// What the last capture's title route did about a missing pastebook,
+    /// once
+    pub fn take_title_routed(&mut self) -> Option<TitleRouted> {
+        self.title_routed.take()
+    }
+    
+    /// Pastebook and clip positions of the newest clip in any pastebook read
+    /// so far whose content is exactly `content`; a capture doesn't read
+    /// every book file. Comparing strings checks their length first, so
+    /// this costs little more than walking the clips.
+    fn latest_with_content(&self, content: &str) -> Option<(usize, usize)> {
+        self.pastebooks
+            .iter()
+            .enumerate()
+            .filter_map(|(book, p)| Some((book, p.clips.get_loaded()?)))
+            .flat_map(|(book, clips)| clips.iter().enumerate().map(move |(index, c)| (book, index, c)))
+            .filter(|(_, _, c)| c.content == content)
+            .max_by_key(|(_, _, c)| c.metadata.timestamp)
+            .map(|(book, index, _)| (book, index))
+    }
+    
+    /// Where the clip `clip` was captured as a duplicate of lives now, if
+    /// it's still around
+    pub fn duplicate_match(&self, clip: &ClipObject) -> Option<DuplicateMatch> {
+        let id = clip.metadata.duplicate_of.as_deref()?;
+        let pastebook = &self.pastebooks[self.pastebook_holding(id, None)?];
+        let earlier = pastebook.clips.iter().find(|c| c.id == id)?;
+        Some(DuplicateMatch {
+            clip_id: earlier.id.clone(),
+            pastebook_id: pastebook.id.clone(),
+            pastebook_name: pastebook.name.clone(),
+            captured_at: earlier.metadata.timestamp,
+        })
+    }
+    
+    /// Make the pastebook holding clip `id` the active one, returning its id
+    pub fn reveal_clip(&mut self, id: &str) -> Option<String> {
+        let pastebook_id = self.pastebooks[self.pastebook_holding(id, None)?].id.clone();
+        self.active_pastebook_id = Some(pastebook_id.clone());
+        Some(pastebook_id)
+    }
+    
+    /// Validate a rule and put it at the end of the list
+    pub fn add_rule(&mut self, rule: CaptureRule) -> Result<CaptureRule, String> {
+        if self.rules.len() >= rules::MAX_RULES {
+            return Err(format!("At most {} rules are allowed", rules::MAX_RULES));
+        }
+        let mut rule = self.validate_rule(rule)?;
+        rule.id = Uuid::new_v4().to_string();
+        self.rules.push(rule.clone());
+        self.rule_set = RuleSet::new(&self.rules);
+        Ok(rule)
+    }
+    
+    /// Replace a rule, keeping its id and position
+    pub fn update_rule(&mut self, id: &str, rule: CaptureRule) -> Result<CaptureRule, String> {
+        let mut rule = self.validate_rule(rule)?;
+        let existing = self
+            .rules
+            .iter_mut()
+            .find(|r| r.id == id)
+            .ok_or_else(|| format!("NotFound: rule {}", id))?;
+        rule.id = existing.id.clone();
+        *existing = rule.clone();
+        self.rule_set = RuleSet::new(&self.rules);
+        Ok(rule)
+    }
+    
+    pub fn delete_rule(&mut self, id: &str) -> bool {
+        let before = self.rules.len();
+        self.rules.retain(|r| r.id != id);
+        self.rule_set = RuleSet::new(&self.rules);
+        self.rules.len() != before
+    }
+    
+    fn validate_rule(&self, rule: CaptureRule) -> Result<CaptureRule, String> {
+        let rule = rules::validate(rule)?;
+        if let Some(id) = &rule.actions.pastebook_id {
+            if !self.pastebooks.iter().any(|p| &p.id == id) {
+                return Err("Pastebook not found".to_string());
+            }
+        }
+        Ok(rule)
+    }
+    
+    /// Validate a preset and put it at the end of the list
+    pub fn add_preset(&mut self, preset: PromptPreset) -> Result<PromptPreset, String> {
+        if self.prompt_presets.len() >= presets::MAX_PRESETS {
+            return Err(format!("At most {} presets are allowed", presets::MAX_PRESETS));
+        }
+        let mut preset = self.validate_preset(preset, None)?;
+        preset.id = Uuid::new_v4().to_string();
+        self.prompt_presets.push(preset.clone());
+        Ok(preset)
+    }
+    
+    /// Replace a preset, keeping its id and position
+    pub fn update_preset(&mut self, id: &str, preset: PromptPreset) -> Result<PromptPreset, String> {
+        let mut preset = self.validate_preset(preset, Some(id))?;
+        let existing = self
+            .prompt_presets
+            .iter_mut()
+            .find(|p| p.id == id)
+            .ok_or_else(|| format!("NotFound: preset {}", id))?;
+        preset.id = existing.id.clone();
+        *existing = preset.clone();
+        Ok(preset)
+    }
+    
+    pub fn delete_preset(&mut self, id: &str) -> bool {
+        let before = self.prompt_presets.len();
+        self.prompt_presets.retain(|p| p.id != id);
+        self.prompt_presets.len() != before
+    }
+    
+    /// Add imported presets; one named like an existing preset replaces it.
+    /// Nothing changes unless all of them fit. Returns how many were imported.
+    pub fn import_presets(&mut self, imported: Vec<PromptPreset>) -> Result<usize, String> {
+        let count = imported.len();
+        let mut merged = self.prompt_presets.clone();
+        for mut preset in imported {
+            match merged.iter_mut().find(|p| p.name.eq_ignore_ascii_case(&preset.name)) {
+                Some(existing) => {
+                    preset.id = existing.id.clone();
+                    *existing = preset;
+                }
+                None => {
+                    preset.id = Uuid::new_v4().to_string();
+                    merged.push(preset);
+                }
+            }
+        }
+        if merged.len() > presets::MAX_PRESETS {
+            return Err(format!("At most {} presets are allowed", presets::MAX_PRESETS));
+        }
+        self.prompt_presets = merged;
+        Ok(count)
+    }
+    
+    fn validate_preset(&self, preset: PromptPreset, except_id: Option<&str>) -> Result<PromptPreset, String> {
+        let preset = presets::validate(preset)?;
+        let taken = self
+            .prompt_presets
+            .iter()
+            .any(|p| Some(p.id.as_str()) != except_id && p.name.eq_ignore_ascii_case(&preset.name));
+        if taken {
+            return Err(format!("AlreadyExists: preset '{}'", preset.name));
+        }
+        Ok(preset)
+    }
+    
+    /// Replace a clip's content (any pastebook) with generated text,
+    /// recording how it was produced, unless it's locked. Plan the edit with
+    /// the operation as its cause.
+    pub fn rewrite_clip(&mut self, id: &str, edit: ContentEdit, provenance: Provenance) -> Result<ClipObject, String> {
+        self.check_unlocked(id)?;
+        let not_found = || format!("NotFound: clip {}", id);
+        let pastebook_id = self.update_clip_content(id, edit, None).ok_or_else(not_found)?;
+        let (_, clip, _) = self.clip_anywhere_mut(id, Some(&pastebook_id)).ok_or_else(not_found)?;
+        clip.provenance = Some(provenance);
+        Ok(clip.clone())
+    }
+    
+    /// The session currently open, if any
+    pub fn active_session_mut(&mut self) -> Option<&mut CaptureSession> {
+        self.sessions.iter_mut().rev().find(|s| s.ended_at.is_none())
+    }
+    
+    /// Start a capture session, ending any open one first
+    pub fn start_session(&mut self, label: Option<String>) -> CaptureSession {
+        self.end_session();
+        let session = CaptureSession {
+            id: Uuid::new_v4().to_string(),
+            label,
+            started_at: Utc::now(),
+            ended_at: None,
+            last_capture_at: None,
+        };
+        self.sessions.push(session.clone());
+        session
+    }
+    
+    /// End the open session, if any
+    pub fn end_session(&mut self) -> Option<CaptureSession> {
+        let session = self.active_session_mut()?;
+        session.ended_at = Some(Utc::now());
+        Some(session.clone())
+    }
+    
+    /// Whether `session` has been idle too long as of `now`
+    fn is_idle(&self, session: &CaptureSession, now: DateTime<Utc>) -> bool {
+        let idle_minutes = self.settings.session_idle_minutes;
+        // A clock set back more than a day ends the session too
+        idle_minutes > 0
+            && !clock::wall_within(session.last_activity(), now, chrono::Duration::minutes(idle_minutes as i64))
+    }
+    
+    /// The open session a capture at `at` belongs to, unless it has gone
+    /// idle by then
+    fn session_for(&self, at: DateTime<Utc>) -> Option<&CaptureSession> {
+        let session = self.sessions.iter().rev().find(|s| s.ended_at.is_none())?;
+        (!self.is_idle(session, at)).then_some(session)
+    }
+    
+    /// Count a stored capture at `at` as activity in the open session,
+    /// closing it first if it had gone idle
+    fn note_session_capture(&mut self, at: DateTime<Utc>) {
+        self.expire_idle_session(at);
+        if let Some(session) = self.active_session_mut() {
+            session.last_capture_at = Some(at);
+        }
+    }
+    
+    /// Close the open session if it has been idle too long as of `now`; it is
+    /// closed at its last activity so idle time (or downtime after a restart)
+    /// doesn't count toward its duration
+    pub fn expire_idle_session(&mut self, now: DateTime<Utc>) -> Option<CaptureSession> {
+        let session = self.sessions.iter().rev().find(|s| s.ended_at.is_none())?;
+        if !self.is_idle(session, now) {
+            return None;
+        }
+        let session = self.active_session_mut()?;
+        session.ended_at = Some(session.last_activity());
+        Some(session.clone())
+    }
+    
+    /// Duration, clip count, per-app breakdown and size of a session
+    pub fn session_report(&self, session_id: &str) -> Option<SessionReport> {
+        let session = self.sessions.iter().find(|s| s.id == session_id)?.clone();
+        
+        let clips: Vec<&ClipObject> = self
+            .pastebooks
+            .iter()
+            .flat_map(|p| p.clips.iter())
+            .filter(|c| c.metadata.session_id.as_deref() == Some(session_id))
+            .collect();
+        
+        let mut per_app: Vec<(String, usize)> = Vec::new();
+        for clip in &clips {
+            match per_app.iter_mut().find(|(app, _)| *app == clip.metadata.source_app) {
+                Some((_, count)) => *count += 1,
+                None => per_app.push((clip.metadata.source_app.clone(), 1)),
+            }
+        }
+        per_app.sort_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.cmp(&b.0)));
+        
+        let end = session.ended_at.unwrap_or_else(Utc::now);
+        Some(SessionReport {
+            duration_secs: end.signed_duration_since(session.started_at).num_seconds(),
+            clip_count: clips.len(),
+            per_app,
+            total_chars: clips.iter().map(|c| textutil::grapheme_count(&c.content)).sum(),
+            session,
+        })
+    }
+    
+    /// Add clips brought in from elsewhere to a pastebook, each placed by its
+    /// timestamp among the newest-first clips. Clips whose content the
+    /// pastebook already holds are skipped. Returns how many were added, or
+    /// None if there's no such pastebook.
+    pub fn import_clips(&mut self, pastebook_id: &str, clips: Vec<ClipObject>) -> Option<usize> {
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == pastebook_id)?;
+        let mut seen: HashSet<String> = pastebook.clips.iter().map(|c| content_hash(&c.content)).collect();
+        // Imports slot in by time among the unpinned clips; pinned ones stay put
+        let (pinned, mut rest) = split_pinned(std::mem::take(&mut pastebook.clips));
+        let mut added = 0;
+        for clip in clips {
+            if validate_content(&clip.content, false).is_err() || !seen.insert(content_hash(&clip.content)) {
+                continue;
+            }
+            let index = rest
+                .iter()
+                .position(|c| c.metadata.timestamp < clip.metadata.timestamp)
+                .unwrap_or(rest.len());
+            self.search_index.insert(&clip);
+            rest.insert(index, clip);
+            added += 1;
+        }
+        *pastebook.clips = place_pinned(pinned, rest);
+        Some(added)
+    }
+    
+    /// Add a clip to a specific pastebook
+    pub fn add_clip_to_pastebook(&mut self, pastebook_id: &str, clip: ClipObject) -> bool {
+        if let Some(pastebook) = self.pastebooks.iter_mut().find(|p| p.id == pastebook_id) {
+            self.search_index.insert(&clip);
+            pastebook.insert_top(clip);
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// Add a captured clip to a pastebook, mirroring it if the pastebook has
+    /// a mirror file
+    pub fn add_capture_to_pastebook(&mut self, pastebook_id: &str, clip: ClipObject) -> bool {
+        if let Some(pastebook) = self.pastebooks.iter_mut().find(|p| p.id == pastebook_id) {
+            self.search_index.insert(&clip);
+            pastebook.push_capture(clip);
+            true
+        } else {
+            false
+        }
+    }
+    
+    /// Get clips from active pastebook, leaving out trashed ones
+    pub fn get_clips(&self) -> Vec<ClipObject> {
+        self.get_active_pastebook()
+            .map(|p| p.live_clips().cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    /// Trashed clips in a pastebook, most recently trashed first; None if
+    /// it doesn't exist
+    pub fn trashed_clips(&self, pastebook_id: &str) -> Option<Vec<ClipObject>> {
+        let pastebook = self.pastebooks.iter().find(|p| p.id == pastebook_id)?;
+        let mut trashed: Vec<ClipObject> = pastebook.clips.iter().filter(|c| c.trashed_at.is_some()).cloned().collect();
+        trashed.sort_by_key(|c| std::cmp::Reverse(c.trashed_at));
+        Some(trashed)
+    }
+    
+    /// Counts for one pastebook, trashed clips included; None if it doesn't exist
+    pub fn pastebook_stats(&self, pastebook_id: &str) -> Option<PastebookStats> {
+        self.pastebooks.iter().find(|p| p.id == pastebook_id).map(Pastebook::stats)
+    }
+    
+    /// Number of clips in active pastebook
+    pub fn get_clips_count(&self) -> usize {
+        self.get_active_pastebook().map_or(0, Pastebook::live_clip_count)
+    }
+    
+    /// Get a clip from active pastebook
+    pub fn get_clip(&self, id: &str) -> Option<&ClipObject> {
+        self.get_active_pastebook()
+            .and_then(|p| p.clips.iter().find(|c| c.id == id))
+    }
+    
+    /// Jump list entries for the pinned clips, the active pastebook's first
+    pub fn jump_list_items(&self) -> Vec<JumpItem> {
+        let active = self.get_active_pastebook().into_iter();
+        let others = self
+            .pastebooks
+            .iter()
+            .filter(|p| Some(&p.id) != self.active_pastebook_id.as_ref());
+        jumplist::items_for(active.chain(others).flat_map(|p| p.clips.iter()))
+    }
+    
+    /// Bring the window title, new-clip badge and jump list in line with the
+    /// active pastebook, its counts and the pins. Called by the commands that
+    /// change those; both sides skip state they already have.
+    pub fn note_chrome(&self) {
+        titlebar::note(
+            self.get_active_pastebook()
+                .map(|p| (p.name.as_str(), p.live_clip_count())),
+            self.new_clip_total(),
+        );
+        jumplist::note(self.jump_list_items());
+    }
+    
+    /// Get a clip by id from any pastebook
+    pub fn find_clip(&self, id: &str) -> Option<&ClipObject> {
+        self.books_loaded_first()
+            .flat_map(|p| p.clips.iter())
+            .find(|c| c.id == id)
+    }
+    
+    /// Id of the pastebook holding a clip
+    pub fn pastebook_of_clip(&self, id: &str) -> Option<&str> {
+        self.books_loaded_first()
+            .find(|p| p.clips.iter().any(|c| c.id == id))
+            .map(|p| p.id.as_str())
+    }
+    
+    /// Flag or unflag a clip in any pastebook as sensitive, returning the
+    /// pastebook it's in. Flagging drops its history, so earlier contents
+    /// don't live on in exports and sync.
+    pub fn set_clip_sensitive(&mut self, id: &str, sensitive: bool, hint: Option<&str>) -> Option<String> {
+        let (pastebook_id, clip, _) = self.clip_anywhere_mut(id, hint)?;
+        clip.sensitive = sensitive;
+        if sensitive {
+            clip.history.clear();
+        }
+        Some(pastebook_id)
+    }
+    
+    /// Set, replace or (with None) clear the reminder on a clip in any
+    /// pastebook. The flag is false when the clip already had that reminder.
+    pub fn set_clip_reminder(&mut self, id: &str, at: Option<DateTime<Utc>>) -> Option<(ClipObject, bool)> {
+        let clip = self
+            .pastebooks
+            .iter_mut()
+            .flat_map(|p| p.clips.iter_mut())
+            .find(|c| c.id == id)?;
+        let reminder = at.map(|at| Reminder { at, fired: false });
+        if clip.reminder == reminder {
+            return Some((clip.clone(), false));
+        }
+        clip.reminder = reminder;
+        clip.edited_at = Some(Utc::now());
+        Some((clip.clone(), true))
+    }
+    
+    /// Mark every reminder due at `now` as fired and return those clips
+    pub fn take_due_reminders(&mut self, now: DateTime<Utc>) -> Vec<ClipObject> {
+        let mut due = Vec::new();
+        // An unloaded pastebook is only read once one of its reminders is due
+        let pastebooks = self.pastebooks.iter_mut().filter(|p| {
+            p.unloaded_counts()
+                .is_none_or(|book| book.next_reminder.is_some_and(|at| at <= now))
+        });
+        for clip in pastebooks.flat_map(|p| p.clips.iter_mut()) {
+            if let Some(reminder) = clip.reminder.as_mut() {
+                if !reminder.fired && reminder.at <= now {
+                    reminder.fired = true;
+                    due.push(clip.clone());
+                }
+            }
+        }
+        due
+    }
+    
+    /// Give a clip in any pastebook a title unless it already has one
+    pub fn set_missing_title(&mut self, id: &str, title: String) -> Option<ClipObject> {
+        let clip = self
+            .pastebooks
+            .iter_mut()
+            .flat_map(|p| p.clips.iter_mut())
+            .find(|c| c.id == id && c.title.is_none())?;
+        clip.title = Some(title);
+        clip.edited_at = Some(Utc::now());
+        self.search_index.insert(clip);
+        Some(clip.clone())
+    }
+    
+    /// Delete a clip from whichever pastebook holds it, `hint` being the
+    /// likeliest. Returns that pastebook's id; None if there's no such clip.
+    pub fn delete_clip(&mut self, id: &str, hint: Option<&str>) -> Option<String> {
+        let position = self.pastebook_holding(id, hint)?;
+        let pastebook_id = self.pastebooks[position].id.clone();
+        let undo = self.clips_before(&pastebook_id, |c| c.id == id);
+        self.pastebooks[position].clips.retain(|c| c.id != id);
+        self.search_index.remove(id);
+        self.push_clips_undo("Delete clip".to_string(), undo);
+        Some(pastebook_id)
+    }
+    
+    /// Update a clip's content in any pastebook, as a manual edit, returning
+    /// the pastebook it's in. Diffs under the caller's lock; commands plan
+    /// the edit on a copy instead.
+    #[cfg(test)]
+    pub fn update_clip(&mut self, id: &str, content: String, hint: Option<&str>) -> Option<String> {
+        let edit = self.find_clip(id)?.plan_content(content, "manual", Utc::now());
+        self.update_clip_content(id, edit, hint)
+    }
+    
+    /// Override a clip's source app, window title and/or capture time (None
+    /// keeps each), in any pastebook. Locked clips refuse, and the time can't
+    /// be more than a minute past `now`.
+    pub fn update_clip_metadata(
+        &mut self,
+        id: &str,
+        source_app: Option<String>,
+        window_title: Option<String>,
+        timestamp: Option<DateTime<Utc>>,
+        now: DateTime<Utc>,
+    ) -> Result<ClipObject, String> {
+        let source_app = source_app.map(|app| app.trim().to_string());
+        if source_app.as_deref() == Some("") {
+            return Err("Source app is empty".to_string());
+        }
+        if timestamp.is_some_and(|t| t > now + chrono::Duration::minutes(1)) {
+            return Err("Timestamp is in the future".to_string());
+        }
+        
+        let clip = self
+            .pastebooks
+            .iter_mut()
+            .flat_map(|p| p.clips.iter_mut())
+            .find(|c| c.id == id)
+            .ok_or_else(|| format!("NotFound: clip {}", id))?;
+        if clip.locked {
+            return Err(locked_error(id));
+        }
+        let mut metadata = clip.metadata.clone();
+        if let Some(source_app) = source_app {
+            metadata.source_app = source_app;
+        }
+        if let Some(window_title) = window_title {
+            metadata.window_title = window_title;
+        }
+        if let Some(timestamp) = timestamp {
+            metadata.timestamp = timestamp;
+        }
+        if clip.set_metadata(&metadata, "metadata", now) {
+            clip.edited_at = Some(now);
+            self.search_index.insert(clip);
+        }
+        Ok(clip.clone())
+    }
+    
+    /// Refuse to change a locked clip's content or metadata
+    fn check_unlocked(&self, id: &str) -> Result<(), String> {
+        match self.find_clip(id) {
+            Some(clip) if clip.locked => Err(locked_error(id)),
+            _ => Ok(()),
+        }
+    }
+    
+    /// Apply a planned content edit after checking it; blank content needs
+    /// `allow_empty` and locked clips refuse. The pastebook it's in, or None
+    /// if there's no such clip.
+    pub fn edit_clip_content(
+        &mut self,
+        id: &str,
+        edit: ContentEdit,
+        allow_empty: bool,
+        hint: Option<&str>,
+    ) -> Result<Option<String>, String> {
+        validate_content(&edit.content, allow_empty)?;
+        self.check_unlocked(id)?;
+        Ok(self.update_clip_content(id, edit, hint))
+    }
+    
+    /// Replace a clip's content (any pastebook), keeping the old content in
+    /// its history under the edit's cause
+    fn update_clip_content(&mut self, id: &str, edit: ContentEdit, hint: Option<&str>) -> Option<String> {
+        let (pastebook_id, clip, index) = self.clip_anywhere_mut(id, hint)?;
+        clip.apply_content(edit);
+        clip.content_ref = None;
+        index.insert(clip);
+        Some(pastebook_id)
+    }
+    
+    /// A clip's earlier contents, newest first
+    pub fn clip_revisions(&self, id: &str) -> Result<Vec<ClipRevisionView>, String> {
+        self.find_clip(id)
+            .ok_or_else(|| format!("NotFound: clip {}", id))?
+            .revisions()
+    }
+    
+    /// Put back a clip's content (any pastebook) with an edit from
+    /// `ClipObject::plan_revert`, unless it's locked
+    pub fn revert_clip(&mut self, id: &str, edit: ContentEdit) -> Result<ClipObject, String> {
+        self.check_unlocked(id)?;
+        if self.update_clip_content(id, edit, None).is_none() {
+            return Err(format!("NotFound: clip {}", id));
+        }
+        self.find_clip(id).cloned().ok_or_else(|| format!("NotFound: clip {}", id))
+    }
+    
+    /// Move a clip in any pastebook to the trash at `now`, or back out of
+    /// it, returning the pastebook it's in; locked clips can't be trashed
+    pub fn set_clip_trashed(&mut self, id: &str, trashed: bool, hint: Option<&str>, now: DateTime<Utc>) -> Result<Option<String>, String> {
+        if trashed {
+            self.check_unlocked(id)?;
+        }
+        let Some((pastebook_id, clip, _)) = self.clip_anywhere_mut(id, hint) else {
+            return Err(format!("NotFound: clip {}", id));
+        };
+        if clip.trashed_at.is_some() == trashed {
+            return Ok(None);
+        }
+        clip.trashed_at = trashed.then_some(now);
+        Ok(Some(pastebook_id))
+    }
+    
+    /// Drop clips trashed more than `TRASH_RETENTION_DAYS` before `now`,
+    /// returning how many went. Only pastebooks read so far are looked at;
+    /// the rest are purged once they're read.
+    pub fn purge_trash(&mut self, now: DateTime<Utc>) -> usize {
+        let cutoff = now - chrono::Duration::days(TRASH_RETENTION_DAYS);
+        let expired = |c: &ClipObject| c.trashed_at.is_some_and(|at| at <= cutoff);
+        let due: Vec<String> = self
+            .pastebooks
+            .iter()
+            .filter(|p| p.clips.is_loaded() && p.live_clip_count() < p.clips.len() && p.clips.iter().any(expired))
+            .map(|p| p.id.clone())
+            .collect();
+        let mut purged = 0;
+        for id in due {
+            let undo = self.clips_before(&id, expired);
+            let Some((pastebook, index)) = self.pastebook_and_index(&id) else {
+                continue;
+            };
+            let (gone, kept): (Vec<ClipObject>, Vec<ClipObject>) =
+                std::mem::take(&mut *pastebook.clips).into_iter().partition(expired);
+            *pastebook.clips = kept;
+            for clip in &gone {
+                index.remove(&clip.id);
+            }
+            purged += gone.len();
+            let label = format!("Purge trash in '{}'", pastebook.name);
+            self.push_clips_undo(label, undo);
+        }
+        purged
+    }
+    
+    /// Set or clear the color label of a clip in any pastebook, returning
+    /// the pastebook it's in
+    pub fn set_clip_label(&mut self, id: &str, label: Option<String>, hint: Option<&str>) -> Option<String> {
+        let (pastebook_id, clip, _) = self.clip_anywhere_mut(id, hint)?;
+        clip.label = label;
+        Some(pastebook_id)
+    }
+    
+    /// Set or clear the color label on several clips, returning how many were found
+    pub fn set_label_for(&mut self, ids: &[String], label: Option<String>) -> usize {
+        let Some(pastebook) = self.get_active_pastebook_mut() else {
+            return 0;
+        };
+        
+        let mut updated = 0;
+        for clip in pastebook.clips.iter_mut().filter(|c| ids.contains(&c.id)) {
+            clip.label = label.clone();
+            clip.edited_at = Some(Utc::now());
+            updated += 1;
+        }
+        updated
+    }
+    
+    /// Apply a validated patch to each of `ids` in the active pastebook,
+    /// skipping locked clips unless the patch unlocks them
+    pub fn bulk_update_clips(&mut self, ids: &[String], patch: &ClipPatch) -> Vec<BulkUpdateResult> {
+        let undo = match self.active_pastebook_id.clone() {
+            Some(id) => self.clips_before(&id, |c| ids.contains(&c.id)),
+            None => None,
+        };
+        let mut clips = self.get_active_pastebook_mut().map(|p| &mut p.clips);
+        let results: Vec<BulkUpdateResult> = ids
+            .iter()
+            .map(|id| {
+                let clip = clips
+                    .as_deref_mut()
+                    .and_then(|clips| clips.iter_mut().find(|c| &c.id == id));
+                let outcome = match clip {
+                    Some(clip) if clip.locked && patch.locked != Some(false) => BulkOutcome::Locked,
+                    Some(clip) => {
+                        patch.apply(clip);
+                        BulkOutcome::Updated
+                    }
+                    None => BulkOutcome::NotFound,
+                };
+                BulkUpdateResult { id: id.clone(), outcome }
+            })
+            .collect();
+        let updated = results.iter().filter(|r| r.outcome == BulkOutcome::Updated).count();
+        if updated > 0 {
+            self.push_clips_undo(format!("Update {} clip(s)", updated), undo);
+        }
+        results
+    }
+    
+    /// Reference a stored asset from a clip in any pastebook, returning the
+    /// pastebook it's in
+    pub fn attach_asset(&mut self, id: &str, hash: String, hint: Option<&str>) -> Option<String> {
+        let (pastebook_id, clip, _) = self.clip_anywhere_mut(id, hint)?;
+        if !clip.assets.contains(&hash) {
+            clip.assets.push(hash);
+        }
+        Some(pastebook_id)
+    }
+    
+    /// Asset hashes referenced by any clip in any pastebook, or one the undo
+    /// stack could put back
+    pub fn referenced_assets(&self) -> HashSet<String> {
+        self.pastebooks
+            .iter()
+            .flat_map(|p| p.clips.iter())
+            .chain(self.undo.clips())
+            .flat_map(|c| c.assets.iter().chain(&c.content_ref).cloned())
+            .collect()
+    }
+    
+    /// Clips captured on a calendar day in `tz`, in 24 hourly buckets (all
+    /// present even when empty). Each timestamp is converted on its own, so
+    /// days with a DST change get their 23 or 25 hours right; the repeated
+    /// hour of a fall-back day shares one bucket. None searches every pastebook.
+    pub fn timeline<Tz: TimeZone>(
+        &self,
+        tz: &Tz,
+        date: NaiveDate,
+        pastebook_id: Option<&str>,
+    ) -> Result<Vec<TimelineHour>, String> {
+        let pastebooks: Vec<&Pastebook> = match pastebook_id {
+            Some(id) => vec![self
+                .pastebooks
+                .iter()
+                .find(|p| p.id == id)
+                .ok_or("Pastebook not found")?],
+            None => self.pastebooks.iter().collect(),
+        };
+        
+        let mut hours: Vec<TimelineHour> = (0..24)
+            .map(|hour| TimelineHour { hour, entries: Vec::new() })
+            .collect();
+        for pastebook in pastebooks {
+            for clip in &pastebook.clips {
+                let local = clip.metadata.timestamp.with_timezone(tz);
+                if local.date_naive() == date {
+                    hours[local.hour() as usize]
+                        .entries
+                        .push(TimelineEntry::new(clip, &pastebook.id));
+                }
+            }
+        }
+        for hour in &mut hours {
+            hour.entries.sort_by_key(|entry| entry.timestamp);
+        }
+        Ok(hours)
+    }
+    
+    /// Get clips with the given label (None returns unlabeled clips)
+    pub fn get_clips_by_label(&self, label: Option<&str>) -> Vec<ClipObject> {
+        self.get_active_pastebook()
+            .map(|p| {
+                p.clips
+                    .iter()
+                    .filter(|c| c.label.as_deref() == label)
+                    .cloned()
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    
+    // ==================== ORDERING ====================
+    // Every reorder goes through `apply_order`. Pinned clips keep their
+    // positions (indexes in the pastebook) through reorders and merges, and
+    // only unpinned clips move around them. New captures still arrive at the
+    // top and push everything down.
+    
+    /// Reorder the active pastebook's unpinned clips to follow `ids`, leaving
+    /// pinned clips where they are. Clips not listed follow the listed ones
+    /// in their current order. Returns the resulting order.
+    pub fn apply_order(&mut self, ids: &[String]) -> Vec<String> {
+        let Some(id) = self.active_pastebook_id.clone() else {
+            return Vec::new();
+        };
+        self.apply_pastebook_order(&id, ids).unwrap_or_default()
+    }
+    
+    /// Like `apply_order`, in any pastebook; None if it doesn't exist
+    pub fn apply_pastebook_order(&mut self, pastebook_id: &str, ids: &[String]) -> Option<Vec<String>> {
+        let undo = self.clips_before(pastebook_id, |_| false);
+        let pastebook = self.pastebooks.iter_mut().find(|p| p.id == pastebook_id)?;
+        let (pinned, mut rest) = split_pinned(std::mem::take(&mut pastebook.clips));
+        
+        let mut ordered = Vec::with_capacity(rest.len());
+        for id in ids {
+            // Taking the clip out also drops repeated ids
+            if let Some(index) = rest.iter().position(|c| &c.id == id) {
+                ordered.push(rest.remove(index));
+            }
+        }
+        ordered.extend(rest);
+        
+        *pastebook.clips = place_pinned(pinned, ordered);
+        let order: Vec<String> = pastebook.clips.iter().map(|c| c.id.clone()).collect();
+        let label = format!("Reorder pastebook '{}'", pastebook.name);
+        self.push_reorder_undo(label, undo, &order);
+        Some(order)
+    }
+    
+    /// Reorder just the clips in `ids` among the slots they already hold,
+    /// following `ids`; every other clip, and any pinned one, stays where it
+    /// is. Returns the resulting order.
+    pub fn apply_scoped_order(&mut self, ids: &[String]) -> Vec<String> {
+        let undo = match self.active_pastebook_id.clone() {
+            Some(id) => self.clips_before(&id, |_| false),
+            None => None,
+        };
+        let Some(pastebook) = self.get_active_pastebook_mut() else {
+            return Vec::new();
+        };
+        let slots: Vec<usize> = (0..pastebook.clips.len())
+            .filter(|&i| !pastebook.clips[i].pinned && ids.contains(&pastebook.clips[i].id))
+            .collect();
+        let mut moved: Vec<ClipObject> = slots.iter().map(|&i| pastebook.clips[i].clone()).collect();
+        moved.sort_by_key(|c| ids.iter().position(|id| id == &c.id));
+        for (slot, clip) in slots.into_iter().zip(moved) {
+            pastebook.clips[slot] = clip;
+        }
+        let order: Vec<String> = pastebook.clips.iter().map(|c| c.id.clone()).collect();
+        let label = format!("Reorder pastebook '{}'", pastebook.name);
+        self.push_reorder_undo(label, undo, &order);
+        order
+    }
+    
+    /// `push_clips_undo` for a reorder that left the clips in `order`, if
+    /// that's a new order
+    fn push_reorder_undo(&mut self, label: String, undo: Option<PastebookUndo>, order: &[String]) {
+        if let Some(PastebookUndo::Clips { order: old, .. }) = &undo {
+            if old != order {
+                self.push_clips_undo(label, undo);
+            }
+        }
+    }
+    
+    /// Reorder clips (see `apply_order`)
+    pub fn reorder_clips(&mut self, ids: Vec<String>) -> Vec<String> {
+        self.apply_order(&ids)
+    }
+    
+    /// Sort the active pastebook's unpinned clips
+    #[cfg(test)]
+    pub fn sort_clips(&mut self, by: SortOrder) -> Vec<String> {
+        let Some(id) = self.active_pastebook_id.clone() else {
+            return Vec::new();
+        };
+        self.sort_pastebook_clips(&id, by).unwrap_or_default()
+    }
+    
+    /// Like `sort_clips`, in any pastebook; None if it doesn't exist
+    pub fn sort_pastebook_clips(&mut self, pastebook_id: &str, by: SortOrder) -> Option<Vec<String>> {
+        let pastebook = self.pastebooks.iter().find(|p| p.id == pastebook_id)?;
+        let mut clips: Vec<&ClipObject> = pastebook.clips.iter().collect();
+        match by {
+            SortOrder::Newest => clips.sort_by_key(|c| std::cmp::Reverse(c.metadata.timestamp)),
+            SortOrder::Oldest => clips.sort_by_key(|c| c.metadata.timestamp),
+            SortOrder::SourceApp => clips.sort_by_key(|c| c.metadata.source_app.to_lowercase()),
+            SortOrder::Title => clips.sort_by_key(|c| c.title.as_deref().unwrap_or("").to_lowercase()),
+        }
+        let ids: Vec<String> = clips.iter().map(|c| c.id.clone()).collect();
+        self.apply_pastebook_order(pastebook_id, &ids)
+    }
+    
+    /// The clips an AI action works on: the active pastebook's clips in
+    /// `ids`, in pastebook order, or all of them without a scope. Ids not in
+    /// the active pastebook are rejected, all named in the error.
+    pub fn scoped_clips(&self, ids: Option<&[String]>) -> Result<Vec<&ClipObject>, String> {
+        let pastebook = self.get_active_pastebook().ok_or_else(|| NO_ACTIVE_PASTEBOOK.to_string())?;
+        let Some(ids) = ids else {
+            return Ok(pastebook.clips.iter().collect());
+        };
+        let missing: Vec<&str> = ids
+            .iter()
+            .filter(|id| !pastebook.clips.iter().any(|c| &c.id == *id))
+            .map(String::as_str)
+            .collect();
+        if !missing.is_empty() {
+            return Err(format!("Validation: not in the active pastebook: {}", missing.join(", ")));
+        }
+        Ok(pastebook.clips.iter().filter(|c| ids.contains(&c.id)).collect())
+    }
+    
+    /// The clips `ids` name in the active pastebook, with their positions
+    /// there, in the order given
+    fn merge_sources(&self, ids: &[String]) -> Option<Vec<(usize, &ClipObject)>> {
+        let pastebook = self.get_active_pastebook()?;
+        Some(
+            ids.iter()
+                .filter_map(|id| pastebook.clips.iter().enumerate().find(|(_, c)| &c.id == id))
+                .collect(),
+        )
+    }
+    
+    /// Ids of the clips merging `ids` would take from, top first, so the UI
+    /// can find their cards (in whatever order it shows them) to animate
+    pub fn merge_source_ids(&self, ids: &[String]) -> Vec<String> {
+        let mut sources = self.merge_sources(ids).unwrap_or_default();
+        sources.sort_unstable_by_key(|(position, _)| *position);
+        sources.dedup_by_key(|(position, _)| *position);
+        sources.into_iter().map(|(_, clip)| clip.id.clone()).collect()
+    }
+    
+    /// Build the clip that merging `ids` would produce, without touching storage
+    pub fn build_merged_clip(&self, ids: &[String], options: &MergeOptions) -> Option<ClipObject> {
+        if ids.len() < 2 {
+            return None;
+        }
+        
+        let mut sources = self.merge_sources(ids)?;
+        
+        // Merging a single clip (the other ids unknown) would only duplicate it
+        if sources.len() < 2 {
+            return None;
+        }
+        
+        match options.order {
+            MergeOrder::Selection => {}
+            MergeOrder::Display => sources.sort_by_key(|(position, _)| *position),
+            MergeOrder::Chronological => sources.sort_by_key(|(_, c)| c.metadata.timestamp),
+        }
+        let sources: Vec<&ClipObject> = sources.into_iter().map(|(_, c)| c).collect();
+        
+        let separator = options.separator.as_deref().unwrap_or(DEFAULT_MERGE_SEPARATOR);
+        let content = sources
+            .iter()
+            .map(|c| c.content.as_str())
+            .collect::<Vec<_>>()
+            .join(separator);
+        
+        // Carry the sources' assets over so merging never orphans them
+        let mut assets: Vec<String> = Vec::new();
+        for hash in sources.iter().flat_map(|c| c.assets.iter()) {
+            if !assets.contains(hash) {
+                assets.push(hash.clone());
+            }
+        }
+        
+        Some(ClipObject {
+            id: Uuid::new_v4().to_string(),
+            content,
+            metadata: sources[0].metadata.clone(),
+            status: "raw".to_string(),
+            title: None,
+            tags: Vec::new(),
+            label: None,
+            assets,
+            sensitive: sources.iter().any(|c| c.sensitive),
+            provenance: None,
+            reminder: None,
+            pinned: false,
+            locked: false,
+            content_ref: None,
+            original_content: None,
+            history: Vec::new(),
+            edited_at: None,
+            trashed_at: None,
+            captured_instant: None,
+        })
+    }
+    
+    /// Merge multiple clips
+    pub fn merge_clips(&mut self, ids: Vec<String>, options: &MergeOptions) -> Option<ClipObject> {
+        self.load_contents();
+        let new_clip = self.build_merged_clip(&ids, options)?;
+        let undo = self.clips_before(self.active_pastebook_id.clone()?.as_str(), |c| ids.contains(&c.id));
+        let (pastebook, index) = self.active_pastebook_and_index()?;
+        let (mut pinned, mut rest) = split_pinned(std::mem::take(&mut pastebook.clips));
+        
+        // Remove merged clips, except locked ones
+        if !options.keep_sources {
+            let merged_away = |c: &ClipObject| !c.locked && ids.contains(&c.id);
+            for clip in rest.iter().chain(pinned.iter().map(|(_, c)| c)).filter(|c| merged_away(c)) {
+                index.remove(&clip.id);
+            }
+            rest.retain(|c| !merged_away(c));
+            pinned.retain(|(_, c)| !merged_away(c));
+        }
+        
+        rest.insert(0, new_clip.clone());
+        index.insert(&new_clip);
+        *pastebook.clips = place_pinned(pinned, rest);
+        self.push_clips_undo(format!("Merge {} clips", ids.len()), undo);
+        Some(new_clip)
+    }
+    
+    /// Find (and unless `dry_run`, replace) `pattern` in the active pastebook's clips,
+    /// or only in `ids` when given. Regex replacements may use `$1`-style groups.
+    /// Locked clips are left out.
+    pub fn find_replace_clips(
+        &mut self,
+        pattern: &Regex,
+        replacement: &str,
+        expand_groups: bool,
+        ids: Option<&[String]>,
+        dry_run: bool,
+    ) -> FindReplaceResult {
+        let mut result = FindReplaceResult {
+            clips: Vec::new(),
+            changed: 0,
+        };
+        self.load_contents();
+        
+        let Some((pastebook, index)) = self.active_pastebook_and_index() else {
+            return result;
+        };
+        
+        for clip in pastebook.clips.iter_mut() {
+            if clip.locked || ids.is_some_and(|ids| !ids.contains(&clip.id)) {
+                continue;
+            }
+            
+            let matches = pattern.find_iter(&clip.content).count();
+            if matches == 0 {
+                continue;
             }
             
-            pastebook.clips = new_clips;
+            let replace = |text: &str| {
+                if expand_groups {
+                    pattern.replace_all(text, replacement).into_owned()
+                } else {
+                    pattern.replace_all(text, NoExpand(replacement)).into_owned()
+                }
+            };
+            
+            // Preview: the first match with some context on each side
+            let first = pattern.find(&clip.content).unwrap();
+            let start = textutil::floor_boundary(&clip.content, first.start().saturating_sub(FIND_PREVIEW_CONTEXT));
+            let end = textutil::ceil_boundary(&clip.content, first.end() + FIND_PREVIEW_CONTEXT);
+            let preview_before = clip.content[start..end].to_string();
+            let preview_after = replace(&preview_before);
+            
+            result.clips.push(FindReplaceMatch {
+                id: clip.id.clone(),
+                matches,
+                preview_before,
+                preview_after,
+            });
+            
+            let replaced = replace(&clip.content);
+            if !dry_run && replaced != clip.content {
+                let now = Utc::now();
+                clip.set_content(replaced, "find_replace", now);
+                clip.content_ref = None;
+                clip.edited_at = Some(now);
+                index.insert(clip);
+                result.changed += 1;
+            }
+        }
+        
+        result
+    }
+    
+    /// Get all clips as a single string
+    #[cfg(test)]
+    pub fn get_all_content(&self) -> String {
+        self.active_pastebook_id
+            .as_deref()
+            .and_then(|id| self.pastebook_content(id))
+            .unwrap_or_default()
+    }
+    
+    /// Every clip's content in a pastebook as one string; None if it
+    /// doesn't exist
+    pub fn pastebook_content(&self, pastebook_id: &str) -> Option<String> {
+        let pastebook = self.pastebooks.iter().find(|p| p.id == pastebook_id)?;
+        Some(joined_content(pastebook.live_clips()))
+    }
+    
+    /// Clear the active pastebook's clips except locked ones, returning the
+    /// ids removed
+    pub fn clear_clips(&mut self) -> Vec<String> {
+        let Some(id) = self.active_pastebook_id.clone() else {
+            return Vec::new();
+        };
+        self.clear_pastebook_clips(&id).unwrap_or_default()
+    }
+    
+    /// Like `clear_clips`, in any pastebook; None if it doesn't exist
+    pub fn clear_pastebook_clips(&mut self, pastebook_id: &str) -> Option<Vec<String>> {
+        let undo = self.clips_before(pastebook_id, |c| !c.locked);
+        let (pastebook, index) = self.pastebook_and_index(pastebook_id)?;
+        let (locked, removed): (Vec<ClipObject>, Vec<ClipObject>) =
+            std::mem::take(&mut *pastebook.clips).into_iter().partition(|c| c.locked);
+        *pastebook.clips = locked;
+        for clip in &removed {
+            index.remove(&clip.id);
+        }
+        if !removed.is_empty() {
+            let label = format!("Clear pastebook '{}'", pastebook.name);
+            self.push_clips_undo(label, undo);
+        }
+        Some(removed.into_iter().map(|c| c.id).collect())
+    }
+    
+    // ==================== SEARCH ====================
+    
+    /// Re-index every clip in the pastebooks read so far; the others are
+    /// searched by reading them, once they're read
+    pub fn rebuild_search_index(&mut self) -> SearchIndexStats {
+        self.search_index = self.build_search_index();
+        self.search_index.stats()
+    }
+    
+    /// A fresh index of every clip, leaving the current one in place; for
+    /// rebuilding under a read lock and swapping it in with `install_search_index`
+    pub fn build_search_index(&self) -> SearchIndex {
+        let mut index = SearchIndex::default();
+        index.rebuild(self.loaded_clips());
+        index
+    }
+    
+    /// Use an index from `build_search_index` made at `built_at` revision,
+    /// or rebuild here if the clips have changed since
+    pub fn install_search_index(&mut self, index: SearchIndex, built_at: u64) -> SearchIndexStats {
+        if self.revision != built_at {
+            return self.rebuild_search_index();
+        }
+        self.search_index = index;
+        self.search_index.stats()
+    }
+    
+    /// Clips in the active pastebook whose content, title, source app or
+    /// window title match every word of `query` (case-insensitive), most
+    /// relevant first; see `search::Query::score`
+    #[cfg(test)]
+    pub fn search_clips(&self, query: &str) -> Vec<ClipObject> {
+        let Some(id) = self.active_pastebook_id.as_deref() else {
+            return Vec::new();
+        };
+        self.search_pastebook(id, query)
+            .map(|clips| clips.into_iter().cloned().collect())
+            .unwrap_or_default()
+    }
+    
+    /// Like `search_clips`, in any pastebook; None if it doesn't exist
+    pub fn search_pastebook(&self, pastebook_id: &str, query: &str) -> Option<Vec<&ClipObject>> {
+        let pastebook = self.pastebooks.iter().find(|p| p.id == pastebook_id)?;
+        let query = search::Query::new(query);
+        if query.is_empty() {
+            return Some(pastebook.live_clips().collect());
+        }
+        
+        // Every term has to be in a candidate; a None set rules nothing out
+        let candidates: Vec<_> = query.terms().filter_map(|t| self.search_index.candidates(t)).collect();
+        let may_contain = |c: &ClipObject| {
+            // Clips the index hasn't seen can't be ruled out
+            !self.search_index.contains(&c.id) || candidates.iter().all(|ids| ids.contains(c.id.as_str()))
+        };
+        let mut hits: Vec<(u32, usize)> = pastebook
+            .clips
+            .iter()
+            .enumerate()
+            .filter(|(_, c)| c.trashed_at.is_none() && may_contain(c))
+            .filter_map(|(i, c)| Some((query.score(c, false)?, i)))
+            .collect();
+        // Few exact hits: look at the newest of the rest for fuzzy ones too
+        if hits.len() < search::FUZZY_BELOW {
+            let exact: HashSet<usize> = hits.iter().map(|&(_, i)| i).collect();
+            let fuzzy: Vec<(u32, usize)> = pastebook
+                .clips
+                .iter()
+                .enumerate()
+                .take(search::MAX_FUZZY_SCAN)
+                .filter(|(i, c)| c.trashed_at.is_none() && !exact.contains(i))
+                .filter_map(|(i, c)| Some((query.score(c, true)?, i)))
+                .collect();
+            hits.extend(fuzzy);
+        }
+        // Most relevant first, then in pastebook order
+        hits.sort_by(|a, b| b.0.cmp(&a.0).then(a.1.cmp(&b.1)));
+        Some(hits.into_iter().map(|(_, i)| &pastebook.clips[i]).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{clip, clip_after, clip_at, contents, storage_with, TempStorage};
+    use chrono::{Duration, FixedOffset};
+
+    /// A manual edit planned on the stored clip
+    fn edit(storage: &AppStorage, id: &str, content: &str) -> ContentEdit {
+        storage.find_clip(id).unwrap().plan_content(content.to_string(), "manual", Utc::now())
+    }
+
+    // ==================== LOAD / SAVE ====================
+
+    #[test]
+    fn missing_file_loads_default_storage() {
+        let temp = TempStorage::new();
+        assert_eq!(temp.storage.pastebooks.len(), 1);
+        assert_eq!(
+            temp.storage.active_pastebook_id.as_ref(),
+            Some(&temp.storage.pastebooks[0].id)
+        );
+        assert!(!temp.path().exists());
+    }
+
+    /// Startup from book files against the same store in one file, all of
+    /// it parsed up front; run with --release --ignored
+    #[test]
+    #[ignore]
+    fn loading_a_large_store_is_fast() {
+        // 15 pastebooks of 2,000 clips, about 30 MB on disk
+        let mut temp = TempStorage::new();
+        let storage = &mut temp.storage;
+        let base = Utc::now() - Duration::days(30);
+        for book in 0..15 {
+            storage.create_pastebook(format!("Book {}", book)).unwrap();
+            for i in 0..2_000 {
+                let content = format!("clip {} of book {} about topic {} ", i, book, i % 97).repeat(20);
+                storage.add_clip(clip_after(&content, base, i * 1000)).unwrap();
+            }
+        }
+        storage.commit().unwrap();
+        let single = temp.dir.path().join("single");
+        fs::create_dir(&single).unwrap();
+        temp.storage.write_copy(&single.join("pastebooks.json")).unwrap();
+
+        let started = Instant::now();
+        let eager = AppStorage::load_from(single.join("pastebooks.json"));
+        let eager_took = started.elapsed();
+        let started = Instant::now();
+        let lazy = temp.reload();
+        let lazy_took = started.elapsed();
+        assert!(eager.pastebooks.iter().all(|p| p.clips.is_loaded()));
+        assert_eq!(lazy.pastebooks.iter().filter(|p| p.clips.is_loaded()).count(), 1);
+        assert_eq!(lazy.list_pastebooks().iter().map(|p| p.clip_count).sum::<usize>(), 30_000);
+        assert!(lazy_took.as_millis() < 50, "loading took {:?}", lazy_took);
+        assert!(lazy_took * 4 < eager_took, "lazy {:?} vs eager {:?}", lazy_took, eager_took);
+    }
+
+    /// Two pastebooks saved as book files, "Other" with `other` in it and
+    /// the active one with "active"
+    fn store_with_books(other: &[&str]) -> (TempStorage, String) {
+        let mut temp = TempStorage::new();
+        let other_id = temp.storage.create_pastebook("Other".to_string()).unwrap().id;
+        for content in other {
+            temp.storage.add_clip(clip(content)).unwrap();
+        }
+        temp.storage.create_pastebook("Active".to_string()).unwrap();
+        temp.storage.add_clip(clip("active")).unwrap();
+        temp.storage.commit().unwrap();
+        (temp, other_id)
+    }
+
+    fn is_loaded(storage: &AppStorage, id: &str) -> bool {
+        storage.pastebooks.iter().find(|p| p.id == id).unwrap().clips.is_loaded()
+    }
+
+    #[test]
+    fn only_the_active_pastebook_is_read_at_startup() {
+        let (temp, other) = store_with_books(&["first", "second"]);
+        let index = fs::read_to_string(temp.path()).unwrap();
+        assert!(!index.contains("first") && !index.contains("\"clips\""));
+
+        let loaded = temp.reload();
+        assert!(loaded.get_active_pastebook().unwrap().clips.is_loaded());
+        assert!(!is_loaded(&loaded, &other));
+        let summary = loaded.list_pastebooks().into_iter().find(|p| p.id == other).unwrap();
+        assert_eq!(summary.clip_count, 2);
+        assert_eq!(crate::stats::compute(&loaded, &Utc, Utc::now()).total_clips, 3);
+        assert!(!is_loaded(&loaded, &other));
+
+        // First use reads it, under a shared reference
+        let id = temp.storage.pastebooks.iter().find(|p| p.id == other).unwrap().clips[0].id.clone();
+        assert_eq!(loaded.find_clip(&id).unwrap().content, "second");
+        assert!(is_loaded(&loaded, &other));
+    }
+
+    #[test]
+    fn saving_writes_only_the_pastebooks_read() {
+        let (temp, other) = store_with_books(&["on disk"]);
+        let mut loaded = temp.reload();
+        // Changed behind its back: a save that rewrote it would undo this
+        let book = temp.dir.path().join(BOOKS_DIR).join(format!("{}.json", other));
+        fs::write(&book, fs::read_to_string(&book).unwrap().replace("on disk", "left alone")).unwrap();
+        loaded.add_clip(clip("new")).unwrap();
+        loaded.commit().unwrap();
+        assert!(!is_loaded(&loaded, &other));
+
+        let mut reloaded = temp.reload();
+        reloaded.switch_pastebook(other.clone());
+        assert_eq!(contents(&reloaded), vec!["left alone"]);
+        assert_eq!(reloaded.search_clips("alone").len(), 1);
+
+        // A deleted pastebook's file goes with it
+        reloaded.delete_pastebook(&other, true).unwrap();
+        reloaded.commit().unwrap();
+        assert!(!book.exists());
+    }
+
+    #[test]
+    fn a_corrupt_book_file_is_set_aside() {
+        let (temp, other) = store_with_books(&["lost"]);
+        let book = temp.dir.path().join(BOOKS_DIR).join(format!("{}.json", other));
+        fs::write(&book, "not json").unwrap();
+
+        let mut loaded = temp.reload();
+        loaded.switch_pastebook(other.clone());
+        assert!(contents(&loaded).is_empty());
+        loaded.commit().unwrap();
+        let kept = fs::read_dir(temp.dir.path().join(BOOKS_DIR))
+            .unwrap()
+            .flatten()
+            .filter(|e| e.file_name().to_string_lossy().contains("corrupt"))
+            .count();
+        assert_eq!(kept, 1);
+    }
+
+    #[test]
+    fn a_single_file_store_moves_to_book_files() {
+        let (temp, other) = store_with_books(&["old layout"]);
+        let single = temp.dir.path().join("copy.json");
+        temp.storage.write_copy(&single).unwrap();
+        fs::remove_dir_all(temp.dir.path().join(BOOKS_DIR)).unwrap();
+        fs::rename(&single, temp.path()).unwrap();
+
+        let mut loaded = temp.reload();
+        assert!(is_loaded(&loaded, &other) && !loaded.book_files);
+        loaded.commit().unwrap();
+        assert!(loaded.book_files);
+        assert!(!temp.dir.path().join("migration.journal").exists());
+        let reloaded = temp.reload();
+        assert!(!is_loaded(&reloaded, &other));
+        let book = reloaded.pastebooks.iter().find(|p| p.id == other).unwrap();
+        assert_eq!(book.clips[0].content, "old layout");
+    }
+
+    #[test]
+    fn unloaded_pastebooks_are_read_when_a_reminder_is_due() {
+        let (mut temp, other) = store_with_books(&["remind me"]);
+        let now = Utc::now();
+        let id = temp.storage.pastebooks.iter().find(|p| p.id == other).unwrap().clips[0].id.clone();
+        temp.storage.set_clip_reminder(&id, Some(now + Duration::hours(1))).unwrap();
+        temp.storage.commit().unwrap();
+
+        let mut loaded = temp.reload();
+        assert!(loaded.take_due_reminders(now).is_empty());
+        assert!(!is_loaded(&loaded, &other));
+        assert_eq!(loaded.take_due_reminders(now + Duration::hours(2))[0].id, id);
+    }
+
+    #[test]
+    fn search_works_before_the_index_is_built() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("meeting notes")).unwrap();
+        temp.storage.add_clip(clip("grocery list")).unwrap();
+        temp.storage.save().unwrap();
+
+        let mut loaded = temp.reload();
+        assert_eq!(loaded.search_index.stats().clips, 0);
+        assert_eq!(loaded.search_clips("notes").len(), 1);
+        let (index, built_at) = (loaded.build_search_index(), loaded.revision);
+        loaded.install_search_index(index, built_at);
+        assert_eq!(loaded.search_clips("notes")[0].content, "meeting notes");
+    }
+
+    #[test]
+    fn save_and_load_round_trip() {
+        let mut temp = TempStorage::new();
+        let storage = &mut temp.storage;
+        let mut first = clip("first");
+        first.tags = vec!["a".to_string()];
+        first.label = Some("red".to_string());
+        let first_id = first.id.clone();
+        storage.add_clip(first).unwrap();
+        storage.add_clip(clip("second")).unwrap();
+        let other = storage.create_pastebook("Other".to_string()).unwrap();
+        storage.add_clip(clip("elsewhere")).unwrap();
+        storage.settings.dedup_window_ms = 500;
+        storage.commit().unwrap();
+
+        let loaded = temp.reload();
+        assert_eq!(loaded.revision, 1);
+        assert_eq!(loaded.pastebooks.len(), 2);
+        assert_eq!(loaded.active_pastebook_id, Some(other.id));
+        assert_eq!(loaded.settings.dedup_window_ms, 500);
+        assert_eq!(contents(&loaded), vec!["elsewhere"]);
+
+        let first = loaded.find_clip(&first_id).unwrap();
+        assert_eq!(first.tags, vec!["a"]);
+        assert_eq!(first.label.as_deref(), Some("red"));
+        assert_eq!(loaded.pastebooks[0].clips[0].content, "second");
+    }
+
+    #[test]
+    fn reload_rebuilds_search_index() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("needle in a haystack")).unwrap();
+        temp.storage.add_clip(clip("just hay")).unwrap();
+        temp.storage.save().unwrap();
+
+        let loaded = temp.reload();
+        let found = loaded.search_clips("needle");
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].content, "needle in a haystack");
+    }
+
+    #[test]
+    fn workspace_filter_matches_desktop_and_monitor() {
+        let mut metadata = clip("x").metadata;
+        assert!(metadata.in_workspace(None, None));
+        assert!(!metadata.in_workspace(Some("Desktop 1"), None));
+
+        metadata.virtual_desktop = Some("Research".to_string());
+        metadata.monitor = Some("\\\\.\\DISPLAY2".to_string());
+        assert!(metadata.in_workspace(Some("research"), None));
+        assert!(metadata.in_workspace(Some("Research"), Some("\\\\.\\display2")));
+        assert!(!metadata.in_workspace(Some("Research"), Some("\\\\.\\DISPLAY1")));
+
+        // Renamed since: the GUID still finds it
+        metadata.virtual_desktop = Some("Writing".to_string());
+        metadata.virtual_desktop_id = Some("{AA509086-5CA9-4C25-8F95-589D3C07B48A}".to_string());
+        assert!(metadata.in_workspace(Some("{aa509086-5ca9-4c25-8f95-589d3c07b48a}"), None));
+        assert!(!metadata.in_workspace(Some("Research"), None));
+    }
+
+    #[test]
+    fn editor_clips_keep_their_source_context() {
+        let mut temp = TempStorage::new();
+        let window_info = WindowInfo {
+            app_name: "Code.exe".to_string(),
+            window_title: "● main.rs - stack-backend - Visual Studio Code".to_string(),
+            ..Default::default()
+        };
+        let editor = ClipObject::new("fn main() {}".to_string(), window_info);
+        let id = editor.id.clone();
+        temp.storage.add_clip(editor).unwrap();
+        temp.storage.add_clip(clip("plain")).unwrap();
+        temp.storage.save().unwrap();
+
+        let loaded = temp.reload();
+        let context = loaded.find_clip(&id).unwrap().metadata.context.clone();
+        assert_eq!(context, Some(serde_json::json!({ "file": "main.rs", "project": "stack-backend" })));
+        assert_eq!(loaded.get_clips()[0].metadata.context, None);
+    }
+
+    #[test]
+    fn only_captures_are_mirrored() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("notes.md");
+        let (mut storage, _) = storage_with(&[]);
+        let id = storage.active_pastebook_id.clone().unwrap();
+        storage.set_pastebook_mirror(&id, Some(path.clone())).unwrap();
+
+        storage.add_clip(clip("made by stack")).unwrap();
+        assert!(matches!(storage.add_captured_clip(clip("captured")), CaptureOutcome::Added(_)));
+
+        // Appends run in order on a background thread
+        let deadline = Instant::now() + std::time::Duration::from_secs(5);
+        while !fs::read_to_string(&path).is_ok_and(|text| text.contains("captured")) {
+            assert!(Instant::now() < deadline, "capture never mirrored");
+            std::thread::sleep(std::time::Duration::from_millis(10));
+        }
+        assert!(!fs::read_to_string(&path).unwrap().contains("made by stack"));
+    }
+
+    #[test]
+    fn corrupt_file_is_set_aside_and_storage_starts_empty() {
+        let temp = TempStorage::new();
+        fs::write(temp.path(), "{ \"pastebooks\": [ oops").unwrap();
+
+        let mut loaded = temp.reload();
+        assert_eq!(loaded.pastebooks.len(), 1);
+        assert_eq!(loaded.get_clips_count(), 0);
+        assert!(!temp.path().exists());
+
+        let set_aside: Vec<String> = fs::read_dir(temp.dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .filter(|name| name.starts_with("pastebooks.corrupt-"))
+            .collect();
+        assert_eq!(set_aside.len(), 1);
+        let kept = fs::read_to_string(temp.dir.path().join(&set_aside[0])).unwrap();
+        assert_eq!(kept, "{ \"pastebooks\": [ oops");
+
+        // The fresh storage saves to the original file, not over the backup
+        loaded.add_clip(clip("fresh")).unwrap();
+        loaded.save().unwrap();
+        assert_eq!(contents(&temp.reload()), vec!["fresh"]);
+    }
+
+    #[test]
+    fn storage_without_a_path_commits_in_memory() {
+        let mut storage = AppStorage::default();
+        storage.add_clip(clip("scratch")).unwrap();
+        assert_eq!(storage.commit(), Ok(1));
+        assert!(storage.storage_path.is_none());
+    }
+
+    #[test]
+    fn set_storage_path_moves_later_saves() {
+        let mut temp = TempStorage::new();
+        let moved = temp.dir.path().join("moved.json");
+        temp.storage.set_storage_path(moved.clone());
+        temp.storage.add_clip(clip("moved")).unwrap();
+        temp.storage.save().unwrap();
+        assert!(!temp.path().exists());
+        assert_eq!(contents(&AppStorage::load_from(moved)), vec!["moved"]);
+    }
+
+    #[test]
+    fn check_revision_rejects_stale_writes() {
+        let (mut storage, _) = storage_with(&["a"]);
+        assert!(storage.check_revision(None).is_ok());
+        assert!(storage.check_revision(Some(0)).is_ok());
+        storage.commit().unwrap();
+        let err = storage.check_revision(Some(0)).unwrap_err();
+        assert!(err.starts_with("Conflict"), "{}", err);
+        assert!(storage.check_revision(Some(1)).is_ok());
+    }
+
+    #[test]
+    fn large_content_is_written_as_a_preview_only_while_externalizing() {
+        let mut big = clip(&"x".repeat(1000));
+        big.content_ref = Some("abc".to_string());
+
+        let plain = serde_json::to_value(&big).unwrap();
+        assert_eq!(plain["content"].as_str().unwrap().len(), 1000);
+        assert!(plain.get("content_ref").is_none());
+
+        EXTERNALIZE_OVER.set(Some(500));
+        let stored = serde_json::to_value(&big);
+        EXTERNALIZE_OVER.set(None);
+        let stored = stored.unwrap();
+        assert_eq!(stored["content"].as_str().unwrap().len(), EXTERNAL_PREVIEW_CHARS);
+        assert_eq!(stored["content_ref"], "abc");
+    }
+
+    #[test]
+    fn externalized_contents_are_left_until_after_load() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("inline")).unwrap();
+        temp.storage.save().unwrap();
+        assert!(!temp.reload().contents_pending);
+
+        // Its asset is missing, so only the preview can ever be read
+        let mut big = clip("preview");
+        big.content_ref = Some("0".repeat(64));
+        let id = big.id.clone();
+        temp.storage.add_clip(big).unwrap();
+        temp.storage.save().unwrap();
+
+        let mut loaded = temp.reload();
+        assert!(loaded.contents_pending);
+        assert_eq!(loaded.clip_content(&id).as_deref(), Some("preview"));
+        loaded.load_contents();
+        assert!(!loaded.contents_pending);
+        assert_eq!(loaded.get_clip(&id).unwrap().content, "preview");
+
+        // Readers through the gate never see contents still pending
+        let storage = RwLock::new(temp.reload());
+        assert!(!super::loaded(&storage).contents_pending);
+        let storage = RwLock::new(temp.reload());
+        assert!(!loaded_mut(&storage).contents_pending);
+    }
+
+    // ==================== CAPTURE / DEDUP ====================
+
+    #[test]
+    fn dedup_ignore_drops_repeat_within_window() {
+        let mut storage = AppStorage::default();
+        let base = Utc::now();
+        assert!(matches!(
+            storage.add_captured_clip(clip_at("same", base)),
+            CaptureOutcome::Added(_)
+        ));
+        assert!(matches!(
+            storage.add_captured_clip(clip_after("same", base, 500)),
+            CaptureOutcome::Ignored
+        ));
+        assert_eq!(storage.get_clips_count(), 1);
+    }
+
+    #[test]
+    fn dedup_window_expiry_adds_again() {
+        let mut storage = AppStorage::default();
+        let base = Utc::now();
+        storage.add_captured_clip(clip_at("same", base));
+        let late = storage.settings.dedup_window_ms as i64 + 1;
+        assert!(matches!(
+            storage.add_captured_clip(clip_after("same", base, late)),
+            CaptureOutcome::Added(_)
+        ));
+        assert_eq!(storage.get_clips_count(), 2);
+    }
+
+    #[test]
+    fn dedup_survives_a_clock_set_back() {
+        let mut storage = AppStorage::default();
+        let now = Utc::now();
+        // Captured before the clock went back a few minutes: still a duplicate
+        storage.add_captured_clip(clip_at("skewed", now + Duration::minutes(5)));
+        assert!(matches!(storage.add_captured_clip(clip_at("skewed", now)), CaptureOutcome::Ignored));
+        
+        // Captured before the clock went back days: not recent, so not dropped
+        storage.add_captured_clip(clip_at("future", now + Duration::days(3)));
+        assert!(matches!(storage.add_captured_clip(clip_at("future", now)), CaptureOutcome::Added(_)));
+    }
+    
+    #[test]
+    fn dedup_prefers_capture_instants_to_timestamps() {
+        let mut storage = AppStorage::default();
+        let now = Utc::now();
+        let instant = std::time::Instant::now();
+        let mut first = clip_at("same", now);
+        first.captured_instant = Some(instant);
+        storage.add_captured_clip(first);
+        
+        // The clock jumped an hour forward between two captures a moment apart
+        let mut second = clip_at("same", now + Duration::hours(1));
+        second.captured_instant = Some(instant);
+        assert!(matches!(storage.add_captured_clip(second), CaptureOutcome::Ignored));
+    }
+    
+    #[test]
+    fn sessions_end_when_the_clock_jumps_back_days() {
+        let mut storage = AppStorage::default();
+        let now = Utc::now();
+        storage.start_session(None);
+        storage.add_captured_clip(clip_at("late", now + Duration::minutes(10)));
+        assert!(storage.expire_idle_session(now).is_none());
+        
+        storage.active_session_mut().unwrap().last_capture_at = Some(now + Duration::days(2));
+        assert!(storage.expire_idle_session(now).is_some());
+    }
+    
+    #[test]
+    fn only_stored_captures_count_as_session_activity() {
+        let mut storage = AppStorage::default();
+        let base = Utc::now();
+        storage.start_session(Some("Research".to_string()));
+        storage.add_captured_clip(clip_at("same", base));
+        assert_eq!(storage.active_session_mut().unwrap().last_capture_at, Some(base));
+        
+        // A dropped duplicate leaves the session as it was
+        let ignored = storage.add_captured_clip(clip_after("same", base, 500));
+        assert!(matches!(ignored, CaptureOutcome::Ignored));
+        assert_eq!(storage.active_session_mut().unwrap().last_capture_at, Some(base));
+        
+        // So does one that arrives after the session went idle, until it's stored
+        storage.settings.session_idle_minutes = 5;
+        let late = base + Duration::minutes(30);
+        let CaptureOutcome::Added(added) = storage.add_captured_clip(clip_at("later", late)) else {
+            panic!("expected the capture to be stored");
+        };
+        assert_eq!(added.metadata.session_id, None);
+        assert!(storage.active_session_mut().is_none());
+        assert_eq!(storage.sessions[0].ended_at, Some(base));
+    }
+    
+    #[test]
+    fn dedup_bump_moves_existing_clip_to_top() {
+        let mut storage = AppStorage::default();
+        storage.settings.dedup_action = DedupAction::Bump;
+        let base = Utc::now();
+        let first = clip_at("same", base);
+        let first_id = first.id.clone();
+        storage.add_captured_clip(first);
+        storage.add_captured_clip(clip_after("other", base, 100));
+
+        let again = clip_after("same", base, 200);
+        let bumped_at = again.metadata.timestamp;
+        match storage.add_captured_clip(again) {
+            CaptureOutcome::Bumped(clip) => {
+                assert_eq!(clip.id, first_id);
+                assert_eq!(clip.metadata.timestamp, bumped_at);
+            }
+            other => panic!("expected a bump, got {:?}", other),
+        }
+        assert_eq!(contents(&storage), vec!["same", "other"]);
+    }
+
+    #[test]
+    fn dedup_always_add_and_zero_window_keep_duplicates() {
+        let base = Utc::now();
+        for configure in [
+            |s: &mut Settings| s.dedup_action = DedupAction::AlwaysAdd,
+            |s: &mut Settings| s.dedup_window_ms = 0,
+        ] {
+            let mut storage = AppStorage::default();
+            configure(&mut storage.settings);
+            storage.add_captured_clip(clip_at("same", base));
+            storage.add_captured_clip(clip_after("same", base, 10));
+            assert_eq!(storage.get_clips_count(), 2);
+        }
+    }
+
+    #[test]
+    fn captures_already_in_any_pastebook_are_flagged_or_reused() {
+        let mut storage = AppStorage::default();
+        let base = Utc::now() - Duration::weeks(3);
+        let earlier = clip_at("reference paragraph", base);
+        let earlier_id = earlier.id.clone();
+        storage.add_captured_clip(earlier);
+        let research = storage.pastebooks[0].id.clone();
+        let drafts = storage.create_pastebook("Drafts".to_string()).unwrap().id;
+
+        let CaptureOutcome::Added(again) = storage.add_captured_clip(clip("reference paragraph")) else {
+            panic!("expected the repeat to be stored");
+        };
+        assert_eq!(again.metadata.duplicate_of.as_deref(), Some(earlier_id.as_str()));
+        let found = storage.duplicate_match(&again).unwrap();
+        assert_eq!((found.clip_id, found.pastebook_id, found.captured_at), (earlier_id, research.clone(), base));
+
+        // With reuse, the newest copy is bumped and its pastebook revealed
+        storage.settings.duplicate_elsewhere = DuplicateElsewhere::Reuse;
+        storage.switch_pastebook(research.clone());
+        match storage.add_captured_clip(clip("reference paragraph")) {
+            CaptureOutcome::Reused(clip, switched_to) => {
+                assert_eq!(clip.id, again.id);
+                assert_eq!(switched_to.as_deref(), Some("Drafts"));
+            }
+            other => panic!("expected a reuse, got {:?}", other),
+        }
+        assert_eq!(storage.active_pastebook_id.as_deref(), Some(drafts.as_str()));
+        assert_eq!(storage.get_clips_count(), 1);
+
+        storage.delete_clip(&again.id, None);
+        assert!(storage.latest_with_content("reference paragraph").is_some());
+        assert!(storage.latest_with_content("something new").is_none());
+    }
+
+    #[test]
+    fn capture_rules_route_tag_and_skip() {
+        let mut storage = AppStorage::default();
+        let work = storage.create_pastebook("Work".to_string()).unwrap();
+        let home_id = storage.pastebooks[0].id.clone();
+        storage.switch_pastebook(home_id.clone());
+
+        storage
+            .add_rule(CaptureRule {
+                id: String::new(),
+                name: "Route slack".to_string(),
+                enabled: true,
+                condition: rules::RuleCondition {
+                    source_app: Some("Slack.exe".to_string()),
+                    ..Default::default()
+                },
+                actions: rules::RuleActions {
+                    pastebook_id: Some(work.id.clone()),
+                    add_tags: vec!["chat".to_string()],
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        storage
+            .add_rule(CaptureRule {
+                id: String::new(),
+                name: "No secrets".to_string(),
+                enabled: true,
+                condition: rules::RuleCondition {
+                    content_regex: Some("^sk-".to_string()),
+                    ..Default::default()
+                },
+                actions: rules::RuleActions {
+                    skip: true,
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+
+        let mut from_slack = clip("hello team");
+        from_slack.metadata.source_app = "slack.exe".to_string();
+        match storage.add_captured_clip(from_slack) {
+            CaptureOutcome::Added(clip) => assert_eq!(clip.tags, vec!["chat"]),
+            other => panic!("expected an add, got {:?}", other),
+        }
+        let work_clips = &storage.pastebooks.iter().find(|p| p.id == work.id).unwrap().clips;
+        assert_eq!(work_clips.len(), 1);
+        assert_eq!(storage.get_clips_count(), 0);
+
+        match storage.add_captured_clip(clip("sk-123")) {
+            CaptureOutcome::Skipped(rule) => assert_eq!(rule, "No secrets"),
+            other => panic!("expected a skip, got {:?}", other),
+        }
+
+        // A route to a deleted pastebook falls back to the active one
+        storage.delete_pastebook(&work.id, true).unwrap();
+        let mut from_slack = clip("still here");
+        from_slack.metadata.source_app = "slack.exe".to_string();
+        storage.add_captured_clip(from_slack);
+        assert_eq!(contents(&storage), vec!["still here"]);
+    }
+
+    #[test]
+    fn restoring_keeps_the_file_and_revision() {
+        let mut temp = TempStorage::new();
+        temp.storage.add_clip(clip("before")).unwrap();
+        temp.storage.commit().unwrap();
+        let backup = temp.dir.path().join("backup.json");
+        temp.storage.write_copy(&backup).unwrap();
+
+        temp.storage.add_clip(clip("after")).unwrap();
+        let revision = temp.storage.commit().unwrap();
+        temp.storage.restore_from(&backup).unwrap();
+        assert_eq!(contents(&temp.storage), vec!["before"]);
+        assert_eq!(temp.storage.revision, revision);
+        assert_eq!(temp.storage.storage_path(), Some(temp.path().as_path()));
+        assert_eq!(temp.storage.search_clips("before").len(), 1);
+
+        fs::write(&backup, "not json").unwrap();
+        assert!(temp.storage.restore_from(&backup).is_err());
+        assert_eq!(contents(&temp.storage), vec!["before"]);
+    }
+
+    #[test]
+    fn presets_are_seeded_and_keep_unique_names() {
+        let mut storage = AppStorage::default();
+        let seeded = storage.prompt_presets.len();
+        assert!(storage.prompt_presets.iter().any(|p| p.name == "Concise"));
+
+        let preset = |name: &str| PromptPreset {
+            id: String::new(),
+            name: name.to_string(),
+            instruction: "Translate to French".to_string(),
+            model: None,
+            output: presets::PresetOutput::NewClip,
+        };
+        let french = storage.add_preset(preset("French")).unwrap();
+        assert!(storage.add_preset(preset("french")).unwrap_err().starts_with("AlreadyExists"));
+        assert!(storage.update_preset(&french.id, preset("French")).is_ok());
+
+        // An imported preset named like an existing one replaces it in place
+        let mut concise = preset("CONCISE");
+        concise.instruction = "Halve the length".to_string();
+        assert_eq!(storage.import_presets(vec![concise, preset("German")]).unwrap(), 2);
+        assert_eq!(storage.prompt_presets.len(), seeded + 2);
+        let concise = storage.prompt_presets.iter().find(|p| p.name == "CONCISE").unwrap();
+        assert_eq!(concise.instruction, "Halve the length");
+        assert!(!concise.id.is_empty());
+
+        assert!(storage.delete_preset(&french.id));
+        assert!(!storage.delete_preset(&french.id));
+    }
+
+    #[test]
+    fn rewriting_a_clip_records_the_preset() {
+        let (mut storage, ids) = storage_with(&["long winded text"]);
+        let provenance = Provenance {
+            operation: "run_preset".to_string(),
+            source_ids: vec![ids[0].clone()],
+            model: Some("gemini-flash-latest".to_string()),
+            detail: Some("Concise".to_string()),
+            omitted_ids: Vec::new(),
+        };
+        let rewrite = edit(&storage, &ids[0], "short");
+        let clip = storage.rewrite_clip(&ids[0], rewrite.clone(), provenance.clone()).unwrap();
+        assert_eq!(clip.content, "short");
+        assert_eq!(clip.provenance.unwrap().detail.as_deref(), Some("Concise"));
+        assert_eq!(storage.search_clips("short").len(), 1);
+        assert!(storage.rewrite_clip("missing", rewrite, provenance).unwrap_err().starts_with("NotFound"));
+    }
+
+    #[test]
+    fn ingest_transforms_keep_the_raw_capture() {
+        let mut storage = AppStorage::default();
+        storage.create_pastebook("Book".to_string()).unwrap();
+        storage.settings.ingest_transforms = vec![IngestTransform::Trim, IngestTransform::StraightQuotes];
+        match storage.add_captured_clip(clip("  \u{201C}quoted\u{201D}  \n")) {
+            CaptureOutcome::Added(clip) => {
+                assert_eq!(clip.content, "\"quoted\"");
+                assert_eq!(clip.original_content.as_deref(), Some("  \u{201C}quoted\u{201D}  \n"));
+            }
+            other => panic!("expected an add, got {:?}", other),
+        }
+        // Nothing to change leaves no original behind
+        match storage.add_captured_clip(clip("plain")) {
+            CaptureOutcome::Added(clip) => assert!(clip.original_content.is_none()),
+            other => panic!("expected an add, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn terminal_captures_are_cleaned_unless_disabled() {
+        let mut storage = AppStorage::default();
+        let mut captured = clip("PS C:\\> \u{1b}[32mok\u{1b}[0m");
+        captured.metadata.source_app = "WindowsTerminal.exe".to_string();
+        storage.settings.strip_terminal_prompts = true;
+        match storage.add_captured_clip(captured) {
+            CaptureOutcome::Added(clip) => {
+                assert_eq!(clip.content, "ok");
+                assert_eq!(clip.original_content.as_deref(), Some("PS C:\\> \u{1b}[32mok\u{1b}[0m"));
+            }
+            other => panic!("expected an add, got {:?}", other),
+        }
+
+        storage.settings.terminal_cleanup = false;
+        match storage.add_captured_clip(clip("\u{1b}[1m  raw  \u{1b}[0m")) {
+            CaptureOutcome::Added(clip) => {
+                assert_eq!(clip.content, "  raw  ");
+                assert!(clip.original_content.is_none());
+            }
+            other => panic!("expected an add, got {:?}", other),
         }
     }
+
+    #[test]
+    fn rules_reject_unknown_pastebooks_and_ids() {
+        let mut storage = AppStorage::default();
+        let rule = CaptureRule {
+            id: String::new(),
+            name: "Route".to_string(),
+            enabled: true,
+            condition: Default::default(),
+            actions: rules::RuleActions {
+                pastebook_id: Some("missing".to_string()),
+                ..Default::default()
+            },
+        };
+        assert_eq!(storage.add_rule(rule.clone()).unwrap_err(), "Pastebook not found");
+
+        let mut tagging = rule;
+        tagging.actions.pastebook_id = None;
+        tagging.actions.add_tags = vec!["t".to_string()];
+        let err = storage.update_rule("missing", tagging).unwrap_err();
+        assert_eq!(err, "NotFound: rule missing");
+        assert!(!storage.delete_rule("missing"));
+    }
+
+    #[test]
+    fn title_routes_create_pastebooks_up_to_the_limit() {
+        let mut storage = AppStorage::default();
+        let active = storage.pastebooks[0].id.clone();
+        storage.settings.max_auto_pastebooks = 1;
+        storage
+            .add_rule(CaptureRule {
+                id: String::new(),
+                name: "Projects".to_string(),
+                enabled: true,
+                condition: rules::RuleCondition {
+                    title_regex: Some(r"^(.+) - Figma$".to_string()),
+                    ..Default::default()
+                },
+                actions: rules::RuleActions {
+                    pastebook_from_title: true,
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        let mut capture = |title: &str| {
+            let mut captured = clip(title);
+            captured.metadata.window_title = title.to_string();
+            assert!(matches!(storage.add_captured_clip(captured), CaptureOutcome::Added(_)));
+            storage.take_title_routed()
+        };
+
+        let Some(TitleRouted::Created { id, name }) = capture("Redesign - Figma") else {
+            panic!("expected a new pastebook");
+        };
+        assert_eq!(name, "Redesign");
+        assert_eq!(capture("redesign - Figma"), None);
+        assert_eq!(
+            capture("Logo - Figma"),
+            Some(TitleRouted::LimitReached { name: "Logo".to_string(), limit: 1 })
+        );
+
+        let clips_in = |id: &str| storage.pastebooks.iter().find(|p| p.id == id).unwrap().clips.len();
+        assert_eq!((clips_in(&id), clips_in(&active)), (2, 1));
+        assert_eq!(storage.active_pastebook_id.as_deref(), Some(active.as_str()));
+    }
+
+    #[test]
+    fn title_routes_only_create_pastebooks_for_added_clips() {
+        let mut storage = AppStorage::default();
+        storage.settings.duplicate_elsewhere = DuplicateElsewhere::Reuse;
+        storage
+            .add_rule(CaptureRule {
+                id: String::new(),
+                name: "Projects".to_string(),
+                enabled: true,
+                condition: rules::RuleCondition {
+                    title_regex: Some(r"^(.+) - Figma$".to_string()),
+                    ..Default::default()
+                },
+                actions: rules::RuleActions {
+                    pastebook_from_title: true,
+                    ..Default::default()
+                },
+            })
+            .unwrap();
+        storage.add_clip(clip("same")).unwrap();
+        let capture = |storage: &mut AppStorage, content: &str, title: &str| {
+            let mut captured = clip(content);
+            captured.metadata.window_title = title.to_string();
+            storage.add_captured_clip(captured)
+        };
+
+        // Reused elsewhere: the routed pastebook is never made
+        assert!(matches!(capture(&mut storage, "same", "Redesign - Figma"), CaptureOutcome::Reused(..)));
+        assert_eq!(storage.pastebooks.len(), 1);
+        assert_eq!(storage.take_title_routed(), None);
+
+        assert!(matches!(capture(&mut storage, "new", "Redesign - Figma"), CaptureOutcome::Added(_)));
+        assert_eq!(storage.pastebooks.len(), 2);
+        assert!(matches!(storage.take_title_routed(), Some(TitleRouted::Created { .. })));
+    }
+
+    // ==================== CLIP OPERATIONS ====================
+
+    #[test]
+    fn delete_clip_handles_empty_and_unknown_ids() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        assert!(storage.delete_clip("", None).is_none());
+        assert!(storage.delete_clip("unknown", None).is_none());
+        assert_eq!(storage.get_clips_count(), 2);
+
+        assert!(storage.delete_clip(&ids[0], None).is_some());
+        assert!(storage.delete_clip(&ids[0], None).is_none());
+        assert_eq!(contents(&storage), vec!["b"]);
+        assert!(storage.search_clips("a").is_empty());
+    }
+
+    #[test]
+    fn update_clip_reindexes_and_ignores_unknown_ids() {
+        let (mut storage, ids) = storage_with(&["old text"]);
+        assert!(storage.update_clip("unknown", "new".to_string(), None).is_none());
+        assert!(storage.update_clip(&ids[0], "brand new".to_string(), None).is_some());
+        assert!(storage.search_clips("old").is_empty());
+        assert_eq!(storage.search_clips("brand").len(), 1);
+    }
+
+    #[test]
+    fn merge_and_delete_keep_the_index_current() {
+        let (mut storage, ids) = storage_with(&["alpha report", "beta notes", "gamma"]);
+        storage.delete_clip(&ids[2], None).unwrap();
+        assert!(!storage.search_index.contains(&ids[2]));
+        assert!(storage.search_clips("gamma").is_empty());
+
+        let merged = storage.merge_clips(ids[..2].to_vec(), &MergeOptions::default()).unwrap();
+        assert!(storage.search_index.contains(&merged.id));
+        assert!(!storage.search_index.contains(&ids[0]));
+        assert!(!storage.search_index.contains(&ids[1]));
+        let found: Vec<String> = storage.search_clips("report notes").into_iter().map(|c| c.id).collect();
+        assert_eq!(found, vec![merged.id]);
+    }
+
+    #[test]
+    fn clip_operations_reach_clips_in_other_pastebooks() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        let first = storage.pastebooks[0].id.clone();
+        let other = storage.create_pastebook("Other".to_string()).unwrap();
+        assert!(storage.get_clip(&ids[0]).is_none());
+
+        // The clip is edited where it lives; the active pastebook is untouched
+        assert_eq!(storage.update_clip(&ids[0], "edited".to_string(), None), Some(first.clone()));
+        assert_eq!(storage.set_clip_label(&ids[0], Some("red".to_string()), None), Some(first.clone()));
+        assert_eq!(storage.set_clip_sensitive(&ids[0], true, Some(&first)), Some(first.clone()));
+        let clip = storage.find_clip(&ids[0]).unwrap();
+        assert_eq!((clip.content.as_str(), clip.label.as_deref(), clip.sensitive), ("edited", Some("red"), true));
+        assert_eq!(storage.search_pastebook(&first, "edited").unwrap().len(), 1);
+        assert!(storage.get_clips().is_empty());
+
+        // A wrong hint only costs the search
+        assert_eq!(storage.delete_clip(&ids[1], Some(&other.id)), Some(first.clone()));
+        assert!(storage.find_clip(&ids[1]).is_none());
+        assert_eq!(storage.pastebooks[0].clips.len(), 1);
+        assert!(storage.delete_clip(&ids[1], Some(&first)).is_none());
+    }
+
+    #[test]
+    fn reorder_puts_listed_clips_first_and_keeps_the_rest() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        assert_eq!(contents(&storage), vec!["c", "b", "a"]);
+
+        storage.reorder_clips(vec![ids[0].clone(), "unknown".to_string(), ids[0].clone()]);
+        assert_eq!(contents(&storage), vec!["a", "c", "b"]);
+
+        storage.reorder_clips(Vec::new());
+        assert_eq!(contents(&storage), vec!["a", "c", "b"]);
+
+        storage.reorder_clips(vec![ids[1].clone(), ids[2].clone(), ids[0].clone()]);
+        assert_eq!(contents(&storage), vec!["b", "c", "a"]);
+    }
+
+    fn set_flags(storage: &mut AppStorage, id: &str, pinned: bool, locked: bool) {
+        let clip = storage.get_active_pastebook_mut().unwrap().clips.iter_mut().find(|c| c.id == id).unwrap();
+        clip.pinned = pinned;
+        clip.locked = locked;
+    }
+
+    #[test]
+    fn pinned_clips_hold_their_positions_through_every_reorder() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c", "d", "e"]);
+        // Order is e d c b a; pin e (first) and c (middle)
+        set_flags(&mut storage, &ids[4], true, false);
+        set_flags(&mut storage, &ids[2], true, false);
+
+        // Drag and drop: the full order, pinned ids included, as the UI sends it
+        let order = storage.reorder_clips(ids.clone());
+        assert_eq!(contents(&storage), vec!["e", "a", "c", "b", "d"]);
+        assert_eq!(order.len(), 5);
+
+        storage.sort_clips(SortOrder::Newest);
+        assert_eq!(contents(&storage), vec!["e", "d", "c", "b", "a"]);
+        storage.sort_clips(SortOrder::Oldest);
+        assert_eq!(contents(&storage), vec!["e", "a", "c", "b", "d"]);
+
+        // Magic sort: whatever the model returns, including a pinned id moved
+        storage.apply_order(&[ids[2].clone(), ids[3].clone(), ids[1].clone()]);
+        assert_eq!(contents(&storage), vec!["e", "d", "c", "b", "a"]);
+
+        // Merging two unpinned clips: the new clip goes to the first free slot
+        let merged = storage
+            .merge_clips(vec![ids[0].clone(), ids[1].clone()], &MergeOptions::default())
+            .unwrap();
+        assert_eq!(merged.content, "b\n\na");
+        assert_eq!(contents(&storage), vec!["e", "b\n\na", "c", "d"]);
+    }
+
+    #[test]
+    fn pinned_clips_hold_their_positions_through_new_clips() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        // Order is c b a; pin c (first) and a (last)
+        set_flags(&mut storage, &ids[2], true, false);
+        set_flags(&mut storage, &ids[0], true, false);
+
+        storage.add_clip(clip("d")).unwrap();
+        assert_eq!(contents(&storage), vec!["c", "d", "a", "b"]);
+        storage.add_captured_clip(clip("e"));
+        assert_eq!(contents(&storage), vec!["c", "e", "a", "d", "b"]);
+
+        // A reused capture bumps an unpinned clip to the first free slot and
+        // leaves a pinned one where it is
+        storage.settings.duplicate_elsewhere = DuplicateElsewhere::Reuse;
+        storage.add_captured_clip(clip("b"));
+        assert_eq!(contents(&storage), vec!["c", "b", "a", "e", "d"]);
+        storage.add_captured_clip(clip("a"));
+        assert_eq!(contents(&storage), vec!["c", "b", "a", "e", "d"]);
+    }
+
+    #[test]
+    fn locked_clips_survive_bulk_deletes() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        set_flags(&mut storage, &ids[0], false, true);
+
+        storage.merge_clips(vec![ids[0].clone(), ids[1].clone()], &MergeOptions::default());
+        assert_eq!(contents(&storage), vec!["b\n\na", "c", "a"]);
+
+        let cleared = storage.clear_clips();
+        assert_eq!(cleared.len(), 2);
+        assert_eq!(contents(&storage), vec!["a"]);
+        assert!(storage.delete_clip(&ids[0], None).is_some());
+    }
+
+    #[test]
+    fn merge_needs_two_known_clips() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        let options = MergeOptions::default();
+        assert!(storage.merge_clips(Vec::new(), &options).is_none());
+        assert!(storage.merge_clips(vec![ids[0].clone()], &options).is_none());
+        assert!(storage
+            .merge_clips(vec![ids[0].clone(), "unknown".to_string()], &options)
+            .is_none());
+        assert!(storage
+            .merge_clips(vec![ids[0].clone(), ids[0].clone()], &options)
+            .is_some_and(|c| c.content == "a\n\na"));
+        // The duplicate-id merge consumed "a"
+        assert_eq!(contents(&storage), vec!["a\n\na", "b"]);
+    }
+
+    #[test]
+    fn merge_joins_in_selection_order_and_removes_sources() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        let options = MergeOptions {
+            separator: Some(" | ".to_string()),
+            order: MergeOrder::Selection,
+            ..Default::default()
+        };
+        let merged = storage
+            .merge_clips(vec![ids[2].clone(), ids[0].clone()], &options)
+            .unwrap();
+        assert_eq!(merged.content, "c | a");
+        assert_eq!(contents(&storage), vec!["c | a", "b"]);
+        assert_eq!(storage.search_clips("c | a").len(), 1);
+    }
     
-    /// Merge multiple clips
-    pub fn merge_clips(&mut self, ids: Vec<String>) -> Option<ClipObject> {
-        if ids.len() < 2 {
-            return None;
+    #[test]
+    fn scoped_reorders_leave_other_clips_in_place() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c", "d", "e"]);
+        assert_eq!(contents(&storage), vec!["e", "d", "c", "b", "a"]);
+        set_flags(&mut storage, &ids[2], true, false);
+
+        // Unknown ids are all reported, and nothing is picked
+        let err = storage.scoped_clips(Some(&[ids[0].clone(), "x".to_string(), "y".to_string()])).unwrap_err();
+        assert_eq!(err, "Validation: not in the active pastebook: x, y");
+        let scope = [ids[0].clone(), ids[2].clone(), ids[3].clone()];
+        let scoped: Vec<&str> = storage.scoped_clips(Some(&scope)).unwrap().iter().map(|c| c.content.as_str()).collect();
+        assert_eq!(scoped, vec!["d", "c", "a"]);
+        assert_eq!(storage.scoped_clips(None).unwrap().len(), 5);
+
+        // "a" and "d" swap slots; "e" and "b" stay put, and so does pinned "c"
+        storage.apply_scoped_order(&[ids[0].clone(), ids[2].clone(), ids[3].clone()]);
+        assert_eq!(contents(&storage), vec!["e", "a", "c", "b", "d"]);
+    }
+    
+    #[test]
+    fn merge_defaults_to_the_order_on_screen() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c", "d"]);
+        // Dragged out of capture order, then clicked bottom to top
+        storage.apply_order(&[ids[3].clone(), ids[0].clone(), ids[2].clone(), ids[1].clone()]);
+        let clicked = vec![ids[1].clone(), ids[2].clone(), ids[3].clone()];
+        assert_eq!(storage.merge_source_ids(&clicked), vec![ids[3].clone(), ids[2].clone(), ids[1].clone()]);
+
+        let merged = storage.merge_clips(clicked, &MergeOptions::default()).unwrap();
+        assert_eq!(merged.content, "d\n\nc\n\nb");
+        assert_eq!(contents(&storage), vec!["d\n\nc\n\nb", "a"]);
+    }
+
+    #[test]
+    fn merge_chronological_and_keep_sources() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        let options = MergeOptions {
+            order: MergeOrder::Chronological,
+            keep_sources: true,
+            ..Default::default()
+        };
+        let merged = storage
+            .merge_clips(vec![ids[2].clone(), ids[0].clone(), ids[1].clone()], &options)
+            .unwrap();
+        assert_eq!(merged.content, "a\n\nb\n\nc");
+        assert_eq!(storage.get_clips_count(), 4);
+    }
+
+    #[test]
+    fn merge_carries_assets_and_sensitivity() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        storage.attach_asset(&ids[0], "h1".to_string(), None);
+        storage.attach_asset(&ids[1], "h1".to_string(), None);
+        storage.attach_asset(&ids[1], "h2".to_string(), None);
+        storage.set_clip_sensitive(&ids[1], true, None);
+
+        let merged = storage
+            .build_merged_clip(&ids, &MergeOptions::default())
+            .unwrap();
+        assert_eq!(merged.assets, vec!["h1", "h2"]);
+        assert!(merged.sensitive);
+        // Previewing leaves storage alone
+        assert_eq!(storage.get_clips_count(), 2);
+    }
+
+    #[test]
+    fn find_replace_dry_run_and_apply() {
+        let (mut storage, ids) = storage_with(&["foo bar foo", "nothing", "foo"]);
+        let pattern = build_find_regex("foo", false).unwrap();
+
+        let dry = storage.find_replace_clips(&pattern, "baz", false, None, true);
+        assert_eq!(dry.clips.len(), 2);
+        assert_eq!(dry.changed, 0);
+        assert_eq!(contents(&storage), vec!["foo", "nothing", "foo bar foo"]);
+
+        let only = [ids[0].clone()];
+        let applied = storage.find_replace_clips(&pattern, "baz", false, Some(&only), false);
+        assert_eq!(applied.changed, 1);
+        assert_eq!(applied.clips[0].matches, 2);
+        assert_eq!(contents(&storage), vec!["foo", "nothing", "baz bar baz"]);
+
+        let revisions = storage.find_clip(&ids[0]).unwrap().revisions().unwrap();
+        assert_eq!(revisions.len(), 1);
+        assert_eq!(revisions[0].cause, "find_replace");
+        assert_eq!(revisions[0].content, "foo bar foo");
+        assert!(storage.find_clip(&ids[2]).unwrap().history.is_empty());
+    }
+
+    #[test]
+    fn find_replace_expands_groups_only_for_regexes() {
+        let (mut storage, _) = storage_with(&["2024-01-31"]);
+        let pattern = build_find_regex(r"(\d+)-(\d+)-(\d+)", true).unwrap();
+        storage.find_replace_clips(&pattern, "$3/$2/$1", true, None, false);
+        assert_eq!(contents(&storage), vec!["31/01/2024"]);
+
+        let literal = build_find_regex("31", false).unwrap();
+        storage.find_replace_clips(&literal, "$1", false, None, false);
+        assert_eq!(contents(&storage), vec!["$1/01/2024"]);
+    }
+
+    #[test]
+    fn find_patterns_are_validated() {
+        assert_eq!(build_find_regex("", false).unwrap_err(), "Search pattern is empty");
+        assert!(build_find_regex("(", true).is_err());
+        assert!(build_find_regex("(", false).is_ok());
+        // Too big to compile rather than slow to run
+        let err = build_find_regex(r"\w{1000}{1000}", true).unwrap_err();
+        assert!(err.starts_with("Invalid pattern"), "{}", err);
+    }
+
+    #[test]
+    fn bulk_update_reports_missing_ids() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        let patch = ClipPatch {
+            add_tags: vec![" x ".to_string(), "x".to_string()],
+            label: Some(Some("red".to_string())),
+            pinned: Some(true),
+            ..Default::default()
+        }
+        .validate()
+        .unwrap();
+
+        let targets = vec![ids[0].clone(), "unknown".to_string()];
+        let results = storage.bulk_update_clips(&targets, &patch);
+        assert_eq!(results[0].outcome, BulkOutcome::Updated);
+        assert_eq!(results[1].outcome, BulkOutcome::NotFound);
+
+        let updated = storage.get_clip(&ids[0]).unwrap();
+        assert_eq!(updated.tags, vec!["x"]);
+        assert_eq!(updated.label.as_deref(), Some("red"));
+        assert!(updated.pinned);
+        assert!(!storage.get_clip(&ids[1]).unwrap().pinned);
+    }
+
+    #[test]
+    fn bulk_update_skips_locked_clips_unless_unlocking() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        for clip in storage.get_active_pastebook_mut().unwrap().clips.iter_mut() {
+            clip.tags = vec!["old".to_string(), "keep".to_string()];
+            // The newest, "b", is locked
+            clip.locked = clip.id == ids[1];
+        }
+
+        let retag = ClipPatch {
+            add_tags: vec!["new".to_string()],
+            remove_tags: vec!["old".to_string()],
+            ..Default::default()
         }
+        .validate()
+        .unwrap();
+        let results = storage.bulk_update_clips(&ids, &retag);
+        assert_eq!(results[0].outcome, BulkOutcome::Updated);
+        assert_eq!(results[1].outcome, BulkOutcome::Locked);
+        assert_eq!(storage.get_clip(&ids[0]).unwrap().tags, vec!["keep", "new"]);
+        assert_eq!(storage.get_clip(&ids[1]).unwrap().tags, vec!["old", "keep"]);
+
+        let unlock = ClipPatch {
+            locked: Some(false),
+            ..Default::default()
+        };
+        let results = storage.bulk_update_clips(&ids[1..], &unlock.validate().unwrap());
+        assert_eq!(results[0].outcome, BulkOutcome::Updated);
+        assert!(!storage.get_clip(&ids[1]).unwrap().locked);
+    }
+
+    #[test]
+    fn bulk_patches_must_change_something() {
+        assert_eq!(ClipPatch::default().validate().unwrap_err(), "Patch is empty");
+        let blank_status = ClipPatch {
+            status: Some("  ".to_string()),
+            ..Default::default()
+        };
+        assert!(blank_status.validate().is_err());
+        let clear_label: ClipPatch = serde_json::from_str(r#"{"label": null}"#).unwrap();
+        assert_eq!(clear_label.validate().unwrap().label, Some(None));
+    }
+
+    #[test]
+    fn labels_are_set_only_on_found_clips() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        let targets = vec![ids[0].clone(), ids[1].clone(), "unknown".to_string()];
+        assert_eq!(storage.set_label_for(&targets, Some("blue".to_string())), 2);
+        assert!(storage.set_clip_label("unknown", None, None).is_none());
+        assert_eq!(storage.get_clips_by_label(Some("blue")).len(), 2);
+        assert!(storage.get_clips_by_label(None).is_empty());
+    }
+
+    #[test]
+    fn reminders_fire_once() {
+        let (mut storage, ids) = storage_with(&["a"]);
+        let now = Utc::now();
+        assert!(storage.set_clip_reminder("unknown", Some(now)).is_none());
+        storage.set_clip_reminder(&ids[0], Some(now + Duration::minutes(5)));
+
+        assert!(storage.take_due_reminders(now).is_empty());
+        let due = storage.take_due_reminders(now + Duration::minutes(5));
+        assert_eq!(due.len(), 1);
+        assert!(storage.take_due_reminders(now + Duration::hours(1)).is_empty());
+
+        storage.set_clip_reminder(&ids[0], None);
+        assert!(storage.get_clip(&ids[0]).unwrap().reminder.is_none());
+        assert!(!storage.set_clip_reminder(&ids[0], None).unwrap().1);
+    }
+
+    #[test]
+    fn revision_moves_only_for_a_change() {
+        let (mut storage, _) = storage_with(&["a"]);
+        let revision = storage.revision;
+        assert_eq!(storage.commit_if(false).unwrap(), revision);
+        assert_eq!(storage.commit_if(true).unwrap(), revision + 1);
+    }
+
+    #[test]
+    fn clear_clips_empties_the_index_too() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        storage.clear_clips();
+        assert_eq!(storage.get_clips_count(), 0);
+        assert!(storage.search_clips("a").is_empty());
+        assert!(!storage.search_index.contains(&ids[0]));
+    }
+
+    #[test]
+    fn other_pastebooks_are_worked_on_without_switching() {
+        let (mut storage, _) = storage_with(&["first", "second"]);
+        let background = storage.pastebooks[0].id.clone();
+        let active = storage.create_pastebook("Active".to_string()).unwrap().id;
+        storage.add_clip(clip("up front")).unwrap();
+
+        assert_eq!(storage.resolve_pastebook(None).unwrap().id, active);
+        assert_eq!(storage.resolve_pastebook(Some(&background)).unwrap().id, background);
+        assert_eq!(storage.resolve_pastebook(Some("nope")).unwrap_err(), "NotFound: pastebook nope");
+
+        assert_eq!(storage.pastebook_content(&background).unwrap(), "second\n\nfirst");
+        assert_eq!(storage.search_pastebook(&background, "first").unwrap().len(), 1);
+        storage.sort_pastebook_clips(&background, SortOrder::Oldest).unwrap();
+        assert_eq!(storage.pastebook_content(&background).unwrap(), "first\n\nsecond");
+        assert_eq!(storage.clear_pastebook_clips(&background).unwrap().len(), 2);
+        assert!(storage.clear_pastebook_clips("nope").is_none());
+
+        assert_eq!(storage.active_pastebook_id.as_deref(), Some(active.as_str()));
+        assert_eq!(storage.get_all_content(), "up front");
+        assert!(storage.search_clips("first").is_empty());
+    }
+
+    #[test]
+    fn scratchpads_keep_the_text_from_before_the_last_burst_of_edits() {
+        let (mut storage, _) = storage_with(&["kept"]);
+        let start = Utc::now();
+        let pastebook = storage.resolve_pastebook_mut(None).unwrap();
+        assert!(pastebook.set_scratchpad("draft".to_string(), start));
+        assert!(pastebook.set_scratchpad("draft two".to_string(), start + Duration::seconds(2)));
+        assert!(!pastebook.set_scratchpad("draft two".to_string(), start + Duration::seconds(3)));
+        assert_eq!(pastebook.previous_scratchpad, None);
+
+        // A pause starts a new burst; what was there becomes the previous version
+        pastebook.set_scratchpad("rewritten".to_string(), start + Duration::minutes(5));
+        pastebook.set_scratchpad("rewritten!".to_string(), start + Duration::minutes(5) + Duration::seconds(1));
+        let scratchpad = pastebook.scratchpad();
+        assert_eq!(scratchpad.text, "rewritten!");
+        assert_eq!(scratchpad.previous.as_deref(), Some("draft two"));
+
+        storage.clear_clips();
+        assert!(storage.get_all_content().is_empty());
+        assert_eq!(storage.resolve_pastebook(None).unwrap().scratchpad, "rewritten!");
+    }
+
+    #[test]
+    fn timeline_buckets_by_local_hour() {
+        let mut storage = AppStorage::default();
+        let tz = FixedOffset::east_opt(2 * 3600).unwrap();
+        let date = NaiveDate::from_ymd_opt(2026, 3, 10).unwrap();
+        let at = |h, m| {
+            tz.from_local_datetime(&date.and_hms_opt(h, m, 0).unwrap())
+                .unwrap()
+                .with_timezone(&Utc)
+        };
+        storage.add_clip(clip_at("late", at(9, 45))).unwrap();
+        storage.add_clip(clip_at("early", at(9, 5))).unwrap();
+        storage.add_clip(clip_at("midnight", at(0, 30))).unwrap();
+        let mut secret = clip_at("secret", at(23, 59));
+        secret.sensitive = true;
+        storage.add_clip(secret).unwrap();
+        // 22:30 UTC the day before is 00:30 local, but 21:30 UTC is still yesterday
+        storage.add_clip(clip_at("yesterday", at(0, 0) - Duration::minutes(30))).unwrap();
+
+        let hours = storage.timeline(&tz, date, None).unwrap();
+        assert_eq!(hours.len(), 24);
+        let previews = |hour: usize| -> Vec<Option<String>> {
+            hours[hour].entries.iter().map(|e| e.preview.clone()).collect()
+        };
+        assert_eq!(previews(9), vec![Some("early".to_string()), Some("late".to_string())]);
+        assert_eq!(previews(0), vec![Some("midnight".to_string())]);
+        assert_eq!(previews(23), vec![None]);
+        assert_eq!(hours.iter().map(|h| h.entries.len()).sum::<usize>(), 4);
+
+        assert!(storage.timeline(&tz, date, Some("unknown")).is_err());
+    }
+
+    // ==================== PASTEBOOKS ====================
+
+    #[test]
+    fn last_pastebook_cannot_be_deleted() {
+        let mut storage = AppStorage::default();
+        let only = storage.pastebooks[0].id.clone();
+        assert_eq!(storage.delete_pastebook(&only, true), Ok(false));
+        assert_eq!(storage.pastebooks.len(), 1);
+        assert_eq!(storage.active_pastebook_id, Some(only));
+    }
+
+    #[test]
+    fn creating_a_pastebook_switches_to_it() {
+        let mut storage = AppStorage::default();
+        let created = storage.create_pastebook("New".to_string()).unwrap();
+        assert_eq!(storage.active_pastebook_id, Some(created.id.clone()));
+        assert!(storage.switch_pastebook(storage.pastebooks[0].id.clone()));
+        assert!(!storage.switch_pastebook("unknown".to_string()));
+        assert_ne!(storage.active_pastebook_id, Some(created.id));
+    }
+
+    #[test]
+    fn pastebook_counts_come_from_live_clip_count() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        assert_eq!(storage.set_clip_trashed(&ids[1], true, None, Utc::now()), Ok(Some(storage.pastebooks[0].id.clone())));
+        storage.create_pastebook("Empty".to_string()).unwrap();
+        let counts: Vec<usize> = storage.list_pastebooks().iter().map(|p| p.clip_count).collect();
+        assert_eq!(counts, vec![2, 0]);
+        assert_eq!(storage.get_clips_count(), 0);
+        assert_eq!(storage.pastebooks[0].live_clip_count(), 2);
         
-        let pastebook = self.get_active_pastebook_mut()?;
+        // Trashed clips are out of the list, search and content too
+        storage.switch_pastebook(storage.pastebooks[0].id.clone());
+        assert_eq!(contents(&storage), vec!["c", "a"]);
+        assert!(storage.search_clips("b").is_empty());
+        assert_eq!(storage.pastebook_content(&storage.pastebooks[0].id).unwrap(), "c\n\na");
+        
+        // Restoring brings it back; trashing twice changes nothing
+        assert!(storage.set_clip_trashed(&ids[1], false, None, Utc::now()).unwrap().is_some());
+        assert_eq!(storage.set_clip_trashed(&ids[1], false, None, Utc::now()), Ok(None));
+        assert_eq!(storage.get_clips_count(), 3);
+    }
+    
+    #[test]
+    fn stats_count_live_and_trashed_clips() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c"]);
+        let id = storage.pastebooks[0].id.clone();
+        storage.set_clip_trashed(&ids[0], true, None, Utc::now()).unwrap();
+        storage.set_clip_trashed(&ids[2], true, None, Utc::now()).unwrap();
         
-        let mut merged_content = Vec::new();
-        let mut first_metadata: Option<ClipMetadata> = None;
+        let stats = storage.pastebook_stats(&id).unwrap();
+        assert_eq!((stats.clip_count, stats.trashed_count), (1, 2));
+        assert!(storage.pastebook_stats("unknown").is_none());
+        let trashed: Vec<String> = storage.trashed_clips(&id).unwrap().into_iter().map(|c| c.id).collect();
+        assert_eq!(trashed.len(), 2);
         
-        for id in &ids {
-            if let Some(clip) = pastebook.clips.iter().find(|c| &c.id == id) {
-                merged_content.push(clip.content.clone());
-                if first_metadata.is_none() {
-                    first_metadata = Some(clip.metadata.clone());
-                }
-            }
-        }
+        // Locked clips stay out of the trash
+        set_flags(&mut storage, &ids[1], false, true);
+        assert!(storage.set_clip_trashed(&ids[1], true, None, Utc::now()).unwrap_err().starts_with("Locked:"));
+        assert!(storage.set_clip_trashed("unknown", true, None, Utc::now()).unwrap_err().starts_with("NotFound:"));
+    }
+    
+    #[test]
+    fn trash_is_purged_after_the_retention_period() {
+        let (mut storage, ids) = storage_with(&["old", "recent", "live"]);
+        let now = Utc::now();
+        storage.set_clip_trashed(&ids[0], true, None, now - Duration::days(TRASH_RETENTION_DAYS + 1)).unwrap();
+        storage.set_clip_trashed(&ids[1], true, None, now - Duration::days(1)).unwrap();
         
-        if merged_content.is_empty() {
-            return None;
-        }
+        assert_eq!(storage.purge_trash(now), 1);
+        assert!(storage.find_clip(&ids[0]).is_none());
+        let stats = storage.pastebook_stats(&storage.pastebooks[0].id).unwrap();
+        assert_eq!((stats.clip_count, stats.trashed_count), (1, 1));
+        // Nothing trashed long enough: nothing goes
+        assert_eq!(storage.purge_trash(now), 0);
+        // A purge can be undone like any other delete
+        assert_eq!(storage.undo_last_operation().unwrap().label, "Purge trash in 'My First Pastebook'");
+        assert!(storage.find_clip(&ids[0]).is_some());
+    }
+    
+    #[test]
+    fn a_pastebook_with_only_trashed_clips_deletes_without_confirming() {
+        let (mut storage, ids) = storage_with(&["a", "b"]);
+        let full = storage.pastebooks[0].id.clone();
+        storage.create_pastebook("Other".to_string()).unwrap();
         
-        let new_clip = ClipObject {
-            id: Uuid::new_v4().to_string(),
-            content: merged_content.join("\n\n"),
-            metadata: first_metadata.unwrap_or(ClipMetadata {
-                timestamp: Utc::now(),
-                source_app: "Stack".to_string(),
-                window_title: "Merged Clip".to_string(),
-            }),
-            status: "raw".to_string(),
-        };
+        let refused = storage.delete_pastebook(&full, false).unwrap_err();
+        assert!(refused.starts_with("Validation:"), "{}", refused);
+        assert_eq!(storage.pastebooks.len(), 2);
         
-        // Remove merged clips
         for id in &ids {
-            pastebook.clips.retain(|c| &c.id != id);
+            storage.set_clip_trashed(id, true, None, Utc::now()).unwrap();
         }
-        
-        pastebook.clips.insert(0, new_clip.clone());
-        Some(new_clip)
+        assert_eq!(storage.delete_pastebook(&full, false), Ok(true));
+        assert_eq!(storage.pastebooks.len(), 1);
     }
     
-    /// Get all clips as a single string
-    pub fn get_all_content(&self) -> String {
-        self.get_active_pastebook()
-            .map(|p| {
-                p.clips
-                    .iter()
-                    .map(|c| c.content.as_str())
-                    .collect::<Vec<_>>()
-                    .join("\n\n")
-            })
-            .unwrap_or_default()
+    #[test]
+    fn new_clips_are_counted_per_pastebook_until_viewed() {
+        let (mut storage, _) = storage_with(&["from before"]);
+        let first = storage.pastebooks[0].id.clone();
+        let second = storage.create_pastebook("Second".to_string()).unwrap().id;
+        let later = Utc::now() + Duration::minutes(1);
+        storage.add_clip(clip_at("new in second", later)).unwrap();
+        storage.switch_pastebook(first.clone());
+        storage.add_clip(clip_at("new in first", later)).unwrap();
+        storage.add_clip(clip_at("also new", later)).unwrap();
+
+        let counts = |storage: &AppStorage| -> Vec<usize> {
+            storage.list_pastebooks().iter().map(|p| p.new_clip_count).collect()
+        };
+        assert_eq!(counts(&storage), vec![2, 1]);
+        assert_eq!(storage.new_clip_total(), 3);
+
+        // Viewing or clearing one pastebook leaves the other's count alone
+        let (summary, changed) = storage.mark_pastebook_viewed(&first, later).unwrap();
+        assert_eq!((summary.new_clip_count, changed), (0, true));
+        assert_eq!(counts(&storage), vec![0, 1]);
+        // Nothing new since: looking again changes nothing to save
+        let (summary, changed) = storage.mark_pastebook_viewed(&first, later + Duration::minutes(1)).unwrap();
+        assert_eq!((summary.new_clip_count, changed), (0, false));
+        assert_eq!(storage.pastebooks[0].last_viewed_at, Some(later));
+        storage.switch_pastebook(second.clone());
+        storage.clear_pastebook_clips(&first).unwrap();
+        assert_eq!(counts(&storage), vec![0, 1]);
+        assert!(storage.mark_pastebook_viewed("unknown", later).is_none());
+
+        // The mark survives a restart; older files without one count nothing
+        let mut reloaded: AppStorage = serde_json::from_str(&serde_json::to_string(&storage).unwrap()).unwrap();
+        assert_eq!(reloaded.pastebooks[1].last_viewed_at, storage.pastebooks[1].last_viewed_at);
+        assert_eq!(counts(&reloaded), vec![0, 1]);
+        reloaded.pastebooks[1].last_viewed_at = None;
+        assert_eq!(reloaded.new_clip_total(), 0);
+    }
+    
+    #[test]
+    fn imported_clips_are_deduped_and_placed_by_time() {
+        let (mut storage, _) = storage_with(&["old", "new"]);
+        let id = storage.pastebooks[0].id.clone();
+        let between = storage.pastebooks[0].clips[1].metadata.timestamp + chrono::Duration::milliseconds(500);
+        let imported = vec![
+            clip_at("middle", between),
+            clip_at("new", between),
+            clip_at("middle", between),
+        ];
+        assert_eq!(storage.import_clips(&id, imported), Some(1));
+        assert_eq!(contents(&storage), vec!["new", "middle", "old"]);
+        assert_eq!(storage.import_clips("missing", Vec::new()), None);
     }
     
-    /// Clear all clips in active pastebook
-    pub fn clear_clips(&mut self) {
-        if let Some(pastebook) = self.get_active_pastebook_mut() {
-            pastebook.clips.clear();
+    #[test]
+    fn deleting_the_active_pastebook_switches_to_the_first() {
+        let (mut storage, ids) = storage_with(&["kept"]);
+        let first = storage.pastebooks[0].id.clone();
+        let doomed = storage.create_pastebook("Doomed".to_string()).unwrap();
+        storage.add_clip(clip("doomed clip")).unwrap();
+
+        assert_eq!(storage.delete_pastebook("unknown", false), Ok(false));
+        assert_eq!(storage.delete_pastebook(&doomed.id, true), Ok(true));
+        assert_eq!(storage.active_pastebook_id, Some(first));
+        assert!(storage.get_clip(&ids[0]).is_some());
+        assert!(storage.search_clips("doomed").is_empty());
+    }
+
+    #[test]
+    fn deleting_another_pastebook_keeps_the_active_one() {
+        let mut storage = AppStorage::default();
+        let first = storage.pastebooks[0].id.clone();
+        let active = storage.create_pastebook("Active".to_string()).unwrap();
+        assert_eq!(storage.delete_pastebook(&first, false), Ok(true));
+        assert_eq!(storage.active_pastebook_id, Some(active.id));
+    }
+
+    #[test]
+    fn rename_only_known_pastebooks() {
+        let mut storage = AppStorage::default();
+        let id = storage.pastebooks[0].id.clone();
+        assert_eq!(storage.rename_pastebook(&id, "Renamed".to_string()), Ok(true));
+        assert_eq!(storage.rename_pastebook("unknown", "x".to_string()), Ok(false));
+        assert_eq!(storage.list_pastebooks()[0].name, "Renamed");
+    }
+
+    #[test]
+    fn pastebook_names_are_unique_ignoring_case() {
+        let mut storage = AppStorage::default();
+        let first = storage.create_pastebook("Research".to_string()).unwrap();
+        let err = storage.create_pastebook(" research ".to_string()).unwrap_err();
+        assert_eq!(err, "AlreadyExists: pastebook 'Research' (try 'research (2)')");
+        assert!(storage.create_pastebook("  ".to_string()).is_err());
+
+        storage.create_pastebook("Research (2)".to_string()).unwrap();
+        assert_eq!(storage.unique_pastebook_name("Research"), "Research (3)");
+        assert_eq!(
+            storage.find_pastebook_by_name("RESEARCH (2)").map(|p| p.name.as_str()),
+            Some("Research (2)")
+        );
+
+        // Renaming may change a pastebook's own case but not take another's name
+        assert_eq!(storage.rename_pastebook(&first.id, "RESEARCH".to_string()), Ok(true));
+        assert!(storage
+            .rename_pastebook(&first.id, "research (2)".to_string())
+            .unwrap_err()
+            .starts_with("AlreadyExists"));
+    }
+
+    #[test]
+    fn duplicate_names_in_stored_data_are_suffixed_on_load() {
+        let mut temp = TempStorage::new();
+        let storage = &mut temp.storage;
+        for name in ["Research", "research", "Research (2)", "Other"] {
+            let mut pastebook = Pastebook::new(name.to_string());
+            pastebook.clips.push(clip(name));
+            storage.pastebooks.push(pastebook);
+        }
+        storage.save().unwrap();
+
+        let loaded = temp.reload();
+        let names: Vec<&str> = loaded.pastebooks.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(
+            names,
+            vec!["My First Pastebook", "Research", "research (3)", "Research (2)", "Other"]
+        );
+    }
+
+    #[test]
+    fn invalid_names_are_repaired_on_restore_and_sync() {
+        let mut temp = TempStorage::new();
+        let storage = &mut temp.storage;
+        for name in ["  ", "Notes\u{7}", "notes", &"x".repeat(120)] {
+            storage.pastebooks.push(Pastebook::new(name.to_string()));
+        }
+        storage.save().unwrap();
+        let path = storage.storage_path().unwrap().to_path_buf();
+
+        let mut restored = AppStorage::default();
+        restored.restore_from(&path).unwrap();
+        let names: Vec<&str> = restored.pastebooks.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names[1..3], ["Untitled", "Notes"]);
+        assert_eq!(names[3], "notes (2)");
+        assert_eq!(names[4], "x".repeat(100));
+
+        assert_eq!(restored.usable_pastebook_name(" \u{0} "), "Untitled (2)");
+    }
+
+    #[test]
+    fn templates_copy_clip_skeletons_with_fresh_ids() {
+        let (mut storage, ids) = storage_with(&["body"]);
+        let source = storage.pastebooks[0].id.clone();
+        assert!(storage.save_pastebook_as_template("unknown", "T".to_string()).is_none());
+        let template = storage.save_pastebook_as_template(&source, "T".to_string()).unwrap();
+
+        let created = storage
+            .create_pastebook_from_template(&template.id, "From T".to_string())
+            .unwrap();
+        assert_eq!(storage.active_pastebook_id, Some(created.id));
+        assert_eq!(contents(&storage), vec!["body"]);
+        assert_ne!(storage.get_clips()[0].id, ids[0]);
+
+        assert!(storage.delete_template(&template.id));
+        assert!(!storage.delete_template(&template.id));
+        let err = storage
+            .create_pastebook_from_template(&template.id, "x".to_string())
+            .unwrap_err();
+        assert_eq!(err, "Template not found");
+    }
+
+    #[test]
+    fn clipboard_size_limit_can_be_forced_or_disabled() {
+        let mut settings = Settings { clipboard_limit_kb: 1, ..Default::default() };
+        assert!(settings.check_clipboard_size(1024, false).is_ok());
+        let err = settings.check_clipboard_size(1025, false).unwrap_err();
+        assert!(err.starts_with("TooLarge: 1025 bytes"));
+        assert!(err.contains("export_all_content"));
+        assert!(settings.check_clipboard_size(1025, true).is_ok());
+
+        settings.clipboard_limit_kb = 0;
+        assert!(settings.check_clipboard_size(usize::MAX, false).is_ok());
+    }
+
+    #[test]
+    fn stale_active_pastebook_is_reported_and_repaired() {
+        let mut storage = AppStorage {
+            active_pastebook_id: Some("gone".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(storage.add_clip(clip("x")).unwrap_err(), NO_ACTIVE_PASTEBOOK);
+        assert!(matches!(storage.add_captured_clip(clip("x")), CaptureOutcome::NoPastebook(_)));
+
+        storage.pastebooks.clear();
+        let selected = storage.repair_active_pastebook().unwrap();
+        assert!(selected.created);
+        assert_eq!(selected.name, DEFAULT_PASTEBOOK_NAME);
+        assert!(storage.repair_active_pastebook().is_none());
+        assert!(storage.add_clip(clip("x")).is_ok());
+    }
+
+    #[test]
+    fn exclude_list_wins_over_include_list() {
+        let mut settings = Settings {
+            include_apps: Some(vec![" Chrome.exe ".to_string(), "".to_string(), "chrome".to_string(), "SumatraPDF".to_string()]),
+            exclude_apps: vec!["sumatrapdf.exe".to_string()],
+            ..Default::default()
+        };
+        settings.normalize_app_lists();
+        assert_eq!(settings.include_apps, Some(vec!["Chrome.exe".to_string(), "SumatraPDF".to_string()]));
+        assert_eq!(settings.app_filter("chrome.exe"), AppFilter::Allowed);
+        assert_eq!(settings.app_filter("SumatraPDF.exe"), AppFilter::Excluded);
+        assert_eq!(settings.app_filter("slack.exe"), AppFilter::NotIncluded);
+
+        settings.include_apps = Some(vec![" ".to_string()]);
+        settings.normalize_app_lists();
+        assert_eq!(settings.include_apps, None);
+        assert_eq!(settings.app_filter("slack.exe"), AppFilter::Allowed);
+    }
+
+
+    #[test]
+    fn groups_bucket_pastebooks_in_order() {
+        let mut storage = AppStorage::default();
+        let first = storage.pastebooks[0].id.clone();
+        let work = storage.create_pastebook("Work".to_string()).unwrap().id;
+        let notes = storage.create_pastebook("Notes".to_string()).unwrap().id;
+        let todo = storage.create_pastebook("Todo".to_string()).unwrap().id;
+
+        storage.set_pastebook_group(&work, Some("Projects".to_string())).unwrap();
+        storage.set_pastebook_group(&todo, Some(" projects ".to_string())).unwrap();
+        assert!(storage.set_pastebook_group("unknown", None).unwrap_err().starts_with("NotFound"));
+
+        let groups = storage.pastebook_groups();
+        let layout: Vec<(Option<&str>, Vec<&str>)> = groups
+            .iter()
+            .map(|g| (g.group.as_deref(), g.pastebooks.iter().map(|p| p.id.as_str()).collect()))
+            .collect();
+        assert_eq!(
+            layout,
+            vec![
+                (None, vec![first.as_str(), notes.as_str()]),
+                (Some("Projects"), vec![work.as_str(), todo.as_str()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn reordering_stays_within_the_group() {
+        let mut storage = AppStorage::default();
+        let first = storage.pastebooks[0].id.clone();
+        let a = storage.create_pastebook("A".to_string()).unwrap().id;
+        let b = storage.create_pastebook("B".to_string()).unwrap().id;
+        let c = storage.create_pastebook("C".to_string()).unwrap().id;
+        storage.set_pastebook_group(&a, Some("G".to_string())).unwrap();
+        storage.set_pastebook_group(&c, Some("G".to_string())).unwrap();
+
+        assert!(storage.reorder_pastebooks(Some("g"), std::slice::from_ref(&c)).unwrap_err().starts_with("Conflict"));
+        storage.reorder_pastebooks(Some("g"), &[c.clone(), a.clone()]).unwrap();
+        let order: Vec<&str> = storage.pastebooks.iter().map(|p| p.id.as_str()).collect();
+        assert_eq!(order, vec![first.as_str(), b.as_str(), c.as_str(), a.as_str()]);
+    }
+
+    #[test]
+    fn pastebook_operations_undo_newest_first() {
+        let mut storage = AppStorage::default();
+        let first = storage.pastebooks[0].id.clone();
+        let a = storage.create_pastebook("A".to_string()).unwrap().id;
+        let b = storage.create_pastebook("B".to_string()).unwrap().id;
+        storage.switch_pastebook(a.clone());
+        let ids = |s: &AppStorage| s.pastebooks.iter().map(|p| p.id.clone()).collect::<Vec<_>>();
+        let before = ids(&storage);
+
+        storage.rename_pastebook(&a, "Wrong name".to_string()).unwrap();
+        storage.set_pastebook_group(&a, Some("Projects".to_string())).unwrap();
+        storage.set_pastebook_group(&b, Some("Projects".to_string())).unwrap();
+        storage.rename_pastebook_group("projects", "Done").unwrap();
+        assert_eq!(storage.delete_pastebook(&first, false), Ok(true));
+
+        let deleted = storage.undo_last_operation().unwrap();
+        assert_eq!((deleted.label.as_str(), deleted.undone), ("Delete pastebook 'My First Pastebook'", true));
+        assert!(storage.undo_last_operation().unwrap().undone);
+        assert!(storage.pastebooks.iter().all(|p| p.group.as_deref() != Some("Done")));
+        storage.undo_last_operation().unwrap();
+        storage.undo_last_operation().unwrap();
+        assert!(storage.pastebooks.iter().all(|p| p.group.is_none()));
+        assert_eq!(ids(&storage), before);
+        assert_eq!(storage.undo_last_operation().unwrap().label, "Rename pastebook 'A'");
+        assert_eq!(storage.get_active_pastebook().unwrap().name, "A");
+        assert!(storage.undo_last_operation().unwrap_err().starts_with("NotFound"));
+    }
+
+    #[test]
+    fn a_failed_undo_stays_on_the_stack() {
+        let mut storage = AppStorage::default();
+        let a = storage.create_pastebook("A".to_string()).unwrap().id;
+        storage.rename_pastebook(&a, "C".to_string()).unwrap();
+        let taken = storage.create_pastebook("A".to_string()).unwrap().id;
+
+        assert!(storage.undo_last_operation().unwrap_err().starts_with("AlreadyExists"));
+        storage.pastebooks.iter_mut().find(|p| p.id == taken).unwrap().name = "B".to_string();
+        assert!(storage.undo_last_operation().unwrap().undone);
+        assert_eq!(storage.pastebooks.iter().find(|p| p.id == a).unwrap().name, "A");
+    }
+
+    #[test]
+    fn a_deleted_pastebook_comes_back_under_its_id() {
+        let (mut storage, ids) = storage_with(&["kept"]);
+        let doomed = storage.pastebooks[0].id.clone();
+        storage.create_pastebook("Other".to_string()).unwrap();
+        storage.switch_pastebook(doomed.clone());
+        storage.rename_pastebook(&doomed, "Renamed".to_string()).unwrap();
+        storage.delete_pastebook(&doomed, true).unwrap();
+        assert!(storage.search_clips("kept").is_empty());
+
+        // Its clips, place in the list and the active mark come back, and
+        // the rename from before it can be undone in turn
+        assert!(storage.undo_last_operation().unwrap().undone);
+        assert_eq!(storage.pastebooks[0].id, doomed);
+        assert_eq!(storage.active_pastebook_id, Some(doomed.clone()));
+        assert!(storage.get_clip(&ids[0]).is_some());
+        assert_eq!(storage.search_clips("kept").len(), 1);
+        assert!(storage.undo_last_operation().unwrap().undone);
+        assert_eq!(storage.pastebooks[0].name, "My First Pastebook");
+    }
+
+    #[test]
+    fn clip_operations_undo_to_the_same_clips_and_order() {
+        let (mut storage, ids) = storage_with(&["a", "b", "c", "d"]);
+        let before = contents(&storage);
+
+        storage.delete_clip(&ids[1], None).unwrap();
+        let merged = storage.merge_clips(vec![ids[0].clone(), ids[2].clone()], &MergeOptions::default()).unwrap();
+        storage.apply_order(&[ids[3].clone(), merged.id.clone()]);
+        let patch = ClipPatch { add_tags: vec!["x".to_string()], ..Default::default() };
+        storage.bulk_update_clips(&[ids[3].clone()], &patch);
+        // Reordering into the order the clips already have isn't an operation
+        storage.apply_order(&[ids[3].clone(), merged.id.clone()]);
+        storage.add_clip(clip("arrived since")).unwrap();
+
+        let labels: Vec<String> = (0..4).map(|_| storage.undo_last_operation().unwrap().label).collect();
+        assert_eq!(labels, vec!["Update 1 clip(s)", "Reorder pastebook 'My First Pastebook'", "Merge 2 clips", "Delete clip"]);
+        // A capture from after the operations stays, on top
+        assert_eq!(contents(&storage)[0], "arrived since");
+        assert_eq!(contents(&storage)[1..], before[..]);
+        assert!(storage.get_clip(&merged.id).is_none());
+        assert!(storage.get_clip(&ids[3]).unwrap().tags.is_empty());
+        assert_eq!(storage.search_clips("b").len(), 1);
+
+        storage.clear_clips();
+        assert!(storage.undo_last_operation().unwrap().undone);
+        assert_eq!(contents(&storage).len(), 5);
+    }
+
+    #[test]
+    fn renaming_a_group_moves_every_member() {
+        let mut storage = AppStorage::default();
+        let a = storage.create_pastebook("A".to_string()).unwrap().id;
+        let b = storage.create_pastebook("B".to_string()).unwrap().id;
+        storage.set_pastebook_group(&a, Some("Old".to_string())).unwrap();
+        storage.set_pastebook_group(&b, Some("Other".to_string())).unwrap();
+
+        assert!(storage.rename_pastebook_group("old", "other").unwrap_err().starts_with("AlreadyExists"));
+        assert!(storage.rename_pastebook_group("missing", "x").unwrap_err().starts_with("NotFound"));
+        assert_eq!(storage.rename_pastebook_group("old", "OLD").unwrap(), 1);
+        assert_eq!(storage.pastebooks[1].group.as_deref(), Some("OLD"));
+    }
+
+
+    #[test]
+    fn metadata_edits_keep_unset_fields_and_refuse_locked_clips() {
+        let (mut storage, ids) = storage_with(&["a"]);
+        let now = Utc::now();
+        let clip = storage
+            .update_clip_metadata(&ids[0], Some(" Notes ".to_string()), None, None, now)
+            .unwrap();
+        assert_eq!(clip.metadata.source_app, "Notes");
+        assert_eq!(clip.metadata.window_title, "Test Window");
+        assert_eq!(storage.search_clips("notes").len(), 1);
+
+        let future = now + Duration::minutes(2);
+        let err = storage.update_clip_metadata(&ids[0], None, None, Some(future), now).unwrap_err();
+        assert_eq!(err, "Timestamp is in the future");
+        assert!(storage.update_clip_metadata(&ids[0], None, None, Some(now + Duration::seconds(30)), now).is_ok());
+        assert!(storage.update_clip_metadata("nope", None, None, None, now).unwrap_err().starts_with("NotFound"));
+
+        storage.get_active_pastebook_mut().unwrap().clips[0].locked = true;
+        let err = storage
+            .update_clip_metadata(&ids[0], None, Some("x".to_string()), None, now)
+            .unwrap_err();
+        assert!(err.starts_with("Locked"));
+    }
+
+    #[test]
+    fn metadata_edits_are_revisions_that_can_be_reverted() {
+        let (mut storage, ids) = storage_with(&["a"]);
+        let now = Utc::now();
+        let original = storage.find_clip(&ids[0]).unwrap().metadata.clone();
+        storage
+            .update_clip_metadata(&ids[0], Some("Notes".to_string()), Some("Draft".to_string()), None, now)
+            .unwrap();
+        // Nothing to change, nothing recorded
+        storage.update_clip_metadata(&ids[0], Some("Notes".to_string()), None, None, now).unwrap();
+        storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], "b"), false, None).unwrap();
+
+        let revisions = storage.clip_revisions(&ids[0]).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!((revisions[0].cause.as_str(), revisions[0].content.as_str()), ("manual", "a"));
+        assert_eq!(revisions[0].metadata.source_app, "Notes");
+        assert_eq!((revisions[1].cause.as_str(), revisions[1].content.as_str()), ("metadata", "a"));
+        assert_eq!(revisions[1].metadata.source_app, original.source_app);
+        assert_eq!(revisions[1].metadata.window_title, original.window_title);
+
+        let revert = storage.find_clip(&ids[0]).unwrap().plan_revert(revisions[1].index, now).unwrap();
+        let clip = storage.revert_clip(&ids[0], revert).unwrap();
+        assert_eq!(clip.content, "a");
+        assert!(clip.metadata.same_origin(&original));
+        // Both halves of the revert can be reverted in turn
+        let revisions = storage.clip_revisions(&ids[0]).unwrap();
+        assert_eq!(revisions[0].metadata.source_app, "Notes");
+        assert_eq!(revisions[1].content, "b");
+    }
+
+    #[test]
+    fn locked_clips_refuse_content_changes() {
+        let (mut storage, ids) = storage_with(&["foo", "foo too"]);
+        storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], "foo again"), false, None).unwrap();
+        let planned = edit(&storage, &ids[0], "edited");
+        let revert = storage.find_clip(&ids[0]).unwrap().plan_revert(0, Utc::now()).unwrap();
+        let provenance = Provenance {
+            operation: "run_preset".to_string(),
+            source_ids: vec![ids[0].clone()],
+            model: None,
+            detail: None,
+            omitted_ids: Vec::new(),
+        };
+        set_flags(&mut storage, &ids[0], false, true);
+
+        assert!(storage.edit_clip_content(&ids[0], planned.clone(), false, None).unwrap_err().starts_with("Locked"));
+        assert!(storage.revert_clip(&ids[0], revert).unwrap_err().starts_with("Locked"));
+        assert!(storage.rewrite_clip(&ids[0], planned, provenance).unwrap_err().starts_with("Locked"));
+        let pattern = build_find_regex("foo", false).unwrap();
+        let replaced = storage.find_replace_clips(&pattern, "baz", false, None, false);
+        assert_eq!((replaced.clips.len(), replaced.changed), (1, 1));
+        assert_eq!(contents(&storage), vec!["baz too", "foo again"]);
+    }
+
+
+    #[test]
+    fn names_are_trimmed_and_bounded() {
+        assert_eq!(validate_name("pastebook name", "  Notes \n").unwrap(), "Notes");
+        assert_eq!(
+            validate_name("pastebook name", "   ").unwrap_err(),
+            "Validation: pastebook name must not be empty"
+        );
+        assert_eq!(
+            validate_name("group name", &"x".repeat(101)).unwrap_err(),
+            "Validation: group name must be at most 100 characters"
+        );
+        assert!(validate_name("group name", &"é".repeat(100)).is_ok());
+        assert_eq!(
+            validate_name("template name", "\u{7}\u{1b}").unwrap_err(),
+            "Validation: template name must not be only control characters"
+        );
+    }
+
+    #[test]
+    fn blank_names_never_reach_pastebooks_or_groups() {
+        let mut storage = AppStorage::default();
+        let id = storage.pastebooks[0].id.clone();
+        assert!(storage.create_pastebook("".to_string()).unwrap_err().starts_with("Validation"));
+        assert!(storage.rename_pastebook(&id, "   ".to_string()).unwrap_err().starts_with("Validation"));
+        assert!(storage.set_pastebook_group(&id, Some("\u{0}".to_string())).unwrap_err().starts_with("Validation"));
+        // A blank group still just ungroups
+        assert_eq!(storage.set_pastebook_group(&id, Some("  ".to_string())).unwrap().group, None);
+        storage.set_pastebook_group(&id, Some("G".to_string())).unwrap();
+        assert!(storage.rename_pastebook_group("G", " ").unwrap_err().starts_with("Validation"));
+    }
+
+    #[test]
+    fn emptying_a_clip_needs_allow_empty() {
+        let (mut storage, ids) = storage_with(&["text"]);
+        let err = storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], " \n"), false, None).unwrap_err();
+        assert!(err.starts_with("Validation: content must not be empty"));
+        assert_eq!(contents(&storage), vec!["text"]);
+        assert!(storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], ""), true, None).unwrap().is_some());
+        assert_eq!(contents(&storage), vec![""]);
+    }
+
+    #[test]
+    fn rapid_edits_share_a_revision() {
+        let (mut storage, ids) = storage_with(&["Draft"]);
+        let mut typed = String::from("Draft");
+        for c in " about 👨‍👩‍👧 and café\r\n".chars().cycle().take(50) {
+            typed.push(c);
+            assert!(storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], &typed), false, None).unwrap().is_some());
+        }
+        let clip = storage.get_clip(&ids[0]).unwrap();
+        assert!(clip.history.len() <= 3, "{} revisions", clip.history.len());
+        assert_eq!(clip.revisions().unwrap().last().unwrap().content, "Draft");
+
+        // Paced-out edits each keep their own revision
+        let mut clip = clip.clone();
+        let start = clip.history.last().unwrap().at;
+        for i in 1..=4 {
+            clip.set_content(format!("version {}", i), "manual", start + Duration::seconds(60 * i));
         }
+        assert_eq!(clip.history.len(), 5);
+        clip.set_content("version 4".to_string(), "manual", start + Duration::seconds(600));
+        assert_eq!(clip.history.len(), 5);
+    }
+
+    #[test]
+    fn revert_restores_exact_content() {
+        let original = "  Line one\r\n\tcafe\u{301} 👍🏽  \n\nend without newline ";
+        let (mut storage, ids) = storage_with(&[original]);
+        let id = ids[0].clone();
+        storage.edit_clip_content(&id, edit(&storage, &id, "Line one, reworded\nend"), false, None).unwrap();
+        storage.get_active_pastebook_mut().unwrap().clips[0].history[0].at -= Duration::minutes(5);
+        storage.edit_clip_content(&id, edit(&storage, &id, "Something else entirely"), false, None).unwrap();
+
+        let revisions = storage.clip_revisions(&id).unwrap();
+        assert_eq!(revisions.len(), 2);
+        assert_eq!(revisions[0].content, "Line one, reworded\nend");
+        assert_eq!(revisions[1].content.as_bytes(), original.as_bytes());
+
+        let revert = |storage: &AppStorage, index| storage.find_clip(&id).unwrap().plan_revert(index, Utc::now());
+        let reverted = storage.revert_clip(&id, revert(&storage, 0).unwrap()).unwrap();
+        assert_eq!(reverted.content.as_bytes(), original.as_bytes());
+        // The revert is a revision too; undoing it gets the latest edit back
+        assert_eq!(reverted.history.last().unwrap().cause, "revert");
+        assert_eq!(storage.revert_clip(&id, revert(&storage, 2).unwrap()).unwrap().content, "Something else entirely");
+        assert!(revert(&storage, 9).unwrap_err().starts_with("NotFound"));
+        assert_eq!(storage.search_clips("entirely").len(), 1);
+    }
+
+    #[test]
+    fn edits_planned_on_a_stale_copy_diff_again() {
+        let (mut storage, ids) = storage_with(&["first"]);
+        let stale = edit(&storage, &ids[0], "third");
+        storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], "second"), false, None).unwrap();
+        storage.get_active_pastebook_mut().unwrap().clips[0].history[0].at -= Duration::minutes(5);
+        storage.edit_clip_content(&ids[0], stale, false, None).unwrap();
+
+        let revisions = storage.clip_revisions(&ids[0]).unwrap();
+        let contents: Vec<_> = revisions.iter().map(|r| r.content.as_str()).collect();
+        assert_eq!(contents, ["second", "first"]);
+        assert_eq!(storage.get_clip(&ids[0]).unwrap().content, "third");
+    }
+
+    #[test]
+    fn sensitive_clips_keep_no_history() {
+        let (mut storage, ids) = storage_with(&["old password"]);
+        storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], "new password"), false, None).unwrap();
+        assert_eq!(storage.clip_revisions(&ids[0]).unwrap().len(), 1);
+
+        storage.set_clip_sensitive(&ids[0], true, None);
+        assert!(storage.clip_revisions(&ids[0]).unwrap().is_empty());
+        storage.edit_clip_content(&ids[0], edit(&storage, &ids[0], "newer password"), false, None).unwrap();
+        let clip = storage.get_clip(&ids[0]).unwrap();
+        assert_eq!(clip.content, "newer password");
+        assert!(clip.history.is_empty());
+    }
+
+    #[test]
+    fn imports_skip_blank_clips() {
+        let mut storage = AppStorage::default();
+        let id = storage.pastebooks[0].id.clone();
+        let added = storage.import_clips(&id, vec![clip("  "), clip("kept")]).unwrap();
+        assert_eq!(added, 1);
+        assert_eq!(contents(&storage), vec!["kept"]);
     }
+
 }
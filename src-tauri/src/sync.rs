@@ -0,0 +1,719 @@
+use aes_gcm::aead::rand_core::RngCore;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Key, Nonce};
+use base64::{engine::general_purpose::STANDARD as BASE64, Engine};
+use chrono::{DateTime, Utc};
+use pbkdf2::pbkdf2_hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::time::Duration;
+use uuid::Uuid;
+
+use crate::storage::{self, content_hash, AppStorage, ClipObject, Pastebook};
+
+/// How often the background reconciliation runs
+pub const SYNC_INTERVAL: Duration = Duration::from_secs(5 * 60);
+
+const SALT_FILE: &str = "stack-sync-salt.txt";
+const DEVICE_FILE_PREFIX: &str = "device-";
+const DEVICE_FILE_SUFFIX: &str = ".json";
+const KDF_ROUNDS: u32 = if cfg!(test) { 1_000 } else { 100_000 };
+const MIN_PASSPHRASE_LEN: usize = 8;
+
+/// One sync pass at a time, so passes don't race on the device log
+static PASS: Mutex<()> = Mutex::new(());
+
+/// The AES-256 key derived from the passphrase, kept out of Debug output
+#[derive(Clone)]
+struct SyncKey([u8; 32]);
+
+impl std::fmt::Debug for SyncKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("SyncKey(..)")
+    }
+}
+
+/// Persisted sync configuration and bookkeeping for this device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncState {
+    pub dir: PathBuf,
+    /// The key, in memory only
+    #[serde(skip)]
+    key: Option<SyncKey>,
+    /// The key sealed with DPAPI for this Windows user (base64). Other
+    /// platforms store nothing and need the passphrase again after a restart.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sealed_key: Option<String>,
+    /// The plaintext key older versions stored; sealed and dropped on load
+    #[serde(default, rename = "key", skip_serializing)]
+    legacy_key: Option<String>,
+    pub device_id: String,
+    /// Sequence number of the last operation this device wrote
+    seq: u64,
+    /// Highest applied sequence number per remote device
+    #[serde(default)]
+    applied: HashMap<String, u64>,
+    /// Clip id -> fingerprint as of the last sync, used to detect local changes
+    #[serde(default)]
+    snapshot: HashMap<String, String>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum SyncOpKind {
+    Upsert {
+        pastebook_id: String,
+        pastebook_name: String,
+        clip: Box<ClipObject>,
+    },
+    Delete {
+        clip_id: String,
+    },
+}
+
+impl SyncOpKind {
+    fn clip_id(&self) -> &str {
+        match self {
+            SyncOpKind::Upsert { clip, .. } => &clip.id,
+            SyncOpKind::Delete { clip_id } => clip_id,
+        }
+    }
+}
+
+/// One entry in a device's operation log
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SyncOp {
+    seq: u64,
+    at: DateTime<Utc>,
+    #[serde(flatten)]
+    kind: SyncOpKind,
+}
+
+/// On-disk envelope of an encrypted device log
+#[derive(Debug, Serialize, Deserialize)]
+struct EncryptedFile {
+    version: u32,
+    nonce: String,
+    data: String,
+}
+
+/// Result of a sync pass
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct SyncReport {
+    pub pushed: usize,
+    pub applied: usize,
+    pub conflicts: usize,
+    pub errors: Vec<String>,
+}
+
+/// Sync status for the settings UI
+#[derive(Debug, Clone, Serialize)]
+pub struct SyncStatus {
+    pub configured: bool,
+    pub dir: Option<PathBuf>,
+    pub device_id: Option<String>,
+    pub last_sync: Option<DateTime<Utc>>,
+    pub last_error: Option<String>,
+    pub remote_devices: Vec<String>,
+}
+
+fn device_file(dir: &Path, device_id: &str) -> PathBuf {
+    dir.join(format!(
+        "{}{}{}",
+        DEVICE_FILE_PREFIX, device_id, DEVICE_FILE_SUFFIX
+    ))
+}
+
+/// List the device ids that have a log in the sync dir, excluding `own_id`
+fn remote_devices(dir: &Path, own_id: &str) -> Vec<String> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+
+    let mut devices: Vec<String> = entries
+        .flatten()
+        .filter_map(|e| {
+            let name = e.file_name().to_string_lossy().into_owned();
+            name.strip_prefix(DEVICE_FILE_PREFIX)
+                .and_then(|n| n.strip_suffix(DEVICE_FILE_SUFFIX))
+                .map(str::to_string)
+        })
+        .filter(|id| id != own_id)
+        .collect();
+    devices.sort();
+    devices
+}
+
+/// Read the shared salt from the sync dir, creating it on first use
+fn read_or_create_salt(dir: &Path) -> Result<Vec<u8>, String> {
+    let path = dir.join(SALT_FILE);
+
+    if let Ok(encoded) = fs::read_to_string(&path) {
+        return BASE64
+            .decode(encoded.trim())
+            .map_err(|e| format!("Corrupt sync salt file: {}", e));
+    }
+
+    let mut salt = [0u8; 16];
+    OsRng.fill_bytes(&mut salt);
+    fs::write(&path, BASE64.encode(salt))
+        .map_err(|e| format!("Failed to write sync salt: {}", e))?;
+    Ok(salt.to_vec())
+}
+
+fn derive_key(passphrase: &str, salt: &[u8]) -> [u8; 32] {
+    let mut key = [0u8; 32];
+    pbkdf2_hmac::<Sha256>(passphrase.as_bytes(), salt, KDF_ROUNDS, &mut key);
+    key
+}
+
+/// Seal a key so only this Windows user can unseal it
+#[cfg(windows)]
+fn seal(key: &[u8; 32]) -> Option<String> {
+    use windows::core::PCWSTR;
+    use windows::Win32::Foundation::{LocalFree, HLOCAL};
+    use windows::Win32::Security::Cryptography::{CryptProtectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB};
+
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: key.len() as u32,
+        pbData: key.as_ptr() as *mut u8,
+    };
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        CryptProtectData(&input, PCWSTR::null(), None, None, None, CRYPTPROTECT_UI_FORBIDDEN, &mut output).ok()?;
+        let sealed = std::slice::from_raw_parts(output.pbData, output.cbData as usize).to_vec();
+        let _ = LocalFree(HLOCAL(output.pbData as *mut _));
+        Some(BASE64.encode(sealed))
+    }
+}
+
+#[cfg(windows)]
+fn unseal(sealed: &str) -> Option<[u8; 32]> {
+    use windows::Win32::Foundation::{LocalFree, HLOCAL};
+    use windows::Win32::Security::Cryptography::{CryptUnprotectData, CRYPTPROTECT_UI_FORBIDDEN, CRYPT_INTEGER_BLOB};
+
+    let mut sealed = BASE64.decode(sealed).ok()?;
+    let input = CRYPT_INTEGER_BLOB {
+        cbData: sealed.len() as u32,
+        pbData: sealed.as_mut_ptr(),
+    };
+    let mut output = CRYPT_INTEGER_BLOB::default();
+    unsafe {
+        CryptUnprotectData(&input, None, None, None, None, CRYPTPROTECT_UI_FORBIDDEN, &mut output).ok()?;
+        let key = std::slice::from_raw_parts(output.pbData, output.cbData as usize).try_into().ok();
+        let _ = LocalFree(HLOCAL(output.pbData as *mut _));
+        key
+    }
+}
+
+#[cfg(not(windows))]
+fn seal(_key: &[u8; 32]) -> Option<String> {
+    None
+}
+
+#[cfg(not(windows))]
+fn unseal(_sealed: &str) -> Option<[u8; 32]> {
+    None
+}
+
+impl SyncState {
+    /// Move a plaintext key from an older version into memory and, where
+    /// possible, a sealed copy; saves never write the plaintext back
+    pub fn seal_legacy_key(&mut self) {
+        let Some(legacy) = self.legacy_key.take() else {
+            return;
+        };
+        if let Some(key) = BASE64.decode(legacy).ok().and_then(|key| <[u8; 32]>::try_from(key).ok()) {
+            self.sealed_key = seal(&key);
+            self.key = Some(SyncKey(key));
+        }
+    }
+
+    /// The key from memory or the sealed copy, kept in memory from then on
+    fn unlock(&mut self) -> Result<Aes256Gcm, String> {
+        self.seal_legacy_key();
+        if self.key.is_none() {
+            let sealed = self
+                .sealed_key
+                .as_deref()
+                .ok_or("Sync needs the passphrase again; reconfigure sync")?;
+            let key = unseal(sealed).ok_or("Stored sync key is invalid; reconfigure sync")?;
+            self.key = Some(SyncKey(key));
+        }
+        let key = self.key.as_ref().unwrap();
+        Ok(Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key.0)))
+    }
+}
+
+/// Read and decrypt a device log; a missing file is an empty log
+fn read_log(cipher: &Aes256Gcm, path: &Path) -> Result<Vec<SyncOp>, String> {
+    let Ok(raw) = fs::read_to_string(path) else {
+        return Ok(Vec::new());
+    };
+
+    let file: EncryptedFile =
+        serde_json::from_str(&raw).map_err(|e| format!("Unreadable sync file: {}", e))?;
+    let nonce = BASE64.decode(&file.nonce).map_err(|e| e.to_string())?;
+    let data = BASE64.decode(&file.data).map_err(|e| e.to_string())?;
+    if nonce.len() != 12 {
+        return Err("Unreadable sync file: bad nonce".to_string());
+    }
+
+    let plaintext = cipher
+        .decrypt(Nonce::from_slice(&nonce), data.as_ref())
+        .map_err(|_| "Wrong passphrase or corrupted sync file".to_string())?;
+
+    serde_json::from_slice(&plaintext).map_err(|e| format!("Unreadable sync file: {}", e))
+}
+
+/// Encrypt and atomically write a device log
+fn write_log(cipher: &Aes256Gcm, path: &Path, ops: &[SyncOp]) -> Result<(), String> {
+    let plaintext = serde_json::to_vec(ops).map_err(|e| format!("Failed to serialize: {}", e))?;
+    let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+    let data = cipher
+        .encrypt(&nonce, plaintext.as_ref())
+        .map_err(|_| "Failed to encrypt sync file".to_string())?;
+
+    let file = EncryptedFile {
+        version: 1,
+        nonce: BASE64.encode(nonce),
+        data: BASE64.encode(data),
+    };
+    let json = serde_json::to_string(&file).map_err(|e| format!("Failed to serialize: {}", e))?;
+
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, json).map_err(|e| format!("Failed to write sync file: {}", e))?;
+    fs::rename(&tmp, path).map_err(|e| format!("Failed to write sync file: {}", e))
+}
+
+/// Keep only the latest operation per clip; remote devices only need the final state
+fn compact(ops: Vec<SyncOp>) -> Vec<SyncOp> {
+    let mut latest: HashMap<String, SyncOp> = HashMap::new();
+    for op in ops {
+        let id = op.kind.clip_id().to_string();
+        match latest.get(&id) {
+            Some(existing) if existing.seq > op.seq => {}
+            _ => {
+                latest.insert(id, op);
+            }
+        }
+    }
+
+    let mut ops: Vec<SyncOp> = latest.into_values().collect();
+    ops.sort_by_key(|op| op.seq);
+    ops
+}
+
+fn fingerprint(clip: &ClipObject) -> String {
+    content_hash(&serde_json::to_string(clip).unwrap_or_default())
+}
+
+fn find_clip(storage: &AppStorage, id: &str) -> Option<(usize, usize)> {
+    storage
+        .pastebooks
+        .iter()
+        .enumerate()
+        .find_map(|(pi, p)| p.clips.iter().position(|c| c.id == id).map(|ci| (pi, ci)))
+}
+
+/// Insert a clip keeping the newest-first order by timestamp
+fn insert_by_time(pastebook: &mut Pastebook, clip: ClipObject) {
+    let index = pastebook
+        .clips
+        .iter()
+        .position(|c| c.metadata.timestamp < clip.metadata.timestamp)
+        .unwrap_or(pastebook.clips.len());
+    pastebook.clips.insert(index, clip);
+}
+
+/// Whether `incoming` should replace `local`: the one changed last wins,
+/// ties going to the higher fingerprint so every device picks the same one
+fn remote_wins(local: &ClipObject, incoming: &ClipObject) -> bool {
+    (incoming.modified_at(), fingerprint(incoming)) > (local.modified_at(), fingerprint(local))
+}
+
+/// Apply one remote operation, last writer wins; returns the id of the clip
+/// it changed, if any, and whether that overrode a local edit
+fn apply_remote_op(
+    storage: &mut AppStorage,
+    op: SyncOp,
+    locally_changed: &HashSet<String>,
+    known_before: &HashMap<String, String>,
+) -> Option<(String, bool)> {
+    match op.kind {
+        SyncOpKind::Upsert {
+            pastebook_id,
+            pastebook_name,
+            clip,
+        } => match find_clip(storage, &clip.id) {
+            Some((pi, ci)) => {
+                let local = &mut storage.pastebooks[pi].clips[ci];
+                if fingerprint(local) == fingerprint(&clip) || !remote_wins(local, &clip) {
+                    return None;
+                }
+                let id = clip.id.clone();
+                *local = *clip;
+                let overrode = locally_changed.contains(&id);
+                Some((id, overrode))
+            }
+            None => {
+                // Deleted here since the last sync, which is later than any
+                // edit another device logged before this pass
+                if known_before.contains_key(&clip.id) {
+                    return None;
+                }
+
+                if !storage.pastebooks.iter().any(|p| p.id == pastebook_id) {
+                    // Names stay valid and unique even when another device reused one
+                    let mut pastebook = Pastebook::new(storage.usable_pastebook_name(&pastebook_name));
+                    pastebook.id = pastebook_id.clone();
+                    storage.pastebooks.push(pastebook);
+                }
+                let pastebook = storage.pastebooks.iter_mut().find(|p| p.id == pastebook_id)?;
+                let id = clip.id.clone();
+                insert_by_time(pastebook, *clip);
+                Some((id, false))
+            }
+        },
+        SyncOpKind::Delete { clip_id } => {
+            // An edit made after the delete was logged wins and gets pushed again
+            let (pi, ci) = find_clip(storage, &clip_id)?;
+            if storage.pastebooks[pi].clips[ci].modified_at() > op.at {
+                return None;
+            }
+            storage.pastebooks[pi].clips.remove(ci);
+            let overrode = locally_changed.contains(&clip_id);
+            Some((clip_id, overrode))
+        }
+    }
+}
+
+/// Configure (or reconfigure) sync against a shared folder, keeping the
+/// bookkeeping of `previous` when it used the same folder. Derives the key
+/// and reads the folder, so call it without the storage lock.
+pub fn configure(previous: Option<SyncState>, dir: PathBuf, passphrase: &str) -> Result<SyncState, String> {
+    if !dir.is_dir() {
+        return Err(format!("{} is not a directory", dir.display()));
+    }
+    if passphrase.chars().count() < MIN_PASSPHRASE_LEN {
+        return Err(format!(
+            "Passphrase must be at least {} characters",
+            MIN_PASSPHRASE_LEN
+        ));
+    }
+
+    let salt = read_or_create_salt(&dir)?;
+    let key = derive_key(passphrase, &salt);
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key));
+
+    let device_id = previous
+        .as_ref()
+        .map(|s| s.device_id.clone())
+        .unwrap_or_else(|| Uuid::new_v4().simple().to_string());
+
+    // Make sure the passphrase matches what the other devices use
+    if let Some(remote) = remote_devices(&dir, &device_id).first() {
+        read_log(&cipher, &device_file(&dir, remote))
+            .map_err(|_| "Passphrase doesn't match the existing sync folder".to_string())?;
+    }
+
+    let previous = previous.filter(|s| s.dir == dir);
+
+    Ok(SyncState {
+        dir,
+        key: Some(SyncKey(key)),
+        sealed_key: seal(&key),
+        legacy_key: None,
+        device_id,
+        seq: previous.as_ref().map_or(0, |s| s.seq),
+        applied: previous
+            .as_ref()
+            .map(|s| s.applied.clone())
+            .unwrap_or_default(),
+        snapshot: previous.map(|s| s.snapshot).unwrap_or_default(),
+        last_sync: None,
+        last_error: None,
+    })
+}
+
+/// Push local changes and apply remote ones. The storage lock is held only
+/// to copy the clips out and to merge; the sync folder is read and written
+/// without it.
+pub fn sync(storage: &RwLock<AppStorage>) -> Result<SyncReport, String> {
+    let _pass = PASS.lock().unwrap_or_else(|e| e.into_inner());
+    let (mut state, pastebooks) = {
+        let storage = storage::loaded(storage);
+        let state = storage.sync.clone().ok_or("Sync is not configured")?;
+        (state, storage.pastebooks.clone())
+    };
+    let cipher = state.unlock()?;
+    let own_path = device_file(&state.dir, &state.device_id);
+    let mut report = SyncReport::default();
+
+    // An unreadable own log (e.g. after a passphrase change) is rebuilt from scratch
+    let mut own_ops = match read_log(&cipher, &own_path) {
+        Ok(ops) => ops,
+        Err(_) => {
+            state.snapshot.clear();
+            Vec::new()
+        }
+    };
+
+    // 1. Turn local changes since the last sync into operations
+    let now = Utc::now();
+    let logged_before = own_ops.len();
+    let current: HashMap<String, String> = pastebooks
+        .iter()
+        .flat_map(|p| p.clips.iter())
+        .map(|c| (c.id.clone(), fingerprint(c)))
+        .collect();
+    let mut locally_changed = HashSet::new();
+
+    for pastebook in &pastebooks {
+        for clip in &pastebook.clips {
+            if state.snapshot.get(&clip.id) != current.get(&clip.id) {
+                state.seq += 1;
+                own_ops.push(SyncOp {
+                    seq: state.seq,
+                    at: clip.modified_at(),
+                    kind: SyncOpKind::Upsert {
+                        pastebook_id: pastebook.id.clone(),
+                        pastebook_name: pastebook.name.clone(),
+                        clip: Box::new(clip.clone()),
+                    },
+                });
+                locally_changed.insert(clip.id.clone());
+            }
+        }
+    }
+    drop(pastebooks);
+
+    let mut deleted: Vec<&String> = state
+        .snapshot
+        .keys()
+        .filter(|id| !current.contains_key(*id))
+        .collect();
+    deleted.sort();
+    for id in deleted {
+        state.seq += 1;
+        own_ops.push(SyncOp {
+            seq: state.seq,
+            at: now,
+            kind: SyncOpKind::Delete {
+                clip_id: id.clone(),
+            },
+        });
+    }
+    report.pushed = own_ops.len() - logged_before;
+    write_log(&cipher, &own_path, &compact(own_ops))?;
+
+    // 2. Read operations from other devices, oldest first
+    let mut applied = state.applied.clone();
+    let mut incoming: Vec<(String, SyncOp)> = Vec::new();
+    for device in remote_devices(&state.dir, &state.device_id) {
+        let seen = applied.get(&device).copied().unwrap_or(0);
+        match read_log(&cipher, &device_file(&state.dir, &device)) {
+            Ok(ops) => {
+                if let Some(max_seq) = ops.iter().map(|op| op.seq).max() {
+                    applied.insert(device.clone(), max_seq.max(seen));
+                }
+                incoming.extend(
+                    ops.into_iter()
+                        .filter(|op| op.seq > seen)
+                        .map(|op| (device.clone(), op)),
+                );
+            }
+            Err(e) => report.errors.push(format!("device {}: {}", device, e)),
+        }
+    }
+    incoming.sort_by(|(da, a), (db, b)| a.at.cmp(&b.at).then(da.cmp(db)).then(a.seq.cmp(&b.seq)));
+
+    // 3. Merge them and remember what we've seen
+    let mut storage = storage.write().unwrap();
+    let unchanged = storage
+        .sync
+        .as_ref()
+        .is_some_and(|s| s.dir == state.dir && s.device_id == state.device_id);
+    if !unchanged {
+        return Err("Sync was reconfigured during the pass".to_string());
+    }
+
+    let known_before = std::mem::replace(&mut state.snapshot, current);
+    for (_, op) in incoming {
+        report.applied += 1;
+        if let Some((id, overrode)) = apply_remote_op(&mut storage, op, &locally_changed, &known_before) {
+            if overrode {
+                report.conflicts += 1;
+            }
+            // What we now hold is what the other device has
+            match find_clip(&storage, &id) {
+                Some((pi, ci)) => state.snapshot.insert(id, fingerprint(&storage.pastebooks[pi].clips[ci])),
+                None => state.snapshot.remove(&id),
+            };
+        }
+    }
+
+    if report.applied > 0 {
+        storage.rebuild_search_index();
+    }
+
+    state.applied = applied;
+    state.last_sync = Some(Utc::now());
+    state.last_error = if report.errors.is_empty() {
+        None
+    } else {
+        Some(report.errors.join("; "))
+    };
+    storage.sync = Some(state);
+
+    Ok(report)
+}
+
+/// Current sync status
+pub fn status(state: Option<&SyncState>) -> SyncStatus {
+    match state {
+        Some(state) => SyncStatus {
+            configured: true,
+            dir: Some(state.dir.clone()),
+            device_id: Some(state.device_id.clone()),
+            last_sync: state.last_sync,
+            last_error: state.last_error.clone(),
+            remote_devices: remote_devices(&state.dir, &state.device_id),
+        },
+        None => SyncStatus {
+            configured: false,
+            dir: None,
+            device_id: None,
+            last_sync: None,
+            last_error: None,
+            remote_devices: Vec::new(),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+    use chrono::Duration as ChronoDuration;
+
+    const PASSPHRASE: &str = "correct horse battery";
+
+    /// A device with one pastebook, syncing through `dir`
+    fn device(dir: &Path) -> RwLock<AppStorage> {
+        let mut storage = AppStorage::default();
+        storage.sync = Some(configure(None, dir.to_path_buf(), PASSPHRASE).unwrap());
+        RwLock::new(storage)
+    }
+
+    fn contents(device: &RwLock<AppStorage>) -> Vec<String> {
+        let storage = device.read().unwrap();
+        let mut contents: Vec<String> = storage
+            .pastebooks
+            .iter()
+            .flat_map(|p| p.clips.iter().map(|c| c.content.clone()))
+            .collect();
+        contents.sort();
+        contents
+    }
+
+    fn edit(device: &RwLock<AppStorage>, id: &str, content: &str, at: DateTime<Utc>) {
+        let mut storage = device.write().unwrap();
+        let (pi, ci) = find_clip(&storage, id).unwrap();
+        let clip = &mut storage.pastebooks[pi].clips[ci];
+        clip.content = content.to_string();
+        clip.edited_at = Some(at);
+    }
+
+    #[test]
+    fn clips_reach_the_other_device() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (device(dir.path()), device(dir.path()));
+        a.write().unwrap().pastebooks[0].clips.push(clip("from a"));
+
+        assert_eq!(sync(&a).unwrap().pushed, 1);
+        assert_eq!(sync(&b).unwrap().applied, 1);
+        assert_eq!(contents(&b), ["from a"]);
+        // Nothing new the second time round
+        let again = sync(&b).unwrap();
+        assert_eq!((again.pushed, again.applied), (0, 0));
+    }
+
+    #[test]
+    fn the_later_edit_wins_on_both_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (device(dir.path()), device(dir.path()));
+        let shared = clip("original");
+        let id = shared.id.clone();
+        a.write().unwrap().pastebooks[0].clips.push(shared);
+        sync(&a).unwrap();
+        sync(&b).unwrap();
+
+        let now = Utc::now();
+        // b edits later but syncs first
+        edit(&b, &id, "edited on b", now);
+        edit(&a, &id, "edited on a", now - ChronoDuration::minutes(1));
+        sync(&b).unwrap();
+        let report = sync(&a).unwrap();
+        assert_eq!(report.conflicts, 1);
+        sync(&b).unwrap();
+
+        assert_eq!(contents(&a), ["edited on b"]);
+        assert_eq!(contents(&b), ["edited on b"]);
+    }
+
+    #[test]
+    fn an_edit_after_a_delete_survives_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (device(dir.path()), device(dir.path()));
+        let (kept, gone) = (clip("kept"), clip("gone"));
+        let (kept_id, gone_id) = (kept.id.clone(), gone.id.clone());
+        a.write().unwrap().pastebooks[0].clips.extend([kept, gone]);
+        sync(&a).unwrap();
+        sync(&b).unwrap();
+
+        a.write().unwrap().pastebooks[0].clips.clear();
+        sync(&a).unwrap();
+        edit(&b, &kept_id, "kept, edited", Utc::now() + ChronoDuration::minutes(1));
+        sync(&b).unwrap();
+        sync(&a).unwrap();
+
+        assert_eq!(contents(&b), ["kept, edited"]);
+        assert_eq!(contents(&a), ["kept, edited"]);
+        assert!(find_clip(&b.read().unwrap(), &gone_id).is_none());
+    }
+
+    #[test]
+    fn the_key_is_never_saved_in_plain_text() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = configure(None, dir.path().to_path_buf(), PASSPHRASE).unwrap();
+        let key = BASE64.encode(state.key.as_ref().unwrap().0);
+        let json = serde_json::to_string(&state).unwrap();
+        assert!(!json.contains(&key));
+        assert!(!format!("{:?}", state).contains(&key));
+
+        // A plaintext key from an older version still works, and isn't written back
+        let mut legacy = serde_json::to_value(&state).unwrap();
+        legacy["key"] = key.clone().into();
+        legacy.as_object_mut().unwrap().remove("sealed_key");
+        let mut loaded: SyncState = serde_json::from_value(legacy).unwrap();
+        loaded.seal_legacy_key();
+        assert!(loaded.unlock().is_ok());
+        assert!(!serde_json::to_string(&loaded).unwrap().contains(&key));
+    }
+
+    #[test]
+    fn a_wrong_passphrase_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let a = device(dir.path());
+        sync(&a).unwrap();
+        let err = configure(None, dir.path().to_path_buf(), "another passphrase").unwrap_err();
+        assert!(err.contains("doesn't match"));
+    }
+}
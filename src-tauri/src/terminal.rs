@@ -0,0 +1,213 @@
+use regex::Regex;
+use std::iter::Peekable;
+use std::str::Chars;
+use std::sync::OnceLock;
+
+use crate::storage::ClipObject;
+use crate::textutil;
+
+/// Terminal emulators and shells, lowercased without ".exe"
+const TERMINAL_APPS: [&str; 12] = [
+    "windowsterminal", "wt", "cmd", "powershell", "pwsh", "conhost", "openconsole",
+    "wezterm-gui", "alacritty", "mintty", "kitty", "hyper",
+];
+/// Lines must be at least this wide before a shared length counts as wrapping
+const MIN_WRAP_COLUMN: usize = 40;
+const ESC: char = '\u{1b}';
+
+/// Whether a capture looks like it came from a terminal: a known terminal
+/// app, or escape sequences in the text (e.g. VS Code's integrated terminal)
+pub fn is_terminal_source(app_name: &str, content: &str) -> bool {
+    let app = app_name.to_lowercase();
+    let app = app.strip_suffix(".exe").unwrap_or(&app);
+    TERMINAL_APPS.contains(&app) || content.contains(ESC) || content.contains('\u{9b}')
+}
+
+/// Remove ANSI/VT escape sequences: CSI (colors, cursor moves), OSC (titles,
+/// hyperlinks) and two-character escapes
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            ESC => match chars.next() {
+                Some('[') => skip_csi(&mut chars),
+                Some(']') => skip_osc(&mut chars),
+                // A two-character escape; its second char goes too
+                _ => {}
+            },
+            '\u{9b}' => skip_csi(&mut chars),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// Parameters and intermediates, then one final byte in @..~
+fn skip_csi(chars: &mut impl Iterator<Item = char>) {
+    for c in chars.by_ref() {
+        if ('@'..='~').contains(&c) {
+            break;
+        }
+    }
+}
+
+/// Everything up to BEL or ESC \
+fn skip_osc(chars: &mut Peekable<Chars>) {
+    while let Some(c) = chars.next() {
+        if c == '\u{7}' || (c == ESC && chars.next_if_eq(&'\\').is_some()) {
+            break;
+        }
+    }
+}
+
+fn prompt_regex() -> &'static Regex {
+    static PROMPT: OnceLock<Regex> = OnceLock::new();
+    PROMPT.get_or_init(|| {
+        Regex::new(concat!(
+            r"^(?:",
+            r"[\u{2500}-\u{257F}]+\s*[❯➜λ$#>]?\s*",  // ╰─❯ and friends
+            r"|PS [A-Za-z]:\\[^>]*> ?",               // PowerShell
+            r"|[A-Za-z]:\\[^>]*>",                    // cmd
+            r"|[\w.-]+@[\w.-]+:[^$\n]*\$ ",           // user@host:~/dir$
+            r"|\$ |❯ |➜ ",
+            r")",
+        ))
+        .unwrap()
+    })
+}
+
+/// Drop shell prompts from the start of lines ("$ ", "PS C:\dir> ", ...)
+pub fn strip_prompts(text: &str) -> String {
+    text.lines()
+        .map(|line| prompt_regex().replace(line, ""))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Rejoin lines the terminal broke at its width. The width is taken to be
+/// the longest line's length, and only counts when at least two lines hit it
+/// exactly; lines of that length ending in a space are left alone.
+pub fn unwrap_lines(text: &str) -> String {
+    let lines: Vec<&str> = text.lines().collect();
+    let width = lines.iter().map(|l| textutil::grapheme_count(l)).max().unwrap_or(0);
+    let at_width = |line: &str| textutil::grapheme_count(line) == width && !line.ends_with(' ');
+    if width < MIN_WRAP_COLUMN || lines.iter().filter(|l| at_width(l)).count() < 2 {
+        return text.to_string();
+    }
+
+    let mut out = String::with_capacity(text.len());
+    for (i, line) in lines.iter().enumerate() {
+        out.push_str(line);
+        if i + 1 < lines.len() && !at_width(line) {
+            out.push('\n');
+        }
+    }
+    out
+}
+
+/// Full cleanup of terminal text: escapes, padding, wrapping and (when asked)
+/// prompts. A trailing newline in the capture is kept.
+pub fn clean(text: &str, strip_prompt_prefixes: bool) -> String {
+    let stripped = strip_ansi(&text.replace("\r\n", "\n"));
+    let trimmed: Vec<&str> = stripped.lines().map(str::trim_end).collect();
+    let mut cleaned = unwrap_lines(&trimmed.join("\n"));
+    if strip_prompt_prefixes {
+        cleaned = strip_prompts(&cleaned);
+    }
+    if text.ends_with('\n') && !cleaned.ends_with('\n') {
+        cleaned.push('\n');
+    }
+    cleaned
+}
+
+/// Clean a clip captured from a terminal, keeping the raw text in
+/// `original_content` when anything changed. Returns whether it did.
+pub fn clean_clip(clip: &mut ClipObject, strip_prompt_prefixes: bool) -> bool {
+    if !is_terminal_source(&clip.metadata.source_app, &clip.content) {
+        return false;
+    }
+    let cleaned = clean(&clip.content, strip_prompt_prefixes);
+    if cleaned == clip.content || cleaned.trim().is_empty() {
+        return false;
+    }
+    clip.original_content = Some(std::mem::replace(&mut clip.content, cleaned));
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::clip;
+
+    /// `ls --color=always` in a bash prompt, as copied from Windows Terminal
+    const COLORED_LS: &str = "\u{1b}]0;dev@box: ~/stack\u{7}\u{1b}[01;32mdev@box\u{1b}[00m:\u{1b}[01;34m~/stack\u{1b}[00m$ ls\n\u{1b}[0m\u{1b}[01;34mnode_modules\u{1b}[0m  package.json  \u{1b}[01;34msrc\u{1b}[0m  \u{1b}[01;34msrc-tauri\u{1b}[0m  \u{1b}[01;32mbuild.sh\u{1b}[0m\n";
+
+    /// PowerShell 7 error output, red, hard-wrapped at 80 columns
+    const POWERSHELL_ERROR: &str = concat!(
+        "PS C:\\Users\\dev> Get-Item C:\\Users\\dev\\missing\\file.txt\r\n",
+        "\u{1b}[31;1mGet-Item: \u{1b}[31;1mCannot find path 'C:\\Users\\dev\\missing\\file.txt' because it does not e\r\n",
+        "xist. The item may have been moved or deleted by another process and cannot be f\r\n",
+        "ound.\u{1b}[0m\r\n",
+        "PS C:\\Users\\dev>     \r\n",
+    );
+
+    #[test]
+    fn colored_ls_loses_escapes_and_keeps_columns() {
+        assert_eq!(
+            clean(COLORED_LS, false),
+            "dev@box:~/stack$ ls\nnode_modules  package.json  src  src-tauri  build.sh\n"
+        );
+        assert_eq!(
+            clean(COLORED_LS, true),
+            "ls\nnode_modules  package.json  src  src-tauri  build.sh\n"
+        );
+    }
+
+    #[test]
+    fn powershell_error_is_unwrapped_and_unprompted() {
+        let cleaned = clean(POWERSHELL_ERROR, true);
+        assert_eq!(
+            cleaned,
+            concat!(
+                "Get-Item C:\\Users\\dev\\missing\\file.txt\n",
+                "Get-Item: Cannot find path 'C:\\Users\\dev\\missing\\file.txt' because it does not exist. ",
+                "The item may have been moved or deleted by another process and cannot be found.\n",
+            )
+        );
+    }
+
+    #[test]
+    fn short_or_ragged_lines_are_not_joined() {
+        let text = "first line of a normal paragraph that is long\nsecond line, which is shorter\n";
+        assert_eq!(unwrap_lines(text), text);
+        let table = format!("{}\n{}\n", "a".repeat(30), "b".repeat(30));
+        assert_eq!(unwrap_lines(&table), table);
+    }
+
+    #[test]
+    fn box_drawing_and_cmd_prompts_are_stripped() {
+        let text = "╭─ ~/stack  main\n╰─❯ cargo test\nC:\\code>dir";
+        assert_eq!(strip_prompts(text), "~/stack  main\ncargo test\ndir");
+    }
+
+    #[test]
+    fn terminal_sources_are_detected() {
+        assert!(is_terminal_source("WindowsTerminal.exe", "plain"));
+        assert!(is_terminal_source("Code.exe", "\u{1b}[32mok\u{1b}[0m"));
+        assert!(!is_terminal_source("Code.exe", "fn main() {}"));
+    }
+
+    #[test]
+    fn clips_keep_their_raw_capture() {
+        let mut captured = clip("\u{1b}[1mbold\u{1b}[0m");
+        assert!(clean_clip(&mut captured, false));
+        assert_eq!(captured.content, "bold");
+        assert_eq!(captured.original_content.as_deref(), Some("\u{1b}[1mbold\u{1b}[0m"));
+
+        let mut plain = clip("nothing to do");
+        plain.metadata.source_app = "pwsh.exe".to_string();
+        assert!(!clean_clip(&mut plain, true));
+        assert!(plain.original_content.is_none());
+    }
+}
@@ -0,0 +1,73 @@
+use chrono::{DateTime, Duration, Utc};
+use std::path::PathBuf;
+use tempfile::TempDir;
+
+use crate::storage::{AppStorage, ClipObject};
+use crate::window::WindowInfo;
+
+/// A clip with `content`, captured from a fixed test app
+pub fn clip(content: &str) -> ClipObject {
+    ClipObject::new(
+        content.to_string(),
+        WindowInfo {
+            app_name: "test.exe".to_string(),
+            window_title: "Test Window".to_string(),
+            ..Default::default()
+        },
+    )
+}
+
+/// A clip with `content` captured at `at`
+pub fn clip_at(content: &str, at: DateTime<Utc>) -> ClipObject {
+    let mut clip = clip(content);
+    clip.metadata.timestamp = at;
+    clip
+}
+
+/// A clip captured `ms` milliseconds after `base`
+pub fn clip_after(content: &str, base: DateTime<Utc>, ms: i64) -> ClipObject {
+    clip_at(content, base + Duration::milliseconds(ms))
+}
+
+/// Storage backed by a file in a fresh temp dir; the dir lives as long as
+/// the returned guard
+pub struct TempStorage {
+    pub dir: TempDir,
+    pub storage: AppStorage,
+}
+
+impl TempStorage {
+    pub fn new() -> Self {
+        let dir = tempfile::tempdir().expect("temp dir");
+        let storage = AppStorage::load_from(dir.path().join("pastebooks.json"));
+        Self { dir, storage }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        self.dir.path().join("pastebooks.json")
+    }
+
+    /// Load the file again, as a restart would
+    pub fn reload(&self) -> AppStorage {
+        AppStorage::load_from(self.path())
+    }
+}
+
+/// In-memory storage holding `contents` in the active pastebook, newest
+/// (last given) first, plus their ids in the order given
+pub fn storage_with(contents: &[&str]) -> (AppStorage, Vec<String>) {
+    let mut storage = AppStorage::default();
+    let base = Utc::now() - Duration::hours(1);
+    let mut ids = Vec::new();
+    for (i, content) in contents.iter().enumerate() {
+        let clip = clip_after(content, base, i as i64 * 1000);
+        ids.push(clip.id.clone());
+        storage.add_clip(clip).unwrap();
+    }
+    (storage, ids)
+}
+
+/// Contents of the active pastebook's clips, in order
+pub fn contents(storage: &AppStorage) -> Vec<String> {
+    storage.get_clips().into_iter().map(|c| c.content).collect()
+}
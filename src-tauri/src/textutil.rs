@@ -0,0 +1,155 @@
+use unicode_segmentation::{GraphemeCursor, UnicodeSegmentation};
+
+/// Number of user-perceived characters (grapheme clusters): a flag, a family
+/// emoji or an e with a combining accent each count as one
+pub fn grapheme_count(text: &str) -> usize {
+    // In ASCII only CR LF joins into one cluster; skip segmenting the common case
+    if text.is_ascii() {
+        return text.len() - text.matches("\r\n").count();
+    }
+    text.graphemes(true).count()
+}
+
+/// Compare a presented secret with the expected one in time that doesn't
+/// depend on where they first differ
+pub fn secrets_match(presented: &str, expected: &str) -> bool {
+    let (a, b) = (presented.as_bytes(), expected.as_bytes());
+    a.len() == b.len() && a.iter().zip(b).fold(0u8, |diff, (x, y)| diff | (x ^ y)) == 0
+}
+
+/// The first `max` grapheme clusters of `text`
+pub fn truncate(text: &str, max: usize) -> &str {
+    match text.grapheme_indices(true).nth(max) {
+        Some((end, _)) => &text[..end],
+        None => text,
+    }
+}
+
+/// The longest prefix of `text` that fits in `max_bytes` without splitting
+/// a grapheme cluster
+pub fn truncate_bytes(text: &str, max_bytes: usize) -> &str {
+    if text.len() <= max_bytes {
+        return text;
+    }
+    &text[..floor_boundary(text, max_bytes)]
+}
+
+/// Snap a byte offset down to the nearest grapheme cluster boundary
+pub fn floor_boundary(text: &str, index: usize) -> usize {
+    let index = floor_char_boundary(text, index);
+    let mut cursor = GraphemeCursor::new(index, text.len(), true);
+    if cursor.is_boundary(text, 0).unwrap_or(true) {
+        return index;
+    }
+    cursor.prev_boundary(text, 0).ok().flatten().unwrap_or(0)
+}
+
+/// Snap a byte offset up to the nearest grapheme cluster boundary
+pub fn ceil_boundary(text: &str, index: usize) -> usize {
+    let index = ceil_char_boundary(text, index);
+    let mut cursor = GraphemeCursor::new(index, text.len(), true);
+    if cursor.is_boundary(text, 0).unwrap_or(true) {
+        return index;
+    }
+    cursor.next_boundary(text, 0).ok().flatten().unwrap_or(text.len())
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+fn ceil_char_boundary(text: &str, mut index: usize) -> usize {
+    index = index.min(text.len());
+    while !text.is_char_boundary(index) {
+        index += 1;
+    }
+    index
+}
+
+/// Split into words, punctuation and whitespace runs by Unicode word
+/// boundaries, so joining the pieces gives back the exact text
+pub fn word_bounds(text: &str) -> impl Iterator<Item = &str> {
+    text.split_word_bounds()
+}
+
+/// Split into sentences, each keeping its trailing punctuation and spaces
+pub fn sentences(text: &str) -> impl Iterator<Item = &str> {
+    text.split_sentence_bounds()
+}
+
+/// Whole sentences from the start of `text` up to `max` grapheme clusters;
+/// the first sentence is cut if even it doesn't fit
+pub fn truncate_sentences(text: &str, max: usize) -> &str {
+    let mut end = 0;
+    let mut count = 0;
+    for sentence in sentences(text) {
+        count += grapheme_count(sentence);
+        if count > max {
+            break;
+        }
+        end += sentence.len();
+    }
+    if end == 0 {
+        return truncate(text, max);
+    }
+    &text[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Text mixing ZWJ emoji sequences, flags, skin tones, combining marks,
+    /// Arabic with harakat, Hangul jamo and CRLF
+    const SAMPLES: &[&str] = &[
+        "👨‍👩‍👧‍👦 family, 🏳️‍🌈 flag and 👍🏽 thumbs",
+        "e\u{301}te\u{301} cafe\u{301} n\u{303}o Z\u{35f}\u{36d}\u{34f}a\u{35c}l\u{308}g\u{301}o",
+        "مَرْحَبًا بِالْعَالَمِ، كَيْفَ حَالُكَ؟",
+        "🇬🇧🇫🇷🇯🇵\r\n\u{1100}\u{1161}\u{11A8}한국어 ok",
+    ];
+
+    /// Every cut lands on a cluster boundary: what's kept plus the rest
+    /// regroup into exactly the original clusters
+    fn assert_clean_cut(text: &str, kept: &str) {
+        assert!(text.starts_with(kept));
+        let rest = &text[kept.len()..];
+        let mut rejoined: Vec<&str> = kept.graphemes(true).collect();
+        rejoined.extend(rest.graphemes(true));
+        assert_eq!(rejoined, text.graphemes(true).collect::<Vec<_>>(), "split a cluster at {}", kept.len());
+    }
+
+    #[test]
+    fn truncation_never_splits_a_grapheme_cluster() {
+        let mixed = SAMPLES.concat();
+        for text in SAMPLES.iter().copied().chain([mixed.as_str()]) {
+            for max in 0..=grapheme_count(text) + 1 {
+                let kept = truncate(text, max);
+                assert_eq!(grapheme_count(kept), max.min(grapheme_count(text)));
+                assert_clean_cut(text, kept);
+                assert_clean_cut(text, truncate_sentences(text, max));
+            }
+            for max in 0..=text.len() + 1 {
+                let kept = truncate_bytes(text, max);
+                assert!(kept.len() <= max);
+                assert_clean_cut(text, kept);
+                let up = ceil_boundary(text, max);
+                assert!(up >= max.min(text.len()));
+                assert_clean_cut(text, &text[..up]);
+            }
+        }
+    }
+
+    #[test]
+    fn clusters_count_as_one() {
+        assert_eq!(grapheme_count("👨‍👩‍👧‍👦"), 1);
+        assert_eq!(grapheme_count("a\r\n\r\rb\n"), "a\r\n\r\rb\n".graphemes(true).count());
+        assert_eq!(truncate("e\u{301}x", 1), "e\u{301}");
+        assert_eq!(truncate_bytes("👍🏽!", 5), "");
+        assert_eq!(word_bounds("hi, there").collect::<Vec<_>>(), ["hi", ",", " ", "there"]);
+        assert_eq!(truncate_sentences("One. Two. Three.", 10), "One. Two. ");
+    }
+}
@@ -0,0 +1,77 @@
+use tauri::plugin::TauriPlugin;
+use tauri::{AppHandle, Emitter, Manager, Runtime, Theme};
+
+use crate::storage::ThemePreference;
+
+/// What the OS asks apps to use, read straight from the registry so it's
+/// known before any window exists
+#[cfg(windows)]
+pub fn system_theme() -> Option<Theme> {
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_DWORD};
+
+    let mut value: u32 = 0;
+    let mut size = std::mem::size_of::<u32>() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            &HSTRING::from("Software\\Microsoft\\Windows\\CurrentVersion\\Themes\\Personalize"),
+            &HSTRING::from("AppsUseLightTheme"),
+            RRF_RT_REG_DWORD,
+            None,
+            Some(&mut value as *mut u32 as *mut _),
+            Some(&mut size),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return None;
+    }
+    Some(if value == 0 { Theme::Dark } else { Theme::Light })
+}
+
+#[cfg(not(windows))]
+pub fn system_theme() -> Option<Theme> {
+    // Other platforms only report it through a window
+    None
+}
+
+/// The theme an explicit preference forces on window chrome (None follows the OS)
+pub fn preferred_theme(preference: ThemePreference) -> Option<Theme> {
+    match preference {
+        ThemePreference::System => None,
+        ThemePreference::Light => Some(Theme::Light),
+        ThemePreference::Dark => Some(Theme::Dark),
+    }
+}
+
+/// Resolve the preference to light or dark, asking the OS (then the main
+/// window) when it's "system"
+pub fn effective_theme(app: Option<&AppHandle>, preference: ThemePreference) -> Theme {
+    preferred_theme(preference)
+        .or_else(system_theme)
+        .or_else(|| {
+            app.and_then(|app| app.get_webview_window("main"))
+                .and_then(|window| window.theme().ok())
+        })
+        .unwrap_or(Theme::Dark)
+}
+
+/// Plugin whose init script tags every webview with the startup theme before
+/// the page loads, so the first paint already uses the right one
+pub fn init<R: Runtime>(theme: Theme) -> TauriPlugin<R> {
+    let script = format!(
+        "window.__STACK_THEME__ = '{theme}';\
+         (function apply() {{\
+           if (document.documentElement) document.documentElement.dataset.theme = '{theme}';\
+           else document.addEventListener('readystatechange', apply, {{ once: true }});\
+         }})();"
+    );
+    tauri::plugin::Builder::new("theme").js_init_script(script).build()
+}
+
+/// Point window chrome at the preference and tell every window the result
+pub fn apply(app: &AppHandle, preference: ThemePreference) {
+    app.set_theme(preferred_theme(preference));
+    let _ = app.emit("theme-changed", effective_theme(Some(app), preference));
+}
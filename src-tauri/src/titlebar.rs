@@ -0,0 +1,181 @@
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+use tauri::{AppHandle, Manager};
+
+use crate::tray;
+
+/// Title changes inside this window are coalesced into one update
+const DEBOUNCE: Duration = Duration::from_millis(250);
+
+struct TitleState {
+    /// Latest title wanted, waiting for the debounce
+    pending: Option<String>,
+    /// Title the window currently has
+    applied: Option<String>,
+    flush_scheduled: bool,
+    /// New clips across every pastebook, waiting for the debounce
+    pending_new: Option<usize>,
+    /// New-clip count the badge and tray currently show
+    applied_new: usize,
+    badge_enabled: bool,
+}
+
+static STATE: Mutex<TitleState> = Mutex::new(TitleState {
+    pending: None,
+    applied: None,
+    flush_scheduled: false,
+    pending_new: None,
+    applied_new: 0,
+    badge_enabled: true,
+});
+static APP: OnceLock<AppHandle> = OnceLock::new();
+
+/// Give the title bar an app handle; applies whatever title is pending
+pub fn attach(app: AppHandle) {
+    let _ = APP.set(app);
+    flush();
+}
+
+pub fn title_for(active: Option<(&str, usize)>) -> String {
+    match active {
+        Some((name, count)) => format!("Stack — {} ({})", name, count),
+        None => "Stack".to_string(),
+    }
+}
+
+/// Record the active pastebook and the new-clip count after a change. Cheap
+/// enough to call after every change: the window is only touched once per
+/// debounce, and only if the title or count differs.
+pub fn note(active: Option<(&str, usize)>, new_clips: usize) {
+    let title = title_for(active);
+    let mut state = STATE.lock().unwrap();
+    let title_unchanged = state.pending.as_ref() == Some(&title)
+        || (state.pending.is_none() && state.applied.as_ref() == Some(&title));
+    let new_unchanged = state.pending_new.unwrap_or(state.applied_new) == new_clips;
+    if title_unchanged && new_unchanged {
+        return;
+    }
+    if !title_unchanged {
+        state.pending = Some(title);
+    }
+    if !new_unchanged {
+        state.pending_new = Some(new_clips);
+    }
+    if state.flush_scheduled || APP.get().is_none() {
+        return;
+    }
+    state.flush_scheduled = true;
+    drop(state);
+
+    std::thread::spawn(|| {
+        std::thread::sleep(DEBOUNCE);
+        flush();
+    });
+}
+
+fn flush() {
+    let mut state = STATE.lock().unwrap();
+    state.flush_scheduled = false;
+    let Some(app) = APP.get() else {
+        return;
+    };
+    let Some(window) = app.get_webview_window("main") else {
+        return;
+    };
+    if let Some(title) = state.pending.take() {
+        if state.applied.as_ref() != Some(&title) && window.set_title(&title).is_ok() {
+            state.applied = Some(title);
+        }
+    }
+    if let Some(new_clips) = state.pending_new.take() {
+        state.applied_new = new_clips;
+        show_badge(&window, if state.badge_enabled { new_clips } else { 0 });
+        tray::set_new_clips(app, new_clips);
+    }
+}
+
+/// Turn the new-clip badge on or off; the tray tooltip keeps the count either way
+pub fn set_badge_enabled(app: &AppHandle, enabled: bool) {
+    let new_clips = {
+        let mut state = STATE.lock().unwrap();
+        state.badge_enabled = enabled;
+        state.pending_new.unwrap_or(state.applied_new)
+    };
+    if let Some(window) = app.get_webview_window("main") {
+        show_badge(&window, if enabled { new_clips } else { 0 });
+    }
+}
+
+#[cfg(windows)]
+fn show_badge(window: &tauri::WebviewWindow, new_clips: usize) {
+    let icon = (new_clips > 0).then(|| badge_icon(new_clips));
+    let _ = window.set_overlay_icon(icon);
+}
+
+#[cfg(not(windows))]
+fn show_badge(window: &tauri::WebviewWindow, new_clips: usize) {
+    let _ = window.set_badge_count((new_clips > 0).then_some(new_clips as i64));
+}
+
+/// 3x5 bitmaps for 0-9, one row per byte, high bit on the left
+#[cfg(windows)]
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b010, 0b010, 0b010],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+/// A 16x16 red dot with the count (capped at 99) in white, for the taskbar overlay
+#[cfg(windows)]
+fn badge_icon(count: usize) -> tauri::image::Image<'static> {
+    const SIZE: usize = 16;
+    const SCALE: usize = 2;
+    let mut rgba = vec![0u8; SIZE * SIZE * 4];
+    let mut put = |x: usize, y: usize, color: [u8; 4]| {
+        let i = (y * SIZE + x) * 4;
+        rgba[i..i + 4].copy_from_slice(&color);
+    };
+
+    for y in 0..SIZE {
+        for x in 0..SIZE {
+            let (dx, dy) = (x as f32 - 7.5, y as f32 - 7.5);
+            if dx * dx + dy * dy <= 64.0 {
+                put(x, y, [0xE5, 0x39, 0x35, 0xFF]);
+            }
+        }
+    }
+
+    let digits: Vec<usize> = count
+        .min(99)
+        .to_string()
+        .bytes()
+        .map(|b| (b - b'0') as usize)
+        .collect();
+    let width = digits.len() * 4 * SCALE - SCALE;
+    let left = (SIZE - width) / 2;
+    let top = (SIZE - 5 * SCALE) / 2;
+    for (n, digit) in digits.iter().enumerate() {
+        for (row, bits) in DIGITS[*digit].iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                for sy in 0..SCALE {
+                    for sx in 0..SCALE {
+                        let x = left + (n * 4 + col) * SCALE + sx;
+                        put(x, top + row * SCALE + sy, [0xFF, 0xFF, 0xFF, 0xFF]);
+                    }
+                }
+            }
+        }
+    }
+
+    tauri::image::Image::new_owned(rgba, SIZE as u32, SIZE as u32)
+}
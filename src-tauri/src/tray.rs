@@ -0,0 +1,84 @@
+use std::sync::Mutex;
+use tauri::menu::{Menu, MenuItem};
+use tauri::tray::{MouseButton, MouseButtonState, TrayIconBuilder, TrayIconEvent};
+use tauri::AppHandle;
+
+const DIAGNOSTICS_ITEM: &str = "create-diagnostics";
+
+/// What to run for each tray icon mouse action and menu item
+pub struct Handlers {
+    pub double_click: fn(&AppHandle),
+    pub middle_click: fn(&AppHandle),
+    pub create_diagnostics: fn(&AppHandle),
+}
+
+/// Put Stack's icon in the notification area. A single left click does
+/// nothing, since it comes ahead of every double-click; the menu is on
+/// right click.
+pub fn create(app: &AppHandle, handlers: &'static Handlers) -> tauri::Result<()> {
+    let diagnostics = MenuItem::with_id(app, DIAGNOSTICS_ITEM, "Create diagnostics bundle…", true, None::<&str>)?;
+    let menu = Menu::with_items(app, &[&diagnostics])?;
+    let mut builder = TrayIconBuilder::with_id("main")
+        .tooltip("Stack")
+        .menu(&menu)
+        .show_menu_on_left_click(false);
+    if let Some(icon) = app.default_window_icon() {
+        builder = builder.icon(icon.clone());
+    }
+    builder
+        .on_menu_event(move |app, event| {
+            if event.id() == DIAGNOSTICS_ITEM {
+                (handlers.create_diagnostics)(app);
+            }
+        })
+        .on_tray_icon_event(move |tray, event| match event {
+            TrayIconEvent::DoubleClick {
+                button: MouseButton::Left,
+                ..
+            } => (handlers.double_click)(tray.app_handle()),
+            TrayIconEvent::Click {
+                button: MouseButton::Middle,
+                button_state: MouseButtonState::Up,
+                ..
+            } => (handlers.middle_click)(tray.app_handle()),
+            _ => {}
+        })
+        .build(app)?;
+    Ok(())
+}
+
+/// What the tooltip shows besides the name: a clip held on the clipboard,
+/// and clips captured since their pastebooks were last viewed
+static TOOLTIP: Mutex<(bool, usize)> = Mutex::new((false, 0));
+
+fn tooltip_for(holding: bool, new_clips: usize) -> String {
+    let mut tooltip = "Stack".to_string();
+    match new_clips {
+        0 => {}
+        1 => tooltip.push_str(" — 1 new clip"),
+        n => tooltip.push_str(&format!(" — {} new clips", n)),
+    }
+    if holding {
+        tooltip.push_str(" (holding a clip on the clipboard)");
+    }
+    tooltip
+}
+
+/// Show in the tray tooltip whether a clip is being held on the clipboard
+pub fn set_holding(app: &AppHandle, holding: bool) {
+    TOOLTIP.lock().unwrap().0 = holding;
+    refresh_tooltip(app);
+}
+
+/// Show in the tray tooltip how many clips are new across every pastebook
+pub fn set_new_clips(app: &AppHandle, new_clips: usize) {
+    TOOLTIP.lock().unwrap().1 = new_clips;
+    refresh_tooltip(app);
+}
+
+fn refresh_tooltip(app: &AppHandle) {
+    let (holding, new_clips) = *TOOLTIP.lock().unwrap();
+    if let Some(tray) = app.tray_by_id("main") {
+        let _ = tray.set_tooltip(Some(tooltip_for(holding, new_clips)));
+    }
+}
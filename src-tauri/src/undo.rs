@@ -0,0 +1,80 @@
+use serde::Serialize;
+
+use crate::storage::{ClipObject, Pastebook};
+
+/// Undo entries kept; the oldest drop off past this
+const MAX_ENTRIES: usize = 50;
+
+/// How to put a pastebook operation back. Pastebooks and clips are put back
+/// under their old ids, so anything pointing at one stays valid.
+#[derive(Debug, Clone)]
+pub enum PastebookUndo {
+    /// Give a renamed pastebook its old name back
+    Rename { id: String, old_name: String },
+    /// Return a pastebook to its old group and place in the list: before
+    /// the pastebook that followed it, or at its old index if that's gone
+    Group { id: String, old_group: Option<String>, old_index: usize, old_next: Option<String> },
+    /// Give the pastebooks of a renamed group their old group name back
+    GroupRename { ids: Vec<String>, old_group: String },
+    /// Bring a deleted pastebook back with its clips at its old index, as
+    /// the active pastebook again if it was
+    Delete { pastebook: Box<Pastebook>, index: usize, was_active: bool },
+    /// Put a pastebook's clips back after a delete, clear, merge, reorder,
+    /// bulk update or trash purge: `before` holds the clips it touched as
+    /// they were, `order` the old order of ids and `added` the clips it made
+    Clips { id: String, order: Vec<String>, before: Vec<ClipObject>, added: Vec<String> },
+}
+
+/// One operation on the undo stack
+#[derive(Debug, Clone)]
+pub struct UndoEntry {
+    /// What was done, for the UI, e.g. "Rename pastebook 'Work'"
+    pub label: String,
+    /// None for operations that can't be taken back
+    pub undo: Option<PastebookUndo>,
+}
+
+/// Result of `undo_last_operation`
+#[derive(Debug, Clone, Serialize)]
+pub struct UndoOutcome {
+    pub label: String,
+    /// False when the operation can't be undone; it's dropped from the
+    /// stack all the same so the next undo reaches the one before it
+    pub undone: bool,
+}
+
+/// Pastebook operations of this session, newest last
+#[derive(Debug, Clone, Default)]
+pub struct UndoStack {
+    entries: Vec<UndoEntry>,
+}
+
+impl UndoStack {
+    pub fn push(&mut self, label: String, undo: Option<PastebookUndo>) {
+        self.entries.push(UndoEntry { label, undo });
+        if self.entries.len() > MAX_ENTRIES {
+            self.entries.remove(0);
+        }
+    }
+
+    pub fn last(&self) -> Option<&UndoEntry> {
+        self.entries.last()
+    }
+
+    pub fn pop(&mut self) -> Option<UndoEntry> {
+        self.entries.pop()
+    }
+
+    /// Clips the stack could put back, whose assets have to be kept
+    pub fn clips(&self) -> impl Iterator<Item = &ClipObject> {
+        self.entries.iter().flat_map(|entry| {
+            let clips: &[ClipObject] = match &entry.undo {
+                Some(PastebookUndo::Clips { before, .. }) => before,
+                Some(PastebookUndo::Delete { pastebook, .. }) => &pastebook.clips,
+                _ => &[],
+            };
+            clips.iter()
+        })
+    }
+}
+
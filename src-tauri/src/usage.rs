@@ -0,0 +1,238 @@
+use chrono::{DateTime, Datelike, Duration, NaiveDate, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::ai::{AiReply, TokenUsage};
+
+/// Ledger entries older than this are dropped as new ones come in
+const RETAIN_DAYS: i64 = 400;
+
+/// One AI request's token usage
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageEntry {
+    pub at: DateTime<Utc>,
+    /// The command that made the request, e.g. "draft_document"
+    pub command: String,
+    pub model: String,
+    #[serde(flatten)]
+    pub usage: TokenUsage,
+}
+
+/// What a model costs, in USD per million tokens
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ModelPrice {
+    /// Model name, or a prefix such as "gemini-1.5-flash" covering its versions
+    pub model: String,
+    pub input_per_million: f64,
+    pub output_per_million: f64,
+}
+
+fn price(model: &str, input_per_million: f64, output_per_million: f64) -> ModelPrice {
+    ModelPrice {
+        model: model.to_string(),
+        input_per_million,
+        output_per_million,
+    }
+}
+
+/// Published list prices for the default models; editable in settings
+pub fn default_prices() -> Vec<ModelPrice> {
+    vec![
+        price("gemini-flash-latest", 0.30, 2.50),
+        price("gemini-1.5-flash", 0.075, 0.30),
+        price("gemini-1.5-pro", 1.25, 5.00),
+    ]
+}
+
+/// Token and cost totals over some set of requests
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct UsageTotals {
+    pub requests: usize,
+    pub tokens_in: u64,
+    pub tokens_out: u64,
+    /// USD, from the price table; models without a price add nothing
+    pub estimated_cost: f64,
+}
+
+impl UsageTotals {
+    fn add(&mut self, entry: &UsageEntry, cost: f64) {
+        self.requests += 1;
+        self.tokens_in += entry.usage.tokens_in;
+        self.tokens_out += entry.usage.tokens_out;
+        self.estimated_cost += cost;
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct DayUsage {
+    pub date: NaiveDate,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CommandUsage {
+    pub command: String,
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+}
+
+/// Usage between two dates, with per-day and per-command breakdowns
+#[derive(Debug, Clone, Serialize)]
+pub struct UsageReport {
+    #[serde(flatten)]
+    pub totals: UsageTotals,
+    /// Days with any requests, oldest first
+    pub per_day: Vec<DayUsage>,
+    /// Most tokens first
+    pub per_command: Vec<CommandUsage>,
+    /// Models that were used but have no price, so their cost is missing
+    pub unpriced_models: Vec<String>,
+}
+
+/// The price for `model`: an exact match, else the longest matching prefix
+fn price_for<'a>(prices: &'a [ModelPrice], model: &str) -> Option<&'a ModelPrice> {
+    prices
+        .iter()
+        .filter(|p| model.starts_with(p.model.as_str()))
+        .max_by_key(|p| p.model.len())
+}
+
+/// Estimated USD cost of one request, if its model has a price
+fn cost(prices: &[ModelPrice], entry: &UsageEntry) -> Option<f64> {
+    price_for(prices, &entry.model).map(|p| {
+        (entry.usage.tokens_in as f64 * p.input_per_million
+            + entry.usage.tokens_out as f64 * p.output_per_million)
+            / 1_000_000.0
+    })
+}
+
+/// Add a reply's usage to the ledger, dropping entries past retention
+pub fn record(ledger: &mut Vec<UsageEntry>, command: &str, reply: &AiReply, now: DateTime<Utc>) {
+    ledger.retain(|e| now - e.at < Duration::days(RETAIN_DAYS));
+    ledger.push(UsageEntry {
+        at: now,
+        command: command.to_string(),
+        model: reply.model.clone(),
+        usage: reply.usage,
+    });
+}
+
+/// Usage on dates `from` to `to` (inclusive) in `tz`
+pub fn report<Tz: TimeZone>(
+    ledger: &[UsageEntry],
+    prices: &[ModelPrice],
+    tz: &Tz,
+    from: NaiveDate,
+    to: NaiveDate,
+) -> UsageReport {
+    let mut totals = UsageTotals::default();
+    let mut per_day: BTreeMap<NaiveDate, UsageTotals> = BTreeMap::new();
+    let mut per_command: BTreeMap<&str, UsageTotals> = BTreeMap::new();
+    let mut unpriced_models: Vec<String> = Vec::new();
+
+    for entry in ledger {
+        let date = entry.at.with_timezone(tz).date_naive();
+        if date < from || date > to {
+            continue;
+        }
+        let cost = cost(prices, entry).unwrap_or_else(|| {
+            if !unpriced_models.contains(&entry.model) {
+                unpriced_models.push(entry.model.clone());
+            }
+            0.0
+        });
+        totals.add(entry, cost);
+        per_day.entry(date).or_default().add(entry, cost);
+        per_command.entry(&entry.command).or_default().add(entry, cost);
+    }
+
+    let mut per_command: Vec<CommandUsage> = per_command
+        .into_iter()
+        .map(|(command, totals)| CommandUsage {
+            command: command.to_string(),
+            totals,
+        })
+        .collect();
+    per_command.sort_by_key(|c| std::cmp::Reverse(c.totals.tokens_in + c.totals.tokens_out));
+
+    UsageReport {
+        totals,
+        per_day: per_day
+            .into_iter()
+            .map(|(date, totals)| DayUsage { date, totals })
+            .collect(),
+        per_command,
+        unpriced_models,
+    }
+}
+
+/// Tokens (in and out) used so far in the calendar month of `now`, in `tz`
+pub fn month_tokens<Tz: TimeZone>(ledger: &[UsageEntry], tz: &Tz, now: DateTime<Utc>) -> u64 {
+    let today = now.with_timezone(tz).date_naive();
+    let month_start = today.with_day(1).unwrap_or(today);
+    ledger
+        .iter()
+        .filter(|e| {
+            let date = e.at.with_timezone(tz).date_naive();
+            date >= month_start && date <= today
+        })
+        .map(|e| e.usage.tokens_in + e.usage.tokens_out)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reply(model: &str, tokens_in: u64, tokens_out: u64) -> AiReply {
+        AiReply {
+            text: String::new(),
+            model: model.to_string(),
+            usage: TokenUsage { tokens_in, tokens_out },
+        }
+    }
+
+    fn at(date: &str) -> DateTime<Utc> {
+        format!("{}T12:00:00Z", date).parse().unwrap()
+    }
+
+    fn date(text: &str) -> NaiveDate {
+        text.parse().unwrap()
+    }
+
+    #[test]
+    fn report_totals_break_down_by_day_and_command() {
+        let mut ledger = Vec::new();
+        record(&mut ledger, "chat_submit", &reply("gemini-1.5-pro-002", 1_000_000, 0), at("2026-03-01"));
+        record(&mut ledger, "draft_document", &reply("gemini-1.5-flash", 0, 2_000_000), at("2026-03-01"));
+        record(&mut ledger, "chat_submit", &reply("my-model", 10, 10), at("2026-03-02"));
+        record(&mut ledger, "chat_submit", &reply("gemini-1.5-pro", 5, 5), at("2026-04-01"));
+
+        let report = report(&ledger, &default_prices(), &Utc, date("2026-03-01"), date("2026-03-31"));
+        assert_eq!(report.totals.requests, 3);
+        assert_eq!(report.totals.tokens_in, 1_000_010);
+        // 1M pro input at 1.25, 2M flash output at 0.30
+        assert!((report.totals.estimated_cost - 1.85).abs() < 1e-9);
+        assert_eq!(report.per_day.len(), 2);
+        assert_eq!(report.per_day[0].totals.requests, 2);
+        assert_eq!(report.per_command[0].command, "draft_document");
+        assert_eq!(report.unpriced_models, vec!["my-model"]);
+    }
+
+    #[test]
+    fn month_tokens_count_only_this_month() {
+        let mut ledger = Vec::new();
+        record(&mut ledger, "chat_submit", &reply("m", 100, 50), at("2026-02-28"));
+        record(&mut ledger, "chat_submit", &reply("m", 10, 5), at("2026-03-01"));
+        assert_eq!(month_tokens(&ledger, &Utc, at("2026-03-15")), 15);
+    }
+
+    #[test]
+    fn old_entries_are_dropped() {
+        let mut ledger = Vec::new();
+        record(&mut ledger, "chat_submit", &reply("m", 1, 1), at("2024-01-01"));
+        record(&mut ledger, "chat_submit", &reply("m", 1, 1), at("2026-01-01"));
+        assert_eq!(ledger.len(), 1);
+    }
+}
@@ -0,0 +1,274 @@
+use chrono::{DateTime, Utc};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::Duration;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+/// Wait before the single retry of a failed delivery
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_WEBHOOKS: usize = 20;
+
+/// Events a webhook can subscribe to; each is POSTed with its event payload
+pub const WEBHOOK_EVENTS: [&str; 5] = [
+    "clip-captured",
+    "clip-updated",
+    "clips-updated",
+    "storage-changed",
+    "clips-cleared",
+];
+
+/// A URL that captures and changes are POSTed to
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+    /// Events to send; empty sends all of them
+    #[serde(default)]
+    pub events: Vec<String>,
+    /// Sent as `Authorization: Bearer <token>`
+    #[serde(default)]
+    pub bearer_token: Option<String>,
+    /// Send clip text; without it `content` and `preview` fields are left out
+    #[serde(default)]
+    pub include_content: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl WebhookConfig {
+    fn wants(&self, event: &str) -> bool {
+        self.enabled && (self.events.is_empty() || self.events.iter().any(|e| e == event))
+    }
+}
+
+/// Delivery counters for one webhook, as returned by `get_webhook_status`
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WebhookStatus {
+    pub url: String,
+    pub enabled: bool,
+    pub delivered: u64,
+    /// Deliveries that failed even after the retry
+    pub failures: u64,
+    pub last_error: Option<String>,
+    pub last_delivered_at: Option<DateTime<Utc>>,
+    pub last_failure_at: Option<DateTime<Utc>>,
+}
+
+static CONFIGS: Mutex<Vec<WebhookConfig>> = Mutex::new(Vec::new());
+/// Counters by URL, kept across settings changes
+static STATUS: OnceLock<Mutex<HashMap<String, WebhookStatus>>> = OnceLock::new();
+static CLIENT: OnceLock<Client> = OnceLock::new();
+
+fn status() -> &'static Mutex<HashMap<String, WebhookStatus>> {
+    STATUS.get_or_init(Default::default)
+}
+
+/// Check and normalize webhook settings before they're saved
+pub fn validate(webhooks: Vec<WebhookConfig>) -> Result<Vec<WebhookConfig>, String> {
+    if webhooks.len() > MAX_WEBHOOKS {
+        return Err(format!("At most {} webhooks are allowed", MAX_WEBHOOKS));
+    }
+
+    let mut normalized: Vec<WebhookConfig> = Vec::with_capacity(webhooks.len());
+    for mut webhook in webhooks {
+        webhook.url = webhook.url.trim().to_string();
+        let url = Url::parse(&webhook.url)
+            .map_err(|e| format!("Invalid webhook URL '{}': {}", webhook.url, e))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(format!("Webhook URL '{}' must be http or https", webhook.url));
+        }
+        if normalized.iter().any(|w| w.url == webhook.url) {
+            return Err(format!("Webhook '{}' is listed twice", webhook.url));
+        }
+        if let Some(event) = webhook
+            .events
+            .iter()
+            .find(|e| !WEBHOOK_EVENTS.contains(&e.as_str()))
+        {
+            return Err(format!("Unknown webhook event '{}'", event));
+        }
+        webhook.bearer_token = webhook
+            .bearer_token
+            .map(|token| token.trim().to_string())
+            .filter(|token| !token.is_empty());
+        normalized.push(webhook);
+    }
+    Ok(normalized)
+}
+
+/// Use these webhooks from now on
+pub fn configure(webhooks: &[WebhookConfig]) {
+    *CONFIGS.lock().unwrap() = webhooks.to_vec();
+}
+
+/// Counters for each configured webhook, in settings order
+pub fn current_status() -> Vec<WebhookStatus> {
+    let configs = CONFIGS.lock().unwrap().clone();
+    let status = status().lock().unwrap();
+    configs
+        .into_iter()
+        .map(|config| WebhookStatus {
+            enabled: config.enabled,
+            ..status.get(&config.url).cloned().unwrap_or_else(|| WebhookStatus {
+                url: config.url.clone(),
+                ..Default::default()
+            })
+        })
+        .collect()
+}
+
+/// Drop clip text from a payload, wherever it sits
+fn strip_content(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.remove("content");
+            map.remove("preview");
+            map.values_mut().for_each(strip_content);
+        }
+        Value::Array(items) => items.iter_mut().for_each(strip_content),
+        _ => {}
+    }
+}
+
+/// POST an event to every webhook that wants it, in the background. Failures
+/// are only counted; they never reach the caller.
+pub fn dispatch<S: Serialize>(event: &'static str, payload: &S) {
+    let targets: Vec<WebhookConfig> = CONFIGS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|w| w.wants(event))
+        .cloned()
+        .collect();
+    if targets.is_empty() {
+        return;
+    }
+    let Ok(payload) = serde_json::to_value(payload) else {
+        return;
+    };
+
+    for webhook in targets {
+        let mut body = payload.clone();
+        if !webhook.include_content {
+            strip_content(&mut body);
+        }
+        tauri::async_runtime::spawn(async move {
+            let result = match deliver(&webhook, event, &body).await {
+                Err(_) => {
+                    tokio::time::sleep(RETRY_DELAY).await;
+                    deliver(&webhook, event, &body).await
+                }
+                ok => ok,
+            };
+            record(&webhook.url, result);
+        });
+    }
+}
+
+async fn deliver(webhook: &WebhookConfig, event: &str, body: &Value) -> Result<(), String> {
+    let client = CLIENT.get_or_init(|| {
+        Client::builder()
+            .timeout(REQUEST_TIMEOUT)
+            .build()
+            .unwrap_or_default()
+    });
+    let mut request = client
+        .post(&webhook.url)
+        .header("X-Stack-Event", event)
+        .json(body);
+    if let Some(token) = &webhook.bearer_token {
+        request = request.bearer_auth(token);
+    }
+
+    let response = request.send().await.map_err(|e| e.to_string())?;
+    if !response.status().is_success() {
+        return Err(format!("HTTP {}", response.status()));
+    }
+    Ok(())
+}
+
+fn record(url: &str, result: Result<(), String>) {
+    let mut status = status().lock().unwrap();
+    let entry = status.entry(url.to_string()).or_insert_with(|| WebhookStatus {
+        url: url.to_string(),
+        ..Default::default()
+    });
+    match result {
+        Ok(()) => {
+            entry.delivered += 1;
+            entry.last_delivered_at = Some(Utc::now());
+        }
+        Err(e) => {
+            eprintln!("Webhook {} failed: {}", url, e);
+            entry.failures += 1;
+            entry.last_error = Some(e);
+            entry.last_failure_at = Some(Utc::now());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn webhook(url: &str) -> WebhookConfig {
+        WebhookConfig {
+            url: url.to_string(),
+            enabled: true,
+            events: Vec::new(),
+            bearer_token: None,
+            include_content: false,
+        }
+    }
+
+    #[test]
+    fn only_http_urls_are_accepted() {
+        let valid = validate(vec![webhook(" http://localhost:5678/hook ")]).unwrap();
+        assert_eq!(valid[0].url, "http://localhost:5678/hook");
+        assert!(validate(vec![webhook("https://hooks.example.com/x")]).is_ok());
+        assert!(validate(vec![webhook("file:///etc/passwd")]).is_err());
+        assert!(validate(vec![webhook("not a url")]).is_err());
+        assert!(validate(vec![webhook("http://a/"), webhook("http://a/")]).is_err());
+    }
+
+    #[test]
+    fn events_must_be_known_and_tokens_are_trimmed() {
+        let mut unknown = webhook("http://a/");
+        unknown.events = vec!["clip-deleted".to_string()];
+        assert_eq!(validate(vec![unknown]).unwrap_err(), "Unknown webhook event 'clip-deleted'");
+
+        let mut config = webhook("http://a/");
+        config.events = vec!["clip-captured".to_string()];
+        config.bearer_token = Some("  ".to_string());
+        let config = validate(vec![config]).unwrap().remove(0);
+        assert!(config.bearer_token.is_none());
+        assert!(config.wants("clip-captured"));
+        assert!(!config.wants("clip-updated"));
+    }
+
+    #[test]
+    fn disabled_webhooks_want_nothing() {
+        let mut config = webhook("http://a/");
+        assert!(config.wants("clips-cleared"));
+        config.enabled = false;
+        assert!(!config.wants("clips-cleared"));
+    }
+
+    #[test]
+    fn content_is_stripped_at_any_depth() {
+        let mut payload = json!({
+            "id": "1",
+            "preview": "secret",
+            "clips": [{ "id": "2", "content": "secret", "title": "kept" }]
+        });
+        strip_content(&mut payload);
+        assert_eq!(payload, json!({ "id": "1", "clips": [{ "id": "2", "title": "kept" }] }));
+    }
+}
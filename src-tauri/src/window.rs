@@ -1,19 +1,40 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::Mutex;
+use std::time::Duration;
+#[cfg(windows)]
+use std::time::Instant;
+
+#[cfg(windows)]
 use std::ffi::OsString;
+#[cfg(windows)]
 use std::os::windows::ffi::OsStringExt;
 
 #[cfg(windows)]
 use windows::Win32::{
-    Foundation::HWND,
+    Foundation::{CloseHandle, HWND},
     System::ProcessStatus::GetModuleBaseNameW,
     System::Threading::{OpenProcess, PROCESS_QUERY_INFORMATION, PROCESS_VM_READ},
-    UI::WindowsAndMessaging::{GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId},
+    UI::WindowsAndMessaging::{
+        GetClassNameW, GetForegroundWindow, GetWindowTextW, GetWindowThreadProcessId,
+        SetForegroundWindow,
+    },
 };
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WindowInfo {
     pub app_name: String,
     pub window_title: String,
+    /// Virtual desktop the window is on: its name, "Desktop N", or its id
+    #[serde(default)]
+    pub virtual_desktop: Option<String>,
+    /// GUID of that desktop
+    #[serde(default)]
+    pub virtual_desktop_id: Option<String>,
+    /// Device name of the monitor showing most of the window
+    #[serde(default)]
+    pub monitor: Option<String>,
 }
 
 impl Default for WindowInfo {
@@ -21,71 +42,626 @@ impl Default for WindowInfo {
         Self {
             app_name: "unknown".to_string(),
             window_title: "Unknown Window".to_string(),
+            virtual_desktop: None,
+            virtual_desktop_id: None,
+            monitor: None,
         }
     }
 }
 
-/// Get information about the currently active (foreground) window
+/// The last foreground window that didn't belong to Stack
+#[derive(Debug, Clone, Serialize)]
+pub struct ForegroundRecord {
+    pub info: WindowInfo,
+    pub observed_at: DateTime<Utc>,
+}
+
+/// A tracked window: its record, and its handle for handing focus back.
+/// Kept under one lock so the two always describe the same window.
+struct Tracked {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    hwnd: isize,
+    record: ForegroundRecord,
+}
+
+static LAST_FOREGROUND: Mutex<Option<Tracked>> = Mutex::new(None);
+/// Window classes of the taskbar and tray overflow, which take focus when
+/// the tray icon is clicked
+#[cfg(windows)]
+const TASKBAR_CLASSES: [&str; 3] = ["Shell_TrayWnd", "Shell_SecondaryTrayWnd", "NotifyIconOverflowWindow"];
+
+const VSCODE_APPS: [&str; 3] = ["code", "code - insiders", "vscodium"];
+const JETBRAINS_APPS: [&str; 11] = [
+    "idea", "idea64", "pycharm64", "webstorm64", "clion64", "rustrover64", "goland64",
+    "rider64", "phpstorm64", "datagrip64", "studio64",
+];
+/// Markers editors put in front of the title when the file has unsaved changes
+const UNSAVED_MARKERS: [char; 2] = ['●', '*'];
+const BROWSER_APPS: [&str; 7] = ["chrome", "msedge", "firefox", "brave", "opera", "vivaldi", "arc"];
+/// Page text kept on each side of a browser selection
+#[cfg(windows)]
+const SELECTION_CONTEXT_CHARS: i32 = 100;
+/// How far up from the focused element to look for the document's text pattern
 #[cfg(windows)]
-pub fn get_active_window_info() -> WindowInfo {
+const MAX_TEXT_PATTERN_ANCESTORS: usize = 8;
+/// How long a capture waits for the selection context once its copy is done
+pub const SELECTION_CONTEXT_BUDGET: Duration = Duration::from_millis(100);
+/// How long a window lookup waits for the virtual desktop manager, which
+/// asks Explorer and can stall while it is busy
+#[cfg(windows)]
+const DESKTOP_LOOKUP_BUDGET: Duration = Duration::from_millis(20);
+#[cfg(windows)]
+const VIRTUAL_DESKTOPS_KEY: &str = "Software\\Microsoft\\Windows\\CurrentVersion\\Explorer\\VirtualDesktops";
+
+/// Text just before and after the selection on a web page, for citations
+#[derive(Debug, Clone, Serialize)]
+pub struct SelectionContext {
+    pub context_before: String,
+    pub context_after: String,
+}
+
+impl SelectionContext {
+    /// Add the context to a clip's metadata context object
+    pub fn merge_into(self, context: &mut Option<serde_json::Value>) {
+        let object = context.get_or_insert_with(|| serde_json::json!({}));
+        if let Some(object) = object.as_object_mut() {
+            object.insert("context_before".to_string(), self.context_before.into());
+            object.insert("context_after".to_string(), self.context_after.into());
+        }
+    }
+}
+
+/// A selection context read running on its own thread
+pub struct PendingSelectionContext(Receiver<Option<SelectionContext>>);
+
+impl PendingSelectionContext {
+    /// The context if it arrived within `budget`; a slow read is abandoned
+    pub fn wait(self, budget: Duration) -> Option<SelectionContext> {
+        self.0.recv_timeout(budget).ok().flatten()
+    }
+}
+
+pub fn is_browser(app_name: &str) -> bool {
+    let app = app_name.to_lowercase();
+    let app = app.strip_suffix(".exe").unwrap_or(&app);
+    BROWSER_APPS.contains(&app)
+}
+
+/// Start reading the text around the focused selection in the background,
+/// so it can overlap with the copy
+pub fn read_selection_context() -> PendingSelectionContext {
+    let (tx, rx) = mpsc::channel();
+    std::thread::spawn(move || {
+        let _ = tx.send(selection_context());
+    });
+    PendingSelectionContext(rx)
+}
+
+/// Read the focused document's text around its selection through UI Automation
+#[cfg(windows)]
+fn selection_context() -> Option<SelectionContext> {
+    use windows::Win32::System::Com::{
+        CoCreateInstance, CoInitializeEx, CoUninitialize, CLSCTX_INPROC_SERVER, COINIT_MULTITHREADED,
+    };
+    use windows::Win32::UI::Accessibility::{
+        CUIAutomation, IUIAutomation, IUIAutomationTextPattern, TextPatternRangeEndpoint_End,
+        TextPatternRangeEndpoint_Start, TextUnit_Character, UIA_TextPatternId,
+    };
+
+    unsafe {
+        let initialized = CoInitializeEx(None, COINIT_MULTITHREADED).is_ok();
+        let context = (|| {
+            let automation: IUIAutomation =
+                CoCreateInstance(&CUIAutomation, None, CLSCTX_INPROC_SERVER).ok()?;
+            let walker = automation.ControlViewWalker().ok()?;
+
+            // Focus is often on a link or span; the text pattern lives on the document
+            let mut element = automation.GetFocusedElement().ok()?;
+            let mut pattern = None;
+            for _ in 0..MAX_TEXT_PATTERN_ANCESTORS {
+                if let Ok(found) =
+                    element.GetCurrentPatternAs::<IUIAutomationTextPattern>(UIA_TextPatternId)
+                {
+                    pattern = Some(found);
+                    break;
+                }
+                element = walker.GetParentElement(&element).ok()?;
+            }
+
+            let selection = pattern?.GetSelection().ok()?;
+            if selection.Length().ok()? < 1 {
+                return None;
+            }
+            let range = selection.GetElement(0).ok()?;
+
+            // Collapse a copy onto each end of the selection, then widen it outwards
+            let before = range.Clone().ok()?;
+            before
+                .MoveEndpointByRange(TextPatternRangeEndpoint_End, &range, TextPatternRangeEndpoint_Start)
+                .ok()?;
+            before
+                .MoveEndpointByUnit(TextPatternRangeEndpoint_Start, TextUnit_Character, -SELECTION_CONTEXT_CHARS)
+                .ok()?;
+
+            let after = range.Clone().ok()?;
+            after
+                .MoveEndpointByRange(TextPatternRangeEndpoint_Start, &range, TextPatternRangeEndpoint_End)
+                .ok()?;
+            after
+                .MoveEndpointByUnit(TextPatternRangeEndpoint_End, TextUnit_Character, SELECTION_CONTEXT_CHARS)
+                .ok()?;
+
+            Some(SelectionContext {
+                context_before: before.GetText(SELECTION_CONTEXT_CHARS).ok()?.to_string(),
+                context_after: after.GetText(SELECTION_CONTEXT_CHARS).ok()?.to_string(),
+            })
+        })();
+
+        if initialized {
+            CoUninitialize();
+        }
+        context
+    }
+}
+
+#[cfg(not(windows))]
+fn selection_context() -> Option<SelectionContext> {
+    // No UI Automation equivalent wired up off Windows
+    None
+}
+
+/// Parse editor window titles into `{file, project}` (plus `path` when the
+/// title has one); None for other apps or titles we don't recognise
+pub fn source_context(app_name: &str, window_title: &str) -> Option<serde_json::Value> {
+    let app = app_name.to_lowercase();
+    let app = app.strip_suffix(".exe").unwrap_or(&app);
+
+    if VSCODE_APPS.contains(&app) {
+        parse_vscode_title(window_title)
+    } else if JETBRAINS_APPS.contains(&app) {
+        parse_jetbrains_title(window_title)
+    } else {
+        None
+    }
+}
+
+fn strip_unsaved_marker(name: &str) -> &str {
+    name.trim().trim_start_matches(UNSAVED_MARKERS).trim()
+}
+
+/// "● main.rs - stack-backend - Visual Studio Code"
+fn parse_vscode_title(title: &str) -> Option<serde_json::Value> {
+    let rest = title
+        .trim()
+        .strip_suffix("Visual Studio Code - Insiders")
+        .or_else(|| title.trim().strip_suffix("Visual Studio Code"))
+        .or_else(|| title.trim().strip_suffix("VSCodium"))?
+        .trim_end()
+        .strip_suffix(" -")?;
+
+    let parts: Vec<&str> = rest.split(" - ").map(str::trim).collect();
+    let file = strip_unsaved_marker(parts.first()?);
+    // Welcome, Settings etc. aren't files
+    if file.is_empty() || !file.contains('.') {
+        return None;
+    }
+
+    let mut context = serde_json::json!({ "file": file });
+    if parts.len() > 1 {
+        // The workspace is last; drop "(Workspace)" and "[SSH: host]" style suffixes
+        let project = parts[parts.len() - 1].trim_end_matches(" (Workspace)");
+        let project = project.split(" [").next().unwrap_or(project).trim();
+        context["project"] = project.into();
+    }
+    Some(context)
+}
+
+/// "stack-backend – src/main.rs" or "stack-backend [~/code/stack] – …/src/main.rs"
+fn parse_jetbrains_title(title: &str) -> Option<serde_json::Value> {
+    let (project, path) = title.trim().split_once(" – ")?;
+    let project = project.split(" [").next()?.trim();
+    let path = strip_unsaved_marker(path.split(" – ").last()?);
+    let file = path.rsplit(['/', '\\']).next()?.trim();
+
+    if project.is_empty() || file.is_empty() {
+        return None;
+    }
+
+    let mut context = serde_json::json!({ "file": file, "project": project });
+    if path != file {
+        context["path"] = path.into();
+    }
+    Some(context)
+}
+
+/// A GUID as stored in the registry (little-endian fields, then 8 bytes as is)
+#[cfg_attr(not(windows), allow(dead_code))]
+fn guid_from_bytes(bytes: &[u8]) -> Option<u128> {
+    let bytes: &[u8; 16] = bytes.try_into().ok()?;
+    let data1 = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u128;
+    let data2 = u16::from_le_bytes([bytes[4], bytes[5]]) as u128;
+    let data3 = u16::from_le_bytes([bytes[6], bytes[7]]) as u128;
+    let data4 = u64::from_be_bytes(bytes[8..].try_into().ok()?) as u128;
+    Some((data1 << 96) | (data2 << 80) | (data3 << 64) | data4)
+}
+
+/// "{AA509086-5CA9-4C25-8F95-589D3C07B48A}", the way registry keys name GUIDs
+#[cfg_attr(not(windows), allow(dead_code))]
+fn guid_string(guid: u128) -> String {
+    format!(
+        "{{{:08X}-{:04X}-{:04X}-{:04X}-{:012X}}}",
+        guid >> 96,
+        (guid >> 80) & 0xFFFF,
+        (guid >> 64) & 0xFFFF,
+        (guid >> 48) & 0xFFFF,
+        guid & 0xFFFF_FFFF_FFFF
+    )
+}
+
+/// What to call a virtual desktop: the name the user gave it, else its
+/// position in `desktop_ids` (the registry's list of GUIDs, in taskbar
+/// order) as "Desktop N", else the GUID itself
+#[cfg_attr(not(windows), allow(dead_code))]
+fn desktop_label(desktop: u128, desktop_ids: &[u8], name: Option<String>) -> String {
+    if let Some(name) = name.filter(|n| !n.trim().is_empty()) {
+        return name;
+    }
+    desktop_ids
+        .chunks(16)
+        .position(|chunk| guid_from_bytes(chunk) == Some(desktop))
+        .map(|i| format!("Desktop {}", i + 1))
+        .unwrap_or_else(|| guid_string(desktop))
+}
+
+/// Read a value under the current user's virtual desktop key
+#[cfg(windows)]
+fn read_desktop_value(subkey: &str, name: &str, string: bool) -> Option<Vec<u8>> {
+    use windows::core::HSTRING;
+    use windows::Win32::Foundation::ERROR_SUCCESS;
+    use windows::Win32::System::Registry::{RegGetValueW, HKEY_CURRENT_USER, RRF_RT_REG_BINARY, RRF_RT_REG_SZ};
+
+    let key = HSTRING::from(format!("{}{}", VIRTUAL_DESKTOPS_KEY, subkey));
+    let flags = if string { RRF_RT_REG_SZ } else { RRF_RT_REG_BINARY };
+    let mut buffer = vec![0u8; 1024];
+    let mut size = buffer.len() as u32;
+    let status = unsafe {
+        RegGetValueW(
+            HKEY_CURRENT_USER,
+            &key,
+            &HSTRING::from(name),
+            flags,
+            None,
+            Some(buffer.as_mut_ptr() as *mut _),
+            Some(&mut size),
+        )
+    };
+    if status != ERROR_SUCCESS {
+        return None;
+    }
+    buffer.truncate(size as usize);
+    Some(buffer)
+}
+
+/// Friendly name of a virtual desktop, from what Explorer keeps in the registry
+#[cfg(windows)]
+fn resolve_desktop(desktop: u128) -> String {
+    let ids = read_desktop_value("", "VirtualDesktopIDs", false).unwrap_or_default();
+    let subkey = format!("\\Desktops\\{}", guid_string(desktop));
+    let name = read_desktop_value(&subkey, "Name", true).map(|bytes| {
+        let wide: Vec<u16> = bytes.chunks_exact(2).map(|c| u16::from_le_bytes([c[0], c[1]])).collect();
+        let len = wide.iter().position(|&c| c == 0).unwrap_or(wide.len());
+        String::from_utf16_lossy(&wide[..len])
+    });
+    desktop_label(desktop, &ids, name)
+}
+
+/// A lookup for the thread below: the window, when the caller stops
+/// waiting, and where to send the desktop's GUID and label
+#[cfg(windows)]
+type DesktopRequest = (isize, Instant, mpsc::Sender<Option<(String, String)>>);
+
+/// Sender to the thread that owns the virtual desktop manager, started on
+/// first use so COM is set up once rather than on every lookup
+#[cfg(windows)]
+fn desktop_lookups() -> &'static Mutex<mpsc::Sender<DesktopRequest>> {
+    use std::sync::OnceLock;
+    use windows::Win32::System::Com::{CoCreateInstance, CoInitializeEx, CLSCTX_ALL, COINIT_MULTITHREADED};
+    use windows::Win32::UI::Shell::{IVirtualDesktopManager, VirtualDesktopManager};
+
+    static LOOKUPS: OnceLock<Mutex<mpsc::Sender<DesktopRequest>>> = OnceLock::new();
+    LOOKUPS.get_or_init(|| {
+        let (tx, rx) = mpsc::channel::<DesktopRequest>();
+        std::thread::spawn(move || unsafe {
+            let _ = CoInitializeEx(None, COINIT_MULTITHREADED);
+            let manager: Option<IVirtualDesktopManager> =
+                CoCreateInstance(&VirtualDesktopManager, None, CLSCTX_ALL).ok();
+            for (hwnd, deadline, reply) in rx {
+                // The caller gave up on a lookup that queued behind a slow one
+                if Instant::now() >= deadline {
+                    continue;
+                }
+                let desktop = manager.as_ref().and_then(|manager| {
+                    let id = manager.GetWindowDesktopId(HWND(hwnd as *mut _)).ok()?.to_u128();
+                    // Windows with no desktop (some tool windows) report a zero id
+                    (id != 0).then(|| (guid_string(id), resolve_desktop(id)))
+                });
+                let _ = reply.send(desktop);
+            }
+        });
+        Mutex::new(tx)
+    })
+}
+
+/// GUID and label of the virtual desktop a window is on, if the manager
+/// answers in time
+#[cfg(windows)]
+fn virtual_desktop(hwnd: HWND) -> Option<(String, String)> {
+    let (tx, rx) = mpsc::channel();
+    let deadline = Instant::now() + DESKTOP_LOOKUP_BUDGET;
+    desktop_lookups().lock().unwrap().send((hwnd.0 as isize, deadline, tx)).ok()?;
+    rx.recv_timeout(DESKTOP_LOOKUP_BUDGET).ok().flatten()
+}
+
+/// Fill in the desktop and monitor of the window a capture came from;
+/// only captures pay for the lookups, not the tracker's polls
+#[cfg(windows)]
+fn with_workspace(mut info: WindowInfo, hwnd: HWND) -> WindowInfo {
+    if let Some((id, label)) = virtual_desktop(hwnd) {
+        info.virtual_desktop_id = Some(id);
+        info.virtual_desktop = Some(label);
+    }
+    info.monitor = monitor(hwnd);
+    info
+}
+
+/// Device name of the monitor a window is mostly on, e.g. `\\.\DISPLAY2`
+#[cfg(windows)]
+fn monitor(hwnd: HWND) -> Option<String> {
+    use windows::Win32::Graphics::Gdi::{GetMonitorInfoW, MonitorFromWindow, MONITORINFO, MONITORINFOEXW, MONITOR_DEFAULTTONULL};
+
+    unsafe {
+        let monitor = MonitorFromWindow(hwnd, MONITOR_DEFAULTTONULL);
+        if monitor.is_invalid() {
+            return None;
+        }
+        let mut info = MONITORINFOEXW::default();
+        info.monitorInfo.cbSize = std::mem::size_of::<MONITORINFOEXW>() as u32;
+        if !GetMonitorInfoW(monitor, &mut info as *mut MONITORINFOEXW as *mut MONITORINFO).as_bool() {
+            return None;
+        }
+        let len = info.szDevice.iter().position(|&c| c == 0).unwrap_or(info.szDevice.len());
+        Some(String::from_utf16_lossy(&info.szDevice[..len]))
+    }
+}
+
+/// Get the window title of a window
+#[cfg(windows)]
+unsafe fn window_title(hwnd: HWND) -> String {
+    let mut title_buffer: [u16; 512] = [0; 512];
+    let title_len = GetWindowTextW(hwnd, &mut title_buffer);
+    if title_len > 0 {
+        OsString::from_wide(&title_buffer[..title_len as usize])
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        "Unknown Window".to_string()
+    }
+}
+
+/// Get the executable name of a process
+#[cfg(windows)]
+unsafe fn process_name(process_id: u32) -> String {
+    if process_id == 0 {
+        return "unknown".to_string();
+    }
+
+    let Ok(process_handle) = OpenProcess(
+        PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
+        false,
+        process_id,
+    ) else {
+        return "unknown".to_string();
+    };
+
+    let mut name_buffer: [u16; 256] = [0; 256];
+    let name_len = GetModuleBaseNameW(process_handle, None, &mut name_buffer);
+    let _ = CloseHandle(process_handle);
+
+    if name_len > 0 {
+        OsString::from_wide(&name_buffer[..name_len as usize])
+            .to_string_lossy()
+            .into_owned()
+    } else {
+        "unknown".to_string()
+    }
+}
+
+/// Whether the window is part of the taskbar
+#[cfg(windows)]
+unsafe fn is_taskbar(hwnd: HWND) -> bool {
+    let mut class_buffer: [u16; 64] = [0; 64];
+    let class_len = GetClassNameW(hwnd, &mut class_buffer).max(0) as usize;
+    let class = String::from_utf16_lossy(&class_buffer[..class_len]);
+    TASKBAR_CLASSES.contains(&class.as_str())
+}
+
+/// Get the foreground window's info, owning process id and handle. The
+/// taskbar, which a tray click focuses, doesn't count.
+#[cfg(windows)]
+fn foreground_window() -> Option<(WindowInfo, u32, HWND)> {
     unsafe {
         let hwnd: HWND = GetForegroundWindow();
-        
-        if hwnd.0.is_null() {
-            return WindowInfo::default();
+
+        if hwnd.0.is_null() || is_taskbar(hwnd) {
+            return None;
         }
-        
-        // Get window title
-        let mut title_buffer: [u16; 512] = [0; 512];
-        let title_len = GetWindowTextW(hwnd, &mut title_buffer);
-        let window_title = if title_len > 0 {
-            OsString::from_wide(&title_buffer[..title_len as usize])
-                .to_string_lossy()
-                .into_owned()
-        } else {
-            "Unknown Window".to_string()
-        };
-        
-        // Get process ID
+
         let mut process_id: u32 = 0;
         GetWindowThreadProcessId(hwnd, Some(&mut process_id));
-        
-        // Get process name
-        let app_name = if process_id != 0 {
-            if let Ok(process_handle) = OpenProcess(
-                PROCESS_QUERY_INFORMATION | PROCESS_VM_READ,
-                false,
-                process_id,
-            ) {
-                let mut name_buffer: [u16; 256] = [0; 256];
-                let name_len = GetModuleBaseNameW(
-                    process_handle,
-                    None,
-                    &mut name_buffer,
-                );
-                
-                if name_len > 0 {
-                    OsString::from_wide(&name_buffer[..name_len as usize])
-                        .to_string_lossy()
-                        .into_owned()
-                } else {
-                    "unknown".to_string()
-                }
-            } else {
-                "unknown".to_string()
-            }
-        } else {
-            "unknown".to_string()
+
+        let info = WindowInfo {
+            app_name: process_name(process_id),
+            window_title: window_title(hwnd),
+            ..Default::default()
         };
-        
-        WindowInfo {
-            app_name,
-            window_title,
+        Some((info, process_id, hwnd))
+    }
+}
+
+/// Start polling the foreground window so captures can attribute clips to
+/// the app the user was in, even if focus moves while the capture runs
+#[cfg(windows)]
+pub fn start_foreground_tracker() {
+    let own_process_id = std::process::id();
+
+    std::thread::spawn(move || loop {
+        if let Some((info, process_id, hwnd)) = foreground_window() {
+            // Stack's own windows never count as a capture source
+            if process_id != own_process_id {
+                *LAST_FOREGROUND.lock().unwrap() = Some(Tracked {
+                    hwnd: hwnd.0 as isize,
+                    record: ForegroundRecord {
+                        info,
+                        observed_at: Utc::now(),
+                    },
+                });
+            }
         }
+        std::thread::sleep(std::time::Duration::from_millis(250));
+    });
+}
+
+#[cfg(not(windows))]
+pub fn start_foreground_tracker() {
+    // No foreground tracking on non-windows
+}
+
+/// Get the last non-Stack foreground window seen by the tracker
+pub fn get_last_foreground() -> Option<ForegroundRecord> {
+    LAST_FOREGROUND.lock().unwrap().as_ref().map(|tracked| tracked.record.clone())
+}
+
+/// Give focus back to the last tracked window, e.g. once a tray click has
+/// moved it to the taskbar. Returns whether that worked.
+#[cfg(windows)]
+pub fn focus_last_foreground() -> bool {
+    let Some(hwnd) = LAST_FOREGROUND.lock().unwrap().as_ref().map(|tracked| tracked.hwnd) else {
+        return false;
+    };
+    unsafe { SetForegroundWindow(HWND(hwnd as *mut _)).as_bool() }
+}
+
+#[cfg(not(windows))]
+pub fn focus_last_foreground() -> bool {
+    // No foreground tracking on non-windows
+    false
+}
+
+/// Window info to attribute a capture to, taken at the moment of the call:
+/// the live foreground window, or the last tracked one if Stack itself has
+/// focus. When the tracker already has the foreground window, its process
+/// name is reused, which saves querying the process on the capture path;
+/// the title is always read live, since it may have changed since the poll.
+#[cfg(windows)]
+pub fn capture_window_info() -> WindowInfo {
+    let hwnd = unsafe { GetForegroundWindow() };
+    let tracked = LAST_FOREGROUND
+        .lock()
+        .unwrap()
+        .as_ref()
+        .map(|tracked| (HWND(tracked.hwnd as *mut _), tracked.record.info.clone()));
+    if let Some((_, info)) = tracked.as_ref().filter(|(tracked, _)| tracked.0 == hwnd.0) {
+        let info = WindowInfo {
+            window_title: unsafe { window_title(hwnd) },
+            ..info.clone()
+        };
+        return with_workspace(info, hwnd);
+    }
+    match foreground_window() {
+        Some((info, process_id, hwnd)) if process_id != std::process::id() => with_workspace(info, hwnd),
+        _ => match tracked {
+            Some((hwnd, info)) => with_workspace(info, hwnd),
+            None => WindowInfo::default(),
+        },
     }
 }
 
 #[cfg(not(windows))]
-pub fn get_active_window_info() -> WindowInfo {
-    WindowInfo::default()
+pub fn capture_window_info() -> WindowInfo {
+    get_last_foreground()
+        .map(|record| record.info)
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn vscode_titles_give_file_and_project() {
+        assert_eq!(
+            source_context("Code.exe", "● main.rs - stack-backend - Visual Studio Code"),
+            Some(json!({ "file": "main.rs", "project": "stack-backend" }))
+        );
+        assert_eq!(
+            source_context("code", "lib.rs - api (Workspace) - Visual Studio Code - Insiders"),
+            Some(json!({ "file": "lib.rs", "project": "api" }))
+        );
+        assert_eq!(
+            source_context("vscodium", "notes.md - box [SSH: dev] - VSCodium"),
+            Some(json!({ "file": "notes.md", "project": "box" }))
+        );
+        assert_eq!(
+            source_context("Code.exe", "todo.txt - Visual Studio Code"),
+            Some(json!({ "file": "todo.txt" }))
+        );
+    }
+
+    #[test]
+    fn vscode_non_file_tabs_are_ignored() {
+        assert_eq!(source_context("Code.exe", "Welcome - stack - Visual Studio Code"), None);
+        assert_eq!(source_context("Code.exe", "Visual Studio Code"), None);
+    }
+
+    #[test]
+    fn jetbrains_titles_give_file_project_and_path() {
+        assert_eq!(
+            source_context("rustrover64.exe", "stack-backend – main.rs"),
+            Some(json!({ "file": "main.rs", "project": "stack-backend" }))
+        );
+        assert_eq!(
+            source_context("idea64.exe", "stack [~/code/stack] – *src/main.rs"),
+            Some(json!({ "file": "main.rs", "project": "stack", "path": "src/main.rs" }))
+        );
+        assert_eq!(source_context("idea64.exe", "stack"), None);
+    }
+
+    #[test]
+    fn desktops_are_named_then_numbered_then_identified() {
+        let first: u128 = 0xAA509086_5CA9_4C25_8F95_589D3C07B48A;
+        let second: u128 = 0x01234567_89AB_CDEF_0011_223344556677;
+        let as_stored = |guid: u128| {
+            let mut bytes = Vec::new();
+            bytes.extend_from_slice(&((guid >> 96) as u32).to_le_bytes());
+            bytes.extend_from_slice(&((guid >> 80) as u16).to_le_bytes());
+            bytes.extend_from_slice(&((guid >> 64) as u16).to_le_bytes());
+            bytes.extend_from_slice(&(guid as u64).to_be_bytes());
+            bytes
+        };
+        let ids = [as_stored(first), as_stored(second)].concat();
+
+        assert_eq!(desktop_label(second, &ids, Some("Research".to_string())), "Research");
+        assert_eq!(desktop_label(second, &ids, Some(" ".to_string())), "Desktop 2");
+        assert_eq!(desktop_label(first, &ids, None), "Desktop 1");
+        assert_eq!(desktop_label(first, &[], None), "{AA509086-5CA9-4C25-8F95-589D3C07B48A}");
+    }
+
+    #[test]
+    fn other_apps_have_no_context() {
+        assert_eq!(
+            source_context("notepad.exe", "main.rs - stack - Visual Studio Code"),
+            None
+        );
+    }
 }
@@ -4,11 +4,17 @@ const { listen } = window.__TAURI__.event;
 
 // State
 let clips = [];
-let pastebooks = [];
+let pastebookGroups = [];
 let activePastebook = null;
 let selectedIds = new Set();
 let searchQuery = '';
+let searchMatches = new Map(); // ids of clips matching searchQuery -> relevance rank
+let searchSeq = 0; // drops results of searches superseded by newer keystrokes
 let draggedId = null;
+let revision = null; // storage revision our view of the clips was read at
+let storageInMemory = false; // data dir unwritable: changes won't survive a restart
+let presets = []; // saved AI instructions, shown as buttons on every clip
+let notificationsHeld = false; // the notification policy is holding toasts (Focus Assist)
 
 // DOM Elements
 const canvasGrid = document.getElementById('canvas-grid');
@@ -37,18 +43,93 @@ let modalAction = null;
 // ==================== INITIALIZATION ====================
 
 async function init() {
+  // The startup theme is already applied; this catches a reload after it changed
+  document.documentElement.dataset.theme = await invoke('get_effective_theme');
+  notificationsHeld = await invoke('get_notifications_held');
+  const health = await invoke('get_storage_health');
+  if (health.mode === 'in_memory') {
+    storageInMemory = true;
+    showToast(`Storage unavailable, nothing will be saved: ${health.error}`, 'error');
+  }
   await loadPastebooks();
+  presets = await invoke('list_presets');
   await loadClips();
   setupEventListeners();
   setupDragAndDrop();
+  await offerCleanBackup();
+  await reportSafeMode();
+}
+
+// Repeated crashes start Stack read-only with AI and shortcuts off. The notice
+// stays up with the way out: export a backup, then leave safe mode.
+async function reportSafeMode() {
+  const reason = await invoke('get_safe_mode_reason');
+  if (!reason) return;
+  const toast = renderToast(
+    `Safe mode: Stack crashed ${reason.crashes} times in a row. Changes aren't saved and AI and shortcuts are off until a backup is exported.
+     <button class="btn btn-secondary" data-safe-mode="backup">Export backup</button>
+     <button class="btn btn-secondary" data-safe-mode="exit"${reason.backup_exported ? '' : ' disabled'}>Leave safe mode</button>`,
+    'error',
+    24 * 60 * 60 * 1000
+  );
+  const exitButton = toast.querySelector('[data-safe-mode="exit"]');
+  toast.querySelector('[data-safe-mode="backup"]').addEventListener('click', async () => {
+    try {
+      const written = await invoke('export_backup');
+      if (written === null) return;
+      exitButton.disabled = false;
+      showToast('Backup exported', 'success');
+    } catch (error) {
+      showToast(`Couldn't export a backup: ${escapeHtml(String(error))}`, 'error');
+    }
+  });
+  exitButton.addEventListener('click', async () => {
+    try {
+      await invoke('exit_safe_mode');
+      toast.remove();
+    } catch (error) {
+      showToast(escapeHtml(String(error)), 'error');
+    }
+  });
+}
+
+// The last session ended without its final save; offer the copy from the last clean exit
+async function offerCleanBackup() {
+  const previous = await invoke('get_previous_shutdown');
+  if (!previous) return;
+  if (!previous.backup) {
+    showToast('Stack did not shut down cleanly last time; recent changes may be missing', 'info');
+    await invoke('dismiss_previous_shutdown');
+    return;
+  }
+  const savedAt = formatTimestamp(previous.backup_saved_at);
+  showModal(
+    'Restore Backup?',
+    `Stack did not shut down cleanly last time, so recent changes may be missing. Restore the backup from the last clean exit (${savedAt})? Clips added since then will be replaced.`,
+    async () => {
+      try {
+        await invoke('restore_clean_backup');
+        await loadPastebooks();
+        await loadClips();
+        showToast('Backup restored', 'success');
+      } catch (error) {
+        showToast(`Restore failed: ${escapeHtml(String(error))}`, 'error');
+      }
+    }
+  );
 }
 
 // ==================== PASTEBOOK MANAGEMENT ====================
 
 async function loadPastebooks() {
   try {
-    pastebooks = await invoke('list_pastebooks');
-    activePastebook = await invoke('get_active_pastebook');
+    activePastebook = (await invoke('get_active_pastebook')).data;
+    // Whatever is on screen has been seen; other pastebooks keep their new counts
+    if (activePastebook && document.hasFocus()) {
+      // Refused in safe mode, where the counts just stay as they are
+      await invoke('mark_pastebook_viewed', { id: activePastebook.id }).catch(() => {});
+    }
+    pastebookGroups = (await invoke('list_pastebook_groups')).data;
     renderPastebookMenu();
     updatePastebookDisplay();
   } catch (error) {
@@ -57,16 +138,22 @@ async function loadPastebooks() {
 }
 
 function renderPastebookMenu() {
-  const menuItems = pastebooks.map(([id, name, count]) => {
+  const renderItem = ({ id, name, clip_count: count, new_clip_count: newCount }) => {
     const isActive = activePastebook && activePastebook.id === id;
+    const fresh = newCount > 0 ? ` · <span class="pastebook-item-new">${newCount} new</span>` : '';
     return `
       <div class="pastebook-item ${isActive ? 'active' : ''}" data-id="${id}" onclick="switchPastebook('${id}')">
         <div class="pastebook-item-info">
           <span class="pastebook-item-name">${escapeHtml(name)}</span>
-          <span class="pastebook-item-count">${count} clip${count !== 1 ? 's' : ''}</span>
+          <span class="pastebook-item-count">${count} clip${count !== 1 ? 's' : ''}${fresh}</span>
         </div>
       </div>
     `;
+  };
+  const menuItems = pastebookGroups.map(({ group, pastebooks }) => {
+    const items = pastebooks.map(renderItem).join('');
+    if (group === null) return items;
+    return `<div class="pastebook-group-name">${escapeHtml(group)}</div>${items}`;
   }).join('');
 
   pastebookMenu.innerHTML = menuItems + `
@@ -114,7 +201,8 @@ async function createPastebook(name) {
     showToast(`Created "${name}"`, 'success');
   } catch (error) {
     console.error('Failed to create pastebook:', error);
-    showToast('Failed to create pastebook', 'error');
+    const taken = String(error).match(/^AlreadyExists: .*\(try '(.*)'\)$/);
+    showToast(taken ? `That name is taken, try "${escapeHtml(taken[1])}"` : 'Failed to create pastebook', 'error');
   }
 }
 
@@ -130,8 +218,11 @@ function closePastebookMenu() {
 
 async function loadClips() {
   try {
-    clips = await invoke('get_clips');
+    const result = await invoke('get_clips');
+    clips = result.data;
+    revision = result.revision;
     selectedIds.clear();
+    await refreshSearch();
     renderClips();
     updateUI();
   } catch (error) {
@@ -177,16 +268,18 @@ function createClipCardHtml(clip) {
   return `
     <div class="clip-card ${isSelected ? 'selected' : ''}" 
          data-id="${clip.id}" 
-         draggable="true">
+         draggable="${!searchQuery}">
       <div class="clip-card-header">
         <div class="clip-card-source">
-          <span class="app-name">${escapeHtml(clip.metadata.source_app)}</span>
+          <span class="app-name" title="${escapeHtml(formatsTooltip(clip)).replace(/"/g, '&quot;')}">${escapeHtml(clip.metadata.source_app)}</span>
           <span>•</span>
-          <span>${escapeHtml(truncate(clip.metadata.window_title, 40))}</span>
+          <span title="${escapeHtml(workspaceTooltip(clip)).replace(/"/g, '&quot;')}">${escapeHtml(truncate(clip.metadata.window_title, 40))}</span>
         </div>
         <div class="clip-card-actions">
           <button class="btn btn-icon btn-secondary" onclick="editClip('${clip.id}')" title="Edit">✏️</button>
           <button class="btn btn-icon btn-secondary" onclick="copyClip('${clip.id}')" title="Copy">📋</button>
+          <button class="btn btn-icon btn-secondary" onclick="exportClip('${clip.id}')" title="Save as file">💾</button>
+          <button class="btn btn-icon btn-secondary" onclick="copyClipAsFile('${clip.id}')" title="Copy as file, to paste as an attachment">📎</button>
           <button class="btn btn-icon btn-danger" onclick="confirmDeleteClip('${clip.id}')" title="Delete">🗑️</button>
         </div>
       </div>
@@ -197,6 +290,9 @@ function createClipCardHtml(clip) {
         <button class="btn btn-secondary" onclick="cancelEdit('${clip.id}')">Cancel</button>
       </div>
       ${isLong ? `<button class="expand-btn" onclick="toggleExpand('${clip.id}')">Show more</button>` : ''}
+      <div class="clip-card-presets">
+        ${presets.map(p => `<button class="btn btn-secondary btn-preset" onclick="runPreset('${clip.id}', '${p.id}')" title="${escapeHtml(p.instruction)}">${escapeHtml(p.name)}</button>`).join('')}
+      </div>
       <div class="clip-card-footer">
         <span class="clip-card-timestamp">${timestamp}</span>
         <input type="checkbox" class="clip-card-checkbox" 
@@ -209,18 +305,32 @@ function createClipCardHtml(clip) {
 
 function getFilteredClips() {
   if (!searchQuery) return clips;
+  return clips
+    .filter(clip => searchMatches.has(clip.id))
+    .sort((a, b) => searchMatches.get(a.id) - searchMatches.get(b.id));
+}
 
-  const query = searchQuery.toLowerCase();
-  return clips.filter(clip =>
-    clip.content.toLowerCase().includes(query) ||
-    clip.metadata.source_app.toLowerCase().includes(query) ||
-    clip.metadata.window_title.toLowerCase().includes(query)
-  );
+// Ask the backend's search index which clips match the current query
+async function refreshSearch() {
+  if (!searchQuery) return;
+
+  const seq = ++searchSeq;
+  try {
+    const result = await invoke('search_clips', { query: searchQuery });
+    if (seq === searchSeq) {
+      searchMatches = new Map(result.data.map((clip, rank) => [clip.id, rank]));
+    }
+  } catch (error) {
+    console.error('Search failed:', error);
+  }
 }
 
 function updateUI() {
   // Update clip count
   clipCount.textContent = `${clips.length} clip${clips.length !== 1 ? 's' : ''}`;
+  if (storageInMemory) {
+    clipCount.textContent += ' · not saved';
+  }
 
   // Update selection info
   if (selectedIds.size > 0) {
@@ -239,8 +349,9 @@ function updateUI() {
 
 function setupEventListeners() {
   // Search
-  searchInput.addEventListener('input', (e) => {
+  searchInput.addEventListener('input', async (e) => {
     searchQuery = e.target.value;
+    await refreshSearch();
     renderClips();
   });
 
@@ -278,6 +389,7 @@ function setupEventListeners() {
 
   // Settings Modal
   document.getElementById('settings-save').addEventListener('click', saveSettings);
+  document.getElementById('settings-verify').addEventListener('click', verifyApiKey);
   document.getElementById('settings-cancel').addEventListener('click', closeSettingsModal);
   document.getElementById('btn-check-models').addEventListener('click', checkModels);
   document.getElementById('btn-check-models').addEventListener('click', checkModels);
@@ -289,25 +401,287 @@ function setupEventListeners() {
     if (e.target === modalOverlay) closeModal();
   });
 
+  // Coming back to the window counts as looking at the active pastebook
+  window.addEventListener('focus', () => loadPastebooks());
+
   // Listen for clip captured from hotkey
-  listen('clip-captured', (event) => {
-    const newClip = event.payload;
-    clips.unshift(newClip);
-    renderClips();
-    updateUI();
-    showToast('Clip captured!', 'success');
+  listen('clip-captured', async (event) => {
+    announce(event.payload.announcement);
+    // Only clips in the pastebook on screen join the list; the event only
+    // carries a preview, so fetch the full clip
+    if (activePastebook && event.payload.pastebook_id === activePastebook.id) {
+      try {
+        const newClip = await invoke('get_clip', { id: event.payload.id });
+        placeOnTop(newClip);
+        renderClips();
+        updateUI();
+      } catch (error) {
+        await loadClips();
+      }
+    }
     // Update pastebook list to reflect new clip count
     loadPastebooks();
+    // Already clipped somewhere else; offer a jump to the earlier copy
+    const duplicate = event.payload.duplicate_of;
+    if (duplicate) {
+      const when = formatTimestamp(duplicate.captured_at);
+      const toast = showToast(`Also in ${escapeHtml(duplicate.pastebook_name)} (${when}) <button class="btn btn-secondary">Show</button>`, 'info', 8000);
+      toast.querySelector('button').addEventListener('click', async () => {
+        toast.remove();
+        try {
+          await invoke('reveal_clip', { id: duplicate.clip_id });
+        } catch (error) {
+          showToast(`Couldn't show the clip: ${escapeHtml(String(error))}`, 'error');
+        }
+      });
+    }
+  });
+  // A clip changed (a repeat capture bumped it, a preset or title filled it in);
+  // the event only carries a preview, so refetch clips on screen
+  listen('clip-updated', async (event) => {
+    const index = clips.findIndex(c => c.id === event.payload.id);
+    if (index === -1) return;
+    try {
+      const updated = await invoke('get_clip', { id: event.payload.id });
+      const bumped = updated.metadata.timestamp !== clips[index].metadata.timestamp;
+      if (bumped && !updated.pinned) placeOnTop(updated); else clips[index] = updated;
+      renderClips();
+    } catch (error) {
+      await loadClips();
+    }
+  });
+  // A clip's pastebook became the active one (reveal_clip, or a capture that reused it)
+  listen('clip-revealed', async (event) => {
+    // A reused capture moved the view to another pastebook; say so
+    const switchedTo = event.payload.switched_to;
+    if (switchedTo) showToast(`Already in ${escapeHtml(switchedTo)}: switched to it`, 'info');
+    await loadPastebooks();
+    await loadClips();
+    const card = canvasGrid.querySelector(`.clip-card[data-id="${event.payload.clip_id}"]`);
+    if (!card) return;
+    card.scrollIntoView({ block: 'center', behavior: 'smooth' });
+    card.classList.add('revealed');
+    setTimeout(() => card.classList.remove('revealed'), 2000);
+  });
+  // A burst of captures (e.g. a clipboard manager replaying history) arrives as one batch
+  listen('clips-captured-batch', async (event) => {
+    announce(event.payload.announcement);
+    await loadClips();
+    loadPastebooks();
+  });
+
+  // Capture pause (set_capture_paused) refused a capture, or ended
+  listen('capture-blocked', (event) => {
+    announce(event.payload.announcement);
+    const excluded = event.payload?.reason === 'excluded_app';
+    showToast(excluded ? 'Not captured: app is on the exclude list' : 'Capture is paused', 'error');
+  });
+  // Focus mode: a hotkey copy from an app outside the include list
+  listen('capture-held', (event) => {
+    const { pending_id, source_app } = event.payload;
+    announce(event.payload.announcement);
+    const toast = showToast(`${escapeHtml(source_app)} is not in the focus list. <button class="btn btn-secondary">Capture anyway</button>`, 'info', 8000);
+    toast.querySelector('button').addEventListener('click', async () => {
+      toast.remove();
+      try {
+        await invoke('capture_anyway', { pendingId: pending_id });
+      } catch (error) {
+        showToast(`Couldn't capture: ${escapeHtml(String(error))}`, 'error');
+      }
+    });
+  });
+  listen('capture-resumed', () => {
+    showToast('Capture resumed', 'success');
+  });
+  listen('capture-failed', (event) => {
+    announce(event.payload.announcement);
+    const reason = event.payload.reason === 'binary-content' ? 'clipboard held binary data' : event.payload.reason;
+    showToast(`Capture failed: ${reason}`, 'error');
+  });
+  // A capture rule matched, or its app is copying faster than the rate limit
+  listen('capture-skipped', (event) => {
+    announce(event.payload.announcement);
+    const { reason, rule, source_app } = event.payload;
+    const why = reason === 'rate_limited'
+      ? `${escapeHtml(source_app)} is copying too fast`
+      : `rule "${escapeHtml(rule)}"`;
+    showToast(`Not captured (${why})`, 'info');
+  });
+
+  listen('mirror-failed', (event) => {
+    const { path, error } = event.payload;
+    showToast(`Couldn't append to ${escapeHtml(path)}: ${escapeHtml(error)}`, 'error');
+  });
+  listen('auto-export-failed', (event) => {
+    const { path, error } = event.payload;
+    showToast(`Scheduled export to ${escapeHtml(path)} failed: ${escapeHtml(error)}`, 'error');
+  });
+  listen('drop-rejected', (event) => {
+    const { name, reason } = event.payload;
+    showToast(`Not captured: ${escapeHtml(name)} (${escapeHtml(reason)})`, 'error');
+  });
+  // Links pushed to stack:// that were malformed, too large or disabled
+  listen('deeplink-rejected', (event) => {
+    showToast(`Link rejected: ${event.payload}`, 'error');
+  });
+
+  // Data dir became unwritable (or writable again); clips are kept in memory meanwhile
+  listen('storage-unavailable', (event) => {
+    storageInMemory = true;
+    updateUI();
+    showToast(`Storage unavailable, nothing will be saved: ${event.payload.error}`, 'error');
+  });
+  listen('storage-recovered', () => {
+    storageInMemory = false;
+    updateUI();
+    showToast('Storage is writable again; all clips saved', 'success');
+  });
+
+  // The active pastebook vanished (hand edit, sync merge); a capture picked another
+  listen('pastebook-auto-selected', async (event) => {
+    const { name, created } = event.payload;
+    await loadPastebooks();
+    await loadClips();
+    showToast(created ? `Created "${escapeHtml(name)}" for new clips` : `Switched to "${escapeHtml(name)}"`, 'info');
+  });
+
+  // The tray menu saved a diagnostics bundle for a bug report
+  listen('diagnostics-saved', (event) => {
+    showToast(`Diagnostics saved to ${escapeHtml(event.payload.path)}`, 'success', 6000);
+  });
+  listen('diagnostics-failed', (event) => {
+    showToast(`Couldn't create diagnostics: ${escapeHtml(event.payload.error)}`, 'error');
+  });
+
+  // A rule routing by window title made a pastebook, or wasn't allowed to make more
+  listen('pastebook-title-routed', async (event) => {
+    const routed = event.payload;
+    if (routed.status === 'created') {
+      await loadPastebooks();
+      showToast(`Created "${escapeHtml(routed.name)}" for clips from its windows`, 'info');
+    } else {
+      showToast(`Didn't create "${escapeHtml(routed.name)}": rules already made ${routed.limit} pastebooks. The clip went to the current one.`, 'info', 8000);
+    }
+  });
+
+  // OS theme switches, or the theme setting changing
+  listen('theme-changed', (event) => {
+    document.documentElement.dataset.theme = event.payload;
+  });
+
+  // Bulk edits from anywhere; reload unless we made them ourselves
+  listen('storage-changed', async (event) => {
+    const { pastebook_id: pastebookId } = event.payload;
+    if (event.payload.revision === revision) return;
+    // A change only to another pastebook leaves these clips as they are
+    if (pastebookId && pastebookId !== activePastebook?.id && event.payload.revision === revision + 1) {
+      revision = event.payload.revision;
+      loadPastebooks();
+      return;
+    }
+    await loadClips();
+  });
+
+  // Copy-all / clear-all hotkeys fire even while the window is hidden
+  listen('copied-all', (event) => {
+    announce(event.payload.announcement);
+    const { clips, pasted, error } = event.payload;
+    if (error) {
+      showToast('Too much to copy at once; export to a file instead', 'error');
+    } else if (clips === 0) {
+      showToast('Nothing to copy', 'info');
+    } else {
+      showToast(`${pasted ? 'Pasted' : 'Copied'} ${clips} clip${clips === 1 ? '' : 's'}`, 'success');
+    }
+  });
+  listen('ai-budget-exceeded', (event) => {
+    const { used, budget } = event.payload;
+    showToast(`AI usage this month (${used.toLocaleString()} tokens) is over your budget of ${budget.toLocaleString()}`, 'error');
+  });
+
+  // Tray double-click captures and middle-click pastes happen with the window hidden too
+  listen('top-clip-pasted', (event) => {
+    announce(event.payload.announcement);
+    showToast('Pasted the top clip', 'success');
+  });
+  listen('tray-action-failed', (event) => {
+    announce(event.payload.announcement);
+    showToast(`Tray action failed: ${event.payload.reason}`, 'error');
+  });
+  listen('clear-all-armed', (event) => {
+    const seconds = Math.round(event.payload.window_ms / 1000);
+    showToast(`Press again within ${seconds}s to clear all clips`, 'info');
+  });
+  listen('clips-cleared', (event) => {
+    showToast(`Cleared ${event.payload.clips} clips`, 'success');
+  });
+
+  // hold_on_clipboard started or ended (null when the clip is released)
+  listen('clipboard-hold', (event) => {
+    if (event.payload) {
+      const until = new Date(event.payload.until).toLocaleTimeString([], { hour: '2-digit', minute: '2-digit' });
+      showToast(`Keeping the clip on your clipboard until ${until}`, 'info');
+    } else {
+      showToast('Clipboard hold ended', 'info');
+    }
+  });
+
+  // Capture and reminder toasts, after the notification policy (Focus Assist
+  // may hold them and send one summary later)
+  listen('notification', (event) => {
+    const { message, level } = event.payload;
+    renderToast(escapeHtml(message), level);
+  });
+  listen('safe-mode-exited', () => {
+    showToast('Left safe mode: changes are saved again. Shortcuts come back at the next launch.', 'success', 6000);
+  });
+  // Every other toast follows the same policy through showToast
+  listen('notifications-held', (event) => {
+    notificationsHeld = event.payload.held;
   });
 }
 
+// Put a clip first among the unpinned ones, replacing any copy already in
+// the list. Pinned clips keep their positions, as they do in the backend.
+function placeOnTop(clip) {
+  const pinned = clips.map((c, i) => [i, c]).filter(([, c]) => c.pinned && c.id !== clip.id);
+  const rest = clips.filter(c => !c.pinned && c.id !== clip.id);
+  rest.unshift(clip);
+  const placed = [];
+  while (pinned.length || rest.length) {
+    if (pinned.length && (pinned[0][0] <= placed.length || !rest.length)) placed.push(pinned.shift()[1]);
+    else placed.push(rest.shift());
+  }
+  clips = placed;
+}
+
 // ==================== DRAG AND DROP ====================
 
 function setupDragAndDrop() {
-  // Already attached in renderClips
+  // Clip reordering is attached in renderClips. Text dragged in from another
+  // app becomes a clip; dropped files are handled by the backend.
+  document.addEventListener('dragover', (e) => {
+    if (!draggedId && e.dataTransfer.types.includes('text/plain')) e.preventDefault();
+  });
+  document.addEventListener('drop', async (e) => {
+    if (draggedId || e.dataTransfer.files.length > 0) return;
+    const text = e.dataTransfer.getData('text/plain');
+    if (!text.trim()) return;
+    e.preventDefault();
+    try {
+      await invoke('capture_dropped_text', { content: text });
+    } catch (error) {
+      showToast(`Couldn't add dropped text: ${escapeHtml(String(error))}`, 'error');
+    }
+  });
 }
 
 function handleDragStart(e) {
+  // A filtered view hides the clips in between, so there's no order to drop into
+  if (searchQuery) {
+    e.preventDefault();
+    return;
+  }
   draggedId = e.target.dataset.id;
   e.target.classList.add('dragging');
   e.dataTransfer.effectAllowed = 'move';
@@ -337,7 +711,7 @@ function handleDragLeave(e) {
 async function handleDrop(e) {
   e.preventDefault();
   const targetCard = e.target.closest('.clip-card');
-  if (!targetCard || !draggedId) return;
+  if (!targetCard || !draggedId || searchQuery) return;
 
   const targetId = targetCard.dataset.id;
   if (targetId === draggedId) return;
@@ -356,7 +730,11 @@ async function handleDrop(e) {
 
   // Update backend
   try {
-    await invoke('reorder_clips', { ids: clips.map(c => c.id) });
+    const result = await invoke('reorder_clips', { ids: clips.map(c => c.id), expectedRevision: revision });
+    revision = result.revision;
+    // Pinned clips don't move, so take the order the backend settled on
+    const order = new Map(result.data.map((id, index) => [id, index]));
+    clips.sort((a, b) => order.get(a.id) - order.get(b.id));
     renderClips();
     showToast('Clips reordered', 'success');
   } catch (error) {
@@ -372,13 +750,71 @@ async function copyClip(id) {
   if (!clip) return;
 
   try {
-    await navigator.clipboard.writeText(clip.content);
+    // The backend appends the source attribution when that setting is on
+    await invoke('copy_clip', { id });
     showToast('Copied to clipboard', 'success');
   } catch (error) {
     console.error('Copy failed:', error);
   }
 }
 
+// The clipboard formats the source app offered, for debugging odd captures
+function formatsTooltip(clip) {
+  const formats = clip.metadata.available_formats || [];
+  return formats.length ? `Clipboard formats: ${formats.join(', ')}` : '';
+}
+
+// Where the source window was: virtual desktop and monitor, when known
+function workspaceTooltip(clip) {
+  const { virtual_desktop: desktop, monitor } = clip.metadata;
+  return [desktop, monitor].filter(Boolean).join(', ');
+}
+
+// Save one clip as a file; the backend asks where with a save dialog.
+// A sensitive clip would end up in a plain file, so ask first.
+async function exportClip(id) {
+  const clip = clips.find(c => c.id === id);
+  if (clip?.sensitive && !confirm('This clip is marked sensitive. Save it to a file anyway?')) return;
+  try {
+    const result = await invoke('export_clip', { id, confirmSensitive: !!clip?.sensitive });
+    if (result.status === 'saved') showToast(`Saved ${escapeHtml(result.path)}`, 'success');
+  } catch (error) {
+    showToast(`Couldn't save the clip: ${escapeHtml(String(error))}`, 'error');
+  }
+}
+
+// Copy a clip as a file reference so pasting into a chat app attaches it.
+// A sensitive clip would land in a temp file, so ask first.
+async function copyClipAsFile(id) {
+  const clip = clips.find(c => c.id === id);
+  if (clip?.sensitive && !confirm('This clip is marked sensitive. Copy it as a file anyway?')) return;
+  try {
+    const result = await invoke('copy_clip_as_file', { id, confirmSensitive: !!clip?.sensitive });
+    if (result.mode === 'file') showToast('Copied as a file', 'success');
+    else showToast(escapeHtml(result.warning), 'info');
+  } catch (error) {
+    showToast(`Couldn't copy the clip as a file: ${escapeHtml(String(error))}`, 'error');
+  }
+}
+
+// Run a saved AI instruction; the preset decides whether the clip is rewritten
+// (clip-updated) or a new clip is added (clip-captured)
+async function runPreset(clipId, presetId) {
+  const preset = presets.find(p => p.id === presetId);
+  showToast(`Running "${escapeHtml(preset.name)}"...`, 'info');
+  try {
+    const result = await invoke('run_preset', { clipId, presetId });
+    if (result.output === 'in-place') {
+      const index = clips.findIndex(c => c.id === clipId);
+      if (index !== -1) clips[index] = result.clip;
+      renderClips();
+    }
+    showToast(`"${escapeHtml(preset.name)}" done`, 'success');
+  } catch (error) {
+    showToast(`"${escapeHtml(preset.name)}" failed: ${escapeHtml(String(error))}`, 'error');
+  }
+}
+
 function editClip(id) {
   const card = document.querySelector(`[data-id="${id}"]`);
   if (card) {
@@ -412,7 +848,13 @@ async function saveEdit(id) {
   }
 
   try {
-    await invoke('update_clip', { id, content: newContent });
+    const result = await invoke('update_clip', {
+      id,
+      content: newContent,
+      pastebookId: activePastebook?.id,
+      expectedRevision: revision,
+    });
+    revision = result.revision;
     const clip = clips.find(c => c.id === id);
     if (clip) {
       clip.content = newContent;
@@ -421,6 +863,7 @@ async function saveEdit(id) {
     showToast('Clip updated', 'success');
   } catch (error) {
     console.error('Update failed:', error);
+    if (await reloadOnConflict(error)) return;
     showToast('Failed to update clip', 'error');
   }
 }
@@ -460,27 +903,44 @@ function deselectAll() {
 
 // ==================== BULK ACTIONS ====================
 
+// Fold the cards at these pastebook positions away before the list reloads
+function collapseCards(ids) {
+  const cards = ids
+    .map(id => canvasGrid.querySelector(`.clip-card[data-id="${id}"]`))
+    .filter(Boolean);
+  if (cards.length === 0) return Promise.resolve();
+  cards.forEach(card => card.classList.add('collapsing'));
+  return new Promise(resolve => setTimeout(resolve, 200));
+}
+
 async function mergeSelected() {
   const ids = Array.from(selectedIds);
   if (ids.length < 2) return;
 
   try {
-    const merged = await invoke('merge_clips', { ids });
-    if (merged) {
+    const merged = await invoke('merge_clips', { ids, expectedRevision: revision });
+    if (merged.data) {
+      await collapseCards(merged.data.source_ids);
       await loadClips();
       selectedIds.clear();
       updateUI();
-      showToast(`Merged ${ids.length} clips`, 'success');
+      showToast(`Merged ${ids.length} clips (${formatBytes(merged.data.bytes)})`, 'success');
     }
   } catch (error) {
     console.error('Merge failed:', error);
+    if (await reloadOnConflict(error)) return;
+    if (String(error).startsWith('TooLarge')) {
+      showToast('Merged clip would be too large', 'error');
+      return;
+    }
     showToast('Failed to merge clips', 'error');
   }
 }
 
 async function deleteClip(id) {
   try {
-    await invoke('delete_clip', { id });
+    const result = await invoke('delete_clip', { id, pastebookId: activePastebook?.id, expectedRevision: revision });
+    revision = result.revision;
     clips = clips.filter(c => c.id !== id);
     selectedIds.delete(id);
     renderClips();
@@ -489,6 +949,7 @@ async function deleteClip(id) {
     loadPastebooks();
   } catch (error) {
     console.error('Delete failed:', error);
+    if (await reloadOnConflict(error)) return;
     showToast('Failed to delete clip', 'error');
   }
 }
@@ -498,7 +959,8 @@ async function deleteSelected() {
 
   try {
     for (const id of ids) {
-      await invoke('delete_clip', { id });
+      const result = await invoke('delete_clip', { id, pastebookId: activePastebook?.id, expectedRevision: revision });
+      revision = result.revision;
     }
     clips = clips.filter(c => !ids.includes(c.id));
     selectedIds.clear();
@@ -508,31 +970,45 @@ async function deleteSelected() {
     loadPastebooks();
   } catch (error) {
     console.error('Delete failed:', error);
+    if (await reloadOnConflict(error)) return;
     showToast('Failed to delete clips', 'error');
   }
 }
 
 async function copyAll() {
   try {
-    await invoke('copy_all_to_clipboard');
-    showToast('All clips copied to clipboard!', 'success');
+    const result = await invoke('copy_all_to_clipboard');
+    showToast(`All clips copied to clipboard (${formatBytes(result.bytes)})`, 'success');
   } catch (error) {
     console.error('Copy all failed:', error);
+    if (String(error).startsWith('TooLarge')) {
+      showToast('Too much to copy at once; export to a file instead', 'error');
+      return;
+    }
     showToast('Failed to copy clips', 'error');
   }
 }
 
+function formatBytes(bytes) {
+  if (bytes < 1024) return `${bytes} B`;
+  if (bytes < 1024 * 1024) return `${(bytes / 1024).toFixed(1)} KB`;
+  return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
+}
+
 async function clearAll() {
   try {
-    await invoke('clear_all_clips');
-    clips = [];
+    const result = await invoke('clear_all_clips', { expectedRevision: revision });
+    revision = result.revision;
+    // Locked clips are kept
+    clips = clips.filter(c => c.locked);
     selectedIds.clear();
     renderClips();
     updateUI();
-    showToast('All clips cleared', 'success');
+    showToast(`Cleared ${result.data} clips`, 'success');
     loadPastebooks();
   } catch (error) {
     console.error('Clear failed:', error);
+    if (await reloadOnConflict(error)) return;
     showToast('Failed to clear clips', 'error');
   }
 }
@@ -545,7 +1021,9 @@ async function handleMagicSort() {
   btn.innerHTML = '✨ Sorting...';
 
   try {
-    await invoke('magic_sort');
+    // With a search showing only some clips, sort just those among themselves
+    const ids = searchQuery ? getFilteredClips().map(clip => clip.id) : null;
+    await invoke('magic_sort', { ids, expectedRevision: revision });
     await loadClips();
     showToast('✨ Stack sorted magically!', 'success');
   } catch (error) {
@@ -586,13 +1064,13 @@ async function handleChatSubmit() {
   appendChatMessage('Thinking... 🤔', 'bot', true);
 
   try {
-    const response = await invoke('chat_submit', { prompt });
+    const reply = await invoke('chat_submit', { prompt });
     // Remove loading message
     const loader = chatMessages.querySelector('.loading');
     if (loader) loader.remove();
 
     // Add bot response
-    appendChatMessage(response, 'bot');
+    appendChatMessage(reply.text, 'bot');
   } catch (error) {
     const loader = chatMessages.querySelector('.loading');
     if (loader) loader.remove();
@@ -642,6 +1120,26 @@ async function saveSettings() {
   }
 }
 
+async function verifyApiKey() {
+  const status = document.getElementById('api-key-status');
+  status.textContent = 'Checking...';
+  try {
+    const key = apiKeyInput.value.trim() || null;
+    const result = await invoke('verify_api_key', { key });
+    const messages = {
+      valid: () => `Key works (${result.models.length} models available)`,
+      invalid: () => `Key rejected: ${result.message}`,
+      quota_exhausted: () => `Key works but is out of quota: ${result.message}`,
+      network_error: () => `Couldn't reach Google: ${result.message}`,
+    };
+    status.textContent = messages[result.status]();
+    status.style.color = result.status === 'valid' ? 'var(--success)' : 'var(--error)';
+  } catch (error) {
+    status.textContent = String(error);
+    status.style.color = 'var(--error)';
+  }
+}
+
 async function checkModels() {
   const listDiv = document.getElementById('models-list');
   listDiv.innerHTML = 'Loading models...';
@@ -721,6 +1219,15 @@ function confirmClearAll() {
 
 // ==================== UTILITIES ====================
 
+// Storage changed since we last loaded (another window, hotkey capture, sync):
+// refetch so the user can retry against current data
+async function reloadOnConflict(error) {
+  if (!String(error).startsWith('Conflict')) return false;
+  await loadClips();
+  showToast('Clips changed elsewhere, reloaded. Please try again.', 'error');
+  return true;
+}
+
 function escapeHtml(text) {
   const div = document.createElement('div');
   div.textContent = text;
@@ -748,7 +1255,28 @@ function formatTimestamp(isoString) {
   return date.toLocaleDateString();
 }
 
-function showToast(message, type = 'info') {
+// Hand an event's announcement to the ARIA live region for screen readers
+function announce(text) {
+  if (!text) return;
+  const region = document.getElementById('announcer');
+  region.textContent = '';
+  // Clearing first and setting it a frame later makes the same text read again
+  requestAnimationFrame(() => { region.textContent = text; });
+}
+
+// Show a toast unless the notification policy is holding them, in which case
+// it only counts towards the summary shown once the hold ends
+function showToast(message, type = 'info', duration = 3000) {
+  if (!notificationsHeld) return renderToast(message, type, duration);
+  invoke('hold_notification').catch(() => {});
+  // Callers may wire up buttons on the toast; hand them one that's never shown
+  const toast = document.createElement('div');
+  toast.innerHTML = `<span>${message}</span>`;
+  return toast;
+}
+
+// Show a toast now; `notification` events have been through the policy already
+function renderToast(message, type = 'info', duration = 3000) {
   const container = document.getElementById('toast-container');
   const toast = document.createElement('div');
   toast.className = `toast ${type}`;
@@ -758,12 +1286,16 @@ function showToast(message, type = 'info') {
   setTimeout(() => {
     toast.style.opacity = '0';
     setTimeout(() => toast.remove(), 300);
-  }, 3000);
+  }, duration);
+  return toast;
 }
 
 // Make functions available globally for onclick handlers
 window.editClip = editClip;
 window.copyClip = copyClip;
+window.exportClip = exportClip;
+window.copyClipAsFile = copyClipAsFile;
+window.runPreset = runPreset;
 window.saveEdit = saveEdit;
 window.cancelEdit = cancelEdit;
 window.toggleExpand = toggleExpand;
@@ -23,7 +23,7 @@ async function init() {
 // Load clips from backend
 async function loadClips() {
     try {
-        clips = await invoke('get_clips');
+        clips = (await invoke('get_clips')).data;
         renderClips();
     } catch (error) {
         console.error('Failed to load clips:', error);
@@ -57,20 +57,36 @@ function renderClips() {
 // Setup event listeners
 function setupEventListeners() {
     // Listen for clip captured from hotkey (backend does the capture now)
-    listen('clip-captured', async (event) => {
-        const newClip = event.payload;
+    listen('clip-captured', async () => {
         // Show flash animation
         captureFlash.classList.add('active');
         setTimeout(() => captureFlash.classList.remove('active'), 150);
 
-        clips.unshift(newClip);
-        renderClips();
+        // The clip may have gone into another pastebook than the one shown
+        await loadClips();
+    });
+
+    // Only a preview comes with the event; reload if the clip is listed
+    listen('clip-updated', async (event) => {
+        if (clips.some(c => c.id === event.payload.id)) {
+            await loadClips();
+        }
+    });
+
+    listen('clips-captured-batch', async () => {
+        captureFlash.classList.add('active');
+        setTimeout(() => captureFlash.classList.remove('active'), 150);
+        await loadClips();
     });
 
     // Listen for clip updates from main window
     listen('clips-updated', async () => {
         await loadClips();
     });
+
+    listen('theme-changed', (event) => {
+        document.documentElement.dataset.theme = event.payload;
+    });
 }
 
 // Delete a clip